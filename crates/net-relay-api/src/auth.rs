@@ -6,10 +6,20 @@ use axum::{
     middleware::Next,
     response::{IntoResponse, Response},
 };
-use net_relay_core::ConfigManager;
+use chrono::{DateTime, TimeDelta, Utc};
+use net_relay_core::config::with_config_actor;
+use net_relay_core::stats::DeniedAttempt;
+use net_relay_core::{ConfigManager, ErrorCode, Stats};
+use serde::Serialize;
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::client_ip::client_ip;
+use crate::handlers::ErrorResponse;
 
 /// Session store for managing authentication tokens.
 #[derive(Clone, Default)]
@@ -21,7 +31,34 @@ pub struct SessionStore {
 #[derive(Clone)]
 pub struct SessionData {
     pub username: String,
-    pub created_at: std::time::Instant,
+    pub created_at: DateTime<Utc>,
+    /// When the session last authenticated a request.
+    pub last_seen_at: DateTime<Utc>,
+    /// Address the session was last used from.
+    pub client_ip: Option<IpAddr>,
+}
+
+impl SessionData {
+    /// Whether the session was created more than `ttl` ago.
+    fn is_expired(&self, ttl: Duration) -> bool {
+        TimeDelta::from_std(ttl).is_ok_and(|ttl| Utc::now() - self.created_at > ttl)
+    }
+}
+
+/// Characters of a token that identify its session in listings.
+pub const TOKEN_PREFIX_LEN: usize = 8;
+
+/// A session as listed to administrators, identified by its token prefix.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub token_prefix: String,
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<IpAddr>,
+    /// Whether this is the session of the request listing it.
+    pub current: bool,
 }
 
 impl SessionStore {
@@ -31,60 +68,141 @@ impl SessionStore {
         }
     }
 
-    /// Create a new session and return the token.
-    pub async fn create_session(&self, username: String) -> String {
+    /// Create a new session for a login from `client_ip` and return the token.
+    pub async fn create_session(&self, username: String, client_ip: Option<IpAddr>) -> String {
         let token = generate_token();
+        let now = Utc::now();
         let session = SessionData {
             username,
-            created_at: std::time::Instant::now(),
+            created_at: now,
+            last_seen_at: now,
+            client_ip,
         };
         self.sessions.write().await.insert(token.clone(), session);
         token
     }
 
-    /// Validate a session token.
-    pub async fn validate(&self, token: &str) -> Option<String> {
+    /// Validate a session token, treating sessions older than `ttl` as gone.
+    pub async fn validate(&self, token: &str, ttl: Duration) -> Option<String> {
+        let sessions = self.sessions.read().await;
+        sessions
+            .get(token)
+            .filter(|s| !s.is_expired(ttl))
+            .map(|s| s.username.clone())
+    }
+
+    /// Validate a session token used from `client_ip`, recording the use.
+    ///
+    /// A session older than `ttl` is removed instead.
+    pub async fn touch(
+        &self,
+        token: &str,
+        client_ip: Option<IpAddr>,
+        ttl: Duration,
+    ) -> Option<String> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions.get_mut(token)?;
+        if session.is_expired(ttl) {
+            sessions.remove(token);
+            return None;
+        }
+        session.last_seen_at = Utc::now();
+        if client_ip.is_some() {
+            session.client_ip = client_ip;
+        }
+        Some(session.username.clone())
+    }
+
+    /// Sessions younger than `ttl` ordered by last use, most recent first;
+    /// `current` is the token of the caller.
+    pub async fn list(&self, current: Option<&str>, ttl: Duration) -> Vec<SessionInfo> {
         let sessions = self.sessions.read().await;
-        sessions.get(token).map(|s| s.username.clone())
+        let mut list: Vec<SessionInfo> = sessions
+            .iter()
+            .filter(|(_, session)| !session.is_expired(ttl))
+            .map(|(token, session)| SessionInfo {
+                token_prefix: token_prefix(token).to_string(),
+                username: session.username.clone(),
+                created_at: session.created_at,
+                last_seen_at: session.last_seen_at,
+                client_ip: session.client_ip,
+                current: current == Some(token.as_str()),
+            })
+            .collect();
+        list.sort_by_key(|session| std::cmp::Reverse(session.last_seen_at));
+        list
+    }
+
+    /// Remove the session whose token starts with `prefix`.
+    ///
+    /// Fails with the number of matching sessions unless exactly one matches.
+    pub async fn remove_prefix(&self, prefix: &str) -> Result<SessionData, usize> {
+        let mut sessions = self.sessions.write().await;
+        let matching: Vec<String> = sessions
+            .keys()
+            .filter(|token| token.starts_with(prefix))
+            .cloned()
+            .collect();
+        match matching.as_slice() {
+            [token] => Ok(sessions.remove(token).expect("matched session")),
+            _ => Err(matching.len()),
+        }
     }
 
     /// Remove a session.
     pub async fn remove(&self, token: &str) {
         self.sessions.write().await.remove(token);
     }
+
+    /// Remove every session except the given one. Returns how many were removed.
+    pub async fn remove_all_except(&self, keep: &str) -> usize {
+        let mut sessions = self.sessions.write().await;
+        let before = sessions.len();
+        sessions.retain(|token, _| token == keep);
+        before - sessions.len()
+    }
+
+    /// Remove every session, or every session of `username`, except `keep`.
+    /// Returns how many were removed.
+    pub async fn remove_except(&self, keep: &str, username: Option<&str>) -> usize {
+        let mut sessions = self.sessions.write().await;
+        let before = sessions.len();
+        sessions.retain(|token, session| {
+            token == keep || username.is_some_and(|username| session.username != username)
+        });
+        before - sessions.len()
+    }
+
+    /// Remove every session of `username`. Returns how many were removed.
+    pub async fn remove_user(&self, username: &str) -> usize {
+        let mut sessions = self.sessions.write().await;
+        let before = sessions.len();
+        sessions.retain(|_, session| session.username != username);
+        before - sessions.len()
+    }
+}
+
+/// Leading characters of `token` shown in session listings.
+pub fn token_prefix(token: &str) -> &str {
+    token.get(..TOKEN_PREFIX_LEN).unwrap_or(token)
 }
 
 /// Generate a secure random token.
 fn generate_token() -> String {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_nanos();
-    let random: u64 = rand_simple();
-    format!("{:x}{:016x}", timestamp, random)
+    net_relay_core::config::random_hex(32).expect("secure random number generator unavailable")
 }
 
-/// Simple pseudo-random number generator (no external dependency).
-fn rand_simple() -> u64 {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let seed = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_nanos() as u64;
-    // xorshift64
-    let mut x = seed;
-    x ^= x << 13;
-    x ^= x >> 7;
-    x ^= x << 17;
-    x
-}
+/// Username of the dashboard session that made a request.
+///
+/// Inserted into both request and response extensions by [`session_auth_middleware`].
+#[derive(Debug, Clone)]
+pub struct DashboardUser(pub String);
 
 /// Session auth middleware that checks for valid session cookie.
 pub async fn session_auth_middleware(
     config_manager: ConfigManager,
     session_store: SessionStore,
-    request: Request,
+    mut request: Request,
     next: Next,
 ) -> Response {
     // Check if authentication is enabled
@@ -99,6 +217,20 @@ pub async fn session_auth_middleware(
         return next.run(request).await;
     }
 
+    // Prometheus scrapes with `dashboard.metrics_token` instead of a session
+    if path == "/api/metrics" {
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "));
+        if let Some(token) = token {
+            if config_manager.verify_metrics_token(token).await {
+                return next.run(request).await;
+            }
+        }
+    }
+
     // Check for session cookie
     let cookie_header = request
         .headers()
@@ -107,8 +239,14 @@ pub async fn session_auth_middleware(
 
     if let Some(cookies) = cookie_header {
         if let Some(token) = extract_session_token(cookies) {
-            if session_store.validate(&token).await.is_some() {
-                return next.run(request).await;
+            let ip = client_ip(&request);
+            let ttl = config_manager.dashboard_session_ttl().await;
+            if let Some(username) = session_store.touch(&token, ip, ttl).await {
+                let user = DashboardUser(username);
+                request.extensions_mut().insert(user.clone());
+                let mut response = next.run(request).await;
+                response.extensions_mut().insert(user);
+                return response;
             }
         }
     }
@@ -116,12 +254,62 @@ pub async fn session_auth_middleware(
     unauthorized_response()
 }
 
+/// Middleware attributing the configuration changes a request makes to its
+/// dashboard user (`-` without one) in [`ConfigManager::provenance`].
+pub async fn config_actor_middleware(request: Request, next: Next) -> Response {
+    let user = request
+        .extensions()
+        .get::<DashboardUser>()
+        .map_or_else(|| "-".to_string(), |DashboardUser(u)| u.clone());
+    with_config_actor(user, next.run(request)).await
+}
+
+/// Middleware that rejects clients outside `dashboard.allowed_ips`.
+///
+/// Checks the client IP derived from `dashboard.trusted_proxies`.
+pub async fn ip_filter_middleware(
+    config_manager: ConfigManager,
+    stats: Arc<Stats>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(client_ip) = client_ip(&request) else {
+        return next.run(request).await;
+    };
+    let client_ip = client_ip.to_string();
+
+    if config_manager.is_dashboard_ip_allowed(&client_ip).await {
+        return next.run(request).await;
+    }
+
+    warn!("Dashboard access denied for IP: {}", client_ip);
+    stats
+        .record_denied(
+            DeniedAttempt::new(
+                client_ip,
+                "dashboard",
+                Some(request.uri().path().to_string()),
+                "IP not in dashboard allowlist",
+            )
+            .with_code(ErrorCode::ClientIpDenied),
+        )
+        .await;
+
+    (
+        StatusCode::FORBIDDEN,
+        ErrorResponse::with_code(ErrorCode::ClientIpDenied, "Access denied"),
+    )
+        .into_response()
+}
+
 /// Check if a path is public (doesn't require auth).
 fn is_public_path(path: &str) -> bool {
     // Auth endpoints are public
     path == "/api/auth/login"
         || path == "/api/auth/check"
         || path == "/api/auth/logout"
+        // Refused once an administrator exists
+        || path == "/api/setup"
         // Static files are public (login page needs to load)
         || path == "/"
         || path == "/index.html"
@@ -148,8 +336,7 @@ fn extract_session_token(cookies: &str) -> Option<String> {
 fn unauthorized_response() -> Response {
     (
         StatusCode::UNAUTHORIZED,
-        [(header::CONTENT_TYPE, "application/json")],
-        r#"{"success":false,"error":"Authentication required"}"#,
+        ErrorResponse::with_code(ErrorCode::Unauthorized, "Authentication required"),
     )
         .into_response()
 }
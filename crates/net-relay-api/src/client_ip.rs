@@ -0,0 +1,142 @@
+//! Client address of API requests behind reverse proxies (`dashboard.trusted_proxies`).
+//!
+//! Forwarding headers are only read when the TCP peer is a trusted proxy, so
+//! other clients can't pick their own address. The forwarded chain is walked
+//! from the right (the hop closest to us), skipping trusted proxies; the first
+//! address that isn't one is the client. An unreadable or hidden hop ends the
+//! walk at the last proxy that was trusted.
+//!
+//! The header is `dashboard.real_ip_header` when configured, otherwise
+//! `Forwarded` (RFC 7239) or, without it, `X-Forwarded-For`.
+
+use axum::extract::{ConnectInfo, Request};
+use axum::http::HeaderMap;
+use axum::middleware::Next;
+use axum::response::Response;
+use net_relay_core::config::ip_in_list;
+use net_relay_core::ConfigManager;
+use std::net::{IpAddr, SocketAddr};
+
+/// Client address of a request, set by [`client_ip_middleware`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Client address of `request` (`None` without a known peer).
+pub fn client_ip(request: &Request) -> Option<IpAddr> {
+    request
+        .extensions()
+        .get::<ClientIp>()
+        .map(|&ClientIp(ip)| ip)
+}
+
+/// Middleware that derives the client address once for every later layer and handler.
+pub async fn client_ip_middleware(
+    config_manager: ConfigManager,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if let Some(&ConnectInfo(peer)) = request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        let dashboard = config_manager.get_dashboard().await;
+        let ip = resolve_client_ip(
+            peer.ip(),
+            request.headers(),
+            &dashboard.trusted_proxies,
+            dashboard.real_ip_header.as_deref(),
+        );
+        request.extensions_mut().insert(ClientIp(ip));
+    }
+    next.run(request).await
+}
+
+/// Client address of a request from `peer` carrying `headers`.
+pub fn resolve_client_ip(
+    peer: IpAddr,
+    headers: &HeaderMap,
+    trusted_proxies: &[String],
+    real_ip_header: Option<&str>,
+) -> IpAddr {
+    let is_trusted = |ip: IpAddr| ip_in_list(&ip.to_string(), trusted_proxies);
+    let mut client = peer.to_canonical();
+    if !is_trusted(client) {
+        return client;
+    }
+
+    for hop in forwarded_chain(headers, real_ip_header).into_iter().rev() {
+        let Some(ip) = hop else {
+            break;
+        };
+        client = ip.to_canonical();
+        if !is_trusted(client) {
+            break;
+        }
+    }
+    client
+}
+
+/// Forwarded addresses, leftmost (original client) first; `None` for hops
+/// that are unknown, obfuscated or malformed.
+fn forwarded_chain(headers: &HeaderMap, real_ip_header: Option<&str>) -> Vec<Option<IpAddr>> {
+    let values = |name: &str| -> Vec<&str> {
+        headers
+            .get_all(name)
+            .iter()
+            // A header that isn't valid text hides every hop it lists
+            .map(|value| value.to_str().unwrap_or("unknown"))
+            .collect()
+    };
+    let list = |name: &str| -> Vec<Option<IpAddr>> {
+        values(name)
+            .into_iter()
+            .flat_map(|value| value.split(','))
+            .map(parse_node)
+            .collect()
+    };
+
+    if let Some(name) = real_ip_header {
+        return list(name);
+    }
+    let forwarded = values("forwarded");
+    if forwarded.is_empty() {
+        return list("x-forwarded-for");
+    }
+    forwarded
+        .into_iter()
+        .flat_map(|value| value.split(','))
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, node)| parse_node(node))
+        })
+        .collect()
+}
+
+/// Address of a node: `192.0.2.1`, `192.0.2.1:8080`, `2001:db8::1`,
+/// `[2001:db8::1]:8080`, optionally quoted.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim();
+    let node = node
+        .strip_prefix('"')
+        .and_then(|node| node.strip_suffix('"'))
+        .unwrap_or(node);
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        let (ip, port) = rest.split_once(']')?;
+        if !(port.is_empty() || port.strip_prefix(':').is_some_and(is_port)) {
+            return None;
+        }
+        return ip.parse::<std::net::Ipv6Addr>().ok().map(IpAddr::V6);
+    }
+    let (ip, port) = node.split_once(':')?;
+    if !is_port(port) {
+        return None;
+    }
+    ip.parse::<std::net::Ipv4Addr>().ok().map(IpAddr::V4)
+}
+
+fn is_port(port: &str) -> bool {
+    port.parse::<u16>().is_ok()
+}
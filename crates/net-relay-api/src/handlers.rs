@@ -1,17 +1,44 @@
 //! API route handlers.
 
 use axum::extract::State;
-use axum::http::header::SET_COOKIE;
-use axum::http::HeaderMap;
+use axum::http::header::{self, SET_COOKIE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::Json;
-use net_relay_core::stats::{AggregatedStats, ConnectionStats, Stats, UserStats};
+use chrono::{DateTime, Utc};
+use net_relay_core::alerts::AlertStatus;
+use net_relay_core::backup::{self, ConfigBackup};
+use net_relay_core::capture::{CaptureDirection, CaptureError, CaptureInfo};
+use net_relay_core::config::{
+    hash_password, new_rule_id, SecurityConfig, DEFAULT_CREDENTIALS, MAX_CONNECT_RETRIES,
+    MAX_RELAY_BUFFER_SIZE, MIN_PASSWORD_LENGTH, MIN_RELAY_BUFFER_SIZE, TOKEN_USERNAME,
+};
+use net_relay_core::connection::{normalize_note, normalize_tags, Protocol};
+use net_relay_core::hostname;
+use net_relay_core::proxy::RelayBufferStats;
+use net_relay_core::rule_list::{self, RuleListEntry};
+use net_relay_core::runtime::{self, ListenerQueue, ProcessMetrics, TokioMetrics};
+use net_relay_core::stats::{
+    AggregatedStats, ConnectionStats, DeniedAttempt, HistoryPage, HistorySince, HistoryWindow,
+    ListenerStats, RuleHits, Stats, StatsSizes, TrafficStats, UserStats,
+};
+use net_relay_core::tls::{CertSource, CertStatus, CertStore, DashboardCerts, DashboardTlsStatus};
+use net_relay_core::upstream::{FailoverEvent, UpstreamStatus};
 use net_relay_core::{
-    AccessControlConfig, AccessRule, Config, ConfigManager, ConnectionInfo, ServerConfig, User,
+    AccessControlConfig, AccessRule, ApiRateLimitConfig, BuildInfo, Config, ConfigLock,
+    ConfigManager, ConfigProfile, ConfigProvenance, ConfigSaveError, ConnectionInfo,
+    DashboardConfig, DashboardSecurityWarning, DnsStats, ErrorCode, IpDecision, LimitsConfig,
+    ListenerInfo, PhaseLatency, QuotaPeriod, QuotaStatus, RuleAction, RuleMode, SaveStatus,
+    ServerConfig, ServerState, StatsConfig, TargetConnectStats, TargetDecision, User, UserToken,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use crate::auth::SessionStore;
+use crate::auth::{token_prefix, DashboardUser, SessionInfo, SessionStore, TOKEN_PREFIX_LEN};
+use crate::client_ip::ClientIp;
+use crate::request_log::{ApiMetrics, EndpointLatency};
 
 /// Shared application state.
 #[derive(Clone)]
@@ -19,6 +46,14 @@ pub struct AppState {
     pub stats: Arc<Stats>,
     pub config_manager: ConfigManager,
     pub session_store: SessionStore,
+    pub api_metrics: ApiMetrics,
+    pub services: ActiveServices,
+    /// API/Dashboard certificates when served over HTTPS.
+    pub tls: Option<Arc<DashboardCerts>>,
+    /// Certificates TLS listeners load from files.
+    pub certs: Arc<CertStore>,
+    /// Addresses the listeners are bound to.
+    pub server: Arc<ServerState>,
 }
 
 /// API response wrapper.
@@ -45,6 +80,12 @@ impl<T> ApiResponse<T> {
 pub struct ErrorResponse {
     pub success: bool,
     pub error: String,
+    /// Stable error code (see [`ErrorCode`]).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+    /// Per-field validation errors.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
 }
 
 impl ErrorResponse {
@@ -52,7 +93,90 @@ impl ErrorResponse {
         Json(Self {
             success: false,
             error: error.into(),
+            code: None,
+            errors: Vec::new(),
+        })
+    }
+
+    /// Error carrying a stable code.
+    pub fn with_code(code: ErrorCode, error: impl Into<String>) -> Json<Self> {
+        Json(Self {
+            success: false,
+            error: error.into(),
+            code: Some(code),
+            errors: Vec::new(),
+        })
+    }
+
+    /// Validation failure listing what is wrong with each field.
+    pub fn validation(errors: Vec<FieldError>) -> Json<Self> {
+        let error = errors
+            .iter()
+            .map(|e| format!("{} {}", e.field, e.message))
+            .collect::<Vec<_>>()
+            .join("; ");
+        Json(Self {
+            success: false,
+            error,
+            code: Some(ErrorCode::ValidationFailed),
+            errors,
+        })
+    }
+}
+
+/// Validation error of a single request field.
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Response to a failed configuration update. A change that was applied but
+/// could not be saved is reported with `config_not_saved`; otherwise the error
+/// is prefixed with `context`.
+fn update_failed(context: &str, e: anyhow::Error) -> (StatusCode, Json<ErrorResponse>) {
+    let body = match e.downcast_ref::<ConfigSaveError>() {
+        Some(e) => ErrorResponse::with_code(ErrorCode::ConfigNotSaved, e.to_string()),
+        None => ErrorResponse::new(format!("{}: {}", context, e)),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, body)
+}
+
+/// Refuse a request for data that isn't kept while `stats.enabled` is off.
+fn require_stats(state: &AppState) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if state.stats.is_enabled() {
+        return Ok(());
+    }
+    Err((
+        StatusCode::NOT_FOUND,
+        ErrorResponse::with_code(
+            ErrorCode::StatsDisabled,
+            "Statistics are disabled (stats.enabled = false); connections and history are not kept",
+        ),
+    ))
+}
+
+/// Check `password` (the request's `field`) against `security.password_policy`.
+async fn check_password(
+    state: &AppState,
+    field: &str,
+    password: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let errors: Vec<FieldError> = state
+        .config_manager
+        .password_policy()
+        .await
+        .check(password)
+        .into_iter()
+        .map(|message| FieldError {
+            field: field.to_string(),
+            message,
         })
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err((StatusCode::BAD_REQUEST, ErrorResponse::validation(errors)))
     }
 }
 
@@ -61,6 +185,60 @@ impl ErrorResponse {
 pub struct HealthResponse {
     pub status: String,
     pub version: String,
+    pub services: ActiveServices,
+    /// Accept counters of the proxy and tunnel listeners.
+    pub listeners: Vec<ListenerStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<DashboardTlsStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<UpstreamHealth>,
+    /// Set while configuration edits are refused.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_lock: Option<ConfigLock>,
+    /// Set while the running configuration differs from the config file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_save: Option<SaveStatus>,
+    /// Certificates loaded from files (see `POST /api/tls/reload`).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub certificates: Vec<CertStatus>,
+    /// Certificates expiring within `server.tls_expiry_warning_days`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub expiring_certificates: Vec<CertExpiry>,
+    /// Set while anyone reaching the dashboard can use it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub security_warning: Option<DashboardSecurityWarning>,
+}
+
+/// Expiry of a certificate presented by a TLS listener.
+#[derive(Debug, Clone, Serialize)]
+pub struct CertExpiry {
+    /// Listener presenting the certificate (`api` or `tunnel`).
+    pub listener: String,
+    pub not_after: DateTime<Utc>,
+    /// Whole days left, negative once expired.
+    pub days_left: i64,
+}
+
+/// Upstream relay summary in the health check.
+#[derive(Debug, Serialize)]
+pub struct UpstreamHealth {
+    /// Relay new connections use (`None` when every relay is down).
+    pub active: Option<String>,
+    /// Whether connections are using a relay other than the most preferred one.
+    pub failed_over: bool,
+    pub healthy: usize,
+    pub total: usize,
+}
+
+/// Services started by this instance.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ActiveServices {
+    pub socks5: bool,
+    pub http: bool,
+    pub api: bool,
+    pub tunnel: bool,
+    /// SOCKS5 and HTTP on `server.combined_port`.
+    pub combined: bool,
 }
 
 /// Stats response.
@@ -74,16 +252,231 @@ pub struct StatsResponse {
 #[derive(Debug, Deserialize)]
 pub struct HistoryQuery {
     pub limit: Option<usize>,
+    /// Only history entries carrying this tag, or of users carrying it.
+    pub tag: Option<String>,
+    /// Only history entries after this cursor (`seq`).
+    pub since_id: Option<u64>,
+    /// Only history entries closed after this time.
+    pub since_time: Option<DateTime<Utc>>,
+}
+
+/// Build and runtime details for support requests.
+#[derive(Debug, Serialize)]
+pub struct VersionResponse {
+    /// Features include those of the server binary.
+    #[serde(flatten)]
+    pub build: BuildInfo,
+    /// Kernel release (Linux only).
+    pub kernel: Option<String>,
+    pub pid: u32,
+    pub started_at: DateTime<Utc>,
+    pub uptime_secs: i64,
+    pub subsystems: Vec<Subsystem>,
+}
+
+/// An optional part of the server.
+#[derive(Debug, Serialize)]
+pub struct Subsystem {
+    pub name: &'static str,
+    /// Built into this binary.
+    pub compiled: bool,
+    /// Turned on in the running configuration.
+    pub enabled: bool,
+}
+
+/// Version, build and runtime environment details (the detailed variant of
+/// [`health`]).
+pub async fn get_version(State(state): State<AppState>) -> Json<ApiResponse<VersionResponse>> {
+    let mut build = BuildInfo::current();
+    build.features.extend(state.server.features());
+    build.features.sort_unstable();
+    build.features.dedup();
+    let compiled = |feature| build.features.contains(&feature);
+
+    let config = state.config_manager.get().await;
+    let subsystems = vec![
+        Subsystem {
+            name: "tls",
+            compiled: true,
+            enabled: state.tls.is_some() || !state.certs.statuses().is_empty(),
+        },
+        Subsystem {
+            name: "acme",
+            compiled: compiled("acme"),
+            enabled: compiled("acme") && config.acme.enabled,
+        },
+        // Served on this API whenever it runs
+        Subsystem {
+            name: "prometheus",
+            compiled: true,
+            enabled: true,
+        },
+        Subsystem {
+            name: "statsd",
+            compiled: true,
+            enabled: config.metrics.statsd.enabled,
+        },
+        Subsystem {
+            name: "otlp_telemetry",
+            compiled: compiled("telemetry"),
+            enabled: compiled("telemetry") && config.telemetry.enabled,
+        },
+    ];
+
+    let started_at = state.stats.started_at();
+    ApiResponse::ok(VersionResponse {
+        kernel: std::fs::read_to_string("/proc/sys/kernel/osrelease")
+            .ok()
+            .map(|release| release.trim().to_string()),
+        pid: std::process::id(),
+        started_at,
+        uptime_secs: (Utc::now() - started_at).num_seconds(),
+        subsystems,
+        build,
+    })
 }
 
+/// Seconds a listener's last accept error keeps the health status degraded.
+const ACCEPT_ERROR_WINDOW_SECS: i64 = 60;
+
 /// Health check endpoint.
-pub async fn health() -> Json<ApiResponse<HealthResponse>> {
+pub async fn health(State(state): State<AppState>) -> Json<ApiResponse<HealthResponse>> {
+    let tls = state.tls.as_ref().map(|certs| certs.status());
+    // A failed certificate issuance or renewal needs attention before it expires
+    let mut degraded = tls
+        .as_ref()
+        .is_some_and(|tls| !tls.ready || tls.last_error.is_some());
+
+    let relays = state.config_manager.upstream_pool().statuses();
+    let upstream = (!relays.is_empty()).then(|| UpstreamHealth {
+        active: relays
+            .iter()
+            .find(|relay| relay.active)
+            .map(|relay| relay.address.clone()),
+        failed_over: !relays[0].active,
+        healthy: relays.iter().filter(|relay| relay.healthy).count(),
+        total: relays.len(),
+    });
+    degraded |= upstream
+        .as_ref()
+        .is_some_and(|upstream| upstream.failed_over);
+
+    // Listeners failing to accept (e.g. out of file descriptors) turn clients away
+    let listeners = state.stats.get_listener_stats();
+    let recent = Utc::now() - chrono::Duration::seconds(ACCEPT_ERROR_WINDOW_SECS);
+    degraded |= listeners
+        .iter()
+        .any(|listener| listener.last_error_at.is_some_and(|at| at > recent));
+
+    // Changes made since a failed save are lost on restart
+    let config_save = Some(state.config_manager.save_status()).filter(|status| status.dirty);
+    degraded |= config_save.is_some();
+
+    // Expiring certificates need replacing, failed reloads leave the old one in use
+    let certificates = state.certs.statuses();
+    degraded |= certificates.iter().any(|cert| cert.last_error.is_some());
+    let warning_days = state
+        .config_manager
+        .get_server()
+        .await
+        .tls_expiry_warning_days;
+    let now = Utc::now();
+    let expiring_certificates: Vec<_> = certificate_expiries(&state)
+        .into_iter()
+        .filter(|cert| cert.not_after - now <= chrono::Duration::days(warning_days.into()))
+        .collect();
+    degraded |= !expiring_certificates.is_empty();
+
     ApiResponse::ok(HealthResponse {
-        status: "healthy".to_string(),
+        status: if degraded { "degraded" } else { "healthy" }.to_string(),
         version: env!("CARGO_PKG_VERSION").to_string(),
+        services: state.services,
+        listeners,
+        tls,
+        upstream,
+        config_lock: state.config_manager.config_lock(),
+        config_save,
+        certificates,
+        expiring_certificates,
+        security_warning: state
+            .config_manager
+            .get_dashboard()
+            .await
+            .security_warning(),
     })
 }
 
+/// Expiry of every certificate in use: the ones loaded from files and the
+/// API/Dashboard certificate issued through ACME.
+fn certificate_expiries(state: &AppState) -> Vec<CertExpiry> {
+    let mut expiries: Vec<_> = state
+        .certs
+        .statuses()
+        .into_iter()
+        .map(|cert| (cert.listener, cert.not_after))
+        .collect();
+    let acme = state
+        .tls
+        .as_ref()
+        .map(|certs| certs.status())
+        .filter(|status| status.source == CertSource::Acme);
+    if let Some(not_after) = acme.and_then(|status| status.not_after) {
+        expiries.push(("api".to_string(), not_after));
+    }
+    let now = Utc::now();
+    expiries
+        .into_iter()
+        .map(|(listener, not_after)| CertExpiry {
+            listener,
+            not_after,
+            days_left: (not_after - now).num_days(),
+        })
+        .collect()
+}
+
+/// Reload the certificates of all TLS listeners from their files.
+///
+/// A certificate that fails to load is reported and the previous one stays
+/// in use.
+pub async fn reload_tls(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+) -> Result<Json<ApiResponse<Vec<CertStatus>>>, (StatusCode, Json<ErrorResponse>)> {
+    let before = state.certs.statuses();
+    if before.is_empty() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            ErrorResponse::new("No TLS certificates are loaded from files"),
+        ));
+    }
+    let certificates = state.certs.reload();
+    let failed: Vec<_> = certificates
+        .iter()
+        .filter_map(|cert| Some(format!("{}: {}", cert.listener, cert.last_error.as_ref()?)))
+        .collect();
+    tracing::warn!(
+        target: "net_relay_api::audit",
+        user = %user.as_ref().map_or("-", |axum::Extension(DashboardUser(u))| u.as_str()),
+        client_ip = %audit_ip(client_ip),
+        failed = failed.len(),
+        "TLS certificates reloaded"
+    );
+    if !failed.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::with_code(
+                ErrorCode::ConfigError,
+                format!(
+                    "Kept the previous certificate after failing to reload {}",
+                    failed.join("; ")
+                ),
+            ),
+        ));
+    }
+    Ok(ApiResponse::ok(certificates))
+}
+
 /// Get server statistics.
 pub async fn get_stats(State(state): State<AppState>) -> Json<ApiResponse<StatsResponse>> {
     let aggregated = state.stats.get_aggregated().await;
@@ -95,228 +488,1679 @@ pub async fn get_stats(State(state): State<AppState>) -> Json<ApiResponse<StatsR
     })
 }
 
+/// Active connections query parameters.
+#[derive(Debug, Deserialize)]
+pub struct ConnectionsQuery {
+    /// Only return connections of this user.
+    pub user: Option<String>,
+    /// Only return connections carrying this tag, or of users carrying it.
+    pub tag: Option<String>,
+    /// Only return connections suspected dead: relays that are gone, or
+    /// that outlived `limits.idle_timeout` without data.
+    #[serde(default)]
+    pub stale: bool,
+}
+
+/// How far past `limits.idle_timeout` a silent relay is reported as stale;
+/// the relay checks its idle time once a second.
+const STALE_IDLE_GRACE_SECS: u64 = 10;
+
 /// Get active connections.
 pub async fn get_connections(
     State(state): State<AppState>,
-) -> Json<ApiResponse<Vec<ConnectionInfo>>> {
-    let connections = state.stats.get_active().await;
-    ApiResponse::ok(connections)
+    axum::extract::Query(query): axum::extract::Query<ConnectionsQuery>,
+) -> Result<Json<ApiResponse<Vec<ConnectionInfo>>>, (StatusCode, Json<ErrorResponse>)> {
+    require_stats(&state)?;
+    let mut connections = if query.stale {
+        let idle_timeout = state.config_manager.get_limits().await.idle_timeout;
+        let idle = (idle_timeout > 0)
+            .then(|| std::time::Duration::from_secs(idle_timeout + STALE_IDLE_GRACE_SECS));
+        let mut stale = state.stats.get_stale(idle).await;
+        if let Some(user) = &query.user {
+            stale.retain(|c| c.username.as_ref() == Some(user));
+        }
+        stale
+    } else {
+        match &query.user {
+            Some(user) => state.stats.get_active_for_user(user).await,
+            None => state.stats.get_active().await,
+        }
+    };
+    if let Some(tag) = query.tag {
+        let users = tagged_users(&state.config_manager, &tag).await;
+        connections.retain(|c| {
+            c.tags.contains(&tag) || c.username.as_ref().is_some_and(|name| users.contains(name))
+        });
+    }
+    Ok(ApiResponse::ok(connections))
 }
 
-/// Get connection history.
-pub async fn get_history(
-    State(state): State<AppState>,
-    axum::extract::Query(query): axum::extract::Query<HistoryQuery>,
-) -> Json<ApiResponse<Vec<ConnectionStats>>> {
-    let history = state.stats.get_history(query.limit).await;
-    ApiResponse::ok(history)
+/// Body of `PATCH /api/connections/{id}`; omitted fields are left unchanged.
+#[derive(Debug, Deserialize)]
+pub struct AnnotateConnectionRequest {
+    /// Replaces the connection's tags.
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    /// `null` or an empty string removes the note.
+    #[serde(default, deserialize_with = "present")]
+    pub note: Option<Option<String>>,
 }
 
-// ==================== Configuration API ====================
-
-/// Get current configuration.
-pub async fn get_config(State(state): State<AppState>) -> Json<ApiResponse<Config>> {
-    let config = state.config_manager.get().await;
-    ApiResponse::ok(config)
+/// Normalized tags and note of a request; `None` leaves them unchanged.
+struct Annotations {
+    tags: Option<Vec<String>>,
+    note: Option<Option<String>>,
 }
 
-/// Get access control configuration only.
-pub async fn get_access_control(
-    State(state): State<AppState>,
-) -> Json<ApiResponse<AccessControlConfig>> {
-    let config = state.config_manager.get().await;
-    ApiResponse::ok(config.access_control)
+/// Normalize tags and a note from a request, or report the invalid fields.
+fn check_annotations(
+    tags: Option<Vec<String>>,
+    note: Option<Option<String>>,
+) -> Result<Annotations, (StatusCode, Json<ErrorResponse>)> {
+    let mut errors = Vec::new();
+    let tags = tags.and_then(|tags| match normalize_tags(&tags) {
+        Ok(tags) => Some(tags),
+        Err(message) => {
+            errors.push(FieldError {
+                field: "tags".to_string(),
+                message,
+            });
+            None
+        }
+    });
+    let note = note.and_then(|note| match note.as_deref().map(normalize_note) {
+        None => Some(None),
+        Some(Ok(note)) => Some(note),
+        Some(Err(message)) => {
+            errors.push(FieldError {
+                field: "note".to_string(),
+                message,
+            });
+            None
+        }
+    });
+    if !errors.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, ErrorResponse::validation(errors)));
+    }
+    Ok(Annotations { tags, note })
 }
 
-/// Update access control configuration.
-pub async fn update_access_control(
+/// Tag or annotate an active connection (the tags carry into its history
+/// entry) or one still in the history.
+pub async fn annotate_connection(
     State(state): State<AppState>,
-    Json(access_control): Json<AccessControlConfig>,
-) -> Json<ApiResponse<AccessControlConfig>> {
-    match state
-        .config_manager
-        .update_access_control(access_control.clone())
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    Json(req): Json<AnnotateConnectionRequest>,
+) -> Result<Json<ApiResponse<ConnectionInfo>>, (StatusCode, Json<ErrorResponse>)> {
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            ErrorResponse::new(format!("Connection not found: {}", id)),
+        )
+    };
+    let conn_id = uuid::Uuid::parse_str(&id).map_err(|_| not_found())?;
+    let Annotations { tags, note } = check_annotations(req.tags, req.note)?;
+
+    let connection = state
+        .stats
+        .annotate_connection(conn_id, tags, note)
         .await
-    {
-        Ok(_) => ApiResponse::ok(access_control),
-        Err(e) => Json(ApiResponse {
-            success: false,
-            data: access_control,
-            message: Some(format!("Failed to save: {}", e)),
-        }),
-    }
+        .ok_or_else(not_found)?;
+    tracing::warn!(
+        target: "net_relay_api::audit",
+        user = %user.as_ref().map_or("-", |axum::Extension(DashboardUser(u))| u.as_str()),
+        client_ip = %audit_ip(client_ip),
+        connection = %conn_id,
+        tags = ?connection.tags,
+        note = connection.note.as_deref().unwrap_or("-"),
+        "Connection annotated"
+    );
+    Ok(ApiResponse::ok(connection))
 }
 
-/// Add IP to blacklist.
+/// Anomalous connection query parameters.
 #[derive(Debug, Deserialize)]
-pub struct IpListRequest {
-    pub ip: String,
+pub struct AnomaliesQuery {
+    /// Report connections open at least this many seconds (default 3600).
+    pub min_age: Option<u64>,
+
+    /// Report connections without traffic for at least this many seconds (default 300).
+    pub max_idle: Option<u64>,
+
+    /// Terminate every reported connection (requires a dashboard session).
+    #[serde(default)]
+    pub kill: bool,
 }
 
-pub async fn add_ip_blacklist(
-    State(state): State<AppState>,
-    Json(req): Json<IpListRequest>,
-) -> Json<ApiResponse<AccessControlConfig>> {
-    let mut config = state.config_manager.get().await;
-    if !config.access_control.ip_blacklist.contains(&req.ip) {
-        config.access_control.ip_blacklist.push(req.ip);
-    }
-    let _ = state
-        .config_manager
-        .update_access_control(config.access_control.clone())
-        .await;
-    ApiResponse::ok(config.access_control)
+/// An active connection flagged as long-lived or idle.
+#[derive(Debug, Serialize)]
+pub struct AnomalousConnection {
+    #[serde(flatten)]
+    pub info: ConnectionInfo,
+
+    /// Seconds since the connection was opened.
+    pub age_secs: i64,
+
+    /// Seconds since data last moved (or since it became active, if never).
+    pub idle_secs: i64,
 }
 
-pub async fn remove_ip_blacklist(
-    State(state): State<AppState>,
-    Json(req): Json<IpListRequest>,
-) -> Json<ApiResponse<AccessControlConfig>> {
-    let mut config = state.config_manager.get().await;
-    config
-        .access_control
-        .ip_blacklist
-        .retain(|ip| ip != &req.ip);
-    let _ = state
-        .config_manager
-        .update_access_control(config.access_control.clone())
-        .await;
-    ApiResponse::ok(config.access_control)
+/// Long-lived / idle connection report.
+#[derive(Debug, Serialize)]
+pub struct AnomalyReport {
+    /// Age threshold used, in seconds.
+    pub min_age: u64,
+
+    /// Idle threshold used, in seconds.
+    pub max_idle: u64,
+
+    /// Number of matching connections.
+    pub count: usize,
+
+    /// Bytes relayed so far by the matching connections.
+    pub total_bytes: u64,
+
+    /// Connections terminated (only with `kill=true`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub killed: Option<usize>,
+
+    /// Matching connections, oldest first.
+    pub connections: Vec<AnomalousConnection>,
 }
 
-pub async fn add_ip_whitelist(
+/// Report active connections older than `min_age` or idle longer than `max_idle`.
+pub async fn get_connection_anomalies(
     State(state): State<AppState>,
-    Json(req): Json<IpListRequest>,
-) -> Json<ApiResponse<AccessControlConfig>> {
-    let mut config = state.config_manager.get().await;
-    if !config.access_control.ip_whitelist.contains(&req.ip) {
-        config.access_control.ip_whitelist.push(req.ip);
+    user: Option<axum::Extension<DashboardUser>>,
+    axum::extract::Query(query): axum::extract::Query<AnomaliesQuery>,
+) -> Result<Json<ApiResponse<AnomalyReport>>, (StatusCode, Json<ErrorResponse>)> {
+    require_stats(&state)?;
+    if query.kill && user.is_none() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            ErrorResponse::new("kill requires a dashboard admin session"),
+        ));
     }
-    let _ = state
-        .config_manager
-        .update_access_control(config.access_control.clone())
-        .await;
-    ApiResponse::ok(config.access_control)
-}
 
-pub async fn remove_ip_whitelist(
-    State(state): State<AppState>,
-    Json(req): Json<IpListRequest>,
-) -> Json<ApiResponse<AccessControlConfig>> {
-    let mut config = state.config_manager.get().await;
-    config
-        .access_control
-        .ip_whitelist
-        .retain(|ip| ip != &req.ip);
-    let _ = state
-        .config_manager
-        .update_access_control(config.access_control.clone())
-        .await;
-    ApiResponse::ok(config.access_control)
+    let min_age = query.min_age.unwrap_or(3600);
+    let max_idle = query.max_idle.unwrap_or(300);
+    let now = Utc::now();
+    let connections: Vec<AnomalousConnection> = state
+        .stats
+        .get_active()
+        .await
+        .into_iter()
+        .filter_map(|info| {
+            let age_secs = (now - info.connected_at).num_seconds();
+            let last_seen = info
+                .last_activity_at
+                .or(info.active_at)
+                .unwrap_or(info.connected_at);
+            let idle_secs = (now - last_seen).num_seconds();
+            (age_secs >= min_age as i64 || idle_secs >= max_idle as i64).then_some(
+                AnomalousConnection {
+                    info,
+                    age_secs,
+                    idle_secs,
+                },
+            )
+        })
+        .collect();
+
+    let killed = query.kill.then(|| {
+        connections
+            .iter()
+            .filter(|c| state.stats.kill_connection(c.info.id))
+            .count()
+    });
+
+    Ok(ApiResponse::ok(AnomalyReport {
+        min_age,
+        max_idle,
+        count: connections.len(),
+        total_bytes: connections
+            .iter()
+            .map(|c| c.info.bytes_sent + c.info.bytes_received)
+            .sum(),
+        killed,
+        connections,
+    }))
 }
 
-/// Add access rule.
-pub async fn add_rule(
-    State(state): State<AppState>,
-    Json(rule): Json<AccessRule>,
-) -> Json<ApiResponse<AccessControlConfig>> {
-    let mut config = state.config_manager.get().await;
-    config.access_control.rules.push(rule);
-    let _ = state
-        .config_manager
-        .update_access_control(config.access_control.clone())
-        .await;
-    ApiResponse::ok(config.access_control)
+/// Ban query parameters.
+#[derive(Debug, Deserialize)]
+pub struct BanQuery {
+    /// Ban length in seconds; omit for a permanent blacklist entry.
+    pub duration: Option<u64>,
 }
 
-/// Remove access rule by index.
-#[derive(Debug, Deserialize)]
-pub struct RemoveRuleRequest {
-    pub index: usize,
+/// Result of banning a connection's client.
+#[derive(Debug, Serialize)]
+pub struct BanResponse {
+    /// Banned client IP.
+    pub ip: String,
+
+    /// Whether the IP was added to the blacklist.
+    pub permanent: bool,
+
+    /// When a temporary ban ends.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+
+    /// Active connections from the IP that were terminated.
+    pub terminated: usize,
 }
 
-pub async fn remove_rule(
+/// Ban a connection's client IP and kill all of its active connections.
+pub async fn ban_connection(
     State(state): State<AppState>,
-    Json(req): Json<RemoveRuleRequest>,
-) -> Json<ApiResponse<AccessControlConfig>> {
-    let mut config = state.config_manager.get().await;
-    if req.index < config.access_control.rules.len() {
-        config.access_control.rules.remove(req.index);
-    }
-    let _ = state
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::Query(query): axum::extract::Query<BanQuery>,
+) -> Result<Json<ApiResponse<BanResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            ErrorResponse::new(format!("Connection not found: {}", id)),
+        )
+    };
+    let conn_id = uuid::Uuid::parse_str(&id).map_err(|_| not_found())?;
+    let connection = state.stats.get_connection(conn_id).ok_or_else(not_found)?;
+
+    let ip = connection
+        .client_addr
+        .parse::<std::net::SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| connection.client_addr.parse())
+        .map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                ErrorResponse::new(format!(
+                    "Connection has no client IP: {}",
+                    connection.client_addr
+                )),
+            )
+        })?
+        .to_canonical();
+
+    let duration = match query.duration {
+        Some(0) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                ErrorResponse::new("duration must be at least 1 second"),
+            ))
+        }
+        duration => duration.map(std::time::Duration::from_secs),
+    };
+    let expires_at = state
         .config_manager
-        .update_access_control(config.access_control.clone())
-        .await;
-    ApiResponse::ok(config.access_control)
+        .ban_ip(&ip.to_string(), duration)
+        .await
+        .map_err(|e| update_failed(&format!("Failed to ban {}", ip), e))?;
+    let terminated = state.stats.kill_client_ip(ip);
+    tracing::info!(
+        "Banned {} ({}), terminated {} connections",
+        ip,
+        expires_at.map_or("permanent".to_string(), |t| format!("until {}", t)),
+        terminated
+    );
+
+    Ok(ApiResponse::ok(BanResponse {
+        ip: ip.to_string(),
+        permanent: expires_at.is_none(),
+        expires_at,
+        terminated,
+    }))
 }
 
-// ==================== Security & User Management API ====================
+/// Default `bytes` of a capture.
+const DEFAULT_CAPTURE_BYTES: usize = 64 * 1024;
 
-/// Security configuration response (without exposing passwords).
-#[derive(Debug, Serialize)]
-pub struct SecurityResponse {
-    pub auth_enabled: bool,
-    pub users: Vec<UserInfo>,
-    pub user_count: usize,
+/// Options of a new capture.
+#[derive(Debug, Deserialize)]
+pub struct StartCaptureQuery {
+    /// Bytes to capture, up to `debug.capture_max_bytes` (default 64 KiB).
+    pub bytes: Option<usize>,
+    #[serde(default)]
+    pub direction: CaptureDirection,
 }
 
-/// User info without password.
-#[derive(Debug, Serialize)]
-pub struct UserInfo {
-    pub username: String,
-    pub enabled: bool,
-    pub description: Option<String>,
-    pub bandwidth_limit: u64,
-    pub connection_limit: u32,
+/// Download format of a capture.
+#[derive(Debug, Deserialize)]
+pub struct CaptureDownloadQuery {
+    /// `hex` (default) or `raw`.
+    pub format: Option<String>,
 }
 
-impl From<&User> for UserInfo {
-    fn from(user: &User) -> Self {
-        Self {
-            username: user.username.clone(),
-            enabled: user.enabled,
-            description: user.description.clone(),
-            bandwidth_limit: user.bandwidth_limit,
-            connection_limit: user.connection_limit,
-        }
+/// Refuse captures unless `debug.capture_enabled` is set and the caller is
+/// a dashboard administrator; returns the administrator and the connection.
+async fn capture_access(
+    state: &AppState,
+    user: Option<axum::Extension<DashboardUser>>,
+    id: &str,
+) -> Result<(String, uuid::Uuid), (StatusCode, Json<ErrorResponse>)> {
+    if !state.config_manager.get_debug().await.capture_enabled {
+        return Err((
+            StatusCode::FORBIDDEN,
+            ErrorResponse::new("Captures are disabled (debug.capture_enabled)"),
+        ));
     }
+    let Some(axum::Extension(DashboardUser(user))) = user else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            ErrorResponse::new("captures require a dashboard admin session"),
+        ));
+    };
+    let conn_id = uuid::Uuid::parse_str(id).map_err(|_| {
+        (
+            StatusCode::NOT_FOUND,
+            ErrorResponse::new(format!("Connection not found: {}", id)),
+        )
+    })?;
+    Ok((user, conn_id))
 }
 
-/// Get security configuration (without passwords).
-pub async fn get_security(State(state): State<AppState>) -> Json<ApiResponse<SecurityResponse>> {
-    let security = state.config_manager.get_security().await;
-    let users: Vec<UserInfo> = security.users.iter().map(UserInfo::from).collect();
-    ApiResponse::ok(SecurityResponse {
-        auth_enabled: security.auth_enabled,
-        user_count: users.len(),
-        users,
-    })
-}
-
-/// Update security settings (enable/disable auth).
-#[derive(Debug, Deserialize)]
-pub struct UpdateSecurityRequest {
-    pub auth_enabled: Option<bool>,
+/// Start capturing the bytes an active connection relays.
+pub async fn start_capture(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::Query(query): axum::extract::Query<StartCaptureQuery>,
+) -> Result<Json<ApiResponse<CaptureInfo>>, (StatusCode, Json<ErrorResponse>)> {
+    let (user, conn_id) = capture_access(&state, user, &id).await?;
+    let max_bytes = state.config_manager.get_debug().await.capture_max_bytes;
+    let bytes = query.bytes.unwrap_or(DEFAULT_CAPTURE_BYTES.min(max_bytes));
+    if bytes == 0 || bytes > max_bytes {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::validation(vec![FieldError {
+                field: "bytes".to_string(),
+                message: format!("must be between 1 and {}", max_bytes),
+            }]),
+        ));
+    }
+    let capture = state
+        .stats
+        .start_capture(conn_id, query.direction, bytes, &user)
+        .map_err(|e| {
+            let status = match e {
+                CaptureError::NotActive => StatusCode::NOT_FOUND,
+                CaptureError::AlreadyCapturing => StatusCode::CONFLICT,
+            };
+            (status, ErrorResponse::new(format!("{}: {}", e, id)))
+        })?;
+    tracing::warn!(
+        target: "net_relay_api::audit",
+        user = %user,
+        client_ip = %audit_ip(client_ip),
+        connection = %conn_id,
+        bytes,
+        direction = ?query.direction,
+        "Connection capture started"
+    );
+    Ok(ApiResponse::ok(capture.info()))
 }
 
-pub async fn update_security(
+/// Download the latest capture of a connection as a hex dump or raw bytes.
+pub async fn download_capture(
     State(state): State<AppState>,
-    Json(req): Json<UpdateSecurityRequest>,
-) -> Json<ApiResponse<SecurityResponse>> {
-    let mut security = state.config_manager.get_security().await;
-
-    if let Some(enabled) = req.auth_enabled {
-        security.auth_enabled = enabled;
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::Query(query): axum::extract::Query<CaptureDownloadQuery>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let (user, conn_id) = capture_access(&state, user, &id).await?;
+    let format = query.format.as_deref().unwrap_or("hex");
+    if format != "hex" && format != "raw" {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::validation(vec![FieldError {
+                field: "format".to_string(),
+                message: format!("unsupported format '{}' (hex, raw)", format),
+            }]),
+        ));
     }
-
-    let _ = state.config_manager.update_security(security.clone()).await;
-
-    let users: Vec<UserInfo> = security.users.iter().map(UserInfo::from).collect();
-    ApiResponse::ok(SecurityResponse {
-        auth_enabled: security.auth_enabled,
+    let capture = state.stats.capture(conn_id).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            ErrorResponse::new(format!("No capture of connection {}", id)),
+        )
+    })?;
+    let info = capture.info();
+    tracing::warn!(
+        target: "net_relay_api::audit",
+        user = %user,
+        client_ip = %audit_ip(client_ip),
+        connection = %conn_id,
+        bytes = info.bytes,
+        format,
+        "Connection capture downloaded"
+    );
+    let active = if info.active { "true" } else { "false" };
+    let response = if format == "raw" {
+        let disposition = format!("attachment; filename=\"capture-{}.bin\"", conn_id);
+        (
+            [
+                (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+                (header::CONTENT_DISPOSITION, disposition),
+                (
+                    header::HeaderName::from_static("x-capture-active"),
+                    active.to_string(),
+                ),
+            ],
+            capture.raw(),
+        )
+            .into_response()
+    } else {
+        (
+            [
+                (
+                    header::CONTENT_TYPE,
+                    "text/plain; charset=utf-8".to_string(),
+                ),
+                (
+                    header::HeaderName::from_static("x-capture-active"),
+                    active.to_string(),
+                ),
+            ],
+            capture.hex_dump(),
+        )
+            .into_response()
+    };
+    Ok(response)
+}
+
+/// Closed connections with the span the history covers.
+#[derive(Debug, Serialize)]
+pub struct HistoryResponse {
+    #[serde(flatten)]
+    pub page: HistoryPage,
+    pub window: HistoryWindow,
+}
+
+/// Get connection history.
+pub async fn get_history(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<HistoryQuery>,
+) -> Result<Json<ApiResponse<HistoryResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    require_stats(&state)?;
+    let since = HistorySince {
+        id: query.since_id,
+        time: query.since_time,
+    };
+    let page = match query.tag {
+        Some(tag) => {
+            let users = tagged_users(&state.config_manager, &tag).await;
+            state
+                .stats
+                .get_history_matching(query.limit, since, |entry| {
+                    entry.info.tags.contains(&tag)
+                        || entry
+                            .info
+                            .username
+                            .as_ref()
+                            .is_some_and(|name| users.contains(name))
+                })
+                .await
+        }
+        None => state.stats.get_history(query.limit, since).await,
+    };
+    let window = state.stats.history_window().await;
+    Ok(ApiResponse::ok(HistoryResponse { page, window }))
+}
+
+/// Names of the proxy users carrying `tag`.
+async fn tagged_users(config_manager: &ConfigManager, tag: &str) -> HashSet<String> {
+    let security = config_manager.get_security().await;
+    security
+        .users
+        .into_iter()
+        .filter(|user| user.tags.iter().any(|t| t == tag))
+        .map(|user| user.username)
+        .collect()
+}
+
+/// Get recent attempts refused by access control.
+pub async fn get_denied(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<HistoryQuery>,
+) -> Result<Json<ApiResponse<Vec<DeniedAttempt>>>, (StatusCode, Json<ErrorResponse>)> {
+    require_stats(&state)?;
+    let denied = state.stats.get_denied(query.limit).await;
+    Ok(ApiResponse::ok(denied))
+}
+
+/// Get target resolver statistics.
+pub async fn get_dns_stats(State(state): State<AppState>) -> Json<ApiResponse<DnsStats>> {
+    ApiResponse::ok(state.config_manager.dns_stats().await)
+}
+
+/// Get handshake, connect and time-to-first-byte latency of closed connections.
+pub async fn get_latency_stats(
+    State(state): State<AppState>,
+) -> Json<ApiResponse<Vec<PhaseLatency>>> {
+    ApiResponse::ok(state.stats.get_latency())
+}
+
+/// Target hosts that failed to connect in the last hour, with their
+/// outcome counts and error rate, most failures first.
+pub async fn get_target_errors(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<HistoryQuery>,
+) -> Result<Json<ApiResponse<Vec<TargetConnectStats>>>, (StatusCode, Json<ErrorResponse>)> {
+    require_stats(&state)?;
+    Ok(ApiResponse::ok(state.stats.get_target_errors(query.limit)))
+}
+
+/// State of every `[alerts]` rule at its latest evaluation, in rule order.
+pub async fn get_alerts(State(state): State<AppState>) -> Json<ApiResponse<Vec<AlertStatus>>> {
+    ApiResponse::ok(state.stats.alerts())
+}
+
+/// Match counters of access rules by rule id, split into enforced and
+/// monitored hits.
+pub async fn get_rule_hits(
+    State(state): State<AppState>,
+) -> Json<ApiResponse<HashMap<String, RuleHits>>> {
+    ApiResponse::ok(state.stats.get_rule_hits())
+}
+
+/// One upstream relay with its health and traffic.
+#[derive(Debug, Serialize)]
+pub struct UpstreamInfo {
+    #[serde(flatten)]
+    pub status: UpstreamStatus,
+    pub traffic: TrafficStats,
+}
+
+/// Upstream relay health and failover history.
+#[derive(Debug, Serialize)]
+pub struct UpstreamsResponse {
+    pub enabled: bool,
+    /// Relay new connections use.
+    pub active: Option<String>,
+    /// Relays, most preferred first.
+    pub relays: Vec<UpstreamInfo>,
+    /// Recent failover events, newest first.
+    pub events: Vec<FailoverEvent>,
+}
+
+/// Get upstream relay health, traffic and failover events.
+pub async fn get_upstreams(State(state): State<AppState>) -> Json<ApiResponse<UpstreamsResponse>> {
+    let pool = state.config_manager.upstream_pool();
+    let traffic = state.stats.get_upstream_stats();
+    let relays = pool
+        .statuses()
+        .into_iter()
+        .map(|status| {
+            let traffic = traffic
+                .iter()
+                .find(|t| t.address == status.address)
+                .cloned()
+                .unwrap_or_else(|| TrafficStats {
+                    address: status.address.clone(),
+                    ..Default::default()
+                });
+            UpstreamInfo { status, traffic }
+        })
+        .collect();
+
+    ApiResponse::ok(UpstreamsResponse {
+        enabled: state.config_manager.get_upstream().await.is_some(),
+        active: pool.active(),
+        relays,
+        events: pool.events(),
+    })
+}
+
+/// Process, Tokio runtime and statistics structure metrics.
+#[derive(Debug, Serialize)]
+pub struct RuntimeResponse {
+    /// Spawned connection tasks still running (SOCKS5, HTTP and tunnel).
+    pub connection_tasks: u64,
+    /// Connection tasks that ended in a panic since start.
+    pub panicked_tasks: u64,
+    /// Process resource usage (Linux only).
+    pub process: ProcessMetrics,
+    pub tokio: Option<TokioMetrics>,
+    /// Accept queues of this instance's listeners (Linux only).
+    pub listeners: Vec<ListenerQueue>,
+    pub stats: StatsSizes,
+    /// Copy buffer memory held by running relays.
+    pub relay_buffers: RelayBufferStats,
+}
+
+async fn runtime_metrics(state: &AppState) -> RuntimeResponse {
+    // Bound ports, which differ from the configured ones for port 0
+    let mut ports: Vec<u16> = state
+        .server
+        .bound_addrs(None)
+        .iter()
+        .map(|addr| addr.port())
+        .collect();
+    ports.sort_unstable();
+    ports.dedup();
+
+    RuntimeResponse {
+        connection_tasks: state.stats.connection_tasks(),
+        panicked_tasks: state.stats.panicked_tasks(),
+        process: ProcessMetrics::collect(),
+        tokio: TokioMetrics::collect(),
+        listeners: runtime::listener_queues(&ports),
+        stats: state.stats.sizes().await,
+        relay_buffers: state.stats.relay_buffers().stats(),
+    }
+}
+
+/// Get runtime and resource metrics (requires a dashboard session).
+pub async fn get_runtime(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+) -> Result<Json<ApiResponse<RuntimeResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    if user.is_none() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            ErrorResponse::new("runtime metrics require a dashboard admin session"),
+        ));
+    }
+    Ok(ApiResponse::ok(runtime_metrics(&state).await))
+}
+
+/// Export relay, runtime and API metrics in the Prometheus text format.
+pub async fn get_metrics(State(state): State<AppState>) -> Response {
+    let aggregated = state.stats.get_aggregated().await;
+    let runtime = runtime_metrics(&state).await;
+    let body = crate::metrics::render(
+        &aggregated,
+        &runtime,
+        &state.stats.get_listener_stats(),
+        &state.stats.get_latency(),
+        &state.config_manager.dns_stats().await,
+        &state
+            .stats
+            .get_target_errors(Some(crate::metrics::MAX_TARGET_LABELS)),
+        &state.api_metrics.snapshot(),
+        &certificate_expiries(&state),
+    );
+    ([(header::CONTENT_TYPE, crate::metrics::CONTENT_TYPE)], body).into_response()
+}
+
+// ==================== Configuration API ====================
+
+/// Get current configuration.
+pub async fn get_config(State(state): State<AppState>) -> Json<ApiResponse<ConfigResponse>> {
+    let config = state.config_manager.get().await;
+    ApiResponse::ok(ConfigResponse {
+        config,
+        save_status: state.config_manager.save_status(),
+    })
+}
+
+/// The running configuration and whether it is saved.
+#[derive(Debug, Serialize)]
+pub struct ConfigResponse {
+    #[serde(flatten)]
+    pub config: Config,
+    /// `dirty` is set while changes exist only in memory (see `POST /api/config/flush`).
+    pub save_status: SaveStatus,
+}
+
+/// Export query parameters.
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub include_secrets: bool,
+}
+
+/// Export the current configuration as a downloadable TOML file.
+///
+/// Passwords are redacted unless `include_secrets=true` is given.
+pub async fn export_config(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<ExportQuery>,
+) -> Response {
+    let config = state.config_manager.get().await;
+    let config = if query.include_secrets {
+        config
+    } else {
+        config.redacted()
+    };
+
+    match toml::to_string_pretty(&config) {
+        Ok(content) => (
+            [
+                (header::CONTENT_TYPE, "application/toml"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"net-relay-config.toml\"",
+                ),
+            ],
+            content,
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorResponse::new(format!("Failed to serialize config: {}", e)),
+        )
+            .into_response(),
+    }
+}
+
+/// Import query parameters.
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    /// Comma-separated list of sections to import (default: all sections).
+    pub sections: Option<String>,
+    /// Validate and report the diff without applying it.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Import result with a summary of what changed.
+#[derive(Debug, Serialize)]
+pub struct ImportResponse {
+    pub applied: bool,
+    pub sections: Vec<String>,
+    pub changed_sections: Vec<String>,
+    pub users_added: Vec<String>,
+    pub users_removed: Vec<String>,
+    pub rules_before: usize,
+    pub rules_after: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup_path: Option<String>,
+}
+
+/// Import a TOML configuration, fully or for selected sections.
+///
+/// The pre-import configuration is backed up next to the config file before applying.
+pub async fn import_config(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<ImportQuery>,
+    body: String,
+) -> Result<Json<ApiResponse<ImportResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |msg: String| (StatusCode::BAD_REQUEST, ErrorResponse::new(msg));
+
+    let imported: Config =
+        toml::from_str(&body).map_err(|e| bad_request(format!("Invalid TOML: {}", e)))?;
+
+    let sections: Vec<&str> = match &query.sections {
+        Some(list) => list
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .collect(),
+        None => Config::SECTIONS.to_vec(),
+    };
+
+    let current = state.config_manager.get().await;
+    let mut merged = current.clone();
+    merged
+        .merge_sections(&imported, &sections)
+        .map_err(|e| bad_request(e.to_string()))?;
+    merged.restore_redacted(&current);
+    merged
+        .validate()
+        .map_err(|e| bad_request(format!("Invalid config: {}", e)))?;
+    // The deprecated allowlist is enforced as the IP whitelist
+    merged.migrate_allowed_ips();
+
+    let users_added = merged
+        .security
+        .users
+        .iter()
+        .filter(|u| {
+            !current
+                .security
+                .users
+                .iter()
+                .any(|c| c.username == u.username)
+        })
+        .map(|u| u.username.clone())
+        .collect();
+    let users_removed = current
+        .security
+        .users
+        .iter()
+        .filter(|c| {
+            !merged
+                .security
+                .users
+                .iter()
+                .any(|u| u.username == c.username)
+        })
+        .map(|c| c.username.clone())
+        .collect();
+
+    let mut response = ImportResponse {
+        applied: false,
+        sections: sections.iter().map(|s| s.to_string()).collect(),
+        changed_sections: merged
+            .changed_sections(&current)
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        users_added,
+        users_removed,
+        rules_before: current.access_control.rules.len(),
+        rules_after: merged.access_control.rules.len(),
+        backup_path: None,
+    };
+
+    if query.dry_run {
+        return Ok(ApiResponse::ok(response));
+    }
+
+    let internal = |msg: String| (StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse::new(msg));
+
+    response.backup_path = state
+        .config_manager
+        .backup("pre-import")
+        .await
+        .map_err(|e| internal(format!("Failed to back up current config: {}", e)))?
+        .map(|p| p.display().to_string());
+
+    let stats_config = merged.stats.clone();
+    state
+        .config_manager
+        .update(merged)
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    state
+        .stats
+        .set_history_limits(stats_config.max_history, stats_config.max_history_age());
+    state.stats.set_enabled(stats_config.enabled).await;
+
+    response.applied = true;
+    Ok(ApiResponse::ok(response))
+}
+
+/// List the config snapshots in `backup.directory`, newest first.
+pub async fn list_config_backups(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<ConfigBackup>>>, (StatusCode, Json<ErrorResponse>)> {
+    let directory = state.config_manager.get().await.backup.directory;
+    backup::list(Path::new(&directory))
+        .map(ApiResponse::ok)
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorResponse::new(format!("Failed to list backups: {}", e)),
+            )
+        })
+}
+
+/// Result of restoring a config snapshot.
+#[derive(Debug, Serialize)]
+pub struct RestoreBackupResponse {
+    pub restored: String,
+    pub changed_sections: Vec<String>,
+    /// Snapshot of the configuration that was replaced.
+    pub pre_restore: ConfigBackup,
+}
+
+/// Roll the configuration back to a snapshot.
+///
+/// The current config file is snapshotted (labelled `pre-restore`) first.
+pub async fn restore_config_backup(
+    State(state): State<AppState>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> Result<Json<ApiResponse<RestoreBackupResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |msg: String| (StatusCode::BAD_REQUEST, ErrorResponse::new(msg));
+    let internal = |msg: String| (StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse::new(msg));
+
+    let Some(config_file) = state.config_manager.config_path().map(PathBuf::from) else {
+        return Err(bad_request(
+            "Restoring a backup requires a config file".to_string(),
+        ));
+    };
+    let current = state.config_manager.get().await;
+    let content = backup::read(Path::new(&current.backup.directory), &name)
+        .map_err(|e| internal(format!("Failed to read backup: {}", e)))?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                ErrorResponse::new(format!("Backup '{}' not found", name)),
+            )
+        })?;
+    let mut restored: Config = toml::from_str(&content)
+        .map_err(|e| bad_request(format!("Invalid TOML in backup: {}", e)))?;
+    restored
+        .validate()
+        .map_err(|e| bad_request(format!("Invalid config in backup: {}", e)))?;
+    restored.migrate_allowed_ips();
+
+    let pre_restore = backup::snapshot(&config_file, &current.backup, Some("pre-restore"))
+        .map_err(|e| internal(format!("Failed to back up current config: {}", e)))?
+        .ok_or_else(|| internal("Failed to back up current config".to_string()))?;
+
+    let changed_sections = restored
+        .changed_sections(&current)
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let stats_config = restored.stats.clone();
+    state
+        .config_manager
+        .update(restored)
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    state
+        .stats
+        .set_history_limits(stats_config.max_history, stats_config.max_history_age());
+    state.stats.set_enabled(stats_config.enabled).await;
+
+    Ok(ApiResponse::ok(RestoreBackupResponse {
+        restored: name,
+        changed_sections,
+        pre_restore,
+    }))
+}
+
+/// Body of `POST /api/config/lock`.
+#[derive(Debug, Default, Deserialize)]
+pub struct LockConfigRequest {
+    pub reason: Option<String>,
+}
+
+/// Configuration lock state.
+#[derive(Debug, Serialize)]
+pub struct ConfigLockResponse {
+    pub locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock: Option<ConfigLock>,
+}
+
+/// Freeze the configuration: every edit is refused until it is unlocked.
+pub async fn lock_config(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    body: Option<Json<LockConfigRequest>>,
+) -> Result<Json<ApiResponse<ConfigLockResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let Some(axum::Extension(DashboardUser(username))) = user else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            ErrorResponse::new("locking the configuration requires a dashboard admin session"),
+        ));
+    };
+    let reason = body
+        .and_then(|Json(body)| body.reason)
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty());
+
+    match state.config_manager.lock_config(&username, reason).await {
+        Ok(lock) => {
+            tracing::warn!(
+                target: "net_relay_api::audit",
+                user = %username,
+                client_ip = %audit_ip(client_ip),
+                reason = lock.reason.as_deref().unwrap_or("-"),
+                "Configuration locked"
+            );
+            Ok(ApiResponse::ok(ConfigLockResponse {
+                locked: true,
+                lock: Some(lock),
+            }))
+        }
+        Err(existing) => Err((
+            StatusCode::CONFLICT,
+            ErrorResponse::new(format!(
+                "Configuration is already locked by {}",
+                existing.locked_by
+            )),
+        )),
+    }
+}
+
+/// Lift the configuration lock.
+pub async fn unlock_config(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+) -> Result<Json<ApiResponse<ConfigLockResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let Some(axum::Extension(DashboardUser(username))) = user else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            ErrorResponse::new("unlocking the configuration requires a dashboard admin session"),
+        ));
+    };
+
+    if let Some(lock) = state.config_manager.unlock_config() {
+        tracing::warn!(
+            target: "net_relay_api::audit",
+            user = %username,
+            client_ip = %audit_ip(client_ip),
+            locked_by = %lock.locked_by,
+            "Configuration unlocked"
+        );
+    }
+    Ok(ApiResponse::ok(ConfigLockResponse {
+        locked: false,
+        lock: None,
+    }))
+}
+
+/// Where each top-level section of the running configuration came from:
+/// `default`, `file`, `env`, `api:<user>@<time>` or `runtime@<time>`.
+pub async fn get_config_provenance(
+    State(state): State<AppState>,
+) -> Json<ApiResponse<ConfigProvenance>> {
+    ApiResponse::ok(state.config_manager.provenance())
+}
+
+/// Write the running configuration to the config file again, e.g. after a
+/// save failed on a read-only file system.
+pub async fn flush_config(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+) -> Result<Json<ApiResponse<SaveStatus>>, (StatusCode, Json<ErrorResponse>)> {
+    let was_dirty = state.config_manager.save_status().dirty;
+    let status = state
+        .config_manager
+        .flush()
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    tracing::warn!(
+        target: "net_relay_api::audit",
+        user = %user.as_ref().map_or("-", |axum::Extension(DashboardUser(u))| u.as_str()),
+        client_ip = %audit_ip(client_ip),
+        was_dirty,
+        "Configuration flushed"
+    );
+    Ok(ApiResponse::ok(status))
+}
+
+/// Client IP for audit log lines (`-` when unknown).
+fn audit_ip(client_ip: Option<axum::Extension<ClientIp>>) -> String {
+    client_ip.map_or_else(
+        || "-".to_string(),
+        |axum::Extension(ClientIp(ip))| ip.to_string(),
+    )
+}
+
+/// Get access control configuration only.
+pub async fn get_access_control(
+    State(state): State<AppState>,
+) -> Json<ApiResponse<AccessControlResponse>> {
+    let config = state.config_manager.get().await;
+    ApiResponse::ok(config.access_control.into())
+}
+
+/// Update access control configuration.
+pub async fn update_access_control(
+    State(state): State<AppState>,
+    Json(mut access_control): Json<AccessControlConfig>,
+) -> Result<Json<ApiResponse<AccessControlResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    for (index, rule) in access_control.rules.iter_mut().enumerate() {
+        normalize_rule_domain(rule, &format!("rules[{}].domain", index))?;
+    }
+    state
+        .config_manager
+        .update_access_control(access_control.clone())
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    Ok(ApiResponse::ok(access_control.into()))
+}
+
+/// Add IP to blacklist.
+#[derive(Debug, Deserialize)]
+pub struct IpListRequest {
+    pub ip: String,
+}
+
+pub async fn add_ip_blacklist(
+    State(state): State<AppState>,
+    Json(req): Json<IpListRequest>,
+) -> Result<Json<ApiResponse<AccessControlResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let mut config = state.config_manager.get().await;
+    if !config.access_control.ip_blacklist.contains(&req.ip) {
+        config.access_control.ip_blacklist.push(req.ip);
+    }
+    state
+        .config_manager
+        .update_access_control(config.access_control.clone())
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    Ok(ApiResponse::ok(config.access_control.into()))
+}
+
+pub async fn remove_ip_blacklist(
+    State(state): State<AppState>,
+    Json(req): Json<IpListRequest>,
+) -> Result<Json<ApiResponse<AccessControlResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let mut config = state.config_manager.get().await;
+    config
+        .access_control
+        .ip_blacklist
+        .retain(|ip| ip != &req.ip);
+    state
+        .config_manager
+        .update_access_control(config.access_control.clone())
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    Ok(ApiResponse::ok(config.access_control.into()))
+}
+
+pub async fn add_ip_whitelist(
+    State(state): State<AppState>,
+    Json(req): Json<IpListRequest>,
+) -> Result<Json<ApiResponse<AccessControlResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let mut config = state.config_manager.get().await;
+    if !config.access_control.ip_whitelist.contains(&req.ip) {
+        config.access_control.ip_whitelist.push(req.ip);
+    }
+    state
+        .config_manager
+        .update_access_control(config.access_control.clone())
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    Ok(ApiResponse::ok(config.access_control.into()))
+}
+
+pub async fn remove_ip_whitelist(
+    State(state): State<AppState>,
+    Json(req): Json<IpListRequest>,
+) -> Result<Json<ApiResponse<AccessControlResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let mut config = state.config_manager.get().await;
+    config
+        .access_control
+        .ip_whitelist
+        .retain(|ip| ip != &req.ip);
+    state
+        .config_manager
+        .update_access_control(config.access_control.clone())
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    Ok(ApiResponse::ok(config.access_control.into()))
+}
+
+/// Store the domain of `rule` (the request's `field`) normalized, refusing
+/// domains that aren't host names.
+fn normalize_rule_domain(
+    rule: &mut AccessRule,
+    field: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    rule.normalize_domain().map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::validation(vec![FieldError {
+                field: field.to_string(),
+                message: "must be a host name or a *.domain pattern".to_string(),
+            }]),
+        )
+    })
+}
+
+/// Add access rule. An internationalized domain is stored in punycode, with
+/// its Unicode form in `domain_unicode`.
+pub async fn add_rule(
+    State(state): State<AppState>,
+    Json(mut rule): Json<AccessRule>,
+) -> Result<Json<ApiResponse<AccessControlResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    normalize_rule_domain(&mut rule, "domain")?;
+    let mut config = state.config_manager.get().await;
+    let rules = &config.access_control.rules;
+    if rule.id.is_empty() || rules.iter().any(|r| r.id == rule.id) {
+        rule.id = new_rule_id();
+    }
+    config.access_control.rules.push(rule);
+    state
+        .config_manager
+        .update_access_control(config.access_control.clone())
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    Ok(ApiResponse::ok(config.access_control.into()))
+}
+
+/// Remove access rule by index.
+#[derive(Debug, Deserialize)]
+pub struct RemoveRuleRequest {
+    pub index: usize,
+}
+
+pub async fn remove_rule(
+    State(state): State<AppState>,
+    Json(req): Json<RemoveRuleRequest>,
+) -> Result<Json<ApiResponse<AccessControlResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let mut config = state.config_manager.get().await;
+    if req.index < config.access_control.rules.len() {
+        config.access_control.rules.remove(req.index);
+    }
+    state
+        .config_manager
+        .update_access_control(config.access_control.clone())
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    Ok(ApiResponse::ok(config.access_control.into()))
+}
+
+/// Partial update of an access rule; omitted fields are left unchanged.
+#[derive(Debug, Deserialize)]
+pub struct UpdateRuleRequest {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// `null` removes the path.
+    #[serde(default, deserialize_with = "present")]
+    pub path: Option<Option<String>>,
+    #[serde(default)]
+    pub action: Option<RuleAction>,
+    #[serde(default)]
+    pub mode: Option<RuleMode>,
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// `null` removes the expiry.
+    #[serde(default, deserialize_with = "present")]
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+}
+
+/// Deserialize a field that may be `null`, distinguishing it from an omitted one.
+fn present<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Update fields of the access rule with the given id.
+pub async fn update_rule(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    Json(req): Json<UpdateRuleRequest>,
+) -> Result<Json<ApiResponse<AccessControlResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let mut config = state.config_manager.get().await;
+    let Some(rule) = config
+        .access_control
+        .rules
+        .iter_mut()
+        .find(|rule| rule.id == id)
+    else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            ErrorResponse::new(format!("Rule '{}' not found", id)),
+        ));
+    };
+
+    if let Some(domain) = req.domain {
+        if domain.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                ErrorResponse::validation(vec![FieldError {
+                    field: "domain".to_string(),
+                    message: "must not be empty".to_string(),
+                }]),
+            ));
+        }
+        rule.domain = domain;
+        normalize_rule_domain(rule, "domain")?;
+    }
+    if let Some(name) = req.name {
+        rule.name = name;
+    }
+    if let Some(path) = req.path {
+        rule.path = path;
+    }
+    if let Some(action) = req.action {
+        rule.action = action;
+    }
+    if let Some(mode) = req.mode {
+        rule.mode = mode;
+    }
+    if let Some(enabled) = req.enabled {
+        rule.enabled = enabled;
+    }
+    if let Some(expires_at) = req.expires_at {
+        rule.expires_at = expires_at;
+    }
+
+    state
+        .config_manager
+        .update_access_control(config.access_control.clone())
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    Ok(ApiResponse::ok(config.access_control.into()))
+}
+
+/// Rule list import query parameters.
+#[derive(Debug, Deserialize)]
+pub struct RuleImportQuery {
+    /// Action of lines without an `allow:`/`deny:` prefix (default `deny`).
+    #[serde(default)]
+    pub action: Option<RuleAction>,
+    /// Name of the imported rules, identifying the batch (default `import <time>`).
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// Rule list import summary.
+#[derive(Debug, Serialize)]
+pub struct RuleImportResponse {
+    /// Name the added rules were given.
+    pub name: String,
+    /// Rules added, in list order.
+    pub added: Vec<AccessRule>,
+    /// Lines skipped because a rule for the domain (without a path or
+    /// ports) already exists or an earlier line has it.
+    pub duplicates: Vec<RuleListEntry>,
+    /// Rules in the configuration after the import.
+    pub rule_count: usize,
+}
+
+/// Add access rules from a plain text list of domains (see
+/// [`net_relay_core::rule_list`]). Invalid lines fail the whole import;
+/// otherwise all new rules are saved in one update.
+pub async fn import_rules(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<RuleImportQuery>,
+    body: String,
+) -> Result<Json<ApiResponse<RuleImportResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let entries =
+        rule_list::parse(&body, query.action.unwrap_or(RuleAction::Deny)).map_err(|errors| {
+            (
+                StatusCode::BAD_REQUEST,
+                ErrorResponse::validation(
+                    errors
+                        .into_iter()
+                        .map(|e| FieldError {
+                            field: format!("line {}", e.line),
+                            message: e.message,
+                        })
+                        .collect(),
+                ),
+            )
+        })?;
+    let name = query
+        .name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| format!("import {}", Utc::now().format("%Y-%m-%d %H:%M")));
+
+    let mut config = state.config_manager.get().await;
+    let mut domains: HashSet<String> = config
+        .access_control
+        .rules
+        .iter()
+        .filter(|rule| rule.path.is_none() && rule.ports.is_none())
+        .map(|rule| rule.domain.clone())
+        .collect();
+    let mut added = Vec::new();
+    let mut duplicates = Vec::new();
+    for entry in entries {
+        if domains.insert(entry.domain.clone()) {
+            added.push(entry.to_rule(&name));
+        } else {
+            duplicates.push(entry);
+        }
+    }
+
+    if !added.is_empty() {
+        config.access_control.rules.extend(added.iter().cloned());
+        state
+            .config_manager
+            .update_access_control(config.access_control.clone())
+            .await
+            .map_err(|e| update_failed("Failed to save", e))?;
+    }
+    Ok(ApiResponse::ok(RuleImportResponse {
+        name,
+        added,
+        duplicates,
+        rule_count: config.access_control.rules.len(),
+    }))
+}
+
+/// Rule list export query parameters.
+#[derive(Debug, Deserialize)]
+pub struct RuleExportQuery {
+    /// Only `txt` for now (default).
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+/// Export the access rules as a plain text list that `import_rules` reads
+/// back. Rules the list can't express are left out.
+pub async fn export_rules(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<RuleExportQuery>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let format = query.format.as_deref().unwrap_or("txt");
+    if format != "txt" {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::validation(vec![FieldError {
+                field: "format".to_string(),
+                message: format!("unsupported format '{}' (txt)", format),
+            }]),
+        ));
+    }
+    let config = state.config_manager.get().await;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"net-relay-rules.txt\"",
+            ),
+        ],
+        rule_list::format(&config.access_control.rules),
+    )
+        .into_response())
+}
+
+/// Access rule with its current state.
+#[derive(Debug, Serialize)]
+pub struct RuleInfo {
+    #[serde(flatten)]
+    pub rule: AccessRule,
+    /// Past `expires_at` and no longer matching; removed an hour after expiry.
+    pub expired: bool,
+}
+
+/// Access control configuration with rule states.
+#[derive(Debug, Serialize)]
+pub struct AccessControlResponse {
+    #[serde(flatten)]
+    pub config: AccessControlConfig,
+    pub rules: Vec<RuleInfo>,
+}
+
+impl From<AccessControlConfig> for AccessControlResponse {
+    fn from(mut config: AccessControlConfig) -> Self {
+        let now = Utc::now();
+        // `rules` is left out of the flattened config while empty
+        let rules = std::mem::take(&mut config.rules)
+            .into_iter()
+            .map(|rule| RuleInfo {
+                expired: rule.is_expired(now),
+                rule,
+            })
+            .collect();
+        Self { config, rules }
+    }
+}
+
+/// Rule tester request.
+#[derive(Debug, Deserialize)]
+pub struct RuleTestRequest {
+    pub client_ip: String,
+    pub target_host: String,
+    pub target_port: u16,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub protocol: Option<Protocol>,
+}
+
+/// Rule tester response with the full evaluation trace.
+#[derive(Debug, Serialize)]
+pub struct RuleTestResponse {
+    pub allowed: bool,
+    /// Which stage decided the outcome: "ip", "rule" or "default".
+    pub decided_by: String,
+    pub ip: IpDecision,
+    pub target: TargetDecision,
+    pub auth_required: bool,
+    /// Whether the given username exists and is enabled (only when provided).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_valid: Option<bool>,
+    pub target_host: String,
+    pub target_port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<Protocol>,
+}
+
+/// Evaluate a hypothetical request against the current access control config.
+pub async fn test_rules(
+    State(state): State<AppState>,
+    Json(req): Json<RuleTestRequest>,
+) -> Json<ApiResponse<RuleTestResponse>> {
+    let config = state.config_manager.get().await;
+
+    let ip = config.access_control.evaluate_ip(&req.client_ip);
+    // Rules are matched against normalized hosts, as the proxies do
+    let target_host = hostname::normalize(&req.target_host)
+        .map(|host| host.into_owned())
+        .unwrap_or(req.target_host);
+    let target =
+        config
+            .access_control
+            .evaluate_target(&target_host, req.target_port, req.path.as_deref());
+
+    let decided_by = if !ip.allowed {
+        "ip"
+    } else if target.default_applied {
+        "default"
+    } else {
+        "rule"
+    };
+
+    let user_valid = req.username.as_ref().map(|name| {
+        config
+            .security
+            .users
+            .iter()
+            .any(|u| u.enabled && &u.username == name)
+            || config.security.username.as_ref() == Some(name)
+    });
+
+    ApiResponse::ok(RuleTestResponse {
+        allowed: ip.allowed && target.allowed,
+        decided_by: decided_by.to_string(),
+        ip,
+        target,
+        auth_required: config.security.auth_enabled,
+        user_valid,
+        target_host,
+        target_port: req.target_port,
+        protocol: req.protocol,
+    })
+}
+
+// ==================== Security & User Management API ====================
+
+/// Security configuration response (without exposing passwords).
+#[derive(Debug, Serialize)]
+pub struct SecurityResponse {
+    pub auth_enabled: bool,
+    pub users: Vec<UserInfo>,
+    pub user_count: usize,
+}
+
+/// User info without password.
+#[derive(Debug, Serialize)]
+pub struct UserInfo {
+    pub username: String,
+    pub enabled: bool,
+    pub description: Option<String>,
+    pub bandwidth_limit: u64,
+    pub connection_limit: u32,
+    /// Override of `limits.max_connection_lifetime_secs`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_connection_lifetime_secs: Option<u64>,
+    pub quota_bytes: u64,
+    pub quota_period: QuotaPeriod,
+    /// Usage and remaining bytes in the current period (absent when unlimited).
+    pub quota: Option<QuotaStatus>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl UserInfo {
+    fn new(user: &User, stats: &Stats) -> Self {
+        Self {
+            username: user.username.clone(),
+            enabled: user.enabled,
+            description: user.description.clone(),
+            bandwidth_limit: user.bandwidth_limit,
+            connection_limit: user.connection_limit,
+            max_connection_lifetime_secs: user.max_connection_lifetime_secs,
+            quota_bytes: user.quota_bytes,
+            quota_period: user.quota_period,
+            quota: stats.quota_status(user),
+            tags: user.tags.clone(),
+            note: user.note.clone(),
+        }
+    }
+}
+
+fn security_response(security: &SecurityConfig, stats: &Stats) -> SecurityResponse {
+    let users: Vec<UserInfo> = security
+        .users
+        .iter()
+        .map(|u| UserInfo::new(u, stats))
+        .collect();
+    SecurityResponse {
+        auth_enabled: security.auth_enabled,
         user_count: users.len(),
         users,
-    })
+    }
+}
+
+fn user_not_found(username: &str) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::NOT_FOUND,
+        ErrorResponse::new(format!("User not found: {}", username)),
+    )
+}
+
+/// Get security configuration (without passwords).
+pub async fn get_security(State(state): State<AppState>) -> Json<ApiResponse<SecurityResponse>> {
+    let security = state.config_manager.get_security().await;
+    ApiResponse::ok(security_response(&security, &state.stats))
+}
+
+/// Update security settings (enable/disable auth).
+#[derive(Debug, Deserialize)]
+pub struct UpdateSecurityRequest {
+    pub auth_enabled: Option<bool>,
+}
+
+pub async fn update_security(
+    State(state): State<AppState>,
+    Json(req): Json<UpdateSecurityRequest>,
+) -> Result<Json<ApiResponse<SecurityResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let mut security = state.config_manager.get_security().await;
+
+    if let Some(enabled) = req.auth_enabled {
+        security.auth_enabled = enabled;
+    }
+
+    state
+        .config_manager
+        .update_security(security.clone())
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+
+    Ok(ApiResponse::ok(security_response(&security, &state.stats)))
 }
 
 /// Add user request.
@@ -328,13 +2172,27 @@ pub struct AddUserRequest {
     pub description: Option<String>,
     #[serde(default)]
     pub enabled: Option<bool>,
+    #[serde(default)]
+    pub quota_bytes: Option<u64>,
+    #[serde(default)]
+    pub quota_period: Option<QuotaPeriod>,
+    /// Overrides `limits.max_connection_lifetime_secs` (0 = unlimited).
+    #[serde(default)]
+    pub max_connection_lifetime_secs: Option<u64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub note: Option<String>,
 }
 
-/// Add a new user.
-pub async fn add_user(
-    State(state): State<AppState>,
-    Json(req): Json<AddUserRequest>,
-) -> Json<ApiResponse<SecurityResponse>> {
+/// Add the user of `req`, returning the saved security settings, or `None`
+/// when the username is taken.
+async fn insert_user(
+    state: &AppState,
+    req: AddUserRequest,
+) -> Result<Option<SecurityConfig>, (StatusCode, Json<ErrorResponse>)> {
+    check_password(state, "password", &req.password).await?;
+    let Annotations { tags, note } = check_annotations(Some(req.tags), Some(req.note))?;
     let mut security = state.config_manager.get_security().await;
 
     let user = User {
@@ -344,104 +2202,572 @@ pub async fn add_user(
         description: req.description,
         bandwidth_limit: 0,
         connection_limit: 0,
+        max_connection_lifetime_secs: req.max_connection_lifetime_secs,
+        quota_bytes: req.quota_bytes.unwrap_or(0),
+        quota_period: req.quota_period.unwrap_or_default(),
+        tokens: Vec::new(),
+        tags: tags.unwrap_or_default(),
+        note: note.flatten(),
     };
 
     if !security.add_user(user) {
-        return Json(ApiResponse {
-            success: false,
-            data: SecurityResponse {
-                auth_enabled: security.auth_enabled,
-                user_count: security.users.len(),
-                users: security.users.iter().map(UserInfo::from).collect(),
-            },
-            message: Some("User already exists".to_string()),
-        });
+        return Ok(None);
     }
 
-    let _ = state.config_manager.update_security(security.clone()).await;
+    state
+        .config_manager
+        .update_security(security.clone())
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    Ok(Some(security))
+}
 
-    let users: Vec<UserInfo> = security.users.iter().map(UserInfo::from).collect();
-    ApiResponse::ok(SecurityResponse {
-        auth_enabled: security.auth_enabled,
-        user_count: users.len(),
-        users,
-    })
+/// Add a new user.
+///
+/// Deprecated in favor of `POST /api/users`.
+pub async fn add_user(
+    State(state): State<AppState>,
+    Json(req): Json<AddUserRequest>,
+) -> Result<Json<ApiResponse<SecurityResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    match insert_user(&state, req).await? {
+        Some(security) => Ok(ApiResponse::ok(security_response(&security, &state.stats))),
+        None => {
+            let security = state.config_manager.get_security().await;
+            Ok(Json(ApiResponse {
+                success: false,
+                data: security_response(&security, &state.stats),
+                message: Some("User already exists".to_string()),
+            }))
+        }
+    }
 }
 
-/// Update user request.
-#[derive(Debug, Deserialize)]
-pub struct UpdateUserRequest {
-    pub username: String,
+/// Changes to a user; absent fields are left unchanged.
+#[derive(Debug, Default, Deserialize)]
+pub struct UserChanges {
     #[serde(default)]
     pub password: Option<String>,
     #[serde(default)]
     pub enabled: Option<bool>,
     #[serde(default)]
     pub description: Option<String>,
+    #[serde(default)]
+    pub quota_bytes: Option<u64>,
+    #[serde(default)]
+    pub quota_period: Option<QuotaPeriod>,
+    /// `null` removes the override of `limits.max_connection_lifetime_secs`.
+    #[serde(default, deserialize_with = "present")]
+    pub max_connection_lifetime_secs: Option<Option<u64>>,
+    /// Replaces the user's tags.
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    /// `null` or an empty string removes the note.
+    #[serde(default, deserialize_with = "present")]
+    pub note: Option<Option<String>>,
+}
+
+/// Update user request.
+#[derive(Debug, Deserialize)]
+pub struct UpdateUserRequest {
+    pub username: String,
+    #[serde(flatten)]
+    pub changes: UserChanges,
+}
+
+/// Apply `req` to the user `username`, returning the saved security
+/// settings, or `None` when there is no such user.
+async fn modify_user(
+    state: &AppState,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    username: &str,
+    req: UserChanges,
+) -> Result<Option<SecurityConfig>, (StatusCode, Json<ErrorResponse>)> {
+    if let Some(password) = &req.password {
+        check_password(state, "password", password).await?;
+    }
+    let Annotations { tags, note } = check_annotations(req.tags, req.note)?;
+    let mut security = state.config_manager.get_security().await;
+
+    let Some(existing) = security.users.iter_mut().find(|u| u.username == username) else {
+        return Ok(None);
+    };
+    if let Some(pwd) = req.password {
+        existing.password = pwd;
+    }
+    if let Some(enabled) = req.enabled {
+        existing.enabled = enabled;
+    }
+    if let Some(desc) = req.description {
+        existing.description = Some(desc);
+    }
+    if let Some(quota_bytes) = req.quota_bytes {
+        existing.quota_bytes = quota_bytes;
+    }
+    if let Some(quota_period) = req.quota_period {
+        existing.quota_period = quota_period;
+    }
+    if let Some(lifetime) = req.max_connection_lifetime_secs {
+        existing.max_connection_lifetime_secs = lifetime;
+    }
+    let annotated = tags.is_some() || note.is_some();
+    if let Some(tags) = tags {
+        existing.tags = tags;
+    }
+    if let Some(note) = note {
+        existing.note = note;
+    }
+    if annotated {
+        tracing::warn!(
+            target: "net_relay_api::audit",
+            user = %user.as_ref().map_or("-", |axum::Extension(DashboardUser(u))| u.as_str()),
+            client_ip = %audit_ip(client_ip),
+            proxy_user = %existing.username,
+            tags = ?existing.tags,
+            note = existing.note.as_deref().unwrap_or("-"),
+            "User annotated"
+        );
+    }
+
+    state
+        .config_manager
+        .update_security(security.clone())
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    Ok(Some(security))
 }
 
-/// Update an existing user.
+/// Update an existing user; unknown usernames are ignored.
+///
+/// Deprecated in favor of `PUT /api/users/{username}`.
 pub async fn update_user(
     State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
     Json(req): Json<UpdateUserRequest>,
-) -> Json<ApiResponse<SecurityResponse>> {
+) -> Result<Json<ApiResponse<SecurityResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let security = match modify_user(&state, user, client_ip, &req.username, req.changes).await? {
+        Some(security) => security,
+        None => state.config_manager.get_security().await,
+    };
+    Ok(ApiResponse::ok(security_response(&security, &state.stats)))
+}
+
+/// Generate password query.
+#[derive(Debug, Deserialize)]
+pub struct GeneratePasswordQuery {
+    /// Length (default `security.password_policy.generated_length`).
+    #[serde(default)]
+    pub length: Option<usize>,
+}
+
+/// Generated password response.
+#[derive(Debug, Serialize)]
+pub struct GeneratedPassword {
+    pub password: String,
+}
+
+/// Longest password `generate_password` returns.
+const MAX_GENERATED_PASSWORD_LENGTH: usize = 128;
+
+/// Generate a random password satisfying `security.password_policy`.
+pub async fn generate_password(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<GeneratePasswordQuery>,
+) -> Result<Json<ApiResponse<GeneratedPassword>>, (StatusCode, Json<ErrorResponse>)> {
+    let policy = state.config_manager.password_policy().await;
+    let length = query.length.unwrap_or(policy.generated_length);
+    let min_length = policy.min_length.max(MIN_PASSWORD_LENGTH);
+    if !(min_length..=MAX_GENERATED_PASSWORD_LENGTH).contains(&length) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::validation(vec![FieldError {
+                field: "length".to_string(),
+                message: format!(
+                    "must be between {} and {}",
+                    min_length, MAX_GENERATED_PASSWORD_LENGTH
+                ),
+            }]),
+        ));
+    }
+
+    let password = policy.generate(length).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorResponse::new(format!("Failed to generate password: {}", e)),
+        )
+    })?;
+    Ok(ApiResponse::ok(GeneratedPassword { password }))
+}
+
+/// Remove user request.
+#[derive(Debug, Deserialize)]
+pub struct RemoveUserRequest {
+    pub username: String,
+}
+
+/// Remove a user.
+///
+/// Deprecated in favor of `DELETE /api/users/{username}`.
+pub async fn remove_user(
+    State(state): State<AppState>,
+    Json(req): Json<RemoveUserRequest>,
+) -> Result<Json<ApiResponse<SecurityResponse>>, (StatusCode, Json<ErrorResponse>)> {
     let mut security = state.config_manager.get_security().await;
 
-    if let Some(existing) = security
+    security.remove_user(&req.username);
+
+    state
+        .config_manager
+        .update_security(security.clone())
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+
+    Ok(ApiResponse::ok(security_response(&security, &state.stats)))
+}
+
+/// List users (without passwords).
+pub async fn list_users(State(state): State<AppState>) -> Json<ApiResponse<Vec<UserInfo>>> {
+    let security = state.config_manager.get_security().await;
+    ApiResponse::ok(security_response(&security, &state.stats).users)
+}
+
+/// Info of `username` in `security`.
+fn saved_user(
+    security: &SecurityConfig,
+    username: &str,
+    stats: &Stats,
+) -> Result<Json<ApiResponse<UserInfo>>, (StatusCode, Json<ErrorResponse>)> {
+    security
         .users
-        .iter_mut()
-        .find(|u| u.username == req.username)
-    {
-        if let Some(pwd) = req.password {
-            existing.password = pwd;
-        }
-        if let Some(enabled) = req.enabled {
-            existing.enabled = enabled;
-        }
-        if let Some(desc) = req.description {
-            existing.description = Some(desc);
+        .iter()
+        .find(|u| u.username == username)
+        .map(|u| ApiResponse::ok(UserInfo::new(u, stats)))
+        .ok_or_else(|| user_not_found(username))
+}
+
+/// Create a user; `409 Conflict` when the username is taken.
+pub async fn create_user(
+    State(state): State<AppState>,
+    Json(req): Json<AddUserRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<UserInfo>>), (StatusCode, Json<ErrorResponse>)> {
+    let username = req.username.clone();
+    match insert_user(&state, req).await? {
+        Some(security) => Ok((
+            StatusCode::CREATED,
+            saved_user(&security, &username, &state.stats)?,
+        )),
+        None => Err((
+            StatusCode::CONFLICT,
+            ErrorResponse::new(format!("User already exists: {}", username)),
+        )),
+    }
+}
+
+/// Update the user `username`.
+pub async fn put_user(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    axum::extract::Path(username): axum::extract::Path<String>,
+    Json(req): Json<UserChanges>,
+) -> Result<Json<ApiResponse<UserInfo>>, (StatusCode, Json<ErrorResponse>)> {
+    let security = modify_user(&state, user, client_ip, &username, req)
+        .await?
+        .ok_or_else(|| user_not_found(&username))?;
+    saved_user(&security, &username, &state.stats)
+}
+
+/// Remove the user `username`.
+pub async fn delete_user(
+    State(state): State<AppState>,
+    axum::extract::Path(username): axum::extract::Path<String>,
+) -> Result<Json<ApiResponse<()>>, (StatusCode, Json<ErrorResponse>)> {
+    let mut security = state.config_manager.get_security().await;
+    if !security.remove_user(&username) {
+        return Err(user_not_found(&username));
+    }
+    state
+        .config_manager
+        .update_security(security)
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    Ok(ApiResponse::ok(()))
+}
+
+async fn set_user_enabled(
+    state: AppState,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    username: String,
+    enabled: bool,
+) -> Result<Json<ApiResponse<UserInfo>>, (StatusCode, Json<ErrorResponse>)> {
+    let changes = UserChanges {
+        enabled: Some(enabled),
+        ..UserChanges::default()
+    };
+    let security = modify_user(&state, user, client_ip, &username, changes)
+        .await?
+        .ok_or_else(|| user_not_found(&username))?;
+    saved_user(&security, &username, &state.stats)
+}
+
+/// Disable the user `username`; its credentials are refused until enabled.
+pub async fn disable_user(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    axum::extract::Path(username): axum::extract::Path<String>,
+) -> Result<Json<ApiResponse<UserInfo>>, (StatusCode, Json<ErrorResponse>)> {
+    set_user_enabled(state, user, client_ip, username, false).await
+}
+
+/// Enable the user `username`.
+pub async fn enable_user(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    axum::extract::Path(username): axum::extract::Path<String>,
+) -> Result<Json<ApiResponse<UserInfo>>, (StatusCode, Json<ErrorResponse>)> {
+    set_user_enabled(state, user, client_ip, username, true).await
+}
+
+/// Token metadata (the token itself is only returned when created).
+#[derive(Debug, Serialize)]
+pub struct TokenInfo {
+    pub id: String,
+    pub label: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub expired: bool,
+}
+
+impl From<&UserToken> for TokenInfo {
+    fn from(token: &UserToken) -> Self {
+        Self {
+            id: token.id.clone(),
+            label: token.label.clone(),
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+            expired: token.is_expired(Utc::now()),
         }
+    }
+}
+
+/// Create token request.
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateTokenRequest {
+    pub label: Option<String>,
+    /// When the token stops being accepted (default: never).
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A newly created token.
+#[derive(Debug, Serialize)]
+pub struct CreatedToken {
+    #[serde(flatten)]
+    pub info: TokenInfo,
+    /// The token; it can't be retrieved again.
+    pub token: String,
+    /// Username to send with the token as the SOCKS5 password.
+    pub socks_username: &'static str,
+}
+
+/// List a user's proxy tokens.
+pub async fn list_user_tokens(
+    State(state): State<AppState>,
+    axum::extract::Path(username): axum::extract::Path<String>,
+) -> Result<Json<ApiResponse<Vec<TokenInfo>>>, (StatusCode, Json<ErrorResponse>)> {
+    let security = state.config_manager.get_security().await;
+    let user = security
+        .users
+        .iter()
+        .find(|u| u.username == username)
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                ErrorResponse::new(format!("User '{}' not found", username)),
+            )
+        })?;
+    Ok(ApiResponse::ok(
+        user.tokens.iter().map(TokenInfo::from).collect(),
+    ))
+}
+
+/// Create a proxy token for a user.
+///
+/// Clients send it as `Proxy-Authorization: Bearer <token>` (HTTP) or as the
+/// password of the `token` user (SOCKS5).
+pub async fn create_user_token(
+    State(state): State<AppState>,
+    axum::extract::Path(username): axum::extract::Path<String>,
+    body: Option<Json<CreateTokenRequest>>,
+) -> Result<Json<ApiResponse<CreatedToken>>, (StatusCode, Json<ErrorResponse>)> {
+    let req = body.map(|Json(req)| req).unwrap_or_default();
+    if req
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::new("expires_at must be in the future"),
+        ));
+    }
+
+    match state
+        .config_manager
+        .create_user_token(&username, req.label, req.expires_at)
+        .await
+    {
+        Ok(Some((record, token))) => Ok(ApiResponse::ok(CreatedToken {
+            info: TokenInfo::from(&record),
+            token,
+            socks_username: TOKEN_USERNAME,
+        })),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            ErrorResponse::new(format!("User '{}' not found", username)),
+        )),
+        Err(e) => Err(update_failed("Failed to create token", e)),
+    }
+}
+
+/// Revoke one of a user's proxy tokens.
+pub async fn revoke_user_token(
+    State(state): State<AppState>,
+    axum::extract::Path((username, id)): axum::extract::Path<(String, String)>,
+) -> Result<Json<ApiResponse<()>>, (StatusCode, Json<ErrorResponse>)> {
+    match state.config_manager.revoke_user_token(&username, &id).await {
+        Ok(true) => Ok(ApiResponse::ok(())),
+        Ok(false) => Err((
+            StatusCode::NOT_FOUND,
+            ErrorResponse::new(format!("Token '{}' of user '{}' not found", id, username)),
+        )),
+        Err(e) => Err(update_failed("Failed to revoke token", e)),
+    }
+}
 
-        let _ = state.config_manager.update_security(security.clone()).await;
+/// Get per-endpoint API latency statistics.
+pub async fn get_api_stats(
+    State(state): State<AppState>,
+) -> Json<ApiResponse<Vec<EndpointLatency>>> {
+    ApiResponse::ok(state.api_metrics.snapshot())
+}
+
+/// User statistics query parameters.
+#[derive(Debug, Deserialize)]
+pub struct UserStatsQuery {
+    /// `true` for only client IP buckets, `false` for only real users (default both).
+    pub ip_buckets: Option<bool>,
+}
+
+/// Get per-user statistics.
+pub async fn get_user_stats(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<UserStatsQuery>,
+) -> Result<Json<ApiResponse<Vec<UserStats>>>, (StatusCode, Json<ErrorResponse>)> {
+    require_stats(&state)?;
+    let mut user_stats = state.stats.get_user_stats().await;
+    if let Some(ip_buckets) = query.ip_buckets {
+        user_stats.retain(|user| user.ip_bucket == ip_buckets);
     }
+    Ok(ApiResponse::ok(user_stats))
+}
 
-    let users: Vec<UserInfo> = security.users.iter().map(UserInfo::from).collect();
-    ApiResponse::ok(SecurityResponse {
-        auth_enabled: security.auth_enabled,
-        user_count: users.len(),
-        users,
-    })
+/// Effective limits applied to a user's connections.
+#[derive(Debug, Serialize)]
+pub struct EffectiveLimits {
+    /// Bandwidth limit in bytes per second (0 = unlimited).
+    pub bandwidth_limit: u64,
+    /// Concurrent connection limit (0 = unlimited).
+    pub connection_limit: u32,
+    /// Seconds each connection may stay open (0 = unlimited).
+    pub max_connection_lifetime_secs: u64,
 }
 
-/// Remove user request.
+/// Combined configuration and statistics for a single user.
+#[derive(Debug, Serialize)]
+pub struct UserDetailResponse {
+    pub user: UserInfo,
+    pub stats: UserStats,
+    pub active_connections: Vec<ConnectionInfo>,
+    pub recent_history: Vec<ConnectionStats>,
+    pub limits: EffectiveLimits,
+}
+
+/// User detail query parameters.
 #[derive(Debug, Deserialize)]
-pub struct RemoveUserRequest {
-    pub username: String,
+pub struct UserDetailQuery {
+    /// Maximum number of history entries to include (default 20).
+    pub history_limit: Option<usize>,
 }
 
-/// Remove a user.
-pub async fn remove_user(
+/// Get configuration, stats, active connections and recent history for one user.
+pub async fn get_user_detail(
     State(state): State<AppState>,
-    Json(req): Json<RemoveUserRequest>,
-) -> Json<ApiResponse<SecurityResponse>> {
-    let mut security = state.config_manager.get_security().await;
-
-    security.remove_user(&req.username);
+    axum::extract::Path(username): axum::extract::Path<String>,
+    axum::extract::Query(query): axum::extract::Query<UserDetailQuery>,
+) -> Result<Json<ApiResponse<UserDetailResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let security = state.config_manager.get_security().await;
+    let user = security
+        .users
+        .iter()
+        .find(|u| u.username == username)
+        .ok_or_else(|| user_not_found(&username))?;
 
-    let _ = state.config_manager.update_security(security.clone()).await;
+    let stats = state
+        .stats
+        .get_user(&username)
+        .await
+        .unwrap_or_else(|| UserStats {
+            username: username.clone(),
+            ..Default::default()
+        });
+    let active_connections = state.stats.get_active_for_user(&username).await;
+    let recent_history = state
+        .stats
+        .get_history_for_user(&username, Some(query.history_limit.unwrap_or(20)))
+        .await;
 
-    let users: Vec<UserInfo> = security.users.iter().map(UserInfo::from).collect();
-    ApiResponse::ok(SecurityResponse {
-        auth_enabled: security.auth_enabled,
-        user_count: users.len(),
-        users,
-    })
+    Ok(ApiResponse::ok(UserDetailResponse {
+        user: UserInfo::new(user, &state.stats),
+        stats,
+        active_connections,
+        recent_history,
+        limits: EffectiveLimits {
+            bandwidth_limit: user.bandwidth_limit,
+            connection_limit: user.connection_limit,
+            max_connection_lifetime_secs: user.max_connection_lifetime_secs.unwrap_or(
+                state
+                    .config_manager
+                    .get_limits()
+                    .await
+                    .max_connection_lifetime_secs,
+            ),
+        },
+    }))
 }
 
-/// Get per-user statistics.
-pub async fn get_user_stats(State(state): State<AppState>) -> Json<ApiResponse<Vec<UserStats>>> {
-    let user_stats = state.stats.get_user_stats().await;
-    ApiResponse::ok(user_stats)
+/// Reset a user's quota usage for the current period.
+pub async fn reset_user_quota(
+    State(state): State<AppState>,
+    axum::extract::Path(username): axum::extract::Path<String>,
+) -> Result<Json<ApiResponse<UserInfo>>, (StatusCode, Json<ErrorResponse>)> {
+    let security = state.config_manager.get_security().await;
+    let user = security
+        .users
+        .iter()
+        .find(|u| u.username == username)
+        .ok_or_else(|| user_not_found(&username))?;
+
+    state.stats.reset_quota(user).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorResponse::new(format!("Failed to save quota usage: {}", e)),
+        )
+    })?;
+
+    Ok(ApiResponse::ok(UserInfo::new(user, &state.stats)))
 }
 
 // ==================== Authentication API ====================
@@ -466,6 +2792,12 @@ pub struct AuthCheckResponse {
     pub auth_enabled: bool,
     pub authenticated: bool,
     pub username: Option<String>,
+    /// Whether `POST /api/setup` can still create the first administrator.
+    pub setup_available: bool,
+    /// Set while anyone reaching the dashboard can use it (only shown to
+    /// authenticated callers).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub security_warning: Option<DashboardSecurityWarning>,
 }
 
 /// Check authentication status.
@@ -473,13 +2805,17 @@ pub async fn auth_check(
     State(state): State<AppState>,
     headers: HeaderMap,
 ) -> Json<ApiResponse<AuthCheckResponse>> {
-    let auth_enabled = state.config_manager.is_dashboard_auth_enabled().await;
+    let dashboard = state.config_manager.get_dashboard().await;
+    let auth_enabled = dashboard.auth_enabled;
+    let setup_available = !dashboard.has_admins();
 
     if !auth_enabled {
         return ApiResponse::ok(AuthCheckResponse {
             auth_enabled: false,
             authenticated: true,
             username: None,
+            setup_available,
+            security_warning: dashboard.security_warning(),
         });
     }
 
@@ -490,7 +2826,12 @@ pub async fn auth_check(
 
     let username = match cookie_header {
         Some(cookies) => match extract_session_token(cookies) {
-            Some(token) => state.session_store.validate(&token).await,
+            Some(token) => {
+                state
+                    .session_store
+                    .validate(&token, dashboard.session_ttl())
+                    .await
+            }
             None => None,
         },
         None => None,
@@ -502,12 +2843,15 @@ pub async fn auth_check(
         auth_enabled,
         authenticated,
         username,
+        setup_available,
+        security_warning: dashboard.security_warning().filter(|_| authenticated),
     })
 }
 
 /// Login handler.
 pub async fn login(
     State(state): State<AppState>,
+    client_ip: Option<axum::Extension<ClientIp>>,
     Json(req): Json<LoginRequest>,
 ) -> (HeaderMap, Json<ApiResponse<LoginResponse>>) {
     let mut headers = HeaderMap::new();
@@ -521,13 +2865,18 @@ pub async fn login(
         // Create session
         let token = state
             .session_store
-            .create_session(req.username.clone())
+            .create_session(
+                req.username.clone(),
+                client_ip.map(|axum::Extension(ClientIp(ip))| ip),
+            )
             .await;
 
-        // Set cookie
+        // Set cookie, expiring with the session
+        let ttl = state.config_manager.dashboard_session_ttl().await;
         let cookie = format!(
-            "net_relay_session={}; Path=/; HttpOnly; SameSite=Strict; Max-Age=86400",
-            token
+            "net_relay_session={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}",
+            token,
+            ttl.as_secs()
         );
         headers.insert(SET_COOKIE, cookie.parse().unwrap());
 
@@ -577,6 +2926,76 @@ pub async fn logout(
     (response_headers, ApiResponse::ok(true))
 }
 
+/// Change password request.
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// Change password response.
+#[derive(Debug, Serialize)]
+pub struct ChangePasswordResponse {
+    /// Number of other sessions that were logged out.
+    pub sessions_revoked: usize,
+}
+
+/// Change the dashboard password after verifying the current one.
+///
+/// All sessions except the caller's are invalidated.
+pub async fn change_password(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<ChangePasswordRequest>,
+) -> Result<Json<ApiResponse<ChangePasswordResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let mut dashboard = state.config_manager.get_dashboard().await;
+
+    if !dashboard.auth_enabled {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::new(
+                "Dashboard authentication is disabled; enable it before changing the password",
+            ),
+        ));
+    }
+
+    let caller_token = caller_token(&headers);
+    let username = state
+        .session_store
+        .validate(&caller_token, dashboard.session_ttl())
+        .await
+        .unwrap_or_default();
+
+    if !state
+        .config_manager
+        .authenticate_dashboard(&username, &req.current_password)
+        .await
+    {
+        return Err((
+            StatusCode::FORBIDDEN,
+            ErrorResponse::new("Current password is incorrect"),
+        ));
+    }
+
+    check_password(&state, "new_password", &req.new_password).await?;
+
+    if dashboard.auth_enabled && !dashboard.set_password(&username, req.new_password) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            ErrorResponse::new(format!("Dashboard user '{}' not found", username)),
+        ));
+    }
+    state
+        .config_manager
+        .update_dashboard(dashboard)
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+
+    let sessions_revoked = state.session_store.remove_all_except(&caller_token).await;
+
+    Ok(ApiResponse::ok(ChangePasswordResponse { sessions_revoked }))
+}
+
 /// Extract session token from cookie header.
 fn extract_session_token(cookies: &str) -> Option<String> {
     for cookie in cookies.split(';') {
@@ -590,6 +3009,481 @@ fn extract_session_token(cookies: &str) -> Option<String> {
     None
 }
 
+// ==================== First-run Setup API ====================
+
+/// First administrator of a fresh install.
+#[derive(Debug, Deserialize)]
+pub struct SetupRequest {
+    pub username: String,
+    pub password: String,
+}
+
+/// Create the first dashboard administrator (with a hashed password) and
+/// turn on dashboard authentication.
+///
+/// Only available while no administrator exists; afterwards 410 Gone.
+pub async fn setup(
+    State(state): State<AppState>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    Json(req): Json<SetupRequest>,
+) -> Result<Json<ApiResponse<DashboardAuthResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let gone = || {
+        (
+            StatusCode::GONE,
+            ErrorResponse::new("Setup has already been completed"),
+        )
+    };
+    if state.config_manager.get_dashboard().await.has_admins() {
+        return Err(gone());
+    }
+
+    let username = req.username.trim().to_string();
+    if username.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::validation(vec![FieldError {
+                field: "username".to_string(),
+                message: "must not be empty".to_string(),
+            }]),
+        ));
+    }
+    if DEFAULT_CREDENTIALS
+        .iter()
+        .any(|&(_, default_password)| req.password == default_password)
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::validation(vec![FieldError {
+                field: "password".to_string(),
+                message: "is a well-known default password".to_string(),
+            }]),
+        ));
+    }
+    check_password(&state, "password", &req.password).await?;
+
+    let password = hash_password(&req.password).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorResponse::new(e.to_string()),
+        )
+    })?;
+    let admin = net_relay_core::DashboardUser {
+        username: username.clone(),
+        password,
+    };
+    let completed = state
+        .config_manager
+        .complete_setup(admin)
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    if !completed {
+        return Err(gone());
+    }
+
+    tracing::warn!(
+        target: "net_relay_api::audit",
+        user = %username,
+        client_ip = %audit_ip(client_ip),
+        "Dashboard setup completed"
+    );
+    let dashboard = state.config_manager.get_dashboard().await;
+    Ok(ApiResponse::ok(DashboardAuthResponse::new(&dashboard)))
+}
+
+// ==================== Dashboard Sessions API ====================
+
+/// Session token of the request, empty without one.
+fn caller_token(headers: &HeaderMap) -> String {
+    headers
+        .get(axum::http::header::COOKIE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(extract_session_token)
+        .unwrap_or_default()
+}
+
+/// List the dashboard sessions that are logged in.
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    headers: HeaderMap,
+) -> Result<Json<ApiResponse<Vec<SessionInfo>>>, (StatusCode, Json<ErrorResponse>)> {
+    if user.is_none() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            ErrorResponse::new("listing sessions requires a dashboard admin session"),
+        ));
+    }
+    let caller = caller_token(&headers);
+    let ttl = state.config_manager.dashboard_session_ttl().await;
+    Ok(ApiResponse::ok(
+        state.session_store.list(Some(caller.as_str()), ttl).await,
+    ))
+}
+
+/// Sessions logged out by a revocation.
+#[derive(Debug, Serialize)]
+pub struct RevokeSessionsResponse {
+    pub revoked: usize,
+}
+
+/// Log out the session whose token starts with `token_prefix`.
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    axum::extract::Path(prefix): axum::extract::Path<String>,
+) -> Result<Json<ApiResponse<RevokeSessionsResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let Some(axum::Extension(DashboardUser(username))) = user else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            ErrorResponse::new("revoking sessions requires a dashboard admin session"),
+        ));
+    };
+    if prefix.len() < TOKEN_PREFIX_LEN {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::new(format!(
+                "token prefix must be at least {} characters",
+                TOKEN_PREFIX_LEN
+            )),
+        ));
+    }
+
+    match state.session_store.remove_prefix(&prefix).await {
+        Ok(session) => {
+            tracing::warn!(
+                target: "net_relay_api::audit",
+                user = %username,
+                client_ip = %audit_ip(client_ip),
+                session_user = %session.username,
+                token_prefix = %token_prefix(&prefix),
+                "Dashboard session revoked"
+            );
+            Ok(ApiResponse::ok(RevokeSessionsResponse { revoked: 1 }))
+        }
+        Err(0) => Err((
+            StatusCode::NOT_FOUND,
+            ErrorResponse::new(format!("No session matches '{}'", prefix)),
+        )),
+        Err(matching) => Err((
+            StatusCode::CONFLICT,
+            ErrorResponse::new(format!(
+                "'{}' matches {} sessions; give more of the token",
+                prefix, matching
+            )),
+        )),
+    }
+}
+
+/// Query of [`revoke_all_sessions`].
+#[derive(Debug, Deserialize)]
+pub struct RevokeAllSessionsQuery {
+    /// Only log out this dashboard user's sessions.
+    pub user: Option<String>,
+}
+
+/// Log out every session (or every session of `user`) except the caller's.
+pub async fn revoke_all_sessions(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    headers: HeaderMap,
+    axum::extract::Query(query): axum::extract::Query<RevokeAllSessionsQuery>,
+) -> Result<Json<ApiResponse<RevokeSessionsResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let Some(axum::Extension(DashboardUser(username))) = user else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            ErrorResponse::new("revoking sessions requires a dashboard admin session"),
+        ));
+    };
+    let target = query.user.filter(|user| !user.is_empty());
+
+    let revoked = state
+        .session_store
+        .remove_except(&caller_token(&headers), target.as_deref())
+        .await;
+    tracing::warn!(
+        target: "net_relay_api::audit",
+        user = %username,
+        client_ip = %audit_ip(client_ip),
+        session_user = target.as_deref().unwrap_or("*"),
+        revoked,
+        "Dashboard sessions revoked"
+    );
+    Ok(ApiResponse::ok(RevokeSessionsResponse { revoked }))
+}
+
+// ==================== Dashboard Administrators API ====================
+
+/// Dashboard settings, separate from proxy users in `/config/security`.
+///
+/// Passwords and the metrics token are left out.
+#[derive(Debug, Serialize)]
+pub struct DashboardAuthResponse {
+    pub auth_enabled: bool,
+    /// Usernames of the dashboard administrators.
+    pub admins: Vec<String>,
+    pub admin_count: usize,
+    pub session_ttl_secs: u64,
+    pub trusted_proxies: Vec<String>,
+    pub real_ip_header: Option<String>,
+    pub allowed_ips: Vec<String>,
+    pub allowed_origins: Vec<String>,
+    /// Whether `metrics_token` is set.
+    pub metrics_token_set: bool,
+    pub security_headers: bool,
+    pub frame_ancestors: Vec<String>,
+    pub content_security_policy: Option<String>,
+    pub rate_limit: ApiRateLimitConfig,
+}
+
+impl DashboardAuthResponse {
+    fn new(dashboard: &DashboardConfig) -> Self {
+        let admins: Vec<String> = dashboard
+            .users
+            .iter()
+            .map(|admin| admin.username.clone())
+            .chain(
+                dashboard
+                    .username
+                    .clone()
+                    .filter(|_| dashboard.password.is_some()),
+            )
+            .collect();
+        Self {
+            auth_enabled: dashboard.auth_enabled,
+            admin_count: admins.len(),
+            admins,
+            session_ttl_secs: dashboard.session_ttl_secs,
+            trusted_proxies: dashboard.trusted_proxies.clone(),
+            real_ip_header: dashboard.real_ip_header.clone(),
+            allowed_ips: dashboard.allowed_ips.clone(),
+            allowed_origins: dashboard.allowed_origins.clone(),
+            metrics_token_set: dashboard.metrics_token.is_some(),
+            security_headers: dashboard.security_headers,
+            frame_ancestors: dashboard.frame_ancestors.clone(),
+            content_security_policy: dashboard.content_security_policy.clone(),
+            rate_limit: dashboard.rate_limit.clone(),
+        }
+    }
+}
+
+async fn save_dashboard(
+    state: &AppState,
+    dashboard: DashboardConfig,
+) -> Result<Json<ApiResponse<DashboardAuthResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let response = DashboardAuthResponse::new(&dashboard);
+    if let Err(e) = state.config_manager.update_dashboard(dashboard).await {
+        if e.is::<ConfigSaveError>() {
+            return Err(update_failed("Failed to save", e));
+        }
+        // Invalid settings are refused before anything changes
+        return Err((StatusCode::BAD_REQUEST, ErrorResponse::new(e.to_string())));
+    }
+    Ok(ApiResponse::ok(response))
+}
+
+/// Get dashboard settings.
+pub async fn get_dashboard_auth(
+    State(state): State<AppState>,
+) -> Json<ApiResponse<DashboardAuthResponse>> {
+    let dashboard = state.config_manager.get_dashboard().await;
+    ApiResponse::ok(DashboardAuthResponse::new(&dashboard))
+}
+
+/// Update dashboard settings request.
+///
+/// An empty string clears `real_ip_header`, `metrics_token` and
+/// `content_security_policy`.
+#[derive(Debug, Deserialize)]
+pub struct UpdateDashboardAuthRequest {
+    pub auth_enabled: Option<bool>,
+    pub session_ttl_secs: Option<u64>,
+    pub trusted_proxies: Option<Vec<String>>,
+    pub real_ip_header: Option<String>,
+    pub allowed_ips: Option<Vec<String>>,
+    pub allowed_origins: Option<Vec<String>>,
+    pub metrics_token: Option<String>,
+    pub security_headers: Option<bool>,
+    pub frame_ancestors: Option<Vec<String>>,
+    pub content_security_policy: Option<String>,
+    pub rate_limit: Option<ApiRateLimitConfig>,
+}
+
+/// Update dashboard settings; they apply from the next request on.
+pub async fn update_dashboard_auth(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    Json(req): Json<UpdateDashboardAuthRequest>,
+) -> Result<Json<ApiResponse<DashboardAuthResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let mut dashboard = state.config_manager.get_dashboard().await;
+    let before = dashboard.clone();
+    if let Some(enabled) = req.auth_enabled {
+        if enabled && !dashboard.has_admins() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                ErrorResponse::new("Add a dashboard administrator before enabling authentication"),
+            ));
+        }
+        dashboard.auth_enabled = enabled;
+    }
+    if let Some(ttl) = req.session_ttl_secs {
+        dashboard.session_ttl_secs = ttl;
+    }
+    if let Some(proxies) = req.trusted_proxies {
+        dashboard.trusted_proxies = proxies;
+    }
+    if let Some(header) = req.real_ip_header {
+        dashboard.real_ip_header = Some(header).filter(|h| !h.is_empty());
+    }
+    if let Some(ips) = req.allowed_ips {
+        dashboard.allowed_ips = ips;
+    }
+    if let Some(origins) = req.allowed_origins {
+        dashboard.allowed_origins = origins;
+    }
+    if let Some(token) = req.metrics_token {
+        dashboard.metrics_token = Some(token).filter(|t| !t.is_empty());
+    }
+    if let Some(enabled) = req.security_headers {
+        dashboard.security_headers = enabled;
+    }
+    if let Some(ancestors) = req.frame_ancestors {
+        dashboard.frame_ancestors = ancestors;
+    }
+    if let Some(policy) = req.content_security_policy {
+        dashboard.content_security_policy = Some(policy).filter(|p| !p.is_empty());
+    }
+    if let Some(rate_limit) = req.rate_limit {
+        dashboard.rate_limit = rate_limit;
+    }
+
+    let changed = dashboard_changes(&before, &dashboard);
+    let response = save_dashboard(&state, dashboard).await?;
+    if !changed.is_empty() {
+        tracing::warn!(
+            target: "net_relay_api::audit",
+            user = %user.as_ref().map_or("-", |axum::Extension(DashboardUser(u))| u.as_str()),
+            client_ip = %audit_ip(client_ip),
+            changed = %changed.join(","),
+            "Dashboard settings changed"
+        );
+    }
+    Ok(response)
+}
+
+/// Names of the dashboard settings that differ between `before` and `after`.
+fn dashboard_changes(before: &DashboardConfig, after: &DashboardConfig) -> Vec<&'static str> {
+    [
+        ("auth_enabled", before.auth_enabled != after.auth_enabled),
+        (
+            "session_ttl_secs",
+            before.session_ttl_secs != after.session_ttl_secs,
+        ),
+        (
+            "trusted_proxies",
+            before.trusted_proxies != after.trusted_proxies,
+        ),
+        (
+            "real_ip_header",
+            before.real_ip_header != after.real_ip_header,
+        ),
+        ("allowed_ips", before.allowed_ips != after.allowed_ips),
+        (
+            "allowed_origins",
+            before.allowed_origins != after.allowed_origins,
+        ),
+        ("metrics_token", before.metrics_token != after.metrics_token),
+        (
+            "security_headers",
+            before.security_headers != after.security_headers,
+        ),
+        (
+            "frame_ancestors",
+            before.frame_ancestors != after.frame_ancestors,
+        ),
+        (
+            "content_security_policy",
+            before.content_security_policy != after.content_security_policy,
+        ),
+        ("rate_limit", before.rate_limit != after.rate_limit),
+    ]
+    .into_iter()
+    .filter_map(|(name, changed)| changed.then_some(name))
+    .collect()
+}
+
+/// Add dashboard administrator request.
+#[derive(Debug, Deserialize)]
+pub struct AddDashboardAdminRequest {
+    pub username: String,
+    pub password: String,
+}
+
+/// Add a dashboard administrator.
+pub async fn add_dashboard_admin(
+    State(state): State<AppState>,
+    Json(req): Json<AddDashboardAdminRequest>,
+) -> Result<Json<ApiResponse<DashboardAuthResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    if req.username.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::validation(vec![FieldError {
+                field: "username".to_string(),
+                message: "must not be empty".to_string(),
+            }]),
+        ));
+    }
+    check_password(&state, "password", &req.password).await?;
+    let mut dashboard = state.config_manager.get_dashboard().await;
+    if DashboardAuthResponse::new(&dashboard)
+        .admins
+        .contains(&req.username)
+    {
+        return Err((
+            StatusCode::CONFLICT,
+            ErrorResponse::new(format!("Dashboard user '{}' already exists", req.username)),
+        ));
+    }
+    dashboard.users.push(net_relay_core::DashboardUser {
+        username: req.username,
+        password: req.password,
+    });
+    save_dashboard(&state, dashboard).await
+}
+
+/// Remove a dashboard administrator and sign out their sessions.
+pub async fn remove_dashboard_admin(
+    State(state): State<AppState>,
+    axum::extract::Path(username): axum::extract::Path<String>,
+) -> Result<Json<ApiResponse<DashboardAuthResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let mut dashboard = state.config_manager.get_dashboard().await;
+    let before = dashboard.users.len();
+    dashboard.users.retain(|admin| admin.username != username);
+    if dashboard.users.len() == before {
+        if dashboard.username.as_deref() != Some(username.as_str()) {
+            return Err((
+                StatusCode::NOT_FOUND,
+                ErrorResponse::new(format!("Dashboard user '{}' not found", username)),
+            ));
+        }
+        dashboard.username = None;
+        dashboard.password = None;
+    }
+    if dashboard.auth_enabled && !dashboard.has_admins() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::new(
+                "Cannot remove the last dashboard administrator while authentication is enabled",
+            ),
+        ));
+    }
+    let response = save_dashboard(&state, dashboard).await?;
+    state.session_store.remove_user(&username).await;
+    Ok(response)
+}
+
 // ==================== Server Configuration API ====================
 
 /// Server configuration response.
@@ -599,7 +3493,13 @@ pub struct ServerConfigResponse {
     pub socks_port: u16,
     pub http_port: u16,
     pub api_port: u16,
+    pub api_host: Option<String>,
+    pub socks_enabled: bool,
+    pub http_enabled: bool,
+    pub api_enabled: bool,
     pub requires_restart: bool,
+    /// Listeners as currently bound; changes only take effect on restart.
+    pub listeners: Vec<ListenerInfo>,
 }
 
 impl From<ServerConfig> for ServerConfigResponse {
@@ -609,7 +3509,12 @@ impl From<ServerConfig> for ServerConfigResponse {
             socks_port: config.socks_port,
             http_port: config.http_port,
             api_port: config.api_port,
+            api_host: config.api_host,
+            socks_enabled: config.socks_enabled,
+            http_enabled: config.http_enabled,
+            api_enabled: config.api_enabled,
             requires_restart: false,
+            listeners: Vec::new(),
         }
     }
 }
@@ -619,7 +3524,15 @@ pub async fn get_server_config(
     State(state): State<AppState>,
 ) -> Json<ApiResponse<ServerConfigResponse>> {
     let server = state.config_manager.get_server().await;
-    ApiResponse::ok(ServerConfigResponse::from(server))
+    let mut response = ServerConfigResponse::from(server);
+    response.listeners = state.server.listeners();
+    ApiResponse::ok(response)
+}
+
+/// Get the listeners with the addresses they are bound to, which tells the
+/// actual ports when `0` is configured.
+pub async fn get_listeners(State(state): State<AppState>) -> Json<ApiResponse<Vec<ListenerInfo>>> {
+    ApiResponse::ok(state.server.listeners())
 }
 
 /// Update server configuration request.
@@ -629,13 +3542,18 @@ pub struct UpdateServerRequest {
     pub socks_port: Option<u16>,
     pub http_port: Option<u16>,
     pub api_port: Option<u16>,
+    /// Empty string resets the API host to `host`.
+    pub api_host: Option<String>,
+    pub socks_enabled: Option<bool>,
+    pub http_enabled: Option<bool>,
+    pub api_enabled: Option<bool>,
 }
 
 /// Update server configuration.
 pub async fn update_server_config(
     State(state): State<AppState>,
     Json(req): Json<UpdateServerRequest>,
-) -> Json<ApiResponse<ServerConfigResponse>> {
+) -> Result<Json<ApiResponse<ServerConfigResponse>>, (StatusCode, Json<ErrorResponse>)> {
     let mut server = state.config_manager.get_server().await;
 
     if let Some(host) = req.host {
@@ -650,17 +3568,212 @@ pub async fn update_server_config(
     if let Some(port) = req.api_port {
         server.api_port = port;
     }
+    if let Some(api_host) = req.api_host {
+        server.api_host = Some(api_host).filter(|h| !h.is_empty());
+    }
+    if let Some(enabled) = req.socks_enabled {
+        server.socks_enabled = enabled;
+    }
+    if let Some(enabled) = req.http_enabled {
+        server.http_enabled = enabled;
+    }
+    if let Some(enabled) = req.api_enabled {
+        server.api_enabled = enabled;
+    }
 
-    match state.config_manager.update_server(server.clone()).await {
-        Ok(_) => {
-            let mut response = ServerConfigResponse::from(server);
-            response.requires_restart = true;
-            ApiResponse::ok(response)
+    if let Err(e) = state.config_manager.update_server(server.clone()).await {
+        if e.is::<ConfigSaveError>() {
+            return Err(update_failed("Failed to save", e));
         }
-        Err(e) => Json(ApiResponse {
-            success: false,
-            data: ServerConfigResponse::from(server),
-            message: Some(format!("Failed to save: {}", e)),
-        }),
+        // Conflicting ports are refused before anything changes
+        return Err((StatusCode::BAD_REQUEST, ErrorResponse::new(e.to_string())));
+    }
+    let mut response = ServerConfigResponse::from(server);
+    response.requires_restart = true;
+    response.listeners = state.server.listeners();
+    Ok(ApiResponse::ok(response))
+}
+
+// ==================== Limits API ====================
+
+/// Get connection limits.
+pub async fn get_limits(State(state): State<AppState>) -> Json<ApiResponse<LimitsConfig>> {
+    ApiResponse::ok(state.config_manager.get_limits().await)
+}
+
+/// Update limits request.
+#[derive(Debug, Deserialize)]
+pub struct UpdateLimitsRequest {
+    pub max_connections: Option<usize>,
+    pub timeout: Option<u64>,
+    pub idle_timeout: Option<u64>,
+    pub max_connection_lifetime_secs: Option<u64>,
+    pub global_bandwidth: Option<u64>,
+    pub quota_cutoff_active: Option<bool>,
+    pub connect_retries: Option<u32>,
+    pub connect_retry_delay_ms: Option<u64>,
+    pub connect_retry_jitter_ms: Option<u64>,
+    pub relay_buffer_size: Option<usize>,
+    pub max_relay_buffer_memory: Option<u64>,
+}
+
+/// Update connection limits. The global bandwidth cap applies to running
+/// relays; buffer sizes to relays started afterwards.
+pub async fn update_limits(
+    State(state): State<AppState>,
+    Json(req): Json<UpdateLimitsRequest>,
+) -> Result<Json<ApiResponse<LimitsConfig>>, (StatusCode, Json<ErrorResponse>)> {
+    let mut limits = state.config_manager.get_limits().await;
+
+    if let Some(max_connections) = req.max_connections {
+        limits.max_connections = max_connections;
+    }
+    if let Some(timeout) = req.timeout {
+        limits.timeout = timeout;
+    }
+    if let Some(idle_timeout) = req.idle_timeout {
+        limits.idle_timeout = idle_timeout;
+    }
+    if let Some(lifetime) = req.max_connection_lifetime_secs {
+        limits.max_connection_lifetime_secs = lifetime;
+    }
+    if let Some(global_bandwidth) = req.global_bandwidth {
+        limits.global_bandwidth = global_bandwidth;
+    }
+    if let Some(quota_cutoff_active) = req.quota_cutoff_active {
+        limits.quota_cutoff_active = quota_cutoff_active;
+    }
+    if let Some(connect_retries) = req.connect_retries {
+        limits.connect_retries = connect_retries.min(MAX_CONNECT_RETRIES);
+    }
+    if let Some(delay) = req.connect_retry_delay_ms {
+        limits.connect_retry_delay_ms = delay;
+    }
+    if let Some(jitter) = req.connect_retry_jitter_ms {
+        limits.connect_retry_jitter_ms = jitter;
+    }
+    if let Some(size) = req.relay_buffer_size {
+        limits.relay_buffer_size = size.clamp(MIN_RELAY_BUFFER_SIZE, MAX_RELAY_BUFFER_SIZE);
+    }
+    if let Some(cap) = req.max_relay_buffer_memory {
+        limits.max_relay_buffer_memory = cap;
+    }
+
+    state
+        .config_manager
+        .update_limits(limits.clone())
+        .await
+        .map_err(|e| update_failed("Failed to save", e))?;
+    Ok(ApiResponse::ok(limits))
+}
+
+/// Get statistics settings.
+pub async fn get_stats_config(State(state): State<AppState>) -> Json<ApiResponse<StatsConfig>> {
+    ApiResponse::ok(state.config_manager.get_stats().await)
+}
+
+/// Update statistics request.
+#[derive(Debug, Deserialize)]
+pub struct UpdateStatsRequest {
+    pub enabled: Option<bool>,
+}
+
+/// Turn statistics collection on or off. Turning it off drops the recorded
+/// connections, history and per-user statistics.
+pub async fn update_stats_config(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    Json(req): Json<UpdateStatsRequest>,
+) -> Result<Json<ApiResponse<StatsConfig>>, (StatusCode, Json<ErrorResponse>)> {
+    let mut stats_config = state.config_manager.get_stats().await;
+    let was_enabled = stats_config.enabled;
+    if let Some(enabled) = req.enabled {
+        stats_config.enabled = enabled;
+    }
+
+    let saved = state
+        .config_manager
+        .update_stats(stats_config.clone())
+        .await;
+    // A change that couldn't be saved still applies
+    state.stats.set_enabled(stats_config.enabled).await;
+    if was_enabled != stats_config.enabled {
+        tracing::warn!(
+            target: "net_relay_api::audit",
+            user = %user.as_ref().map_or("-", |axum::Extension(DashboardUser(u))| u.as_str()),
+            client_ip = %audit_ip(client_ip),
+            enabled = stats_config.enabled,
+            "Statistics collection toggled"
+        );
+    }
+    saved.map_err(|e| update_failed("Failed to save", e))?;
+    Ok(ApiResponse::ok(stats_config))
+}
+
+/// Configured profiles.
+#[derive(Debug, Serialize)]
+pub struct ProfilesResponse {
+    /// Name of the active profile, if any.
+    pub active: Option<String>,
+    pub profiles: Vec<ProfileEntry>,
+}
+
+/// A profile and whether it is active.
+#[derive(Debug, Serialize)]
+pub struct ProfileEntry {
+    pub name: String,
+    pub active: bool,
+    #[serde(flatten)]
+    pub profile: ConfigProfile,
+}
+
+async fn profiles_response(config_manager: &ConfigManager) -> ProfilesResponse {
+    let (active, profiles) = config_manager.get_profiles().await;
+    ProfilesResponse {
+        profiles: profiles
+            .into_iter()
+            .map(|(name, profile)| ProfileEntry {
+                active: active.as_ref() == Some(&name),
+                name,
+                profile,
+            })
+            .collect(),
+        active,
+    }
+}
+
+/// List the configured profiles.
+pub async fn list_profiles(State(state): State<AppState>) -> Json<ApiResponse<ProfilesResponse>> {
+    ApiResponse::ok(profiles_response(&state.config_manager).await)
+}
+
+/// Switch the live access control (and limits) to a profile.
+pub async fn activate_profile(
+    State(state): State<AppState>,
+    user: Option<axum::Extension<DashboardUser>>,
+    client_ip: Option<axum::Extension<ClientIp>>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> Result<Json<ApiResponse<ProfilesResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let (previous, _) = state.config_manager.get_profiles().await;
+    match state.config_manager.activate_profile(&name).await {
+        Ok(true) => {
+            tracing::warn!(
+                target: "net_relay_api::audit",
+                user = %user.as_ref().map_or("-", |axum::Extension(DashboardUser(u))| u.as_str()),
+                client_ip = %audit_ip(client_ip),
+                profile = %name,
+                previous = %previous.as_deref().unwrap_or("-"),
+                "Profile activated"
+            );
+            Ok(ApiResponse::ok(
+                profiles_response(&state.config_manager).await,
+            ))
+        }
+        Ok(false) => Err((
+            StatusCode::NOT_FOUND,
+            ErrorResponse::new(format!("Profile '{}' not found", name)),
+        )),
+        Err(e) => Err(update_failed("Failed to activate profile", e)),
     }
 }
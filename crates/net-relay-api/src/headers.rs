@@ -0,0 +1,81 @@
+//! Security headers and CORS for the dashboard and API.
+
+use axum::extract::Request;
+use axum::http::header::{self, HeaderName};
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use net_relay_core::ConfigManager;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+
+/// Built-in policy: everything from the dashboard's own origin. Inline styles
+/// are allowed because the markup toggles elements with `style` attributes.
+const DEFAULT_CSP: &str = "default-src 'self'; script-src 'self'; \
+    style-src 'self' 'unsafe-inline'; img-src 'self' data:; connect-src 'self'; \
+    object-src 'none'; base-uri 'self'; form-action 'self'";
+
+/// Middleware adding `dashboard.security_headers` to every response.
+///
+/// Headers already set by a handler are left alone.
+pub async fn security_headers_middleware(
+    config_manager: ConfigManager,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+
+    let dashboard = config_manager.get_dashboard().await;
+    if !dashboard.security_headers {
+        return response;
+    }
+
+    let csp = match &dashboard.content_security_policy {
+        Some(csp) => csp.clone(),
+        None if dashboard.frame_ancestors.is_empty() => {
+            format!("{}; frame-ancestors 'none'", DEFAULT_CSP)
+        }
+        None => format!(
+            "{}; frame-ancestors 'self' {}",
+            DEFAULT_CSP,
+            dashboard.frame_ancestors.join(" ")
+        ),
+    };
+
+    let mut headers: Vec<(HeaderName, String)> = vec![
+        (header::CONTENT_SECURITY_POLICY, csp),
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        (header::REFERRER_POLICY, "same-origin".to_string()),
+    ];
+    // X-Frame-Options can't name other origins; frame-ancestors covers them
+    if dashboard.frame_ancestors.is_empty() {
+        headers.push((header::X_FRAME_OPTIONS, "DENY".to_string()));
+    }
+
+    for (name, value) in headers {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().entry(name).or_insert(value);
+        }
+    }
+    response
+}
+
+/// CORS for `dashboard.allowed_origins`, read per request so changes apply
+/// without a restart.
+///
+/// Listed origins may send the session cookie; any other cross-origin request
+/// gets no CORS headers, so browsers keep it same-origin.
+pub fn cors_layer(config_manager: ConfigManager) -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::async_predicate(move |origin, _| {
+            let config_manager = config_manager.clone();
+            async move {
+                match origin.to_str() {
+                    Ok(origin) => config_manager.is_dashboard_origin_allowed(origin).await,
+                    Err(_) => false,
+                }
+            }
+        }))
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_headers(AllowHeaders::mirror_request())
+        .allow_credentials(true)
+}
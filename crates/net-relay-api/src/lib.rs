@@ -3,8 +3,20 @@
 //! REST API for the net-relay dashboard and monitoring.
 
 pub mod auth;
+pub mod client_ip;
 pub mod handlers;
+pub mod headers;
+pub mod metrics;
+pub mod rate_limit;
+pub mod request_log;
+pub mod revision;
 pub mod router;
+pub mod tls;
 
-pub use auth::{session_auth_middleware, SessionStore};
+pub use auth::{session_auth_middleware, DashboardUser, SessionStore};
+pub use client_ip::{client_ip_middleware, ClientIp};
+pub use handlers::ActiveServices;
+pub use rate_limit::{rate_limit_middleware, ApiRateLimiter};
+pub use request_log::{request_log_middleware, ApiMetrics};
 pub use router::create_router;
+pub use tls::{serve_http_challenges, TlsConnectInfo, TlsListener};
@@ -0,0 +1,417 @@
+//! Prometheus text exposition for `/api/metrics`.
+
+use net_relay_core::stats::{AggregatedStats, ListenerStats};
+use net_relay_core::{ConnectOutcome, DnsStats, PhaseLatency, TargetConnectStats};
+use std::fmt::Write;
+
+use crate::handlers::{CertExpiry, RuntimeResponse};
+use crate::request_log::EndpointLatency;
+
+/// Content type of the text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Failing targets exported with a `host` label, most failures first; the
+/// rest are only listed by `GET /api/stats/targets/errors`.
+pub const MAX_TARGET_LABELS: usize = 20;
+
+/// Metric families in the text exposition format.
+#[derive(Default)]
+struct Exposition {
+    out: String,
+}
+
+impl Exposition {
+    /// Start a metric family.
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    /// Add a sample to the current family.
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+                .collect();
+            let _ = write!(self.out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.out, " {}", value);
+    }
+
+    /// A family with a single unlabeled sample.
+    fn single(&mut self, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+        self.family(name, kind, help);
+        self.sample(name, &[], value);
+    }
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Render relay, runtime and API metrics.
+#[allow(clippy::too_many_arguments)]
+pub fn render(
+    aggregated: &AggregatedStats,
+    runtime: &RuntimeResponse,
+    listeners: &[ListenerStats],
+    latency: &[PhaseLatency],
+    dns: &DnsStats,
+    targets: &[TargetConnectStats],
+    api: &[EndpointLatency],
+    certificates: &[CertExpiry],
+) -> String {
+    let mut exp = Exposition::default();
+
+    exp.single(
+        "net_relay_connections_total",
+        "counter",
+        "Proxy connections since start.",
+        aggregated.total_connections,
+    );
+    let name = "net_relay_attempts_total";
+    exp.family(name, "counter", "Proxy requests since start, by outcome.");
+    let attempts = &aggregated.attempts;
+    for (outcome, count) in [
+        ("relayed", attempts.relayed),
+        ("denied", attempts.denied),
+        ("connect_failed", attempts.connect_failed),
+        ("auth_failed", attempts.auth_failed),
+    ] {
+        exp.sample(name, &[("outcome", outcome)], count);
+    }
+    let name = "net_relay_decision_cache_total";
+    exp.family(
+        name,
+        "counter",
+        "Target checks by access decision cache result.",
+    );
+    let cache = &aggregated.decision_cache;
+    for (result, count) in [("hit", cache.hits), ("miss", cache.misses)] {
+        exp.sample(name, &[("result", result)], count);
+    }
+    exp.single(
+        "net_relay_active_connections",
+        "gauge",
+        "Currently active proxy connections.",
+        aggregated.active_connections,
+    );
+    exp.single(
+        "net_relay_bytes_sent_total",
+        "counter",
+        "Bytes sent to clients.",
+        aggregated.total_bytes_sent,
+    );
+    exp.single(
+        "net_relay_bytes_received_total",
+        "counter",
+        "Bytes received from clients.",
+        aggregated.total_bytes_received,
+    );
+    exp.single(
+        "net_relay_uptime_seconds",
+        "gauge",
+        "Seconds since the server started.",
+        aggregated.uptime_secs,
+    );
+
+    exp.single(
+        "net_relay_connection_tasks",
+        "gauge",
+        "Spawned connection tasks still running.",
+        runtime.connection_tasks,
+    );
+    exp.single(
+        "net_relay_connection_task_panics_total",
+        "counter",
+        "Connection tasks that ended in a panic.",
+        runtime.panicked_tasks,
+    );
+    let buffers = &runtime.relay_buffers;
+    exp.single(
+        "net_relay_relay_buffer_bytes",
+        "gauge",
+        "Bytes of copy buffers held by running relays.",
+        buffers.bytes,
+    );
+    exp.single(
+        "net_relay_relay_buffer_fallbacks_total",
+        "counter",
+        "Relay buffers cut to the fallback size by limits.max_relay_buffer_memory.",
+        buffers.fallbacks_total,
+    );
+    let process = &runtime.process;
+    let process_gauges = [
+        (
+            "process_open_fds",
+            "Open file descriptors.",
+            process.open_fds,
+        ),
+        (
+            "process_max_fds",
+            "Maximum open file descriptors.",
+            process.max_fds,
+        ),
+        (
+            "process_resident_memory_bytes",
+            "Resident memory in bytes.",
+            process.resident_memory_bytes,
+        ),
+        (
+            "process_virtual_memory_bytes",
+            "Virtual memory in bytes.",
+            process.virtual_memory_bytes,
+        ),
+        ("process_threads", "OS threads.", process.threads),
+    ];
+    for (name, help, value) in process_gauges {
+        if let Some(value) = value {
+            exp.single(name, "gauge", help, value);
+        }
+    }
+
+    if let Some(tokio) = &runtime.tokio {
+        exp.single(
+            "net_relay_tokio_workers",
+            "gauge",
+            "Tokio worker threads.",
+            tokio.workers,
+        );
+        exp.single(
+            "net_relay_tokio_alive_tasks",
+            "gauge",
+            "Tokio tasks currently alive.",
+            tokio.alive_tasks,
+        );
+        exp.single(
+            "net_relay_tokio_global_queue_depth",
+            "gauge",
+            "Tokio tasks waiting in the global queue.",
+            tokio.global_queue_depth,
+        );
+    }
+
+    if !runtime.listeners.is_empty() {
+        exp.family(
+            "net_relay_listener_queued",
+            "gauge",
+            "Connections waiting to be accepted.",
+        );
+        for listener in &runtime.listeners {
+            let port = listener.port.to_string();
+            exp.sample(
+                "net_relay_listener_queued",
+                &[("port", &port)],
+                listener.queued,
+            );
+        }
+    }
+
+    if !listeners.is_empty() {
+        for (name, help) in [
+            (
+                "net_relay_accepted_total",
+                "Connections accepted by the listener.",
+            ),
+            (
+                "net_relay_accept_errors_total",
+                "Failed accepts on the listener (e.g. out of file descriptors).",
+            ),
+        ] {
+            exp.family(name, "counter", help);
+            for listener in listeners {
+                let value = if name == "net_relay_accepted_total" {
+                    listener.accepted
+                } else {
+                    listener.accept_errors
+                };
+                exp.sample(
+                    name,
+                    &[
+                        ("protocol", &listener.protocol),
+                        ("address", &listener.address),
+                    ],
+                    value,
+                );
+            }
+        }
+    }
+
+    if !certificates.is_empty() {
+        let name = "net_relay_tls_cert_expiry_timestamp";
+        exp.family(
+            name,
+            "gauge",
+            "Expiry (notAfter) of the certificate presented by a TLS listener, in seconds since the epoch.",
+        );
+        for cert in certificates {
+            exp.sample(
+                name,
+                &[("listener", &cert.listener)],
+                cert.not_after.timestamp(),
+            );
+        }
+    }
+
+    let sizes = &runtime.stats;
+    exp.family(
+        "net_relay_stats_entries",
+        "gauge",
+        "Entries held by in-memory statistics structures.",
+    );
+    for (structure, len) in [
+        ("history", sizes.history),
+        ("active", sizes.active),
+        ("users", sizes.users),
+        ("upstreams", sizes.upstreams),
+        ("egress", sizes.egress),
+        ("denied", sizes.denied),
+        ("targets", sizes.targets),
+    ] {
+        exp.sample("net_relay_stats_entries", &[("structure", structure)], len);
+    }
+    exp.family(
+        "net_relay_stats_evictions_total",
+        "counter",
+        "Entries dropped to keep statistics structures within their limits.",
+    );
+    for (structure, evicted) in [
+        ("users", sizes.evicted_users),
+        ("ip_buckets", sizes.evicted_ip_buckets),
+        ("denied", sizes.evicted_denied),
+    ] {
+        exp.sample(
+            "net_relay_stats_evictions_total",
+            &[("structure", structure)],
+            evicted,
+        );
+    }
+
+    let name = "net_relay_connection_phase_duration_seconds";
+    exp.family(
+        name,
+        "histogram",
+        "Duration of the handshake, connect and time-to-first-byte phases of proxy connections.",
+    );
+    for phase in latency {
+        let labels = [("phase", phase.phase.as_str())];
+        let mut cumulative = 0;
+        for bucket in &phase.buckets {
+            cumulative += bucket.count;
+            let le = match bucket.le_ms {
+                Some(ms) => (ms as f64 / 1000.0).to_string(),
+                None => "+Inf".to_string(),
+            };
+            exp.sample(
+                &format!("{}_bucket", name),
+                &[labels[0], ("le", &le)],
+                cumulative,
+            );
+        }
+        exp.sample(
+            &format!("{}_sum", name),
+            &labels,
+            phase.avg_ms * phase.count as f64 / 1000.0,
+        );
+        exp.sample(&format!("{}_count", name), &labels, phase.count);
+    }
+
+    exp.single(
+        "net_relay_dns_cache_hits_total",
+        "counter",
+        "Target lookups answered from the DNS cache.",
+        dns.cache_hits,
+    );
+    let name = "net_relay_dns_lookups_total";
+    exp.family(name, "counter", "Target lookups sent to a resolver.");
+    for resolver in &dns.resolvers {
+        exp.sample(name, &[("resolver", &resolver.resolver)], resolver.lookups);
+    }
+    let name = "net_relay_dns_lookup_failures_total";
+    exp.family(
+        name,
+        "counter",
+        "Failed target lookups, timeouts included, by resolver.",
+    );
+    for resolver in &dns.resolvers {
+        exp.sample(name, &[("resolver", &resolver.resolver)], resolver.failures);
+    }
+    let name = "net_relay_dns_lookup_duration_seconds";
+    exp.family(
+        name,
+        "histogram",
+        "Duration of target lookups, by resolver.",
+    );
+    for resolver in &dns.resolvers {
+        let labels = [("resolver", resolver.resolver.as_str())];
+        let latency = &resolver.latency;
+        let mut cumulative = 0;
+        for bucket in &latency.buckets {
+            cumulative += bucket.count;
+            let le = match bucket.le_ms {
+                Some(ms) => (ms as f64 / 1000.0).to_string(),
+                None => "+Inf".to_string(),
+            };
+            exp.sample(
+                &format!("{}_bucket", name),
+                &[labels[0], ("le", &le)],
+                cumulative,
+            );
+        }
+        exp.sample(
+            &format!("{}_sum", name),
+            &labels,
+            latency.avg_ms * latency.count as f64 / 1000.0,
+        );
+        exp.sample(&format!("{}_count", name), &labels, latency.count);
+    }
+
+    let name = "net_relay_target_connects";
+    exp.family(
+        name,
+        "gauge",
+        "Connects to the most failing targets in the last hour, by outcome.",
+    );
+    for target in targets.iter().take(MAX_TARGET_LABELS) {
+        for outcome in ConnectOutcome::ALL {
+            exp.sample(
+                name,
+                &[("host", &target.host), ("outcome", outcome.as_str())],
+                target.count(outcome),
+            );
+        }
+    }
+
+    let name = "net_relay_api_request_duration_seconds";
+    exp.family(name, "histogram", "API request latency.");
+    for endpoint in api {
+        let labels = [("endpoint", endpoint.endpoint.as_str())];
+        let mut cumulative = 0;
+        for bucket in &endpoint.buckets {
+            cumulative += bucket.count;
+            let le = match bucket.le_ms {
+                Some(ms) => (ms as f64 / 1000.0).to_string(),
+                None => "+Inf".to_string(),
+            };
+            exp.sample(
+                &format!("{}_bucket", name),
+                &[labels[0], ("le", &le)],
+                cumulative,
+            );
+        }
+        exp.sample(
+            &format!("{}_sum", name),
+            &labels,
+            endpoint.avg_ms * endpoint.count as f64 / 1000.0,
+        );
+        exp.sample(&format!("{}_count", name), &labels, endpoint.count);
+    }
+
+    exp.out
+}
@@ -0,0 +1,194 @@
+//! Per-client request limits on the API (`dashboard.rate_limit`).
+//!
+//! Every client IP has a token bucket per [`RouteClass`]. Requests are counted
+//! when they arrive, so a long-lived streaming response costs a single token
+//! however long it stays open.
+
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use net_relay_core::{ApiRateLimitConfig, ConfigManager, ErrorCode, RateLimit};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::client_ip::client_ip;
+use crate::handlers::ErrorResponse;
+
+/// Buckets kept before full (idle) ones are dropped; beyond this new clients
+/// are not limited.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Slack for refills that land a rounding error short of a whole token.
+const TOKEN_EPSILON: f64 = 1e-9;
+
+/// Group of routes sharing a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteClass {
+    Standard,
+    Expensive,
+    Auth,
+}
+
+impl RouteClass {
+    /// Class of an API path.
+    pub fn of(path: &str) -> Self {
+        match path {
+            "/api/auth/login" | "/api/auth/change-password" | "/api/setup" => Self::Auth,
+            "/api/config/export"
+            | "/api/config/import"
+            | "/api/metrics"
+            | "/api/debug/runtime"
+            | "/api/connections/anomalies" => Self::Expensive,
+            _ => Self::Standard,
+        }
+    }
+
+    /// Configured limit of this class.
+    pub fn limit(self, config: &ApiRateLimitConfig) -> RateLimit {
+        match self {
+            Self::Standard => config.standard,
+            Self::Expensive => config.expensive,
+            Self::Auth => config.auth,
+        }
+    }
+}
+
+/// Outcome of counting a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    /// Size of the bucket.
+    pub limit: u32,
+    /// Requests that may still be made right away.
+    pub remaining: u32,
+    /// Seconds until the next request is allowed (0 when allowed).
+    pub retry_after: u64,
+    /// Seconds until the bucket is full again.
+    pub reset: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When the bucket will have refilled completely.
+    full_at: Instant,
+}
+
+/// Token buckets per client IP and route class.
+#[derive(Clone, Default)]
+pub struct ApiRateLimiter {
+    buckets: Arc<Mutex<HashMap<(IpAddr, RouteClass), Bucket>>>,
+}
+
+impl ApiRateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a request from `ip` against `limit`.
+    pub fn check(&self, ip: IpAddr, class: RouteClass, limit: RateLimit) -> RateLimitDecision {
+        let now = Instant::now();
+        let rate = f64::from(limit.per_minute.max(1)) / 60.0;
+        let burst = f64::from(limit.burst.max(1));
+        let key = (ip.to_canonical(), class);
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+            // A full bucket is the same as no bucket
+            buckets.retain(|_, bucket| bucket.full_at > now);
+            if buckets.len() >= MAX_BUCKETS {
+                return RateLimitDecision {
+                    allowed: true,
+                    limit: limit.burst,
+                    remaining: limit.burst,
+                    retry_after: 0,
+                    reset: 0,
+                };
+            }
+        }
+
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+            full_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens + TOKEN_EPSILON >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let until_full = (burst - bucket.tokens).max(0.0) / rate;
+        bucket.full_at = now + Duration::from_secs_f64(until_full);
+
+        RateLimitDecision {
+            allowed,
+            limit: limit.burst,
+            remaining: (bucket.tokens + TOKEN_EPSILON) as u32,
+            retry_after: if allowed {
+                0
+            } else {
+                ((1.0 - bucket.tokens) / rate).ceil().max(1.0) as u64
+            },
+            reset: until_full.ceil() as u64,
+        }
+    }
+}
+
+/// Middleware enforcing `dashboard.rate_limit` on `/api` requests.
+///
+/// Responses carry `X-RateLimit-Limit`, `X-RateLimit-Remaining` and
+/// `X-RateLimit-Reset`; rejected requests get 429 with `Retry-After`.
+pub async fn rate_limit_middleware(
+    config_manager: ConfigManager,
+    limiter: ApiRateLimiter,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !request.uri().path().starts_with("/api/") {
+        return next.run(request).await;
+    }
+    let dashboard = config_manager.get_dashboard().await;
+    if !dashboard.rate_limit.enabled {
+        return next.run(request).await;
+    }
+    let Some(ip) = client_ip(&request) else {
+        return next.run(request).await;
+    };
+
+    let class = RouteClass::of(request.uri().path());
+    let decision = limiter.check(ip, class, class.limit(&dashboard.rate_limit));
+
+    let mut response = if decision.allowed {
+        next.run(request).await
+    } else {
+        let mut response = (
+            StatusCode::TOO_MANY_REQUESTS,
+            ErrorResponse::with_code(
+                ErrorCode::RateLimited,
+                format!("Too many requests, retry in {} s", decision.retry_after),
+            ),
+        )
+            .into_response();
+        response
+            .headers_mut()
+            .insert(axum::http::header::RETRY_AFTER, decision.retry_after.into());
+        response
+    };
+
+    let headers = response.headers_mut();
+    for (name, value) in [
+        ("x-ratelimit-limit", u64::from(decision.limit)),
+        ("x-ratelimit-remaining", u64::from(decision.remaining)),
+        ("x-ratelimit-reset", decision.reset),
+    ] {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+    }
+    response
+}
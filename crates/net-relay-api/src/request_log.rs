@@ -0,0 +1,160 @@
+//! Request logging and latency tracking for API calls.
+
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use net_relay_core::tls::ClientIdentity;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{debug, info};
+
+use crate::auth::DashboardUser;
+use crate::client_ip::client_ip;
+
+/// Upper bounds (in milliseconds) of the latency histogram buckets.
+const LATENCY_BUCKETS_MS: [u64; 9] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500];
+
+/// Latency statistics for a single endpoint.
+#[derive(Debug, Clone, Default)]
+struct EndpointStats {
+    count: u64,
+    server_errors: u64,
+    total_micros: u64,
+    max_micros: u64,
+    /// One counter per bucket in `LATENCY_BUCKETS_MS`, plus one for overflow.
+    buckets: [u64; LATENCY_BUCKETS_MS.len() + 1],
+}
+
+/// Histogram bucket in an API latency report.
+#[derive(Debug, Serialize)]
+pub struct LatencyBucket {
+    /// Upper bound in milliseconds (`None` for the overflow bucket).
+    pub le_ms: Option<u64>,
+    pub count: u64,
+}
+
+/// Latency report for a single endpoint.
+#[derive(Debug, Serialize)]
+pub struct EndpointLatency {
+    /// Method and route pattern, e.g. `GET /api/stats`.
+    pub endpoint: String,
+    pub count: u64,
+    pub server_errors: u64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+    pub buckets: Vec<LatencyBucket>,
+}
+
+/// Per-endpoint API latency histograms.
+#[derive(Clone, Default)]
+pub struct ApiMetrics {
+    endpoints: Arc<Mutex<HashMap<String, EndpointStats>>>,
+}
+
+impl ApiMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a completed request.
+    pub fn record(&self, endpoint: String, status: u16, micros: u64) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let stats = endpoints.entry(endpoint).or_default();
+        stats.count += 1;
+        if status >= 500 {
+            stats.server_errors += 1;
+        }
+        stats.total_micros += micros;
+        stats.max_micros = stats.max_micros.max(micros);
+
+        let millis = micros / 1000;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&le| millis <= le)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        stats.buckets[bucket] += 1;
+    }
+
+    /// Snapshot of all endpoint latencies, sorted by endpoint.
+    pub fn snapshot(&self) -> Vec<EndpointLatency> {
+        let endpoints = self.endpoints.lock().unwrap();
+        let mut report: Vec<EndpointLatency> = endpoints
+            .iter()
+            .map(|(endpoint, stats)| EndpointLatency {
+                endpoint: endpoint.clone(),
+                count: stats.count,
+                server_errors: stats.server_errors,
+                avg_ms: stats.total_micros as f64 / stats.count.max(1) as f64 / 1000.0,
+                max_ms: stats.max_micros as f64 / 1000.0,
+                buckets: stats
+                    .buckets
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &count)| LatencyBucket {
+                        le_ms: LATENCY_BUCKETS_MS.get(i).copied(),
+                        count,
+                    })
+                    .collect(),
+            })
+            .collect();
+        report.sort_by(|a, b| a.endpoint.cmp(&b.endpoint));
+        report
+    }
+}
+
+/// Middleware that logs one line per request and feeds the latency histograms.
+///
+/// API calls are logged at info level; static asset requests only at debug level.
+pub async fn request_log_middleware(metrics: ApiMetrics, request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string());
+    let client_ip = client_ip(&request).map_or_else(|| "-".to_string(), |ip| ip.to_string());
+    let client_cert = request
+        .extensions()
+        .get::<ClientIdentity>()
+        .map(|identity| identity.name().to_string())
+        .unwrap_or_else(|| "-".into());
+
+    let response = next.run(request).await;
+
+    let elapsed = start.elapsed();
+    let status = response.status().as_u16();
+    let user = response
+        .extensions()
+        .get::<DashboardUser>()
+        .map(|u| u.0.as_str())
+        .unwrap_or("-");
+    let latency_ms = elapsed.as_secs_f64() * 1000.0;
+
+    match route.filter(|r| r.starts_with("/api")) {
+        Some(route) => {
+            metrics.record(
+                format!("{} {}", method, route),
+                status,
+                elapsed.as_micros() as u64,
+            );
+            info!(
+                target: "net_relay_api::request",
+                %method, %path, status, latency_ms, user, client_ip = %client_ip,
+                client_cert = %client_cert,
+                "API request"
+            );
+        }
+        None => {
+            debug!(
+                target: "net_relay_api::request",
+                %method, %path, status, latency_ms, client_ip = %client_ip,
+                "Static request"
+            );
+        }
+    }
+
+    response
+}
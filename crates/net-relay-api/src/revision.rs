@@ -0,0 +1,102 @@
+//! Conflict detection for configuration edits.
+//!
+//! Every `/config` response carries the configuration revision as an `ETag`.
+//! Edits may send it back in `If-Match` (or `?expected_revision=`) and are
+//! rejected with 409 Conflict when the configuration changed in between.
+//! Edits without a revision overwrite as before. While the configuration is
+//! locked (`POST /api/config/lock`), edits are refused with 423 Locked.
+
+use axum::extract::{Query, Request};
+use axum::http::{header, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use net_relay_core::{ConfigLock, ConfigManager, ErrorCode};
+use serde::Deserialize;
+
+use crate::handlers::ErrorResponse;
+
+#[derive(Debug, Deserialize)]
+struct RevisionQuery {
+    expected_revision: Option<u64>,
+}
+
+/// Middleware for the `/config` and `/profiles` routes: serializes edits, refuses them while
+/// the configuration is locked, checks the revision they are based on and adds
+/// the current revision as `ETag`.
+pub async fn config_revision_middleware(
+    config_manager: ConfigManager,
+    request: Request,
+    next: Next,
+) -> Response {
+    let editing = !matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+
+    let mut response = if editing {
+        match expected_revision(&request) {
+            Ok(expected) => match config_manager.begin_edit(expected).await {
+                Ok(edit) => match config_manager.config_lock() {
+                    Some(lock) => locked_response(&lock),
+                    None => {
+                        let response = next.run(request).await;
+                        drop(edit);
+                        response
+                    }
+                },
+                Err(conflict) => (
+                    StatusCode::CONFLICT,
+                    ErrorResponse::with_code(ErrorCode::RevisionConflict, conflict.to_string()),
+                )
+                    .into_response(),
+            },
+            Err(message) => (StatusCode::BAD_REQUEST, ErrorResponse::new(message)).into_response(),
+        }
+    } else {
+        next.run(request).await
+    };
+
+    let etag = format!("\"{}\"", config_manager.revision());
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    response
+}
+
+fn locked_response(lock: &ConfigLock) -> Response {
+    let mut message = format!(
+        "Configuration is locked by {} since {}",
+        lock.locked_by,
+        lock.locked_at.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    if let Some(reason) = &lock.reason {
+        message.push_str(&format!(" ({})", reason));
+    }
+    (
+        StatusCode::LOCKED,
+        ErrorResponse::with_code(ErrorCode::ConfigLocked, message),
+    )
+        .into_response()
+}
+
+/// Revision from `If-Match` or `expected_revision` (`None` = don't check).
+fn expected_revision(request: &Request) -> Result<Option<u64>, String> {
+    if let Some(value) = request.headers().get(header::IF_MATCH) {
+        let value = value
+            .to_str()
+            .map_err(|_| "Invalid If-Match header".to_string())?
+            .trim();
+        if value == "*" {
+            return Ok(None);
+        }
+        let tag = value.strip_prefix("W/").unwrap_or(value).trim_matches('"');
+        return tag
+            .parse::<u64>()
+            .map(Some)
+            .map_err(|_| format!("Invalid If-Match revision: {}", value));
+    }
+
+    Query::<RevisionQuery>::try_from_uri(request.uri())
+        .map(|Query(query)| query.expected_revision)
+        .map_err(|_| "Invalid expected_revision".to_string())
+}
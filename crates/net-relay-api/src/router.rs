@@ -4,50 +4,104 @@ use axum::body::Body;
 use axum::http::{header, HeaderValue, Request, StatusCode};
 use axum::middleware;
 use axum::response::Response;
-use axum::routing::{delete, get, post, put};
+use axum::routing::{delete, get, patch, post, put};
 use axum::Router;
-use net_relay_core::{ConfigManager, Stats};
+use net_relay_core::tls::{CertStore, DashboardCerts};
+use net_relay_core::{ConfigManager, ServerState, Stats};
 use rust_embed::Embed;
+use std::collections::HashMap;
 use std::path::PathBuf;
-use std::sync::Arc;
-use tower_http::cors::{Any, CorsLayer};
+use std::sync::{Arc, OnceLock};
+use tower_http::compression::CompressionLayer;
 use tower_http::services::ServeDir;
 use tower_http::trace::TraceLayer;
 
-use crate::auth::{session_auth_middleware, SessionStore};
-use crate::handlers::{self, AppState};
+use crate::auth::{
+    config_actor_middleware, ip_filter_middleware, session_auth_middleware, SessionStore,
+};
+use crate::client_ip::client_ip_middleware;
+use crate::handlers::{self, ActiveServices, AppState};
+use crate::headers::{cors_layer, security_headers_middleware};
+use crate::rate_limit::{rate_limit_middleware, ApiRateLimiter};
+use crate::request_log::{request_log_middleware, ApiMetrics};
+use crate::revision::config_revision_middleware;
 
 /// Embedded frontend assets - compiled into the binary
 #[derive(Embed)]
 #[folder = "../../frontend/"]
 struct FrontendAssets;
 
+/// Cache lifetime for embedded assets other than index.html.
+const ASSET_MAX_AGE_SECS: u64 = 3600;
+
+/// ETags for every embedded asset, computed once on first use.
+static ASSET_ETAGS: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Look up the ETag for an embedded asset.
+fn asset_etag(path: &str) -> Option<&'static str> {
+    let etags = ASSET_ETAGS.get_or_init(|| {
+        FrontendAssets::iter()
+            .filter_map(|name| {
+                let file = FrontendAssets::get(&name)?;
+                let hash = file.metadata.sha256_hash();
+                let hex: String = hash[..16].iter().map(|b| format!("{:02x}", b)).collect();
+                Some((name.into_owned(), format!("\"{}\"", hex)))
+            })
+            .collect()
+    });
+    etags.get(path).map(String::as_str)
+}
+
+/// Build the response for an embedded asset, honoring `If-None-Match`.
+fn embedded_response(path: &str, data: Vec<u8>, if_none_match: Option<&str>) -> Response {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    let cache_control = if path == "index.html" {
+        // Always revalidate so new deploys are picked up immediately
+        "no-cache".to_string()
+    } else {
+        format!("public, max-age={}", ASSET_MAX_AGE_SECS)
+    };
+
+    let mut builder = Response::builder().header(header::CACHE_CONTROL, cache_control);
+
+    if let Some(etag) = asset_etag(path) {
+        builder = builder.header(header::ETAG, etag);
+        if if_none_match
+            .is_some_and(|inm| inm.split(',').any(|t| t.trim() == etag || t.trim() == "*"))
+        {
+            return builder
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+                .unwrap();
+        }
+    }
+
+    builder
+        .status(StatusCode::OK)
+        .header(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(mime.as_ref()).unwrap(),
+        )
+        .body(Body::from(data))
+        .unwrap()
+}
+
 /// Handler for serving embedded static files
 async fn serve_embedded(req: Request<Body>) -> Response {
     let path = req.uri().path().trim_start_matches('/');
     let path = if path.is_empty() { "index.html" } else { path };
+    let if_none_match = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok());
 
     match FrontendAssets::get(path) {
-        Some(content) => {
-            let mime = mime_guess::from_path(path).first_or_octet_stream();
-            Response::builder()
-                .status(StatusCode::OK)
-                .header(
-                    header::CONTENT_TYPE,
-                    HeaderValue::from_str(mime.as_ref()).unwrap(),
-                )
-                .body(Body::from(content.data.into_owned()))
-                .unwrap()
-        }
+        Some(content) => embedded_response(path, content.data.into_owned(), if_none_match),
         None => {
             // For SPA: return index.html for unknown paths (client-side routing)
             if !path.contains('.') {
                 if let Some(index) = FrontendAssets::get("index.html") {
-                    return Response::builder()
-                        .status(StatusCode::OK)
-                        .header(header::CONTENT_TYPE, HeaderValue::from_static("text/html"))
-                        .body(Body::from(index.data.into_owned()))
-                        .unwrap();
+                    return embedded_response("index.html", index.data.into_owned(), if_none_match);
                 }
             }
             Response::builder()
@@ -63,13 +117,23 @@ pub fn create_router(
     stats: Arc<Stats>,
     config_manager: ConfigManager,
     static_dir: Option<PathBuf>,
+    services: ActiveServices,
+    tls: Option<Arc<DashboardCerts>>,
+    certs: Arc<CertStore>,
+    server: Arc<ServerState>,
 ) -> Router {
     let session_store = SessionStore::new();
+    let api_metrics = ApiMetrics::new();
 
     let state = AppState {
-        stats,
+        stats: stats.clone(),
         config_manager: config_manager.clone(),
         session_store: session_store.clone(),
+        api_metrics: api_metrics.clone(),
+        services,
+        tls,
+        certs,
+        server,
     };
 
     // Auth routes (public, no auth required)
@@ -77,18 +141,79 @@ pub fn create_router(
         .route("/auth/check", get(handlers::auth_check))
         .route("/auth/login", post(handlers::login))
         .route("/auth/logout", post(handlers::logout))
+        .route("/auth/change-password", post(handlers::change_password))
+        .route("/auth/sessions", get(handlers::list_sessions))
+        .route(
+            "/auth/sessions/revoke-all",
+            post(handlers::revoke_all_sessions),
+        )
+        .route(
+            "/auth/sessions/{token_prefix}",
+            delete(handlers::revoke_session),
+        )
         .with_state(state.clone());
 
     // Protected API routes
     let api_routes = Router::new()
         // Health & Stats
         .route("/health", get(handlers::health))
+        .route("/version", get(handlers::get_version))
         .route("/stats", get(handlers::get_stats))
         .route("/connections", get(handlers::get_connections))
+        .route(
+            "/connections/anomalies",
+            get(handlers::get_connection_anomalies),
+        )
+        .route("/connections/{id}", patch(handlers::annotate_connection))
+        .route("/connections/{id}/ban", post(handlers::ban_connection))
+        .route(
+            "/connections/{id}/capture",
+            post(handlers::start_capture).get(handlers::download_capture),
+        )
         .route("/history", get(handlers::get_history))
         .route("/stats/users", get(handlers::get_user_stats))
-        // Configuration
+        .route("/stats/api", get(handlers::get_api_stats))
+        .route("/stats/denied", get(handlers::get_denied))
+        .route("/stats/dns", get(handlers::get_dns_stats))
+        .route("/stats/latency", get(handlers::get_latency_stats))
+        .route("/stats/rules", get(handlers::get_rule_hits))
+        .route("/alerts", get(handlers::get_alerts))
+        .route("/stats/targets/errors", get(handlers::get_target_errors))
+        .route("/upstreams", get(handlers::get_upstreams))
+        .route("/debug/runtime", get(handlers::get_runtime))
+        .route("/metrics", get(handlers::get_metrics))
+        .route("/tls/reload", post(handlers::reload_tls))
+        // GET only; the user's configuration is edited with the config routes
+        .route("/users/{username}", get(handlers::get_user_detail))
+        .route(
+            "/users/{username}/quota/reset",
+            post(handlers::reset_user_quota),
+        )
+        .route("/config/test", post(handlers::test_rules))
+        .route(
+            "/config/lock",
+            post(handlers::lock_config).delete(handlers::unlock_config),
+        )
+        // Retrying a failed save changes nothing, so it isn't refused while locked
+        .route("/config/flush", post(handlers::flush_config))
+        .with_state(state.clone());
+
+    // Configuration, with revision checks on edits
+    let revision_config_manager = config_manager.clone();
+    let revision_layer = middleware::from_fn(move |req, next| {
+        let cm = revision_config_manager.clone();
+        async move { config_revision_middleware(cm, req, next).await }
+    });
+    let config_routes = Router::new()
         .route("/config", get(handlers::get_config))
+        .route("/config/export", get(handlers::export_config))
+        .route("/config/provenance", get(handlers::get_config_provenance))
+        .route("/config/import", post(handlers::import_config))
+        .route("/config/backups", get(handlers::list_config_backups))
+        .route(
+            "/config/backups/{name}/restore",
+            post(handlers::restore_config_backup),
+        )
         .route("/config/access-control", get(handlers::get_access_control))
         .route(
             "/config/access-control",
@@ -108,21 +233,69 @@ pub fn create_router(
         // Access rules
         .route("/config/rules", post(handlers::add_rule))
         .route("/config/rules", delete(handlers::remove_rule))
+        .route("/config/rules/{id}", patch(handlers::update_rule))
+        .route("/config/rules/import", post(handlers::import_rules))
+        .route("/config/rules/export", get(handlers::export_rules))
         // Security & Users
         .route("/config/security", get(handlers::get_security))
         .route("/config/security", put(handlers::update_security))
+        .route("/config/dashboard", get(handlers::get_dashboard_auth))
+        .route("/config/dashboard", put(handlers::update_dashboard_auth))
+        .route(
+            "/config/dashboard/admins",
+            post(handlers::add_dashboard_admin),
+        )
+        .route(
+            "/config/dashboard/admins/{username}",
+            delete(handlers::remove_dashboard_admin),
+        )
+        .route(
+            "/users",
+            get(handlers::list_users).post(handlers::create_user),
+        )
+        .route(
+            "/users/{username}",
+            put(handlers::put_user).delete(handlers::delete_user),
+        )
+        .route("/users/{username}/disable", post(handlers::disable_user))
+        .route("/users/{username}/enable", post(handlers::enable_user))
+        // Deprecated aliases of the `/users` routes, with the username in the body
         .route("/config/users", post(handlers::add_user))
         .route("/config/users", put(handlers::update_user))
         .route("/config/users", delete(handlers::remove_user))
+        .route(
+            "/config/users/generate-password",
+            get(handlers::generate_password),
+        )
+        .route(
+            "/config/users/{username}/tokens",
+            get(handlers::list_user_tokens).post(handlers::create_user_token),
+        )
+        .route(
+            "/config/users/{username}/tokens/{id}",
+            delete(handlers::revoke_user_token),
+        )
         // Server configuration
         .route("/config/server", get(handlers::get_server_config))
         .route("/config/server", put(handlers::update_server_config))
-        .with_state(state);
+        .route("/server/listeners", get(handlers::get_listeners))
+        // Limits
+        .route("/config/limits", get(handlers::get_limits))
+        .route("/config/limits", put(handlers::update_limits))
+        // Statistics collection
+        .route("/config/stats", get(handlers::get_stats_config))
+        .route("/config/stats", put(handlers::update_stats_config))
+        // Profiles
+        .route("/profiles", get(handlers::list_profiles))
+        .route(
+            "/profiles/{name}/activate",
+            post(handlers::activate_profile),
+        )
+        .route("/setup", post(handlers::setup))
+        .with_state(state)
+        .route_layer(revision_layer);
 
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+    let cors = cors_layer(config_manager.clone());
 
     // Create session auth middleware layer
     let auth_config_manager = config_manager.clone();
@@ -133,8 +306,46 @@ pub fn create_router(
         async move { session_auth_middleware(cm, ss, req, next).await }
     });
 
+    // Dashboard IP allowlist is checked before anything else, including static files
+    let ip_config_manager = config_manager.clone();
+    let ip_layer = middleware::from_fn(move |req, next| {
+        let cm = ip_config_manager.clone();
+        let stats = stats.clone();
+        async move { ip_filter_middleware(cm, stats, req, next).await }
+    });
+
+    // Rate limits apply once the IP allowlist passed, before authentication
+    let rate_config_manager = config_manager.clone();
+    let rate_limiter = ApiRateLimiter::new();
+    let rate_layer = middleware::from_fn(move |req, next| {
+        let cm = rate_config_manager.clone();
+        let limiter = rate_limiter.clone();
+        async move { rate_limit_middleware(cm, limiter, req, next).await }
+    });
+
+    // Security headers go on every response, including static files and rejections
+    let headers_config_manager = config_manager.clone();
+    let headers_layer = middleware::from_fn(move |req, next| {
+        let cm = headers_config_manager.clone();
+        async move { security_headers_middleware(cm, req, next).await }
+    });
+
+    // Request logging wraps everything, including static files
+    let log_layer = middleware::from_fn(move |req, next| {
+        let metrics = api_metrics.clone();
+        async move { request_log_middleware(metrics, req, next).await }
+    });
+
+    // The client IP is derived before anything looks at it
+    let client_ip_config_manager = config_manager.clone();
+    let client_ip_layer = middleware::from_fn(move |req, next| {
+        let cm = client_ip_config_manager.clone();
+        async move { client_ip_middleware(cm, req, next).await }
+    });
+
     let mut app = Router::new()
-        .nest("/api", auth_routes.merge(api_routes))
+        .nest("/api", auth_routes.merge(api_routes).merge(config_routes))
+        .layer(middleware::from_fn(config_actor_middleware))
         .layer(auth_layer)
         .layer(cors)
         .layer(TraceLayer::new_for_http());
@@ -145,8 +356,15 @@ pub fn create_router(
         app = app.fallback_service(ServeDir::new(dir));
     } else {
         tracing::info!("Serving embedded static files (frontend built into binary)");
+        // Hash all assets up front so the first dashboard load doesn't pay for it
+        let _ = asset_etag("index.html");
         app = app.fallback(serve_embedded);
     }
 
-    app
+    app.layer(rate_layer)
+        .layer(ip_layer)
+        .layer(headers_layer)
+        .layer(log_layer)
+        .layer(client_ip_layer)
+        .layer(CompressionLayer::new())
 }
@@ -0,0 +1,273 @@
+//! HTTPS listener for the API/Dashboard.
+//!
+//! Connections on the API port are sniffed: TLS handshakes are completed in the
+//! background and handed to axum, while plain-HTTP requests only get ACME
+//! http-01 challenge responses.
+
+use axum::extract::{ConnectInfo, Request};
+use axum::serve::{IncomingStream, Listener};
+use axum::Router;
+use net_relay_core::tls::{ClientIdentity, DashboardCerts, ACME_TLS_ALPN};
+use std::convert::Infallible;
+use std::future::{ready, Ready};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::{self, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tower::Service;
+use tracing::{debug, info};
+
+/// First byte of a TLS handshake record.
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+
+/// Time allowed for the TLS handshake or the challenge request line.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Path prefix of http-01 challenge requests.
+const CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Largest plain-HTTP request head read while answering challenges.
+const MAX_REQUEST_HEAD: usize = 4096;
+
+/// Handshaken connections waiting for axum to accept them.
+const ACCEPT_BACKLOG: usize = 64;
+
+/// [`Listener`] yielding TLS streams served with [`DashboardCerts`].
+pub struct TlsListener {
+    incoming: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    /// Start accepting on `listener` with `config` (built from `certs`);
+    /// handshakes run concurrently so a slow client can't hold up others.
+    pub fn new(
+        listener: TcpListener,
+        config: Arc<ServerConfig>,
+        certs: Arc<DashboardCerts>,
+    ) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (tx, incoming) = mpsc::channel(ACCEPT_BACKLOG);
+        tokio::spawn(accept_loop(listener, TlsAcceptor::from(config), certs, tx));
+        Ok(Self {
+            incoming,
+            local_addr,
+        })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.incoming.recv().await {
+            Some(conn) => conn,
+            // The accept loop never exits while this receiver is alive
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    certs: Arc<DashboardCerts>,
+    tx: mpsc::Sender<(TlsStream<TcpStream>, SocketAddr)>,
+) {
+    while !tx.is_closed() {
+        let (stream, addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                debug!("API accept error: {}", e);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                continue;
+            }
+        };
+        let (acceptor, certs, tx) = (acceptor.clone(), Arc::clone(&certs), tx.clone());
+        tokio::spawn(async move {
+            if let Some(stream) = handshake(stream, addr, acceptor, &certs).await {
+                let _ = tx.send((stream, addr)).await;
+            }
+        });
+    }
+}
+
+/// Complete the TLS handshake, or answer a plain-HTTP challenge request.
+///
+/// Returns `None` when the connection isn't meant for axum.
+async fn handshake(
+    stream: TcpStream,
+    addr: SocketAddr,
+    acceptor: TlsAcceptor,
+    certs: &DashboardCerts,
+) -> Option<TlsStream<TcpStream>> {
+    let mut first = [0u8; 1];
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, stream.peek(&mut first)).await {
+        Ok(Ok(1)) => {}
+        _ => return None,
+    }
+    if first[0] != TLS_HANDSHAKE_RECORD {
+        answer_http_challenge(stream, certs).await;
+        return None;
+    }
+
+    let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => {
+            let rejected = matches!(
+                e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()),
+                Some(rustls::Error::NoCertificatesPresented | rustls::Error::InvalidCertificate(_))
+            );
+            if rejected {
+                info!("API client certificate from {} rejected: {}", addr, e);
+            } else {
+                debug!("API TLS handshake with {} failed: {}", addr, e);
+            }
+            return None;
+        }
+        Err(_) => return None,
+    };
+    // tls-alpn-01 validation only needs the handshake
+    if stream.get_ref().1.alpn_protocol() == Some(ACME_TLS_ALPN) {
+        debug!("Answered ACME tls-alpn-01 validation from {}", addr);
+        return None;
+    }
+    Some(stream)
+}
+
+/// Make-service for [`TlsListener`] connections.
+///
+/// Each connection's requests carry `ConnectInfo<SocketAddr>` and, when the
+/// client presented a verified certificate, its [`ClientIdentity`].
+#[derive(Clone)]
+pub struct TlsConnectInfo {
+    router: Router,
+}
+
+impl TlsConnectInfo {
+    pub fn new(router: Router) -> Self {
+        Self { router }
+    }
+}
+
+impl Service<IncomingStream<'_, TlsListener>> for TlsConnectInfo {
+    type Response = PeerService;
+    type Error = Infallible;
+    type Future = Ready<Result<PeerService, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, stream: IncomingStream<'_, TlsListener>) -> Self::Future {
+        let identity = stream
+            .io()
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|chain| chain.first())
+            .and_then(ClientIdentity::from_der);
+        ready(Ok(PeerService {
+            router: self.router.clone(),
+            addr: *stream.remote_addr(),
+            identity,
+        }))
+    }
+}
+
+/// [`Router`] for one TLS connection, tagging requests with the peer's details.
+#[derive(Clone)]
+pub struct PeerService {
+    router: Router,
+    addr: SocketAddr,
+    identity: Option<ClientIdentity>,
+}
+
+impl<B> Service<Request<B>> for PeerService
+where
+    Router: Service<Request<B>>,
+{
+    type Response = <Router as Service<Request<B>>>::Response;
+    type Error = <Router as Service<Request<B>>>::Error;
+    type Future = <Router as Service<Request<B>>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::<Request<B>>::poll_ready(&mut self.router, cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        request.extensions_mut().insert(ConnectInfo(self.addr));
+        if let Some(identity) = &self.identity {
+            request.extensions_mut().insert(identity.clone());
+        }
+        self.router.call(request)
+    }
+}
+
+/// Serve http-01 challenge responses on a dedicated plain-HTTP listener.
+pub async fn serve_http_challenges(listener: TcpListener, certs: Arc<DashboardCerts>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                debug!("ACME challenge accept error: {}", e);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                continue;
+            }
+        };
+        let certs = Arc::clone(&certs);
+        tokio::spawn(async move { answer_http_challenge(stream, &certs).await });
+    }
+}
+
+/// Answer one plain-HTTP request: the key authorization for a pending
+/// http-01 token, 404 for anything else.
+async fn answer_http_challenge(mut stream: TcpStream, certs: &DashboardCerts) {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    let read = tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+        while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_REQUEST_HEAD {
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => return false,
+                Ok(n) => head.extend_from_slice(&buf[..n]),
+            }
+        }
+        true
+    });
+    if !matches!(read.await, Ok(true)) {
+        return;
+    }
+
+    let request_line = String::from_utf8_lossy(&head);
+    let mut parts = request_line.split_whitespace();
+    let key_authorization = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => path
+            .strip_prefix(CHALLENGE_PREFIX)
+            .and_then(|token| certs.http_challenge(token)),
+        _ => None,
+    };
+
+    let response = match key_authorization {
+        Some(body) => format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        ),
+        None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            .to_string(),
+    };
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
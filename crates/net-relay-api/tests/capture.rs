@@ -0,0 +1,113 @@
+//! Capturing the bytes of a connection.
+
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use common::{login, request_as, request_text_as, serve_api};
+use net_relay_core::proxy::{Socks5Client, Socks5Proxy};
+use net_relay_core::{Config, ConfigManager, Stats};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const CONFIG: &str = r#"
+    [dashboard]
+    auth_enabled = true
+
+    [[dashboard.users]]
+    username = "admin"
+    password = "correct-horse-battery"
+"#;
+
+/// An API and a SOCKS5 proxy sharing configuration and statistics, and an
+/// echo server to connect to.
+async fn start(debug: &str) -> (SocketAddr, SocketAddr, SocketAddr, Arc<Stats>) {
+    let config: Config = toml::from_str(&format!("{}\n{}", CONFIG, debug)).unwrap();
+    let manager = ConfigManager::new(config, None);
+    let stats = Arc::new(Stats::new(10));
+    let api = serve_api(manager.clone(), Arc::clone(&stats)).await;
+
+    let proxy = Socks5Proxy::builder()
+        .config(manager)
+        .stats(Arc::clone(&stats))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+
+    let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let echo_addr = echo.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = echo.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    (api, proxy_addr, echo_addr, stats)
+}
+
+#[tokio::test]
+async fn a_capture_stops_at_its_byte_limit() {
+    let (api, proxy, echo, stats) = start(
+        r#"
+        [debug]
+        capture_enabled = true
+        capture_max_bytes = 1024
+        "#,
+    )
+    .await;
+    let admin = login(api, "admin", "correct-horse-battery").await;
+    let mut stream = Socks5Client::connect(proxy, "127.0.0.1", echo.port(), None)
+        .await
+        .unwrap();
+    let id = stats.get_active().await[0].id;
+    let path = format!("/connections/{}/capture", id);
+
+    let (status, _) = request_as(api, &admin, "POST", &format!("{}?bytes=4096", path), None).await;
+    assert_eq!(status, 400);
+    let start = format!("{}?bytes=10&direction=sent", path);
+    let (status, body) = request_as(api, &admin, "POST", &start, None).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"]["active"], true);
+    assert_eq!(body["data"]["started_by"], "admin");
+    let (status, _) = request_as(api, &admin, "POST", &start, None).await;
+    assert_eq!(status, 409);
+
+    stream.write_all(b"hello world, twice").await.unwrap();
+    let mut echoed = [0u8; 18];
+    stream.read_exact(&mut echoed).await.unwrap();
+
+    let raw = format!("{}?format=raw", path);
+    let (status, content_type, body) = request_text_as(api, &admin, "GET", &raw, "").await;
+    assert_eq!(status, 200);
+    assert_eq!(content_type, "application/octet-stream");
+    assert_eq!(body, "hello worl");
+    let (status, _, dump) = request_text_as(api, &admin, "GET", &path, "").await;
+    assert_eq!(status, 200);
+    assert!(dump.starts_with("> sent 10 bytes at "), "{}", dump);
+    assert!(dump.contains("68 65 6c 6c 6f 20 77 6f  72 6c"), "{}", dump);
+    assert!(dump.contains("|hello worl|"), "{}", dump);
+
+    // The limit detached the capture, so another one can start
+    let (status, _) = request_as(api, &admin, "POST", &start, None).await;
+    assert_eq!(status, 200);
+}
+
+#[tokio::test]
+async fn captures_are_off_unless_enabled() {
+    let (api, proxy, echo, stats) = start("").await;
+    let admin = login(api, "admin", "correct-horse-battery").await;
+    let _stream = Socks5Client::connect(proxy, "127.0.0.1", echo.port(), None)
+        .await
+        .unwrap();
+    let id = stats.get_active().await[0].id;
+    let path = format!("/connections/{}/capture", id);
+
+    let (status, _) = request_as(api, &admin, "POST", &path, None).await;
+    assert_eq!(status, 403);
+    let (status, _, _) = request_text_as(api, &admin, "GET", &path, "").await;
+    assert_eq!(status, 403);
+}
@@ -0,0 +1,175 @@
+//! Client IP derivation behind trusted reverse proxies.
+
+use std::net::IpAddr;
+
+use axum::http::{HeaderMap, HeaderValue};
+use net_relay_api::client_ip::resolve_client_ip;
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for &(name, value) in pairs {
+        headers.append(name, HeaderValue::from_str(value).unwrap());
+    }
+    headers
+}
+
+fn trusted() -> Vec<String> {
+    vec!["127.0.0.1".to_string(), "10.0.0.0/8".to_string()]
+}
+
+#[test]
+fn untrusted_peers_cannot_spoof() {
+    let spoofed = headers(&[
+        ("x-forwarded-for", "198.51.100.7"),
+        ("forwarded", "for=198.51.100.7"),
+        ("x-real-ip", "198.51.100.7"),
+    ]);
+    let peer = ip("203.0.113.9");
+
+    assert_eq!(resolve_client_ip(peer, &spoofed, &trusted(), None), peer);
+    assert_eq!(
+        resolve_client_ip(peer, &spoofed, &trusted(), Some("x-real-ip")),
+        peer
+    );
+    // Nothing is trusted by default
+    assert_eq!(
+        resolve_client_ip(ip("127.0.0.1"), &spoofed, &[], None),
+        ip("127.0.0.1")
+    );
+}
+
+#[test]
+fn rightmost_untrusted_hop_is_the_client() {
+    let peer = ip("127.0.0.1");
+
+    // The client made up the first entry; 10.1.2.3 is our own load balancer
+    let chain = headers(&[("x-forwarded-for", "1.1.1.1, 198.51.100.7, 10.1.2.3")]);
+    assert_eq!(
+        resolve_client_ip(peer, &chain, &trusted(), None),
+        ip("198.51.100.7")
+    );
+
+    // Repeated header lines form one list
+    let split = headers(&[
+        ("x-forwarded-for", "1.1.1.1, 198.51.100.7"),
+        ("x-forwarded-for", "10.1.2.3"),
+    ]);
+    assert_eq!(
+        resolve_client_ip(peer, &split, &trusted(), None),
+        ip("198.51.100.7")
+    );
+
+    // Only trusted hops: the leftmost one is the client
+    let internal = headers(&[("x-forwarded-for", "10.9.9.9, 10.1.2.3")]);
+    assert_eq!(
+        resolve_client_ip(peer, &internal, &trusted(), None),
+        ip("10.9.9.9")
+    );
+
+    // No header: the peer itself
+    assert_eq!(
+        resolve_client_ip(peer, &HeaderMap::new(), &trusted(), None),
+        peer
+    );
+}
+
+#[test]
+fn forwarded_header_takes_precedence() {
+    let peer = ip("10.0.0.1");
+    let both = headers(&[
+        (
+            "forwarded",
+            "for=192.0.2.60;proto=http, For=\"[2001:db8:cafe::17]:4711\";by=10.0.0.1",
+        ),
+        ("x-forwarded-for", "198.51.100.7"),
+    ]);
+    assert_eq!(
+        resolve_client_ip(peer, &both, &trusted(), None),
+        ip("2001:db8:cafe::17")
+    );
+
+    let with_port = headers(&[("forwarded", "for=\"192.0.2.60:8080\", for=10.0.0.2")]);
+    assert_eq!(
+        resolve_client_ip(peer, &with_port, &trusted(), None),
+        ip("192.0.2.60")
+    );
+}
+
+#[test]
+fn configured_header_is_used_alone() {
+    let peer = ip("127.0.0.1");
+    let request = headers(&[
+        ("x-real-ip", "192.0.2.44"),
+        ("x-forwarded-for", "198.51.100.7"),
+    ]);
+    assert_eq!(
+        resolve_client_ip(peer, &request, &trusted(), Some("X-Real-IP")),
+        ip("192.0.2.44")
+    );
+    // Missing configured header: the peer
+    assert_eq!(
+        resolve_client_ip(
+            peer,
+            &headers(&[("x-forwarded-for", "198.51.100.7")]),
+            &trusted(),
+            Some("x-real-ip")
+        ),
+        peer
+    );
+}
+
+#[test]
+fn malformed_hops_stop_at_the_last_trusted_proxy() {
+    let peer = ip("127.0.0.1");
+    for value in [
+        "198.51.100.7, not-an-ip, 10.1.2.3",
+        "198.51.100.7, , 10.1.2.3",
+        "198.51.100.7, 10.1.2.3:99999, 10.1.2.3",
+        "198.51.100.7, [2001:db8::1, 10.1.2.3",
+        "198.51.100.7, 2001:db8::1:80x, 10.1.2.3",
+    ] {
+        let chain = headers(&[("x-forwarded-for", value)]);
+        assert_eq!(
+            resolve_client_ip(peer, &chain, &trusted(), None),
+            ip("10.1.2.3"),
+            "{}",
+            value
+        );
+    }
+
+    for value in [
+        "for=unknown, for=10.1.2.3",
+        "for=_hidden, for=10.1.2.3",
+        "proto=https, for=10.1.2.3",
+        "for=\"[2001:db8::1\", for=10.1.2.3",
+    ] {
+        let chain = headers(&[("forwarded", value)]);
+        assert_eq!(
+            resolve_client_ip(peer, &chain, &trusted(), None),
+            ip("10.1.2.3"),
+            "{}",
+            value
+        );
+    }
+
+    // A header that is not valid text is ignored entirely
+    let mut binary = HeaderMap::new();
+    binary.insert(
+        "x-forwarded-for",
+        HeaderValue::from_bytes(b"198.51.100.7\xff").unwrap(),
+    );
+    assert_eq!(resolve_client_ip(peer, &binary, &trusted(), None), peer);
+}
+
+#[test]
+fn mapped_addresses_are_canonical() {
+    let chain = headers(&[("x-forwarded-for", "::ffff:198.51.100.7")]);
+    assert_eq!(
+        resolve_client_ip(ip("::ffff:127.0.0.1"), &chain, &trusted(), None),
+        ip("198.51.100.7")
+    );
+}
@@ -0,0 +1,177 @@
+//! An API server on a local port and a minimal client for it.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use net_relay_api::{create_router, ActiveServices};
+use net_relay_core::tls::CertStore;
+use net_relay_core::{Config, ConfigManager, ServerState, Stats};
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Serve the API for a default configuration kept in memory.
+#[allow(dead_code)]
+pub async fn start_api() -> (SocketAddr, ConfigManager) {
+    start_api_with(Config::default()).await
+}
+
+/// Serve the API for `config`, kept in memory.
+pub async fn start_api_with(config: Config) -> (SocketAddr, ConfigManager) {
+    let manager = ConfigManager::new(config, None);
+    let addr = serve_api(manager.clone(), Arc::new(Stats::new(10))).await;
+    (addr, manager)
+}
+
+/// Serve the API for `manager` and `stats`, e.g. shared with proxies.
+pub async fn serve_api(manager: ConfigManager, stats: Arc<Stats>) -> SocketAddr {
+    let router = create_router(
+        stats,
+        manager.clone(),
+        None,
+        ActiveServices {
+            socks5: false,
+            http: false,
+            api: true,
+            tunnel: false,
+            combined: false,
+        },
+        None,
+        Arc::new(CertStore::new()),
+        Arc::new(ServerState::new()),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let service = router.into_make_service_with_connect_info::<SocketAddr>();
+        axum::serve(listener, service).await
+    });
+    addr
+}
+
+/// Send a JSON request, returning the status code and JSON body.
+#[allow(dead_code)]
+pub async fn request(
+    api: SocketAddr,
+    method: &str,
+    path: &str,
+    body: Option<Value>,
+) -> (u16, Value) {
+    let (status, _, body) = send_json(api, method, path, None, body).await;
+    (status, body)
+}
+
+/// Send a JSON request with the dashboard session `cookie`.
+#[allow(dead_code)]
+pub async fn request_as(
+    api: SocketAddr,
+    cookie: &str,
+    method: &str,
+    path: &str,
+    body: Option<Value>,
+) -> (u16, Value) {
+    let (status, _, body) = send_json(api, method, path, Some(cookie), body).await;
+    (status, body)
+}
+
+/// Log in to the dashboard, returning the session cookie.
+#[allow(dead_code)]
+pub async fn login(api: SocketAddr, username: &str, password: &str) -> String {
+    let body = serde_json::json!({ "username": username, "password": password });
+    let (_, head, _) = send_json(api, "POST", "/auth/login", None, Some(body)).await;
+    head.lines()
+        .find_map(|line| line.strip_prefix("set-cookie: "))
+        .and_then(|cookie| cookie.split(';').next())
+        .expect("login failed")
+        .to_string()
+}
+
+async fn send_json(
+    api: SocketAddr,
+    method: &str,
+    path: &str,
+    cookie: Option<&str>,
+    body: Option<Value>,
+) -> (u16, String, Value) {
+    let body = body.map(|b| b.to_string()).unwrap_or_default();
+    let cookie = cookie
+        .map(|cookie| format!("Cookie: {}\r\n", cookie))
+        .unwrap_or_default();
+    let request = format!(
+        "{} /api{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}\
+         Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        cookie,
+        body.len(),
+        body
+    );
+    let mut stream = TcpStream::connect(api).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response[9..12].parse().unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (
+        status,
+        head.to_string(),
+        serde_json::from_str(body).unwrap_or(Value::Null),
+    )
+}
+
+/// Send a request with a raw body, returning the status code, the content
+/// type and the body.
+#[allow(dead_code)]
+pub async fn request_text(
+    api: SocketAddr,
+    method: &str,
+    path: &str,
+    body: &str,
+) -> (u16, String, String) {
+    send_text(api, method, path, None, body).await
+}
+
+/// Send a request with a raw body and the dashboard session `cookie`.
+#[allow(dead_code)]
+pub async fn request_text_as(
+    api: SocketAddr,
+    cookie: &str,
+    method: &str,
+    path: &str,
+    body: &str,
+) -> (u16, String, String) {
+    send_text(api, method, path, Some(cookie), body).await
+}
+
+async fn send_text(
+    api: SocketAddr,
+    method: &str,
+    path: &str,
+    cookie: Option<&str>,
+    body: &str,
+) -> (u16, String, String) {
+    let cookie = cookie
+        .map(|cookie| format!("Cookie: {}\r\n", cookie))
+        .unwrap_or_default();
+    let request = format!(
+        "{} /api{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}\
+         Content-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        cookie,
+        body.len(),
+        body
+    );
+    let mut stream = TcpStream::connect(api).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response[9..12].parse().unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let content_type = head
+        .lines()
+        .find_map(|line| line.strip_prefix("content-type: "))
+        .unwrap_or_default()
+        .to_string();
+    (status, content_type, body.to_string())
+}
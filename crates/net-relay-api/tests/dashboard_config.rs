@@ -0,0 +1,97 @@
+//! Reading and updating the `[dashboard]` settings.
+
+mod common;
+
+use common::{login, request_as, start_api_with};
+use net_relay_core::Config;
+use serde_json::json;
+
+fn config() -> Config {
+    toml::from_str(
+        r#"
+        [dashboard]
+        auth_enabled = true
+        metrics_token = "scrape-token-1234"
+        trusted_proxies = ["10.0.0.1"]
+
+        [[dashboard.users]]
+        username = "admin"
+        password = "correct-horse-battery"
+        "#,
+    )
+    .unwrap()
+}
+
+#[tokio::test]
+async fn settings_are_listed_without_secrets() {
+    let (api, _) = start_api_with(config()).await;
+    let admin = login(api, "admin", "correct-horse-battery").await;
+
+    let (status, body) = request_as(api, &admin, "GET", "/config/dashboard", None).await;
+    assert_eq!(status, 200);
+    let data = &body["data"];
+    assert_eq!(data["admins"], json!(["admin"]));
+    assert_eq!(data["session_ttl_secs"], 86400);
+    assert_eq!(data["trusted_proxies"], json!(["10.0.0.1"]));
+    assert_eq!(data["metrics_token_set"], true);
+    assert_eq!(data["rate_limit"]["enabled"], true);
+    let text = body.to_string();
+    assert!(!text.contains("scrape-token-1234"));
+    assert!(!text.contains("correct-horse-battery"));
+}
+
+#[tokio::test]
+async fn invalid_settings_are_refused_whole() {
+    let (api, config_manager) = start_api_with(config()).await;
+    let admin = login(api, "admin", "correct-horse-battery").await;
+
+    for body in [
+        json!({ "session_ttl_secs": 10, "allowed_origins": ["https://portal.example.com"] }),
+        json!({ "allowed_origins": ["portal.example.com"] }),
+        json!({ "frame_ancestors": ["https://portal.example.com/embed"] }),
+        json!({ "trusted_proxies": ["10.0.0.0/33"] }),
+        json!({ "real_ip_header": "X Real IP" }),
+    ] {
+        let (status, _) = request_as(api, &admin, "PUT", "/config/dashboard", Some(body)).await;
+        assert_eq!(status, 400);
+    }
+    let dashboard = config_manager.get_dashboard().await;
+    assert_eq!(dashboard.session_ttl_secs, 86400);
+    assert!(dashboard.allowed_origins.is_empty());
+}
+
+#[tokio::test]
+async fn updates_apply_without_a_restart() {
+    let (api, config_manager) = start_api_with(config()).await;
+    let admin = login(api, "admin", "correct-horse-battery").await;
+
+    let (status, body) = request_as(
+        api,
+        &admin,
+        "PUT",
+        "/config/dashboard",
+        Some(json!({
+            "session_ttl_secs": 3600,
+            "allowed_origins": ["https://portal.example.com"],
+            "metrics_token": "",
+        })),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"]["session_ttl_secs"], 3600);
+    assert_eq!(body["data"]["metrics_token_set"], false);
+    let dashboard = config_manager.get_dashboard().await;
+    assert_eq!(dashboard.metrics_token, None);
+    assert!(
+        config_manager
+            .is_dashboard_origin_allowed("https://portal.example.com")
+            .await
+    );
+
+    // Locking the API to another network takes effect on the next request
+    let body = json!({ "allowed_ips": ["10.0.0.0/8"] });
+    let (status, _) = request_as(api, &admin, "PUT", "/config/dashboard", Some(body)).await;
+    assert_eq!(status, 200);
+    let (status, _) = request_as(api, &admin, "GET", "/config/dashboard", None).await;
+    assert_eq!(status, 403);
+}
@@ -0,0 +1,100 @@
+//! API rate limiter tests, driven by a paused clock.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use net_relay_api::rate_limit::{ApiRateLimiter, RouteClass};
+use net_relay_core::{ApiRateLimitConfig, RateLimit};
+
+const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+const OTHER_CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 2));
+
+/// One request per second, three at once.
+const LIMIT: RateLimit = RateLimit {
+    per_minute: 60,
+    burst: 3,
+};
+
+#[tokio::test(start_paused = true)]
+async fn burst_then_refill() {
+    let limiter = ApiRateLimiter::new();
+
+    for remaining in [2, 1, 0] {
+        let decision = limiter.check(CLIENT, RouteClass::Standard, LIMIT);
+        assert!(decision.allowed);
+        assert_eq!(decision.limit, 3);
+        assert_eq!(decision.remaining, remaining);
+    }
+
+    let denied = limiter.check(CLIENT, RouteClass::Standard, LIMIT);
+    assert!(!denied.allowed);
+    assert_eq!(denied.retry_after, 1);
+    assert_eq!(denied.reset, 3);
+
+    tokio::time::advance(Duration::from_millis(500)).await;
+    assert!(!limiter.check(CLIENT, RouteClass::Standard, LIMIT).allowed);
+
+    tokio::time::advance(Duration::from_millis(500)).await;
+    let allowed = limiter.check(CLIENT, RouteClass::Standard, LIMIT);
+    assert!(allowed.allowed);
+    assert_eq!(allowed.remaining, 0);
+
+    // Idle long enough to refill completely, but never beyond the burst
+    tokio::time::advance(Duration::from_secs(60)).await;
+    assert_eq!(
+        limiter.check(CLIENT, RouteClass::Standard, LIMIT).remaining,
+        2
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_rates_refill_exactly() {
+    let limiter = ApiRateLimiter::new();
+    let limit = RateLimit {
+        per_minute: 10,
+        burst: 1,
+    };
+
+    assert!(limiter.check(CLIENT, RouteClass::Auth, limit).allowed);
+    let denied = limiter.check(CLIENT, RouteClass::Auth, limit);
+    assert!(!denied.allowed);
+    assert_eq!(denied.retry_after, 6);
+
+    tokio::time::advance(Duration::from_secs(6)).await;
+    assert!(limiter.check(CLIENT, RouteClass::Auth, limit).allowed);
+}
+
+#[tokio::test(start_paused = true)]
+async fn buckets_are_per_client_and_class() {
+    let limiter = ApiRateLimiter::new();
+    let limit = RateLimit {
+        per_minute: 1,
+        burst: 1,
+    };
+
+    assert!(limiter.check(CLIENT, RouteClass::Expensive, limit).allowed);
+    assert!(!limiter.check(CLIENT, RouteClass::Expensive, limit).allowed);
+
+    assert!(limiter.check(CLIENT, RouteClass::Standard, limit).allowed);
+    assert!(
+        limiter
+            .check(OTHER_CLIENT, RouteClass::Expensive, limit)
+            .allowed
+    );
+
+    // IPv4-mapped IPv6 addresses share the IPv4 bucket
+    let mapped: IpAddr = "::ffff:192.0.2.1".parse().unwrap();
+    assert!(!limiter.check(mapped, RouteClass::Expensive, limit).allowed);
+}
+
+#[test]
+fn route_classes() {
+    assert_eq!(RouteClass::of("/api/auth/login"), RouteClass::Auth);
+    assert_eq!(RouteClass::of("/api/config/export"), RouteClass::Expensive);
+    assert_eq!(RouteClass::of("/api/metrics"), RouteClass::Expensive);
+    assert_eq!(RouteClass::of("/api/stats"), RouteClass::Standard);
+    assert_eq!(RouteClass::of("/api/auth/check"), RouteClass::Standard);
+
+    let config = ApiRateLimitConfig::default();
+    assert_eq!(RouteClass::Auth.limit(&config), config.auth);
+}
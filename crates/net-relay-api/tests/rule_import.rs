@@ -0,0 +1,87 @@
+//! Importing and exporting access rules as plain text domain lists.
+
+mod common;
+
+use common::{request_text, start_api};
+use net_relay_core::{AccessRule, RuleAction};
+use serde_json::Value;
+
+#[tokio::test]
+async fn lists_are_imported_once() {
+    let (api, manager) = start_api().await;
+    let mut access_control = manager.get().await.access_control;
+    access_control.rules.push(AccessRule {
+        id: "existing".to_string(),
+        name: "manual".to_string(),
+        domain: "ads.example".to_string(),
+        domain_unicode: None,
+        path: None,
+        ports: None,
+        action: RuleAction::Deny,
+        mode: Default::default(),
+        enabled: true,
+        expires_at: None,
+    });
+    manager.update_access_control(access_control).await.unwrap();
+
+    let list = "# blocklist\nads.example\ntracker.example\nallow: cdn.example\nTracker.Example.\n";
+    let (status, _, body) = request_text(
+        api,
+        "POST",
+        "/config/rules/import?action=deny&name=feed-42",
+        list,
+    )
+    .await;
+    assert_eq!(status, 200, "{}", body);
+    let summary: Value = serde_json::from_str(&body).unwrap();
+    let data = &summary["data"];
+    assert_eq!(data["name"], "feed-42");
+    assert_eq!(data["added"].as_array().unwrap().len(), 2);
+    let duplicates: Vec<u64> = data["duplicates"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["line"].as_u64().unwrap())
+        .collect();
+    assert_eq!(duplicates, [2, 5]);
+    assert_eq!(data["rule_count"], 3);
+
+    let rules = manager.get().await.access_control.rules;
+    assert_eq!(rules[1].name, "feed-42");
+    assert_eq!(rules[1].domain, "tracker.example");
+    assert_eq!(rules[2].action, RuleAction::Allow);
+
+    let (status, content_type, text) =
+        request_text(api, "GET", "/config/rules/export?format=txt", "").await;
+    assert_eq!(status, 200);
+    assert!(content_type.starts_with("text/plain"));
+    assert_eq!(
+        text,
+        "# net-relay access rules\ndeny: ads.example\ndeny: tracker.example\nallow: cdn.example\n"
+    );
+
+    // Importing the export again adds nothing
+    let (_, _, body) = request_text(api, "POST", "/config/rules/import", &text).await;
+    let summary: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(summary["data"]["added"].as_array().unwrap().len(), 0);
+    assert_eq!(summary["data"]["duplicates"].as_array().unwrap().len(), 3);
+
+    let (status, _, _) = request_text(api, "GET", "/config/rules/export?format=csv", "").await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn invalid_lines_fail_the_whole_import() {
+    let (api, manager) = start_api().await;
+    let (status, _, body) = request_text(
+        api,
+        "POST",
+        "/config/rules/import",
+        "good.example\nblock: bad.example\n",
+    )
+    .await;
+    assert_eq!(status, 400);
+    let error: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(error["errors"][0]["field"], "line 2");
+    assert!(manager.get().await.access_control.rules.is_empty());
+}
@@ -0,0 +1,111 @@
+//! Listing and revoking dashboard sessions.
+
+mod common;
+
+use common::{login, request, request_as, start_api_with};
+use net_relay_core::Config;
+
+fn config() -> Config {
+    toml::from_str(
+        r#"
+        [dashboard]
+        auth_enabled = true
+
+        [[dashboard.users]]
+        username = "admin"
+        password = "correct-horse-battery"
+
+        [[dashboard.users]]
+        username = "ops"
+        password = "staple-horse-correct"
+        "#,
+    )
+    .unwrap()
+}
+
+#[tokio::test]
+async fn sessions_are_listed_with_their_last_use() {
+    let (api, _) = start_api_with(config()).await;
+    let admin = login(api, "admin", "correct-horse-battery").await;
+    let ops = login(api, "ops", "staple-horse-correct").await;
+    request_as(api, &ops, "GET", "/stats", None).await;
+
+    let (status, body) = request_as(api, &admin, "GET", "/auth/sessions", None).await;
+    assert_eq!(status, 200);
+    let sessions = body["data"].as_array().unwrap();
+    assert_eq!(sessions.len(), 2);
+    let own = sessions.iter().find(|s| s["username"] == "admin").unwrap();
+    assert_eq!(own["current"], true);
+    assert_eq!(own["client_ip"], "127.0.0.1");
+    assert_eq!(own["token_prefix"].as_str().unwrap().len(), 8);
+    let other = sessions.iter().find(|s| s["username"] == "ops").unwrap();
+    assert_eq!(other["current"], false);
+    assert!(other["last_seen_at"].as_str() >= other["created_at"].as_str());
+
+    // Without a session the listing is refused
+    let (status, _) = request(api, "GET", "/auth/sessions", None).await;
+    assert_eq!(status, 401);
+}
+
+#[tokio::test]
+async fn a_session_is_revoked_by_its_token_prefix() {
+    let (api, _) = start_api_with(config()).await;
+    let admin = login(api, "admin", "correct-horse-battery").await;
+    let ops = login(api, "ops", "staple-horse-correct").await;
+    let ops_token = ops.strip_prefix("net_relay_session=").unwrap();
+
+    let (status, _) = request_as(api, &admin, "DELETE", "/auth/sessions/abc", None).await;
+    assert_eq!(status, 400);
+    let (status, _) = request_as(api, &admin, "DELETE", "/auth/sessions/zzzzzzzz", None).await;
+    assert_eq!(status, 404);
+
+    let path = format!("/auth/sessions/{}", &ops_token[..8]);
+    let (status, body) = request_as(api, &admin, "DELETE", &path, None).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"]["revoked"], 1);
+    let (status, _) = request_as(api, &ops, "GET", "/stats", None).await;
+    assert_eq!(status, 401);
+    let (status, _) = request_as(api, &admin, "GET", "/stats", None).await;
+    assert_eq!(status, 200);
+}
+
+#[tokio::test]
+async fn revoke_all_keeps_the_callers_session() {
+    let (api, _) = start_api_with(config()).await;
+    let admin = login(api, "admin", "correct-horse-battery").await;
+    let admin_elsewhere = login(api, "admin", "correct-horse-battery").await;
+    let ops = login(api, "ops", "staple-horse-correct").await;
+    let ops_again = login(api, "ops", "staple-horse-correct").await;
+
+    let (status, body) = request_as(
+        api,
+        &admin,
+        "POST",
+        "/auth/sessions/revoke-all?user=ops",
+        None,
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"]["revoked"], 2);
+    assert_eq!(request_as(api, &ops, "GET", "/stats", None).await.0, 401);
+    assert_eq!(
+        request_as(api, &ops_again, "GET", "/stats", None).await.0,
+        401
+    );
+    assert_eq!(
+        request_as(api, &admin_elsewhere, "GET", "/stats", None)
+            .await
+            .0,
+        200
+    );
+
+    let (_, body) = request_as(api, &admin, "POST", "/auth/sessions/revoke-all", None).await;
+    assert_eq!(body["data"]["revoked"], 1);
+    assert_eq!(
+        request_as(api, &admin_elsewhere, "GET", "/stats", None)
+            .await
+            .0,
+        401
+    );
+    assert_eq!(request_as(api, &admin, "GET", "/stats", None).await.0, 200);
+}
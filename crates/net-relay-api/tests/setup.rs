@@ -0,0 +1,93 @@
+//! First-run setup of the dashboard administrator.
+
+mod common;
+
+use common::{login, request, request_as, start_api, start_api_with};
+use net_relay_core::Config;
+use serde_json::json;
+
+#[tokio::test]
+async fn setup_creates_the_first_admin_once() {
+    let (api, manager) = start_api().await;
+
+    let (_, body) = request(api, "GET", "/health", None).await;
+    assert_eq!(body["data"]["security_warning"], "auth_disabled");
+    let (_, body) = request(api, "GET", "/auth/check", None).await;
+    assert_eq!(body["data"]["setup_available"], true);
+    assert_eq!(body["data"]["security_warning"], "auth_disabled");
+
+    let (status, body) = request(
+        api,
+        "POST",
+        "/setup",
+        Some(json!({ "username": "root", "password": "short" })),
+    )
+    .await;
+    assert_eq!(status, 400);
+    assert_eq!(body["errors"][0]["field"], "password");
+    let (status, _) = request(
+        api,
+        "POST",
+        "/setup",
+        Some(json!({ "username": "admin", "password": "changeme" })),
+    )
+    .await;
+    assert_eq!(status, 400);
+
+    let (status, body) = request(
+        api,
+        "POST",
+        "/setup",
+        Some(json!({ "username": "root", "password": "long-enough-secret" })),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"]["auth_enabled"], true);
+    assert_eq!(body["data"]["admins"], json!(["root"]));
+    let dashboard = manager.get_dashboard().await;
+    assert!(dashboard.users[0].password.starts_with("pbkdf2-sha256$"));
+
+    // The API is now closed to anonymous callers, and the setup is gone
+    let (status, _) = request(api, "GET", "/health", None).await;
+    assert_eq!(status, 401);
+    let (status, _) = request(
+        api,
+        "POST",
+        "/setup",
+        Some(json!({ "username": "other", "password": "long-enough-secret" })),
+    )
+    .await;
+    assert_eq!(status, 410);
+
+    let cookie = login(api, "root", "long-enough-secret").await;
+    let (status, body) = request_as(api, &cookie, "GET", "/health", None).await;
+    assert_eq!(status, 200);
+    assert!(body["data"].get("security_warning").is_none());
+    let (_, body) = request_as(api, &cookie, "GET", "/auth/check", None).await;
+    assert_eq!(body["data"]["setup_available"], false);
+}
+
+#[tokio::test]
+async fn default_credentials_are_only_reported_to_admins() {
+    let config: Config = toml::from_str(
+        r#"
+        [dashboard]
+        auth_enabled = true
+        [[dashboard.users]]
+        username = "admin"
+        password = "admin"
+        "#,
+    )
+    .unwrap();
+    let (api, _) = start_api_with(config).await;
+
+    let (_, body) = request(api, "GET", "/auth/check", None).await;
+    assert_eq!(body["data"]["authenticated"], false);
+    assert!(body["data"].get("security_warning").is_none());
+
+    let cookie = login(api, "admin", "admin").await;
+    let (_, body) = request_as(api, &cookie, "GET", "/auth/check", None).await;
+    assert_eq!(body["data"]["security_warning"], "default_credentials");
+    let (_, body) = request_as(api, &cookie, "GET", "/health", None).await;
+    assert_eq!(body["data"]["security_warning"], "default_credentials");
+}
@@ -0,0 +1,86 @@
+//! The RESTful user routes and their deprecated body-based aliases.
+
+mod common;
+
+use common::{request, start_api};
+use serde_json::json;
+
+const PASSWORD: &str = "Velvet-Anchor-2931";
+
+#[tokio::test]
+async fn users_are_managed_by_path() {
+    let (api, manager) = start_api().await;
+
+    let alice = json!({ "username": "alice", "password": PASSWORD, "tags": ["ops"] });
+    let (status, body) = request(api, "POST", "/users", Some(alice.clone())).await;
+    assert_eq!(status, 201, "{}", body);
+    assert_eq!(body["data"]["username"], "alice");
+    assert!(body["data"].get("password").is_none());
+    let (status, _) = request(api, "POST", "/users", Some(alice)).await;
+    assert_eq!(status, 409);
+
+    let (status, body) = request(api, "GET", "/users", None).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"].as_array().unwrap().len(), 1);
+    let (status, body) = request(api, "GET", "/users/alice", None).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"]["user"]["tags"], json!(["ops"]));
+
+    let changes = json!({ "description": "on call", "note": null });
+    let (status, body) = request(api, "PUT", "/users/alice", Some(changes)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["data"]["description"], "on call");
+
+    let (status, body) = request(api, "POST", "/users/alice/disable", None).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"]["enabled"], false);
+    assert!(manager.authenticate("alice", PASSWORD).await.is_none());
+    let (_, body) = request(api, "POST", "/users/alice/enable", None).await;
+    assert_eq!(body["data"]["enabled"], true);
+    assert!(manager.authenticate("alice", PASSWORD).await.is_some());
+
+    let (status, _) = request(api, "DELETE", "/users/alice", None).await;
+    assert_eq!(status, 200);
+    assert!(manager.get_user("alice").await.is_none());
+}
+
+#[tokio::test]
+async fn unknown_users_are_not_found() {
+    let (api, _) = start_api().await;
+    for (method, path) in [
+        ("GET", "/users/bob"),
+        ("DELETE", "/users/bob"),
+        ("POST", "/users/bob/disable"),
+        ("POST", "/users/bob/enable"),
+    ] {
+        let (status, _) = request(api, method, path, None).await;
+        assert_eq!(status, 404, "{} {}", method, path);
+    }
+    let (status, _) = request(api, "PUT", "/users/bob", Some(json!({}))).await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn deprecated_aliases_still_work() {
+    let (api, manager) = start_api().await;
+
+    let bob = json!({ "username": "bob", "password": PASSWORD });
+    let (status, body) = request(api, "POST", "/config/users", Some(bob.clone())).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"]["user_count"], 1);
+    let (status, body) = request(api, "POST", "/config/users", Some(bob)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["success"], false);
+
+    let update = json!({ "username": "bob", "enabled": false, "note": "leaving" });
+    let (status, _) = request(api, "PUT", "/config/users", Some(update)).await;
+    assert_eq!(status, 200);
+    assert!(manager.get_user("bob").await.is_none());
+    let security = manager.get_security().await;
+    let user = security.users.iter().find(|u| u.username == "bob").unwrap();
+    assert_eq!(user.note.as_deref(), Some("leaving"));
+
+    let remove = json!({ "username": "bob" });
+    let (_, body) = request(api, "DELETE", "/config/users", Some(remove)).await;
+    assert_eq!(body["data"]["user_count"], 0);
+}
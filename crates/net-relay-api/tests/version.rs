@@ -0,0 +1,53 @@
+//! Build and runtime details at `GET /api/version`.
+
+mod common;
+
+use common::{login, request, request_as, start_api_with};
+use net_relay_core::Config;
+
+#[tokio::test]
+async fn version_details_need_a_dashboard_session() {
+    let config: Config = toml::from_str(
+        r#"
+        [dashboard]
+        auth_enabled = true
+
+        [[dashboard.users]]
+        username = "support"
+        password = "correct-horse-battery"
+
+        [metrics.statsd]
+        enabled = true
+        "#,
+    )
+    .unwrap();
+    let (api, _) = start_api_with(config).await;
+
+    let (status, _) = request(api, "GET", "/version", None).await;
+    assert_eq!(status, 401);
+
+    let session = login(api, "support", "correct-horse-battery").await;
+    let (status, body) = request_as(api, &session, "GET", "/version", None).await;
+    assert_eq!(status, 200);
+    let data = &body["data"];
+    assert_eq!(data["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(data["os"], std::env::consts::OS);
+    assert!(data["rustc"].as_str().unwrap().starts_with("rustc "));
+    assert!(data["build_date"].is_string());
+    assert!(data["features"].is_array());
+    assert!(data["uptime_secs"].as_i64().unwrap() >= 0);
+    assert_eq!(data["pid"], std::process::id());
+
+    let subsystem = |name: &str| {
+        data["subsystems"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|s| s["name"] == name)
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(subsystem("statsd")["enabled"], true);
+    assert_eq!(subsystem("tls")["enabled"], false);
+    assert_eq!(subsystem("prometheus")["compiled"], true);
+}
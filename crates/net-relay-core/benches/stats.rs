@@ -0,0 +1,134 @@
+//! Connection open/close throughput of `Stats` under concurrency.
+//!
+//! `baseline` reproduces the previous design (one `RwLock<Vec>` for active
+//! connections, one `RwLock<HashMap>` for per-user totals and a history
+//! `RwLock<VecDeque>` written on every close) so the two can be compared on
+//! the same machine. The gap grows with the number of cores:
+//!
+//! ```text
+//! cargo bench -p net-relay-core --bench stats
+//! ```
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use net_relay_core::connection::{CloseReason, ConnectionInfo, Protocol};
+use net_relay_core::{QuotaTracker, Stats};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use tokio::sync::RwLock;
+
+/// Concurrent connection handlers per iteration.
+const TASKS: usize = 64;
+
+/// Open/close cycles per handler per iteration.
+const CYCLES: usize = 50;
+
+/// Distinct users the connections are spread over.
+const USERS: usize = 16;
+
+fn connection(task: usize) -> ConnectionInfo {
+    ConnectionInfo::with_user(
+        Protocol::Socks5,
+        "127.0.0.1:40000".to_string(),
+        "example.com".to_string(),
+        443,
+        Some(format!("user{}", task % USERS)),
+    )
+}
+
+/// The pre-sharding `Stats` layout.
+#[derive(Default)]
+struct Baseline {
+    active: RwLock<Vec<ConnectionInfo>>,
+    users: RwLock<HashMap<String, (u64, u64, u64)>>,
+    history: RwLock<VecDeque<ConnectionInfo>>,
+    quota: QuotaTracker,
+}
+
+impl Baseline {
+    async fn add_connection(&self, info: ConnectionInfo) {
+        if let Some(ref username) = info.username {
+            let mut users = self.users.write().await;
+            let entry = users.entry(username.clone()).or_default();
+            entry.0 += 1;
+        }
+        self.active.write().await.push(info);
+    }
+
+    async fn close_connection(&self, id: uuid::Uuid, sent: u64, received: u64) {
+        let mut active = self.active.write().await;
+        if let Some(pos) = active.iter().position(|c| c.id == id) {
+            let info = active.remove(pos);
+            if let Some(ref username) = info.username {
+                let mut users = self.users.write().await;
+                if let Some(entry) = users.get_mut(username) {
+                    entry.1 += sent;
+                    entry.2 += received;
+                }
+                self.quota.add(username, sent + received);
+            }
+            let mut history = self.history.write().await;
+            if history.len() >= 1000 {
+                history.pop_front();
+            }
+            history.push_back(info);
+        }
+    }
+}
+
+fn churn(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("connection_churn");
+    group.throughput(Throughput::Elements((TASKS * CYCLES) as u64));
+
+    group.bench_function(BenchmarkId::new("stats", TASKS), |b| {
+        b.to_async(&runtime).iter(|| async {
+            let stats = Arc::new(Stats::new(1000));
+            let handles: Vec<_> = (0..TASKS)
+                .map(|task| {
+                    let stats = Arc::clone(&stats);
+                    tokio::spawn(async move {
+                        for _ in 0..CYCLES {
+                            let info = connection(task);
+                            let id = info.id;
+                            stats.add_connection(info).await;
+                            stats
+                                .close_connection(id, 1024, 4096, CloseReason::ClientEof)
+                                .await;
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        });
+    });
+
+    group.bench_function(BenchmarkId::new("baseline", TASKS), |b| {
+        b.to_async(&runtime).iter(|| async {
+            let stats = Arc::new(Baseline::default());
+            let handles: Vec<_> = (0..TASKS)
+                .map(|task| {
+                    let stats = Arc::clone(&stats);
+                    tokio::spawn(async move {
+                        for _ in 0..CYCLES {
+                            let info = connection(task);
+                            let id = info.id;
+                            stats.add_connection(info).await;
+                            stats.close_connection(id, 1024, 4096).await;
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, churn);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+//! Records where the build came from for [`BuildInfo`](src/build_info.rs):
+//! git commit, build time, compiler, target and profile.
+
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    if let Some(commit) = git(&["rev-parse", "--short=12", "HEAD"]) {
+        println!("cargo:rustc-env=NET_RELAY_GIT_COMMIT={}", commit);
+    }
+    // Reproducible builds pin the date through SOURCE_DATE_EPOCH
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or_default()
+        });
+    println!("cargo:rustc-env=NET_RELAY_BUILD_TIMESTAMP={}", timestamp);
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    if let Some(version) = output(Command::new(rustc).arg("--version")) {
+        println!("cargo:rustc-env=NET_RELAY_RUSTC={}", version);
+    }
+    for name in ["TARGET", "PROFILE"] {
+        if let Ok(value) = std::env::var(name) {
+            println!("cargo:rustc-env=NET_RELAY_{}={}", name, value);
+        }
+    }
+
+    // Rerun when HEAD moves; paths that don't exist would rerun every build
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        let git_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(git_dir);
+        let mut watched = vec![git_dir.join("HEAD"), git_dir.join("packed-refs")];
+        if let Some(head) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            watched.push(git_dir.join(head));
+        }
+        for path in watched.into_iter().filter(|path| path.exists()) {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    output(
+        Command::new("git")
+            .args(args)
+            .current_dir(env!("CARGO_MANIFEST_DIR")),
+    )
+}
+
+/// Trimmed standard output of a successful command.
+fn output(command: &mut Command) -> Option<String> {
+    let output = command.output().ok()?;
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let stdout = stdout.trim();
+    (output.status.success() && !stdout.is_empty()).then(|| stdout.to_string())
+}
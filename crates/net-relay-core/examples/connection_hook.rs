@@ -0,0 +1,126 @@
+//! Run custom logic as connections open and close.
+//!
+//! The hook refuses SMTP targets, caps each client IP at two concurrent
+//! connections and prints a line per closed connection, where a real
+//! deployment might publish to a message queue instead.
+//!
+//! ```text
+//! cargo run -p net-relay-core --example connection_hook
+//! ```
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use futures::future::BoxFuture;
+use net_relay_core::hooks::{ConnectionHook, HookDecision, HookFailurePolicy};
+use net_relay_core::proxy::{CancellationToken, Socks5Client, Socks5Proxy};
+use net_relay_core::ConnectionInfo;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Most concurrent connections per client IP.
+const MAX_PER_CLIENT: usize = 2;
+
+/// Policy and reporting hook.
+#[derive(Default)]
+struct PolicyHook {
+    /// Open connections by client IP.
+    open: Mutex<HashMap<String, usize>>,
+}
+
+fn client_ip(info: &ConnectionInfo) -> String {
+    info.client_addr
+        .parse::<SocketAddr>()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|_| info.client_addr.clone())
+}
+
+impl ConnectionHook for PolicyHook {
+    fn name(&self) -> &str {
+        "policy"
+    }
+
+    fn on_connect<'a>(
+        &'a self,
+        info: &'a ConnectionInfo,
+    ) -> BoxFuture<'a, anyhow::Result<HookDecision>> {
+        Box::pin(async move {
+            if info.target_port == 25 {
+                return Ok(HookDecision::Deny("SMTP is not relayed".to_string()));
+            }
+            let mut open = self.open.lock().unwrap();
+            let count = open.entry(client_ip(info)).or_default();
+            if *count >= MAX_PER_CLIENT {
+                return Ok(HookDecision::Deny(format!(
+                    "more than {} connections",
+                    MAX_PER_CLIENT
+                )));
+            }
+            *count += 1;
+            Ok(HookDecision::Allow)
+        })
+    }
+
+    fn on_close<'a>(&'a self, info: &'a ConnectionInfo) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            if let Some(count) = self.open.lock().unwrap().get_mut(&client_ip(info)) {
+                *count = count.saturating_sub(1);
+            }
+            println!(
+                "closed {} -> {}:{} ({} bytes sent, {} received, reason {})",
+                info.client_addr,
+                info.target_addr,
+                info.target_port,
+                info.bytes_sent,
+                info.bytes_received,
+                info.close_reason
+                    .map(|reason| reason.to_string())
+                    .unwrap_or_else(|| "not relayed".to_string()),
+            );
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let echo = TcpListener::bind("127.0.0.1:0").await?;
+    let echo_addr = echo.local_addr()?;
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = echo.accept().await {
+            tokio::spawn(async move {
+                let (mut read, mut write) = stream.split();
+                let _ = tokio::io::copy(&mut read, &mut write).await;
+            });
+        }
+    });
+
+    // A hook that fails (errors or panics) lets connections through
+    let shutdown = CancellationToken::new();
+    let proxy = Socks5Proxy::builder()
+        .hook(Arc::new(PolicyHook::default()), HookFailurePolicy::Allow)
+        .shutdown(shutdown.clone())
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let proxy_addr = listener.local_addr()?;
+    let proxy_task = tokio::spawn(async move { proxy.run(listener).await });
+
+    let host = echo_addr.ip().to_string();
+    let mut stream = Socks5Client::connect(proxy_addr, &host, echo_addr.port(), None).await?;
+    stream.write_all(b"hello").await?;
+    let mut echoed = [0u8; 5];
+    stream.read_exact(&mut echoed).await?;
+    println!("echoed {}", String::from_utf8_lossy(&echoed));
+    drop(stream);
+
+    match Socks5Client::connect(proxy_addr, "mail.example.com", 25, None).await {
+        Ok(_) => println!("SMTP connection was allowed"),
+        Err(e) => println!("SMTP connection refused: {}", e),
+    }
+
+    // Let the relay of the first connection record its close
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    shutdown.cancel();
+    proxy_task.await??;
+    Ok(())
+}
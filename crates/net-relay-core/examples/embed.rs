@@ -0,0 +1,141 @@
+//! Run the SOCKS5 and HTTP CONNECT proxies inside another program.
+//!
+//! Both proxies share one configuration and statistics collector, relay a
+//! message each to a local echo server and are then shut down.
+//!
+//! ```text
+//! cargo run -p net-relay-core --example embed
+//! ```
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use net_relay_core::proxy::{CancellationToken, HttpProxy, Socks5Proxy};
+use net_relay_core::{Config, ConfigManager, Stats};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let echo = start_echo_server().await?;
+
+    // Defaults: no authentication, every target allowed
+    let config_manager = ConfigManager::new(Config::default(), None);
+    let stats = Arc::new(Stats::new(100));
+    let shutdown = CancellationToken::new();
+
+    let socks = Socks5Proxy::builder()
+        .config(config_manager.clone())
+        .stats(Arc::clone(&stats))
+        .shutdown(shutdown.clone())
+        .build();
+    let http = HttpProxy::builder()
+        .config(config_manager)
+        .stats(Arc::clone(&stats))
+        .shutdown(shutdown.clone())
+        .build();
+
+    // Bind ephemeral ports up front so the addresses are known
+    let socks_listener = TcpListener::bind("127.0.0.1:0").await?;
+    let http_listener = TcpListener::bind("127.0.0.1:0").await?;
+    let socks_addr = socks_listener.local_addr()?;
+    let http_addr = http_listener.local_addr()?;
+    let socks_task = tokio::spawn(async move { socks.run(socks_listener).await });
+    let http_task = tokio::spawn(async move { http.run(http_listener).await });
+    println!(
+        "SOCKS5 proxy on {}, HTTP proxy on {}",
+        socks_addr, http_addr
+    );
+
+    println!("SOCKS5: {}", socks5_echo(socks_addr, echo, b"hello").await?);
+    println!("HTTP:   {}", http_echo(http_addr, echo, b"world").await?);
+
+    // Stop accepting, then end relays still open
+    shutdown.cancel();
+    socks_task.await??;
+    http_task.await??;
+    stats.begin_shutdown();
+    while stats.active_connections() > 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let totals = stats.get_aggregated().await;
+    println!(
+        "Stopped after {} connections ({} bytes sent, {} received)",
+        totals.total_connections, totals.total_bytes_sent, totals.total_bytes_received
+    );
+    Ok(())
+}
+
+/// Echo every byte back until the peer closes.
+async fn start_echo_server() -> std::io::Result<SocketAddr> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut read, mut write) = stream.split();
+                let _ = tokio::io::copy(&mut read, &mut write).await;
+            });
+        }
+    });
+    Ok(addr)
+}
+
+/// Send `message` through the SOCKS5 proxy and return the echo.
+async fn socks5_echo(
+    proxy: SocketAddr,
+    target: SocketAddr,
+    message: &[u8],
+) -> anyhow::Result<String> {
+    let SocketAddr::V4(target) = target else {
+        anyhow::bail!("IPv4 target expected");
+    };
+    let mut stream = TcpStream::connect(proxy).await?;
+
+    // Greeting without authentication
+    stream.write_all(&[0x05, 0x01, 0x00]).await?;
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method).await?;
+
+    let mut request = vec![0x05, 0x01, 0x00, 0x01];
+    request.extend_from_slice(&target.ip().octets());
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await?;
+    let mut reply = [0u8; 10];
+    stream.read_exact(&mut reply).await?;
+    anyhow::ensure!(reply[1] == 0x00, "SOCKS5 reply {:#04x}", reply[1]);
+
+    echo(stream, message).await
+}
+
+/// Send `message` through the HTTP CONNECT proxy and return the echo.
+async fn http_echo(
+    proxy: SocketAddr,
+    target: SocketAddr,
+    message: &[u8],
+) -> anyhow::Result<String> {
+    let mut stream = BufReader::new(TcpStream::connect(proxy).await?);
+    let request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", target);
+    stream.get_mut().write_all(request.as_bytes()).await?;
+
+    let mut status = String::new();
+    stream.read_line(&mut status).await?;
+    anyhow::ensure!(status.contains(" 200 "), "HTTP proxy answered {}", status);
+    loop {
+        let mut line = String::new();
+        stream.read_line(&mut line).await?;
+        if line.trim().is_empty() {
+            break;
+        }
+    }
+
+    echo(stream.into_inner(), message).await
+}
+
+async fn echo(mut stream: TcpStream, message: &[u8]) -> anyhow::Result<String> {
+    stream.write_all(message).await?;
+    let mut echoed = vec![0u8; message.len()];
+    stream.read_exact(&mut echoed).await?;
+    Ok(String::from_utf8_lossy(&echoed).into_owned())
+}
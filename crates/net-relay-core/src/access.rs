@@ -0,0 +1,431 @@
+//! Precompiled access-control matcher.
+//!
+//! [`AccessMatcher`] is built from an [`AccessControlConfig`] whenever access
+//! control changes and answers the same questions as
+//! [`AccessControlConfig::is_ip_allowed`] and
+//! [`AccessControlConfig::is_target_allowed`] without scanning the lists.
+//! [`DecisionCache`] keeps recent target decisions of the current matcher.
+
+use chrono::{DateTime, Utc};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::{AccessControlConfig, PortRanges, RuleAction, RuleMode};
+use crate::hostname;
+
+/// IP patterns grouped by prefix length, networks stored pre-masked.
+#[derive(Debug, Default)]
+struct IpSet {
+    v4: HashMap<u8, HashSet<u32>>,
+    v6: HashMap<u8, HashSet<u128>>,
+    /// Raw patterns, compared verbatim against inputs that aren't IP addresses.
+    literal: HashSet<String>,
+}
+
+impl IpSet {
+    fn new(patterns: &[String]) -> Self {
+        let mut set = Self::default();
+        for pattern in patterns {
+            set.literal.insert(pattern.clone());
+            match parse_network(pattern) {
+                Some((IpAddr::V4(network), prefix)) => {
+                    set.v4
+                        .entry(prefix)
+                        .or_default()
+                        .insert(u32::from(network) & v4_mask(prefix));
+                }
+                Some((IpAddr::V6(network), prefix)) => {
+                    set.v6
+                        .entry(prefix)
+                        .or_default()
+                        .insert(u128::from(network) & v6_mask(prefix));
+                }
+                None => {}
+            }
+        }
+        set
+    }
+
+    fn contains(&self, ip: &str) -> bool {
+        let Ok(addr) = ip.parse::<IpAddr>() else {
+            return self.literal.contains(ip);
+        };
+        match addr.to_canonical() {
+            IpAddr::V4(addr) => {
+                let addr = u32::from(addr);
+                self.v4
+                    .iter()
+                    .any(|(prefix, networks)| networks.contains(&(addr & v4_mask(*prefix))))
+            }
+            IpAddr::V6(addr) => {
+                let addr = u128::from(addr);
+                self.v6
+                    .iter()
+                    .any(|(prefix, networks)| networks.contains(&(addr & v6_mask(*prefix))))
+            }
+        }
+    }
+}
+
+/// Parse an exact or CIDR pattern the way [`ip_matches`](crate::config::ip_matches) does.
+///
+/// Returns `None` for patterns that can never match an address.
+fn parse_network(pattern: &str) -> Option<(IpAddr, u8)> {
+    let (network, prefix) = match pattern.split_once('/') {
+        Some((network, prefix)) => (network.trim(), Some(prefix.trim().parse::<u8>().ok()?)),
+        None => (pattern.trim(), None),
+    };
+    let network = network.parse::<IpAddr>().ok()?.to_canonical();
+    let width = if network.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(width);
+    (prefix <= width).then_some((network, prefix))
+}
+
+fn v4_mask(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)
+}
+
+fn v6_mask(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0)
+}
+
+/// A compiled domain/path rule.
+#[derive(Debug)]
+struct CompiledRule {
+    id: String,
+    name: String,
+    path: Option<String>,
+    ports: Option<PortRanges>,
+    allow: bool,
+    /// Matches are recorded but the action isn't applied.
+    monitor: bool,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// The rule that blocked a target; both fields are `None` when
+/// `allow_by_default = false` did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TargetBlock {
+    pub rule_id: Option<String>,
+    pub rule_name: Option<String>,
+}
+
+impl TargetBlock {
+    /// Short description, e.g. `rule "Ads" (id 1f2e)` or `default policy`.
+    pub fn describe(&self) -> String {
+        match (&self.rule_name, &self.rule_id) {
+            (Some(name), Some(id)) => format!("rule \"{}\" (id {})", name, id),
+            (None, Some(id)) => format!("rule {}", id),
+            _ => "default policy".to_string(),
+        }
+    }
+}
+
+/// Outcome of [`AccessMatcher::evaluate_target`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TargetCheck {
+    /// Why the target is blocked; `None` when it may proceed.
+    pub block: Option<TargetBlock>,
+    /// Denial that monitoring let through: a monitored `deny` rule, or the
+    /// default policy with `monitor_only`.
+    pub would_block: Option<TargetBlock>,
+    /// Enforced rule that decided the outcome.
+    pub rule_id: Option<String>,
+    /// First monitored rule that matched ahead of `rule_id`.
+    pub monitored_rule_id: Option<String>,
+}
+
+/// Compiled form of [`AccessControlConfig`] for the per-connection checks.
+#[derive(Debug, Default)]
+pub struct AccessMatcher {
+    blacklist: IpSet,
+    whitelist: IpSet,
+    whitelist_active: bool,
+    rules: Vec<CompiledRule>,
+    /// Hosts matched exactly (including the bare domain of `*.` patterns), to rule indices.
+    exact: HashMap<String, Vec<usize>>,
+    /// Dot-prefixed suffixes from `*.` patterns, to rule indices.
+    suffix: HashMap<String, Vec<usize>>,
+    allow_by_default: bool,
+    monitor_only: bool,
+    /// Expiry times of the enabled rules, ascending.
+    expiries: Vec<DateTime<Utc>>,
+}
+
+impl AccessMatcher {
+    /// Compile the IP lists and enabled rules of `config`.
+    pub fn new(config: &AccessControlConfig) -> Self {
+        let mut exact: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut suffix: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut rules = Vec::with_capacity(config.rules.len());
+
+        for rule in &config.rules {
+            let index = rules.len();
+            rules.push(CompiledRule {
+                id: rule.id.clone(),
+                name: rule.name.clone(),
+                path: rule.path.clone(),
+                ports: rule.ports.clone(),
+                allow: rule.action == RuleAction::Allow,
+                monitor: config.monitor_only || rule.mode == RuleMode::Monitor,
+                expires_at: rule.expires_at,
+            });
+            if !rule.enabled {
+                continue;
+            }
+            let pattern = hostname::normalize_pattern(&rule.domain)
+                .unwrap_or(Cow::Borrowed(rule.domain.as_str()));
+            if let Some(domain) = pattern.strip_prefix("*.") {
+                suffix
+                    .entry(pattern[1..].to_string())
+                    .or_default()
+                    .push(index);
+                exact.entry(domain.to_string()).or_default().push(index);
+            } else {
+                exact.entry(pattern.into_owned()).or_default().push(index);
+            }
+        }
+
+        let mut expiries: Vec<_> = config
+            .rules
+            .iter()
+            .filter(|rule| rule.enabled)
+            .filter_map(|rule| rule.expires_at)
+            .collect();
+        expiries.sort_unstable();
+
+        Self {
+            blacklist: IpSet::new(&config.ip_blacklist),
+            whitelist: IpSet::new(&config.ip_whitelist),
+            whitelist_active: !config.ip_whitelist.is_empty(),
+            rules,
+            exact,
+            suffix,
+            allow_by_default: config.allow_by_default,
+            monitor_only: config.monitor_only,
+            expiries,
+        }
+    }
+
+    /// When the next rule expires after `now`, changing what the matcher decides.
+    pub fn next_expiry(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let next = self
+            .expiries
+            .partition_point(|expires_at| *expires_at <= now);
+        self.expiries.get(next).copied()
+    }
+
+    /// Check if an IP is allowed.
+    pub fn is_ip_allowed(&self, ip: &str) -> bool {
+        if self.blacklist.contains(ip) {
+            return false;
+        }
+        !self.whitelist_active || self.whitelist.contains(ip)
+    }
+
+    /// Check if a target (domain + port + optional path) is allowed.
+    ///
+    /// A `None` port skips rules restricted to `ports`.
+    pub fn is_target_allowed(&self, host: &str, port: Option<u16>, path: Option<&str>) -> bool {
+        match self.matching_rule(host, port, path, false) {
+            Some(i) => self.rules[i].allow,
+            None => self.monitor_only || self.allow_by_default,
+        }
+    }
+
+    /// Like [`is_target_allowed`](Self::is_target_allowed), but a blocked
+    /// target comes with the rule that blocked it.
+    pub fn check_target(
+        &self,
+        host: &str,
+        port: Option<u16>,
+        path: Option<&str>,
+    ) -> Result<(), TargetBlock> {
+        match self.evaluate_target(host, port, path).block {
+            Some(block) => Err(block),
+            None => Ok(()),
+        }
+    }
+
+    /// Check a target and report the rules involved, including monitored
+    /// rules that would have blocked it.
+    pub fn evaluate_target(
+        &self,
+        host: &str,
+        port: Option<u16>,
+        path: Option<&str>,
+    ) -> TargetCheck {
+        let rule = self.matching_rule(host, port, path, false);
+        let monitored = self
+            .matching_rule(host, port, path, true)
+            .filter(|&m| rule.is_none_or(|r| m < r));
+
+        let block = match rule {
+            Some(i) => (!self.rules[i].allow).then(|| self.block(i)),
+            None => (!self.monitor_only && !self.allow_by_default).then(TargetBlock::default),
+        };
+        let would_block = match monitored {
+            _ if block.is_some() => None,
+            Some(m) => (!self.rules[m].allow).then(|| self.block(m)),
+            None => (rule.is_none() && !self.allow_by_default).then(TargetBlock::default),
+        };
+
+        TargetCheck {
+            block,
+            would_block,
+            rule_id: rule.map(|i| self.rules[i].id.clone()),
+            monitored_rule_id: monitored.map(|m| self.rules[m].id.clone()),
+        }
+    }
+
+    fn block(&self, index: usize) -> TargetBlock {
+        let rule = &self.rules[index];
+        TargetBlock {
+            rule_id: Some(rule.id.clone()),
+            rule_name: Some(rule.name.clone()).filter(|name| !name.is_empty()),
+        }
+    }
+
+    /// Index of the first live rule matching the target, among the
+    /// monitored or the enforced rules.
+    fn matching_rule(
+        &self,
+        host: &str,
+        port: Option<u16>,
+        path: Option<&str>,
+        monitored: bool,
+    ) -> Option<usize> {
+        let now = Utc::now();
+        let suffixes = host
+            .match_indices('.')
+            .filter_map(|(i, _)| self.suffix.get(&host[i..]));
+
+        // Each candidate list is in rule order, so the first path match per
+        // list is that list's earliest match; the earliest overall decides.
+        self.exact
+            .get(host)
+            .into_iter()
+            .chain(suffixes)
+            .filter_map(|indices| {
+                indices.iter().copied().find(|&i| {
+                    self.rules[i].monitor == monitored
+                        && self.is_live(i, now)
+                        && self.port_matches(i, port)
+                        && self.path_matches(i, path)
+                })
+            })
+            .min()
+    }
+
+    fn is_live(&self, index: usize, now: DateTime<Utc>) -> bool {
+        self.rules[index]
+            .expires_at
+            .is_none_or(|expires_at| expires_at > now)
+    }
+
+    fn port_matches(&self, index: usize, port: Option<u16>) -> bool {
+        match &self.rules[index].ports {
+            Some(ports) => port.is_some_and(|port| ports.contains(port)),
+            None => true,
+        }
+    }
+
+    fn path_matches(&self, index: usize, path: Option<&str>) -> bool {
+        match &self.rules[index].path {
+            Some(rule_path) => path.is_some_and(|p| p.starts_with(rule_path.as_str())),
+            None => true,
+        }
+    }
+}
+
+/// How long a cached target decision is reused.
+pub const DECISION_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Decisions cached at most; a full cache is emptied.
+const DECISION_CACHE_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct DecisionKey {
+    client: IpAddr,
+    host: String,
+    port: u16,
+    username: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct DecisionEntries {
+    /// Bumped by every invalidation.
+    generation: u64,
+    entries: HashMap<DecisionKey, (TargetCheck, Instant)>,
+}
+
+/// Recent [`AccessMatcher::evaluate_target`] results, keyed by client IP,
+/// normalized target host, port and username.
+///
+/// Entries live for [`DECISION_CACHE_TTL`] or until the next rule expires,
+/// whichever comes first. The owner of the matcher must call
+/// [`invalidate`](Self::invalidate) after publishing a new one.
+#[derive(Debug, Default)]
+pub struct DecisionCache {
+    inner: Mutex<DecisionEntries>,
+}
+
+impl DecisionCache {
+    /// Evaluate a target with `matcher`, reusing a cached decision.
+    ///
+    /// `matcher` is only loaded on a miss, after the cache was consulted, so
+    /// a decision computed from a matcher replaced in the meantime is
+    /// returned but not cached. Returns the decision and whether it came
+    /// from the cache.
+    pub fn evaluate(
+        &self,
+        client: IpAddr,
+        host: &str,
+        port: u16,
+        username: Option<&str>,
+        matcher: impl FnOnce() -> Arc<AccessMatcher>,
+    ) -> (TargetCheck, bool) {
+        let key = DecisionKey {
+            client: client.to_canonical(),
+            host: host.to_string(),
+            port,
+            username: username.map(str::to_string),
+        };
+        let generation = {
+            let inner = self.inner.lock().unwrap();
+            if let Some((check, expires)) = inner.entries.get(&key) {
+                if *expires > Instant::now() {
+                    return (check.clone(), true);
+                }
+            }
+            inner.generation
+        };
+
+        let matcher = matcher();
+        let now = (Instant::now(), Utc::now());
+        let check = matcher.evaluate_target(host, Some(port), None);
+        let ttl = match matcher.next_expiry(now.1) {
+            Some(next) => DECISION_CACHE_TTL.min((next - now.1).to_std().unwrap_or_default()),
+            None => DECISION_CACHE_TTL,
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation == generation && !ttl.is_zero() {
+            if inner.entries.len() >= DECISION_CACHE_CAPACITY {
+                inner.entries.clear();
+            }
+            inner.entries.insert(key, (check.clone(), now.0 + ttl));
+        }
+        (check, false)
+    }
+
+    /// Drop every cached decision, and any being computed from an older matcher.
+    pub fn invalidate(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner.entries.clear();
+    }
+}
@@ -0,0 +1,185 @@
+//! Per-connection access log.
+//!
+//! One line is written for every closed connection, separate from the
+//! application log. Two formats are supported:
+//!
+//! - `clf`: Common Log Format style, compatible with GoAccess/awstats custom formats:
+//!   `client_ip - user [time] "CONNECT host:port PROTOCOL" 200 bytes_received bytes_sent duration_ms close_reason`
+//! - `json`: one JSON object per line with the same fields, plus the
+//!   connection's `tags` when it has any.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+
+use crate::connection::ConnectionInfo;
+
+/// Access log line format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// Common Log Format style line.
+    #[default]
+    Clf,
+    /// One JSON object per line.
+    Json,
+}
+
+/// How often the access and event log files are rotated (`logging.rotation`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    /// Start a new file every hour.
+    Hourly,
+    /// Start a new file every day.
+    #[default]
+    Daily,
+    /// Keep writing to a single file.
+    Never,
+}
+
+/// Appender for the log file at `path`, creating its directory.
+///
+/// Rotated files get a date suffix (`access.log.2024-01-31`); with
+/// [`LogRotation::Never`] the file is written at `path` as is.
+pub(crate) fn open_appender(
+    path: &Path,
+    rotation: LogRotation,
+    default_name: &str,
+) -> std::io::Result<RollingFileAppender> {
+    let dir = path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let filename = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or(default_name);
+
+    std::fs::create_dir_all(dir)?;
+    let rotation = match rotation {
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(filename)
+        .build(dir)
+        .map_err(std::io::Error::other)
+}
+
+/// JSON access log record.
+#[derive(Debug, Serialize)]
+struct AccessLogRecord<'a> {
+    time: DateTime<Utc>,
+    client_ip: &'a str,
+    user: Option<&'a str>,
+    protocol: String,
+    target: String,
+    bytes_sent: u64,
+    bytes_received: u64,
+    duration_ms: i64,
+    close_reason: Option<String>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tags: &'a [String],
+}
+
+/// Non-blocking access log writer.
+#[derive(Clone)]
+pub struct AccessLog {
+    writer: NonBlocking,
+    format: AccessLogFormat,
+    /// Flushes pending lines when the last clone is dropped.
+    _guard: Arc<WorkerGuard>,
+}
+
+impl std::fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessLog")
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+impl AccessLog {
+    /// Open an access log at the given path.
+    pub fn open(
+        path: impl AsRef<Path>,
+        format: AccessLogFormat,
+        rotation: LogRotation,
+    ) -> std::io::Result<Self> {
+        let appender = open_appender(path.as_ref(), rotation, "access.log")?;
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+
+        Ok(Self {
+            writer,
+            format,
+            _guard: Arc::new(guard),
+        })
+    }
+
+    /// Write the line for a closed connection.
+    pub fn record(&self, info: &ConnectionInfo) {
+        let line = format_line(info, self.format);
+        let _ = self.writer.clone().write_all(line.as_bytes());
+    }
+}
+
+/// Format a single access log line (including the trailing newline).
+pub fn format_line(info: &ConnectionInfo, format: AccessLogFormat) -> String {
+    let closed_at = info.closed_at.unwrap_or_else(Utc::now);
+    let duration_ms = (closed_at - info.connected_at).num_milliseconds();
+    let client_ip = client_ip(&info.client_addr);
+    let target = format!("{}:{}", info.target_addr, info.target_port);
+
+    match format {
+        AccessLogFormat::Clf => format!(
+            "{} - {} [{}] \"CONNECT {} {}\" 200 {} {} {} {}\n",
+            client_ip,
+            info.username.as_deref().unwrap_or("-"),
+            closed_at.format("%d/%b/%Y:%H:%M:%S %z"),
+            target,
+            info.protocol,
+            info.bytes_received,
+            info.bytes_sent,
+            duration_ms,
+            info.close_reason
+                .map(|r| r.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+        ),
+        AccessLogFormat::Json => {
+            let record = AccessLogRecord {
+                time: closed_at,
+                client_ip,
+                user: info.username.as_deref(),
+                protocol: info.protocol.to_string(),
+                target,
+                bytes_sent: info.bytes_sent,
+                bytes_received: info.bytes_received,
+                duration_ms,
+                close_reason: info.close_reason.map(|r| r.to_string()),
+                tags: &info.tags,
+            };
+            let mut line = serde_json::to_string(&record).unwrap_or_default();
+            line.push('\n');
+            line
+        }
+    }
+}
+
+/// Strip the port from a client address.
+pub(crate) fn client_ip(client_addr: &str) -> &str {
+    match client_addr.parse::<SocketAddr>() {
+        Ok(_) => client_addr
+            .rsplit_once(':')
+            .map(|(ip, _)| ip.trim_start_matches('[').trim_end_matches(']'))
+            .unwrap_or(client_addr),
+        Err(_) => client_addr,
+    }
+}
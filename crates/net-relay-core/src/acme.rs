@@ -0,0 +1,560 @@
+//! ACME (RFC 8555) client that keeps the API/Dashboard certificate issued and renewed.
+//!
+//! State (account key, certificate and key) is kept in `acme.state_dir` so a
+//! restart reuses the existing certificate instead of requesting a new one.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use rcgen::{CertificateParams, CustomExtension, DistinguishedName, KeyPair};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair as _, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tracing::{debug, info, warn};
+
+use crate::config::{AcmeChallenge, AcmeConfig};
+use crate::error::{Error, Result};
+use crate::http_client::{HttpRequest, HttpResponse};
+use crate::tls::{self, DashboardCerts};
+
+const ACCOUNT_KEY_FILE: &str = "account.key";
+const CERT_FILE: &str = "cert.pem";
+const KEY_FILE: &str = "key.pem";
+
+/// Longest sleep between expiry checks.
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 3600);
+
+/// First retry delay after a failed issuance, doubled up to [`MAX_RETRY`].
+const MIN_RETRY: Duration = Duration::from_secs(60);
+const MAX_RETRY: Duration = Duration::from_secs(6 * 3600);
+
+/// Delay between authorization/order status polls.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Polls before a pending authorization or order is given up.
+const MAX_POLLS: usize = 60;
+
+/// Timeout for a single request to the CA.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Keeps the dashboard certificate in [`DashboardCerts`] valid.
+pub struct AcmeManager {
+    config: AcmeConfig,
+    certs: Arc<DashboardCerts>,
+}
+
+impl AcmeManager {
+    pub fn new(config: AcmeConfig, certs: Arc<DashboardCerts>) -> Self {
+        Self { config, certs }
+    }
+
+    /// Install the certificate left in the state directory by a previous run.
+    ///
+    /// Returns `false` when there is none yet; a self-signed placeholder is
+    /// served meanwhile so `/api/health` stays reachable.
+    pub fn load_existing(&self) -> Result<bool> {
+        let (cert_path, key_path) = (self.path(CERT_FILE), self.path(KEY_FILE));
+        if !cert_path.exists() || !key_path.exists() {
+            self.certs
+                .set_placeholder(self_signed(&self.config.domain, Vec::new())?);
+            return Ok(false);
+        }
+        let chain = tls::load_certs(&cert_path)?;
+        let not_after = tls::not_after(&chain[0])?;
+        let key = tls::certified_key(chain, tls::load_private_key(&key_path)?)?;
+        self.certs.set_certificate(key, Some(not_after));
+        Ok(true)
+    }
+
+    /// Issue the certificate when missing and renew it before it expires, forever.
+    pub async fn run(self) {
+        let mut retry = MIN_RETRY;
+        loop {
+            let now = Utc::now();
+            if let Some(due) = self.renewal_due().filter(|due| *due > now) {
+                self.certs.update_status(|s| s.next_renewal = Some(due));
+                let wait = (due - now).to_std().unwrap_or_default();
+                tokio::time::sleep(wait.min(CHECK_INTERVAL)).await;
+                continue;
+            }
+
+            match self.issue().await {
+                Ok(not_after) => {
+                    info!(
+                        "ACME certificate for {} issued, valid until {}",
+                        self.config.domain, not_after
+                    );
+                    retry = MIN_RETRY;
+                    self.certs.update_status(|s| {
+                        s.issued_at = Some(Utc::now());
+                        s.last_error = None;
+                        s.last_error_at = None;
+                    });
+                    if self.renewal_due().is_some_and(|due| due <= Utc::now()) {
+                        warn!(
+                            "acme.renew_before_days ({}) exceeds the certificate lifetime, next renewal in {}h",
+                            self.config.renew_before_days,
+                            CHECK_INTERVAL.as_secs() / 3600
+                        );
+                        let next = chrono::Duration::from_std(CHECK_INTERVAL)
+                            .ok()
+                            .map(|d| Utc::now() + d);
+                        self.certs.update_status(|s| s.next_renewal = next);
+                        tokio::time::sleep(CHECK_INTERVAL).await;
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        "ACME certificate issuance for {} failed (retrying in {}s): {}",
+                        self.config.domain,
+                        retry.as_secs(),
+                        e
+                    );
+                    let now = Utc::now();
+                    self.certs.update_status(|s| {
+                        s.last_error = Some(e.to_string());
+                        s.last_error_at = Some(now);
+                        s.next_renewal = chrono::Duration::from_std(retry).ok().map(|d| now + d);
+                    });
+                    tokio::time::sleep(retry).await;
+                    retry = (retry * 2).min(MAX_RETRY);
+                }
+            }
+        }
+    }
+
+    /// When the current certificate should be replaced (`None` if there is none).
+    fn renewal_due(&self) -> Option<DateTime<Utc>> {
+        let not_after = self.certs.status().not_after?;
+        Some(not_after - chrono::Duration::days(self.config.renew_before_days as i64))
+    }
+
+    fn path(&self, file: &str) -> PathBuf {
+        PathBuf::from(&self.config.state_dir).join(file)
+    }
+
+    /// Run one complete order and install the resulting certificate.
+    async fn issue(&self) -> Result<DateTime<Utc>> {
+        std::fs::create_dir_all(&self.config.state_dir)?;
+        let mut client = AcmeClient::connect(&self.config, self.account_key()?).await?;
+        client.register(&self.config.email).await?;
+
+        let (order_url, order) = client.new_order(&self.config.domain).await?;
+        for url in &order.authorizations {
+            self.authorize(&mut client, url).await?;
+        }
+
+        let key_pair = KeyPair::generate().map_err(acme_error)?;
+        let mut params =
+            CertificateParams::new(vec![self.config.domain.clone()]).map_err(acme_error)?;
+        params.distinguished_name = DistinguishedName::new();
+        let csr = params.serialize_request(&key_pair).map_err(acme_error)?;
+        client
+            .post(&order.finalize, Some(json!({ "csr": b64(csr.der()) })))
+            .await?;
+
+        let order = client.poll_order(&order_url).await?;
+        let cert_url = order
+            .certificate
+            .ok_or_else(|| Error::Acme("valid order has no certificate URL".into()))?;
+        let pem = client.post(&cert_url, None).await?.body;
+        let chain = CertificateDer::pem_slice_iter(&pem)
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(acme_error)?;
+        let Some(leaf) = chain.first() else {
+            return Err(Error::Acme("CA returned no certificates".into()));
+        };
+        let not_after = tls::not_after(leaf)?;
+        let key = tls::certified_key(
+            chain,
+            PrivatePkcs8KeyDer::from(key_pair.serialize_der()).into(),
+        )?;
+
+        write_private(&self.path(KEY_FILE), key_pair.serialize_pem().as_bytes())?;
+        std::fs::write(self.path(CERT_FILE), &pem)?;
+        self.certs.set_certificate(key, Some(not_after));
+        Ok(not_after)
+    }
+
+    /// Complete one authorization with the configured challenge type.
+    async fn authorize(&self, client: &mut AcmeClient, url: &str) -> Result<()> {
+        let authz: Authorization = client.post_json(url, None).await?;
+        if authz.status == "valid" {
+            return Ok(());
+        }
+        let domain = authz.identifier.value;
+        let kind = match self.config.challenge {
+            AcmeChallenge::Http01 => "http-01",
+            AcmeChallenge::TlsAlpn01 => "tls-alpn-01",
+        };
+        let challenge = authz
+            .challenges
+            .into_iter()
+            .find(|c| c.kind == kind)
+            .ok_or_else(|| {
+                Error::Acme(format!("CA offers no {} challenge for {}", kind, domain))
+            })?;
+
+        let key_authorization = format!("{}.{}", challenge.token, client.thumbprint);
+        match self.config.challenge {
+            AcmeChallenge::Http01 => self
+                .certs
+                .set_http_challenge(&challenge.token, Some(key_authorization)),
+            AcmeChallenge::TlsAlpn01 => self.certs.set_alpn_challenge(
+                &domain,
+                Some(alpn_certificate(&domain, &key_authorization)?),
+            ),
+        }
+        debug!("Answering ACME {} challenge for {}", kind, domain);
+
+        let result = client.validate(url, &challenge.url, &domain).await;
+        match self.config.challenge {
+            AcmeChallenge::Http01 => self.certs.set_http_challenge(&challenge.token, None),
+            AcmeChallenge::TlsAlpn01 => self.certs.set_alpn_challenge(&domain, None),
+        }
+        result
+    }
+
+    /// Load the account key, creating one on first use.
+    fn account_key(&self) -> Result<EcdsaKeyPair> {
+        let path = self.path(ACCOUNT_KEY_FILE);
+        let key_pair = if path.exists() {
+            KeyPair::from_pem(&std::fs::read_to_string(&path)?).map_err(acme_error)?
+        } else {
+            let key_pair = KeyPair::generate().map_err(acme_error)?;
+            write_private(&path, key_pair.serialize_pem().as_bytes())?;
+            info!("Created ACME account key {}", path.display());
+            key_pair
+        };
+        EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            &key_pair.serialize_der(),
+            &SystemRandom::new(),
+        )
+        .map_err(|e| Error::Acme(format!("unusable account key {}: {}", path.display(), e)))
+    }
+}
+
+/// Self-signed certificate carrying the tls-alpn-01 `acmeIdentifier` extension.
+fn alpn_certificate(domain: &str, key_authorization: &str) -> Result<Arc<CertifiedKey>> {
+    let digest = ring::digest::digest(&ring::digest::SHA256, key_authorization.as_bytes());
+    self_signed(
+        domain,
+        vec![CustomExtension::new_acme_identifier(digest.as_ref())],
+    )
+}
+
+fn self_signed(domain: &str, extensions: Vec<CustomExtension>) -> Result<Arc<CertifiedKey>> {
+    let mut params = CertificateParams::new(vec![domain.to_string()]).map_err(acme_error)?;
+    params.custom_extensions = extensions;
+    let key_pair = KeyPair::generate().map_err(acme_error)?;
+    let cert = params.self_signed(&key_pair).map_err(acme_error)?;
+    // Not `tls::certified_key`: its consistency check rejects the critical acmeIdentifier
+    let key = PrivatePkcs8KeyDer::from(key_pair.serialize_der()).into();
+    let signing_key =
+        tokio_rustls::rustls::crypto::ring::sign::any_supported_type(&key).map_err(acme_error)?;
+    Ok(Arc::new(CertifiedKey::new(
+        vec![cert.der().clone()],
+        signing_key,
+    )))
+}
+
+/// Write a file only the owner can read.
+fn write_private(path: &std::path::Path, contents: &[u8]) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    std::io::Write::write_all(&mut options.open(path)?, contents)?;
+    Ok(())
+}
+
+fn acme_error(e: impl fmt::Display) -> Error {
+    Error::Acme(e.to_string())
+}
+
+fn b64(data: impl AsRef<[u8]>) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    status: String,
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+    error: Option<Problem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    #[serde(default)]
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    #[serde(default)]
+    token: String,
+    error: Option<Problem>,
+}
+
+/// RFC 7807 problem document returned by the CA on errors.
+#[derive(Debug, Default, Deserialize)]
+struct Problem {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    detail: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = self.kind.trim_start_matches("urn:ietf:params:acme:error:");
+        match (kind.is_empty(), self.detail.is_empty()) {
+            (false, false) => write!(f, "{} ({})", self.detail, kind),
+            (true, false) => f.write_str(&self.detail),
+            _ => f.write_str(kind),
+        }
+    }
+}
+
+/// A session with the CA using one account key.
+struct AcmeClient {
+    directory: Directory,
+    ca_file: Option<String>,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    jwk: Value,
+    /// RFC 7638 JWK thumbprint, the suffix of every key authorization.
+    thumbprint: String,
+    /// Account URL, sent instead of the JWK once registered.
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+impl AcmeClient {
+    async fn connect(config: &AcmeConfig, key: EcdsaKeyPair) -> Result<Self> {
+        let ca_file = config.ca_file.clone();
+        let response = http_request("GET", &config.directory_url, None, ca_file.as_deref()).await?;
+        if response.status != 200 {
+            return Err(Error::Acme(format!(
+                "directory {} returned HTTP {}",
+                config.directory_url, response.status
+            )));
+        }
+        let directory: Directory = response.json()?;
+
+        // Uncompressed P-256 point: 0x04 || x || y
+        let point = key.public_key().as_ref();
+        let (x, y) = (b64(&point[1..33]), b64(&point[33..65]));
+        let thumbprint = b64(ring::digest::digest(
+            &ring::digest::SHA256,
+            format!(r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#, x, y).as_bytes(),
+        ));
+
+        Ok(Self {
+            directory,
+            ca_file,
+            key,
+            rng: SystemRandom::new(),
+            jwk: json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y }),
+            thumbprint,
+            kid: None,
+            nonce: None,
+        })
+    }
+
+    /// Create the account, or look up the existing one for this key.
+    async fn register(&mut self, email: &str) -> Result<()> {
+        let url = self.directory.new_account.clone();
+        let payload = json!({
+            "termsOfServiceAgreed": true,
+            "contact": [format!("mailto:{}", email)],
+        });
+        let response = self.post(&url, Some(payload)).await?;
+        let kid = response
+            .header("location")
+            .ok_or_else(|| Error::Acme("account response has no Location".into()))?;
+        self.kid = Some(kid.to_string());
+        Ok(())
+    }
+
+    async fn new_order(&mut self, domain: &str) -> Result<(String, Order)> {
+        let url = self.directory.new_order.clone();
+        let payload = json!({ "identifiers": [{ "type": "dns", "value": domain }] });
+        let response = self.post(&url, Some(payload)).await?;
+        let order_url = response
+            .header("location")
+            .ok_or_else(|| Error::Acme("order response has no Location".into()))?
+            .to_string();
+        Ok((order_url, response.json()?))
+    }
+
+    /// Tell the CA the challenge is ready and wait for the authorization to settle.
+    async fn validate(&mut self, authz_url: &str, challenge_url: &str, domain: &str) -> Result<()> {
+        self.post(challenge_url, Some(json!({}))).await?;
+        for _ in 0..MAX_POLLS {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let authz: Authorization = self.post_json(authz_url, None).await?;
+            match authz.status.as_str() {
+                "valid" => return Ok(()),
+                "pending" => continue,
+                status => {
+                    let detail = authz
+                        .challenges
+                        .iter()
+                        .find_map(|c| c.error.as_ref())
+                        .map(|problem| format!(": {}", problem))
+                        .unwrap_or_default();
+                    return Err(Error::Acme(format!(
+                        "authorization for {} is {}{}",
+                        domain, status, detail
+                    )));
+                }
+            }
+        }
+        Err(Error::Acme(format!(
+            "authorization for {} still pending",
+            domain
+        )))
+    }
+
+    /// Wait for a finalized order to become valid.
+    async fn poll_order(&mut self, url: &str) -> Result<Order> {
+        for _ in 0..MAX_POLLS {
+            let order: Order = self.post_json(url, None).await?;
+            match order.status.as_str() {
+                "valid" => return Ok(order),
+                "pending" | "ready" | "processing" => tokio::time::sleep(POLL_INTERVAL).await,
+                status => {
+                    let detail = order
+                        .error
+                        .map(|problem| format!(": {}", problem))
+                        .unwrap_or_default();
+                    return Err(Error::Acme(format!("order is {}{}", status, detail)));
+                }
+            }
+        }
+        Err(Error::Acme("order still processing".into()))
+    }
+
+    async fn post_json<T: DeserializeOwned>(
+        &mut self,
+        url: &str,
+        payload: Option<Value>,
+    ) -> Result<T> {
+        self.post(url, payload).await?.json()
+    }
+
+    /// Signed POST (POST-as-GET when `payload` is `None`), retrying once on a stale nonce.
+    async fn post(&mut self, url: &str, payload: Option<Value>) -> Result<HttpResponse> {
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => self.fetch_nonce().await?,
+            };
+            let body = self.sign(url, &nonce, payload.as_ref())?;
+            let response = http_request("POST", url, Some(&body), self.ca_file.as_deref()).await?;
+            self.nonce = response.header("replay-nonce").map(str::to_string);
+            if response.status < 400 {
+                return Ok(response);
+            }
+
+            let problem: Problem = serde_json::from_slice(&response.body).unwrap_or_default();
+            if problem.kind.ends_with(":badNonce") && !retried {
+                retried = true;
+                continue;
+            }
+            return Err(Error::Acme(format!(
+                "{} returned HTTP {}: {}",
+                url, response.status, problem
+            )));
+        }
+    }
+
+    async fn fetch_nonce(&self) -> Result<String> {
+        let response = http_request(
+            "HEAD",
+            &self.directory.new_nonce,
+            None,
+            self.ca_file.as_deref(),
+        )
+        .await?;
+        response
+            .header("replay-nonce")
+            .map(str::to_string)
+            .ok_or_else(|| Error::Acme("newNonce returned no Replay-Nonce".into()))
+    }
+
+    /// Flattened JWS (RFC 7515) over `payload`.
+    fn sign(&self, url: &str, nonce: &str, payload: Option<&Value>) -> Result<Vec<u8>> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk.clone(),
+        }
+        let protected = b64(protected.to_string());
+        let payload = payload.map(|p| b64(p.to_string())).unwrap_or_default();
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{}.{}", protected, payload).as_bytes())
+            .map_err(|_| Error::Acme("failed to sign request".into()))?;
+        let jws = json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": b64(signature),
+        });
+        Ok(jws.to_string().into_bytes())
+    }
+}
+
+/// One request to the CA, reporting failures as [`Error::Acme`].
+async fn http_request(
+    method: &str,
+    url: &str,
+    body: Option<&[u8]>,
+    ca_file: Option<&str>,
+) -> Result<HttpResponse> {
+    let request = HttpRequest {
+        method,
+        url,
+        headers: &[],
+        body: body.map(|body| ("application/jose+json", body)),
+        ca_file,
+        timeout: REQUEST_TIMEOUT,
+    };
+    request.send().await.map_err(|e| match e {
+        Error::Http(reason) => Error::Acme(format!("request to CA failed: {}", reason)),
+        e => e,
+    })
+}
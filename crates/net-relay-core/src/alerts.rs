@@ -0,0 +1,301 @@
+//! Threshold alerts on the statistics (`[alerts]`).
+//!
+//! [`run`] samples [`Stats`] every `alerts.interval_secs` and checks every
+//! rule in `alerts.rules` against the sample. `active_connections` and
+//! `throughput` are read as they are; the other metrics count what happened
+//! during the rule's `window_secs`. A rule fires once its metric is past
+//! `threshold` and resolves only once it is back past `resolve_threshold`,
+//! so a value hovering at the threshold doesn't flap.
+//!
+//! Every transition is logged, appended to the event log as an `alert` event
+//! and posted as JSON to the rule's webhook. The current state of every rule
+//! is kept in [`Stats::alerts`].
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+use crate::config::{AlertComparison, AlertMetric, AlertRule, AlertsConfig, WebhookConfig};
+use crate::http_client::HttpRequest;
+use crate::{ConfigManager, Stats};
+
+/// Longest a webhook may take to accept a notification.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Values of the metrics at one point in time; counters are totals since start.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AlertSample {
+    pub active_connections: u64,
+    pub throughput: u64,
+    pub connections: u64,
+    pub denied_attempts: u64,
+    pub auth_failures: u64,
+    pub connect_failures: u64,
+}
+
+impl AlertSample {
+    /// Read the current values from `stats`.
+    pub fn take(stats: &Stats) -> Self {
+        let attempts = stats.attempts();
+        Self {
+            active_connections: stats.active_connections(),
+            throughput: stats.current_throughput(),
+            connections: stats.total_connections(),
+            denied_attempts: attempts.denied,
+            auth_failures: attempts.auth_failed,
+            connect_failures: attempts.connect_failed,
+        }
+    }
+
+    fn get(&self, metric: AlertMetric) -> u64 {
+        match metric {
+            AlertMetric::ActiveConnections => self.active_connections,
+            AlertMetric::Throughput => self.throughput,
+            AlertMetric::Connections => self.connections,
+            AlertMetric::DeniedAttempts => self.denied_attempts,
+            AlertMetric::AuthFailures => self.auth_failures,
+            AlertMetric::ConnectFailures => self.connect_failures,
+        }
+    }
+}
+
+/// Whether a rule is firing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    /// The metric is within bounds (or was never past the threshold).
+    Resolved,
+    Firing,
+}
+
+/// State of an alert rule at its latest evaluation.
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertStatus {
+    pub name: String,
+    pub metric: AlertMetric,
+    pub comparison: AlertComparison,
+    pub threshold: u64,
+    /// Value the metric must get back past to resolve.
+    pub resolve_threshold: u64,
+    /// Seconds counter metrics are summed over.
+    pub window_secs: u64,
+    pub state: AlertState,
+    /// When the rule entered `state`, or was first evaluated.
+    pub since: DateTime<Utc>,
+    /// Value of the metric at the latest evaluation.
+    pub value: u64,
+    pub evaluated_at: DateTime<Utc>,
+    /// Webhook the rule's transitions are posted to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify: Option<String>,
+}
+
+/// A rule that started firing or resolved.
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertTransition {
+    /// The rule after the change; `since` is when it changed.
+    #[serde(flatten)]
+    pub status: AlertStatus,
+    /// When the rule entered its previous state.
+    pub previous_since: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+struct AlertEngineState {
+    /// Samples in time order, back to the start of the longest window.
+    samples: VecDeque<(DateTime<Utc>, AlertSample)>,
+    /// Latest status of every rule, in rule order.
+    statuses: Vec<AlertStatus>,
+}
+
+/// Samples and rule states behind the alerts.
+#[derive(Debug, Default)]
+pub struct AlertEngine {
+    state: Mutex<AlertEngineState>,
+}
+
+impl AlertEngine {
+    /// An engine whose counters start from zero at `started_at`.
+    pub fn new(started_at: DateTime<Utc>) -> Self {
+        let engine = Self::default();
+        engine
+            .state
+            .lock()
+            .unwrap()
+            .samples
+            .push_back((started_at, AlertSample::default()));
+        engine
+    }
+
+    /// Add `sample` taken at `now` and evaluate the rules of `config`,
+    /// returning the rules that changed state.
+    ///
+    /// Counter metrics compare against the newest sample at least a window
+    /// old, or the oldest sample while the history is shorter than that.
+    /// Rules are tracked by name; the state of removed rules is dropped.
+    pub fn evaluate(
+        &self,
+        config: &AlertsConfig,
+        sample: AlertSample,
+        now: DateTime<Utc>,
+    ) -> Vec<AlertTransition> {
+        let mut state = self.state.lock().unwrap();
+        state.samples.push_back((now, sample));
+        let longest = config
+            .rules
+            .iter()
+            .filter(|rule| rule.metric.is_counter())
+            .map(|rule| rule.window_secs)
+            .max()
+            .unwrap_or(0);
+        let cutoff = now - chrono::Duration::seconds(longest as i64);
+        while state.samples.len() > 1 && state.samples[1].0 <= cutoff {
+            state.samples.pop_front();
+        }
+
+        let mut previous: HashMap<String, AlertStatus> = state
+            .statuses
+            .drain(..)
+            .map(|status| (status.name.clone(), status))
+            .collect();
+        let mut transitions = Vec::new();
+        let mut statuses = Vec::with_capacity(config.rules.len());
+        for rule in &config.rules {
+            let value = metric_value(&state.samples, rule, now);
+            let resolve_threshold = rule.resolve_threshold.unwrap_or(rule.threshold);
+            let mut status = AlertStatus {
+                name: rule.name.clone(),
+                metric: rule.metric,
+                comparison: rule.comparison,
+                threshold: rule.threshold,
+                resolve_threshold,
+                window_secs: rule.window_secs,
+                state: AlertState::Resolved,
+                since: now,
+                value,
+                evaluated_at: now,
+                notify: rule.notify.clone(),
+            };
+            let previous = previous.remove(&rule.name);
+            let (was, since) = previous
+                .as_ref()
+                .map_or((AlertState::Resolved, now), |p| (p.state, p.since));
+            let (fires, clears) = match rule.comparison {
+                AlertComparison::Above => (value > rule.threshold, value <= resolve_threshold),
+                AlertComparison::Below => (value < rule.threshold, value >= resolve_threshold),
+            };
+            status.state = match was {
+                AlertState::Resolved if fires => AlertState::Firing,
+                AlertState::Firing if clears => AlertState::Resolved,
+                unchanged => unchanged,
+            };
+            if status.state == was {
+                status.since = since;
+            } else {
+                transitions.push(AlertTransition {
+                    status: status.clone(),
+                    previous_since: since,
+                });
+            }
+            statuses.push(status);
+        }
+        state.statuses = statuses;
+        transitions
+    }
+
+    /// Status of every rule at the latest evaluation.
+    pub fn statuses(&self) -> Vec<AlertStatus> {
+        self.state.lock().unwrap().statuses.clone()
+    }
+}
+
+/// Value of `rule`'s metric at the newest of `samples`.
+fn metric_value(
+    samples: &VecDeque<(DateTime<Utc>, AlertSample)>,
+    rule: &AlertRule,
+    now: DateTime<Utc>,
+) -> u64 {
+    let Some((_, latest)) = samples.back() else {
+        return 0;
+    };
+    let latest = latest.get(rule.metric);
+    if !rule.metric.is_counter() {
+        return latest;
+    }
+    let window_start = now - chrono::Duration::seconds(rule.window_secs as i64);
+    let baseline = samples
+        .iter()
+        .rev()
+        .find(|(at, _)| *at <= window_start)
+        .or(samples.front())
+        .map_or(0, |(_, sample)| sample.get(rule.metric));
+    latest.saturating_sub(baseline)
+}
+
+/// Evaluate `alerts.rules` every `alerts.interval_secs`, logging and posting
+/// the transitions.
+pub async fn run(config_manager: ConfigManager, stats: Arc<Stats>) {
+    loop {
+        let config = config_manager.get_alerts().await;
+        for transition in stats.evaluate_alerts(&config) {
+            let status = &transition.status;
+            match status.state {
+                AlertState::Firing => warn!(
+                    target: "net_relay_core::alerts",
+                    alert = %status.name,
+                    "Alert firing: {} is {} ({} {})",
+                    status.metric,
+                    status.value,
+                    status.comparison,
+                    status.threshold
+                ),
+                AlertState::Resolved => info!(
+                    target: "net_relay_core::alerts",
+                    alert = %status.name,
+                    "Alert resolved: {} is {}",
+                    status.metric,
+                    status.value
+                ),
+            }
+            let webhook = status
+                .notify
+                .as_ref()
+                .and_then(|name| config.webhooks.get(name))
+                .cloned();
+            if let Some(webhook) = webhook {
+                tokio::spawn(notify(webhook, transition));
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(config.interval_secs.max(1))).await;
+    }
+}
+
+/// Post `transition` to `webhook`, logging failures.
+pub async fn notify(webhook: WebhookConfig, transition: AlertTransition) {
+    let Ok(body) = serde_json::to_vec(&transition) else {
+        return;
+    };
+    let request = HttpRequest {
+        method: "POST",
+        url: &webhook.url,
+        headers: &[],
+        body: Some(("application/json", &body)),
+        ca_file: webhook.ca_file.as_deref(),
+        timeout: NOTIFY_TIMEOUT,
+    };
+    let name = &transition.status.name;
+    match request.send().await {
+        Ok(response) if (200..300).contains(&response.status) => {
+            debug!("Posted alert '{}' to {}", name, webhook.url)
+        }
+        Ok(response) => warn!(
+            "Webhook {} answered alert '{}' with status {}",
+            webhook.url, name, response.status
+        ),
+        Err(e) => warn!("Failed to post alert '{}' to {}: {}", name, webhook.url, e),
+    }
+}
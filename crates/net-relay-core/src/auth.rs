@@ -0,0 +1,258 @@
+//! Pluggable authentication of proxy clients.
+//!
+//! Credentials that don't match `security.users` are handed to the backend
+//! selected by `auth.backend`. The only built-in backend is
+//! [`HttpCallbackAuthenticator`].
+
+use futures::future::BoxFuture;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::config::{HttpCallbackConfig, UnavailablePolicy};
+use crate::connection::Protocol;
+use crate::error::{Error, Result};
+use crate::http_client::HttpRequest;
+
+/// Most accepted credentials remembered by the callback cache.
+const MAX_CACHE_ENTRIES: usize = 10_000;
+
+/// Characters of an attempted username kept in logs.
+const MAX_LOGGED_USERNAME_CHARS: usize = 64;
+
+/// Credentials presented by a proxy client.
+#[derive(Debug, Clone)]
+pub struct AuthRequest<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+    pub client_ip: IpAddr,
+    pub protocol: Protocol,
+}
+
+/// Limits applying to the connections of an authenticated session.
+///
+/// Unset fields fall back to the user's configured limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionLimits {
+    /// Bandwidth shared by the user's connections, in bytes per second (0 = unlimited).
+    #[serde(default, alias = "bandwidth", skip_serializing_if = "Option::is_none")]
+    pub bandwidth_limit: Option<u64>,
+
+    /// Concurrent connections of the user (0 = unlimited).
+    #[serde(
+        default,
+        alias = "connections",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub connection_limit: Option<u32>,
+
+    /// Seconds each connection may stay open (0 = unlimited), falling back
+    /// to `limits.max_connection_lifetime_secs` when neither sets it.
+    #[serde(default, alias = "lifetime", skip_serializing_if = "Option::is_none")]
+    pub max_connection_lifetime_secs: Option<u64>,
+}
+
+/// A successfully authenticated client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticatedUser {
+    pub username: String,
+    pub limits: SessionLimits,
+}
+
+impl AuthenticatedUser {
+    /// User without limit overrides.
+    pub fn new(username: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            limits: SessionLimits::default(),
+        }
+    }
+}
+
+/// An attempted username as it may be written to logs: invalid UTF-8 and
+/// control characters become `?` and it is cut to 64 characters.
+pub fn loggable_username(raw: &[u8]) -> String {
+    let lossy = String::from_utf8_lossy(raw);
+    let mut chars = lossy.chars().map(|c| {
+        if c.is_control() || c == char::REPLACEMENT_CHARACTER {
+            '?'
+        } else {
+            c
+        }
+    });
+    let mut name: String = chars.by_ref().take(MAX_LOGGED_USERNAME_CHARS).collect();
+    if chars.next().is_some() {
+        name.push('…');
+    }
+    name
+}
+
+/// Backend deciding whether credentials are valid.
+pub trait Authenticator: Send + Sync {
+    /// Check `request`, returning the user on success.
+    fn authenticate<'a>(
+        &'a self,
+        request: &'a AuthRequest<'a>,
+    ) -> BoxFuture<'a, Option<AuthenticatedUser>>;
+}
+
+/// Body POSTed to the callback endpoint.
+#[derive(Serialize)]
+struct CallbackRequest<'a> {
+    username: &'a str,
+    password: &'a str,
+    client_ip: IpAddr,
+    protocol: Protocol,
+}
+
+/// Response expected from the callback endpoint.
+#[derive(Deserialize)]
+struct CallbackResponse {
+    #[serde(default)]
+    allow: bool,
+    #[serde(default)]
+    limits: SessionLimits,
+}
+
+/// `http_callback` backend: asks an HTTP endpoint about each login.
+///
+/// Accepted credentials are cached for `cache_ttl_secs`; rejections are not,
+/// so a user the endpoint just enabled can log in right away.
+pub struct HttpCallbackAuthenticator {
+    config: HttpCallbackConfig,
+    /// Accepted credentials by hash of username and password.
+    cache: Mutex<HashMap<Vec<u8>, (AuthenticatedUser, Instant)>>,
+}
+
+impl HttpCallbackAuthenticator {
+    pub fn new(config: HttpCallbackConfig) -> Self {
+        Self {
+            config,
+            cache: Mutex::default(),
+        }
+    }
+
+    fn cache_key(request: &AuthRequest<'_>) -> Vec<u8> {
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        ctx.update(request.username.as_bytes());
+        ctx.update(&[0]);
+        ctx.update(request.password.as_bytes());
+        ctx.finish().as_ref().to_vec()
+    }
+
+    fn cached(&self, key: &[u8]) -> Option<AuthenticatedUser> {
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(key) {
+            Some((user, expires)) if *expires > Instant::now() => Some(user.clone()),
+            Some(_) => {
+                cache.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn remember(&self, key: Vec<u8>, user: &AuthenticatedUser) {
+        if self.config.cache_ttl_secs == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHE_ENTRIES {
+            cache.retain(|_, (_, expires)| *expires > now);
+            if cache.len() >= MAX_CACHE_ENTRIES {
+                return;
+            }
+        }
+        let ttl = Duration::from_secs(self.config.cache_ttl_secs);
+        cache.insert(key, (user.clone(), now + ttl));
+    }
+
+    /// Ask the endpoint. `Err` means it gave no usable answer.
+    async fn call(&self, request: &AuthRequest<'_>) -> Result<Option<AuthenticatedUser>> {
+        let body = serde_json::to_vec(&CallbackRequest {
+            username: request.username,
+            password: request.password,
+            client_ip: request.client_ip.to_canonical(),
+            protocol: request.protocol.clone(),
+        })
+        .map_err(|e| Error::Http(e.to_string()))?;
+        let secret_header = [(
+            self.config.secret_header.as_str(),
+            self.config.secret.as_str(),
+        )];
+        let headers: &[(&str, &str)] = if self.config.secret.is_empty() {
+            &[]
+        } else {
+            &secret_header
+        };
+
+        let response = HttpRequest {
+            method: "POST",
+            url: &self.config.url,
+            headers,
+            body: Some(("application/json", &body)),
+            ca_file: self.config.ca_file.as_deref(),
+            timeout: Duration::from_millis(self.config.timeout_ms),
+        }
+        .send()
+        .await?;
+
+        match response.status {
+            200 => {
+                let answer: CallbackResponse = response.json()?;
+                Ok(answer.allow.then(|| AuthenticatedUser {
+                    username: request.username.to_string(),
+                    limits: answer.limits,
+                }))
+            }
+            status if status >= 500 => Err(Error::Http(format!("endpoint returned {}", status))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Authenticator for HttpCallbackAuthenticator {
+    fn authenticate<'a>(
+        &'a self,
+        request: &'a AuthRequest<'a>,
+    ) -> BoxFuture<'a, Option<AuthenticatedUser>> {
+        Box::pin(async move {
+            let key = Self::cache_key(request);
+            if let Some(user) = self.cached(&key) {
+                return Some(user);
+            }
+
+            match self.call(request).await {
+                Ok(Some(user)) => {
+                    self.remember(key, &user);
+                    Some(user)
+                }
+                Ok(None) => {
+                    debug!("Auth callback rejected {}", request.username);
+                    None
+                }
+                Err(e) => match self.config.on_unavailable {
+                    UnavailablePolicy::Deny => {
+                        warn!(
+                            "Auth callback unavailable, rejecting {}: {}",
+                            request.username, e
+                        );
+                        None
+                    }
+                    UnavailablePolicy::Allow => {
+                        warn!(
+                            "Auth callback unavailable, accepting {}: {}",
+                            request.username, e
+                        );
+                        Some(AuthenticatedUser::new(request.username))
+                    }
+                },
+            }
+        })
+    }
+}
@@ -0,0 +1,189 @@
+//! Scheduled snapshots of the config file (`[backup]`).
+//!
+//! Snapshots are plain copies of the file named
+//! `config-<UTC timestamp>[-<label>].toml` in `backup.directory`. A scheduled
+//! snapshot is skipped when the file hasn't changed since the newest one, and
+//! the oldest snapshots beyond `backup.retention` are removed.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::{BackupConfig, ConfigManager};
+
+/// How often the schedule (and `backup.enabled`) is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+const PREFIX: &str = "config-";
+const EXTENSION: &str = ".toml";
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// A snapshot in the backup directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConfigBackup {
+    /// File name, used to restore it.
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    /// Size in bytes.
+    pub size: u64,
+    /// Why it was taken, e.g. `pre-restore` (`None` for scheduled snapshots).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Snapshots in `dir`, newest first (none if the directory doesn't exist).
+pub fn list(dir: &Path) -> anyhow::Result<Vec<ConfigBackup>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut backups = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let Some((created_at, label)) = parse_name(&name) else {
+            continue;
+        };
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            backups.push(ConfigBackup {
+                name,
+                created_at,
+                size: metadata.len(),
+                label,
+            });
+        }
+    }
+    backups.sort_by(|a, b| (b.created_at, &b.name).cmp(&(a.created_at, &a.name)));
+    Ok(backups)
+}
+
+/// Contents of the snapshot `name` in `dir`, `None` if there is no such snapshot.
+pub fn read(dir: &Path, name: &str) -> anyhow::Result<Option<String>> {
+    if parse_name(name).is_none() {
+        return Ok(None);
+    }
+    match std::fs::read_to_string(dir.join(name)) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Copy `config_file` into the backup directory and remove snapshots beyond
+/// the retention.
+///
+/// Without a `label` nothing is written if the file is identical to the
+/// newest snapshot; labelled snapshots are always written.
+pub fn snapshot(
+    config_file: &Path,
+    config: &BackupConfig,
+    label: Option<&str>,
+) -> anyhow::Result<Option<ConfigBackup>> {
+    let content = std::fs::read(config_file)?;
+    let dir = Path::new(&config.directory);
+    let mut created_at = Utc::now();
+    if let Some(newest) = list(dir)?.first() {
+        if label.is_none() && std::fs::read(dir.join(&newest.name))? == content {
+            return Ok(None);
+        }
+        // Keep names ordered even when snapshots land in the same millisecond
+        created_at = created_at.max(newest.created_at + chrono::Duration::milliseconds(1));
+    }
+
+    std::fs::create_dir_all(dir)?;
+    let mut name = format!("{}{}", PREFIX, created_at.format(TIMESTAMP_FORMAT));
+    if let Some(label) = label {
+        name = format!("{}-{}", name, label);
+    }
+    name.push_str(EXTENSION);
+    let (created_at, label) =
+        parse_name(&name).ok_or_else(|| anyhow::anyhow!("Invalid backup label: {:?}", label))?;
+    std::fs::write(dir.join(&name), &content)?;
+
+    if config.retention > 0 {
+        for old in list(dir)?.into_iter().skip(config.retention) {
+            std::fs::remove_file(dir.join(&old.name))?;
+        }
+    }
+    Ok(Some(ConfigBackup {
+        name,
+        created_at,
+        size: content.len() as u64,
+        label,
+    }))
+}
+
+/// Snapshot the config file every `backup.interval_secs` while `backup.enabled`.
+pub async fn run(config_manager: ConfigManager) {
+    let Some(config_file) = config_manager.config_path().map(PathBuf::from) else {
+        return;
+    };
+    let mut last_check: Option<DateTime<Utc>> = None;
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let config = config_manager.get().await.backup;
+        if !config.enabled {
+            continue;
+        }
+
+        // After a restart, pick up the schedule where the newest snapshot left it
+        let last = last_check.or_else(|| {
+            list(Path::new(&config.directory))
+                .ok()
+                .and_then(|backups| backups.first().map(|b| b.created_at))
+        });
+        let period = chrono::Duration::from_std(Duration::from_secs(config.interval_secs))
+            .unwrap_or(chrono::Duration::MAX);
+        let now = Utc::now();
+        if let Some(last) = last {
+            if last.checked_add_signed(period).is_none_or(|due| now < due) {
+                continue;
+            }
+        }
+        last_check = Some(now);
+
+        match snapshot(&config_file, &config, None) {
+            Ok(Some(backup)) => info!("Saved config snapshot {}", backup.name),
+            Ok(None) => {}
+            Err(e) => warn!(
+                "Failed to snapshot {} into {}: {}",
+                config_file.display(),
+                config.directory,
+                e
+            ),
+        }
+    }
+}
+
+/// Timestamp and label of a snapshot file name.
+fn parse_name(name: &str) -> Option<(DateTime<Utc>, Option<String>)> {
+    let stem = name.strip_prefix(PREFIX)?.strip_suffix(EXTENSION)?;
+    let timestamp_len = "YYYYmmddTHHMMSS.sssZ".len();
+    let timestamp = stem.get(..timestamp_len)?;
+    let created_at = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+        .ok()?
+        .and_utc();
+    let label = match &stem[timestamp_len..] {
+        "" => None,
+        rest => {
+            let label = rest.strip_prefix('-')?;
+            let valid = !label.is_empty()
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+            if !valid {
+                return None;
+            }
+            Some(label.to_string())
+        }
+    };
+    Some((created_at, label))
+}
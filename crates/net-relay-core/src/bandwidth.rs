@@ -0,0 +1,124 @@
+//! Server-wide bandwidth limiting.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Largest chunk a single draw may take, so one busy connection can't
+/// drain the bucket ahead of the others.
+const MAX_DRAW: u64 = 16 * 1024;
+
+/// Token bucket state plus a one-second throughput window.
+#[derive(Debug)]
+struct LimiterState {
+    tokens: f64,
+    refilled_at: Instant,
+    started: Instant,
+    window_second: u64,
+    window_bytes: u64,
+    previous_bytes: u64,
+}
+
+impl LimiterState {
+    fn record(&mut self, bytes: u64, now: Instant) {
+        let second = now.duration_since(self.started).as_secs();
+        if second != self.window_second {
+            self.previous_bytes = if second == self.window_second + 1 {
+                self.window_bytes
+            } else {
+                0
+            };
+            self.window_second = second;
+            self.window_bytes = 0;
+        }
+        self.window_bytes += bytes;
+    }
+}
+
+/// Shared token bucket every relay direction draws from before writing.
+///
+/// The rate can be changed at any time and applies to running relays.
+#[derive(Debug)]
+pub struct BandwidthLimiter {
+    /// Bytes per second (0 = unlimited).
+    rate: AtomicU64,
+    state: Mutex<LimiterState>,
+}
+
+impl BandwidthLimiter {
+    /// Create a limiter with the given rate in bytes per second (0 = unlimited).
+    pub fn new(rate: u64) -> Self {
+        let now = Instant::now();
+        Self {
+            rate: AtomicU64::new(rate),
+            state: Mutex::new(LimiterState {
+                tokens: 0.0,
+                refilled_at: now,
+                started: now,
+                window_second: 0,
+                window_bytes: 0,
+                previous_bytes: 0,
+            }),
+        }
+    }
+
+    /// Configured rate in bytes per second (0 = unlimited).
+    pub fn rate(&self) -> u64 {
+        self.rate.load(Ordering::Relaxed)
+    }
+
+    /// Change the rate.
+    pub fn set_rate(&self, rate: u64) {
+        self.rate.store(rate, Ordering::Relaxed);
+    }
+
+    /// Bytes relayed during the last full second.
+    pub fn throughput(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+        let second = state.started.elapsed().as_secs();
+        if second == state.window_second {
+            state.previous_bytes
+        } else if second == state.window_second + 1 {
+            state.window_bytes
+        } else {
+            0
+        }
+    }
+
+    /// Wait until some of `want` bytes may be written and return how many.
+    ///
+    /// Grants at most a twentieth of a second's worth (capped at 16 KiB) per
+    /// call; callers loop until their buffer is written.
+    pub async fn acquire(&self, want: usize) -> usize {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let now = Instant::now();
+                let rate = self.rate();
+                if rate == 0 {
+                    state.record(want as u64, now);
+                    return want;
+                }
+
+                let elapsed = now.duration_since(state.refilled_at).as_secs_f64();
+                state.tokens = (state.tokens + elapsed * rate as f64).min(rate as f64);
+                state.refilled_at = now;
+
+                let grant = (want as u64).min((rate / 20).clamp(1, MAX_DRAW));
+                if state.tokens >= grant as f64 {
+                    state.tokens -= grant as f64;
+                    state.record(grant, now);
+                    return grant as usize;
+                }
+                Duration::from_secs_f64((grant as f64 - state.tokens) / rate as f64)
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+impl Default for BandwidthLimiter {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
@@ -0,0 +1,49 @@
+//! What this binary was built from and what it was built with.
+//!
+//! The git commit, build time, compiler, target and profile are recorded by
+//! the build script; each is `None` when the build couldn't tell (e.g. a
+//! source tarball without `.git`).
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Version and build details of the running binary.
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// Abbreviated commit hash.
+    pub git_commit: Option<&'static str>,
+    /// When the build script ran, or `SOURCE_DATE_EPOCH`.
+    pub build_date: Option<DateTime<Utc>>,
+    /// `rustc --version` of the compiler used.
+    pub rustc: Option<&'static str>,
+    /// Target triple.
+    pub target: Option<&'static str>,
+    /// `debug` or `release`.
+    pub profile: Option<&'static str>,
+    /// Cargo features of the library that are compiled in.
+    pub features: Vec<&'static str>,
+    pub os: &'static str,
+    pub arch: &'static str,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_commit: option_env!("NET_RELAY_GIT_COMMIT"),
+            build_date: option_env!("NET_RELAY_BUILD_TIMESTAMP")
+                .and_then(|secs| secs.parse().ok())
+                .and_then(|secs| DateTime::from_timestamp(secs, 0)),
+            rustc: option_env!("NET_RELAY_RUSTC"),
+            target: option_env!("NET_RELAY_TARGET"),
+            profile: option_env!("NET_RELAY_PROFILE"),
+            features: [("acme", cfg!(feature = "acme"))]
+                .into_iter()
+                .filter_map(|(name, enabled)| enabled.then_some(name))
+                .collect(),
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+        }
+    }
+}
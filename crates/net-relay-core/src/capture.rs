@@ -0,0 +1,232 @@
+//! Debug captures of the bytes a connection relays.
+//!
+//! A [`Capture`] is attached to the relay of one active connection (see
+//! [`Stats::start_capture`](crate::Stats::start_capture)) and copies what it
+//! relays until `limit` bytes are captured or the connection closes. Relays
+//! without a capture only check an atomic flag. Captures are only possible
+//! with `debug.capture_enabled`.
+
+use std::fmt::Write as _;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Captures kept for download, oldest dropped first.
+pub const MAX_CAPTURES: usize = 16;
+
+/// Which way the captured bytes flow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureDirection {
+    /// Both directions, interleaved as relayed.
+    #[default]
+    Both,
+    /// Client to target.
+    Sent,
+    /// Target to client.
+    Received,
+}
+
+impl CaptureDirection {
+    fn includes(self, direction: CaptureDirection) -> bool {
+        self == CaptureDirection::Both || self == direction
+    }
+}
+
+/// Why a capture couldn't be started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum CaptureError {
+    #[error("connection is not active")]
+    NotActive,
+    #[error("connection is already being captured")]
+    AlreadyCapturing,
+}
+
+/// Why a capture stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureEnd {
+    /// `limit` bytes were captured.
+    LimitReached,
+    /// The connection's relay ended.
+    ConnectionClosed,
+}
+
+/// Bytes relayed in one direction by one read.
+#[derive(Debug, Clone)]
+pub struct CaptureSegment {
+    /// [`CaptureDirection::Sent`] or [`CaptureDirection::Received`].
+    pub direction: CaptureDirection,
+    pub at: DateTime<Utc>,
+    pub data: Vec<u8>,
+}
+
+/// Summary of a capture, without the bytes.
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureInfo {
+    pub connection_id: Uuid,
+    pub direction: CaptureDirection,
+    /// Bytes the capture stops at.
+    pub limit: usize,
+    /// Bytes captured so far.
+    pub bytes: usize,
+    /// Dashboard user who started the capture (`-` without one).
+    pub started_by: String,
+    pub started_at: DateTime<Utc>,
+    /// Whether bytes are still being captured.
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ended_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_reason: Option<CaptureEnd>,
+}
+
+#[derive(Debug, Default)]
+struct CaptureState {
+    segments: Vec<CaptureSegment>,
+    bytes: usize,
+    ended: Option<(DateTime<Utc>, CaptureEnd)>,
+}
+
+/// Bytes captured from one connection.
+#[derive(Debug)]
+pub struct Capture {
+    connection_id: Uuid,
+    direction: CaptureDirection,
+    limit: usize,
+    started_by: String,
+    started_at: DateTime<Utc>,
+    state: Mutex<CaptureState>,
+}
+
+impl Capture {
+    /// Capture up to `limit` bytes flowing in `direction` of a connection.
+    pub fn new(
+        connection_id: Uuid,
+        direction: CaptureDirection,
+        limit: usize,
+        started_by: impl Into<String>,
+    ) -> Self {
+        Self {
+            connection_id,
+            direction,
+            limit,
+            started_by: started_by.into(),
+            started_at: Utc::now(),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Copy `data` relayed in `direction` (`Sent` or `Received`).
+    ///
+    /// Returns `false` once the capture has ended.
+    pub fn record(&self, direction: CaptureDirection, data: &[u8]) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.ended.is_some() {
+            return false;
+        }
+        if self.direction.includes(direction) && !data.is_empty() {
+            let take = data.len().min(self.limit - state.bytes);
+            state.bytes += take;
+            state.segments.push(CaptureSegment {
+                direction,
+                at: Utc::now(),
+                data: data[..take].to_vec(),
+            });
+        }
+        if state.bytes >= self.limit {
+            state.ended = Some((Utc::now(), CaptureEnd::LimitReached));
+            return false;
+        }
+        true
+    }
+
+    /// Stop capturing; does nothing if the capture already ended.
+    pub fn finish(&self, reason: CaptureEnd) {
+        let mut state = self.state.lock().unwrap();
+        if state.ended.is_none() {
+            state.ended = Some((Utc::now(), reason));
+        }
+    }
+
+    /// The connection being captured.
+    pub fn connection_id(&self) -> Uuid {
+        self.connection_id
+    }
+
+    pub fn info(&self) -> CaptureInfo {
+        let state = self.state.lock().unwrap();
+        CaptureInfo {
+            connection_id: self.connection_id,
+            direction: self.direction,
+            limit: self.limit,
+            bytes: state.bytes,
+            started_by: self.started_by.clone(),
+            started_at: self.started_at,
+            active: state.ended.is_none(),
+            ended_at: state.ended.map(|(at, _)| at),
+            end_reason: state.ended.map(|(_, reason)| reason),
+        }
+    }
+
+    /// Captured reads, in the order they were relayed.
+    pub fn segments(&self) -> Vec<CaptureSegment> {
+        self.state.lock().unwrap().segments.clone()
+    }
+
+    /// Captured bytes of all segments, concatenated.
+    pub fn raw(&self) -> Vec<u8> {
+        let state = self.state.lock().unwrap();
+        state
+            .segments
+            .iter()
+            .flat_map(|segment| segment.data.iter().copied())
+            .collect()
+    }
+
+    /// `hexdump -C` style listing, one block per segment headed by its
+    /// direction (`>` sent, `<` received) and time.
+    pub fn hex_dump(&self) -> String {
+        let mut out = String::new();
+        for segment in self.segments() {
+            let (arrow, label) = match segment.direction {
+                CaptureDirection::Received => ('<', "received"),
+                _ => ('>', "sent"),
+            };
+            let _ = writeln!(
+                out,
+                "{} {} {} bytes at {}",
+                arrow,
+                label,
+                segment.data.len(),
+                segment.at.to_rfc3339()
+            );
+            for (line, chunk) in segment.data.chunks(16).enumerate() {
+                let _ = write!(out, "{:08x}  ", line * 16);
+                for i in 0..16 {
+                    match chunk.get(i) {
+                        Some(byte) => {
+                            let _ = write!(out, "{:02x} ", byte);
+                        }
+                        None => out.push_str("   "),
+                    }
+                    if i == 7 {
+                        out.push(' ');
+                    }
+                }
+                out.push_str(" |");
+                out.extend(chunk.iter().map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                }));
+                out.push_str("|\n");
+            }
+        }
+        out
+    }
+}
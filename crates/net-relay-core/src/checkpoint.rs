@@ -0,0 +1,63 @@
+//! Lifetime byte counters kept across restarts.
+//!
+//! A checkpoint holds the aggregate and per-user totals of [`Stats`]; it is
+//! written periodically and on shutdown to `stats.state_dir` and restored
+//! with [`Stats::with_checkpoint`] at startup. Active connection counts are
+//! runtime state and always start at zero.
+//!
+//! [`Stats`]: crate::stats::Stats
+//! [`Stats::with_checkpoint`]: crate::stats::Stats::with_checkpoint
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::stats::UserStats;
+
+/// Name of the checkpoint file in `stats.state_dir`.
+pub const CHECKPOINT_FILE: &str = "stats_checkpoint.json";
+
+/// Lifetime counters of a [`Stats`](crate::stats::Stats) collector.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsCheckpoint {
+    /// When the checkpoint was taken.
+    pub saved_at: DateTime<Utc>,
+
+    /// Total connections.
+    pub total_connections: u64,
+
+    /// Total bytes sent.
+    pub total_bytes_sent: u64,
+
+    /// Total bytes received.
+    pub total_bytes_received: u64,
+
+    /// Per-user totals (`active_connections` is ignored on restore).
+    #[serde(default)]
+    pub users: Vec<UserStats>,
+}
+
+impl StatsCheckpoint {
+    /// Read the checkpoint at `path`, or `None` if there is none yet.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Option<Self>> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the checkpoint to `path`, creating its directory if needed.
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let content = serde_json::to_string_pretty(self)?;
+        // Write then rename so a crash never leaves a truncated file
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
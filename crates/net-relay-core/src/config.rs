@@ -1,9 +1,34 @@
 //! Configuration structures for net-relay.
 
+use arc_swap::{ArcSwap, ArcSwapOption};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::sync::{watch, Mutex as AsyncMutex, OwnedMutexGuard, RwLock};
+
+use crate::access::{AccessMatcher, DecisionCache, TargetBlock, TargetCheck};
+use crate::access_log::{AccessLogFormat, LogRotation};
+use crate::auth::{
+    AuthRequest, AuthenticatedUser, Authenticator, HttpCallbackAuthenticator, SessionLimits,
+};
+use crate::bandwidth::BandwidthLimiter;
+use crate::connection::normalize_tags;
+use crate::dns::{DnsResolver, DnsStats};
+use crate::egress::EgressSelector;
+use crate::error::Result;
+use crate::event_log::EventLogBackpressure;
+use crate::hostname::{self, InvalidHostname};
+use crate::proxy::socks5::AuthPolicy;
+use crate::quota::QuotaPeriod;
+use crate::stats::IpAttribution;
+use crate::upstream::UpstreamPool;
 
 /// Main configuration structure.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -20,6 +45,10 @@ pub struct Config {
     #[serde(default)]
     pub security: SecurityConfig,
 
+    /// Responses of the HTTP CONNECT proxy.
+    #[serde(default)]
+    pub http_proxy: HttpProxyConfig,
+
     /// Connection limits.
     #[serde(default)]
     pub limits: LimitsConfig,
@@ -35,6 +64,54 @@ pub struct Config {
     /// Dashboard authentication configuration.
     #[serde(default)]
     pub dashboard: DashboardConfig,
+
+    /// Trusted upstream relay configuration.
+    #[serde(default)]
+    pub upstream: UpstreamConfig,
+
+    /// Target name resolution configuration.
+    #[serde(default)]
+    pub dns: DnsConfig,
+
+    /// Automatic dashboard certificates from an ACME CA.
+    #[serde(default)]
+    pub acme: AcmeConfig,
+
+    /// Local source addresses for outbound connections.
+    #[serde(default)]
+    pub egress: EgressConfig,
+
+    /// External authentication backend for proxy clients.
+    #[serde(default)]
+    pub auth: AuthConfig,
+
+    /// Metrics pushed to external collectors.
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+
+    /// Connection traces exported over OTLP.
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+
+    /// Scheduled snapshots of the config file.
+    #[serde(default)]
+    pub backup: BackupConfig,
+
+    /// Debugging aids that expose traffic.
+    #[serde(default)]
+    pub debug: DebugConfig,
+
+    /// Threshold alerts on the statistics.
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+
+    /// Profile whose access control is in effect (see `profiles`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_profile: Option<String>,
+
+    /// Named access control (and optionally limits) sets to switch between.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, ConfigProfile>,
 }
 
 impl Config {
@@ -45,411 +122,4227 @@ impl Config {
         Ok(config)
     }
 
+    /// Load the configuration the server runs with: the TOML file at `path`
+    /// (if any) with [`ENV_PREFIX`] overrides from `vars` on top, and where
+    /// each section came from.
+    ///
+    /// `NET_RELAY__LIMITS__MAX_CONNECTIONS=1000` sets `limits.max_connections`;
+    /// values are parsed as TOML and fall back to plain strings.
+    pub fn load_layered<I>(path: Option<&Path>, vars: I) -> anyhow::Result<(Self, ConfigProvenance)>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut table = match path {
+            Some(path) => std::fs::read_to_string(path)?.parse::<toml::Table>()?,
+            None => toml::Table::new(),
+        };
+        let mut provenance: ConfigProvenance = Config::SECTIONS
+            .iter()
+            .map(|section| (section.to_string(), ConfigSource::Default))
+            .collect();
+        for key in table.keys() {
+            if let Some(section) = section_of(key) {
+                provenance.insert(section.to_string(), ConfigSource::File);
+            }
+        }
+
+        for (name, value) in vars {
+            let Some(key_path) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let keys: Vec<String> = key_path.split("__").map(str::to_lowercase).collect();
+            let Some(section) = section_of(&keys[0]) else {
+                anyhow::bail!("{}: unknown config section '{}'", name, keys[0]);
+            };
+            if keys.iter().any(String::is_empty) {
+                anyhow::bail!("{}: empty key", name);
+            }
+            let (last, parents) = keys.split_last().expect("split never returns nothing");
+            let mut target = &mut table;
+            for key in parents {
+                target = match target
+                    .entry(key.as_str())
+                    .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                {
+                    toml::Value::Table(inner) => inner,
+                    _ => anyhow::bail!("{}: '{}' is not a table", name, key),
+                };
+            }
+            target.insert(last.clone(), parse_env_value(&value));
+            provenance.insert(section.to_string(), ConfigSource::Env);
+        }
+
+        let config = toml::Value::Table(table).try_into()?;
+        Ok((config, provenance))
+    }
+
     /// Save configuration to a TOML file.
+    ///
+    /// The content is written to a temporary file next to `path` (with the
+    /// permissions of the file it replaces) and renamed over it, so readers
+    /// never see a partially written file.
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
         let content = toml::to_string_pretty(self)?;
-        std::fs::write(path, content)?;
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut file = std::fs::File::create(&tmp_path)?;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
         Ok(())
     }
-}
 
-/// Runtime configuration manager for hot-reload support.
-#[derive(Clone)]
-pub struct ConfigManager {
-    config: Arc<RwLock<Config>>,
-    config_path: Option<String>,
-}
+    /// Names of the top-level configuration sections.
+    pub const SECTIONS: &'static [&'static str] = &[
+        "server",
+        "logging",
+        "security",
+        "http_proxy",
+        "limits",
+        "stats",
+        "access_control",
+        "dashboard",
+        "upstream",
+        "dns",
+        "acme",
+        "egress",
+        "auth",
+        "metrics",
+        "telemetry",
+        "backup",
+        "debug",
+        "alerts",
+        "profiles",
+    ];
 
-impl ConfigManager {
-    pub fn new(config: Config, config_path: Option<String>) -> Self {
-        Self {
-            config: Arc::new(RwLock::new(config)),
-            config_path,
+    /// Fail when two enabled listeners are configured with the same port.
+    pub fn check_port_conflicts(&self) -> anyhow::Result<()> {
+        let server = &self.server;
+        let mut ports: Vec<(&str, u16)> = Vec::new();
+        if server.socks_enabled {
+            ports.push(("server.socks_port", server.socks_port));
+        }
+        if server.http_enabled {
+            ports.push(("server.http_port", server.http_port));
+        }
+        if server.api_enabled {
+            ports.push(("server.api_port", server.api_port));
+        }
+        if let Some(port) = server.combined_port {
+            ports.push(("server.combined_port", port));
         }
+        if let Some(port) = server.tunnel_port {
+            ports.push(("server.tunnel_port", port));
+        }
+        if let (true, AcmeChallenge::Http01, Some(port)) =
+            (self.acme.enabled, self.acme.challenge, self.acme.http_port)
+        {
+            ports.push(("acme.http_port", port));
+        }
+        // Port 0 picks a free port for each listener
+        ports.retain(|(_, port)| *port != 0);
+        for (i, (key, port)) in ports.iter().enumerate() {
+            if let Some((other, _)) = ports[..i].iter().find(|(_, p)| p == port) {
+                anyhow::bail!("{} and {} are both set to {}", other, key, port);
+            }
+        }
+        Ok(())
     }
 
-    /// Get current configuration.
-    pub async fn get(&self) -> Config {
-        self.config.read().await.clone()
-    }
+    /// Check the configuration for values that would break the server at runtime.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut seen = std::collections::HashSet::new();
+        for user in &self.security.users {
+            if user.username.is_empty() {
+                anyhow::bail!("security.users: username must not be empty");
+            }
+            if !seen.insert(user.username.as_str()) {
+                anyhow::bail!("security.users: duplicate username '{}'", user.username);
+            }
+            if let Err(e) = normalize_tags(&user.tags) {
+                anyhow::bail!("security.users: tags of '{}' {}", user.username, e);
+            }
+        }
+        if self.security.socks5_auth_methods.is_empty() {
+            anyhow::bail!("security.socks5_auth_methods: at least one method is required");
+        }
+        let limits = &self.security.credential_limits;
+        if limits.max_length == 0 || limits.max_length > 255 {
+            anyhow::bail!("security.credential_limits: max_length must be between 1 and 255");
+        }
+        if limits.min_length > limits.max_length {
+            anyhow::bail!("security.credential_limits: min_length must not exceed max_length");
+        }
+        let policy = &self.security.password_policy;
+        if policy.generated_length < policy.min_length.max(MIN_PASSWORD_LENGTH) {
+            anyhow::bail!(
+                "security.password_policy: generated_length must be at least min_length and {}",
+                MIN_PASSWORD_LENGTH
+            );
+        }
 
-    /// Update configuration and optionally save to file.
-    pub async fn update(&self, config: Config) -> anyhow::Result<()> {
-        let mut current = self.config.write().await;
-        if let Some(path) = &self.config_path {
-            config.save_to_file(path)?;
+        if self.server.listen_backlog == 0 {
+            anyhow::bail!("server: listen_backlog must be greater than 0");
         }
-        *current = config;
-        Ok(())
-    }
 
-    /// Update access control rules only.
-    pub async fn update_access_control(
-        &self,
-        access_control: AccessControlConfig,
-    ) -> anyhow::Result<()> {
-        let mut config = self.config.write().await;
-        config.access_control = access_control;
-        if let Some(path) = &self.config_path {
-            config.save_to_file(path)?;
+        if self.limits.connect_retries > MAX_CONNECT_RETRIES {
+            anyhow::bail!(
+                "limits: connect_retries must be at most {}",
+                MAX_CONNECT_RETRIES
+            );
+        }
+        if !(MIN_RELAY_BUFFER_SIZE..=MAX_RELAY_BUFFER_SIZE).contains(&self.limits.relay_buffer_size)
+        {
+            anyhow::bail!(
+                "limits: relay_buffer_size must be between {} and {}",
+                MIN_RELAY_BUFFER_SIZE,
+                MAX_RELAY_BUFFER_SIZE
+            );
+        }
+        let keepalive = &self.limits.tcp_keepalive;
+        if keepalive.enabled
+            && (keepalive.idle_secs == 0 || keepalive.interval_secs == 0 || keepalive.count == 0)
+        {
+            anyhow::bail!(
+                "limits.tcp_keepalive: idle_secs, interval_secs and count must be greater than 0"
+            );
         }
-        Ok(())
-    }
 
-    /// Check if an IP is allowed.
-    pub async fn is_ip_allowed(&self, ip: &str) -> bool {
-        let config = self.config.read().await;
-        config.access_control.is_ip_allowed(ip)
-    }
+        for (name, value) in &self.http_proxy.headers {
+            if name.is_empty()
+                || !name
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+            {
+                anyhow::bail!("http_proxy.headers: invalid header name '{}'", name);
+            }
+            if RESERVED_RESPONSE_HEADERS
+                .iter()
+                .any(|reserved| name.eq_ignore_ascii_case(reserved))
+            {
+                anyhow::bail!("http_proxy.headers: '{}' is set by the proxy", name);
+            }
+            if value.chars().any(|c| c.is_control() && c != '\t') {
+                anyhow::bail!("http_proxy.headers.{}: value has control characters", name);
+            }
+        }
+        if self.http_proxy.keep_alive {
+            if self.http_proxy.keep_alive_timeout_secs == 0 {
+                anyhow::bail!("http_proxy: keep_alive_timeout_secs must be greater than 0");
+            }
+            if self.http_proxy.max_requests_per_connection == 0 {
+                anyhow::bail!("http_proxy: max_requests_per_connection must be greater than 0");
+            }
+        }
 
-    /// Check if a target (domain + path) is allowed.
-    pub async fn is_target_allowed(&self, host: &str, path: Option<&str>) -> bool {
-        let config = self.config.read().await;
-        config.access_control.is_target_allowed(host, path)
-    }
+        validate_rules("access_control", &self.access_control.rules)?;
+        for (name, profile) in &self.profiles {
+            if !is_profile_name(name) {
+                anyhow::bail!(
+                    "profiles: invalid name '{}' (use letters, digits, '-' and '_')",
+                    name
+                );
+            }
+            validate_rules(
+                &format!("profiles.{}.access_control", name),
+                &profile.access_control.rules,
+            )?;
+        }
+        if let Some(name) = &self.active_profile {
+            if !self.profiles.contains_key(name) {
+                anyhow::bail!("active_profile: no profile named '{}'", name);
+            }
+        }
 
-    /// Check if authentication is required.
-    pub async fn is_auth_enabled(&self) -> bool {
-        let config = self.config.read().await;
-        config.security.auth_enabled
+        self.dashboard.validate()?;
+
+        if self.auth.backend == AuthBackend::HttpCallback {
+            let callback = &self.auth.http_callback;
+            if !callback.url.starts_with("http://") && !callback.url.starts_with("https://") {
+                anyhow::bail!("auth.http_callback: url must start with http:// or https://");
+            }
+            if callback.timeout_ms == 0 {
+                anyhow::bail!("auth.http_callback: timeout_ms must be at least 1");
+            }
+            let valid_header = !callback.secret_header.is_empty()
+                && callback
+                    .secret_header
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+            if !callback.secret.is_empty() && !valid_header {
+                anyhow::bail!(
+                    "auth.http_callback: '{}' is not a valid header name",
+                    callback.secret_header
+                );
+            }
+        }
+
+        if self.stats.rate_window_secs == 0 {
+            anyhow::bail!("stats: rate_window_secs must be at least 1");
+        }
+        if self.stats.max_history == 0 {
+            anyhow::bail!("stats: max_history must be at least 1");
+        }
+        if self.stats.checkpoint_interval_secs == 0 {
+            anyhow::bail!("stats: checkpoint_interval_secs must be at least 1");
+        }
+        if self.stats.attribute_ipv4_prefix > 32 {
+            anyhow::bail!("stats: attribute_ipv4_prefix must be at most 32");
+        }
+        if self.stats.attribute_ipv6_prefix > 128 {
+            anyhow::bail!("stats: attribute_ipv6_prefix must be at most 128");
+        }
+        if self.stats.attribute_by_ip && self.stats.max_ip_buckets == 0 {
+            anyhow::bail!("stats: max_ip_buckets must be at least 1");
+        }
+        if self.stats.max_tracked_users == 0 {
+            anyhow::bail!("stats: max_tracked_users must be at least 1");
+        }
+        if self.stats.max_denied_attempts == 0 {
+            anyhow::bail!("stats: max_denied_attempts must be at least 1");
+        }
+
+        let statsd = &self.metrics.statsd;
+        if statsd.enabled {
+            if statsd.host.is_empty() {
+                anyhow::bail!("metrics.statsd: enabled requires host");
+            }
+            if statsd.flush_interval_secs == 0 {
+                anyhow::bail!("metrics.statsd: flush_interval_secs must be at least 1");
+            }
+            let invalid_part = |s: &str| s.is_empty() || s.contains(['|', ',', '#', ':', '\n']);
+            if !statsd.prefix.is_empty() && statsd.prefix.split('.').any(invalid_part) {
+                anyhow::bail!("metrics.statsd: invalid prefix '{}'", statsd.prefix);
+            }
+            if let Some(tag) = statsd
+                .tags
+                .iter()
+                .find(|tag| tag.is_empty() || tag.contains(['|', ',', '#', '\n']))
+            {
+                anyhow::bail!("metrics.statsd: invalid tag '{}'", tag);
+            }
+        }
+
+        let telemetry = &self.telemetry;
+        if telemetry.enabled {
+            if !telemetry.endpoint.starts_with("http://")
+                && !telemetry.endpoint.starts_with("https://")
+            {
+                anyhow::bail!(
+                    "telemetry: endpoint must be an http:// or https:// URL, got '{}'",
+                    telemetry.endpoint
+                );
+            }
+            if !(0.0..=1.0).contains(&telemetry.sample_ratio) {
+                anyhow::bail!("telemetry: sample_ratio must be between 0 and 1");
+            }
+        }
+
+        let syslog = &self.logging.syslog;
+        if syslog.enabled {
+            syslog.transport()?;
+            if syslog.app_name.is_empty() || syslog.app_name.contains(char::is_whitespace) {
+                anyhow::bail!(
+                    "logging.syslog: app_name must be non-empty without spaces, got '{}'",
+                    syslog.app_name
+                );
+            }
+        }
+
+        let backup = &self.backup;
+        if backup.enabled {
+            if backup.directory.is_empty() {
+                anyhow::bail!("backup: enabled requires directory");
+            }
+            if backup.interval_secs == 0 {
+                anyhow::bail!("backup: interval_secs must be at least 1");
+            }
+        }
+
+        self.alerts.validate()?;
+
+        if self.upstream.enabled {
+            let endpoints = self.upstream.endpoints();
+            if endpoints.is_empty() {
+                anyhow::bail!("upstream: enabled requires address, node_name and node_secret");
+            }
+            let mut addresses = HashSet::new();
+            for endpoint in &endpoints {
+                if endpoint.node_name.is_empty() || endpoint.node_secret.is_empty() {
+                    anyhow::bail!(
+                        "upstream: relay {} requires node_name and node_secret",
+                        endpoint.address
+                    );
+                }
+                if !addresses.insert(endpoint.address.as_str()) {
+                    anyhow::bail!("upstream: duplicate relay address {}", endpoint.address);
+                }
+            }
+            if self.upstream.health_check_interval == 0 {
+                anyhow::bail!("upstream: health_check_interval must be at least 1 second");
+            }
+        }
+        if let Some(target) = &self.upstream.probe_target {
+            let valid = target
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+            if !valid {
+                anyhow::bail!("upstream: probe_target must be host:port, got '{}'", target);
+            }
+        }
+
+        self.check_port_conflicts()?;
+
+        if self.server.tunnel_port.is_some()
+            && (self.server.tunnel_tls_cert.is_none() || self.server.tunnel_tls_key.is_none())
+        {
+            anyhow::bail!("server: tunnel_port requires tunnel_tls_cert and tunnel_tls_key");
+        }
+
+        if self.server.api_tls_cert.is_some() != self.server.api_tls_key.is_some() {
+            anyhow::bail!("server: api_tls_cert and api_tls_key must be set together");
+        }
+
+        if self.acme.enabled {
+            if self.acme.domain.is_empty() || self.acme.email.is_empty() {
+                anyhow::bail!("acme: enabled requires domain and email");
+            }
+            if self.server.api_tls_cert.is_some() {
+                anyhow::bail!("acme: enabled conflicts with server.api_tls_cert");
+            }
+            // The CA's validation handshake carries no client certificate
+            if self.acme.challenge == AcmeChallenge::TlsAlpn01
+                && self.server.api_require_client_cert
+            {
+                anyhow::bail!(
+                    "acme: challenge tls-alpn-01 conflicts with server.api_require_client_cert"
+                );
+            }
+        }
+
+        if self.server.api_require_client_cert && self.server.api_client_ca_path.is_none() {
+            anyhow::bail!("server: api_require_client_cert requires api_client_ca_path");
+        }
+        if self.server.api_client_ca_path.is_some()
+            && self.server.api_tls_cert.is_none()
+            && !self.acme.enabled
+        {
+            anyhow::bail!("server: api_client_ca_path requires api_tls_cert or acme");
+        }
+
+        match self.dns.mode {
+            DnsMode::System => {}
+            DnsMode::Udp | DnsMode::Dot if self.dns.resolver.is_none() => {
+                anyhow::bail!("dns: mode {:?} requires resolver", self.dns.mode);
+            }
+            DnsMode::Doh
+                if !self
+                    .dns
+                    .resolver
+                    .as_deref()
+                    .is_some_and(|r| r.starts_with("https://")) =>
+            {
+                anyhow::bail!("dns: mode doh requires an https:// resolver URL");
+            }
+            _ => {}
+        }
+        for ip in &self.dns.bootstrap {
+            if ip.parse::<IpAddr>().is_err() {
+                anyhow::bail!("dns.bootstrap: invalid IP address '{}'", ip);
+            }
+        }
+
+        Ok(())
     }
 
-    /// Authenticate a user. Returns the username if successful.
-    pub async fn authenticate(&self, username: &str, password: &str) -> Option<String> {
-        let config = self.config.read().await;
-        config.security.authenticate(username, password)
+    /// Return a copy of this configuration with all passwords replaced by [`REDACTED_SECRET`].
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        for user in &mut config.security.users {
+            user.password = REDACTED_SECRET.to_string();
+        }
+        if config.security.password.is_some() {
+            config.security.password = Some(REDACTED_SECRET.to_string());
+        }
+        if config.dashboard.password.is_some() {
+            config.dashboard.password = Some(REDACTED_SECRET.to_string());
+        }
+        if config.dashboard.metrics_token.is_some() {
+            config.dashboard.metrics_token = Some(REDACTED_SECRET.to_string());
+        }
+        for downstream in &mut config.server.trusted_downstreams {
+            downstream.secret = REDACTED_SECRET.to_string();
+        }
+        if !config.upstream.node_secret.is_empty() {
+            config.upstream.node_secret = REDACTED_SECRET.to_string();
+        }
+        if !config.auth.http_callback.secret.is_empty() {
+            config.auth.http_callback.secret = REDACTED_SECRET.to_string();
+        }
+        for relay in &mut config.upstream.relays {
+            if relay.node_secret.is_some() {
+                relay.node_secret = Some(REDACTED_SECRET.to_string());
+            }
+        }
+        config
     }
 
-    /// Get security configuration.
-    pub async fn get_security(&self) -> SecurityConfig {
-        let config = self.config.read().await;
-        config.security.clone()
+    /// Replace [`REDACTED_SECRET`] placeholders with the matching secrets from `current`.
+    ///
+    /// This lets a redacted export be imported again without wiping passwords.
+    pub fn restore_redacted(&mut self, current: &Config) {
+        for user in &mut self.security.users {
+            if user.password == REDACTED_SECRET {
+                if let Some(existing) = current
+                    .security
+                    .users
+                    .iter()
+                    .find(|u| u.username == user.username)
+                {
+                    user.password = existing.password.clone();
+                }
+            }
+        }
+        if self.security.password.as_deref() == Some(REDACTED_SECRET) {
+            self.security.password = current.security.password.clone();
+        }
+        if self.dashboard.password.as_deref() == Some(REDACTED_SECRET) {
+            self.dashboard.password = current.dashboard.password.clone();
+        }
+        if self.dashboard.metrics_token.as_deref() == Some(REDACTED_SECRET) {
+            self.dashboard.metrics_token = current.dashboard.metrics_token.clone();
+        }
+        for downstream in &mut self.server.trusted_downstreams {
+            if downstream.secret == REDACTED_SECRET {
+                if let Some(existing) = current
+                    .server
+                    .trusted_downstreams
+                    .iter()
+                    .find(|d| d.name == downstream.name)
+                {
+                    downstream.secret = existing.secret.clone();
+                }
+            }
+        }
+        if self.upstream.node_secret == REDACTED_SECRET {
+            self.upstream.node_secret = current.upstream.node_secret.clone();
+        }
+        if self.auth.http_callback.secret == REDACTED_SECRET {
+            self.auth.http_callback.secret = current.auth.http_callback.secret.clone();
+        }
+        for relay in &mut self.upstream.relays {
+            if relay.node_secret.as_deref() == Some(REDACTED_SECRET) {
+                relay.node_secret = current
+                    .upstream
+                    .relays
+                    .iter()
+                    .find(|r| r.address == relay.address)
+                    .and_then(|r| r.node_secret.clone());
+            }
+        }
     }
 
-    /// Update security configuration.
-    pub async fn update_security(&self, security: SecurityConfig) -> anyhow::Result<()> {
-        let mut config = self.config.write().await;
-        config.security = security;
-        if let Some(path) = &self.config_path {
-            config.save_to_file(path)?;
+    /// Copy the named sections from `other` into this configuration.
+    pub fn merge_sections(&mut self, other: &Config, sections: &[&str]) -> anyhow::Result<()> {
+        for section in sections {
+            match *section {
+                "server" => self.server = other.server.clone(),
+                "logging" => self.logging = other.logging.clone(),
+                "security" => self.security = other.security.clone(),
+                "http_proxy" => self.http_proxy = other.http_proxy.clone(),
+                "limits" => self.limits = other.limits.clone(),
+                "stats" => self.stats = other.stats.clone(),
+                "access_control" => self.access_control = other.access_control.clone(),
+                "dashboard" => self.dashboard = other.dashboard.clone(),
+                "upstream" => self.upstream = other.upstream.clone(),
+                "dns" => self.dns = other.dns.clone(),
+                "acme" => self.acme = other.acme.clone(),
+                "egress" => self.egress = other.egress.clone(),
+                "auth" => self.auth = other.auth.clone(),
+                "metrics" => self.metrics = other.metrics.clone(),
+                "telemetry" => self.telemetry = other.telemetry.clone(),
+                "backup" => self.backup = other.backup.clone(),
+                "debug" => self.debug = other.debug.clone(),
+                "alerts" => self.alerts = other.alerts.clone(),
+                "profiles" => {
+                    self.profiles = other.profiles.clone();
+                    self.active_profile = other.active_profile.clone();
+                }
+                unknown => anyhow::bail!("Unknown config section: {}", unknown),
+            }
         }
         Ok(())
     }
 
-    /// Get dashboard configuration.
-    pub async fn get_dashboard(&self) -> DashboardConfig {
-        let config = self.config.read().await;
-        config.dashboard.clone()
-    }
+    /// List the top-level sections whose contents differ from `other`.
+    pub fn changed_sections(&self, other: &Config) -> Vec<&'static str> {
+        let (Ok(toml::Value::Table(a)), Ok(toml::Value::Table(b))) =
+            (toml::Value::try_from(self), toml::Value::try_from(other))
+        else {
+            return Config::SECTIONS.to_vec();
+        };
 
-    /// Check if dashboard authentication is enabled.
-    pub async fn is_dashboard_auth_enabled(&self) -> bool {
-        let config = self.config.read().await;
-        config.dashboard.auth_enabled
+        Config::SECTIONS
+            .iter()
+            .copied()
+            .filter(|section| {
+                a.get(*section) != b.get(*section)
+                    || (*section == "profiles"
+                        && a.get("active_profile") != b.get("active_profile"))
+            })
+            .collect()
     }
 
-    /// Authenticate for dashboard access.
-    pub async fn authenticate_dashboard(&self, username: &str, password: &str) -> bool {
-        let config = self.config.read().await;
-        config.dashboard.authenticate(username, password)
+    /// Move the deprecated `security.allowed_ips` into the live
+    /// `access_control.ip_whitelist`.
+    ///
+    /// The entries become the whitelist when it is empty; an existing
+    /// whitelist wins and the entries are dropped. Returns `None` when
+    /// `allowed_ips` is empty.
+    pub fn migrate_allowed_ips(&mut self) -> Option<AllowedIpsMigration> {
+        if self.security.allowed_ips.is_empty() {
+            return None;
+        }
+        let allowed_ips = std::mem::take(&mut self.security.allowed_ips);
+        let whitelist = &mut self.access_control.ip_whitelist;
+        if whitelist.is_empty() {
+            whitelist.clone_from(&allowed_ips);
+            Some(AllowedIpsMigration::Moved(allowed_ips))
+        } else {
+            Some(AllowedIpsMigration::Dropped(allowed_ips))
+        }
     }
 
-    /// Get server configuration.
-    pub async fn get_server(&self) -> ServerConfig {
-        let config = self.config.read().await;
-        config.server.clone()
+    /// Make the active profile's access control (and limits) the live ones.
+    fn apply_active_profile(&mut self) {
+        let Some(profile) = self
+            .active_profile
+            .as_ref()
+            .and_then(|name| self.profiles.get(name))
+        else {
+            return;
+        };
+        self.access_control = profile.access_control.clone();
+        if let Some(limits) = &profile.limits {
+            self.limits = limits.clone();
+        }
     }
 
-    /// Update server configuration.
-    pub async fn update_server(&self, server: ServerConfig) -> anyhow::Result<()> {
-        let mut config = self.config.write().await;
-        config.server = server;
-        if let Some(path) = &self.config_path {
-            config.save_to_file(path)?;
+    /// Copy the live access control (and limits) back into the active profile.
+    fn sync_active_profile(&mut self) {
+        let Some(profile) = self
+            .active_profile
+            .as_ref()
+            .and_then(|name| self.profiles.get_mut(name))
+        else {
+            return;
+        };
+        profile.access_control = self.access_control.clone();
+        if profile.limits.is_some() {
+            profile.limits = Some(self.limits.clone());
         }
-        Ok(())
     }
 }
 
-/// Server binding configuration.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ServerConfig {
-    /// Host address to bind.
-    #[serde(default = "default_host")]
-    pub host: String,
+/// Prefix of environment variables overriding config file settings (see
+/// [`Config::load_layered`]).
+pub const ENV_PREFIX: &str = "NET_RELAY__";
 
-    /// SOCKS5 proxy port.
-    #[serde(default = "default_socks_port")]
-    pub socks_port: u16,
+/// Section of [`Config::SECTIONS`] a top-level key belongs to.
+fn section_of(key: &str) -> Option<&'static str> {
+    if key == "active_profile" {
+        return Some("profiles");
+    }
+    Config::SECTIONS.iter().copied().find(|s| *s == key)
+}
 
-    /// HTTP proxy port.
-    #[serde(default = "default_http_port")]
-    pub http_port: u16,
+/// Parse an environment override as a TOML value, or take it as a string.
+fn parse_env_value(raw: &str) -> toml::Value {
+    format!("value = {}", raw)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
 
-    /// API/Dashboard port.
-    #[serde(default = "default_api_port")]
-    pub api_port: u16,
+/// Where the contents of a configuration section came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// Not set anywhere: built-in defaults.
+    Default,
+    /// The config file, as loaded at startup.
+    File,
+    /// `NET_RELAY__*` environment overrides (on top of the file).
+    Env,
+    /// Changed through the API by `user`.
+    Api { user: String, at: DateTime<Utc> },
+    /// Changed by the server itself, e.g. by removing expired rules.
+    Runtime { at: DateTime<Utc> },
 }
 
-impl Default for ServerConfig {
-    fn default() -> Self {
-        Self {
-            host: default_host(),
-            socks_port: default_socks_port(),
-            http_port: default_http_port(),
-            api_port: default_api_port(),
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = |at: &DateTime<Utc>| at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        match self {
+            ConfigSource::Default => f.write_str("default"),
+            ConfigSource::File => f.write_str("file"),
+            ConfigSource::Env => f.write_str("env"),
+            ConfigSource::Api { user, at } => write!(f, "api:{}@{}", user, time(at)),
+            ConfigSource::Runtime { at } => write!(f, "runtime@{}", time(at)),
         }
     }
 }
 
-fn default_host() -> String {
-    "0.0.0.0".to_string()
+impl Serialize for ConfigSource {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
 }
 
-fn default_socks_port() -> u16 {
-    1080
-}
+/// Where each top-level section of the running configuration came from.
+pub type ConfigProvenance = BTreeMap<String, ConfigSource>;
 
-fn default_http_port() -> u16 {
-    8080
+tokio::task_local! {
+    static CONFIG_ACTOR: String;
 }
 
-fn default_api_port() -> u16 {
-    3000
+/// Run `future`, attributing the configuration changes it makes to `user`
+/// (see [`ConfigManager::provenance`]).
+pub async fn with_config_actor<F: std::future::Future>(user: String, future: F) -> F::Output {
+    CONFIG_ACTOR.scope(user, future).await
 }
 
-/// Logging configuration.
+/// Check rule ids and domains of the access control at `section`.
+fn validate_rules(section: &str, rules: &[AccessRule]) -> anyhow::Result<()> {
+    let mut rule_ids = HashSet::new();
+    for (index, rule) in rules.iter().enumerate() {
+        if rule.domain.is_empty() {
+            anyhow::bail!("{}.rules[{}]: domain must not be empty", section, index);
+        }
+        if let Err(e) = hostname::normalize_pattern(&rule.domain) {
+            anyhow::bail!("{}.rules[{}]: {}", section, index, e);
+        }
+        if rule.id.is_empty() {
+            anyhow::bail!("{}.rules[{}]: id must not be empty", section, index);
+        }
+        if !rule_ids.insert(rule.id.as_str()) {
+            anyhow::bail!("{}.rules[{}]: duplicate id '{}'", section, index, rule.id);
+        }
+    }
+    Ok(())
+}
+
+fn is_profile_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Backend for `config`, `None` when only `security.users` are checked.
+fn build_authenticator(config: &AuthConfig) -> Option<Arc<dyn Authenticator>> {
+    match config.backend {
+        AuthBackend::Config => None,
+        AuthBackend::HttpCallback => Some(Arc::new(HttpCallbackAuthenticator::new(
+            config.http_callback.clone(),
+        ))),
+    }
+}
+
+/// How long expired access rules are kept (and reported as expired) before
+/// [`ConfigManager::remove_expired_rules`] deletes them.
+pub const EXPIRED_RULE_RETENTION: chrono::Duration = chrono::Duration::hours(1);
+
+/// Placeholder written in place of secrets when exporting configuration.
+pub const REDACTED_SECRET: &str = "<redacted>";
+
+/// Runtime configuration manager for hot-reload support.
+///
+/// Without a config file (`config_path` of `None`) changes only live in
+/// memory until a path is attached with [`set_config_path`].
+///
+/// [`set_config_path`]: ConfigManager::set_config_path
+#[derive(Clone)]
+pub struct ConfigManager {
+    config: Arc<RwLock<Config>>,
+    config_path: Arc<ArcSwapOption<String>>,
+    /// Latest change, for [`ConfigManager::subscribe`].
+    changes: Arc<watch::Sender<ConfigChange>>,
+    resolver: Arc<DnsResolver>,
+    bandwidth: Arc<BandwidthLimiter>,
+    /// Compiled `access_control`, rebuilt whenever it changes.
+    access: Arc<ArcSwap<AccessMatcher>>,
+    /// Recent decisions of `access`, emptied whenever it is replaced.
+    decisions: Arc<DecisionCache>,
+    /// Temporary client IP bans and when they expire (not persisted).
+    bans: Arc<ArcSwap<HashMap<IpAddr, DateTime<Utc>>>>,
+    /// Health of the `upstream` relays, synced whenever the configuration changes.
+    upstreams: Arc<UpstreamPool>,
+    /// Round-robin state for `egress`.
+    egress: Arc<EgressSelector>,
+    /// Backend for `auth`, rebuilt whenever it changes.
+    authenticator: Arc<ArcSwap<Option<Arc<dyn Authenticator>>>>,
+    /// Bandwidth limiters shared by each user's connections.
+    user_limiters: Arc<Mutex<HashMap<String, Weak<BandwidthLimiter>>>>,
+    /// Revision of the configuration, bumped by every change.
+    revision: Arc<AtomicU64>,
+    /// Held for the length of a read-modify-write edit (see [`ConfigManager::begin_edit`]).
+    edit_lock: Arc<AsyncMutex<()>>,
+    /// Set while the configuration is locked against edits (not persisted).
+    config_lock: Arc<Mutex<Option<ConfigLock>>>,
+    /// Whether the config file holds the in-memory configuration.
+    save_status: Arc<Mutex<SaveStatus>>,
+    /// Where each section of the configuration came from.
+    provenance: Arc<Mutex<ConfigProvenance>>,
+}
+
+/// What [`Config::migrate_allowed_ips`] did with `security.allowed_ips`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllowedIpsMigration {
+    /// The entries became `access_control.ip_whitelist`.
+    Moved(Vec<String>),
+    /// `access_control.ip_whitelist` was already set, so it is kept as it
+    /// is (it is what was enforced) and these entries were dropped.
+    Dropped(Vec<String>),
+}
+
+/// A change to the running configuration, published to
+/// [`ConfigManager::subscribe`] receivers.
+#[derive(Debug, Clone)]
+pub struct ConfigChange {
+    /// Revision of the configuration after the change.
+    pub revision: u64,
+    /// Top-level sections that changed (see [`Config::SECTIONS`]); empty for
+    /// the configuration the manager started with.
+    pub sections: Vec<String>,
+    pub config: Arc<Config>,
+}
+
+impl ConfigChange {
+    /// Whether `section` changed.
+    pub fn touches(&self, section: &str) -> bool {
+        self.sections.iter().any(|s| s == section)
+    }
+}
+
+/// Whether the in-memory configuration has been written to the config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SaveStatus {
+    /// The last save failed: the running configuration differs from the file.
+    pub dirty: bool,
+    /// Why the last save failed (cleared by the next successful save).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_save_error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_save_error_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_saved_at: Option<DateTime<Utc>>,
+}
+
+/// A change was applied in memory but could not be written to the config file.
+///
+/// Returned (inside `anyhow::Error`) by the [`ConfigManager`] update methods;
+/// the change stays in effect until the process exits or the save is retried
+/// with [`ConfigManager::flush`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigSaveError {
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigSaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Change applied but not saved: {}", self.message)
+    }
+}
+
+impl std::error::Error for ConfigSaveError {}
+
+/// Who froze the configuration, and when.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigLock {
+    pub locked_by: String,
+    pub locked_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// An edit was based on an outdated revision of the configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RevisionConflict {
+    pub expected: u64,
+    pub current: u64,
+}
+
+impl std::fmt::Display for RevisionConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Configuration changed since revision {} (now at revision {})",
+            self.expected, self.current
+        )
+    }
+}
+
+impl std::error::Error for RevisionConflict {}
+
+/// Exclusive right to edit the configuration; other edits wait until it is dropped.
+pub struct ConfigEdit {
+    _guard: OwnedMutexGuard<()>,
+}
+
+impl ConfigManager {
+    pub fn new(mut config: Config, config_path: Option<String>) -> Self {
+        config.apply_active_profile();
+        let bandwidth = Arc::new(BandwidthLimiter::new(config.limits.global_bandwidth));
+        let access = Arc::new(ArcSwap::from_pointee(AccessMatcher::new(
+            &config.access_control,
+        )));
+        let upstreams = Arc::new(UpstreamPool::default());
+        upstreams.sync(&config.upstream);
+        let authenticator = Arc::new(ArcSwap::from_pointee(build_authenticator(&config.auth)));
+        let (changes, _) = watch::channel(ConfigChange {
+            revision: 1,
+            sections: Vec::new(),
+            config: Arc::new(config.clone()),
+        });
+        Self {
+            config: Arc::new(RwLock::new(config)),
+            config_path: Arc::new(ArcSwapOption::from(config_path.map(Arc::new))),
+            changes: Arc::new(changes),
+            resolver: Arc::new(DnsResolver::new()),
+            bandwidth,
+            access,
+            decisions: Arc::default(),
+            bans: Arc::default(),
+            upstreams,
+            egress: Arc::default(),
+            authenticator,
+            user_limiters: Arc::default(),
+            revision: Arc::new(AtomicU64::new(1)),
+            edit_lock: Arc::default(),
+            config_lock: Arc::default(),
+            save_status: Arc::default(),
+            provenance: Arc::new(Mutex::new(
+                Config::SECTIONS
+                    .iter()
+                    .map(|section| (section.to_string(), ConfigSource::Default))
+                    .collect(),
+            )),
+        }
+    }
+
+    /// Report where each section came from, as returned by [`Config::load_layered`].
+    ///
+    /// Without it every section is reported as [`ConfigSource::Default`] until changed.
+    pub fn with_provenance(self, provenance: ConfigProvenance) -> Self {
+        self.provenance.lock().unwrap().extend(provenance);
+        self
+    }
+
+    /// Where each top-level section of the running configuration came from.
+    pub fn provenance(&self) -> ConfigProvenance {
+        self.provenance.lock().unwrap().clone()
+    }
+
+    /// Lock the configuration against edits, waiting for an edit in progress.
+    ///
+    /// Returns the existing lock instead if the configuration is already locked.
+    pub async fn lock_config(
+        &self,
+        locked_by: &str,
+        reason: Option<String>,
+    ) -> std::result::Result<ConfigLock, ConfigLock> {
+        let _edit = self.begin_edit(None).await;
+        let mut config_lock = self.config_lock.lock().unwrap();
+        if let Some(existing) = &*config_lock {
+            return Err(existing.clone());
+        }
+        let lock = ConfigLock {
+            locked_by: locked_by.to_string(),
+            locked_at: Utc::now(),
+            reason,
+        };
+        *config_lock = Some(lock.clone());
+        Ok(lock)
+    }
+
+    /// Lift the configuration lock, returning it (`None` if it wasn't locked).
+    pub fn unlock_config(&self) -> Option<ConfigLock> {
+        self.config_lock.lock().unwrap().take()
+    }
+
+    /// Current configuration lock, if any.
+    pub fn config_lock(&self) -> Option<ConfigLock> {
+        self.config_lock.lock().unwrap().clone()
+    }
+
+    /// Current revision of the configuration.
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::Relaxed)
+    }
+
+    /// Start a read-modify-write edit, waiting for any edit in progress.
+    ///
+    /// With `expected_revision`, fails if the configuration has changed since
+    /// that revision; without it, the edit simply overwrites (last write wins).
+    pub async fn begin_edit(
+        &self,
+        expected_revision: Option<u64>,
+    ) -> std::result::Result<ConfigEdit, RevisionConflict> {
+        let guard = Arc::clone(&self.edit_lock).lock_owned().await;
+        let current = self.revision();
+        match expected_revision {
+            Some(expected) if expected != current => Err(RevisionConflict { expected, current }),
+            _ => Ok(ConfigEdit { _guard: guard }),
+        }
+    }
+
+    /// Record a change to `sections`: bump the revision, note who made it and
+    /// save `config` to the config file.
+    ///
+    /// Changes to the live access control (and limits) are copied into the
+    /// active profile first, so editing rules edits the profile. Callers apply
+    /// the change before persisting it, so a failed save leaves it in effect
+    /// and marks the configuration dirty (see [`ConfigSaveError`]).
+    fn persist(&self, config: &mut Config, sections: &[&str]) -> anyhow::Result<()> {
+        config.sync_active_profile();
+        let revision = self.revision.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let at = Utc::now();
+        let source = CONFIG_ACTOR
+            .try_with(|user| ConfigSource::Api {
+                user: user.clone(),
+                at,
+            })
+            .unwrap_or(ConfigSource::Runtime { at });
+        let synced = config.active_profile.is_some()
+            && sections
+                .iter()
+                .any(|s| *s == "access_control" || *s == "limits");
+        let mut provenance = self.provenance.lock().unwrap();
+        for section in sections.iter().chain(synced.then_some(&"profiles")) {
+            provenance.insert(section.to_string(), source.clone());
+        }
+        drop(provenance);
+
+        self.changes.send_replace(ConfigChange {
+            revision,
+            sections: sections
+                .iter()
+                .chain(synced.then_some(&"profiles"))
+                .map(|s| s.to_string())
+                .collect(),
+            config: Arc::new(config.clone()),
+        });
+        self.save(config)
+    }
+
+    /// Receive every change to the configuration, starting from the current one.
+    ///
+    /// The receiver sees the latest change; intermediate ones may be skipped
+    /// when several happen before it looks, so compare with
+    /// [`ConfigChange::config`] rather than relying on `sections` alone.
+    pub fn subscribe(&self) -> watch::Receiver<ConfigChange> {
+        self.changes.subscribe()
+    }
+
+    /// Write `config` to the config file, recording the outcome in [`SaveStatus`].
+    fn save(&self, config: &Config) -> anyhow::Result<()> {
+        let Some(path) = self.config_path.load_full() else {
+            return Ok(());
+        };
+        let result = config.save_to_file(path.as_str());
+        let mut status = self.save_status.lock().unwrap();
+        match result {
+            Ok(()) => {
+                *status = SaveStatus {
+                    last_saved_at: Some(Utc::now()),
+                    ..SaveStatus::default()
+                };
+                Ok(())
+            }
+            Err(e) => {
+                let message = format!("{:#}", e);
+                tracing::error!(path = %path, "Failed to save configuration: {}", message);
+                status.dirty = true;
+                status.last_save_error = Some(message.clone());
+                status.last_save_error_at = Some(Utc::now());
+                Err(ConfigSaveError { message }.into())
+            }
+        }
+    }
+
+    /// Whether the running configuration has been saved to the config file.
+    pub fn save_status(&self) -> SaveStatus {
+        self.save_status.lock().unwrap().clone()
+    }
+
+    /// Save the running configuration to the config file again, e.g. after a
+    /// failed save once the file system is writable.
+    pub async fn flush(&self) -> anyhow::Result<SaveStatus> {
+        let _edit = self.begin_edit(None).await;
+        let config = self.config.read().await;
+        self.save(&config)?;
+        Ok(self.save_status())
+    }
+
+    /// Get current configuration.
+    pub async fn get(&self) -> Config {
+        self.config.read().await.clone()
+    }
+
+    /// Update configuration and optionally save to file.
+    pub async fn update(&self, mut config: Config) -> anyhow::Result<()> {
+        let mut current = self.config.write().await;
+        // Edited or switched profiles win over the live sections
+        if config.changed_sections(&current).contains(&"profiles") {
+            config.apply_active_profile();
+        }
+        let changed = config.changed_sections(&current);
+        self.bandwidth.set_rate(config.limits.global_bandwidth);
+        self.publish_access(&config.access_control);
+        self.upstreams.sync(&config.upstream);
+        if config.auth != current.auth {
+            self.authenticator
+                .store(Arc::new(build_authenticator(&config.auth)));
+        }
+        *current = config;
+        self.persist(&mut current, &changed)
+    }
+
+    /// Apply [`Config::migrate_allowed_ips`] to the running configuration
+    /// and save the result, so the whitelist is enforced from now on.
+    ///
+    /// The migration stays in effect when saving fails; the failure is
+    /// logged and reported by [`save_status`](Self::save_status).
+    pub async fn migrate_allowed_ips(&self) -> Option<AllowedIpsMigration> {
+        let _edit = self.begin_edit(None).await;
+        let mut config = self.get().await;
+        let migration = config.migrate_allowed_ips()?;
+        let _ = self.update(config).await;
+        Some(migration)
+    }
+
+    /// Server-wide bandwidth limiter shared by all relays.
+    pub fn bandwidth_limiter(&self) -> &Arc<BandwidthLimiter> {
+        &self.bandwidth
+    }
+
+    /// Get connection limits.
+    pub async fn get_limits(&self) -> LimitsConfig {
+        let config = self.config.read().await;
+        config.limits.clone()
+    }
+
+    /// Update connection limits; the global bandwidth cap applies to running relays immediately.
+    pub async fn update_limits(&self, limits: LimitsConfig) -> anyhow::Result<()> {
+        let mut config = self.config.write().await;
+        config.limits = limits;
+        self.bandwidth.set_rate(config.limits.global_bandwidth);
+        self.persist(&mut config, &["limits"])
+    }
+
+    /// Configured profiles and the name of the active one.
+    pub async fn get_profiles(&self) -> (Option<String>, BTreeMap<String, ConfigProfile>) {
+        let config = self.config.read().await;
+        (config.active_profile.clone(), config.profiles.clone())
+    }
+
+    /// Switch to the profile `name`: its access control (and limits, if it
+    /// has any) replace the live ones.
+    ///
+    /// Returns `Ok(false)` if there is no such profile.
+    pub async fn activate_profile(&self, name: &str) -> anyhow::Result<bool> {
+        let mut config = self.config.write().await;
+        if !config.profiles.contains_key(name) {
+            return Ok(false);
+        }
+        config.active_profile = Some(name.to_string());
+        config.apply_active_profile();
+        self.publish_access(&config.access_control);
+        self.bandwidth.set_rate(config.limits.global_bandwidth);
+        let sections: &[&str] = if config.profiles[name].limits.is_some() {
+            &["profiles", "access_control", "limits"]
+        } else {
+            &["profiles", "access_control"]
+        };
+        self.persist(&mut config, sections)?;
+        Ok(true)
+    }
+
+    /// Path of the config file, `None` when running without one.
+    pub fn config_path(&self) -> Option<String> {
+        self.config_path.load().as_deref().cloned()
+    }
+
+    /// Save later changes to `path` (or stop saving them with `None`).
+    ///
+    /// Nothing is written until the next change; [`flush`](Self::flush)
+    /// writes the running configuration right away.
+    pub fn set_config_path(&self, path: Option<String>) {
+        self.config_path.store(path.map(Arc::new));
+    }
+
+    /// Whether changes are saved to a config file.
+    pub fn is_persistent(&self) -> bool {
+        self.config_path.load().is_some()
+    }
+
+    /// Save a timestamped copy of the current configuration next to the config file.
+    ///
+    /// Returns the backup path, or `None` when running without a config file.
+    pub async fn backup(&self, label: &str) -> anyhow::Result<Option<PathBuf>> {
+        let Some(path) = self.config_path() else {
+            return Ok(None);
+        };
+        let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ");
+        let backup_path = PathBuf::from(format!("{}.{}-{}.bak", path, label, timestamp));
+        self.config.read().await.save_to_file(&backup_path)?;
+        Ok(Some(backup_path))
+    }
+
+    /// Update access control rules only.
+    pub async fn update_access_control(
+        &self,
+        access_control: AccessControlConfig,
+    ) -> anyhow::Result<()> {
+        let mut config = self.config.write().await;
+        config.access_control = access_control;
+        self.publish_access(&config.access_control);
+        self.persist(&mut config, &["access_control"])?;
+        Ok(())
+    }
+
+    /// Remove rules that expired more than [`EXPIRED_RULE_RETENTION`] ago,
+    /// returning how many were removed.
+    pub async fn remove_expired_rules(&self) -> anyhow::Result<usize> {
+        let cutoff = Utc::now() - EXPIRED_RULE_RETENTION;
+        let _edit = self.begin_edit(None).await;
+        let mut config = self.config.write().await;
+        let before = config.access_control.rules.len();
+        config
+            .access_control
+            .rules
+            .retain(|rule| !rule.is_expired(cutoff));
+        let removed = before - config.access_control.rules.len();
+        if removed > 0 {
+            self.publish_access(&config.access_control);
+            self.persist(&mut config, &["access_control"])?;
+        }
+        Ok(removed)
+    }
+
+    /// Compile `access_control` for the connection checks and drop the
+    /// decisions of the previous rules.
+    fn publish_access(&self, access_control: &AccessControlConfig) {
+        self.access
+            .store(Arc::new(AccessMatcher::new(access_control)));
+        self.decisions.invalidate();
+    }
+
+    /// Check if an IP is allowed.
+    pub async fn is_ip_allowed(&self, ip: &str) -> bool {
+        !self.is_temporarily_banned(ip) && self.access.load().is_ip_allowed(ip)
+    }
+
+    fn is_temporarily_banned(&self, ip: &str) -> bool {
+        let bans = self.bans.load();
+        if bans.is_empty() {
+            return false;
+        }
+        ip.parse::<IpAddr>()
+            .ok()
+            .and_then(|addr| bans.get(&addr.to_canonical()))
+            .is_some_and(|until| *until > Utc::now())
+    }
+
+    /// Block a client IP.
+    ///
+    /// Without a duration the IP is appended to `access_control.ip_blacklist`
+    /// and saved. With one, the ban is kept in memory until it expires, which
+    /// is returned.
+    pub async fn ban_ip(
+        &self,
+        ip: &str,
+        duration: Option<std::time::Duration>,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let addr = ip
+            .parse::<IpAddr>()
+            .map_err(|_| anyhow::anyhow!("Invalid IP address: {}", ip))?
+            .to_canonical();
+
+        if let Some(duration) = duration {
+            let until = Utc::now() + chrono::Duration::from_std(duration)?;
+            self.bans.rcu(|bans| {
+                let now = Utc::now();
+                let mut bans: HashMap<_, _> = bans
+                    .iter()
+                    .filter(|(_, expires)| **expires > now)
+                    .map(|(ip, expires)| (*ip, *expires))
+                    .collect();
+                bans.insert(addr, until);
+                bans
+            });
+            return Ok(Some(until));
+        }
+
+        let mut config = self.config.write().await;
+        let entry = addr.to_string();
+        if !config.access_control.ip_blacklist.contains(&entry) {
+            config.access_control.ip_blacklist.push(entry);
+            self.publish_access(&config.access_control);
+            self.persist(&mut config, &["access_control"])?;
+        }
+        Ok(None)
+    }
+
+    /// Active temporary bans and when they expire.
+    pub fn temporary_bans(&self) -> Vec<(IpAddr, DateTime<Utc>)> {
+        let now = Utc::now();
+        let mut bans: Vec<_> = self
+            .bans
+            .load()
+            .iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(ip, until)| (*ip, *until))
+            .collect();
+        bans.sort_by_key(|(_, until)| *until);
+        bans
+    }
+
+    /// Check if a target (domain + port + optional path) is allowed.
+    pub async fn is_target_allowed(&self, host: &str, port: u16, path: Option<&str>) -> bool {
+        self.access.load().is_target_allowed(host, Some(port), path)
+    }
+
+    /// Check a target, naming the rule that blocked it.
+    pub async fn check_target(
+        &self,
+        host: &str,
+        port: u16,
+        path: Option<&str>,
+    ) -> std::result::Result<(), TargetBlock> {
+        self.access.load().check_target(host, Some(port), path)
+    }
+
+    /// Check a target, reporting monitored rules that would have blocked it.
+    pub async fn evaluate_target(&self, host: &str, port: u16, path: Option<&str>) -> TargetCheck {
+        self.access.load().evaluate_target(host, Some(port), path)
+    }
+
+    /// Like [`evaluate_target`](Self::evaluate_target), reusing the decision
+    /// for the same client, target and user for a few seconds (see
+    /// [`DecisionCache`]). Returns the decision and whether it was cached.
+    pub fn evaluate_target_cached(
+        &self,
+        client: IpAddr,
+        host: &str,
+        port: u16,
+        username: Option<&str>,
+    ) -> (TargetCheck, bool) {
+        self.decisions
+            .evaluate(client, host, port, username, || self.access.load_full())
+    }
+
+    /// Text for the body of HTTP proxy responses to blocked targets.
+    pub async fn block_message(&self) -> Option<String> {
+        let config = self.config.read().await;
+        config.access_control.block_message.clone()
+    }
+
+    /// Check a target whose port is unknown; rules with `ports` don't apply.
+    #[deprecated(note = "pass the target port to `is_target_allowed`")]
+    pub async fn is_host_allowed(&self, host: &str, path: Option<&str>) -> bool {
+        self.access.load().is_target_allowed(host, None, path)
+    }
+
+    /// Get the upstream relay configuration if forwarding is enabled.
+    pub async fn get_upstream(&self) -> Option<UpstreamConfig> {
+        let config = self.config.read().await;
+        Some(config.upstream.clone()).filter(|u| u.enabled)
+    }
+
+    /// Health and failover state of the upstream relays.
+    pub fn upstream_pool(&self) -> &Arc<UpstreamPool> {
+        &self.upstreams
+    }
+
+    /// Find the trusted downstream matching the given credentials.
+    pub async fn verify_downstream(&self, name: &str, secret: &str) -> bool {
+        let config = self.config.read().await;
+        config
+            .server
+            .trusted_downstreams
+            .iter()
+            .any(|d| d.name == name && constant_time_eq(d.secret.as_bytes(), secret.as_bytes()))
+    }
+
+    /// Resolve a target host using the configured `[dns]` mode.
+    pub async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>> {
+        let dns = self.config.read().await.dns.clone();
+        self.resolver.resolve(host, &dns).await
+    }
+
+    /// Pick the local address to bind a connection to `target` to (`None` = chosen by the OS).
+    pub async fn egress_address(
+        &self,
+        target: IpAddr,
+        username: Option<&str>,
+        client_ip: IpAddr,
+    ) -> Option<IpAddr> {
+        let config = self.config.read().await;
+        self.egress
+            .select(&config.egress, target, username, client_ip)
+    }
+
+    /// Check if client addresses are enriched with reverse-DNS names.
+    pub async fn resolves_client_hostnames(&self) -> bool {
+        let config = self.config.read().await;
+        config.stats.resolve_client_hostnames
+    }
+
+    /// Cached reverse-DNS result for a client IP: `Some(None)` if the lookup failed.
+    pub fn cached_client_hostname(&self, ip: IpAddr) -> Option<Option<String>> {
+        self.resolver.cached_reverse(ip.to_canonical())
+    }
+
+    /// Look up the reverse-DNS name of a client IP using the configured `[dns]` mode.
+    pub async fn client_hostname(&self, ip: IpAddr) -> Option<String> {
+        let dns = self.config.read().await.dns.clone();
+        self.resolver.reverse(ip, &dns).await
+    }
+
+    /// Get target resolver statistics.
+    pub async fn dns_stats(&self) -> DnsStats {
+        let config = self.config.read().await;
+        self.resolver.stats(&config.dns)
+    }
+
+    /// Check if TLS SNI inspection is enabled.
+    pub async fn is_sni_inspection_enabled(&self) -> bool {
+        let config = self.config.read().await;
+        config.access_control.inspect_sni
+    }
+
+    /// Check if outbound connections to a target should start with a PROXY protocol header.
+    pub async fn wants_proxy_protocol(&self, host: &str) -> bool {
+        let config = self.config.read().await;
+        config
+            .access_control
+            .proxy_protocol_targets
+            .iter()
+            .any(|pattern| domain_matches(host, pattern))
+    }
+
+    /// Check if authentication is required.
+    pub async fn is_auth_enabled(&self) -> bool {
+        let config = self.config.read().await;
+        config.security.auth_enabled
+    }
+
+    /// Get the synthetic username for a client IP in `security.auth_exempt_ips`.
+    pub async fn auth_exempt_user(&self, ip: &str) -> Option<String> {
+        let config = self.config.read().await;
+        config.security.auth_exempt_user(ip)
+    }
+
+    /// How SOCKS5 auth methods are negotiated with a client at `ip`.
+    pub async fn socks5_auth_policy(&self, ip: &str) -> AuthPolicy {
+        let config = self.config.read().await;
+        AuthPolicy::new(&config.security, ip)
+    }
+
+    /// Length limits of SOCKS5 credentials.
+    pub async fn credential_limits(&self) -> CredentialLimits {
+        let config = self.config.read().await;
+        config.security.credential_limits
+    }
+
+    /// Authenticate a user. Returns the username if successful.
+    pub async fn authenticate(&self, username: &str, password: &str) -> Option<String> {
+        let config = self.config.read().await;
+        config.security.authenticate(username, password)
+    }
+
+    /// Password of an enabled user, see [`SecurityConfig::password_of`].
+    pub async fn password_of(&self, username: &str) -> Option<String> {
+        let config = self.config.read().await;
+        config.security.password_of(username).map(str::to_string)
+    }
+
+    /// Authenticate a proxy client: `security.users` (and tokens) first, then
+    /// the `auth` backend. Limits not set by the backend come from the user's
+    /// configuration.
+    pub async fn authenticate_client(
+        &self,
+        request: &AuthRequest<'_>,
+    ) -> Option<AuthenticatedUser> {
+        let user = match self.authenticate(request.username, request.password).await {
+            Some(username) => AuthenticatedUser::new(username),
+            None => {
+                let authenticator = self.authenticator.load_full();
+                let authenticator = authenticator.as_ref().as_ref()?;
+                authenticator.authenticate(request).await?
+            }
+        };
+        let limits = self.session_limits(&user.username, user.limits).await;
+        Some(AuthenticatedUser { limits, ..user })
+    }
+
+    /// Fill the limits `overrides` leaves unset from `username`'s configuration.
+    pub async fn session_limits(&self, username: &str, overrides: SessionLimits) -> SessionLimits {
+        let user = self.get_user(username).await;
+        SessionLimits {
+            bandwidth_limit: overrides
+                .bandwidth_limit
+                .or(user.as_ref().map(|u| u.bandwidth_limit)),
+            connection_limit: overrides
+                .connection_limit
+                .or(user.as_ref().map(|u| u.connection_limit)),
+            max_connection_lifetime_secs: overrides
+                .max_connection_lifetime_secs
+                .or(user.as_ref().and_then(|u| u.max_connection_lifetime_secs)),
+        }
+    }
+
+    /// Bandwidth limiter shared by `username`'s connections, set to `rate`
+    /// bytes per second (`None` for 0 = unlimited).
+    ///
+    /// The limiter lives as long as the user has relays holding it.
+    pub fn user_bandwidth_limiter(
+        &self,
+        username: &str,
+        rate: u64,
+    ) -> Option<Arc<BandwidthLimiter>> {
+        if rate == 0 {
+            return None;
+        }
+        let mut limiters = self.user_limiters.lock().unwrap();
+        if let Some(limiter) = limiters.get(username).and_then(Weak::upgrade) {
+            limiter.set_rate(rate);
+            return Some(limiter);
+        }
+        limiters.retain(|_, limiter| limiter.strong_count() > 0);
+        let limiter = Arc::new(BandwidthLimiter::new(rate));
+        limiters.insert(username.to_string(), Arc::downgrade(&limiter));
+        Some(limiter)
+    }
+
+    /// Authenticate a proxy token. Returns the owning username if successful.
+    pub async fn authenticate_token(&self, token: &str) -> Option<String> {
+        let config = self.config.read().await;
+        config.security.authenticate_token(token)
+    }
+
+    /// Create a token for `username`, returning its record and the token itself
+    /// (`None` when the user doesn't exist).
+    pub async fn create_user_token(
+        &self,
+        username: &str,
+        label: Option<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Option<(UserToken, String)>> {
+        let mut config = self.config.write().await;
+        let Some(user) = config
+            .security
+            .users
+            .iter_mut()
+            .find(|u| u.username == username)
+        else {
+            return Ok(None);
+        };
+        let (record, token) = UserToken::generate(label, expires_at)?;
+        user.tokens.push(record.clone());
+        self.persist(&mut config, &["security"])?;
+        Ok(Some((record, token)))
+    }
+
+    /// Revoke token `id` of `username`. Returns whether it existed.
+    pub async fn revoke_user_token(&self, username: &str, id: &str) -> anyhow::Result<bool> {
+        let mut config = self.config.write().await;
+        let Some(user) = config
+            .security
+            .users
+            .iter_mut()
+            .find(|u| u.username == username)
+        else {
+            return Ok(false);
+        };
+        let before = user.tokens.len();
+        user.tokens.retain(|t| t.id != id);
+        if user.tokens.len() == before {
+            return Ok(false);
+        }
+        self.persist(&mut config, &["security"])?;
+        Ok(true)
+    }
+
+    /// Find an enabled user by name.
+    pub async fn get_user(&self, username: &str) -> Option<User> {
+        let config = self.config.read().await;
+        config
+            .security
+            .users
+            .iter()
+            .find(|u| u.enabled && u.username == username)
+            .cloned()
+    }
+
+    /// Check if in-flight connections are closed when a quota runs out.
+    pub async fn is_quota_cutoff_active(&self) -> bool {
+        let config = self.config.read().await;
+        config.limits.quota_cutoff_active
+    }
+
+    /// Get security configuration.
+    pub async fn get_security(&self) -> SecurityConfig {
+        let config = self.config.read().await;
+        config.security.clone()
+    }
+
+    /// Get the HTTP proxy response configuration.
+    pub async fn get_http_proxy(&self) -> HttpProxyConfig {
+        let config = self.config.read().await;
+        config.http_proxy.clone()
+    }
+
+    /// Password rules for passwords set through the API.
+    pub async fn password_policy(&self) -> PasswordPolicy {
+        let config = self.config.read().await;
+        config.security.password_policy.clone()
+    }
+
+    /// Update security configuration.
+    pub async fn update_security(&self, security: SecurityConfig) -> anyhow::Result<()> {
+        let mut config = self.config.write().await;
+        config.security = security;
+        self.persist(&mut config, &["security"])?;
+        Ok(())
+    }
+
+    /// Get debugging settings.
+    pub async fn get_alerts(&self) -> AlertsConfig {
+        let config = self.config.read().await;
+        config.alerts.clone()
+    }
+
+    pub async fn get_debug(&self) -> DebugConfig {
+        let config = self.config.read().await;
+        config.debug.clone()
+    }
+
+    /// Get statistics configuration.
+    pub async fn get_stats(&self) -> StatsConfig {
+        let config = self.config.read().await;
+        config.stats.clone()
+    }
+
+    /// Update statistics configuration.
+    pub async fn update_stats(&self, stats: StatsConfig) -> anyhow::Result<()> {
+        let mut config = self.config.write().await;
+        config.stats = stats;
+        self.persist(&mut config, &["stats"])?;
+        Ok(())
+    }
+
+    /// Get dashboard configuration.
+    pub async fn get_dashboard(&self) -> DashboardConfig {
+        let config = self.config.read().await;
+        config.dashboard.clone()
+    }
+
+    /// Update dashboard configuration; settings failing
+    /// [`DashboardConfig::validate`] are refused without changing anything.
+    ///
+    /// Sessions and the API middlewares read the new settings from the next
+    /// request on.
+    pub async fn update_dashboard(&self, dashboard: DashboardConfig) -> anyhow::Result<()> {
+        dashboard.validate()?;
+        let mut config = self.config.write().await;
+        config.dashboard = dashboard;
+        self.persist(&mut config, &["dashboard"])?;
+        Ok(())
+    }
+
+    /// Add the first dashboard administrator and turn on dashboard
+    /// authentication.
+    ///
+    /// Returns `false` without changing anything once any administrator
+    /// exists, so the setup can't be repeated.
+    pub async fn complete_setup(&self, admin: DashboardUser) -> anyhow::Result<bool> {
+        let mut config = self.config.write().await;
+        if config.dashboard.has_admins() {
+            return Ok(false);
+        }
+        config.dashboard.users.push(admin);
+        config.dashboard.auth_enabled = true;
+        self.persist(&mut config, &["dashboard"])?;
+        Ok(true)
+    }
+
+    /// Check if a client IP may reach the dashboard.
+    pub async fn is_dashboard_ip_allowed(&self, ip: &str) -> bool {
+        let config = self.config.read().await;
+        let allowed = &config.dashboard.allowed_ips;
+        allowed.is_empty() || ip_in_list(ip, allowed)
+    }
+
+    /// Check if browser scripts from `origin` may call the API.
+    pub async fn is_dashboard_origin_allowed(&self, origin: &str) -> bool {
+        let config = self.config.read().await;
+        config
+            .dashboard
+            .allowed_origins
+            .iter()
+            .any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin))
+    }
+
+    /// How long a dashboard session stays valid after login.
+    pub async fn dashboard_session_ttl(&self) -> std::time::Duration {
+        let config = self.config.read().await;
+        config.dashboard.session_ttl()
+    }
+
+    /// Check if dashboard authentication is enabled.
+    pub async fn is_dashboard_auth_enabled(&self) -> bool {
+        let config = self.config.read().await;
+        config.dashboard.auth_enabled
+    }
+
+    /// Check a bearer token against `dashboard.metrics_token`.
+    pub async fn verify_metrics_token(&self, token: &str) -> bool {
+        let config = self.config.read().await;
+        config
+            .dashboard
+            .metrics_token
+            .as_deref()
+            .is_some_and(|expected| constant_time_eq(expected.as_bytes(), token.as_bytes()))
+    }
+
+    /// Authenticate for dashboard access.
+    pub async fn authenticate_dashboard(&self, username: &str, password: &str) -> bool {
+        let config = self.config.read().await;
+        config.dashboard.authenticate(username, password)
+    }
+
+    /// Get server configuration.
+    pub async fn get_server(&self) -> ServerConfig {
+        let config = self.config.read().await;
+        config.server.clone()
+    }
+
+    /// Update server configuration.
+    pub async fn update_server(&self, server: ServerConfig) -> anyhow::Result<()> {
+        let mut config = self.config.write().await;
+        let previous = std::mem::replace(&mut config.server, server);
+        if let Err(e) = config.check_port_conflicts() {
+            config.server = previous;
+            return Err(e);
+        }
+        self.persist(&mut config, &["server"])?;
+        Ok(())
+    }
+}
+
+/// Server binding configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerConfig {
+    /// Address to bind: an IP address, or a host name whose addresses are all bound.
+    #[serde(default = "default_host")]
+    pub host: String,
+
+    /// SOCKS5 proxy port.
+    #[serde(default = "default_socks_port")]
+    pub socks_port: u16,
+
+    /// HTTP proxy port.
+    #[serde(default = "default_http_port")]
+    pub http_port: u16,
+
+    /// API/Dashboard port.
+    #[serde(default = "default_api_port")]
+    pub api_port: u16,
+
+    /// Address for the API/Dashboard, IP or host name (defaults to `host`).
+    #[serde(default)]
+    pub api_host: Option<String>,
+
+    /// Start the SOCKS5 proxy.
+    #[serde(default = "default_true")]
+    pub socks_enabled: bool,
+
+    /// Start the HTTP CONNECT proxy.
+    #[serde(default = "default_true")]
+    pub http_enabled: bool,
+
+    /// Start the API/Dashboard.
+    #[serde(default = "default_true")]
+    pub api_enabled: bool,
+
+    /// Port serving both SOCKS5 and HTTP proxy clients, told apart by their
+    /// first bytes (disabled if unset).
+    #[serde(default)]
+    pub combined_port: Option<u16>,
+
+    /// Port for relay-to-relay tunnels from trusted downstream instances (disabled if unset).
+    #[serde(default)]
+    pub tunnel_port: Option<u16>,
+
+    /// PEM certificate chain for the tunnel listener.
+    #[serde(default)]
+    pub tunnel_tls_cert: Option<String>,
+
+    /// PEM private key for the tunnel listener.
+    #[serde(default)]
+    pub tunnel_tls_key: Option<String>,
+
+    /// PEM certificate chain for serving the API/Dashboard over HTTPS.
+    #[serde(default)]
+    pub api_tls_cert: Option<String>,
+
+    /// PEM private key for the API/Dashboard.
+    #[serde(default)]
+    pub api_tls_key: Option<String>,
+
+    /// PEM file with the CAs whose client certificates the API/Dashboard accepts.
+    #[serde(default)]
+    pub api_client_ca_path: Option<String>,
+
+    /// Reject API/Dashboard TLS handshakes without a valid client certificate.
+    #[serde(default)]
+    pub api_require_client_cert: bool,
+
+    /// `/api/health` reports TLS certificates expiring within this many days.
+    #[serde(default = "default_tls_expiry_warning_days")]
+    pub tls_expiry_warning_days: u32,
+
+    /// Pending connections each listener queues before the OS drops new
+    /// SYNs (capped by the kernel, e.g. `net.core.somaxconn` on Linux).
+    #[serde(default = "default_listen_backlog")]
+    pub listen_backlog: u32,
+
+    /// `SO_RCVBUF` of the listening sockets in bytes, inherited by accepted
+    /// connections (OS default when unset).
+    #[serde(default)]
+    pub listen_recv_buffer: Option<u32>,
+
+    /// `SO_SNDBUF` of the listening sockets in bytes (OS default when unset).
+    #[serde(default)]
+    pub listen_send_buffer: Option<u32>,
+
+    /// Downstream relay instances allowed to forward traffic through this one.
+    #[serde(default)]
+    pub trusted_downstreams: Vec<TrustedDownstream>,
+}
+
+/// A downstream relay allowed to open tunnels to this instance.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrustedDownstream {
+    /// Node name the downstream presents.
+    pub name: String,
+
+    /// Shared secret the downstream presents.
+    pub secret: String,
+}
+
+impl ServerConfig {
+    /// Host address the API/Dashboard binds to.
+    pub fn api_host(&self) -> &str {
+        self.api_host.as_deref().unwrap_or(&self.host)
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: default_host(),
+            socks_port: default_socks_port(),
+            http_port: default_http_port(),
+            api_port: default_api_port(),
+            api_host: None,
+            socks_enabled: true,
+            http_enabled: true,
+            api_enabled: true,
+            combined_port: None,
+            tunnel_port: None,
+            tunnel_tls_cert: None,
+            tunnel_tls_key: None,
+            api_tls_cert: None,
+            api_tls_key: None,
+            api_client_ca_path: None,
+            api_require_client_cert: false,
+            tls_expiry_warning_days: default_tls_expiry_warning_days(),
+            listen_backlog: default_listen_backlog(),
+            listen_recv_buffer: None,
+            listen_send_buffer: None,
+            trusted_downstreams: Vec::new(),
+        }
+    }
+}
+
+fn default_listen_backlog() -> u32 {
+    1024
+}
+
+fn default_host() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn default_socks_port() -> u16 {
+    1080
+}
+
+fn default_http_port() -> u16 {
+    8080
+}
+
+fn default_api_port() -> u16 {
+    3000
+}
+
+fn default_tls_expiry_warning_days() -> u32 {
+    14
+}
+
+/// Logging configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// Log level.
+    #[serde(default = "default_log_level")]
+    pub level: String,
+
+    /// Log file path (optional).
+    pub file: Option<String>,
+
+    /// Per-connection access log path (optional, rotated per `rotation`).
+    #[serde(default)]
+    pub access_log: Option<String>,
+
+    /// Access log line format.
+    #[serde(default)]
+    pub access_log_format: AccessLogFormat,
+
+    /// Rotation of the access log and the event log (`stats.event_log`).
+    #[serde(default)]
+    pub rotation: LogRotation,
+
+    /// Application log output to syslog.
+    #[serde(default)]
+    pub syslog: SyslogConfig,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: default_log_level(),
+            file: None,
+            access_log: None,
+            access_log_format: AccessLogFormat::default(),
+            rotation: LogRotation::default(),
+            syslog: SyslogConfig::default(),
+        }
+    }
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+/// Syslog output of the application log (`logging.syslog`).
+///
+/// Read at startup only.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyslogConfig {
+    /// Send log messages to syslog, in addition to the console and file.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Facility messages are logged under.
+    #[serde(default)]
+    pub facility: SyslogFacility,
+
+    /// Remote syslog server as `udp://host:port` or `tcp://host:port`, or a
+    /// local socket as `unix:///path` (defaults to `unix:///dev/log`).
+    #[serde(default)]
+    pub address: Option<String>,
+
+    /// Application name in each message.
+    #[serde(default = "default_syslog_app_name")]
+    pub app_name: String,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            facility: SyslogFacility::default(),
+            address: None,
+            app_name: default_syslog_app_name(),
+        }
+    }
+}
+
+fn default_syslog_app_name() -> String {
+    "net-relay".to_string()
+}
+
+impl SyslogConfig {
+    /// Where messages are sent, parsed from `address`.
+    pub fn transport(&self) -> anyhow::Result<SyslogTransport> {
+        let Some(address) = self.address.as_deref() else {
+            return Ok(SyslogTransport::Unix(PathBuf::from("/dev/log")));
+        };
+        let (scheme, rest) = address.split_once("://").ok_or_else(|| {
+            anyhow::anyhow!(
+                "logging.syslog: address must start with udp://, tcp:// or unix://, got '{}'",
+                address
+            )
+        })?;
+        if rest.is_empty() {
+            anyhow::bail!("logging.syslog: address '{}' has no destination", address);
+        }
+        match scheme {
+            "udp" => Ok(SyslogTransport::Udp(rest.to_string())),
+            "tcp" => Ok(SyslogTransport::Tcp(rest.to_string())),
+            "unix" => Ok(SyslogTransport::Unix(PathBuf::from(rest))),
+            _ => anyhow::bail!(
+                "logging.syslog: unsupported scheme '{}' (expected udp, tcp or unix)",
+                scheme
+            ),
+        }
+    }
+}
+
+/// Transport of syslog messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogTransport {
+    /// Datagrams to a remote server (`host:port`).
+    Udp(String),
+    /// Octet-counted frames (RFC 6587) over a TCP connection (`host:port`).
+    Tcp(String),
+    /// Datagrams to a local socket such as `/dev/log`.
+    Unix(PathBuf),
+}
+
+/// Syslog facility (RFC 5424).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFacility {
+    User,
+    #[default]
+    Daemon,
+    Auth,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    /// Numeric facility code.
+    pub fn code(self) -> u8 {
+        match self {
+            SyslogFacility::User => 1,
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Auth => 4,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+            SyslogFacility::Local2 => 18,
+            SyslogFacility::Local3 => 19,
+            SyslogFacility::Local4 => 20,
+            SyslogFacility::Local5 => 21,
+            SyslogFacility::Local6 => 22,
+            SyslogFacility::Local7 => 23,
+        }
+    }
+}
+
+/// Trusted upstream relay configuration.
+///
+/// When enabled, every proxied connection is forwarded to the upstream
+/// net-relay over TLS instead of connecting to the target directly. The
+/// relay at `address` can be backed by further `relays`; connections use
+/// the most preferred healthy one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamConfig {
+    /// Forward connections through the upstream relay.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Upstream tunnel address (host:port).
+    #[serde(default)]
+    pub address: String,
+
+    /// TLS server name to verify (defaults to the host part of `address`).
+    #[serde(default)]
+    pub server_name: Option<String>,
+
+    /// PEM file with CA certificates to trust (defaults to the public web PKI roots).
+    #[serde(default)]
+    pub ca_file: Option<String>,
+
+    /// Node name presented to the upstream.
+    #[serde(default)]
+    pub node_name: String,
+
+    /// Node secret presented to the upstream.
+    #[serde(default)]
+    pub node_secret: String,
+
+    /// Priority of the relay at `address` (lower is preferred).
+    #[serde(default)]
+    pub priority: u32,
+
+    /// Further relays to fail over to.
+    #[serde(default)]
+    pub relays: Vec<UpstreamRelay>,
+
+    /// Seconds between health checks of each relay.
+    #[serde(default = "default_health_check_interval")]
+    pub health_check_interval: u64,
+
+    /// Seconds a health check may take before the relay counts as down.
+    #[serde(default = "default_health_check_timeout")]
+    pub health_check_timeout: u64,
+
+    /// Target (host:port) opened through each relay as a health check;
+    /// a plain TCP connect to the relay when unset.
+    #[serde(default)]
+    pub probe_target: Option<String>,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: String::new(),
+            server_name: None,
+            ca_file: None,
+            node_name: String::new(),
+            node_secret: String::new(),
+            priority: 0,
+            relays: Vec::new(),
+            health_check_interval: default_health_check_interval(),
+            health_check_timeout: default_health_check_timeout(),
+            probe_target: None,
+        }
+    }
+}
+
+impl UpstreamConfig {
+    /// Every configured relay as a single-relay config, most preferred first.
+    ///
+    /// Relays inherit `ca_file`, `node_name` and `node_secret` when they
+    /// don't set their own; equal priorities keep configuration order.
+    pub fn endpoints(&self) -> Vec<UpstreamConfig> {
+        let single = |address: &str, priority: u32| UpstreamConfig {
+            address: address.to_string(),
+            priority,
+            relays: Vec::new(),
+            ..self.clone()
+        };
+        let mut endpoints = Vec::with_capacity(self.relays.len() + 1);
+        if !self.address.is_empty() {
+            endpoints.push(single(&self.address, self.priority));
+        }
+        for relay in &self.relays {
+            let mut endpoint = single(&relay.address, relay.priority);
+            endpoint.server_name = relay.server_name.clone();
+            if relay.ca_file.is_some() {
+                endpoint.ca_file = relay.ca_file.clone();
+            }
+            if let Some(node_name) = &relay.node_name {
+                endpoint.node_name = node_name.clone();
+            }
+            if let Some(node_secret) = &relay.node_secret {
+                endpoint.node_secret = node_secret.clone();
+            }
+            endpoints.push(endpoint);
+        }
+        endpoints.sort_by_key(|endpoint| endpoint.priority);
+        endpoints
+    }
+}
+
+/// Additional upstream relay in `[[upstream.relays]]`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpstreamRelay {
+    /// Tunnel address (host:port).
+    pub address: String,
+
+    /// Priority (lower is preferred).
+    #[serde(default)]
+    pub priority: u32,
+
+    /// TLS server name to verify (defaults to the host part of `address`).
+    #[serde(default)]
+    pub server_name: Option<String>,
+
+    /// PEM file with CA certificates to trust (defaults to `upstream.ca_file`).
+    #[serde(default)]
+    pub ca_file: Option<String>,
+
+    /// Node name presented to this relay (defaults to `upstream.node_name`).
+    #[serde(default)]
+    pub node_name: Option<String>,
+
+    /// Node secret presented to this relay (defaults to `upstream.node_secret`).
+    #[serde(default)]
+    pub node_secret: Option<String>,
+}
+
+fn default_health_check_interval() -> u64 {
+    10
+}
+
+fn default_health_check_timeout() -> u64 {
+    5
+}
+
+/// How outbound connections are spread across `egress.addresses`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EgressStrategy {
+    /// Each connection takes the next address in turn.
+    #[default]
+    RoundRobin,
+    /// A random address per connection.
+    Random,
+    /// The same address for every connection of a user (by client IP when unauthenticated).
+    HashByUser,
+    /// The same address for every connection from a client IP.
+    HashByClientIp,
+}
+
+/// Local source addresses for outbound connections.
+///
+/// Only direct connections are bound; tunnels through an upstream relay use
+/// the upstream's egress settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EgressConfig {
+    /// Addresses to bind outbound connections to (empty = chosen by the OS).
+    #[serde(default)]
+    pub addresses: Vec<IpAddr>,
+
+    /// How connections are spread across `addresses`.
+    #[serde(default)]
+    pub strategy: EgressStrategy,
+}
+
+/// Where proxy credentials not found in `security` are checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthBackend {
+    /// Only `security.users` (and their tokens).
+    #[default]
+    Config,
+    /// An HTTP endpoint, see [`HttpCallbackConfig`].
+    HttpCallback,
+}
+
+/// What happens to a login while the authentication backend is unreachable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnavailablePolicy {
+    /// Reject the login (fail closed).
+    #[default]
+    Deny,
+    /// Accept the login with the configured limits (fail open).
+    Allow,
+}
+
+/// External authentication for proxy clients.
+///
+/// Users in `security.users` are always checked first; the backend is only
+/// asked about credentials they don't match.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// Backend checked after `security.users`.
+    #[serde(default)]
+    pub backend: AuthBackend,
+
+    /// Settings of the `http_callback` backend.
+    #[serde(default)]
+    pub http_callback: HttpCallbackConfig,
+}
+
+/// `http_callback` backend: credentials are POSTed as JSON
+/// (`{username, password, client_ip, protocol}`) and a 200 response with
+/// `{"allow": true}` accepts them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HttpCallbackConfig {
+    /// Endpoint URL (`http://` or `https://`).
+    #[serde(default)]
+    pub url: String,
+
+    /// Request timeout in milliseconds.
+    #[serde(default = "default_callback_timeout_ms")]
+    pub timeout_ms: u64,
+
+    /// Header carrying `secret`, so the endpoint can tell the relay's requests apart.
+    #[serde(default = "default_callback_secret_header")]
+    pub secret_header: String,
+
+    /// Shared secret sent in `secret_header` (empty = header not sent).
+    #[serde(default)]
+    pub secret: String,
+
+    /// CA bundle for verifying an `https://` endpoint (default: webpki roots).
+    #[serde(default)]
+    pub ca_file: Option<String>,
+
+    /// How long accepted credentials are remembered, in seconds (0 = ask every time).
+    #[serde(default = "default_callback_cache_ttl")]
+    pub cache_ttl_secs: u64,
+
+    /// What to do when the endpoint can't be reached or fails.
+    #[serde(default)]
+    pub on_unavailable: UnavailablePolicy,
+}
+
+impl Default for HttpCallbackConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            timeout_ms: default_callback_timeout_ms(),
+            secret_header: default_callback_secret_header(),
+            secret: String::new(),
+            ca_file: None,
+            cache_ttl_secs: default_callback_cache_ttl(),
+            on_unavailable: UnavailablePolicy::default(),
+        }
+    }
+}
+
+fn default_callback_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_callback_secret_header() -> String {
+    "X-Net-Relay-Secret".to_string()
+}
+
+fn default_callback_cache_ttl() -> u64 {
+    60
+}
+
+/// How proxy targets are resolved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsMode {
+    /// Operating system resolver.
+    #[default]
+    System,
+    /// Plain DNS over UDP to `resolver`.
+    Udp,
+    /// DNS-over-HTTPS to the `resolver` URL.
+    Doh,
+    /// DNS-over-TLS to `resolver`.
+    Dot,
+}
+
+/// Target name resolution configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsConfig {
+    /// Resolution mode.
+    #[serde(default)]
+    pub mode: DnsMode,
+
+    /// Resolver address: `ip[:port]` for udp, `host[:port]` for dot, an https:// URL for doh.
+    #[serde(default)]
+    pub resolver: Option<String>,
+
+    /// IPs used to reach a DoH/DoT resolver without consulting system DNS.
+    #[serde(default)]
+    pub bootstrap: Vec<String>,
+
+    /// Retry with system DNS when the configured resolver fails.
+    #[serde(default)]
+    pub fallback: bool,
+
+    /// Lookup timeout in seconds.
+    #[serde(default = "default_dns_timeout")]
+    pub timeout_secs: u64,
+
+    /// Maximum number of cached names (0 disables caching).
+    #[serde(default = "default_dns_cache_size")]
+    pub cache_size: usize,
+
+    /// Lookups taking at least this many milliseconds are logged as slow (0 = off).
+    #[serde(default = "default_dns_slow_lookup_ms")]
+    pub slow_lookup_ms: u64,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            mode: DnsMode::System,
+            resolver: None,
+            bootstrap: Vec::new(),
+            fallback: false,
+            timeout_secs: default_dns_timeout(),
+            cache_size: default_dns_cache_size(),
+            slow_lookup_ms: default_dns_slow_lookup_ms(),
+        }
+    }
+}
+
+fn default_dns_timeout() -> u64 {
+    5
+}
+
+fn default_dns_cache_size() -> usize {
+    1024
+}
+
+fn default_dns_slow_lookup_ms() -> u64 {
+    500
+}
+
+/// ACME challenge type used to prove control of the dashboard domain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AcmeChallenge {
+    /// Token served over plain HTTP at `/.well-known/acme-challenge/`.
+    #[default]
+    #[serde(rename = "http-01")]
+    Http01,
+    /// Self-signed certificate presented on the API port via the `acme-tls/1` ALPN.
+    #[serde(rename = "tls-alpn-01")]
+    TlsAlpn01,
+}
+
+/// Automatic dashboard certificates (requires the `acme` build feature).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcmeConfig {
+    /// Obtain and renew the API/Dashboard certificate automatically.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Dashboard host name the certificate is issued for.
+    #[serde(default)]
+    pub domain: String,
+
+    /// Contact email registered with the CA.
+    #[serde(default)]
+    pub email: String,
+
+    /// ACME directory URL.
+    #[serde(default = "default_acme_directory")]
+    pub directory_url: String,
+
+    /// PEM file with CA certificates to trust for the directory (defaults to the public web PKI roots).
+    #[serde(default)]
+    pub ca_file: Option<String>,
+
+    /// Challenge type.
+    #[serde(default)]
+    pub challenge: AcmeChallenge,
+
+    /// Separate plain-HTTP port for http-01 challenges (defaults to the API port).
+    #[serde(default)]
+    pub http_port: Option<u16>,
+
+    /// Directory holding the account key and the issued certificate and key.
+    #[serde(default = "default_acme_state_dir")]
+    pub state_dir: String,
+
+    /// Renew this many days before the certificate expires.
+    #[serde(default = "default_acme_renew_days")]
+    pub renew_before_days: u32,
+}
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            domain: String::new(),
+            email: String::new(),
+            directory_url: default_acme_directory(),
+            ca_file: None,
+            challenge: AcmeChallenge::default(),
+            http_port: None,
+            state_dir: default_acme_state_dir(),
+            renew_before_days: default_acme_renew_days(),
+        }
+    }
+}
+
+fn default_acme_directory() -> String {
+    "https://acme-v02.api.letsencrypt.org/directory".to_string()
+}
+
+fn default_acme_state_dir() -> String {
+    "acme".to_string()
+}
+
+fn default_acme_renew_days() -> u32 {
+    30
+}
+
+/// Dashboard authentication configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DashboardConfig {
+    /// Enable dashboard authentication.
+    #[serde(default)]
+    pub auth_enabled: bool,
+
+    /// Administrators allowed to sign in, independent of the proxy users in `[security]`.
+    #[serde(default)]
+    pub users: Vec<DashboardUser>,
+
+    /// Single dashboard login (legacy, moved into `users` at startup).
+    #[serde(default)]
+    pub username: Option<String>,
+
+    /// Password of the single dashboard login (legacy, moved into `users` at startup).
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Reverse proxies in front of the API (IPs or CIDR ranges). Only requests
+    /// from these may set the client IP through forwarding headers.
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+
+    /// Header carrying the client IP as set by `trusted_proxies` (e.g.
+    /// `X-Real-IP`). When unset, `Forwarded` or `X-Forwarded-For` is used.
+    #[serde(default)]
+    pub real_ip_header: Option<String>,
+
+    /// Client IPs allowed to reach the dashboard and API (CIDR notation, empty = all).
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+
+    /// Bearer token that lets Prometheus scrape `/api/metrics` without a
+    /// dashboard session.
+    #[serde(default)]
+    pub metrics_token: Option<String>,
+
+    /// Origins (e.g. `https://portal.example.com`) whose scripts may call the
+    /// API with the dashboard session (empty = same-origin only).
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+
+    /// Send `Content-Security-Policy`, `X-Frame-Options`,
+    /// `X-Content-Type-Options` and `Referrer-Policy` headers.
+    #[serde(default = "default_true")]
+    pub security_headers: bool,
+
+    /// Origins allowed to embed the dashboard in a frame (empty = none).
+    #[serde(default)]
+    pub frame_ancestors: Vec<String>,
+
+    /// Content-Security-Policy replacing the built-in one.
+    #[serde(default)]
+    pub content_security_policy: Option<String>,
+
+    /// Per-client request limits on the API.
+    #[serde(default)]
+    pub rate_limit: ApiRateLimitConfig,
+
+    /// Seconds a dashboard session stays valid after login.
+    #[serde(default = "default_session_ttl_secs")]
+    pub session_ttl_secs: u64,
+}
+
+fn default_session_ttl_secs() -> u64 {
+    86400
+}
+
+/// Shortest allowed `dashboard.session_ttl_secs`.
+pub const MIN_SESSION_TTL_SECS: u64 = 300;
+
+/// Longest allowed `dashboard.session_ttl_secs` (30 days).
+pub const MAX_SESSION_TTL_SECS: u64 = 30 * 86400;
+
+/// Token-bucket limits on API requests, per client IP and route class.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiRateLimitConfig {
+    /// Apply the limits.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Everything not in another class (stats, connections, config reads and edits).
+    #[serde(default = "default_standard_rate")]
+    pub standard: RateLimit,
+
+    /// Costly endpoints: config export/import, metrics, runtime and anomaly reports.
+    #[serde(default = "default_expensive_rate")]
+    pub expensive: RateLimit,
+
+    /// Login, password changes and the first-run setup.
+    #[serde(default = "default_auth_rate")]
+    pub auth: RateLimit,
+}
+
+impl Default for ApiRateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            standard: default_standard_rate(),
+            expensive: default_expensive_rate(),
+            auth: default_auth_rate(),
+        }
+    }
+}
+
+/// A token bucket: `burst` requests at once, refilled at `per_minute`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+fn default_standard_rate() -> RateLimit {
+    RateLimit {
+        per_minute: 600,
+        burst: 100,
+    }
+}
+
+fn default_expensive_rate() -> RateLimit {
+    RateLimit {
+        per_minute: 30,
+        burst: 5,
+    }
+}
+
+fn default_auth_rate() -> RateLimit {
+    RateLimit {
+        per_minute: 10,
+        burst: 5,
+    }
+}
+
+impl Default for DashboardConfig {
+    fn default() -> Self {
+        Self {
+            auth_enabled: false,
+            users: Vec::new(),
+            username: None,
+            password: None,
+            trusted_proxies: Vec::new(),
+            real_ip_header: None,
+            allowed_ips: Vec::new(),
+            metrics_token: None,
+            allowed_origins: Vec::new(),
+            security_headers: true,
+            frame_ancestors: Vec::new(),
+            content_security_policy: None,
+            rate_limit: ApiRateLimitConfig::default(),
+            session_ttl_secs: default_session_ttl_secs(),
+        }
+    }
+}
+
+/// Minimum length accepted for new passwords.
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Dashboard logins that ship in documentation and examples, which anyone
+/// can try.
+pub const DEFAULT_CREDENTIALS: &[(&str, &str)] = &[
+    ("admin", "admin"),
+    ("admin", "password"),
+    ("admin", "changeme"),
+    ("admin", "your-secure-password"),
+    ("admin", "admin-secure-password"),
+];
+
+/// Why the dashboard is open to anyone who reaches it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DashboardSecurityWarning {
+    /// `dashboard.auth_enabled` is off.
+    AuthDisabled,
+    /// An administrator signs in with one of [`DEFAULT_CREDENTIALS`].
+    DefaultCredentials,
+}
+
+/// Start of passwords hashed by [`hash_password`].
+const PASSWORD_HASH_PREFIX: &str = "pbkdf2-sha256$";
+
+/// PBKDF2 rounds of new password hashes.
+const PASSWORD_HASH_ITERATIONS: u32 = 100_000;
+
+/// Hash a dashboard password for storage, as
+/// `pbkdf2-sha256$<iterations>$<salt>$<hash>` (PBKDF2-HMAC-SHA256, base64
+/// salt and hash).
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    use base64::engine::general_purpose::STANDARD_NO_PAD;
+    use base64::Engine;
+
+    let mut salt = [0u8; 16];
+    secure_random(&mut salt)?;
+    let mut hash = [0u8; 32];
+    ring::pbkdf2::derive(
+        ring::pbkdf2::PBKDF2_HMAC_SHA256,
+        std::num::NonZeroU32::new(PASSWORD_HASH_ITERATIONS).expect("non-zero iterations"),
+        &salt,
+        password.as_bytes(),
+        &mut hash,
+    );
+    Ok(format!(
+        "{}{}${}${}",
+        PASSWORD_HASH_PREFIX,
+        PASSWORD_HASH_ITERATIONS,
+        STANDARD_NO_PAD.encode(salt),
+        STANDARD_NO_PAD.encode(hash)
+    ))
+}
+
+/// Check `password` against a stored dashboard password, either hashed by
+/// [`hash_password`] or in plain text.
+pub fn verify_password(stored: &str, password: &str) -> bool {
+    use base64::engine::general_purpose::STANDARD_NO_PAD;
+    use base64::Engine;
+
+    let Some(hashed) = stored.strip_prefix(PASSWORD_HASH_PREFIX) else {
+        return constant_time_eq(stored.as_bytes(), password.as_bytes());
+    };
+    let mut parts = hashed.split('$');
+    let (Some(iterations), Some(salt), Some(hash), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return false;
+    };
+    let (Some(iterations), Ok(salt), Ok(hash)) = (
+        iterations.parse().ok().and_then(std::num::NonZeroU32::new),
+        STANDARD_NO_PAD.decode(salt),
+        STANDARD_NO_PAD.decode(hash),
+    ) else {
+        return false;
+    };
+    ring::pbkdf2::verify(
+        ring::pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        &salt,
+        password.as_bytes(),
+        &hash,
+    )
+    .is_ok()
+}
+
+impl DashboardConfig {
+    /// How long a dashboard session stays valid after login.
+    pub fn session_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.session_ttl_secs)
+    }
+
+    /// Check the settings [`Config::validate`] checks for the `[dashboard]` section.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut admins = HashSet::new();
+        for admin in &self.users {
+            if admin.username.is_empty() || admin.password.is_empty() {
+                anyhow::bail!("dashboard.users: username and password must not be empty");
+            }
+            if !admins.insert(admin.username.as_str()) {
+                anyhow::bail!("dashboard.users: duplicate username '{}'", admin.username);
+            }
+        }
+        if self.auth_enabled && !self.has_admins() {
+            anyhow::bail!("dashboard: auth_enabled requires at least one of dashboard.users");
+        }
+        for (key, ranges) in [
+            ("trusted_proxies", &self.trusted_proxies),
+            ("allowed_ips", &self.allowed_ips),
+        ] {
+            if let Some(range) = ranges.iter().find(|range| !is_ip_or_cidr(range)) {
+                anyhow::bail!(
+                    "dashboard.{}: '{}' is not an IP address or CIDR range",
+                    key,
+                    range
+                );
+            }
+        }
+        if let Some(name) = &self.real_ip_header {
+            if name.is_empty()
+                || !name
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+            {
+                anyhow::bail!("dashboard.real_ip_header: invalid header name '{}'", name);
+            }
+        }
+        for (key, origins) in [
+            ("allowed_origins", &self.allowed_origins),
+            ("frame_ancestors", &self.frame_ancestors),
+        ] {
+            if let Some(origin) = origins.iter().find(|origin| !is_origin(origin)) {
+                anyhow::bail!(
+                    "dashboard.{}: '{}' is not an origin (scheme://host[:port])",
+                    key,
+                    origin
+                );
+            }
+        }
+        if !(MIN_SESSION_TTL_SECS..=MAX_SESSION_TTL_SECS).contains(&self.session_ttl_secs) {
+            anyhow::bail!(
+                "dashboard.session_ttl_secs: must be between {} and {}",
+                MIN_SESSION_TTL_SECS,
+                MAX_SESSION_TTL_SECS
+            );
+        }
+        for (class, limit) in [
+            ("standard", self.rate_limit.standard),
+            ("expensive", self.rate_limit.expensive),
+            ("auth", self.rate_limit.auth),
+        ] {
+            if limit.per_minute == 0 || limit.burst == 0 {
+                anyhow::bail!(
+                    "dashboard.rate_limit.{}: per_minute and burst must be at least 1",
+                    class
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate username and password for dashboard access.
+    pub fn authenticate(&self, username: &str, password: &str) -> bool {
+        if !self.auth_enabled {
+            return true;
+        }
+
+        let legacy = match (&self.username, &self.password) {
+            (Some(u), Some(p)) => u == username && verify_password(p, password),
+            _ => false,
+        };
+        legacy
+            || self.users.iter().any(|admin| {
+                admin.username == username && verify_password(&admin.password, password)
+            })
+    }
+
+    /// Why the dashboard is open to anyone who reaches it, if it is.
+    pub fn security_warning(&self) -> Option<DashboardSecurityWarning> {
+        if !self.auth_enabled {
+            return Some(DashboardSecurityWarning::AuthDisabled);
+        }
+        let legacy = self.username.as_deref().zip(self.password.as_deref());
+        let uses_default = self
+            .users
+            .iter()
+            .map(|admin| (admin.username.as_str(), admin.password.as_str()))
+            .chain(legacy)
+            .any(|(username, stored)| {
+                DEFAULT_CREDENTIALS
+                    .iter()
+                    .any(|&(default_user, default_password)| {
+                        username == default_user && verify_password(stored, default_password)
+                    })
+            });
+        uses_default.then_some(DashboardSecurityWarning::DefaultCredentials)
+    }
+
+    /// Whether anyone can sign in once `auth_enabled` is set.
+    pub fn has_admins(&self) -> bool {
+        !self.users.is_empty() || (self.username.is_some() && self.password.is_some())
+    }
+
+    /// Move the legacy `username`/`password` login into `users`.
+    ///
+    /// Returns whether there was a legacy login to move; the config file is
+    /// rewritten in the new form on its next save.
+    pub fn migrate_legacy_login(&mut self) -> bool {
+        let (Some(username), Some(password)) = (self.username.take(), self.password.take()) else {
+            return false;
+        };
+        match self
+            .users
+            .iter_mut()
+            .find(|admin| admin.username == username)
+        {
+            Some(admin) => admin.password = password,
+            None => self.users.push(DashboardUser { username, password }),
+        }
+        true
+    }
+
+    /// Set the password of the administrator `username`; `false` if there is none.
+    pub fn set_password(&mut self, username: &str, password: String) -> bool {
+        match self
+            .users
+            .iter_mut()
+            .find(|admin| admin.username == username)
+        {
+            Some(admin) => {
+                admin.password = password;
+                true
+            }
+            None if self.username.as_deref() == Some(username) => {
+                self.password = Some(password);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Administrator account of the dashboard.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DashboardUser {
+    /// Login name.
+    pub username: String,
+
+    /// Password.
+    pub password: String,
+}
+
+/// User account for authentication.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct User {
+    /// Username.
+    pub username: String,
+
+    /// Password (plain text for now, should be hashed in production).
+    pub password: String,
+
+    /// Whether this user is enabled.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Optional description or display name.
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// Bandwidth limit in bytes per second (0 = unlimited).
+    #[serde(default)]
+    pub bandwidth_limit: u64,
+
+    /// Connection limit (0 = unlimited).
+    #[serde(default)]
+    pub connection_limit: u32,
+
+    /// Overrides `limits.max_connection_lifetime_secs` (0 = unlimited).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connection_lifetime_secs: Option<u64>,
+
+    /// Data allowed per quota period in bytes, both directions combined (0 = unlimited).
+    #[serde(default)]
+    pub quota_bytes: u64,
+
+    /// Period after which quota usage resets.
+    #[serde(default)]
+    pub quota_period: QuotaPeriod,
+
+    /// Tokens accepted in place of the password.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tokens: Vec<UserToken>,
+
+    /// Labels attached through the API, e.g. `under-investigation`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    /// Free-form note attached through the API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// Username proxy clients send with a token as the password.
+pub const TOKEN_USERNAME: &str = "token";
+
+/// Prefix of generated tokens, so they're recognizable in client configs.
+const TOKEN_PREFIX: &str = "nrt_";
+
+/// Non-interactive credential of a user, for headless clients.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UserToken {
+    /// Identifier used to revoke the token.
+    pub id: String,
+
+    /// What the token is for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+
+    /// SHA-256 of the token (hex); the token itself is only shown when created.
+    pub hash: String,
+
+    /// When the token was created.
+    pub created_at: DateTime<Utc>,
+
+    /// When the token stops being accepted (`None` = never).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl UserToken {
+    /// Generate a new token, returning its record and the token itself.
+    pub fn generate(
+        label: Option<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<(Self, String)> {
+        let token = format!("{}{}", TOKEN_PREFIX, random_hex(32)?);
+        let record = Self {
+            id: uuid::Uuid::new_v4().simple().to_string()[..12].to_string(),
+            label,
+            hash: hash_token(&token),
+            created_at: Utc::now(),
+            expires_at,
+        };
+        Ok((record, token))
+    }
+
+    /// Whether the token has expired at `now`.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// SHA-256 of a token, as stored in [`UserToken::hash`].
+pub fn hash_token(token: &str) -> String {
+    hex(ring::digest::digest(&ring::digest::SHA256, token.as_bytes()).as_ref())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl User {
+    /// Create a new user with username and password.
+    pub fn new(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            password: password.into(),
+            enabled: true,
+            description: None,
+            bandwidth_limit: 0,
+            connection_limit: 0,
+            max_connection_lifetime_secs: None,
+            quota_bytes: 0,
+            quota_period: QuotaPeriod::default(),
+            tokens: Vec::new(),
+            tags: Vec::new(),
+            note: None,
+        }
+    }
+}
+
+/// Responses and connection reuse of the HTTP CONNECT proxy (`[http_proxy]`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HttpProxyConfig {
+    /// Send `Proxy-Agent: net-relay/<version>` on every response.
+    #[serde(default)]
+    pub send_proxy_agent: bool,
+
+    /// Static headers added to every response, by name.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+
+    /// Keep the client connection open when the target closes a tunnel, so
+    /// the client can send another CONNECT on it.
+    #[serde(default)]
+    pub keep_alive: bool,
+
+    /// Seconds to wait for (and read) the next CONNECT on a kept-alive connection.
+    #[serde(default = "default_keep_alive_timeout_secs")]
+    pub keep_alive_timeout_secs: u64,
+
+    /// Most CONNECT requests served on one client connection.
+    #[serde(default = "default_max_requests_per_connection")]
+    pub max_requests_per_connection: u32,
+}
+
+impl Default for HttpProxyConfig {
+    fn default() -> Self {
+        Self {
+            send_proxy_agent: false,
+            headers: BTreeMap::new(),
+            keep_alive: false,
+            keep_alive_timeout_secs: default_keep_alive_timeout_secs(),
+            max_requests_per_connection: default_max_requests_per_connection(),
+        }
+    }
+}
+
+fn default_keep_alive_timeout_secs() -> u64 {
+    15
+}
+
+fn default_max_requests_per_connection() -> u32 {
+    100
+}
+
+/// Response headers the HTTP proxy sets itself.
+const RESERVED_RESPONSE_HEADERS: &[&str] = &[
+    "Proxy-Agent",
+    "Proxy-Authenticate",
+    "Content-Type",
+    "Content-Length",
+    "Connection",
+    "Transfer-Encoding",
+];
+
+/// Security configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityConfig {
+    /// Enable authentication.
+    #[serde(default)]
+    pub auth_enabled: bool,
+
+    /// Username for authentication (legacy single user, deprecated).
+    pub username: Option<String>,
+
+    /// Password for authentication (legacy single user, deprecated).
+    pub password: Option<String>,
+
+    /// Multi-user accounts.
+    #[serde(default)]
+    pub users: Vec<User>,
+
+    /// Deprecated and not enforced here: moved into
+    /// `access_control.ip_whitelist` at startup (see
+    /// [`ConfigManager::migrate_allowed_ips`]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_ips: Vec<String>,
+
+    /// Client IPs that skip proxy authentication (CIDR notation).
+    #[serde(default)]
+    pub auth_exempt_ips: Vec<String>,
+
+    /// Rules for passwords set through the API.
+    #[serde(default)]
+    pub password_policy: PasswordPolicy,
+
+    /// SOCKS5 auth methods the server negotiates.
+    #[serde(default = "default_socks5_auth_methods")]
+    pub socks5_auth_methods: Vec<Socks5AuthMethod>,
+
+    /// Length limits of SOCKS5 usernames and passwords.
+    #[serde(default)]
+    pub credential_limits: CredentialLimits,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            auth_enabled: false,
+            username: None,
+            password: None,
+            users: Vec::new(),
+            allowed_ips: Vec::new(),
+            auth_exempt_ips: Vec::new(),
+            password_policy: PasswordPolicy::default(),
+            socks5_auth_methods: default_socks5_auth_methods(),
+            credential_limits: CredentialLimits::default(),
+        }
+    }
+}
+
+/// SOCKS5 authentication method (RFC 1928).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Socks5AuthMethod {
+    /// No authentication (`0x00`), for clients that don't have to
+    /// authenticate or are in `auth_exempt_ips`.
+    #[serde(rename = "none")]
+    NoAuth,
+    /// Username/password (`0x02`, RFC 1929).
+    UsernamePassword,
+}
+
+impl Socks5AuthMethod {
+    /// Method code in the SOCKS5 greeting.
+    pub fn code(self) -> u8 {
+        match self {
+            Socks5AuthMethod::NoAuth => 0x00,
+            Socks5AuthMethod::UsernamePassword => 0x02,
+        }
+    }
+}
+
+fn default_socks5_auth_methods() -> Vec<Socks5AuthMethod> {
+    vec![Socks5AuthMethod::NoAuth, Socks5AuthMethod::UsernamePassword]
+}
+
+/// Length limits, in bytes, of SOCKS5 usernames and passwords (RFC 1929
+/// allows up to 255).
+///
+/// Credentials outside them are refused before any user is looked up; an
+/// empty username is always refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredentialLimits {
+    #[serde(default = "default_credential_min_length")]
+    pub min_length: usize,
+
+    #[serde(default = "default_credential_max_length")]
+    pub max_length: usize,
+}
+
+impl Default for CredentialLimits {
+    fn default() -> Self {
+        Self {
+            min_length: default_credential_min_length(),
+            max_length: default_credential_max_length(),
+        }
+    }
+}
+
+impl CredentialLimits {
+    /// Why `username` and `password` are refused, if they are.
+    pub fn check(&self, username: &str, password: &str) -> std::result::Result<(), &'static str> {
+        if username.is_empty() {
+            return Err("empty username");
+        }
+        for (len, too_short, too_long) in [
+            (username.len(), "username too short", "username too long"),
+            (password.len(), "password too short", "password too long"),
+        ] {
+            if len < self.min_length {
+                return Err(too_short);
+            }
+            if len > self.max_length {
+                return Err(too_long);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_credential_min_length() -> usize {
+    1
+}
+
+fn default_credential_max_length() -> usize {
+    255
+}
+
+/// Rules for passwords set through the API (proxy users and the dashboard).
+///
+/// Passwords already in the configuration file are not checked.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasswordPolicy {
+    /// Minimum length in characters.
+    #[serde(default = "default_password_min_length")]
+    pub min_length: usize,
+
+    /// Require at least three of: lowercase, uppercase, digits, symbols.
+    #[serde(default)]
+    pub require_mixed_classes: bool,
+
+    /// Reject the built-in list of common passwords.
+    #[serde(default = "default_true")]
+    pub deny_common: bool,
+
+    /// Additional passwords to reject (case-insensitive).
+    #[serde(default)]
+    pub deny_list: Vec<String>,
+
+    /// Length of passwords from `GET /api/config/users/generate-password`.
+    #[serde(default = "default_generated_password_length")]
+    pub generated_length: usize,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: default_password_min_length(),
+            require_mixed_classes: false,
+            deny_common: true,
+            deny_list: Vec::new(),
+            generated_length: default_generated_password_length(),
+        }
+    }
+}
+
+fn default_password_min_length() -> usize {
+    MIN_PASSWORD_LENGTH
+}
+
+fn default_generated_password_length() -> usize {
+    20
+}
+
+/// Passwords rejected when `deny_common` is set (compared case-insensitively).
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456",
+    "1234567",
+    "12345678",
+    "123456789",
+    "1234567890",
+    "12345",
+    "111111",
+    "11111111",
+    "000000",
+    "00000000",
+    "123123",
+    "654321",
+    "987654321",
+    "121212",
+    "112233",
+    "666666",
+    "777777",
+    "888888",
+    "password",
+    "password1",
+    "password123",
+    "passw0rd",
+    "p@ssw0rd",
+    "qwerty",
+    "qwerty123",
+    "qwertyuiop",
+    "asdfgh",
+    "asdfghjkl",
+    "zxcvbnm",
+    "1qaz2wsx",
+    "qazwsx",
+    "abc123",
+    "abcd1234",
+    "iloveyou",
+    "letmein",
+    "welcome",
+    "welcome1",
+    "changeme",
+    "admin",
+    "admin123",
+    "administrator",
+    "root",
+    "toor",
+    "secret",
+    "master",
+    "monkey",
+    "dragon",
+    "football",
+    "baseball",
+    "sunshine",
+    "princess",
+    "shadow",
+    "superman",
+    "trustno1",
+    "starwars",
+    "whatever",
+    "freedom",
+    "computer",
+    "internet",
+    "default",
+    "guest",
+    "test",
+    "test123",
+    "proxy",
+    "proxy123",
+];
+
+/// Characters generated passwords are drawn from (no look-alikes such as `l`/`1`/`O`/`0`).
+const GENERATED_PASSWORD_CHARSET: &[u8] =
+    b"abcdefghijkmnopqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ23456789!#%+-.=?@_";
+
+impl PasswordPolicy {
+    /// Reasons `password` is rejected (empty when it is acceptable).
+    pub fn check(&self, password: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        if password.chars().count() < self.min_length {
+            problems.push(format!("must be at least {} characters", self.min_length));
+        }
+        if self.require_mixed_classes {
+            let classes = [
+                password.chars().any(|c| c.is_lowercase()),
+                password.chars().any(|c| c.is_uppercase()),
+                password.chars().any(|c| c.is_ascii_digit()),
+                password.chars().any(|c| !c.is_alphanumeric()),
+            ];
+            if classes.iter().filter(|present| **present).count() < 3 {
+                problems.push(
+                    "must contain at least three of: lowercase letters, uppercase letters, digits, symbols"
+                        .to_string(),
+                );
+            }
+        }
+        let denied = (self.deny_common
+            && COMMON_PASSWORDS
+                .iter()
+                .any(|common| common.eq_ignore_ascii_case(password)))
+            || self
+                .deny_list
+                .iter()
+                .any(|denied| denied.to_lowercase() == password.to_lowercase());
+        if denied {
+            problems.push("is too common".to_string());
+        }
+        problems
+    }
+
+    /// Generate a random password of `length` characters that satisfies the policy.
+    pub fn generate(&self, length: usize) -> anyhow::Result<String> {
+        if length < self.min_length.max(MIN_PASSWORD_LENGTH) {
+            anyhow::bail!(
+                "length must be at least {}",
+                self.min_length.max(MIN_PASSWORD_LENGTH)
+            );
+        }
+        let charset = GENERATED_PASSWORD_CHARSET;
+        // Bytes at or above this are skipped so every character is equally likely
+        let limit = 256 - 256 % charset.len();
+        let mut bytes = vec![0u8; length];
+        loop {
+            let mut password = String::with_capacity(length);
+            while password.len() < length {
+                secure_random(&mut bytes)?;
+                password.extend(
+                    bytes
+                        .iter()
+                        .filter(|byte| (**byte as usize) < limit)
+                        .map(|byte| charset[*byte as usize % charset.len()] as char)
+                        .take(length - password.len()),
+                );
+            }
+            if self.check(&password).is_empty() {
+                return Ok(password);
+            }
+        }
+    }
+}
+
+/// Fill `buf` from the operating system's secure random number generator.
+pub fn secure_random(buf: &mut [u8]) -> anyhow::Result<()> {
+    use ring::rand::SecureRandom;
+
+    ring::rand::SystemRandom::new()
+        .fill(buf)
+        .map_err(|_| anyhow::anyhow!("secure random number generator unavailable"))
+}
+
+/// `len` secure random bytes, hex encoded.
+pub fn random_hex(len: usize) -> anyhow::Result<String> {
+    let mut bytes = vec![0u8; len];
+    secure_random(&mut bytes)?;
+    Ok(hex(&bytes))
+}
+
+impl SecurityConfig {
+    /// Synthetic username for a client exempt from authentication, e.g. `ip:10.0.0.0/8`.
+    pub fn auth_exempt_user(&self, ip: &str) -> Option<String> {
+        self.auth_exempt_ips
+            .iter()
+            .find(|pattern| ip_matches(ip, pattern))
+            .map(|pattern| format!("ip:{}", pattern))
+    }
+
+    /// Check if a username/password combination is valid.
+    /// Returns the username if authentication succeeds.
+    pub fn authenticate(&self, username: &str, password: &str) -> Option<String> {
+        // First check multi-user list
+        for user in &self.users {
+            if user.enabled && user.username == username && user.password == password {
+                return Some(user.username.clone());
+            }
+        }
+
+        // Fallback to legacy single user
+        if let (Some(u), Some(p)) = (&self.username, &self.password) {
+            if u == username && p == password {
+                return Some(username.to_string());
+            }
+        }
+
+        // Tokens are sent as the password of the fixed token username
+        if username == TOKEN_USERNAME {
+            return self.authenticate_token(password);
+        }
+
+        None
+    }
+
+    /// Password of an enabled user (or the legacy single user), for
+    /// challenge-response schemes that need the plain text.
+    pub fn password_of(&self, username: &str) -> Option<&str> {
+        if let Some(user) = self
+            .users
+            .iter()
+            .find(|u| u.enabled && u.username == username)
+        {
+            return Some(&user.password);
+        }
+        match (&self.username, &self.password) {
+            (Some(u), Some(p)) if u == username => Some(p),
+            _ => None,
+        }
+    }
+
+    /// Check a token. Returns the username of the enabled user owning it.
+    pub fn authenticate_token(&self, token: &str) -> Option<String> {
+        let hash = hash_token(token);
+        let now = Utc::now();
+        self.users
+            .iter()
+            .filter(|user| user.enabled)
+            .find(|user| {
+                user.tokens.iter().any(|t| {
+                    !t.is_expired(now) && constant_time_eq(t.hash.as_bytes(), hash.as_bytes())
+                })
+            })
+            .map(|user| user.username.clone())
+    }
+
+    /// Names of every configured user, enabled or not, and the legacy single user.
+    pub fn configured_usernames(&self) -> HashSet<String> {
+        self.users
+            .iter()
+            .map(|user| user.username.clone())
+            .chain(self.username.clone())
+            .collect()
+    }
+
+    /// Get all enabled users.
+    pub fn get_users(&self) -> Vec<&User> {
+        self.users.iter().filter(|u| u.enabled).collect()
+    }
+
+    /// Add a new user.
+    pub fn add_user(&mut self, user: User) -> bool {
+        if self.users.iter().any(|u| u.username == user.username) {
+            return false;
+        }
+        self.users.push(user);
+        true
+    }
+
+    /// Remove a user by username.
+    pub fn remove_user(&mut self, username: &str) -> bool {
+        let len_before = self.users.len();
+        self.users.retain(|u| u.username != username);
+        self.users.len() < len_before
+    }
+
+    /// Update a user.
+    pub fn update_user(&mut self, user: User) -> bool {
+        if let Some(existing) = self.users.iter_mut().find(|u| u.username == user.username) {
+            *existing = user;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Connection limits configuration.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LoggingConfig {
-    /// Log level.
-    #[serde(default = "default_log_level")]
-    pub level: String,
+pub struct LimitsConfig {
+    /// Maximum concurrent connections.
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
 
-    /// Log file path (optional).
-    pub file: Option<String>,
+    /// Connection timeout in seconds.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+
+    /// Close relays after this many seconds without data in either
+    /// direction (0 = never).
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: u64,
+
+    /// Close relays this many seconds after they started, however busy
+    /// (0 = unlimited). Users may override it.
+    #[serde(default)]
+    pub max_connection_lifetime_secs: u64,
+
+    /// Server-wide bandwidth cap in bytes per second across all relays (0 = unlimited).
+    #[serde(default)]
+    pub global_bandwidth: u64,
+
+    /// Close a user's in-flight connections once their quota runs out,
+    /// instead of only refusing new ones.
+    #[serde(default)]
+    pub quota_cutoff_active: bool,
+
+    /// Further attempts at connecting to a target that refused the
+    /// connection or was unreachable (0 = no retries). Timeouts are never
+    /// retried, and all attempts together stay within `timeout`.
+    #[serde(default)]
+    pub connect_retries: u32,
+
+    /// Pause before the first retry in milliseconds, doubled for every further one.
+    #[serde(default = "default_connect_retry_delay_ms")]
+    pub connect_retry_delay_ms: u64,
+
+    /// Up to this many milliseconds, chosen at random, added to every pause.
+    #[serde(default = "default_connect_retry_jitter_ms")]
+    pub connect_retry_jitter_ms: u64,
+
+    /// Size in bytes of each of a relay's two copy buffers (one per direction).
+    #[serde(default = "default_relay_buffer_size")]
+    pub relay_buffer_size: usize,
+
+    /// Relay buffer memory across all connections in bytes (0 = unlimited).
+    /// Relays started beyond it get small buffers instead of being refused.
+    #[serde(default)]
+    pub max_relay_buffer_memory: u64,
+
+    /// TCP keepalive probing of client and target sockets.
+    #[serde(default)]
+    pub tcp_keepalive: TcpKeepaliveConfig,
 }
 
-impl Default for LoggingConfig {
+impl Default for LimitsConfig {
     fn default() -> Self {
         Self {
-            level: default_log_level(),
-            file: None,
+            max_connections: default_max_connections(),
+            timeout: default_timeout(),
+            idle_timeout: default_idle_timeout(),
+            max_connection_lifetime_secs: 0,
+            global_bandwidth: 0,
+            quota_cutoff_active: false,
+            connect_retries: 0,
+            connect_retry_delay_ms: default_connect_retry_delay_ms(),
+            connect_retry_jitter_ms: default_connect_retry_jitter_ms(),
+            relay_buffer_size: default_relay_buffer_size(),
+            max_relay_buffer_memory: 0,
+            tcp_keepalive: TcpKeepaliveConfig::default(),
         }
     }
 }
 
-fn default_log_level() -> String {
-    "info".to_string()
-}
+/// OS-level TCP keepalive on client and target sockets.
+///
+/// Peers that vanish without closing (a phone losing its network) leave a
+/// relay waiting on a socket nothing will ever arrive on. With keepalive the
+/// OS probes idle connections and fails them once the peer stops answering,
+/// after at most [`detection_time`](Self::detection_time).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TcpKeepaliveConfig {
+    /// Probe idle connections.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
 
-/// Dashboard authentication configuration.
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
-pub struct DashboardConfig {
-    /// Enable dashboard authentication.
-    #[serde(default)]
-    pub auth_enabled: bool,
+    /// Seconds without traffic before the first probe.
+    #[serde(default = "default_keepalive_idle_secs")]
+    pub idle_secs: u64,
 
-    /// Username for dashboard login.
-    #[serde(default)]
-    pub username: Option<String>,
+    /// Seconds between unanswered probes.
+    #[serde(default = "default_keepalive_interval_secs")]
+    pub interval_secs: u64,
 
-    /// Password for dashboard login.
-    #[serde(default)]
-    pub password: Option<String>,
+    /// Unanswered probes after which the connection is dropped (not
+    /// supported on every platform).
+    #[serde(default = "default_keepalive_count")]
+    pub count: u32,
 }
 
-impl DashboardConfig {
-    /// Validate username and password for dashboard access.
-    pub fn authenticate(&self, username: &str, password: &str) -> bool {
-        if !self.auth_enabled {
-            return true;
+impl Default for TcpKeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_secs: default_keepalive_idle_secs(),
+            interval_secs: default_keepalive_interval_secs(),
+            count: default_keepalive_count(),
         }
+    }
+}
 
-        match (&self.username, &self.password) {
-            (Some(u), Some(p)) => u == username && p == password,
-            _ => false,
+impl TcpKeepaliveConfig {
+    /// How long a dead peer can go unnoticed, `None` with keepalive off.
+    pub fn detection_time(&self) -> Option<std::time::Duration> {
+        self.enabled.then(|| {
+            std::time::Duration::from_secs(
+                self.idle_secs
+                    .saturating_add(self.interval_secs.saturating_mul(self.count.into())),
+            )
+        })
+    }
+}
+
+fn default_keepalive_idle_secs() -> u64 {
+    60
+}
+
+fn default_keepalive_interval_secs() -> u64 {
+    10
+}
+
+fn default_keepalive_count() -> u32 {
+    5
+}
+
+/// Most connect retries `limits.connect_retries` may ask for.
+pub const MAX_CONNECT_RETRIES: u32 = 10;
+
+fn default_relay_buffer_size() -> usize {
+    8192
+}
+
+/// Smallest `limits.relay_buffer_size`, also the size relays fall back to
+/// once `limits.max_relay_buffer_memory` is reached.
+pub const MIN_RELAY_BUFFER_SIZE: usize = 1024;
+
+/// Largest `limits.relay_buffer_size`.
+pub const MAX_RELAY_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+impl LimitsConfig {
+    /// Pause before connect retry number `retry` (starting at 1).
+    pub fn connect_retry_delay(&self, retry: u32) -> std::time::Duration {
+        let base = self
+            .connect_retry_delay_ms
+            .saturating_mul(1 << retry.saturating_sub(1).min(MAX_CONNECT_RETRIES));
+        let mut jitter = 0;
+        if self.connect_retry_jitter_ms > 0 {
+            let mut bytes = [0u8; 8];
+            if secure_random(&mut bytes).is_ok() {
+                jitter = u64::from_le_bytes(bytes) % (self.connect_retry_jitter_ms + 1);
+            }
         }
+        std::time::Duration::from_millis(base.saturating_add(jitter))
     }
 }
 
-/// User account for authentication.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub struct User {
-    /// Username.
-    pub username: String,
+fn default_connect_retry_delay_ms() -> u64 {
+    100
+}
 
-    /// Password (plain text for now, should be hashed in production).
-    pub password: String,
+fn default_connect_retry_jitter_ms() -> u64 {
+    50
+}
 
-    /// Whether this user is enabled.
-    #[serde(default = "default_true")]
+fn default_max_connections() -> usize {
+    1000
+}
+
+fn default_timeout() -> u64 {
+    300
+}
+
+fn default_idle_timeout() -> u64 {
+    60
+}
+
+/// Statistics configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsConfig {
+    /// Enable statistics collection. When off only the totals are counted:
+    /// no active connections, history or per-user statistics are kept, and
+    /// turning it off at runtime drops what was recorded.
+    #[serde(default = "default_stats_enabled")]
     pub enabled: bool,
 
-    /// Optional description or display name.
+    /// Retention period in hours.
+    #[serde(default = "default_retention_hours")]
+    pub retention_hours: u64,
+
+    /// File where per-user quota usage is kept across restarts.
+    #[serde(default = "default_quota_file")]
+    pub quota_file: String,
+
+    /// Directory for the checkpoint of lifetime byte counters (unset = not kept across restarts).
     #[serde(default)]
-    pub description: Option<String>,
+    pub state_dir: Option<String>,
 
-    /// Bandwidth limit in bytes per second (0 = unlimited).
+    /// Seconds between checkpoints of the byte counters.
+    #[serde(default = "default_checkpoint_interval_secs")]
+    pub checkpoint_interval_secs: u64,
+
+    /// Count connections without a username per client address in the user statistics.
     #[serde(default)]
-    pub bandwidth_limit: u64,
+    pub attribute_by_ip: bool,
 
-    /// Connection limit (0 = unlimited).
+    /// Leading bits of IPv4 client addresses counted together (32 = each address).
+    #[serde(default = "default_attribute_ipv4_prefix")]
+    pub attribute_ipv4_prefix: u8,
+
+    /// Leading bits of IPv6 client addresses counted together (128 = each address).
+    #[serde(default = "default_attribute_ipv6_prefix")]
+    pub attribute_ipv6_prefix: u8,
+
+    /// Most client address buckets kept in the user statistics.
+    #[serde(default = "default_max_ip_buckets")]
+    pub max_ip_buckets: usize,
+
+    /// Most users with counters; idle users not in the config are evicted beyond it.
+    #[serde(default = "default_max_tracked_users")]
+    pub max_tracked_users: usize,
+
+    /// Most denied attempts kept for `/api/stats/denied`.
+    #[serde(default = "default_max_denied_attempts")]
+    pub max_denied_attempts: usize,
+
+    /// Look up reverse-DNS names of client addresses in the background.
     #[serde(default)]
-    pub connection_limit: u32,
+    pub resolve_client_hostnames: bool,
+
+    /// Seconds of traffic active connection rates are averaged over.
+    #[serde(default = "default_rate_window_secs")]
+    pub rate_window_secs: u64,
+
+    /// Closed connections kept in the in-memory history.
+    #[serde(default = "default_max_history")]
+    pub max_history: usize,
+
+    /// Drop closed connections from the history after this many minutes (0 = no limit).
+    #[serde(default)]
+    pub max_history_age_minutes: u64,
+
+    /// JSON lines file receiving connection events (optional, rotated per `logging.rotation`).
+    #[serde(default)]
+    pub event_log: Option<String>,
+
+    /// Whether events are dropped or recording waits when the event log falls behind.
+    #[serde(default)]
+    pub event_log_backpressure: EventLogBackpressure,
 }
 
-fn default_true() -> bool {
-    true
+impl StatsConfig {
+    /// Attribution of connections without a username, if `attribute_by_ip` is set.
+    pub fn ip_attribution(&self) -> Option<IpAttribution> {
+        self.attribute_by_ip.then_some(IpAttribution {
+            ipv4_prefix: self.attribute_ipv4_prefix,
+            ipv6_prefix: self.attribute_ipv6_prefix,
+            max_buckets: self.max_ip_buckets,
+        })
+    }
+
+    /// Path of the byte counter checkpoint, if `state_dir` is set.
+    pub fn checkpoint_path(&self) -> Option<PathBuf> {
+        self.state_dir
+            .as_ref()
+            .map(|dir| Path::new(dir).join(crate::checkpoint::CHECKPOINT_FILE))
+    }
+
+    /// Age limit of the history.
+    pub fn max_history_age(&self) -> Option<std::time::Duration> {
+        (self.max_history_age_minutes > 0).then(|| {
+            std::time::Duration::from_secs(self.max_history_age_minutes.saturating_mul(60))
+        })
+    }
 }
 
-impl User {
-    /// Create a new user with username and password.
-    pub fn new(username: impl Into<String>, password: impl Into<String>) -> Self {
+impl Default for StatsConfig {
+    fn default() -> Self {
         Self {
-            username: username.into(),
-            password: password.into(),
-            enabled: true,
-            description: None,
-            bandwidth_limit: 0,
-            connection_limit: 0,
+            enabled: default_stats_enabled(),
+            retention_hours: default_retention_hours(),
+            quota_file: default_quota_file(),
+            state_dir: None,
+            checkpoint_interval_secs: default_checkpoint_interval_secs(),
+            attribute_by_ip: false,
+            attribute_ipv4_prefix: default_attribute_ipv4_prefix(),
+            attribute_ipv6_prefix: default_attribute_ipv6_prefix(),
+            max_ip_buckets: default_max_ip_buckets(),
+            max_tracked_users: default_max_tracked_users(),
+            max_denied_attempts: default_max_denied_attempts(),
+            resolve_client_hostnames: false,
+            rate_window_secs: default_rate_window_secs(),
+            max_history: default_max_history(),
+            max_history_age_minutes: 0,
+            event_log: None,
+            event_log_backpressure: EventLogBackpressure::default(),
         }
     }
 }
 
-/// Security configuration.
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
-pub struct SecurityConfig {
-    /// Enable authentication.
+/// Metrics pushed to external collectors (`/api/metrics` is always served).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// Counters and gauges sent to a StatsD / DogStatsD agent.
     #[serde(default)]
-    pub auth_enabled: bool,
+    pub statsd: StatsdConfig,
+}
 
-    /// Username for authentication (legacy single user, deprecated).
-    pub username: Option<String>,
+/// StatsD emitter (`[metrics.statsd]`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatsdConfig {
+    /// Send metrics to the agent.
+    #[serde(default)]
+    pub enabled: bool,
 
-    /// Password for authentication (legacy single user, deprecated).
-    pub password: Option<String>,
+    /// Agent host name or IP address.
+    #[serde(default = "default_statsd_host")]
+    pub host: String,
 
-    /// Multi-user accounts.
-    #[serde(default)]
-    pub users: Vec<User>,
+    /// Agent UDP port.
+    #[serde(default = "default_statsd_port")]
+    pub port: u16,
+
+    /// Prefix of every metric name.
+    #[serde(default = "default_statsd_prefix")]
+    pub prefix: String,
 
-    /// Allowed client IPs (CIDR notation).
+    /// Tags added to every metric (`key:value`, DogStatsD only).
     #[serde(default)]
-    pub allowed_ips: Vec<String>,
+    pub tags: Vec<String>,
+
+    /// Use the DogStatsD tag extension; plain StatsD gets untagged metrics
+    /// and no per-user metrics.
+    #[serde(default = "default_true")]
+    pub dogstatsd: bool,
+
+    /// Seconds between flushes of the aggregated metrics.
+    #[serde(default = "default_statsd_flush_interval")]
+    pub flush_interval_secs: u64,
+
+    /// Users with their own `user` tag; traffic of further users is tagged
+    /// `user:other` (0 = no per-user metrics).
+    #[serde(default = "default_statsd_max_user_tags")]
+    pub max_user_tags: usize,
 }
 
-impl SecurityConfig {
-    /// Check if a username/password combination is valid.
-    /// Returns the username if authentication succeeds.
-    pub fn authenticate(&self, username: &str, password: &str) -> Option<String> {
-        // First check multi-user list
-        for user in &self.users {
-            if user.enabled && user.username == username && user.password == password {
-                return Some(user.username.clone());
-            }
+impl Default for StatsdConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: default_statsd_host(),
+            port: default_statsd_port(),
+            prefix: default_statsd_prefix(),
+            tags: Vec::new(),
+            dogstatsd: true,
+            flush_interval_secs: default_statsd_flush_interval(),
+            max_user_tags: default_statsd_max_user_tags(),
         }
+    }
+}
 
-        // Fallback to legacy single user
-        if let (Some(u), Some(p)) = (&self.username, &self.password) {
-            if u == username && p == password {
-                return Some(username.to_string());
-            }
-        }
+fn default_statsd_host() -> String {
+    "127.0.0.1".to_string()
+}
 
-        None
+fn default_statsd_port() -> u16 {
+    8125
+}
+
+fn default_statsd_prefix() -> String {
+    "net_relay".to_string()
+}
+
+fn default_statsd_flush_interval() -> u64 {
+    10
+}
+
+fn default_statsd_max_user_tags() -> usize {
+    100
+}
+
+/// OpenTelemetry trace export (requires the `telemetry` build feature).
+///
+/// Read at startup only.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TelemetryConfig {
+    /// Export a trace per proxied connection.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// OTLP/HTTP traces URL of the collector.
+    #[serde(default = "default_telemetry_endpoint")]
+    pub endpoint: String,
+
+    /// Headers sent with every export, e.g. collector API keys.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// `service.name` reported with the traces.
+    #[serde(default = "default_telemetry_service_name")]
+    pub service_name: String,
+
+    /// Share of connections traced, from 0.0 (none) to 1.0 (all).
+    #[serde(default = "default_telemetry_sample_ratio")]
+    pub sample_ratio: f64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: default_telemetry_endpoint(),
+            headers: HashMap::new(),
+            service_name: default_telemetry_service_name(),
+            sample_ratio: default_telemetry_sample_ratio(),
+        }
     }
+}
 
-    /// Get all enabled users.
-    pub fn get_users(&self) -> Vec<&User> {
-        self.users.iter().filter(|u| u.enabled).collect()
+fn default_telemetry_endpoint() -> String {
+    "http://localhost:4318/v1/traces".to_string()
+}
+
+fn default_telemetry_service_name() -> String {
+    "net-relay".to_string()
+}
+
+fn default_telemetry_sample_ratio() -> f64 {
+    1.0
+}
+
+/// Debugging aids that expose relayed traffic (`[debug]`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DebugConfig {
+    /// Allow dashboard administrators to capture the bytes of a connection
+    /// (`/api/connections/{id}/capture`).
+    #[serde(default)]
+    pub capture_enabled: bool,
+
+    /// Most bytes a single capture may hold.
+    #[serde(default = "default_capture_max_bytes")]
+    pub capture_max_bytes: usize,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            capture_enabled: false,
+            capture_max_bytes: default_capture_max_bytes(),
+        }
     }
+}
+
+fn default_capture_max_bytes() -> usize {
+    1024 * 1024
+}
+
+/// Threshold alerts on the statistics (`[alerts]`), see [`crate::alerts`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertsConfig {
+    /// Seconds between evaluations of the rules.
+    #[serde(default = "default_alert_interval_secs")]
+    pub interval_secs: u64,
+
+    /// Webhooks alert transitions can be posted to, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub webhooks: BTreeMap<String, WebhookConfig>,
 
-    /// Add a new user.
-    pub fn add_user(&mut self, user: User) -> bool {
-        if self.users.iter().any(|u| u.username == user.username) {
-            return false;
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<AlertRule>,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_alert_interval_secs(),
+            webhooks: BTreeMap::new(),
+            rules: Vec::new(),
         }
-        self.users.push(user);
-        true
     }
+}
 
-    /// Remove a user by username.
-    pub fn remove_user(&mut self, username: &str) -> bool {
-        let len_before = self.users.len();
-        self.users.retain(|u| u.username != username);
-        self.users.len() < len_before
-    }
+impl AlertsConfig {
+    /// Longest `window_secs` of a rule.
+    pub const MAX_WINDOW_SECS: u64 = 24 * 3600;
 
-    /// Update a user.
-    pub fn update_user(&mut self, user: User) -> bool {
-        if let Some(existing) = self.users.iter_mut().find(|u| u.username == user.username) {
-            *existing = user;
-            true
-        } else {
-            false
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.interval_secs == 0 {
+            anyhow::bail!("alerts: interval_secs must be at least 1");
+        }
+        for (name, webhook) in &self.webhooks {
+            if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+                anyhow::bail!(
+                    "alerts.webhooks.{}: url must start with http:// or https://",
+                    name
+                );
+            }
+        }
+        let mut names = HashSet::new();
+        for rule in &self.rules {
+            if rule.name.trim().is_empty() {
+                anyhow::bail!("alerts.rules: name must not be empty");
+            }
+            if !names.insert(rule.name.as_str()) {
+                anyhow::bail!("alerts.rules: duplicate name '{}'", rule.name);
+            }
+            if rule.window_secs == 0 || rule.window_secs > Self::MAX_WINDOW_SECS {
+                anyhow::bail!(
+                    "alerts.rules.{}: window_secs must be between 1 and {}",
+                    rule.name,
+                    Self::MAX_WINDOW_SECS
+                );
+            }
+            if let Some(resolve) = rule.resolve_threshold {
+                let valid = match rule.comparison {
+                    AlertComparison::Above => resolve <= rule.threshold,
+                    AlertComparison::Below => resolve >= rule.threshold,
+                };
+                if !valid {
+                    anyhow::bail!(
+                        "alerts.rules.{}: resolve_threshold must not be {} threshold",
+                        rule.name,
+                        rule.comparison
+                    );
+                }
+            }
+            if let Some(notify) = &rule.notify {
+                if !self.webhooks.contains_key(notify) {
+                    anyhow::bail!(
+                        "alerts.rules.{}: notify names unknown webhook '{}'",
+                        rule.name,
+                        notify
+                    );
+                }
+            }
         }
+        Ok(())
     }
 }
 
-/// Connection limits configuration.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LimitsConfig {
-    /// Maximum concurrent connections.
-    #[serde(default = "default_max_connections")]
-    pub max_connections: usize,
+fn default_alert_interval_secs() -> u64 {
+    10
+}
 
-    /// Connection timeout in seconds.
-    #[serde(default = "default_timeout")]
-    pub timeout: u64,
+/// Where alert transitions are posted (`[alerts.webhooks.<name>]`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// `http://` or `https://` URL receiving a JSON `POST` per transition.
+    pub url: String,
 
-    /// Idle timeout in seconds.
-    #[serde(default = "default_idle_timeout")]
-    pub idle_timeout: u64,
+    /// CA bundle for `https://` URLs (default: webpki roots).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<String>,
 }
 
-impl Default for LimitsConfig {
-    fn default() -> Self {
-        Self {
-            max_connections: default_max_connections(),
-            timeout: default_timeout(),
-            idle_timeout: default_idle_timeout(),
-        }
+/// Statistic an alert rule watches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertMetric {
+    /// Currently active connections.
+    ActiveConnections,
+    /// Bytes per second relayed over the last second.
+    Throughput,
+    /// Connections opened during the window.
+    Connections,
+    /// Requests refused by access control, hooks, quotas or connection
+    /// limits during the window.
+    DeniedAttempts,
+    /// Failed proxy authentications during the window.
+    AuthFailures,
+    /// Failed target connections during the window.
+    ConnectFailures,
+}
+
+impl AlertMetric {
+    /// Whether the metric counts events over the window rather than
+    /// reading a current value.
+    pub fn is_counter(self) -> bool {
+        !matches!(
+            self,
+            AlertMetric::ActiveConnections | AlertMetric::Throughput
+        )
     }
 }
 
-fn default_max_connections() -> usize {
-    1000
+impl std::fmt::Display for AlertMetric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AlertMetric::ActiveConnections => "active_connections",
+            AlertMetric::Throughput => "throughput",
+            AlertMetric::Connections => "connections",
+            AlertMetric::DeniedAttempts => "denied_attempts",
+            AlertMetric::AuthFailures => "auth_failures",
+            AlertMetric::ConnectFailures => "connect_failures",
+        })
+    }
 }
 
-fn default_timeout() -> u64 {
-    300
+/// How an alert rule compares its metric with the threshold.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertComparison {
+    /// Fire when the metric exceeds the threshold.
+    #[default]
+    Above,
+    /// Fire when the metric drops under the threshold.
+    Below,
 }
 
-fn default_idle_timeout() -> u64 {
+impl std::fmt::Display for AlertComparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AlertComparison::Above => "above",
+            AlertComparison::Below => "below",
+        })
+    }
+}
+
+/// A threshold on a statistic (`[[alerts.rules]]`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertRule {
+    /// Unique name, shown in `/api/alerts` and notifications.
+    pub name: String,
+
+    pub metric: AlertMetric,
+
+    #[serde(default)]
+    pub comparison: AlertComparison,
+
+    pub threshold: u64,
+
+    /// Value the metric must get back past for a firing alert to resolve
+    /// (default: `threshold`), so a value hovering at the threshold doesn't
+    /// flap.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolve_threshold: Option<u64>,
+
+    /// Seconds counter metrics are summed over; ignored by
+    /// `active_connections` and `throughput`.
+    #[serde(default = "default_alert_window_secs")]
+    pub window_secs: u64,
+
+    /// Name of the webhook in `alerts.webhooks` to post transitions to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify: Option<String>,
+}
+
+fn default_alert_window_secs() -> u64 {
     60
 }
 
-/// Statistics configuration.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct StatsConfig {
-    /// Enable statistics collection.
-    #[serde(default = "default_stats_enabled")]
+/// Scheduled snapshots of the config file (`[backup]`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupConfig {
+    /// Snapshot the config file periodically.
+    #[serde(default)]
     pub enabled: bool,
 
-    /// Retention period in hours.
-    #[serde(default = "default_retention_hours")]
-    pub retention_hours: u64,
+    /// Seconds between snapshots; unchanged files are not copied again.
+    #[serde(default = "default_backup_interval_secs")]
+    pub interval_secs: u64,
+
+    /// Directory the snapshots are written to.
+    #[serde(default = "default_backup_directory")]
+    pub directory: String,
+
+    /// Number of snapshots kept, oldest removed first (0 = keep all).
+    #[serde(default = "default_backup_retention")]
+    pub retention: usize,
 }
 
-impl Default for StatsConfig {
+impl Default for BackupConfig {
     fn default() -> Self {
         Self {
-            enabled: default_stats_enabled(),
-            retention_hours: default_retention_hours(),
+            enabled: false,
+            interval_secs: default_backup_interval_secs(),
+            directory: default_backup_directory(),
+            retention: default_backup_retention(),
         }
     }
 }
 
+fn default_backup_interval_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_backup_directory() -> String {
+    "backups".to_string()
+}
+
+fn default_backup_retention() -> usize {
+    30
+}
+
+fn default_max_history() -> usize {
+    1000
+}
+
+fn default_rate_window_secs() -> u64 {
+    10
+}
+
 fn default_stats_enabled() -> bool {
     true
 }
@@ -458,6 +4351,34 @@ fn default_retention_hours() -> u64 {
     24
 }
 
+fn default_quota_file() -> String {
+    "quota_usage.json".to_string()
+}
+
+fn default_checkpoint_interval_secs() -> u64 {
+    60
+}
+
+fn default_attribute_ipv4_prefix() -> u8 {
+    32
+}
+
+fn default_attribute_ipv6_prefix() -> u8 {
+    128
+}
+
+fn default_max_ip_buckets() -> usize {
+    1024
+}
+
+fn default_max_tracked_users() -> usize {
+    10_000
+}
+
+fn default_max_denied_attempts() -> usize {
+    1000
+}
+
 /// Access control configuration.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccessControlConfig {
@@ -470,12 +4391,30 @@ pub struct AccessControlConfig {
     pub ip_blacklist: Vec<String>,
 
     /// Domain/path rules.
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub rules: Vec<AccessRule>,
 
     /// Default behavior: true = allow all (blacklist mode), false = deny all (whitelist mode).
     #[serde(default = "default_allow_by_default")]
     pub allow_by_default: bool,
+
+    /// Targets that receive a PROXY protocol v2 header with the real client address
+    /// (domain patterns or IPs, supports wildcards: *.example.com).
+    #[serde(default)]
+    pub proxy_protocol_targets: Vec<String>,
+
+    /// Peek at TLS ClientHellos and apply domain rules to the SNI host name.
+    #[serde(default)]
+    pub inspect_sni: bool,
+
+    /// Text sent in the body of HTTP proxy responses for blocked targets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_message: Option<String>,
+
+    /// Evaluate and record every rule as if it were in `monitor` mode:
+    /// nothing is blocked, would-be denials are logged.
+    #[serde(default)]
+    pub monitor_only: bool,
 }
 
 impl Default for AccessControlConfig {
@@ -485,6 +4424,10 @@ impl Default for AccessControlConfig {
             ip_blacklist: Vec::new(),
             rules: Vec::new(),
             allow_by_default: true, // Blacklist mode by default
+            proxy_protocol_targets: Vec::new(),
+            inspect_sni: false,
+            block_message: None,
+            monitor_only: false,
         }
     }
 }
@@ -493,6 +4436,18 @@ fn default_allow_by_default() -> bool {
     true
 }
 
+/// Named set of access control and limits (`[profiles.<name>]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigProfile {
+    /// Access control in effect while the profile is active.
+    #[serde(default)]
+    pub access_control: AccessControlConfig,
+
+    /// Limits in effect while the profile is active (unchanged when unset).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<LimitsConfig>,
+}
+
 impl AccessControlConfig {
     /// Check if an IP is allowed.
     pub fn is_ip_allowed(&self, ip: &str) -> bool {
@@ -509,23 +4464,189 @@ impl AccessControlConfig {
         true
     }
 
-    /// Check if a target (domain + optional path) is allowed.
-    pub fn is_target_allowed(&self, host: &str, path: Option<&str>) -> bool {
-        // Find matching rules
-        for rule in &self.rules {
-            if rule.matches(host, path) {
-                return rule.action == RuleAction::Allow;
+    /// Check if a target (domain + port + optional path) is allowed.
+    pub fn is_target_allowed(&self, host: &str, port: u16, path: Option<&str>) -> bool {
+        self.first_match(host, Some(port), path)
+    }
+
+    /// Check a target whose port is unknown; rules with `ports` don't apply.
+    #[deprecated(note = "pass the target port to `is_target_allowed`")]
+    pub fn is_host_allowed(&self, host: &str, path: Option<&str>) -> bool {
+        self.first_match(host, None, path)
+    }
+
+    fn first_match(&self, host: &str, port: Option<u16>, path: Option<&str>) -> bool {
+        self.evaluate(host, port, path).allowed
+    }
+
+    /// Whether matches of `rule` are only recorded.
+    fn is_monitored(&self, rule: &AccessRule) -> bool {
+        self.monitor_only || rule.mode == RuleMode::Monitor
+    }
+
+    /// Evaluate an IP against the blacklist/whitelist and report which entries matched.
+    ///
+    /// The `allowed` field always agrees with [`is_ip_allowed`](Self::is_ip_allowed).
+    pub fn evaluate_ip(&self, ip: &str) -> IpDecision {
+        let blacklist_matches: Vec<String> = self
+            .ip_blacklist
+            .iter()
+            .filter(|b| ip_matches(ip, b))
+            .cloned()
+            .collect();
+        let whitelist_matches: Vec<String> = self
+            .ip_whitelist
+            .iter()
+            .filter(|w| ip_matches(ip, w))
+            .cloned()
+            .collect();
+        let whitelist_active = !self.ip_whitelist.is_empty();
+
+        let allowed =
+            blacklist_matches.is_empty() && (!whitelist_active || !whitelist_matches.is_empty());
+
+        IpDecision {
+            allowed,
+            blacklist_matches,
+            whitelist_matches,
+            whitelist_active,
+        }
+    }
+
+    /// Evaluate a target against the rule list and report the deciding rule.
+    ///
+    /// The `allowed` field always agrees with [`is_target_allowed`](Self::is_target_allowed).
+    pub fn evaluate_target(&self, host: &str, port: u16, path: Option<&str>) -> TargetDecision {
+        self.evaluate(host, Some(port), path)
+    }
+
+    fn evaluate(&self, host: &str, port: Option<u16>, path: Option<&str>) -> TargetDecision {
+        // Monitored rules are skipped, remembering the first one that matched
+        let mut monitored_rule = None;
+        let mut matched_rule = None;
+        for (index, rule) in self.rules.iter().enumerate() {
+            if !rule.matches(host, port, path) {
+                continue;
+            }
+            if !self.is_monitored(rule) {
+                matched_rule = Some(MatchedRule::new(index, rule));
+                break;
             }
+            if monitored_rule.is_none() {
+                monitored_rule = Some(MatchedRule::new(index, rule));
+            }
+        }
+
+        let allowed = match &matched_rule {
+            Some(rule) => rule.action == RuleAction::Allow,
+            None => self.monitor_only || self.allow_by_default,
+        };
+        let would_deny = allowed
+            && match &monitored_rule {
+                Some(rule) => rule.action == RuleAction::Deny,
+                None => matched_rule.is_none() && !self.allow_by_default,
+            };
+
+        TargetDecision {
+            allowed,
+            default_applied: matched_rule.is_none(),
+            matched_rule,
+            monitored_rule,
+            would_deny,
         }
+    }
+}
+
+/// Result of evaluating a client IP against the IP lists.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IpDecision {
+    /// Final outcome for the IP check.
+    pub allowed: bool,
+
+    /// Blacklist entries that matched the IP.
+    pub blacklist_matches: Vec<String>,
+
+    /// Whitelist entries that matched the IP.
+    pub whitelist_matches: Vec<String>,
+
+    /// Whether the whitelist is non-empty and therefore enforced.
+    pub whitelist_active: bool,
+}
+
+/// Result of evaluating a target against the access rules.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetDecision {
+    /// Final outcome for the target check.
+    pub allowed: bool,
 
-        // No matching rule, use default behavior
-        self.allow_by_default
+    /// The first enabled, enforced rule that matched, if any.
+    pub matched_rule: Option<MatchedRule>,
+
+    /// Whether `allow_by_default` decided the outcome.
+    pub default_applied: bool,
+
+    /// The first monitored rule that matched ahead of `matched_rule`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monitored_rule: Option<MatchedRule>,
+
+    /// Allowed only because the denying rule (or, with `monitor_only`, the
+    /// default policy) is monitored.
+    #[serde(default)]
+    pub would_deny: bool,
+}
+
+/// Reference to the access rule that decided a target check.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchedRule {
+    /// Position of the rule in `access_control.rules`.
+    pub index: usize,
+
+    /// Rule identifier.
+    pub id: String,
+
+    /// Rule name.
+    pub name: String,
+
+    /// Domain pattern of the rule.
+    pub domain: String,
+
+    /// Path pattern of the rule.
+    pub path: Option<String>,
+
+    /// Ports of the rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ports: Option<PortRanges>,
+
+    /// Action taken by the rule.
+    pub action: RuleAction,
+
+    /// Whether the rule is enforced or only monitored.
+    #[serde(default)]
+    pub mode: RuleMode,
+}
+
+impl MatchedRule {
+    fn new(index: usize, rule: &AccessRule) -> Self {
+        Self {
+            index,
+            id: rule.id.clone(),
+            name: rule.name.clone(),
+            domain: rule.domain.clone(),
+            path: rule.path.clone(),
+            ports: rule.ports.clone(),
+            action: rule.action.clone(),
+            mode: rule.mode,
+        }
     }
 }
 
 /// Access control rule.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccessRule {
+    /// Identifier used to update the rule (generated when missing).
+    #[serde(default = "new_rule_id")]
+    pub id: String,
+
     /// Rule name/description.
     #[serde(default)]
     pub name: String,
@@ -533,22 +4654,62 @@ pub struct AccessRule {
     /// Domain pattern (supports wildcards: *.example.com).
     pub domain: String,
 
+    /// Unicode form of an internationalized `domain`, which is kept in
+    /// punycode (see [`AccessRule::normalize_domain`]); for display only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain_unicode: Option<String>,
+
     /// Path pattern (optional, supports prefix match).
     #[serde(default)]
     pub path: Option<String>,
 
+    /// Target ports the rule applies to (`None` = all ports).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ports: Option<PortRanges>,
+
     /// Action to take.
     pub action: RuleAction,
 
+    /// `monitor` records matches without applying the action.
+    #[serde(default, skip_serializing_if = "RuleMode::is_enforce")]
+    pub mode: RuleMode,
+
     /// Whether this rule is enabled.
     #[serde(default = "default_true")]
     pub enabled: bool,
+
+    /// When the rule stops matching (`None` = never). Expired rules are
+    /// removed from the configuration an hour later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Random identifier for an [`AccessRule`].
+pub fn new_rule_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()[..12].to_string()
 }
 
 impl AccessRule {
-    /// Check if this rule matches the given host and path.
-    pub fn matches(&self, host: &str, path: Option<&str>) -> bool {
-        if !self.enabled {
+    /// Store `domain` in its normalized form (lowercase, punycode, no
+    /// trailing dot) and remember its Unicode form for display.
+    pub fn normalize_domain(&mut self) -> std::result::Result<(), InvalidHostname> {
+        if let Cow::Owned(domain) = hostname::normalize_pattern(&self.domain)? {
+            self.domain = domain;
+        }
+        self.domain_unicode = hostname::to_unicode(&self.domain);
+        Ok(())
+    }
+
+    /// Whether the rule has expired at `now`.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Check if this rule matches the given host, port and path.
+    ///
+    /// A `None` port only matches rules without `ports`.
+    pub fn matches(&self, host: &str, port: Option<u16>, path: Option<&str>) -> bool {
+        if !self.enabled || self.is_expired(Utc::now()) {
             return false;
         }
 
@@ -557,6 +4718,13 @@ impl AccessRule {
             return false;
         }
 
+        // Check port if specified
+        if let Some(ports) = &self.ports {
+            if !port.is_some_and(|port| ports.contains(port)) {
+                return false;
+            }
+        }
+
         // Check path if specified
         if let Some(rule_path) = &self.path {
             if let Some(request_path) = path {
@@ -569,6 +4737,128 @@ impl AccessRule {
     }
 }
 
+/// Ports and port ranges, e.g. `[80, 443, "8000-8100"]` or `"80,443,8000-8100"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortRanges(Vec<RangeInclusive<u16>>);
+
+impl PortRanges {
+    /// Whether `port` is in any of the ranges.
+    pub fn contains(&self, port: u16) -> bool {
+        self.0.iter().any(|range| range.contains(&port))
+    }
+
+    /// The ranges (single ports are ranges of one).
+    pub fn ranges(&self) -> &[RangeInclusive<u16>] {
+        &self.0
+    }
+
+    fn parse_entry(entry: &str) -> std::result::Result<RangeInclusive<u16>, String> {
+        let entry = entry.trim();
+        let parse = |port: &str| {
+            port.trim()
+                .parse::<u16>()
+                .map_err(|_| format!("invalid port '{}'", port.trim()))
+        };
+        let range = match entry.split_once('-') {
+            Some((start, end)) => parse(start)?..=parse(end)?,
+            None => parse(entry)?..=parse(entry)?,
+        };
+        if range.is_empty() {
+            return Err(format!("invalid port range '{}'", entry));
+        }
+        Ok(range)
+    }
+}
+
+impl std::str::FromStr for PortRanges {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let ranges = s
+            .split(',')
+            .map(Self::parse_entry)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(Self(ranges))
+    }
+}
+
+impl std::fmt::Display for PortRanges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, range) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            if range.start() == range.end() {
+                write!(f, "{}", range.start())?;
+            } else {
+                write!(f, "{}-{}", range.start(), range.end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A port or a `start-end` range, as written in configuration.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum PortEntry {
+    Port(u16),
+    Range(String),
+}
+
+impl Serialize for PortRanges {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        let entries: Vec<PortEntry> = self
+            .0
+            .iter()
+            .map(|range| {
+                if range.start() == range.end() {
+                    PortEntry::Port(*range.start())
+                } else {
+                    PortEntry::Range(format!("{}-{}", range.start(), range.end()))
+                }
+            })
+            .collect();
+        entries.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PortRanges {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            One(PortEntry),
+            List(Vec<PortEntry>),
+        }
+
+        let entries = match Raw::deserialize(deserializer)? {
+            Raw::One(entry) => vec![entry],
+            Raw::List(entries) => entries,
+        };
+        let mut ranges = Vec::new();
+        for entry in entries {
+            match entry {
+                PortEntry::Port(port) => ranges.push(port..=port),
+                PortEntry::Range(list) => ranges.extend(
+                    list.parse::<PortRanges>()
+                        .map_err(serde::de::Error::custom)?
+                        .0,
+                ),
+            }
+        }
+        if ranges.is_empty() {
+            return Err(serde::de::Error::custom("ports must not be empty"));
+        }
+        Ok(Self(ranges))
+    }
+}
+
 /// Rule action.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -577,18 +4867,113 @@ pub enum RuleAction {
     Deny,
 }
 
+/// Whether a rule's action is applied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleMode {
+    /// Apply the action.
+    #[default]
+    Enforce,
+    /// Record matches (as would-be denials for `deny` rules) and move on to
+    /// the next rule.
+    Monitor,
+}
+
+impl RuleMode {
+    fn is_enforce(&self) -> bool {
+        *self == RuleMode::Enforce
+    }
+}
+
 /// Check if an IP matches a pattern (supports exact match and CIDR).
-fn ip_matches(ip: &str, pattern: &str) -> bool {
-    if pattern.contains('/') {
-        // CIDR notation - simplified check (exact implementation would use ipnetwork crate)
-        ip.starts_with(pattern.split('/').next().unwrap_or(""))
-    } else {
-        ip == pattern
+///
+/// IPv4-mapped IPv6 addresses are compared as IPv4. Invalid patterns never match.
+pub fn ip_matches(ip: &str, pattern: &str) -> bool {
+    let Ok(addr) = ip.parse::<IpAddr>() else {
+        return ip == pattern;
+    };
+    let addr = addr.to_canonical();
+
+    let (network, prefix) = match pattern.split_once('/') {
+        Some((network, prefix)) => match prefix.trim().parse::<u8>() {
+            Ok(prefix) => (network.trim(), Some(prefix)),
+            Err(_) => return false,
+        },
+        None => (pattern.trim(), None),
+    };
+    let Ok(network) = network.parse::<IpAddr>() else {
+        return false;
+    };
+    let network = network.to_canonical();
+
+    match (addr, network) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => {
+            let prefix = prefix.unwrap_or(32);
+            if prefix > 32 {
+                return false;
+            }
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(a) & mask == u32::from(n) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(n)) => {
+            let prefix = prefix.unwrap_or(128);
+            if prefix > 128 {
+                return false;
+            }
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(a) & mask == u128::from(n) & mask
+        }
+        _ => false,
     }
 }
 
+/// Check if an IP matches any pattern in a list.
+pub fn ip_in_list(ip: &str, patterns: &[String]) -> bool {
+    patterns.iter().any(|p| ip_matches(ip, p))
+}
+
 /// Check if a domain matches a pattern (supports wildcards).
+/// Compare two byte strings without short-circuiting on the first difference.
+/// Whether `value` is an IP address or a CIDR range.
+fn is_ip_or_cidr(value: &str) -> bool {
+    let (network, prefix) = match value.split_once('/') {
+        Some((network, prefix)) => (network, Some(prefix)),
+        None => (value, None),
+    };
+    let Ok(network) = network.trim().parse::<IpAddr>() else {
+        return false;
+    };
+    let max = if network.is_ipv4() { 32 } else { 128 };
+    prefix.is_none_or(|prefix| {
+        prefix
+            .trim()
+            .parse::<u8>()
+            .is_ok_and(|prefix| prefix <= max)
+    })
+}
+
+/// Whether `value` is a bare origin: `scheme://host[:port]`, without a path.
+fn is_origin(value: &str) -> bool {
+    let value = value.trim_end_matches('/');
+    match value.split_once("://") {
+        Some((scheme, host)) => {
+            !scheme.is_empty()
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+                && !host.is_empty()
+                && !host.contains(['/', '?', '#', ' ', '*'])
+        }
+        None => false,
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 fn domain_matches(domain: &str, pattern: &str) -> bool {
+    let pattern = hostname::normalize_pattern(pattern).unwrap_or(Cow::Borrowed(pattern));
     if pattern.starts_with("*.") {
         // Wildcard match
         let suffix = &pattern[1..]; // ".example.com"
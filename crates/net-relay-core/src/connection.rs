@@ -18,19 +18,164 @@ pub enum ConnectionState {
     Closed,
 }
 
+/// Most tags on a connection or user.
+pub const MAX_TAGS: usize = 16;
+
+/// Longest tag, in characters.
+pub const MAX_TAG_LENGTH: usize = 64;
+
+/// Longest note on a connection or user, in characters.
+pub const MAX_NOTE_LENGTH: usize = 1024;
+
+/// Trim and deduplicate tags, keeping their order.
+///
+/// Empty tags, tags longer than [`MAX_TAG_LENGTH`] or containing whitespace
+/// or control characters, and more than [`MAX_TAGS`] tags are refused. Errors
+/// read as a field message, e.g. `must have at most 16 tags`.
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags {
+        let tag = tag.trim();
+        if tag.is_empty() {
+            return Err("must not contain empty tags".to_string());
+        }
+        if tag.chars().count() > MAX_TAG_LENGTH {
+            return Err(format!(
+                "must be at most {} characters each",
+                MAX_TAG_LENGTH
+            ));
+        }
+        if tag.chars().any(|c| c.is_control() || c.is_whitespace()) {
+            return Err(format!(
+                "tag '{}' must not contain whitespace or control characters",
+                tag.escape_debug()
+            ));
+        }
+        if !normalized.iter().any(|t| t == tag) {
+            normalized.push(tag.to_string());
+        }
+    }
+    if normalized.len() > MAX_TAGS {
+        return Err(format!("must have at most {} tags", MAX_TAGS));
+    }
+    Ok(normalized)
+}
+
+/// Trim a note; an empty note removes it.
+pub fn normalize_note(note: &str) -> Result<Option<String>, String> {
+    let note = note.trim();
+    if note.chars().count() > MAX_NOTE_LENGTH {
+        return Err(format!("must be at most {} characters", MAX_NOTE_LENGTH));
+    }
+    Ok(Some(note.to_string()).filter(|note| !note.is_empty()))
+}
+
 /// Protocol type for the connection.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
+#[non_exhaustive]
 pub enum Protocol {
     /// SOCKS5 proxy protocol.
     Socks5,
     /// HTTP CONNECT proxy protocol.
     HttpConnect,
+    /// A protocol added by a later release, read from its history.
+    #[serde(untagged)]
+    Other(String),
+}
+
+impl Protocol {
+    /// Short lowercase name used in metric tags and trace attributes.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Protocol::Socks5 => "socks5",
+            Protocol::HttpConnect => "http",
+            Protocol::Other(name) => name,
+        }
+    }
+}
+
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Protocol::Socks5 => write!(f, "SOCKS5"),
+            Protocol::HttpConnect => write!(f, "HTTP"),
+            Protocol::Other(name) => write!(f, "{}", name.to_uppercase()),
+        }
+    }
+}
+
+/// Why a relayed connection ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// Client closed its side of the connection.
+    #[serde(alias = "client_closed")]
+    ClientEof,
+    /// Target closed its side of the connection.
+    #[serde(alias = "target_closed")]
+    TargetEof,
+    /// Reading from or writing to the client failed.
+    ClientError,
+    /// Reading from or writing to the target failed.
+    TargetError,
+    /// No data moved in either direction for too long.
+    IdleTimeout,
+    /// Terminated by an administrator.
+    Killed,
+    /// The connection reached its transfer limit.
+    TransferCap,
+    /// The user's data quota ran out.
+    QuotaExceeded,
+    /// The server shut down while the connection was open.
+    Shutdown,
+    /// The connection reached `limits.max_connection_lifetime_secs`.
+    LifetimeExceeded,
+    /// The relay ended without recording the close; found by
+    /// [`Stats::sweep_orphans`](crate::Stats::sweep_orphans).
+    Orphaned,
+    /// The connection's task panicked.
+    InternalError,
+}
+
+impl std::fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            CloseReason::ClientEof => "client_eof",
+            CloseReason::TargetEof => "target_eof",
+            CloseReason::ClientError => "client_error",
+            CloseReason::TargetError => "target_error",
+            CloseReason::IdleTimeout => "idle_timeout",
+            CloseReason::Killed => "killed",
+            CloseReason::TransferCap => "transfer_cap",
+            CloseReason::QuotaExceeded => "quota_exceeded",
+            CloseReason::Shutdown => "shutdown",
+            CloseReason::LifetimeExceeded => "lifetime_exceeded",
+            CloseReason::Orphaned => "orphaned",
+            CloseReason::InternalError => "internal_error",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Version of the [`ConnectionInfo`] format written by this release.
+///
+/// Fields are only ever added with a default, so entries from older releases
+/// still read; the version goes up when the meaning of a field changes.
+/// Entries without one predate the field and are version 1.
+pub const CONNECTION_SCHEMA_VERSION: u32 = 1;
+
+fn legacy_schema_version() -> u32 {
+    1
 }
 
 /// Information about a single connection.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConnectionInfo {
+    /// Format version, see [`CONNECTION_SCHEMA_VERSION`].
+    #[serde(default = "legacy_schema_version")]
+    pub schema_version: u32,
+
     /// Unique connection identifier.
     pub id: Uuid,
 
@@ -40,9 +185,18 @@ pub struct ConnectionInfo {
     /// Client address.
     pub client_addr: String,
 
+    /// Reverse-DNS name of the client (when `stats.resolve_client_hostnames` is on).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_hostname: Option<String>,
+
     /// Target address (destination).
     pub target_addr: String,
 
+    /// Target host as the client sent it, when that differs from the
+    /// normalized `target_addr` (e.g. `EXAMPLE.com.` or a Unicode name).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_host: Option<String>,
+
     /// Target port.
     pub target_port: u16,
 
@@ -52,7 +206,68 @@ pub struct ConnectionInfo {
     /// When the connection was established.
     pub connected_at: DateTime<Utc>,
 
+    /// When the client's TCP connection was accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accepted_at: Option<DateTime<Utc>>,
+
+    /// When the client finished authenticating (or was let in without credentials).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authenticated_at: Option<DateTime<Utc>>,
+
+    /// When the connection to the target was established.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_connected_at: Option<DateTime<Utc>>,
+
+    /// Attempts it took to connect to the target (more than 1 after retries).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_attempts: Option<u32>,
+
+    /// When relaying started (the connection became active).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_at: Option<DateTime<Utc>>,
+
+    /// When the first byte arrived from the target.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_byte_at: Option<DateTime<Utc>>,
+
+    /// When the first byte from the client was relayed to the target.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_byte_sent_at: Option<DateTime<Utc>>,
+
+    /// Milliseconds from accept until the client was authenticated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handshake_ms: Option<u64>,
+
+    /// Milliseconds spent connecting to the target (DNS, upstream relays and TCP).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_ms: Option<u64>,
+
+    /// Milliseconds of `connect_ms` spent resolving the target host name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolve_ms: Option<u64>,
+
+    /// Milliseconds from the target connection until its first byte.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttfb_ms: Option<u64>,
+
+    /// When data last moved in either direction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_activity_at: Option<DateTime<Utc>>,
+
+    /// When the connection started closing (graceful shutdown).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closing_at: Option<DateTime<Utc>>,
+
+    /// When the relay is closed for reaching its maximum lifetime.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+
+    /// Seconds left until `expires_at` (active connections only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining_lifetime_secs: Option<u64>,
+
     /// When the connection was closed (if applicable).
+    #[serde(default)]
     pub closed_at: Option<DateTime<Utc>>,
 
     /// Bytes sent to target.
@@ -61,9 +276,50 @@ pub struct ConnectionInfo {
     /// Bytes received from target.
     pub bytes_received: u64,
 
+    /// Bytes per second sent to the target over the rate window (active connections only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_rate_sent: Option<u64>,
+
+    /// Bytes per second received from the target over the rate window (active connections only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_rate_received: Option<u64>,
+
     /// Authenticated username (if any).
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub username: Option<String>,
+
+    /// Server name from the client's TLS ClientHello (when SNI inspection is enabled).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sni: Option<String>,
+
+    /// Downstream relay node that forwarded this connection (tunnel connections only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub via: Option<String>,
+
+    /// Upstream relay this connection was tunneled through.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+
+    /// Local address the outbound connection was bound to (from `egress.addresses`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub egress: Option<String>,
+
+    /// Why the connection ended (set once closed).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub close_reason: Option<CloseReason>,
+
+    /// Labels attached through the API, e.g. `under-investigation`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    /// Free-form note attached through the API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+
+    /// Set on active connections whose relay is no longer running; they are
+    /// closed as [`CloseReason::Orphaned`] by the next sweep.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
 }
 
 impl ConnectionInfo {
@@ -75,17 +331,45 @@ impl ConnectionInfo {
         target_port: u16,
     ) -> Self {
         Self {
+            schema_version: CONNECTION_SCHEMA_VERSION,
             id: Uuid::new_v4(),
             protocol,
             client_addr,
+            client_hostname: None,
             target_addr,
+            requested_host: None,
             target_port,
             state: ConnectionState::Connecting,
             connected_at: Utc::now(),
+            accepted_at: None,
+            authenticated_at: None,
+            target_connected_at: None,
+            connect_attempts: None,
+            active_at: None,
+            first_byte_at: None,
+            first_byte_sent_at: None,
+            handshake_ms: None,
+            connect_ms: None,
+            resolve_ms: None,
+            ttfb_ms: None,
+            last_activity_at: None,
+            closing_at: None,
+            expires_at: None,
+            remaining_lifetime_secs: None,
             closed_at: None,
             bytes_sent: 0,
             bytes_received: 0,
+            current_rate_sent: None,
+            current_rate_received: None,
             username: None,
+            sni: None,
+            via: None,
+            upstream: None,
+            egress: None,
+            close_reason: None,
+            tags: Vec::new(),
+            note: None,
+            stale: false,
         }
     }
 
@@ -98,17 +382,45 @@ impl ConnectionInfo {
         username: Option<String>,
     ) -> Self {
         Self {
+            schema_version: CONNECTION_SCHEMA_VERSION,
             id: Uuid::new_v4(),
             protocol,
             client_addr,
+            client_hostname: None,
             target_addr,
+            requested_host: None,
             target_port,
             state: ConnectionState::Connecting,
             connected_at: Utc::now(),
+            accepted_at: None,
+            authenticated_at: None,
+            target_connected_at: None,
+            connect_attempts: None,
+            active_at: None,
+            first_byte_at: None,
+            first_byte_sent_at: None,
+            handshake_ms: None,
+            connect_ms: None,
+            resolve_ms: None,
+            ttfb_ms: None,
+            last_activity_at: None,
+            closing_at: None,
+            expires_at: None,
+            remaining_lifetime_secs: None,
             closed_at: None,
             bytes_sent: 0,
             bytes_received: 0,
+            current_rate_sent: None,
+            current_rate_received: None,
             username,
+            sni: None,
+            via: None,
+            upstream: None,
+            egress: None,
+            close_reason: None,
+            tags: Vec::new(),
+            note: None,
+            stale: false,
         }
     }
 
@@ -120,11 +432,54 @@ impl ConnectionInfo {
     /// Mark the connection as active.
     pub fn set_active(&mut self) {
         self.state = ConnectionState::Active;
+        self.active_at = Some(Utc::now());
+    }
+
+    /// Record when the client was accepted and when it finished authenticating.
+    pub fn set_handshake(&mut self, accepted_at: DateTime<Utc>, authenticated_at: DateTime<Utc>) {
+        self.accepted_at = Some(accepted_at);
+        self.authenticated_at = Some(authenticated_at);
+        self.handshake_ms = Some(elapsed_ms(accepted_at, authenticated_at));
+    }
+
+    /// Record a target connection that took `attempts` attempts, the first
+    /// beginning at `started_at`, and succeeded at `connected_at`.
+    pub fn set_target_connected(
+        &mut self,
+        started_at: DateTime<Utc>,
+        connected_at: DateTime<Utc>,
+        attempts: u32,
+    ) {
+        self.target_connected_at = Some(connected_at);
+        self.connect_attempts = Some(attempts);
+        self.connect_ms = Some(elapsed_ms(started_at, connected_at));
+        self.update_ttfb();
+    }
+
+    /// Record when the first byte arrived from the target (first call wins).
+    pub fn set_first_byte(&mut self, at: DateTime<Utc>) {
+        self.first_byte_at.get_or_insert(at);
+        self.update_ttfb();
+    }
+
+    /// Record when the first client byte was relayed to the target (first call wins).
+    pub fn set_first_byte_sent(&mut self, at: DateTime<Utc>) {
+        self.first_byte_sent_at.get_or_insert(at);
+    }
+
+    fn update_ttfb(&mut self) {
+        if let (Some(connected), Some(first_byte)) = (self.target_connected_at, self.first_byte_at)
+        {
+            self.ttfb_ms = Some(elapsed_ms(connected, first_byte));
+        }
     }
 
     /// Mark the connection as closing.
     pub fn set_closing(&mut self) {
-        self.state = ConnectionState::Closing;
+        if self.state != ConnectionState::Closed {
+            self.state = ConnectionState::Closing;
+            self.closing_at.get_or_insert_with(Utc::now);
+        }
     }
 
     /// Mark the connection as closed.
@@ -150,6 +505,11 @@ impl ConnectionInfo {
     }
 }
 
+/// Whole milliseconds from `start` to `end` (0 if the clock went backwards).
+fn elapsed_ms(start: DateTime<Utc>, end: DateTime<Utc>) -> u64 {
+    (end - start).num_milliseconds().max(0) as u64
+}
+
 /// A wrapper around an active connection for tracking.
 #[derive(Debug)]
 pub struct Connection {
@@ -0,0 +1,865 @@
+//! Target name resolution over system DNS, plain UDP, DNS-over-TLS or DNS-over-HTTPS,
+//! plus reverse (PTR) lookups of client addresses.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+use tracing::{debug, warn};
+
+use crate::config::{DnsConfig, DnsMode};
+use crate::error::{Error, Result};
+use crate::latency::{LatencyHistogram, LatencySummary};
+use crate::tls;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// Cached answers are kept at least this long.
+const MIN_CACHE_TTL: u32 = 5;
+
+/// Cached answers are kept at most this long.
+const MAX_CACHE_TTL: u32 = 3600;
+
+/// Failed reverse lookups are retried after this many seconds.
+const NEGATIVE_REVERSE_TTL: u32 = 300;
+
+/// Largest DNS response accepted over any transport.
+const MAX_RESPONSE_LEN: usize = 65535;
+
+/// Resolver configuration consulted for reverse lookups in system mode.
+const SYSTEM_RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Name system lookups are reported under.
+const SYSTEM_RESOLVER: &str = "system";
+
+/// Cached resolution result.
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    addrs: Vec<IpAddr>,
+    expires_at: Instant,
+}
+
+/// Cached reverse lookup result (`None` = lookup failed).
+#[derive(Debug, Clone)]
+struct ReverseEntry {
+    hostname: Option<String>,
+    expires_at: Instant,
+}
+
+/// Resolver counters.
+#[derive(Debug, Clone, Serialize)]
+pub struct DnsStats {
+    /// Configured resolution mode.
+    pub mode: DnsMode,
+
+    /// Lookups answered by the configured resolver (cache misses).
+    pub lookups: u64,
+
+    /// Lookups answered from the cache.
+    pub cache_hits: u64,
+
+    /// Lookups that failed on the configured resolver.
+    pub failures: u64,
+
+    /// Failed lookups retried with system DNS (`fallback = true`).
+    pub fallbacks: u64,
+
+    /// Entries currently cached.
+    pub cache_entries: usize,
+
+    /// Reverse lookups of client addresses sent to a resolver.
+    pub reverse_lookups: u64,
+
+    /// Reverse lookup results (including failures) currently cached.
+    pub reverse_cache_entries: usize,
+
+    /// Forward lookups per resolver used since start, including system DNS.
+    pub resolvers: Vec<ResolverStats>,
+}
+
+/// Forward lookups sent to one resolver.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolverStats {
+    /// Resolver address, or `system`.
+    pub resolver: String,
+
+    pub mode: DnsMode,
+
+    /// Lookups sent to the resolver.
+    pub lookups: u64,
+
+    /// Lookups that failed, including timeouts.
+    pub failures: u64,
+
+    /// Lookups that ran out of `timeout_secs`.
+    pub timeouts: u64,
+
+    /// Lookups that took at least `slow_lookup_ms`.
+    pub slow_lookups: u64,
+
+    /// Lookup durations, failed ones included.
+    pub latency: LatencySummary,
+}
+
+/// Live counters of one resolver.
+#[derive(Debug, Default)]
+struct ResolverCounters {
+    lookups: u64,
+    failures: u64,
+    timeouts: u64,
+    slow_lookups: u64,
+    latency: LatencyHistogram,
+}
+
+/// How a forward lookup ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LookupOutcome {
+    Answered,
+    Failed,
+    TimedOut,
+}
+
+/// Resolver for proxy targets with a bounded TTL cache.
+///
+/// The `[dns]` configuration is passed on every call so runtime config
+/// changes apply without rebuilding the resolver.
+#[derive(Debug, Default)]
+pub struct DnsResolver {
+    cache: Mutex<HashMap<String, CacheEntry>>,
+    reverse_cache: Mutex<HashMap<IpAddr, ReverseEntry>>,
+    resolvers: Mutex<HashMap<(DnsMode, String), ResolverCounters>>,
+    lookups: AtomicU64,
+    reverse_lookups: AtomicU64,
+    cache_hits: AtomicU64,
+    failures: AtomicU64,
+    fallbacks: AtomicU64,
+    next_id: AtomicU64,
+}
+
+impl DnsResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve a target host to IP addresses.
+    pub async fn resolve(&self, host: &str, config: &DnsConfig) -> Result<Vec<IpAddr>> {
+        if let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse() {
+            return Ok(vec![ip]);
+        }
+        if config.mode == DnsMode::System {
+            return self.system_lookup(host, config).await;
+        }
+
+        let name = host.trim_end_matches('.').to_ascii_lowercase();
+        if let Some(addrs) = self.cached(&name) {
+            self.cache_hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(addrs);
+        }
+
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        let timeout = Duration::from_secs(config.timeout_secs.max(1));
+        let started = Instant::now();
+        let (result, outcome) =
+            match tokio::time::timeout(timeout, self.query_both(&name, config)).await {
+                Ok(Ok(answer)) => (Ok(answer), LookupOutcome::Answered),
+                Ok(Err(e)) => (Err(e), LookupOutcome::Failed),
+                Err(_) => (
+                    Err(Error::AddressResolution(format!("{}: timed out", name))),
+                    LookupOutcome::TimedOut,
+                ),
+            };
+        let resolver = config.resolver.as_deref().unwrap_or_default();
+        self.record_lookup(
+            &name,
+            config.mode,
+            resolver,
+            config,
+            started.elapsed(),
+            outcome,
+        );
+        let result = result.map_err(|e| match e {
+            Error::AddressResolution(_) => e,
+            other => Error::AddressResolution(format!("{}: {}", name, other)),
+        });
+
+        match result {
+            Ok((addrs, ttl)) => {
+                self.store(&name, addrs.clone(), ttl, config.cache_size);
+                Ok(addrs)
+            }
+            Err(e) => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+                if config.fallback {
+                    warn!(
+                        "DNS lookup for {} failed ({}), falling back to system DNS",
+                        name, e
+                    );
+                    self.fallbacks.fetch_add(1, Ordering::Relaxed);
+                    return self.system_lookup(host, config).await;
+                }
+                warn!("DNS lookup for {} failed: {}", name, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// [`system_lookup`], counted and timed as the `system` resolver.
+    async fn system_lookup(&self, host: &str, config: &DnsConfig) -> Result<Vec<IpAddr>> {
+        let started = Instant::now();
+        let result = system_lookup(host).await;
+        let outcome = match result {
+            Ok(_) => LookupOutcome::Answered,
+            Err(_) => LookupOutcome::Failed,
+        };
+        self.record_lookup(
+            host,
+            DnsMode::System,
+            SYSTEM_RESOLVER,
+            config,
+            started.elapsed(),
+            outcome,
+        );
+        result
+    }
+
+    /// Count a forward lookup of `host` on `resolver`, warning when it was
+    /// slower than `slow_lookup_ms`.
+    fn record_lookup(
+        &self,
+        host: &str,
+        mode: DnsMode,
+        resolver: &str,
+        config: &DnsConfig,
+        elapsed: Duration,
+        outcome: LookupOutcome,
+    ) {
+        let elapsed_ms = elapsed.as_millis() as u64;
+        let slow = config.slow_lookup_ms > 0 && elapsed_ms >= config.slow_lookup_ms;
+        {
+            let mut resolvers = self.resolvers.lock().unwrap();
+            let counters = resolvers.entry((mode, resolver.to_string())).or_default();
+            counters.lookups += 1;
+            if outcome != LookupOutcome::Answered {
+                counters.failures += 1;
+            }
+            if outcome == LookupOutcome::TimedOut {
+                counters.timeouts += 1;
+            }
+            if slow {
+                counters.slow_lookups += 1;
+            }
+            counters.latency.record(elapsed_ms);
+        }
+        if slow {
+            warn!(
+                host,
+                resolver,
+                mode = ?mode,
+                elapsed_ms,
+                threshold_ms = config.slow_lookup_ms,
+                outcome = ?outcome,
+                "Slow DNS lookup"
+            );
+        }
+    }
+
+    /// Snapshot of the resolver counters.
+    pub fn stats(&self, config: &DnsConfig) -> DnsStats {
+        DnsStats {
+            mode: config.mode,
+            lookups: self.lookups.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            fallbacks: self.fallbacks.load(Ordering::Relaxed),
+            cache_entries: self.cache.lock().unwrap().len(),
+            reverse_lookups: self.reverse_lookups.load(Ordering::Relaxed),
+            reverse_cache_entries: self.reverse_cache.lock().unwrap().len(),
+            resolvers: self.resolver_stats(),
+        }
+    }
+
+    fn resolver_stats(&self) -> Vec<ResolverStats> {
+        let resolvers = self.resolvers.lock().unwrap();
+        let mut stats: Vec<ResolverStats> = resolvers
+            .iter()
+            .map(|((mode, resolver), counters)| ResolverStats {
+                resolver: resolver.clone(),
+                mode: *mode,
+                lookups: counters.lookups,
+                failures: counters.failures,
+                timeouts: counters.timeouts,
+                slow_lookups: counters.slow_lookups,
+                latency: counters.latency.summary(),
+            })
+            .collect();
+        stats.sort_by(|a, b| a.resolver.cmp(&b.resolver));
+        stats
+    }
+
+    /// Cached reverse lookup result for `ip`: `Some(None)` if the last lookup failed.
+    pub fn cached_reverse(&self, ip: IpAddr) -> Option<Option<String>> {
+        let cache = self.reverse_cache.lock().unwrap();
+        cache
+            .get(&ip)
+            .filter(|entry| entry.expires_at > Instant::now())
+            .map(|entry| entry.hostname.clone())
+    }
+
+    /// Look up the host name of `ip` with a PTR query.
+    ///
+    /// Uses the configured resolver, or the first resolv.conf nameserver in
+    /// system mode. Failures and timeouts return `None` and are cached too.
+    pub async fn reverse(&self, ip: IpAddr, config: &DnsConfig) -> Option<String> {
+        let ip = ip.to_canonical();
+        if let Some(hostname) = self.cached_reverse(ip) {
+            return hostname;
+        }
+
+        self.reverse_lookups.fetch_add(1, Ordering::Relaxed);
+        let name = reverse_name(ip);
+        let timeout = Duration::from_secs(config.timeout_secs.max(1));
+        let lookup = async {
+            let answer = match config.mode {
+                DnsMode::System => {
+                    let nameserver = system_nameserver().ok_or_else(|| {
+                        Error::AddressResolution("no nameserver in resolv.conf".into())
+                    })?;
+                    let config = DnsConfig {
+                        mode: DnsMode::Udp,
+                        resolver: Some(nameserver),
+                        ..config.clone()
+                    };
+                    self.query(&name, TYPE_PTR, &config).await?
+                }
+                _ => self.query(&name, TYPE_PTR, config).await?,
+            };
+            Ok::<_, Error>(answer)
+        };
+
+        let (hostname, ttl) = match tokio::time::timeout(timeout, lookup).await {
+            Ok(Ok(answer)) if !answer.names.is_empty() => {
+                (answer.names.into_iter().next(), answer.ttl)
+            }
+            Ok(Ok(_)) => (None, NEGATIVE_REVERSE_TTL),
+            Ok(Err(e)) => {
+                debug!("Reverse lookup for {} failed: {}", ip, e);
+                (None, NEGATIVE_REVERSE_TTL)
+            }
+            Err(_) => {
+                debug!("Reverse lookup for {} timed out", ip);
+                (None, NEGATIVE_REVERSE_TTL)
+            }
+        };
+        self.store_reverse(ip, hostname.clone(), ttl, config.cache_size);
+        hostname
+    }
+
+    fn store_reverse(&self, ip: IpAddr, hostname: Option<String>, ttl: u32, max_entries: usize) {
+        if max_entries == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut cache = self.reverse_cache.lock().unwrap();
+        if cache.len() >= max_entries {
+            cache.retain(|_, entry| entry.expires_at > now);
+        }
+        if cache.len() >= max_entries {
+            if let Some(oldest) = cache
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(key, _)| *key)
+            {
+                cache.remove(&oldest);
+            }
+        }
+        let ttl = ttl.clamp(MIN_CACHE_TTL, MAX_CACHE_TTL);
+        cache.insert(
+            ip,
+            ReverseEntry {
+                hostname,
+                expires_at: now + Duration::from_secs(ttl as u64),
+            },
+        );
+    }
+
+    fn cached(&self, name: &str) -> Option<Vec<IpAddr>> {
+        let cache = self.cache.lock().unwrap();
+        cache
+            .get(name)
+            .filter(|entry| entry.expires_at > Instant::now())
+            .map(|entry| entry.addrs.clone())
+    }
+
+    fn store(&self, name: &str, addrs: Vec<IpAddr>, ttl: u32, max_entries: usize) {
+        if max_entries == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= max_entries {
+            cache.retain(|_, entry| entry.expires_at > now);
+        }
+        if cache.len() >= max_entries {
+            // Still full of live entries: drop the one closest to expiry
+            if let Some(oldest) = cache
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(key, _)| key.clone())
+            {
+                cache.remove(&oldest);
+            }
+        }
+        let ttl = ttl.clamp(MIN_CACHE_TTL, MAX_CACHE_TTL);
+        cache.insert(
+            name.to_string(),
+            CacheEntry {
+                addrs,
+                expires_at: now + Duration::from_secs(ttl as u64),
+            },
+        );
+    }
+
+    /// Query A and AAAA records concurrently. Returns addresses (IPv4 first) and the minimum TTL.
+    async fn query_both(&self, name: &str, config: &DnsConfig) -> Result<(Vec<IpAddr>, u32)> {
+        let (v4, v6) = tokio::join!(
+            self.query(name, TYPE_A, config),
+            self.query(name, TYPE_AAAA, config)
+        );
+
+        let mut addrs = Vec::new();
+        let mut ttl = MAX_CACHE_TTL;
+        let mut last_error = None;
+        for answer in [v4, v6] {
+            match answer {
+                Ok(answer) => {
+                    ttl = ttl.min(answer.ttl);
+                    addrs.extend(answer.addrs);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        if addrs.is_empty() {
+            return Err(last_error
+                .unwrap_or_else(|| Error::AddressResolution(format!("{}: no addresses", name))));
+        }
+        Ok((addrs, ttl))
+    }
+
+    async fn query(&self, name: &str, qtype: u16, config: &DnsConfig) -> Result<Answer> {
+        // DoH recommends ID 0 so identical queries are HTTP-cacheable
+        let id = match config.mode {
+            DnsMode::Doh => 0,
+            _ => self.next_id.fetch_add(1, Ordering::Relaxed) as u16 ^ rand_seed(),
+        };
+        let query = build_query(id, name, qtype)?;
+        let resolver = config.resolver.as_deref().unwrap_or_default();
+
+        let response = match config.mode {
+            DnsMode::System => unreachable!("system lookups don't build queries"),
+            DnsMode::Udp => query_udp(resolver, &query).await?,
+            DnsMode::Dot => query_dot(resolver, &config.bootstrap, &query).await?,
+            DnsMode::Doh => query_doh(resolver, &config.bootstrap, &query).await?,
+        };
+
+        debug!("DNS {} type {} answered via {:?}", name, qtype, config.mode);
+        parse_response(&response, id, name)
+    }
+}
+
+/// Addresses, PTR names and TTL from a DNS answer.
+struct Answer {
+    addrs: Vec<IpAddr>,
+    names: Vec<String>,
+    ttl: u32,
+}
+
+/// The `in-addr.arpa` / `ip6.arpa` name queried for `ip`.
+fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, d] = v4.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(v6) => {
+            let mut name = String::with_capacity(72);
+            for byte in v6.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0F, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+/// First `nameserver` entry of the system resolver configuration.
+fn system_nameserver() -> Option<String> {
+    let content = std::fs::read_to_string(SYSTEM_RESOLV_CONF).ok()?;
+    content.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        (fields.next() == Some("nameserver"))
+            .then(|| fields.next())
+            .flatten()
+            .map(str::to_string)
+    })
+}
+
+async fn system_lookup(host: &str) -> Result<Vec<IpAddr>> {
+    let addrs: Vec<IpAddr> = tokio::net::lookup_host((host, 0))
+        .await
+        .map_err(|e| Error::AddressResolution(format!("{}: {}", host, e)))?
+        .map(|addr| addr.ip())
+        .collect();
+    if addrs.is_empty() {
+        return Err(Error::AddressResolution(format!("{}: no addresses", host)));
+    }
+    Ok(addrs)
+}
+
+/// Cheap per-process randomization of query IDs.
+fn rand_seed() -> u16 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u16)
+        .unwrap_or(0)
+}
+
+/// Encode a recursive query for `name`.
+fn build_query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>> {
+    let mut msg = Vec::with_capacity(18 + name.len());
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&0x0100u16.to_be_bytes()); // RD
+    msg.extend_from_slice(&1u16.to_be_bytes()); // QDCOUNT
+    msg.extend_from_slice(&[0; 6]); // ANCOUNT, NSCOUNT, ARCOUNT
+
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(Error::AddressResolution(format!("{}: invalid name", name)));
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(msg)
+}
+
+/// Decode A/AAAA records from a response.
+fn parse_response(msg: &[u8], id: u16, name: &str) -> Result<Answer> {
+    let malformed = || Error::AddressResolution(format!("{}: malformed response", name));
+
+    if msg.len() < 12 || u16::from_be_bytes([msg[0], msg[1]]) != id {
+        return Err(malformed());
+    }
+    let rcode = msg[3] & 0x0F;
+    if rcode == RCODE_NXDOMAIN {
+        return Err(Error::AddressResolution(format!(
+            "{}: no such domain",
+            name
+        )));
+    }
+    if rcode != 0 {
+        return Err(Error::AddressResolution(format!(
+            "{}: server returned rcode {}",
+            name, rcode
+        )));
+    }
+
+    let qdcount = u16::from_be_bytes([msg[4], msg[5]]);
+    let ancount = u16::from_be_bytes([msg[6], msg[7]]);
+    let mut pos = 12;
+
+    for _ in 0..qdcount {
+        pos = skip_name(msg, pos).ok_or_else(malformed)? + 4;
+    }
+
+    let mut answer = Answer {
+        addrs: Vec::new(),
+        names: Vec::new(),
+        ttl: MAX_CACHE_TTL,
+    };
+    for _ in 0..ancount {
+        pos = skip_name(msg, pos).ok_or_else(malformed)?;
+        let fixed = msg.get(pos..pos + 10).ok_or_else(malformed)?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let rdlen = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        pos += 10;
+        let rdata = msg.get(pos..pos + rdlen).ok_or_else(malformed)?;
+        let rdata_start = pos;
+        pos += rdlen;
+
+        if rtype == TYPE_PTR {
+            answer
+                .names
+                .push(read_name(msg, rdata_start).ok_or_else(malformed)?);
+            answer.ttl = answer.ttl.min(ttl);
+            continue;
+        }
+        let addr = match (rtype, rdata.len()) {
+            (TYPE_A, 4) => IpAddr::from(<[u8; 4]>::try_from(rdata).unwrap()),
+            (TYPE_AAAA, 16) => IpAddr::from(<[u8; 16]>::try_from(rdata).unwrap()),
+            _ => continue,
+        };
+        answer.addrs.push(addr);
+        answer.ttl = answer.ttl.min(ttl);
+    }
+
+    Ok(answer)
+}
+
+/// Skip a (possibly compressed) name, returning the position after it.
+fn skip_name(msg: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            l if l & 0xC0 == 0xC0 => return Some(pos + 2),
+            l => pos += 1 + l as usize,
+        }
+    }
+}
+
+/// Decode a (possibly compressed) name starting at `pos`.
+fn read_name(msg: &[u8], mut pos: usize) -> Option<String> {
+    let mut labels = Vec::new();
+    // Bound pointer chasing so a looping message can't hang us
+    for _ in 0..128 {
+        let len = *msg.get(pos)?;
+        match len {
+            0 => return Some(labels.join(".")),
+            l if l & 0xC0 == 0xC0 => {
+                pos = (((l & 0x3F) as usize) << 8) | *msg.get(pos + 1)? as usize;
+            }
+            l => {
+                let label = msg.get(pos + 1..pos + 1 + l as usize)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + l as usize;
+            }
+        }
+    }
+    None
+}
+
+/// Parse `host[:port]`, returning the host and port.
+fn split_host_port(address: &str, default_port: u16) -> Result<(String, u16)> {
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return Ok((addr.ip().to_string(), addr.port()));
+    }
+    if let Ok(ip) = address
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        return Ok((ip.to_string(), default_port));
+    }
+    match address.rsplit_once(':') {
+        Some((host, port)) => port
+            .parse()
+            .map(|port| (host.to_string(), port))
+            .map_err(|_| Error::Config(format!("Invalid DNS resolver address: {}", address))),
+        None => Ok((address.to_string(), default_port)),
+    }
+}
+
+async fn query_udp(resolver: &str, query: &[u8]) -> Result<Vec<u8>> {
+    let (host, port) = split_host_port(resolver, 53)?;
+    let ip: IpAddr = host
+        .parse()
+        .map_err(|_| Error::Config(format!("UDP DNS resolver must be an IP: {}", resolver)))?;
+    let server = SocketAddr::new(ip, port);
+
+    let bind: SocketAddr = if ip.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(server).await?;
+    socket.send(query).await?;
+
+    let mut buf = vec![0u8; 4096];
+    loop {
+        let n = socket.recv(&mut buf).await?;
+        // Ignore stray datagrams that don't answer our query
+        if n < 12 || buf[..2] != query[..2] {
+            continue;
+        }
+        // Truncated: retry over TCP
+        if buf[2] & 0x02 != 0 {
+            let mut stream = TcpStream::connect(server).await?;
+            return exchange_framed(&mut stream, query).await;
+        }
+        buf.truncate(n);
+        return Ok(buf);
+    }
+}
+
+/// Send a length-prefixed query over a stream transport (TCP/DoT).
+async fn exchange_framed<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    query: &[u8],
+) -> Result<Vec<u8>> {
+    let mut frame = Vec::with_capacity(2 + query.len());
+    frame.extend_from_slice(&(query.len() as u16).to_be_bytes());
+    frame.extend_from_slice(query);
+    stream.write_all(&frame).await?;
+    stream.flush().await?;
+
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).await?;
+    let mut response = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+/// Open a TLS connection to a resolver, using bootstrap IPs when configured.
+async fn connect_tls(
+    host: &str,
+    port: u16,
+    bootstrap: &[String],
+) -> Result<tokio_rustls::client::TlsStream<TcpStream>> {
+    let tcp = if bootstrap.is_empty() {
+        TcpStream::connect((host, port)).await?
+    } else {
+        let mut last_error = None;
+        let mut connected = None;
+        for ip in bootstrap {
+            let ip: IpAddr = ip
+                .parse()
+                .map_err(|_| Error::Config(format!("Invalid DNS bootstrap IP: {}", ip)))?;
+            match TcpStream::connect(SocketAddr::new(ip, port)).await {
+                Ok(stream) => {
+                    connected = Some(stream);
+                    break;
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match connected {
+            Some(stream) => stream,
+            None => {
+                return Err(last_error
+                    .map(Error::Io)
+                    .unwrap_or_else(|| Error::Config("No DNS bootstrap IPs".into())))
+            }
+        }
+    };
+
+    let server_name = ServerName::try_from(host.to_string())
+        .map_err(|e| Error::Config(format!("Invalid DNS resolver name {}: {}", host, e)))?;
+    let connector = TlsConnector::from(tls::client_config(None)?);
+    Ok(connector.connect(server_name, tcp).await?)
+}
+
+async fn query_dot(resolver: &str, bootstrap: &[String], query: &[u8]) -> Result<Vec<u8>> {
+    let (host, port) = split_host_port(resolver, 853)?;
+    let mut stream = connect_tls(&host, port, bootstrap).await?;
+    exchange_framed(&mut stream, query).await
+}
+
+async fn query_doh(url: &str, bootstrap: &[String], query: &[u8]) -> Result<Vec<u8>> {
+    let rest = url
+        .strip_prefix("https://")
+        .ok_or_else(|| Error::Config(format!("DoH resolver must be an https:// URL: {}", url)))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/dns-query"),
+    };
+    let (host, port) = split_host_port(authority, 443)?;
+
+    let mut stream = connect_tls(&host, port, bootstrap).await?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/dns-message\r\nAccept: application/dns-message\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        authority,
+        query.len()
+    );
+    stream.write_all(request.as_bytes()).await?;
+    stream.write_all(query).await?;
+    stream.flush().await?;
+
+    let mut raw = Vec::new();
+    (&mut stream)
+        .take(MAX_RESPONSE_LEN as u64 + 16 * 1024)
+        .read_to_end(&mut raw)
+        .await?;
+    parse_http_response(&raw)
+}
+
+/// Extract the body of an HTTP/1.1 response (Content-Length, chunked or close-delimited).
+fn parse_http_response(raw: &[u8]) -> Result<Vec<u8>> {
+    let bad = |reason: &str| Error::AddressResolution(format!("DoH: {}", reason));
+
+    let header_end = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| bad("incomplete response"))?;
+    let head = std::str::from_utf8(&raw[..header_end]).map_err(|_| bad("invalid headers"))?;
+    let body = &raw[header_end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .ok_or_else(|| bad("missing status line"))?;
+    if status != "200" {
+        return Err(bad(&format!("HTTP status {}", status)));
+    }
+
+    let mut content_length = None;
+    let mut chunked = false;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse::<usize>().ok();
+            } else if name.eq_ignore_ascii_case("transfer-encoding")
+                && value.eq_ignore_ascii_case("chunked")
+            {
+                chunked = true;
+            }
+        }
+    }
+
+    if chunked {
+        let mut out = Vec::new();
+        let mut rest = body;
+        loop {
+            let line_end = rest
+                .windows(2)
+                .position(|w| w == b"\r\n")
+                .ok_or_else(|| bad("invalid chunk"))?;
+            let size = std::str::from_utf8(&rest[..line_end])
+                .ok()
+                .and_then(|s| usize::from_str_radix(s.split(';').next()?.trim(), 16).ok())
+                .ok_or_else(|| bad("invalid chunk size"))?;
+            rest = &rest[line_end + 2..];
+            if size == 0 {
+                return Ok(out);
+            }
+            out.extend_from_slice(rest.get(..size).ok_or_else(|| bad("truncated chunk"))?);
+            rest = rest.get(size + 2..).ok_or_else(|| bad("truncated chunk"))?;
+        }
+    }
+
+    match content_length {
+        Some(len) => Ok(body
+            .get(..len)
+            .ok_or_else(|| bad("truncated body"))?
+            .to_vec()),
+        None => Ok(body.to_vec()),
+    }
+}
@@ -0,0 +1,85 @@
+//! Outbound source address selection for `[egress]`.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::config::{EgressConfig, EgressStrategy};
+
+/// Picks the local address each outbound connection is bound to.
+#[derive(Debug, Default)]
+pub struct EgressSelector {
+    /// Round-robin position.
+    next: AtomicUsize,
+}
+
+impl EgressSelector {
+    /// Pick the local address for a connection to `target`.
+    ///
+    /// Only addresses of the target's family are candidates; `None` leaves
+    /// the choice to the OS.
+    pub fn select(
+        &self,
+        config: &EgressConfig,
+        target: IpAddr,
+        username: Option<&str>,
+        client_ip: IpAddr,
+    ) -> Option<IpAddr> {
+        let candidates: Vec<IpAddr> = config
+            .addresses
+            .iter()
+            .copied()
+            .filter(|addr| addr.is_ipv4() == target.is_ipv4())
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let client_key = || ip_bytes(client_ip.to_canonical());
+        let key = match config.strategy {
+            EgressStrategy::RoundRobin => {
+                let index = self.next.fetch_add(1, Ordering::Relaxed) % candidates.len();
+                return Some(candidates[index]);
+            }
+            EgressStrategy::Random => {
+                let random = RandomState::new().build_hasher().finish();
+                return Some(candidates[(random % candidates.len() as u64) as usize]);
+            }
+            EgressStrategy::HashByUser => match username {
+                Some(username) => username.as_bytes().to_vec(),
+                None => client_key(),
+            },
+            EgressStrategy::HashByClientIp => client_key(),
+        };
+
+        // Rendezvous hashing: adding or removing an address only moves the
+        // keys that map to that address
+        candidates
+            .into_iter()
+            .max_by_key(|addr| weight(&key, &ip_bytes(*addr)))
+    }
+}
+
+fn ip_bytes(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+/// Stable (across restarts and builds) weight of `addr` for `key`.
+fn weight(key: &[u8], addr: &[u8]) -> u64 {
+    // FNV-1a, with a length separator so key and address bytes can't run together
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key.iter().chain(&[key.len() as u8]).chain(addr) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    // splitmix64 finalizer, so addresses differing in one octet get unrelated weights
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
@@ -1,5 +1,6 @@
 //! Error types for the net-relay proxy.
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 /// Result type alias for net-relay operations.
@@ -24,6 +25,14 @@ pub enum Error {
     #[error("Authentication failed")]
     AuthenticationFailed,
 
+    /// Proxy credentials were refused; `username` is the attempted name,
+    /// sanitized for logging (see [`crate::auth::loggable_username`]).
+    #[error("Authentication failed: {reason}")]
+    CredentialsRejected {
+        username: Option<String>,
+        reason: String,
+    },
+
     /// Connection refused by target.
     #[error("Connection refused: {0}")]
     ConnectionRefused(String),
@@ -53,6 +62,257 @@ pub enum Error {
     MaxConnectionsReached,
 
     /// Access denied by access control rules.
-    #[error("Access denied: {0}")]
-    AccessDenied(String),
+    #[error("Access denied: {1}")]
+    AccessDenied(DenyReason, String),
+
+    /// User's data quota is used up.
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    /// Relay-to-relay tunnel failure.
+    #[error("Tunnel error: {0}")]
+    Tunnel(String),
+
+    /// Outbound HTTP request failed.
+    #[error("HTTP error: {0}")]
+    Http(String),
+
+    /// Certificate issuance through ACME failed.
+    #[error("ACME error: {0}")]
+    Acme(String),
+}
+
+impl Error {
+    /// Stable code identifying the kind of error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Io(_) => ErrorCode::Io,
+            Error::InvalidSocks5Protocol(_) => ErrorCode::InvalidSocks5Protocol,
+            Error::InvalidHttpProtocol(_) => ErrorCode::InvalidHttpProtocol,
+            Error::AuthenticationFailed | Error::CredentialsRejected { .. } => {
+                ErrorCode::AuthenticationFailed
+            }
+            Error::ConnectionRefused(_) => ErrorCode::ConnectionRefused,
+            Error::Timeout => ErrorCode::Timeout,
+            Error::AddressResolution(_) => ErrorCode::AddressResolutionFailed,
+            Error::UnsupportedCommand(_) => ErrorCode::UnsupportedCommand,
+            Error::UnsupportedAddressType(_) => ErrorCode::UnsupportedAddressType,
+            Error::Config(_) => ErrorCode::ConfigError,
+            Error::MaxConnectionsReached => ErrorCode::MaxConnectionsReached,
+            Error::AccessDenied(reason, _) => reason.code(),
+            Error::QuotaExceeded(_) => ErrorCode::QuotaExceeded,
+            Error::Tunnel(_) => ErrorCode::TunnelError,
+            Error::Http(_) => ErrorCode::HttpError,
+            Error::Acme(_) => ErrorCode::AcmeError,
+        }
+    }
+}
+
+/// Which access check refused a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DenyReason {
+    /// The client IP is blacklisted, banned or missing from the whitelist.
+    ClientIp,
+    /// A rule (or the default action) denies the target.
+    Target,
+    /// A rule denies the server name of the TLS ClientHello.
+    Sni,
+    /// The upstream relay refused the target.
+    Upstream,
+    /// A [`ConnectionHook`](crate::hooks::ConnectionHook) refused the connection.
+    Hook,
+}
+
+impl DenyReason {
+    /// Code reported for denials of this kind.
+    pub fn code(self) -> ErrorCode {
+        match self {
+            DenyReason::ClientIp => ErrorCode::ClientIpDenied,
+            DenyReason::Target => ErrorCode::TargetDenied,
+            DenyReason::Sni => ErrorCode::SniDenied,
+            DenyReason::Upstream => ErrorCode::UpstreamDenied,
+            DenyReason::Hook => ErrorCode::HookDenied,
+        }
+    }
+}
+
+/// Stable, machine-readable error code.
+///
+/// Codes are serialized in `snake_case` (see [`ErrorCode::as_str`]) and are
+/// part of the public interface: API error bodies carry them in `code`, and
+/// existing codes are never renamed or reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ErrorCode {
+    /// `io_error`: network or file I/O failed.
+    #[serde(rename = "io_error")]
+    Io,
+    /// `invalid_socks5_protocol`: the client sent malformed SOCKS5 data.
+    InvalidSocks5Protocol,
+    /// `invalid_http_protocol`: the client sent a malformed HTTP request.
+    InvalidHttpProtocol,
+    /// `authentication_failed`: missing or wrong proxy credentials.
+    AuthenticationFailed,
+    /// `connection_refused`: the target could not be connected to.
+    ConnectionRefused,
+    /// `timeout`: an operation timed out.
+    Timeout,
+    /// `address_resolution_failed`: the target host name did not resolve.
+    AddressResolutionFailed,
+    /// `unsupported_command`: a SOCKS5 command other than CONNECT.
+    UnsupportedCommand,
+    /// `unsupported_address_type`: an unknown SOCKS5 address type.
+    UnsupportedAddressType,
+    /// `config_error`: the configuration is invalid or could not be saved.
+    ConfigError,
+    /// `max_connections_reached`: a connection limit is used up.
+    MaxConnectionsReached,
+    /// `client_ip_denied`: the client IP is blacklisted, banned or not whitelisted.
+    ClientIpDenied,
+    /// `target_denied`: an access rule denies the target.
+    TargetDenied,
+    /// `sni_denied`: an access rule denies the TLS server name.
+    SniDenied,
+    /// `upstream_denied`: the upstream relay refused the target.
+    UpstreamDenied,
+    /// `hook_denied`: a connection hook refused the connection.
+    HookDenied,
+    /// `quota_exceeded`: the user's data quota is used up.
+    QuotaExceeded,
+    /// `tunnel_error`: the relay-to-relay tunnel failed.
+    TunnelError,
+    /// `http_error`: an outbound HTTP request failed.
+    HttpError,
+    /// `acme_error`: certificate issuance through ACME failed.
+    AcmeError,
+    /// `validation_failed`: API request fields are invalid (see `errors`).
+    ValidationFailed,
+    /// `unauthorized`: the API request needs a dashboard session or token.
+    Unauthorized,
+    /// `revision_conflict`: the configuration changed since the given revision.
+    RevisionConflict,
+    /// `config_locked`: the configuration is locked for maintenance.
+    ConfigLocked,
+    /// `config_not_saved`: a change was applied but the config file could not be written.
+    ConfigNotSaved,
+    /// `rate_limited`: too many API requests from the client.
+    RateLimited,
+    /// `stats_disabled`: per-connection statistics are turned off (`stats.enabled`).
+    StatsDisabled,
+}
+
+impl ErrorCode {
+    /// Every code, in documentation order.
+    pub const ALL: &'static [ErrorCode] = &[
+        ErrorCode::Io,
+        ErrorCode::InvalidSocks5Protocol,
+        ErrorCode::InvalidHttpProtocol,
+        ErrorCode::AuthenticationFailed,
+        ErrorCode::ConnectionRefused,
+        ErrorCode::Timeout,
+        ErrorCode::AddressResolutionFailed,
+        ErrorCode::UnsupportedCommand,
+        ErrorCode::UnsupportedAddressType,
+        ErrorCode::ConfigError,
+        ErrorCode::MaxConnectionsReached,
+        ErrorCode::ClientIpDenied,
+        ErrorCode::TargetDenied,
+        ErrorCode::SniDenied,
+        ErrorCode::UpstreamDenied,
+        ErrorCode::HookDenied,
+        ErrorCode::QuotaExceeded,
+        ErrorCode::TunnelError,
+        ErrorCode::HttpError,
+        ErrorCode::AcmeError,
+        ErrorCode::ValidationFailed,
+        ErrorCode::Unauthorized,
+        ErrorCode::RevisionConflict,
+        ErrorCode::ConfigLocked,
+        ErrorCode::ConfigNotSaved,
+        ErrorCode::RateLimited,
+        ErrorCode::StatsDisabled,
+    ];
+
+    /// The code as it appears on the wire.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Io => "io_error",
+            ErrorCode::InvalidSocks5Protocol => "invalid_socks5_protocol",
+            ErrorCode::InvalidHttpProtocol => "invalid_http_protocol",
+            ErrorCode::AuthenticationFailed => "authentication_failed",
+            ErrorCode::ConnectionRefused => "connection_refused",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::AddressResolutionFailed => "address_resolution_failed",
+            ErrorCode::UnsupportedCommand => "unsupported_command",
+            ErrorCode::UnsupportedAddressType => "unsupported_address_type",
+            ErrorCode::ConfigError => "config_error",
+            ErrorCode::MaxConnectionsReached => "max_connections_reached",
+            ErrorCode::ClientIpDenied => "client_ip_denied",
+            ErrorCode::TargetDenied => "target_denied",
+            ErrorCode::SniDenied => "sni_denied",
+            ErrorCode::UpstreamDenied => "upstream_denied",
+            ErrorCode::HookDenied => "hook_denied",
+            ErrorCode::QuotaExceeded => "quota_exceeded",
+            ErrorCode::TunnelError => "tunnel_error",
+            ErrorCode::HttpError => "http_error",
+            ErrorCode::AcmeError => "acme_error",
+            ErrorCode::ValidationFailed => "validation_failed",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::RevisionConflict => "revision_conflict",
+            ErrorCode::ConfigLocked => "config_locked",
+            ErrorCode::ConfigNotSaved => "config_not_saved",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::StatsDisabled => "stats_disabled",
+        }
+    }
+
+    /// SOCKS5 reply (`REP`) sent to a client whose request failed with this code.
+    pub fn socks5_reply(self) -> u8 {
+        match self {
+            ErrorCode::ClientIpDenied
+            | ErrorCode::TargetDenied
+            | ErrorCode::SniDenied
+            | ErrorCode::UpstreamDenied
+            | ErrorCode::HookDenied
+            | ErrorCode::MaxConnectionsReached => 0x02,
+            ErrorCode::AddressResolutionFailed | ErrorCode::Timeout => 0x04,
+            ErrorCode::ConnectionRefused => 0x05,
+            ErrorCode::UnsupportedCommand => 0x07,
+            ErrorCode::UnsupportedAddressType => 0x08,
+            _ => 0x01,
+        }
+    }
+
+    /// HTTP status sent to a client (proxy or API) whose request failed with
+    /// this code.
+    pub fn http_status(self) -> u16 {
+        match self {
+            ErrorCode::InvalidHttpProtocol | ErrorCode::ValidationFailed => 400,
+            ErrorCode::Unauthorized => 401,
+            ErrorCode::ClientIpDenied
+            | ErrorCode::TargetDenied
+            | ErrorCode::SniDenied
+            | ErrorCode::UpstreamDenied
+            | ErrorCode::HookDenied
+            | ErrorCode::QuotaExceeded => 403,
+            ErrorCode::StatsDisabled => 404,
+            ErrorCode::AuthenticationFailed => 407,
+            ErrorCode::RevisionConflict => 409,
+            ErrorCode::ConfigLocked => 423,
+            ErrorCode::MaxConnectionsReached | ErrorCode::RateLimited => 429,
+            ErrorCode::AddressResolutionFailed
+            | ErrorCode::ConnectionRefused
+            | ErrorCode::TunnelError => 502,
+            ErrorCode::Timeout => 504,
+            _ => 500,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
@@ -0,0 +1,209 @@
+//! Connection event log (`stats.event_log`).
+//!
+//! Every event is appended as one JSON object per line, meant to be tailed by
+//! external tools. All records carry the schema version `v`, the event `type`
+//! and its `time`:
+//!
+//! - `open`: `id`, `client_ip`, `user`, `protocol`, `target`, `upstream`, `egress`
+//! - `close`: `id`, `client_ip`, `user`, `protocol`, `target`, `bytes_sent`,
+//!   `bytes_received`, `duration_ms`, `close_reason`
+//! - `denied`: `client_ip`, `source`, `target`, `code`, `reason`
+//! - `auth_failure`: `client_ip`, `source`, `user`, `reason`
+//! - `alert`: `name`, `state`, `metric`, `value`, `threshold`
+//!
+//! Fields are only ever added within a version; `user`, `target`, `upstream`,
+//! `egress` and `code` are `null` when unknown.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use tracing_appender::non_blocking::{NonBlocking, NonBlockingBuilder, WorkerGuard};
+
+use crate::access_log::{client_ip, open_appender, LogRotation};
+use crate::alerts::{AlertState, AlertStatus};
+use crate::config::AlertMetric;
+use crate::connection::{CloseReason, ConnectionInfo, Protocol};
+use crate::error::ErrorCode;
+use crate::stats::DeniedAttempt;
+
+/// Schema version written as `v` in every record.
+pub const EVENT_LOG_VERSION: u32 = 1;
+
+/// What to do when events arrive faster than they can be written
+/// (`stats.event_log_backpressure`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventLogBackpressure {
+    /// Drop events so relays never wait on the log.
+    #[default]
+    Drop,
+    /// Make the recording task wait until the writer catches up.
+    Block,
+}
+
+/// A single logged event.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A connection to a target was established.
+    Open {
+        id: uuid::Uuid,
+        client_ip: &'a str,
+        user: Option<&'a str>,
+        protocol: &'a Protocol,
+        target: String,
+        upstream: Option<&'a str>,
+        egress: Option<&'a str>,
+    },
+    /// A connection ended.
+    Close {
+        id: uuid::Uuid,
+        client_ip: &'a str,
+        user: Option<&'a str>,
+        protocol: &'a Protocol,
+        target: String,
+        bytes_sent: u64,
+        bytes_received: u64,
+        duration_ms: i64,
+        close_reason: Option<CloseReason>,
+    },
+    /// Access control refused a client.
+    Denied {
+        client_ip: &'a str,
+        source: &'a str,
+        target: Option<&'a str>,
+        code: Option<ErrorCode>,
+        reason: &'a str,
+        /// Only a monitored rule denied it; the connection went ahead.
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        would_deny: bool,
+    },
+    /// A proxy client presented missing or wrong credentials.
+    AuthFailure {
+        client_ip: &'a str,
+        source: &'a str,
+        /// Attempted username, sanitized for logging.
+        user: Option<&'a str>,
+        reason: &'a str,
+    },
+    /// An alert rule started firing or resolved.
+    Alert {
+        name: &'a str,
+        state: AlertState,
+        metric: AlertMetric,
+        value: u64,
+        threshold: u64,
+    },
+}
+
+impl<'a> Event<'a> {
+    /// Event for an alert rule that changed state.
+    pub fn alert(status: &'a AlertStatus) -> Self {
+        Event::Alert {
+            name: &status.name,
+            state: status.state,
+            metric: status.metric,
+            value: status.value,
+            threshold: status.threshold,
+        }
+    }
+
+    /// Event for a newly recorded connection.
+    pub fn open(info: &'a ConnectionInfo) -> Self {
+        Event::Open {
+            id: info.id,
+            client_ip: client_ip(&info.client_addr),
+            user: info.username.as_deref(),
+            protocol: &info.protocol,
+            target: format!("{}:{}", info.target_addr, info.target_port),
+            upstream: info.upstream.as_deref(),
+            egress: info.egress.as_deref(),
+        }
+    }
+
+    /// Event for a closed connection.
+    pub fn close(info: &'a ConnectionInfo) -> Self {
+        let closed_at = info.closed_at.unwrap_or_else(Utc::now);
+        Event::Close {
+            id: info.id,
+            client_ip: client_ip(&info.client_addr),
+            user: info.username.as_deref(),
+            protocol: &info.protocol,
+            target: format!("{}:{}", info.target_addr, info.target_port),
+            bytes_sent: info.bytes_sent,
+            bytes_received: info.bytes_received,
+            duration_ms: (closed_at - info.connected_at).num_milliseconds(),
+            close_reason: info.close_reason,
+        }
+    }
+
+    /// Event for a denied attempt.
+    pub fn denied(attempt: &'a DeniedAttempt) -> Self {
+        Event::Denied {
+            client_ip: client_ip(&attempt.client_addr),
+            source: &attempt.source,
+            target: attempt.target.as_deref(),
+            code: attempt.code,
+            reason: &attempt.reason,
+            would_deny: attempt.would_deny,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EventRecord<'a> {
+    v: u32,
+    time: DateTime<Utc>,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// Non-blocking event log writer.
+#[derive(Clone)]
+pub struct EventLog {
+    writer: NonBlocking,
+    /// Flushes pending events when the last clone is dropped.
+    _guard: Arc<WorkerGuard>,
+}
+
+impl std::fmt::Debug for EventLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventLog").finish_non_exhaustive()
+    }
+}
+
+impl EventLog {
+    /// Open an event log at the given path.
+    pub fn open(
+        path: impl AsRef<Path>,
+        rotation: LogRotation,
+        backpressure: EventLogBackpressure,
+    ) -> std::io::Result<Self> {
+        let appender = open_appender(path.as_ref(), rotation, "events.jsonl")?;
+        let (writer, guard) = NonBlockingBuilder::default()
+            .lossy(backpressure == EventLogBackpressure::Drop)
+            .thread_name("net-relay-event-log")
+            .finish(appender);
+
+        Ok(Self {
+            writer,
+            _guard: Arc::new(guard),
+        })
+    }
+
+    /// Append `event`, stamped with the current time.
+    pub fn record(&self, event: &Event<'_>) {
+        let record = EventRecord {
+            v: EVENT_LOG_VERSION,
+            time: Utc::now(),
+            event,
+        };
+        let Ok(mut line) = serde_json::to_string(&record) else {
+            return;
+        };
+        line.push('\n');
+        let _ = self.writer.clone().write_all(line.as_bytes());
+    }
+}
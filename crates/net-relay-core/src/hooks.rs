@@ -0,0 +1,155 @@
+//! Custom per-connection logic for embedders.
+//!
+//! A [`ConnectionHook`] registered on a proxy builder sees every connection
+//! that passes access control before the target is connected, and can turn
+//! it away. Once the connection has closed it sees the final
+//! [`ConnectionInfo`] again. Hooks run in registration order; a hook that
+//! fails (returns an error or panics) is logged and then handled according
+//! to its [`HookFailurePolicy`].
+
+use futures::future::{BoxFuture, FutureExt};
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::connection::ConnectionInfo;
+use crate::error::{DenyReason, Error, Result};
+
+/// Verdict of [`ConnectionHook::on_connect`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookDecision {
+    /// Let the connection proceed.
+    Allow,
+    /// Refuse the connection; the reason is reported to the client and in
+    /// `/api/stats/denied`.
+    Deny(String),
+}
+
+/// Logic run when a proxied connection opens and closes.
+pub trait ConnectionHook: Send + Sync {
+    /// Name used in log messages.
+    fn name(&self) -> &str;
+
+    /// Decide on a connection that passed access control, before its target
+    /// is connected. `info` has no byte counts yet.
+    fn on_connect<'a>(
+        &'a self,
+        info: &'a ConnectionInfo,
+    ) -> BoxFuture<'a, anyhow::Result<HookDecision>>;
+
+    /// Called once for every connection `on_connect` allowed, after it has
+    /// been closed (and recorded in the stats if it got to relaying).
+    ///
+    /// `close_reason` is `None` when the connection ended before relaying
+    /// started, e.g. because the target was unreachable.
+    ///
+    /// Not called while `stats.enabled` is off, since closed connections
+    /// aren't recorded then.
+    fn on_close<'a>(&'a self, info: &'a ConnectionInfo) -> BoxFuture<'a, ()> {
+        let _ = info;
+        Box::pin(async {})
+    }
+}
+
+/// What happens to a connection when a hook returns an error or panics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HookFailurePolicy {
+    /// Log the failure and let the connection proceed (fail open).
+    #[default]
+    Allow,
+    /// Log the failure and refuse the connection (fail closed).
+    Deny,
+}
+
+/// Hooks registered on a proxy.
+#[derive(Clone, Default)]
+pub struct ConnectionHooks {
+    hooks: Arc<Vec<(Arc<dyn ConnectionHook>, HookFailurePolicy)>>,
+}
+
+impl ConnectionHooks {
+    /// Add `hook`, run after the ones added before it.
+    pub fn push(&mut self, hook: Arc<dyn ConnectionHook>, on_failure: HookFailurePolicy) {
+        Arc::make_mut(&mut self.hooks).push((hook, on_failure));
+    }
+
+    /// Whether no hooks are registered.
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Ask every hook about `info`, failing with
+    /// [`Error::AccessDenied`]`(`[`DenyReason::Hook`]`, ..)` on the first denial.
+    pub(crate) async fn on_connect(&self, info: &ConnectionInfo) -> Result<()> {
+        for (hook, on_failure) in self.hooks.iter() {
+            let outcome = AssertUnwindSafe(async { hook.on_connect(info).await })
+                .catch_unwind()
+                .await;
+            let failure = match outcome {
+                Ok(Ok(HookDecision::Allow)) => continue,
+                Ok(Ok(HookDecision::Deny(reason))) => {
+                    return Err(Error::AccessDenied(
+                        DenyReason::Hook,
+                        format!("Denied by hook {}: {}", hook.name(), reason),
+                    ))
+                }
+                Ok(Err(e)) => e.to_string(),
+                Err(panic) => format!("panicked: {}", panic_message(&panic)),
+            };
+            match on_failure {
+                HookFailurePolicy::Allow => {
+                    warn!(
+                        "Hook {} failed, allowing connection: {}",
+                        hook.name(),
+                        failure
+                    );
+                }
+                HookFailurePolicy::Deny => {
+                    warn!(
+                        "Hook {} failed, denying connection: {}",
+                        hook.name(),
+                        failure
+                    );
+                    return Err(Error::AccessDenied(
+                        DenyReason::Hook,
+                        format!("Hook {} failed", hook.name()),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Tell every hook `info` has closed.
+    pub(crate) async fn on_close(&self, info: &ConnectionInfo) {
+        for (hook, _) in self.hooks.iter() {
+            if let Err(panic) = AssertUnwindSafe(async { hook.on_close(info).await })
+                .catch_unwind()
+                .await
+            {
+                warn!(
+                    "Hook {} panicked on close: {}",
+                    hook.name(),
+                    panic_message(&panic)
+                );
+            }
+        }
+    }
+
+    /// Report a connection that ended before relaying started.
+    pub(crate) async fn on_abort(&self, mut info: ConnectionInfo) {
+        if self.is_empty() {
+            return;
+        }
+        info.set_closed();
+        self.on_close(&info).await;
+    }
+}
+
+fn panic_message(panic: &Box<dyn std::any::Any + Send>) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
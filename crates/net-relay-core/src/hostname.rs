@@ -0,0 +1,86 @@
+//! Target host names in the one form rules are matched against.
+//!
+//! Clients may name the same target as `EXAMPLE.com.`, `example.com`,
+//! `bücher.example` or `xn--bcher-kva.example`. Both proxy handlers
+//! [`normalize`] the requested host before access control and stats see it:
+//! lowercase, without the trailing root dot, with internationalized labels
+//! in their punycode (`xn--`) form. Rule domains are compiled the same way
+//! (see [`normalize_pattern`]), so a rule written in Unicode matches clients
+//! sending punycode and the other way round.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::net::IpAddr;
+
+use idna::AsciiDenyList;
+
+/// A host name that can't be used as a DNS name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidHostname(pub String);
+
+impl fmt::Display for InvalidHostname {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid host name '{}'", self.0)
+    }
+}
+
+impl std::error::Error for InvalidHostname {}
+
+/// The normalized form of `host`: IP literals as they are, names lowercased,
+/// without a trailing dot and with internationalized labels in punycode.
+///
+/// Borrows `host` when it already is in that form.
+pub fn normalize(host: &str) -> Result<Cow<'_, str>, InvalidHostname> {
+    if is_ip_literal(host) {
+        return Ok(Cow::Borrowed(host));
+    }
+    let name = host.strip_suffix('.').unwrap_or(host);
+    idna::domain_to_ascii_cow(name.as_bytes(), AsciiDenyList::URL)
+        .ok()
+        .filter(|ascii| valid_labels(ascii))
+        .ok_or_else(|| InvalidHostname(host.to_string()))
+}
+
+/// [`normalize`] for a rule domain, which may start with `*.`.
+pub fn normalize_pattern(pattern: &str) -> Result<Cow<'_, str>, InvalidHostname> {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => {
+            let normalized = normalize(domain).map_err(|_| InvalidHostname(pattern.to_string()))?;
+            if normalized == domain {
+                Ok(Cow::Borrowed(pattern))
+            } else {
+                Ok(Cow::Owned(format!("*.{}", normalized)))
+            }
+        }
+        None => normalize(pattern),
+    }
+}
+
+/// The Unicode form of a normalized host or pattern, `None` when it has no
+/// punycode labels (or they don't decode).
+pub fn to_unicode(normalized: &str) -> Option<String> {
+    if !normalized.split('.').any(|label| label.starts_with("xn--")) {
+        return None;
+    }
+    let (unicode, result) = idna::domain_to_unicode(normalized);
+    result.ok()?;
+    (unicode != normalized).then_some(unicode)
+}
+
+/// Whether `host` is an IPv4 or (possibly bracketed) IPv6 address.
+fn is_ip_literal(host: &str) -> bool {
+    let bare = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    bare.parse::<IpAddr>().is_ok()
+}
+
+/// Non-empty labels of at most 63 bytes, 253 bytes in total.
+fn valid_labels(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 253
+        && name
+            .split('.')
+            .all(|label| !label.is_empty() && label.len() <= 63)
+}
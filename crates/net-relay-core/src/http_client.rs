@@ -0,0 +1,210 @@
+//! Minimal HTTP/1.1 client for outbound calls (ACME, authentication callbacks).
+//!
+//! Every request uses a fresh connection closed by the server afterwards, which
+//! keeps response framing simple and is plenty for the low request rates involved.
+
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+
+use crate::error::{Error, Result};
+use crate::tls;
+
+/// Largest response accepted.
+const MAX_RESPONSE_LEN: u64 = 1024 * 1024;
+
+/// Response to an outbound request.
+pub(crate) struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_slice(&self.body)
+            .map_err(|e| Error::Http(format!("invalid JSON response: {}", e)))
+    }
+}
+
+/// Outbound request over a fresh connection (`https://` or plain `http://`).
+pub(crate) struct HttpRequest<'a> {
+    pub method: &'a str,
+    pub url: &'a str,
+    /// Extra headers (`Host`, `Content-Length` and `Connection` are set automatically).
+    pub headers: &'a [(&'a str, &'a str)],
+    /// Body and its content type.
+    pub body: Option<(&'a str, &'a [u8])>,
+    /// CA bundle for `https://` URLs (default: webpki roots).
+    pub ca_file: Option<&'a str>,
+    pub timeout: Duration,
+}
+
+impl HttpRequest<'_> {
+    /// Send the request, failing with [`Error::Http`] after `timeout`.
+    pub async fn send(&self) -> Result<HttpResponse> {
+        tokio::time::timeout(self.timeout, self.send_inner())
+            .await
+            .map_err(|_| Error::Http(format!("{} {} timed out", self.method, self.url)))?
+    }
+
+    async fn send_inner(&self) -> Result<HttpResponse> {
+        let url = self.url;
+        let (secure, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(Error::Config(format!("Unsupported URL: {}", url)));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                let port = port
+                    .parse::<u16>()
+                    .map_err(|_| Error::Config(format!("Invalid port in URL: {}", url)))?;
+                (host.trim_matches(['[', ']']), port)
+            }
+            _ => (
+                authority.trim_matches(['[', ']']),
+                if secure { 443 } else { 80 },
+            ),
+        };
+
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: net-relay/{}\r\nAccept: application/json\r\nConnection: close\r\n",
+            self.method,
+            path,
+            authority,
+            env!("CARGO_PKG_VERSION")
+        );
+        for (name, value) in self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if let Some((content_type, body)) = self.body {
+            head.push_str(&format!(
+                "Content-Type: {}\r\nContent-Length: {}\r\n",
+                content_type,
+                body.len()
+            ));
+        }
+        head.push_str("\r\n");
+        let body = self.body.map(|(_, body)| body);
+
+        let tcp = TcpStream::connect((host, port)).await?;
+        let raw = if secure {
+            let server_name = ServerName::try_from(host.to_string())
+                .map_err(|e| Error::Config(format!("Invalid host {}: {}", host, e)))?;
+            let connector = TlsConnector::from(tls::client_config(self.ca_file)?);
+            let mut stream = connector.connect(server_name, tcp).await?;
+            exchange(&mut stream, &head, body).await?
+        } else {
+            let mut stream = tcp;
+            exchange(&mut stream, &head, body).await?
+        };
+        parse_response(&raw, self.method == "HEAD")
+    }
+}
+
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    head: &str,
+    body: Option<&[u8]>,
+) -> Result<Vec<u8>> {
+    stream.write_all(head.as_bytes()).await?;
+    if let Some(body) = body {
+        stream.write_all(body).await?;
+    }
+    stream.flush().await?;
+
+    let mut raw = Vec::new();
+    match stream.take(MAX_RESPONSE_LEN).read_to_end(&mut raw).await {
+        Ok(_) => {}
+        // Servers that close without close_notify still sent a complete response
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && !raw.is_empty() => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(raw)
+}
+
+/// Split a close-delimited HTTP/1.1 response into status, headers and body.
+fn parse_response(raw: &[u8], head_only: bool) -> Result<HttpResponse> {
+    let bad = |reason: &str| Error::Http(format!("malformed response: {}", reason));
+
+    let header_end = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| bad("incomplete response"))?;
+    let head = std::str::from_utf8(&raw[..header_end]).map_err(|_| bad("invalid headers"))?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| bad("missing status line"))?;
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    let mut response = HttpResponse {
+        status,
+        headers,
+        body: Vec::new(),
+    };
+    if head_only {
+        return Ok(response);
+    }
+
+    let body = &raw[header_end + 4..];
+    let chunked = response
+        .header("transfer-encoding")
+        .is_some_and(|v| v.eq_ignore_ascii_case("chunked"));
+    response.body = if chunked {
+        let mut out = Vec::new();
+        let mut rest = body;
+        loop {
+            let line_end = rest
+                .windows(2)
+                .position(|w| w == b"\r\n")
+                .ok_or_else(|| bad("invalid chunk"))?;
+            let size = std::str::from_utf8(&rest[..line_end])
+                .ok()
+                .and_then(|s| usize::from_str_radix(s.split(';').next()?.trim(), 16).ok())
+                .ok_or_else(|| bad("invalid chunk size"))?;
+            rest = &rest[line_end + 2..];
+            if size == 0 {
+                break;
+            }
+            if rest.len() < size {
+                return Err(bad("truncated chunk"));
+            }
+            out.extend_from_slice(&rest[..size]);
+            rest = rest.get(size + 2..).unwrap_or_default();
+        }
+        out
+    } else {
+        match response
+            .header("content-length")
+            .and_then(|v| v.parse::<usize>().ok())
+        {
+            Some(len) if len <= body.len() => body[..len].to_vec(),
+            Some(_) => return Err(bad("truncated body")),
+            None => body.to_vec(),
+        }
+    };
+    Ok(response)
+}
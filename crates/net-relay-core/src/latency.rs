@@ -0,0 +1,162 @@
+//! Latency histograms of connection phases.
+//!
+//! Every closed connection adds the durations it recorded on its
+//! [`ConnectionInfo`](crate::connection::ConnectionInfo) (`handshake_ms`,
+//! `connect_ms`, `ttfb_ms`), so slowness can be told apart between the
+//! proxy's own handshake, reaching the target and the target's response.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Upper bounds (in milliseconds) of the histogram buckets.
+pub const LATENCY_BUCKETS_MS: [u64; 12] =
+    [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// Phase of a proxied connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionPhase {
+    /// From accept until the client is authenticated.
+    Handshake,
+    /// Connecting to the target.
+    Connect,
+    /// From the target connection until its first byte.
+    Ttfb,
+}
+
+impl ConnectionPhase {
+    /// Every phase, in the order they happen.
+    pub const ALL: [ConnectionPhase; 3] = [
+        ConnectionPhase::Handshake,
+        ConnectionPhase::Connect,
+        ConnectionPhase::Ttfb,
+    ];
+
+    /// Lowercase name used in metric labels.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionPhase::Handshake => "handshake",
+            ConnectionPhase::Connect => "connect",
+            ConnectionPhase::Ttfb => "ttfb",
+        }
+    }
+}
+
+/// Histogram bucket in a latency report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyBucket {
+    /// Upper bound in milliseconds (`None` for the overflow bucket).
+    pub le_ms: Option<u64>,
+    pub count: u64,
+}
+
+/// Latency report of one connection phase.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhaseLatency {
+    pub phase: ConnectionPhase,
+    pub count: u64,
+    pub avg_ms: f64,
+    pub max_ms: u64,
+    /// Upper bound of the bucket holding the median (`max_ms` in the overflow bucket).
+    pub p50_ms: Option<u64>,
+    pub p90_ms: Option<u64>,
+    pub p99_ms: Option<u64>,
+    pub buckets: Vec<LatencyBucket>,
+}
+
+/// Latency report of a histogram that isn't a connection phase, such as
+/// the lookups of a DNS resolver.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub avg_ms: f64,
+    pub max_ms: u64,
+    /// Upper bound of the bucket holding the median (`max_ms` in the overflow bucket).
+    pub p50_ms: Option<u64>,
+    pub p90_ms: Option<u64>,
+    pub p99_ms: Option<u64>,
+    pub buckets: Vec<LatencyBucket>,
+}
+
+/// Live latency histogram.
+#[derive(Debug, Default)]
+pub(crate) struct LatencyHistogram {
+    count: AtomicU64,
+    total_ms: AtomicU64,
+    max_ms: AtomicU64,
+    /// One counter per bucket in `LATENCY_BUCKETS_MS`, plus one for overflow.
+    buckets: [AtomicU64; LATENCY_BUCKETS_MS.len() + 1],
+}
+
+impl LatencyHistogram {
+    pub(crate) fn record(&self, ms: u64) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_ms.fetch_add(ms, Ordering::Relaxed);
+        self.max_ms.fetch_max(ms, Ordering::Relaxed);
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&le| ms <= le)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self, phase: ConnectionPhase) -> PhaseLatency {
+        let LatencySummary {
+            count,
+            avg_ms,
+            max_ms,
+            p50_ms,
+            p90_ms,
+            p99_ms,
+            buckets,
+        } = self.summary();
+        PhaseLatency {
+            phase,
+            count,
+            avg_ms,
+            max_ms,
+            p50_ms,
+            p90_ms,
+            p99_ms,
+            buckets,
+        }
+    }
+
+    pub(crate) fn summary(&self) -> LatencySummary {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        let count: u64 = counts.iter().sum();
+        let max_ms = self.max_ms.load(Ordering::Relaxed);
+        let percentile = |q: f64| {
+            let rank = ((count as f64 * q).ceil() as u64).max(1);
+            let mut seen = 0;
+            counts.iter().enumerate().find_map(|(i, &n)| {
+                seen += n;
+                (seen >= rank).then(|| {
+                    LATENCY_BUCKETS_MS
+                        .get(i)
+                        .map_or(max_ms, |&le| le.min(max_ms))
+                })
+            })
+        };
+        LatencySummary {
+            count,
+            avg_ms: self.total_ms.load(Ordering::Relaxed) as f64 / count.max(1) as f64,
+            max_ms,
+            p50_ms: percentile(0.5),
+            p90_ms: percentile(0.9),
+            p99_ms: percentile(0.99),
+            buckets: counts
+                .iter()
+                .enumerate()
+                .map(|(i, &count)| LatencyBucket {
+                    le_ms: LATENCY_BUCKETS_MS.get(i).copied(),
+                    count,
+                })
+                .collect(),
+        }
+    }
+}
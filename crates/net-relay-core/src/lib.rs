@@ -3,16 +3,80 @@
 //! Core library for the net-relay proxy service.
 //! Provides SOCKS5 and HTTP CONNECT proxy implementations.
 
+pub mod access;
+pub mod access_log;
+#[cfg(feature = "acme")]
+pub mod acme;
+pub mod alerts;
+pub mod auth;
+pub mod backup;
+pub mod bandwidth;
+pub mod build_info;
+pub mod capture;
+pub mod checkpoint;
 pub mod config;
 pub mod connection;
+pub mod dns;
+pub mod egress;
 pub mod error;
+pub mod event_log;
+pub mod hooks;
+pub mod hostname;
+pub(crate) mod http_client;
+pub mod latency;
+pub mod listeners;
 pub mod proxy;
+pub mod quota;
+pub mod rule_list;
+pub mod runtime;
+pub mod selftest;
 pub mod stats;
+pub mod statsd;
+pub mod target_errors;
+pub mod telemetry;
+pub mod tls;
+pub mod upstream;
 
+pub use access::{AccessMatcher, DecisionCache, TargetBlock, TargetCheck, DECISION_CACHE_TTL};
+pub use access_log::{AccessLog, AccessLogFormat, LogRotation};
+#[cfg(feature = "acme")]
+pub use acme::AcmeManager;
+pub use alerts::{AlertEngine, AlertSample, AlertState, AlertStatus, AlertTransition};
+pub use auth::{
+    AuthRequest, AuthenticatedUser, Authenticator, HttpCallbackAuthenticator, SessionLimits,
+};
+pub use bandwidth::BandwidthLimiter;
+pub use build_info::BuildInfo;
+pub use checkpoint::StatsCheckpoint;
 pub use config::{
-    AccessControlConfig, AccessRule, Config, ConfigManager, DashboardConfig, LoggingConfig,
-    RuleAction, ServerConfig, User,
+    AccessControlConfig, AccessRule, AcmeChallenge, AcmeConfig, AlertComparison, AlertMetric,
+    AlertRule, AlertsConfig, AllowedIpsMigration, ApiRateLimitConfig, AuthBackend, AuthConfig,
+    BackupConfig, Config, ConfigChange, ConfigEdit, ConfigLock, ConfigManager, ConfigProfile,
+    ConfigProvenance, ConfigSaveError, ConfigSource, CredentialLimits, DashboardConfig,
+    DashboardSecurityWarning, DashboardUser, DebugConfig, DnsConfig, DnsMode, EgressConfig,
+    EgressStrategy, HttpCallbackConfig, HttpProxyConfig, IpDecision, LimitsConfig, LoggingConfig,
+    MatchedRule, MetricsConfig, PasswordPolicy, PortRanges, RateLimit, RevisionConflict,
+    RuleAction, RuleMode, SaveStatus, ServerConfig, Socks5AuthMethod, StatsConfig, StatsdConfig,
+    SyslogConfig, SyslogFacility, SyslogTransport, TargetDecision, TcpKeepaliveConfig,
+    TelemetryConfig, TrustedDownstream, UnavailablePolicy, UpstreamConfig, UpstreamRelay, User,
+    UserToken, WebhookConfig,
+};
+pub use connection::{
+    CloseReason, Connection, ConnectionInfo, ConnectionState, CONNECTION_SCHEMA_VERSION,
+};
+pub use dns::{DnsResolver, DnsStats, ResolverStats};
+pub use egress::EgressSelector;
+pub use error::{DenyReason, Error, ErrorCode, Result};
+pub use event_log::{Event, EventLog, EventLogBackpressure};
+pub use hooks::{ConnectionHook, ConnectionHooks, HookDecision, HookFailurePolicy};
+pub use latency::{ConnectionPhase, LatencySummary, PhaseLatency};
+pub use listeners::{ListenOptions, ListenerInfo, ServerState};
+pub use quota::{QuotaPeriod, QuotaStatus, QuotaTracker};
+pub use runtime::{ListenerQueue, ProcessMetrics, TokioMetrics};
+pub use stats::{
+    AttemptCounts, AttemptOutcome, ConnectionStats, DecisionCacheCounts, DeniedAttempt,
+    HistoryPage, HistorySince, HistoryWindow, IpAttribution, ListenerCounters, ListenerStats,
+    RuleHits, Stats, StatsEvent, StatsSizes, TrafficStats, UserStats,
 };
-pub use connection::{Connection, ConnectionInfo, ConnectionState};
-pub use error::{Error, Result};
-pub use stats::{ConnectionStats, Stats, UserStats};
+pub use target_errors::{ConnectOutcome, TargetConnectStats};
+pub use upstream::{FailoverEvent, UpstreamPool, UpstreamStatus};
@@ -0,0 +1,206 @@
+//! Addresses the server's listeners are actually bound to.
+//!
+//! Configured ports may be `0` (the OS picks a free one) and a host name may
+//! resolve to several addresses, so the configuration alone doesn't say
+//! where the server can be reached. The server records every bind in a
+//! [`ServerState`] shared with the API, and marks a listener down when its
+//! service stops.
+//!
+//! Listening sockets are created by [`bind`] with the backlog and buffer
+//! sizes of [`ListenOptions`].
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::RwLock;
+
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpSocket};
+
+use crate::config::ServerConfig;
+
+/// Options of listening sockets, from `server.listen_*`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenOptions {
+    /// Length of the accept queue.
+    pub backlog: u32,
+    /// `SO_RCVBUF` in bytes, OS default when `None`.
+    pub recv_buffer: Option<u32>,
+    /// `SO_SNDBUF` in bytes, OS default when `None`.
+    pub send_buffer: Option<u32>,
+}
+
+impl ListenOptions {
+    pub fn from_config(server: &ServerConfig) -> Self {
+        Self {
+            backlog: server.listen_backlog,
+            recv_buffer: server.listen_recv_buffer,
+            send_buffer: server.listen_send_buffer,
+        }
+    }
+}
+
+impl Default for ListenOptions {
+    fn default() -> Self {
+        Self::from_config(&ServerConfig::default())
+    }
+}
+
+/// Bind a listener on `addr` like [`TcpListener::bind`], with `options`.
+pub fn bind(addr: SocketAddr, options: &ListenOptions) -> io::Result<TcpListener> {
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    // As TcpListener::bind does, so restarts don't wait out TIME_WAIT
+    #[cfg(not(windows))]
+    socket.set_reuseaddr(true)?;
+    if let Some(size) = options.recv_buffer {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(size) = options.send_buffer {
+        socket.set_send_buffer_size(size)?;
+    }
+    socket.bind(addr)?;
+    socket.listen(options.backlog)
+}
+
+/// [`bind`] the first address `host` resolves to that can be bound.
+pub async fn bind_host(host: &str, port: u16, options: &ListenOptions) -> io::Result<TcpListener> {
+    let mut last_error = None;
+    for addr in tokio::net::lookup_host((host, port)).await? {
+        match bind(addr, options) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} resolved to no addresses", host),
+        )
+    }))
+}
+
+/// One listener of the server, as bound at startup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListenerInfo {
+    /// Service on the listener: `socks5`, `http`, `api` or `tunnel`.
+    pub protocol: String,
+
+    /// Address as configured, e.g. `0.0.0.0:0` or `localhost:1080`.
+    pub configured: String,
+
+    /// Address the listener is bound to; `None` when binding failed or the
+    /// service is disabled.
+    pub bound: Option<SocketAddr>,
+
+    /// Whether the service is enabled in the configuration.
+    pub enabled: bool,
+
+    /// Whether the listener is accepting connections.
+    pub up: bool,
+
+    /// Accept queue length the listener was created with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backlog: Option<u32>,
+
+    /// Why binding failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ListenerInfo {
+    /// A listener bound to `bound` with `backlog`, accepting connections.
+    pub fn bound(protocol: &str, configured: &str, bound: SocketAddr, backlog: u32) -> Self {
+        Self {
+            protocol: protocol.to_string(),
+            configured: configured.to_string(),
+            bound: Some(bound),
+            enabled: true,
+            up: true,
+            backlog: Some(backlog),
+            error: None,
+        }
+    }
+
+    /// An enabled listener that couldn't be bound.
+    pub fn failed(protocol: &str, configured: &str, error: impl ToString) -> Self {
+        Self {
+            protocol: protocol.to_string(),
+            configured: configured.to_string(),
+            bound: None,
+            enabled: true,
+            up: false,
+            backlog: None,
+            error: Some(error.to_string()),
+        }
+    }
+
+    /// A service turned off in the configuration.
+    pub fn disabled(protocol: &str, configured: &str) -> Self {
+        Self {
+            protocol: protocol.to_string(),
+            configured: configured.to_string(),
+            bound: None,
+            enabled: false,
+            up: false,
+            backlog: None,
+            error: None,
+        }
+    }
+}
+
+/// The server's listeners, in the order they were bound.
+#[derive(Debug, Default)]
+pub struct ServerState {
+    listeners: RwLock<Vec<ListenerInfo>>,
+    /// Cargo features of the server binary.
+    features: RwLock<Vec<&'static str>>,
+}
+
+impl ServerState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a listener.
+    pub fn add(&self, listener: ListenerInfo) {
+        self.listeners.write().unwrap().push(listener);
+    }
+
+    /// Mark the `protocol` listener bound to `bound` as no longer accepting.
+    pub fn set_down(&self, protocol: &str, bound: SocketAddr) {
+        for listener in self.listeners.write().unwrap().iter_mut() {
+            if listener.protocol == protocol && listener.bound == Some(bound) {
+                listener.up = false;
+            }
+        }
+    }
+
+    /// Record the Cargo features the server binary was built with.
+    pub fn set_features(&self, features: Vec<&'static str>) {
+        *self.features.write().unwrap() = features;
+    }
+
+    /// Cargo features of the server binary, as recorded with [`set_features`](Self::set_features).
+    pub fn features(&self) -> Vec<&'static str> {
+        self.features.read().unwrap().clone()
+    }
+
+    /// All recorded listeners.
+    pub fn listeners(&self) -> Vec<ListenerInfo> {
+        self.listeners.read().unwrap().clone()
+    }
+
+    /// Addresses of the listeners that are up, optionally of one protocol.
+    pub fn bound_addrs(&self, protocol: Option<&str>) -> Vec<SocketAddr> {
+        self.listeners
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|l| l.up && protocol.is_none_or(|p| l.protocol == p))
+            .filter_map(|l| l.bound)
+            .collect()
+    }
+}
@@ -0,0 +1,61 @@
+//! Accept loop bookkeeping shared by the proxy and tunnel listeners.
+//!
+//! Accepts that fail (most often because the process ran out of file
+//! descriptors) are counted per listener and followed by a growing pause,
+//! so a listener that keeps failing doesn't spin a CPU core.
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tracing::error;
+
+use crate::stats::{ListenerCounters, Stats};
+
+/// Pause after the first failed accept, doubled for every further failure.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Longest pause between accept attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Counts accepts of one listener and paces retries after failures.
+pub(crate) struct AcceptBackoff {
+    counters: Arc<ListenerCounters>,
+    address: String,
+    /// Consecutive failed accepts.
+    failures: u32,
+}
+
+impl AcceptBackoff {
+    /// Bookkeeping for the `protocol` listener.
+    pub(crate) fn new(stats: &Stats, protocol: &str, listener: &TcpListener) -> io::Result<Self> {
+        let address = listener.local_addr()?;
+        Ok(Self {
+            counters: stats.listener(protocol, address),
+            address: address.to_string(),
+            failures: 0,
+        })
+    }
+
+    /// Count an accepted connection.
+    pub(crate) fn accepted(&mut self) {
+        self.counters.record_accept();
+        self.failures = 0;
+    }
+
+    /// Count and log a failed accept, then wait before the next attempt.
+    pub(crate) async fn failed(&mut self, e: &io::Error) {
+        self.counters.record_error(e);
+        let delay = INITIAL_BACKOFF
+            .saturating_mul(1 << self.failures.min(10))
+            .min(MAX_BACKOFF);
+        self.failures = self.failures.saturating_add(1);
+        error!(
+            "Failed to accept connection on {}: {} (retrying in {}ms)",
+            self.address,
+            e,
+            delay.as_millis()
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
@@ -0,0 +1,92 @@
+//! Builder shared by [`Socks5Proxy`](super::Socks5Proxy) and [`HttpProxy`](super::HttpProxy).
+
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{Config, ConfigManager};
+use crate::hooks::{ConnectionHook, ConnectionHooks, HookFailurePolicy};
+use crate::stats::Stats;
+
+/// Configures a proxy before it is built.
+///
+/// Everything is optional: without a config manager the proxy runs on
+/// [`Config::default`], without stats it collects into a private [`Stats`]
+/// that keeps no history, and without a shutdown token it runs until its
+/// task is dropped.
+pub struct ProxyBuilder<P> {
+    bind: Option<SocketAddr>,
+    config_manager: Option<ConfigManager>,
+    stats: Option<Arc<Stats>>,
+    shutdown: Option<CancellationToken>,
+    hooks: ConnectionHooks,
+    proxy: PhantomData<fn() -> P>,
+}
+
+/// Settings of a built proxy.
+pub(crate) struct ProxyParts {
+    pub bind: Option<SocketAddr>,
+    pub config_manager: ConfigManager,
+    pub stats: Arc<Stats>,
+    pub shutdown: CancellationToken,
+    pub hooks: ConnectionHooks,
+}
+
+impl<P> ProxyBuilder<P> {
+    pub(crate) fn new() -> Self {
+        Self {
+            bind: None,
+            config_manager: None,
+            stats: None,
+            shutdown: None,
+            hooks: ConnectionHooks::default(),
+            proxy: PhantomData,
+        }
+    }
+
+    /// Address `serve` listens on, instead of the one in the configuration.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.bind = Some(addr);
+        self
+    }
+
+    /// Configuration (users, access control, limits) the proxy enforces.
+    pub fn config(mut self, config_manager: ConfigManager) -> Self {
+        self.config_manager = Some(config_manager);
+        self
+    }
+
+    /// Collector connections are recorded in.
+    pub fn stats(mut self, stats: Arc<Stats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Stop accepting connections once `token` is cancelled.
+    pub fn shutdown(mut self, token: CancellationToken) -> Self {
+        self.shutdown = Some(token);
+        self
+    }
+
+    /// Run `hook` on every connection, after the hooks added before it.
+    ///
+    /// `on_failure` decides whether a connection proceeds when the hook
+    /// returns an error or panics.
+    pub fn hook(mut self, hook: Arc<dyn ConnectionHook>, on_failure: HookFailurePolicy) -> Self {
+        self.hooks.push(hook, on_failure);
+        self
+    }
+
+    pub(crate) fn into_parts(self) -> ProxyParts {
+        ProxyParts {
+            bind: self.bind,
+            config_manager: self
+                .config_manager
+                .unwrap_or_else(|| ConfigManager::new(Config::default(), None)),
+            stats: self.stats.unwrap_or_else(|| Arc::new(Stats::new(0))),
+            shutdown: self.shutdown.unwrap_or_default(),
+            hooks: self.hooks,
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! Minimal SOCKS5 and HTTP CONNECT clients.
+//!
+//! Both open a tunnel through a proxy and hand back the connected stream.
+//! Refusals are mapped onto [`Error`] the way the proxies in this crate
+//! produce them, so a denied target comes back as [`Error::AccessDenied`]
+//! and bad credentials as [`Error::AuthenticationFailed`].
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::net::{IpAddr, SocketAddr};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::error::{DenyReason, Error, Result};
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_NONE: u8 = 0x00;
+const AUTH_PASSWORD: u8 = 0x02;
+const AUTH_NO_ACCEPTABLE: u8 = 0xFF;
+const CMD_CONNECT: u8 = 0x01;
+const ADDR_TYPE_IPV4: u8 = 0x01;
+const ADDR_TYPE_DOMAIN: u8 = 0x03;
+const ADDR_TYPE_IPV6: u8 = 0x04;
+
+/// Largest HTTP response head accepted from the proxy.
+const MAX_RESPONSE_HEAD: usize = 8192;
+
+/// SOCKS5 client (RFC 1928, username/password per RFC 1929).
+pub struct Socks5Client;
+
+impl Socks5Client {
+    /// Connect to `target_host:port` through the SOCKS5 proxy at `proxy_addr`.
+    ///
+    /// `credentials` (username, password) are offered alongside "no
+    /// authentication"; the proxy picks the method.
+    pub async fn connect(
+        proxy_addr: SocketAddr,
+        target_host: &str,
+        port: u16,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<TcpStream> {
+        let mut stream = TcpStream::connect(proxy_addr).await?;
+
+        // Greeting
+        let greeting: &[u8] = match credentials {
+            Some(_) => &[SOCKS_VERSION, 2, AUTH_NONE, AUTH_PASSWORD],
+            None => &[SOCKS_VERSION, 1, AUTH_NONE],
+        };
+        stream.write_all(greeting).await?;
+        let mut choice = [0u8; 2];
+        stream.read_exact(&mut choice).await?;
+        if choice[0] != SOCKS_VERSION {
+            return Err(Error::InvalidSocks5Protocol(format!(
+                "Invalid version: {}",
+                choice[0]
+            )));
+        }
+        match (choice[1], credentials) {
+            (AUTH_NONE, _) => {}
+            (AUTH_PASSWORD, Some((username, password))) => {
+                authenticate(&mut stream, username, password).await?
+            }
+            (AUTH_NO_ACCEPTABLE, _) | (AUTH_PASSWORD, None) => {
+                return Err(Error::AuthenticationFailed)
+            }
+            (method, _) => {
+                return Err(Error::InvalidSocks5Protocol(format!(
+                    "Unexpected auth method: {}",
+                    method
+                )))
+            }
+        }
+
+        // Request: VER CMD RSV ATYP DST.ADDR DST.PORT
+        let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+        match target_host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push(ADDR_TYPE_IPV4);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                request.push(ADDR_TYPE_IPV6);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                let len = u8::try_from(target_host.len()).map_err(|_| {
+                    Error::InvalidSocks5Protocol(format!("Host name too long: {}", target_host))
+                })?;
+                request.push(ADDR_TYPE_DOMAIN);
+                request.push(len);
+                request.extend_from_slice(target_host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        // Reply: VER REP RSV ATYP BND.ADDR BND.PORT
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != SOCKS_VERSION {
+            return Err(Error::InvalidSocks5Protocol("Invalid reply version".into()));
+        }
+        let target = format!("{}:{}", target_host, port);
+        match reply[1] {
+            0x00 => {}
+            0x02 => return Err(Error::AccessDenied(DenyReason::Target, target)),
+            0x04 => return Err(Error::AddressResolution(target)),
+            0x06 => return Err(Error::Timeout),
+            0x07 => return Err(Error::UnsupportedCommand(CMD_CONNECT)),
+            0x08 => return Err(Error::UnsupportedAddressType(request[3])),
+            rep => {
+                return Err(Error::ConnectionRefused(format!(
+                    "{} (SOCKS5 reply {:#04x})",
+                    target, rep
+                )))
+            }
+        }
+        let bound_len = match reply[3] {
+            ADDR_TYPE_IPV4 => 4,
+            ADDR_TYPE_IPV6 => 16,
+            ADDR_TYPE_DOMAIN => stream.read_u8().await? as usize,
+            atyp => return Err(Error::UnsupportedAddressType(atyp)),
+        };
+        let mut bound = vec![0u8; bound_len + 2];
+        stream.read_exact(&mut bound).await?;
+
+        Ok(stream)
+    }
+}
+
+/// Username/password sub-negotiation.
+async fn authenticate(stream: &mut TcpStream, username: &str, password: &str) -> Result<()> {
+    let too_long = || Error::InvalidSocks5Protocol("Credentials longer than 255 bytes".into());
+    let ulen = u8::try_from(username.len()).map_err(|_| too_long())?;
+    let plen = u8::try_from(password.len()).map_err(|_| too_long())?;
+
+    let mut request = vec![0x01, ulen];
+    request.extend_from_slice(username.as_bytes());
+    request.push(plen);
+    request.extend_from_slice(password.as_bytes());
+    stream.write_all(&request).await?;
+
+    let mut status = [0u8; 2];
+    stream.read_exact(&mut status).await?;
+    if status[1] != 0x00 {
+        return Err(Error::AuthenticationFailed);
+    }
+    Ok(())
+}
+
+/// HTTP CONNECT client.
+pub struct HttpConnectClient;
+
+impl HttpConnectClient {
+    /// Connect to `target_host:port` through the HTTP proxy at `proxy_addr`.
+    ///
+    /// `credentials` (username, password) are sent pre-emptively as
+    /// `Proxy-Authorization: Basic`.
+    pub async fn connect(
+        proxy_addr: SocketAddr,
+        target_host: &str,
+        port: u16,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<TcpStream> {
+        let mut stream = TcpStream::connect(proxy_addr).await?;
+
+        let authority = if target_host.contains(':') {
+            format!("[{}]:{}", target_host, port)
+        } else {
+            format!("{}:{}", target_host, port)
+        };
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+        if let Some((username, password)) = credentials {
+            let token = STANDARD.encode(format!("{}:{}", username, password));
+            request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // Read the response head byte by byte so tunnel data stays in the stream
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= MAX_RESPONSE_HEAD {
+                return Err(Error::InvalidHttpProtocol("Response head too large".into()));
+            }
+            head.push(stream.read_u8().await?);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let status_line = head.lines().next().unwrap_or_default();
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| {
+                Error::InvalidHttpProtocol(format!("Invalid status line: {}", status_line))
+            })?;
+
+        match status {
+            200..=299 => Ok(stream),
+            403 => Err(Error::AccessDenied(DenyReason::Target, authority)),
+            407 => Err(Error::AuthenticationFailed),
+            429 => Err(Error::MaxConnectionsReached),
+            502 => Err(Error::ConnectionRefused(authority)),
+            504 => Err(Error::Timeout),
+            _ => Err(Error::InvalidHttpProtocol(format!(
+                "Unexpected response: {}",
+                status_line
+            ))),
+        }
+    }
+}
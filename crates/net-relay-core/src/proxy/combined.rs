@@ -0,0 +1,149 @@
+//! SOCKS5 and HTTP proxying on one port (`server.combined_port`).
+//!
+//! The first bytes of every connection are peeked rather than read, so the
+//! SOCKS5 or HTTP handler gets the connection exactly as the client sent it
+//! and behaves as on its own listener: same authentication, access control
+//! and statistics. A SOCKS5 greeting starts with the version byte `0x05`, an
+//! HTTP request with a method name and a space. Connections that show
+//! neither within [`SNIFF_TIMEOUT`] are closed.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+use crate::error::Result;
+use crate::proxy::accept::AcceptBackoff;
+use crate::proxy::http::HttpProxy;
+use crate::proxy::socks5::{Socks5Proxy, SOCKS_VERSION};
+
+/// How long a client may take to send enough bytes to tell its protocol.
+pub const SNIFF_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pause before peeking again at a connection whose first bytes are still
+/// ambiguous.
+const SNIFF_RETRY: Duration = Duration::from_millis(10);
+
+/// Request methods recognized as HTTP, with the space that follows them.
+const HTTP_METHODS: &[&[u8]] = &[
+    b"CONNECT ",
+    b"GET ",
+    b"POST ",
+    b"PUT ",
+    b"DELETE ",
+    b"HEAD ",
+    b"OPTIONS ",
+    b"PATCH ",
+    b"TRACE ",
+];
+
+/// Bytes peeked at most, enough for the longest method.
+const SNIFF_LEN: usize = 8;
+
+/// Protocol a connection turned out to speak.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sniffed {
+    Socks5,
+    Http,
+    /// Neither SOCKS5 nor HTTP.
+    Unknown,
+}
+
+/// Protocol of a connection starting with `prefix`, `None` while more
+/// bytes are needed to tell.
+pub fn sniff(prefix: &[u8]) -> Option<Sniffed> {
+    if *prefix.first()? == SOCKS_VERSION {
+        return Some(Sniffed::Socks5);
+    }
+    let mut undecided = false;
+    for method in HTTP_METHODS {
+        if prefix.starts_with(method) {
+            return Some(Sniffed::Http);
+        }
+        undecided |= method.starts_with(prefix);
+    }
+    (!undecided).then_some(Sniffed::Unknown)
+}
+
+/// Peek at `stream` until its protocol is known, giving up after
+/// [`SNIFF_TIMEOUT`] or when the client closes.
+async fn sniff_stream(stream: &TcpStream) -> Sniffed {
+    let sniffing = async {
+        let mut buf = [0u8; SNIFF_LEN];
+        loop {
+            let n = match stream.peek(&mut buf).await {
+                Ok(0) | Err(_) => return Sniffed::Unknown,
+                Ok(n) => n,
+            };
+            if let Some(sniffed) = sniff(&buf[..n]) {
+                return sniffed;
+            }
+            // Peeking returns right away while nothing new arrived
+            tokio::time::sleep(SNIFF_RETRY).await;
+        }
+    };
+    tokio::time::timeout(SNIFF_TIMEOUT, sniffing)
+        .await
+        .unwrap_or(Sniffed::Unknown)
+}
+
+/// Listener serving both SOCKS5 and HTTP proxy clients.
+///
+/// Connections are handed to the given proxies, and the accept loop stops
+/// with the SOCKS5 proxy's shutdown token.
+#[derive(Clone)]
+pub struct CombinedProxy {
+    socks5: Socks5Proxy,
+    http: HttpProxy,
+}
+
+impl CombinedProxy {
+    pub fn new(socks5: Socks5Proxy, http: HttpProxy) -> Self {
+        Self { socks5, http }
+    }
+
+    /// Run the combined accept loop on an already bound listener.
+    ///
+    /// Returns once the shutdown token is cancelled.
+    pub async fn run(&self, listener: TcpListener) -> Result<()> {
+        info!(
+            "Combined SOCKS5/HTTP proxy listening on {}",
+            listener.local_addr()?
+        );
+        let stats = self.socks5.stats();
+        let shutdown: &CancellationToken = self.socks5.shutdown_token();
+        let mut backoff = AcceptBackoff::new(stats, "combined", &listener)?;
+
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = shutdown.cancelled() => {
+                    info!("Combined proxy on {} stopped", listener.local_addr()?);
+                    return Ok(());
+                }
+            };
+            match accepted {
+                Ok((stream, client_addr)) => {
+                    backoff.accepted();
+                    let proxy = self.clone();
+                    stats.spawn_connection("combined", client_addr, async move {
+                        proxy.serve_client(stream, client_addr).await;
+                    });
+                }
+                Err(e) => backoff.failed(&e).await,
+            }
+        }
+    }
+
+    async fn serve_client(&self, stream: TcpStream, client_addr: SocketAddr) {
+        match sniff_stream(&stream).await {
+            Sniffed::Socks5 => self.socks5.serve_client(stream, client_addr).await,
+            Sniffed::Http => self.http.serve_client(stream, client_addr).await,
+            Sniffed::Unknown => debug!(
+                "Closing connection from {}: neither SOCKS5 nor HTTP",
+                client_addr
+            ),
+        }
+    }
+}
@@ -0,0 +1,491 @@
+//! Outbound connections to proxy targets.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::time::Instant;
+use tokio_rustls::client::TlsStream;
+use tracing::{debug, warn, Instrument, Span};
+
+use crate::access::TargetBlock;
+use crate::auth::SessionLimits;
+use crate::config::{ConfigManager, TcpKeepaliveConfig};
+use crate::connection::{ConnectionInfo, Protocol};
+use crate::error::{DenyReason, Error, Result};
+use crate::proxy::{proxy_protocol, tcp_keepalive, tunnel};
+use crate::stats::{DeniedAttempt, Stats};
+use crate::target_errors::ConnectOutcome;
+use crate::telemetry;
+
+/// A connection request on behalf of a proxy client.
+#[derive(Debug, Clone)]
+pub struct ConnectRequest<'a> {
+    /// Target host name or IP.
+    pub host: &'a str,
+
+    /// Target port.
+    pub port: u16,
+
+    /// Address of the proxy client.
+    pub client_addr: SocketAddr,
+
+    /// Authenticated username (if any).
+    pub username: Option<&'a str>,
+
+    /// Protocol the client used.
+    pub protocol: Protocol,
+}
+
+/// Outbound stream to a target, either direct or through an upstream relay.
+pub enum TargetStream {
+    /// Direct TCP connection to the target (with the egress address it's bound to).
+    Direct(TcpStream, Option<IpAddr>),
+
+    /// Tunnel through a trusted upstream relay (with the relay's address).
+    Tunnel(Box<TlsStream<TcpStream>>, String),
+}
+
+impl TargetStream {
+    /// Address of the upstream relay carrying this stream, if tunneled.
+    pub fn upstream(&self) -> Option<&str> {
+        match self {
+            TargetStream::Direct(..) => None,
+            TargetStream::Tunnel(_, relay) => Some(relay),
+        }
+    }
+
+    /// Local address a direct stream was bound to from `egress.addresses`.
+    pub fn egress(&self) -> Option<IpAddr> {
+        match self {
+            TargetStream::Direct(_, egress) => *egress,
+            TargetStream::Tunnel(..) => None,
+        }
+    }
+
+    /// Wait until the underlying socket is readable.
+    pub async fn readable(&self) -> io::Result<()> {
+        match self {
+            TargetStream::Direct(stream, _) => stream.readable().await,
+            TargetStream::Tunnel(stream, _) => stream.get_ref().0.readable().await,
+        }
+    }
+}
+
+impl AsyncRead for TargetStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            TargetStream::Direct(stream, _) => Pin::new(stream).poll_read(cx, buf),
+            TargetStream::Tunnel(stream, _) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for TargetStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            TargetStream::Direct(stream, _) => Pin::new(stream).poll_write(cx, buf),
+            TargetStream::Tunnel(stream, _) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            TargetStream::Direct(stream, _) => Pin::new(stream).poll_flush(cx),
+            TargetStream::Tunnel(stream, _) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            TargetStream::Direct(stream, _) => Pin::new(stream).poll_shutdown(cx),
+            TargetStream::Tunnel(stream, _) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// A target connection made by [`connect_target`].
+pub struct ConnectedTarget {
+    pub stream: TargetStream,
+
+    /// Attempts it took to connect.
+    pub attempts: u32,
+
+    /// Milliseconds spent resolving the target over all attempts; `None` for
+    /// IP targets and tunnels, which the upstream relay resolves.
+    pub resolve_ms: Option<u64>,
+}
+
+/// Connect to a proxy target on behalf of a client.
+///
+/// Targets that refuse the connection or are unreachable are tried again up
+/// to `limits.connect_retries` times, pausing with a jittered, doubling delay
+/// in between. All attempts together stay within `limits.timeout` (if set);
+/// running out of it fails with [`Error::Timeout`], which is not retried.
+pub async fn connect_target(
+    request: &ConnectRequest<'_>,
+    config_manager: &ConfigManager,
+) -> Result<ConnectedTarget> {
+    let limits = config_manager.get_limits().await;
+    let deadline =
+        (limits.timeout > 0).then(|| Instant::now() + Duration::from_secs(limits.timeout));
+    let mut attempts = 1;
+    let mut resolve_ms = None;
+    loop {
+        let attempt = connect_once(
+            request,
+            config_manager,
+            &limits.tcp_keepalive,
+            &mut resolve_ms,
+        );
+        let result = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, attempt)
+                .await
+                .unwrap_or(Err(Error::Timeout)),
+            None => attempt.await,
+        };
+        let error = match result {
+            Ok(stream) => {
+                return Ok(ConnectedTarget {
+                    stream,
+                    attempts,
+                    resolve_ms,
+                })
+            }
+            Err(e) if attempts <= limits.connect_retries && is_retryable(&e) => e,
+            Err(e) => return Err(e),
+        };
+        let delay = limits.connect_retry_delay(attempts);
+        if deadline.is_some_and(|deadline| Instant::now() + delay >= deadline) {
+            return Err(error);
+        }
+        debug!(
+            "Connecting to {}:{} failed ({}), retrying in {}ms",
+            request.host,
+            request.port,
+            error,
+            delay.as_millis()
+        );
+        tokio::time::sleep(delay).await;
+        attempts += 1;
+    }
+}
+
+/// Whether a failed connect may succeed when tried again right away.
+fn is_retryable(error: &Error) -> bool {
+    match error {
+        // Reported by an upstream relay for the target
+        Error::ConnectionRefused(_) => true,
+        Error::Io(e) => matches!(
+            e.kind(),
+            io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::HostUnreachable
+                | io::ErrorKind::NetworkUnreachable
+        ),
+        _ => false,
+    }
+}
+
+/// A single attempt of [`connect_target`].
+///
+/// When a trusted upstream is enabled, the connection is tunneled through the
+/// most preferred healthy relay, moving on to the next one if the tunnel
+/// can't be opened; the upstream applies its own outbound options. Otherwise,
+/// if the target is listed in `access_control.proxy_protocol_targets`, a
+/// PROXY protocol v2 header carrying the client address is written before the
+/// stream is returned. The header is not counted in relay byte totals.
+///
+/// Time spent resolving the target is added to `resolve_ms`.
+async fn connect_once(
+    request: &ConnectRequest<'_>,
+    config_manager: &ConfigManager,
+    keepalive: &TcpKeepaliveConfig,
+    resolve_ms: &mut Option<u64>,
+) -> Result<TargetStream> {
+    let pool = config_manager.upstream_pool();
+    let relays = pool.candidates();
+    if !relays.is_empty() {
+        let mut last_error = None;
+        for relay in relays {
+            match tunnel::open_tunnel(&relay, request)
+                .instrument(telemetry::connect_span(Some(&relay.address)))
+                .await
+            {
+                Ok(stream) => {
+                    tcp_keepalive::enable(stream.get_ref().0, keepalive);
+                    Span::current().record("upstream", relay.address.as_str());
+                    pool.record_success(&relay.address, None);
+                    return Ok(TargetStream::Tunnel(Box::new(stream), relay.address));
+                }
+                // The relay answered; the target itself was refused or unreachable
+                Err(e @ (Error::AccessDenied(..) | Error::ConnectionRefused(_))) => return Err(e),
+                Err(e) => {
+                    debug!("Upstream relay {} failed: {}", relay.address, e);
+                    pool.record_failure(&relay.address, &e.to_string(), false);
+                    last_error = Some(e);
+                }
+            }
+        }
+        return Err(last_error.unwrap_or_else(|| Error::Tunnel("No upstream relay".into())));
+    }
+
+    let (mut stream, egress) = connect_resolved(request, config_manager, resolve_ms).await?;
+    tcp_keepalive::enable(&stream, keepalive);
+
+    if config_manager.wants_proxy_protocol(request.host).await {
+        let header = proxy_protocol::v2_header(request.client_addr, stream.peer_addr()?);
+        stream.write_all(&header).await?;
+        debug!(
+            "Sent PROXY protocol header to {}:{}",
+            request.host, request.port
+        );
+    }
+
+    Ok(TargetStream::Direct(stream, egress))
+}
+
+/// Count how [`connect_target`] to `host` ended in the per-target connect
+/// outcomes of `stats`.
+pub fn record_connect_outcome<T>(stats: &Stats, host: &str, result: &Result<T>) {
+    let outcome = match result {
+        Ok(_) => Some(ConnectOutcome::Success),
+        Err(e) => ConnectOutcome::of_error(e),
+    };
+    if let Some(outcome) = outcome {
+        stats.record_connect_outcome(host, outcome);
+    }
+}
+
+/// Error reported to the client when [`connect_target`] fails for `target`.
+///
+/// Denials and resolution failures are passed on; anything else is reported
+/// as [`Error::ConnectionRefused`].
+pub fn connect_failure(error: Error, target: &str) -> Error {
+    match error {
+        Error::AccessDenied(reason, message) => {
+            warn!("Target blocked: {}", message);
+            Error::AccessDenied(reason, message)
+        }
+        Error::AddressResolution(message) => {
+            warn!("Failed to resolve {}: {}", target, message);
+            Error::AddressResolution(message)
+        }
+        e => {
+            warn!("Failed to connect to {}: {}", target, e);
+            Error::ConnectionRefused(target.to_string())
+        }
+    }
+}
+
+/// Apply the access rules to `host`, counting rule hits in `stats`.
+///
+/// `reason` is [`DenyReason::Target`] or [`DenyReason::Sni`]. Targets that
+/// only a monitored rule denies are recorded as would-deny attempts and let
+/// through; blocked ones fail with the rule that blocked them. Decisions
+/// are cached per client, target and `username`.
+#[allow(clippy::too_many_arguments)]
+pub async fn check_target_access(
+    host: &str,
+    port: u16,
+    reason: DenyReason,
+    client_addr: SocketAddr,
+    username: Option<&str>,
+    source: &str,
+    stats: &Stats,
+    config_manager: &ConfigManager,
+) -> std::result::Result<(), (Error, TargetBlock)> {
+    let (check, cached) =
+        config_manager.evaluate_target_cached(client_addr.ip(), host, port, username);
+    stats.record_decision_cache(cached);
+    stats.record_rule_hits(&check);
+
+    let target = format!("{}:{}", host, port);
+    let (kind, subject) = match reason {
+        DenyReason::Sni => ("SNI", host),
+        _ => ("Target", target.as_str()),
+    };
+    if let Some(block) = check.block {
+        let message = format!("{} blocked: {} by {}", kind, subject, block.describe());
+        warn!("{}", message);
+        return Err((Error::AccessDenied(reason, message), block));
+    }
+    if let Some(block) = check.would_block {
+        let message = format!(
+            "{} would be blocked: {} by {}",
+            kind,
+            subject,
+            block.describe()
+        );
+        warn!("{} (monitor mode, allowed)", message);
+        let attempt = DeniedAttempt::new(
+            client_addr.ip().to_canonical().to_string(),
+            source,
+            Some(target),
+            message,
+        )
+        .with_code(reason.code())
+        .monitored();
+        stats.record_denied(attempt).await;
+    }
+    Ok(())
+}
+
+/// Refuse new connections for a user whose data quota is used up.
+pub async fn check_quota(
+    username: Option<&str>,
+    stats: &Stats,
+    config_manager: &ConfigManager,
+) -> Result<()> {
+    let Some(username) = username else {
+        return Ok(());
+    };
+    let Some(user) = config_manager.get_user(username).await else {
+        return Ok(());
+    };
+    match stats.quota_status(&user) {
+        Some(status) if status.exceeded => Err(Error::QuotaExceeded(format!(
+            "user '{}' has used {} of {} bytes; quota resets at {}",
+            username,
+            status.used_bytes,
+            status.quota_bytes,
+            status.resets_at.to_rfc3339()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Fail with [`Error::MaxConnectionsReached`] when `username` already has
+/// `limits.connection_limit` active connections.
+pub async fn check_connection_limit(
+    username: Option<&str>,
+    limits: &SessionLimits,
+    stats: &Stats,
+) -> Result<()> {
+    let (Some(username), Some(limit)) = (username, limits.connection_limit) else {
+        return Ok(());
+    };
+    if limit == 0 {
+        return Ok(());
+    }
+    match stats.get_user(username).await {
+        Some(user) if user.active_connections >= limit as u64 => Err(Error::MaxConnectionsReached),
+        _ => Ok(()),
+    }
+}
+
+/// Record a new connection, attaching the client's reverse-DNS name when
+/// `stats.resolve_client_hostnames` is enabled.
+///
+/// A cached name is attached right away; otherwise the lookup runs in the
+/// background and fills in the active connection when it completes, so the
+/// handshake never waits on DNS.
+pub async fn track_connection(
+    mut info: ConnectionInfo,
+    stats: &Arc<Stats>,
+    config_manager: &ConfigManager,
+) {
+    let client_ip = info
+        .client_addr
+        .parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| info.client_addr.parse::<IpAddr>());
+    let lookup = match client_ip {
+        Ok(ip) if config_manager.resolves_client_hostnames().await => {
+            match config_manager.cached_client_hostname(ip) {
+                Some(hostname) => {
+                    info.client_hostname = hostname;
+                    None
+                }
+                None => Some(ip),
+            }
+        }
+        _ => None,
+    };
+
+    let id = info.id;
+    stats.add_connection(info).await;
+
+    if let Some(ip) = lookup {
+        let stats = Arc::clone(stats);
+        let config_manager = config_manager.clone();
+        tokio::spawn(async move {
+            if let Some(hostname) = config_manager.client_hostname(ip).await {
+                stats.set_client_hostname(id, hostname);
+            }
+        });
+    }
+}
+
+/// Resolve the target with the configured `[dns]` mode and connect to the first reachable address.
+///
+/// Each attempt is bound to an `[egress]` address when one of the target's
+/// family is configured; the chosen address is returned with the stream.
+async fn connect_resolved(
+    request: &ConnectRequest<'_>,
+    config_manager: &ConfigManager,
+    resolve_ms: &mut Option<u64>,
+) -> Result<(TcpStream, Option<IpAddr>)> {
+    let started = Instant::now();
+    let addrs = config_manager
+        .resolve(request.host)
+        .instrument(telemetry::resolve_span(request.host))
+        .await;
+    let literal = request
+        .host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .is_ok();
+    if !literal {
+        *resolve_ms.get_or_insert(0) += started.elapsed().as_millis() as u64;
+    }
+    let addrs = addrs?;
+
+    async {
+        let mut last_error = None;
+        for ip in addrs {
+            let egress = config_manager
+                .egress_address(ip, request.username, request.client_addr.ip())
+                .await;
+            let target = SocketAddr::new(ip, request.port);
+            match connect_from(target, egress).await {
+                Ok(stream) => {
+                    Span::current().record("peer", tracing::field::display(target));
+                    return Ok((stream, egress));
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error
+            .map(Error::Io)
+            .unwrap_or_else(|| Error::AddressResolution(request.host.to_string())))
+    }
+    .instrument(telemetry::connect_span(None))
+    .await
+}
+
+/// Connect to `target`, from `source` when given.
+async fn connect_from(target: SocketAddr, source: Option<IpAddr>) -> io::Result<TcpStream> {
+    let Some(source) = source else {
+        return TcpStream::connect(target).await;
+    };
+    let socket = if target.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    socket.bind(SocketAddr::new(source, 0))?;
+    socket.connect(target).await
+}
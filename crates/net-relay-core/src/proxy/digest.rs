@@ -0,0 +1,269 @@
+//! HTTP Digest authentication (RFC 7616) for the HTTP CONNECT proxy.
+//!
+//! Only `qop=auth` with the MD5 and SHA-256 algorithms is supported (no
+//! `-sess` variants, no `userhash`). Nonces are issued by a [`NonceStore`]
+//! and expire after [`NONCE_TTL`]; each nonce count (`nc`) is accepted once,
+//! so a captured `Proxy-Authorization` header can't be replayed.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::random_hex;
+
+/// How long an issued nonce is accepted.
+pub const NONCE_TTL: Duration = Duration::from_secs(300);
+
+/// Most nonces remembered; the oldest are forgotten first.
+const MAX_NONCES: usize = 10_000;
+
+/// Digest hash algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    Md5,
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    /// Algorithm named in a challenge response; absent means MD5.
+    pub fn parse(name: Option<&str>) -> Option<Self> {
+        match name {
+            None => Some(DigestAlgorithm::Md5),
+            Some(name) if name.eq_ignore_ascii_case("MD5") => Some(DigestAlgorithm::Md5),
+            Some(name) if name.eq_ignore_ascii_case("SHA-256") => Some(DigestAlgorithm::Sha256),
+            Some(_) => None,
+        }
+    }
+
+    /// Name in `algorithm=` parameters.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DigestAlgorithm::Md5 => "MD5",
+            DigestAlgorithm::Sha256 => "SHA-256",
+        }
+    }
+
+    /// Lowercase hex hash of `data`.
+    pub fn hash(self, data: &str) -> String {
+        match self {
+            DigestAlgorithm::Md5 => format!("{:x}", md5::compute(data)),
+            DigestAlgorithm::Sha256 => ring::digest::digest(&ring::digest::SHA256, data.as_bytes())
+                .as_ref()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+        }
+    }
+}
+
+/// Parameters of a `Digest` authorization header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DigestCredentials {
+    pub username: String,
+    pub realm: String,
+    pub nonce: String,
+    pub uri: String,
+    pub response: String,
+    pub algorithm: Option<String>,
+    pub qop: Option<String>,
+    pub nc: Option<String>,
+    pub cnonce: Option<String>,
+}
+
+impl DigestCredentials {
+    /// Parse the parameters following `Digest ` in a `Proxy-Authorization` header.
+    ///
+    /// Returns `None` when a required parameter is missing or the list is malformed.
+    pub fn parse(params: &str) -> Option<Self> {
+        let mut credentials = DigestCredentials::default();
+        let (mut username, mut realm, mut nonce, mut uri, mut response) =
+            (None, None, None, None, None);
+        let mut rest = params.trim();
+        while !rest.is_empty() {
+            let (name, after) = rest.split_once('=')?;
+            let name = name.trim().to_ascii_lowercase();
+            let after = after.trim_start();
+            let (value, after) = if let Some(quoted) = after.strip_prefix('"') {
+                unquote(quoted)?
+            } else {
+                let end = after.find(',').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            };
+            match name.as_str() {
+                "username" => username = Some(value),
+                "realm" => realm = Some(value),
+                "nonce" => nonce = Some(value),
+                "uri" => uri = Some(value),
+                "response" => response = Some(value),
+                "algorithm" => credentials.algorithm = Some(value),
+                "qop" => credentials.qop = Some(value),
+                "nc" => credentials.nc = Some(value),
+                "cnonce" => credentials.cnonce = Some(value),
+                _ => {}
+            }
+            let after = after.trim_start();
+            rest = match after.strip_prefix(',') {
+                Some(next) => next.trim_start(),
+                None if after.is_empty() => after,
+                None => return None,
+            };
+        }
+        credentials.username = username?;
+        credentials.realm = realm?;
+        credentials.nonce = nonce?;
+        credentials.uri = uri?;
+        credentials.response = response?;
+        Some(credentials)
+    }
+}
+
+/// Read a quoted-string body (after the opening quote), returning the
+/// unescaped value and the input after the closing quote.
+fn unquote(input: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &input[i + 1..])),
+            '\\' => value.push(chars.next()?.1),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Expected `response` for `qop=auth`.
+#[allow(clippy::too_many_arguments)]
+pub fn digest_response(
+    algorithm: DigestAlgorithm,
+    username: &str,
+    realm: &str,
+    password: &str,
+    method: &str,
+    uri: &str,
+    nonce: &str,
+    nc: &str,
+    cnonce: &str,
+) -> String {
+    let ha1 = algorithm.hash(&format!("{}:{}:{}", username, realm, password));
+    let ha2 = algorithm.hash(&format!("{}:{}", method, uri));
+    algorithm.hash(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2))
+}
+
+/// State of a nonce presented by a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonceStatus {
+    /// Issued, unexpired, and the nonce count is new; it is now used up.
+    Fresh,
+    /// Expired or unknown (e.g. issued before a restart); the client should
+    /// retry with a new nonce.
+    Stale,
+    /// The nonce count was already used with this nonce.
+    Replayed,
+}
+
+struct Nonce {
+    issued_at: Instant,
+    /// Highest nonce count accepted so far.
+    last_nc: u32,
+}
+
+/// Nonces issued in challenges, bounded and expiring.
+#[derive(Default)]
+pub struct NonceStore {
+    inner: Mutex<Nonces>,
+}
+
+#[derive(Default)]
+struct Nonces {
+    by_value: HashMap<String, Nonce>,
+    /// Issue order, for expiry and eviction.
+    order: VecDeque<String>,
+}
+
+impl Nonces {
+    fn prune(&mut self, now: Instant) {
+        while let Some(oldest) = self.order.front() {
+            let expired = self
+                .by_value
+                .get(oldest)
+                .is_none_or(|n| now.duration_since(n.issued_at) >= NONCE_TTL);
+            if !expired && self.order.len() < MAX_NONCES {
+                break;
+            }
+            if let Some(oldest) = self.order.pop_front() {
+                self.by_value.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl NonceStore {
+    /// Issue a new nonce (`None` if no secure randomness is available).
+    pub fn issue(&self) -> Option<String> {
+        let nonce = random_hex(16).ok()?;
+        let now = Instant::now();
+        let mut nonces = self.inner.lock().unwrap();
+        nonces.prune(now);
+        nonces.by_value.insert(
+            nonce.clone(),
+            Nonce {
+                issued_at: now,
+                last_nc: 0,
+            },
+        );
+        nonces.order.push_back(nonce.clone());
+        Some(nonce)
+    }
+
+    /// Use nonce count `nc` of `nonce`, which must be higher than any
+    /// count used with it before.
+    pub fn consume(&self, nonce: &str, nc: u32) -> NonceStatus {
+        let mut nonces = self.inner.lock().unwrap();
+        match nonces.by_value.get_mut(nonce) {
+            Some(n) if n.issued_at.elapsed() >= NONCE_TTL => NonceStatus::Stale,
+            Some(n) if nc <= n.last_nc => NonceStatus::Replayed,
+            Some(n) => {
+                n.last_nc = nc;
+                NonceStatus::Fresh
+            }
+            None => NonceStatus::Stale,
+        }
+    }
+
+    /// Number of remembered nonces.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().by_value.len()
+    }
+
+    /// Whether no nonces are remembered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Compare two response digests without leaking where they differ.
+pub(crate) fn responses_match(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b.to_ascii_lowercase()))
+            == 0
+}
+
+/// `Proxy-Authenticate` header values offering Digest with `nonce`, strongest first.
+pub fn challenges(realm: &str, nonce: &str, stale: bool) -> Vec<String> {
+    [DigestAlgorithm::Sha256, DigestAlgorithm::Md5]
+        .iter()
+        .map(|algorithm| {
+            format!(
+                "Digest realm=\"{}\", qop=\"auth\", algorithm={}, nonce=\"{}\"{}",
+                realm,
+                algorithm.as_str(),
+                nonce,
+                if stale { ", stale=true" } else { "" }
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,133 @@
+//! Tolerant parsing of HTTP message heads.
+//!
+//! Clients in the wild end lines with `\r\n`, a bare `\n` or a bare `\r`,
+//! pad names and values with odd whitespace and fold long values onto
+//! continuation lines. [`split_head`] finds the end of a head in whatever
+//! the client sent so far, and [`Headers::parse`] turns its header lines
+//! into name/value pairs, keeping every field in the order received.
+
+use std::borrow::Cow;
+
+use crate::error::{Error, Result};
+use crate::proxy::parse::Parsed;
+
+/// Header fields accepted in one head.
+pub const MAX_HEADERS: usize = 100;
+
+/// Split the start line and header lines off the start of `buf`, up to and
+/// including the first blank line after the start line.
+///
+/// The start line's terminator sets the line ending for the whole head: a
+/// bare `\r` ends every line, otherwise `\n` does (with or without a `\r`
+/// before it). Line endings are not part of the returned lines.
+pub fn split_head(buf: &[u8]) -> Parsed<Vec<Cow<'_, str>>> {
+    let first = buf.iter().position(|&b| b == b'\n' || b == b'\r')?;
+    let cr_only = match (buf[first], buf.get(first + 1)) {
+        // Can't tell `\r` from `\r\n` until the next byte arrives
+        (b'\r', None) => return None,
+        (b'\r', Some(&next)) => next != b'\n',
+        _ => false,
+    };
+    let eol = if cr_only { b'\r' } else { b'\n' };
+
+    let mut lines = Vec::new();
+    let mut start = 0;
+    while let Some(offset) = buf[start..].iter().position(|&b| b == eol) {
+        let raw = &buf[start..start + offset];
+        let line = String::from_utf8_lossy(raw.strip_suffix(b"\r").unwrap_or(raw));
+        start += offset + 1;
+        if !lines.is_empty() && line.trim().is_empty() {
+            return Some((lines, start));
+        }
+        lines.push(line);
+    }
+    None
+}
+
+/// Header fields of a message head, in the order received.
+///
+/// Names keep their original case and are looked up case-insensitively;
+/// repeated names are kept as separate fields.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// Parse header lines (without the start line).
+    ///
+    /// A line starting with a space or tab continues the value of the field
+    /// before it, joined by a single space; with no field before it, it is
+    /// read as a field of its own. Names and values are trimmed, and lines
+    /// without a name and `:` are skipped. Fails with more than
+    /// [`MAX_HEADERS`] fields.
+    pub fn parse<S: AsRef<str>>(lines: &[S]) -> Result<Self> {
+        let mut fields: Vec<(String, String)> = Vec::new();
+        for line in lines {
+            let line = line.as_ref();
+            if line.starts_with([' ', '\t']) {
+                if let Some((_, value)) = fields.last_mut() {
+                    let folded = line.trim();
+                    if !folded.is_empty() {
+                        if !value.is_empty() {
+                            value.push(' ');
+                        }
+                        value.push_str(folded);
+                    }
+                    continue;
+                }
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let name = name.trim();
+            if name.is_empty() {
+                continue;
+            }
+            if fields.len() == MAX_HEADERS {
+                return Err(Error::InvalidHttpProtocol("Too many headers".into()));
+            }
+            fields.push((name.to_string(), value.trim().to_string()));
+        }
+        Ok(Self { fields })
+    }
+
+    /// Value of the first field named `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Values of every field named `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether a comma-separated list in the fields named `name` (e.g.
+    /// `Connection`) holds `token`, ignoring case.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Every field as `(name, value)`, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
@@ -1,197 +1,559 @@
 //! HTTP CONNECT proxy implementation.
 
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use std::io;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpListener, TcpStream};
-use tracing::{debug, error, info, warn};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn, Instrument, Span};
 
+use crate::auth::{AuthRequest, AuthenticatedUser, SessionLimits};
 use crate::config::ConfigManager;
-use crate::connection::Protocol;
-use crate::error::{Error, Result};
-use crate::proxy::relay::relay_tcp;
+use crate::connection::{CloseReason, ConnectionInfo, Protocol};
+use crate::error::{DenyReason, Error, Result};
+use crate::hooks::ConnectionHooks;
+use crate::hostname;
+use crate::listeners::{self, ListenOptions};
+use crate::proxy::accept::AcceptBackoff;
+use crate::proxy::builder::ProxyBuilder;
+use crate::proxy::connect::{
+    check_connection_limit, check_quota, check_target_access, connect_failure, connect_target,
+    record_connect_outcome, track_connection, ConnectRequest,
+};
+use crate::proxy::digest::{
+    self, digest_response, responses_match, DigestAlgorithm, DigestCredentials, NonceStatus,
+    NonceStore,
+};
+use crate::proxy::headers::{split_head, Headers};
+use crate::proxy::parse::{read_message, Parsed};
+use crate::proxy::relay::{relay_for_user, relay_reusing_client, RelayResult};
+use crate::proxy::response::HttpResponses;
+use crate::proxy::sni::{inspect_sni, SniInspection};
+use crate::proxy::tcp_keepalive;
 use crate::stats::Stats;
+use crate::telemetry;
+
+/// Largest request head (request line and headers) accepted from a client.
+const MAX_REQUEST_HEAD: usize = 8192;
+
+/// 407 responses sent on one connection before it is closed.
+const MAX_CHALLENGES: usize = 3;
+
+/// Realm of the `Proxy-Authenticate` challenges.
+const REALM: &str = "Proxy";
+
+/// Standard base64 that accepts credentials with or without padding.
+const BASIC_AUTH: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
 
 /// HTTP CONNECT proxy server.
+///
+/// Embedders build it with [`HttpProxy::builder`]. [`run`](HttpProxy::run) serves a
+/// pre-bound listener, [`serve`](HttpProxy::serve) binds one itself; both return
+/// once the shutdown token is cancelled. Connections already accepted keep
+/// relaying until they close or [`Stats::begin_shutdown`] is called.
+#[derive(Clone)]
 pub struct HttpProxy {
-    /// Bind address.
-    bind_addr: SocketAddr,
-
     /// Statistics collector.
     stats: Arc<Stats>,
 
     /// Configuration manager.
     config_manager: ConfigManager,
+
+    /// Address `serve` binds instead of the configured one.
+    bind: Option<SocketAddr>,
+
+    /// Cancelled to stop accepting connections.
+    shutdown: CancellationToken,
+
+    /// Hooks run as connections open and close.
+    hooks: ConnectionHooks,
+
+    /// Nonces issued in Digest challenges.
+    nonces: Arc<NonceStore>,
 }
 
 impl HttpProxy {
     /// Create a new HTTP CONNECT proxy.
     pub fn new(
-        bind_addr: SocketAddr,
         _auth: Option<(String, String)>, // Deprecated, uses config_manager now
         stats: Arc<Stats>,
         config_manager: ConfigManager,
     ) -> Self {
-        Self {
-            bind_addr,
-            stats,
-            config_manager,
-        }
+        Self::builder().stats(stats).config(config_manager).build()
+    }
+
+    /// Start configuring an HTTP CONNECT proxy.
+    pub fn builder() -> ProxyBuilder<Self> {
+        ProxyBuilder::new()
+    }
+
+    /// Statistics collector connections are recorded in.
+    pub fn stats(&self) -> &Arc<Stats> {
+        &self.stats
+    }
+
+    /// Bind the builder's address (or `server.host`:`server.http_port`) and run on it.
+    pub async fn serve(&self) -> Result<()> {
+        let server = self.config_manager.get_server().await;
+        let options = ListenOptions::from_config(&server);
+        let listener = match self.bind {
+            Some(addr) => listeners::bind(addr, &options)?,
+            None => listeners::bind_host(&server.host, server.http_port, &options).await?,
+        };
+        self.run(listener).await
     }
 
-    /// Start the HTTP proxy server.
-    pub async fn run(&self) -> Result<()> {
-        let listener = TcpListener::bind(self.bind_addr).await?;
-        info!("HTTP CONNECT proxy listening on {}", self.bind_addr);
+    /// Run the HTTP proxy accept loop on an already bound listener.
+    ///
+    /// Returns once the shutdown token is cancelled.
+    pub async fn run(&self, listener: TcpListener) -> Result<()> {
+        info!("HTTP CONNECT proxy listening on {}", listener.local_addr()?);
+        let mut backoff = AcceptBackoff::new(&self.stats, "http", &listener)?;
 
         loop {
-            match listener.accept().await {
-                Ok((stream, client_addr)) => {
-                    let stats = Arc::clone(&self.stats);
-                    let config_manager = self.config_manager.clone();
-
-                    tokio::spawn(async move {
-                        if let Err(e) =
-                            handle_client(stream, client_addr, stats, config_manager).await
-                        {
-                            debug!("Connection from {} error: {}", client_addr, e);
-                        }
-                    });
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = self.shutdown.cancelled() => {
+                    info!("HTTP CONNECT proxy on {} stopped", listener.local_addr()?);
+                    return Ok(());
                 }
-                Err(e) => {
-                    error!("Failed to accept connection: {}", e);
+            };
+            match accepted {
+                Ok((stream, client_addr)) => {
+                    backoff.accepted();
+                    let proxy = self.clone();
+                    self.stats
+                        .spawn_connection("http", client_addr, async move {
+                            proxy.serve_client(stream, client_addr).await;
+                        });
                 }
+                Err(e) => backoff.failed(&e).await,
             }
         }
     }
+
+    /// Serve one accepted client connection until it closes.
+    pub(crate) async fn serve_client(&self, stream: TcpStream, client_addr: SocketAddr) {
+        let span = telemetry::connection_span(Protocol::HttpConnect, client_addr);
+        if let Err(e) = handle_client(
+            stream,
+            client_addr,
+            Arc::clone(&self.stats),
+            self.config_manager.clone(),
+            self.hooks.clone(),
+            Arc::clone(&self.nonces),
+        )
+        .instrument(span.clone())
+        .await
+        {
+            telemetry::record_error(&span, &e);
+            debug!("Connection from {} error: {}", client_addr, e);
+            self.stats.record_rejected("http", client_addr, &e).await;
+        }
+    }
+}
+
+impl ProxyBuilder<HttpProxy> {
+    /// Build the proxy.
+    pub fn build(self) -> HttpProxy {
+        let parts = self.into_parts();
+        HttpProxy {
+            stats: parts.stats,
+            config_manager: parts.config_manager,
+            bind: parts.bind,
+            shutdown: parts.shutdown,
+            hooks: parts.hooks,
+            nonces: Arc::default(),
+        }
+    }
+}
+
+/// What the requests on one client connection share.
+struct ClientContext<'a> {
+    client_addr: SocketAddr,
+    stats: &'a Arc<Stats>,
+    config_manager: &'a ConfigManager,
+    hooks: &'a ConnectionHooks,
+    nonces: &'a NonceStore,
+    responses: HttpResponses,
 }
 
 /// Handle a single HTTP CONNECT client.
+///
+/// With `http_proxy.keep_alive`, a tunnel closed by its target leaves the
+/// client connection open for another CONNECT, each recorded as its own
+/// connection. The next request must arrive within `keep_alive_timeout_secs`.
 async fn handle_client(
-    stream: TcpStream,
+    mut stream: TcpStream,
     client_addr: SocketAddr,
     stats: Arc<Stats>,
     config_manager: ConfigManager,
+    hooks: ConnectionHooks,
+    nonces: Arc<NonceStore>,
 ) -> Result<()> {
+    let accepted_at = Utc::now();
     debug!("New HTTP CONNECT connection from {}", client_addr);
+    tcp_keepalive::enable(&stream, &config_manager.get_limits().await.tcp_keepalive);
+
+    let http_proxy = config_manager.get_http_proxy().await;
+    let ctx = ClientContext {
+        client_addr,
+        stats: &stats,
+        config_manager: &config_manager,
+        hooks: &hooks,
+        nonces: &nonces,
+        responses: HttpResponses::new(&http_proxy),
+    };
+    let keep_alive = Duration::from_secs(http_proxy.keep_alive_timeout_secs);
+
+    // Bytes past a request head stay in `buf` and go to that request's target
+    let mut buf = Vec::new();
+    let mut served = 1;
+    // The last request allowed on the connection closes it like without keep-alive
+    let reuse = |served| http_proxy.keep_alive && served < http_proxy.max_requests_per_connection;
+    let mut reusable = handle_request(
+        &mut stream,
+        &mut buf,
+        Some(accepted_at),
+        None,
+        reuse(served),
+        &ctx,
+    )
+    .await?;
+    while reusable {
+        served += 1;
+        let deadline = Instant::now() + keep_alive;
+        reusable = handle_request(
+            &mut stream,
+            &mut buf,
+            None,
+            Some(deadline),
+            reuse(served),
+            &ctx,
+        )
+        .await?;
+    }
+    Ok(())
+}
 
-    // Check IP access control
+/// Serve one CONNECT request on `stream`.
+///
+/// `accepted_at` is when the connection was accepted (first request only;
+/// later requests count from their request head). Reading the request heads,
+/// including answers to challenges, must finish by `deadline`; a kept-alive
+/// client closing or staying idle past it ends the connection without error.
+///
+/// With `reuse_client`, a tunnel closed by the target leaves the client
+/// open; returns whether the client connection can carry another request.
+async fn handle_request(
+    stream: &mut TcpStream,
+    buf: &mut Vec<u8>,
+    accepted_at: Option<DateTime<Utc>>,
+    deadline: Option<Instant>,
+    reuse_client: bool,
+    ctx: &ClientContext<'_>,
+) -> Result<bool> {
+    let ClientContext {
+        client_addr,
+        stats,
+        config_manager,
+        hooks,
+        nonces,
+        ref responses,
+    } = *ctx;
+
+    // Check IP access control (again for each request: the IP may have been banned since)
     let client_ip = client_addr.ip().to_string();
     if !config_manager.is_ip_allowed(&client_ip).await {
         warn!("IP blocked: {}", client_ip);
-        return Err(Error::AccessDenied(format!("IP blocked: {}", client_ip)));
+        return Err(Error::AccessDenied(
+            DenyReason::ClientIp,
+            format!("IP blocked: {}", client_ip),
+        ));
     }
 
-    let mut reader = BufReader::new(stream);
-    let mut request_line = String::new();
-    reader.read_line(&mut request_line).await?;
-
-    // Parse request line: CONNECT host:port HTTP/1.1
-    let parts: Vec<&str> = request_line.split_whitespace().collect();
-
-    if parts.len() < 3 {
-        return Err(Error::InvalidHttpProtocol("Invalid request line".into()));
-    }
-
-    let method = parts[0];
-    let target = parts[1];
-
-    if method != "CONNECT" {
-        let mut stream = reader.into_inner();
-        stream
-            .write_all(b"HTTP/1.1 405 Method Not Allowed\r\n\r\n")
-            .await?;
-        return Err(Error::InvalidHttpProtocol(format!(
-            "Method not allowed: {}",
-            method
-        )));
-    }
-
-    // Parse host:port
-    let (target_addr, target_port) = parse_host_port(target)?;
-
-    // Read headers
-    let mut auth_header = String::new();
-
-    loop {
-        let mut line = String::new();
-        reader.read_line(&mut line).await?;
-
-        if line.trim().is_empty() {
-            break;
+    // Read the request line and headers; bytes past them stay in `buf`.
+    // A client answering a 407 may send its next request on this connection.
+    let auth_enabled = config_manager.is_auth_enabled().await;
+    let mut challenges = 0;
+    let mut received_at = None;
+    let (target_addr, target_port, authenticated_user, limits, wants_close) = loop {
+        let read = read_message(stream, buf, parse_request_head);
+        let read = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, read).await {
+                Ok(read) => read,
+                Err(_) => {
+                    debug!("Kept-alive connection from {} timed out", client_addr);
+                    return Ok(false);
+                }
+            },
+            None => read.await,
+        };
+        let head = match read {
+            Ok(head) => head,
+            // The client gave up after a challenge
+            Err(Error::Io(e)) if challenges > 0 && e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(Error::AuthenticationFailed)
+            }
+            // A kept-alive client closed instead of sending another request
+            Err(Error::Io(e))
+                if deadline.is_some()
+                    && buf.is_empty()
+                    && e.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                return Ok(false)
+            }
+            Err(e) => return Err(e),
+        };
+        received_at.get_or_insert_with(Utc::now);
+
+        if head.method != "CONNECT" {
+            let e = Error::InvalidHttpProtocol(format!("Method not allowed: {}", head.method));
+            let response = responses.status(405, "", &format!("{}\r\n", e));
+            stream.write_all(response.as_bytes()).await?;
+            return Err(e);
         }
 
-        if line.to_lowercase().starts_with("proxy-authorization:") {
-            auth_header = line.trim().to_string();
+        // Parse host:port
+        let (target_addr, target_port) = parse_host_port(&head.target)?;
+        Span::current().record("target", head.target.as_str());
+        let auth_header = head.proxy_authorization().unwrap_or_default();
+        let wants_close = head.wants_close();
+
+        // Check authentication using config_manager (multi-user support)
+        if !auth_enabled {
+            break (
+                target_addr,
+                target_port,
+                None,
+                SessionLimits::default(),
+                wants_close,
+            );
         }
-    }
 
-    // Check authentication using config_manager (multi-user support)
-    let auth_enabled = config_manager.is_auth_enabled().await;
-    let authenticated_user: Option<String>;
+        // Credentials take precedence; clients on exempt subnets may omit them
+        let check = extract_and_verify_auth(
+            auth_header,
+            &head.target,
+            client_addr,
+            config_manager,
+            nonces,
+        )
+        .instrument(telemetry::auth_span())
+        .await;
+        let stale = matches!(check, AuthCheck::StaleNonce);
+        let user = match check {
+            AuthCheck::User(user) => Some(user),
+            AuthCheck::Invalid if auth_header.is_empty() => {
+                match config_manager.auth_exempt_user(&client_ip).await {
+                    Some(username) => Some(AuthenticatedUser {
+                        limits: config_manager
+                            .session_limits(&username, SessionLimits::default())
+                            .await,
+                        username,
+                    }),
+                    None => None,
+                }
+            }
+            AuthCheck::Invalid | AuthCheck::StaleNonce => None,
+        };
+        if let Some(user) = user {
+            Span::current().record("user", user.username.as_str());
+            break (
+                target_addr,
+                target_port,
+                Some(user.username),
+                user.limits,
+                wants_close,
+            );
+        }
 
-    if auth_enabled {
-        authenticated_user = extract_and_verify_auth(&auth_header, &config_manager).await;
-        if authenticated_user.is_none() {
-            let mut stream = reader.into_inner();
-            stream.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"Proxy\"\r\n\r\n").await?;
+        challenge(stream, responses, nonces, stale).await?;
+        challenges += 1;
+        if challenges == MAX_CHALLENGES {
             return Err(Error::AuthenticationFailed);
         }
-    } else {
-        authenticated_user = None;
-    }
+    };
+    let authenticated_at = Utc::now();
+    let accepted_at = accepted_at.or(received_at).unwrap_or(authenticated_at);
+    // The client won't send another request after `Connection: close`
+    let reuse_client = reuse_client && !wants_close;
+
+    // Rules and stats see the normalized name
+    let requested_host = target_addr;
+    let target_addr = match hostname::normalize(&requested_host) {
+        Ok(host) => host.into_owned(),
+        Err(e) => return reject(stream, responses, Error::AddressResolution(e.to_string())).await,
+    };
 
     // Check target access control
-    if !config_manager.is_target_allowed(&target_addr, None).await {
-        warn!("Target blocked: {}:{}", target_addr, target_port);
-        let mut stream = reader.into_inner();
-        stream.write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n").await?;
-        return Err(Error::AccessDenied(format!(
-            "Target blocked: {}:{}",
-            target_addr, target_port
-        )));
+    if let Err((e, block)) = check_target_access(
+        &target_addr,
+        target_port,
+        DenyReason::Target,
+        client_addr,
+        authenticated_user.as_deref(),
+        "http",
+        stats,
+        config_manager,
+    )
+    .await
+    {
+        let custom = config_manager.block_message().await;
+        let response = responses.blocked(&e, &block, custom.as_deref());
+        stream.write_all(response.as_bytes()).await?;
+        return Err(e);
+    }
+
+    if let Err(e) = check_quota(authenticated_user.as_deref(), stats, config_manager).await {
+        warn!("{}", e);
+        return reject(stream, responses, e).await;
+    }
+    if let Err(e) = check_connection_limit(authenticated_user.as_deref(), &limits, stats).await {
+        warn!(
+            "{} for user {}",
+            e,
+            authenticated_user.as_deref().unwrap_or_default()
+        );
+        return reject(stream, responses, e).await;
+    }
+
+    // Create connection for tracking with user info
+    let mut conn_info = ConnectionInfo::with_user(
+        Protocol::HttpConnect,
+        client_addr.to_string(),
+        target_addr.clone(),
+        target_port,
+        authenticated_user.clone(),
+    );
+    conn_info.requested_host = (requested_host != target_addr).then_some(requested_host);
+    conn_info.set_handshake(accepted_at, authenticated_at);
+    if let Err(e) = hooks.on_connect(&conn_info).await {
+        warn!("{}", e);
+        return reject(stream, responses, e).await;
     }
 
     debug!("HTTP CONNECT to {}:{}", target_addr, target_port);
 
     // Connect to target
     let target = format!("{}:{}", target_addr, target_port);
-    let target_stream = match TcpStream::connect(&target).await {
-        Ok(s) => s,
+    let request = ConnectRequest {
+        host: &target_addr,
+        port: target_port,
+        client_addr,
+        username: authenticated_user.as_deref(),
+        protocol: Protocol::HttpConnect,
+    };
+    let connect_started = Utc::now();
+    let connected = connect_target(&request, config_manager).await;
+    record_connect_outcome(stats, &target_addr, &connected);
+    let mut target_stream = match connected {
+        Ok(connected) => {
+            conn_info.set_target_connected(connect_started, Utc::now(), connected.attempts);
+            conn_info.resolve_ms = connected.resolve_ms;
+            connected.stream
+        }
         Err(e) => {
-            warn!("Failed to connect to {}: {}", target, e);
-            let mut stream = reader.into_inner();
-            stream
-                .write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n")
-                .await?;
-            return Err(Error::ConnectionRefused(target));
+            hooks.on_abort(conn_info).await;
+            let e = connect_failure(e, &target);
+            return reject(stream, responses, e).await;
         }
     };
 
-    // Send success response
-    let mut stream = reader.into_inner();
-    stream
-        .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
-        .await?;
+    let opened = async {
+        // Send success response, keeping any client bytes read past the request headers
+        stream.write_all(responses.established().as_bytes()).await?;
+
+        // Apply domain rules to the TLS SNI (covers clients connecting to IP literals)
+        if config_manager.is_sni_inspection_enabled().await {
+            inspect_sni(
+                stream,
+                &mut target_stream,
+                std::mem::take(buf),
+                target_port,
+                client_addr,
+                authenticated_user.as_deref(),
+                "http",
+                stats,
+                config_manager,
+            )
+            .await
+        } else {
+            let pending = std::mem::take(buf);
+            target_stream.write_all(&pending).await?;
+            Ok(SniInspection {
+                sni: None,
+                forwarded: pending.len() as u64,
+            })
+        }
+    }
+    .await;
+    let inspection = match opened {
+        Ok(inspection) => inspection,
+        Err(e) => {
+            hooks.on_abort(conn_info).await;
+            return Err(e);
+        }
+    };
 
-    // Create connection for tracking with user info
-    let conn_info = crate::connection::ConnectionInfo::with_user(
-        Protocol::HttpConnect,
-        client_addr.to_string(),
-        target_addr.clone(),
-        target_port,
-        authenticated_user.clone(),
-    );
+    conn_info.sni = inspection.sni;
+    conn_info.upstream = target_stream.upstream().map(str::to_string);
+    conn_info.egress = target_stream.egress().map(|ip| ip.to_string());
     let conn_id = conn_info.id;
-    stats.add_connection(conn_info).await;
+    track_connection(conn_info, stats, config_manager).await;
 
     // Relay traffic
-    let (bytes_sent, bytes_received) = relay_tcp(stream, target_stream).await;
+    let relay = if reuse_client {
+        relay_reusing_client(
+            &mut *stream,
+            target_stream,
+            conn_id,
+            authenticated_user.as_deref(),
+            &limits,
+            stats,
+            config_manager,
+        )
+        .instrument(telemetry::relay_span())
+        .await
+    } else {
+        relay_for_user(
+            &mut *stream,
+            target_stream,
+            conn_id,
+            authenticated_user.as_deref(),
+            &limits,
+            stats,
+            config_manager,
+        )
+        .instrument(telemetry::relay_span())
+        .await
+    };
+    let RelayResult {
+        bytes_sent,
+        bytes_received,
+        close_reason,
+        ..
+    } = relay;
+    let bytes_sent = bytes_sent + inspection.forwarded;
+    telemetry::record_close(&Span::current(), bytes_sent, bytes_received, close_reason);
 
     // Record stats
-    stats
-        .close_connection(conn_id, bytes_sent, bytes_received)
-        .await;
+    if let Some(closed) = stats
+        .close_connection(conn_id, bytes_sent, bytes_received, close_reason)
+        .await
+    {
+        hooks.on_close(&closed).await;
+    }
 
     let user_info = authenticated_user
         .map(|u| format!(" (user: {})", u))
@@ -201,11 +563,95 @@ async fn handle_client(
         client_addr, target_addr, target_port, user_info, bytes_sent, bytes_received
     );
 
+    Ok(reuse_client && close_reason == CloseReason::TargetEof)
+}
+
+/// Send the response matching `error` and fail with it.
+async fn reject<T>(stream: &mut TcpStream, responses: &HttpResponses, error: Error) -> Result<T> {
+    stream
+        .write_all(responses.error(&error, &[]).as_bytes())
+        .await?;
+    Err(error)
+}
+
+/// Send a 407 offering Basic and Digest (with a new nonce).
+///
+/// `stale` tells Digest clients their credentials were right but the nonce
+/// has expired, so they can retry without asking the user again.
+async fn challenge(
+    stream: &mut TcpStream,
+    responses: &HttpResponses,
+    nonces: &NonceStore,
+    stale: bool,
+) -> Result<()> {
+    let mut challenges = vec![format!("Basic realm=\"{}\"", REALM)];
+    if let Some(nonce) = nonces.issue() {
+        challenges.extend(digest::challenges(REALM, &nonce, stale));
+    }
+    let response = responses.error(&Error::AuthenticationFailed, &challenges);
+    stream.write_all(response.as_bytes()).await?;
     Ok(())
 }
 
-/// Parse host:port string.
-fn parse_host_port(target: &str) -> Result<(String, u16)> {
+/// Request line and headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestHead {
+    pub method: String,
+    pub target: String,
+    /// Every header field, in the order received.
+    pub headers: Headers,
+}
+
+impl RequestHead {
+    /// Value of the (first) `Proxy-Authorization` header.
+    pub fn proxy_authorization(&self) -> Option<&str> {
+        self.headers.get("proxy-authorization")
+    }
+
+    /// Whether the client asked for the connection to be closed after this
+    /// request, with `Connection: close` or `Proxy-Connection: close`.
+    pub fn wants_close(&self) -> bool {
+        self.headers.has_token("connection", "close")
+            || self.headers.has_token("proxy-connection", "close")
+    }
+}
+
+/// Parse the request line and headers, up to and including the blank line
+/// ending them (see [`split_head`] for the line endings accepted).
+///
+/// Heads longer than 8 KiB are refused, whether or not they are complete,
+/// as are heads with more than
+/// [`MAX_HEADERS`](crate::proxy::headers::MAX_HEADERS) fields.
+pub fn parse_request_head(buf: &[u8]) -> Result<Parsed<RequestHead>> {
+    let too_large = || Error::InvalidHttpProtocol("Request head too large".into());
+
+    let Some((lines, len)) = split_head(buf) else {
+        return if buf.len() > MAX_REQUEST_HEAD {
+            Err(too_large())
+        } else {
+            Ok(None)
+        };
+    };
+    if len > MAX_REQUEST_HEAD {
+        return Err(too_large());
+    }
+
+    // Request line: CONNECT host:port HTTP/1.1
+    let parts: Vec<&str> = lines[0].split_whitespace().collect();
+    if parts.len() < 3 {
+        return Err(Error::InvalidHttpProtocol("Invalid request line".into()));
+    }
+
+    let head = RequestHead {
+        method: parts[0].to_string(),
+        target: parts[1].to_string(),
+        headers: Headers::parse(&lines[1..])?,
+    };
+    Ok(Some((head, len)))
+}
+
+/// Split a `host:port` request target.
+pub fn parse_host_port(target: &str) -> Result<(String, u16)> {
     let parts: Vec<&str> = target.rsplitn(2, ':').collect();
 
     if parts.len() != 2 {
@@ -224,63 +670,131 @@ fn parse_host_port(target: &str) -> Result<(String, u16)> {
     Ok((host, port))
 }
 
-/// Extract and verify proxy authentication header using multi-user config.
-/// Returns the authenticated username on success.
-async fn extract_and_verify_auth(header: &str, config_manager: &ConfigManager) -> Option<String> {
-    if header.is_empty() {
-        return None;
-    }
-
-    // Parse "Proxy-Authorization: Basic base64..."
-    let parts: Vec<&str> = header.splitn(2, ':').collect();
-    if parts.len() != 2 {
-        return None;
-    }
-
-    let auth_parts: Vec<&str> = parts[1].trim().splitn(2, ' ').collect();
-    if auth_parts.len() != 2 || auth_parts[0].to_lowercase() != "basic" {
-        return None;
-    }
-
-    // Decode base64
-    let decoded = base64_decode(auth_parts[1].trim())?;
-
-    // Parse username:password
-    let cred_parts: Vec<&str> = decoded.splitn(2, ':').collect();
-    if cred_parts.len() != 2 {
-        return None;
-    }
-
-    let username = cred_parts[0];
-    let password = cred_parts[1];
-
-    // Authenticate using config_manager (supports multi-user)
-    config_manager.authenticate(username, password).await
+/// Outcome of checking a `Proxy-Authorization` header.
+enum AuthCheck {
+    User(AuthenticatedUser),
+    /// Missing, malformed or wrong credentials, or a replayed Digest nonce.
+    Invalid,
+    /// Correct Digest credentials with an expired or unknown nonce.
+    StaleNonce,
 }
 
-/// Simple base64 decode.
-fn base64_decode(input: &str) -> Option<String> {
-    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
-
-    let mut output = Vec::new();
-    let mut buffer: u32 = 0;
-    let mut bits = 0;
-
-    for c in input.chars() {
-        if c == '=' {
-            break;
+/// Verify a `Proxy-Authorization` header value (`Basic`, `Digest` or
+/// `Bearer`) for a CONNECT to `target`.
+async fn extract_and_verify_auth(
+    header: &str,
+    target: &str,
+    client_addr: SocketAddr,
+    config_manager: &ConfigManager,
+    nonces: &NonceStore,
+) -> AuthCheck {
+    let Some((scheme, credentials)) = header.split_once(' ') else {
+        return AuthCheck::Invalid;
+    };
+    let credentials = credentials.trim();
+    let user = match scheme.to_ascii_lowercase().as_str() {
+        "basic" => {
+            let Some((username, password)) = decode_basic_auth(credentials) else {
+                return AuthCheck::Invalid;
+            };
+            // Authenticate using config_manager (users, tokens and the auth backend)
+            let request = AuthRequest {
+                username: &username,
+                password: &password,
+                client_ip: client_addr.ip(),
+                protocol: Protocol::HttpConnect,
+            };
+            config_manager.authenticate_client(&request).await
         }
+        "digest" => {
+            return verify_digest(credentials, target, client_addr, config_manager, nonces).await
+        }
+        // Token issued through the API
+        "bearer" => match config_manager.authenticate_token(credentials).await {
+            Some(username) => Some(AuthenticatedUser {
+                limits: config_manager
+                    .session_limits(&username, SessionLimits::default())
+                    .await,
+                username,
+            }),
+            None => None,
+        },
+        _ => None,
+    };
+    user.map_or(AuthCheck::Invalid, AuthCheck::User)
+}
 
-        let value = CHARSET.iter().position(|&x| x as char == c)? as u32;
-        buffer = (buffer << 6) | value;
-        bits += 6;
-
-        if bits >= 8 {
-            bits -= 8;
-            output.push((buffer >> bits) as u8);
-            buffer &= (1 << bits) - 1;
+/// Verify `Digest` credentials against `security.users`.
+///
+/// The response is checked before the nonce, so only clients that know the
+/// password learn that a nonce is stale.
+async fn verify_digest(
+    params: &str,
+    target: &str,
+    client_addr: SocketAddr,
+    config_manager: &ConfigManager,
+    nonces: &NonceStore,
+) -> AuthCheck {
+    let Some(credentials) = DigestCredentials::parse(params) else {
+        return AuthCheck::Invalid;
+    };
+    let Some(algorithm) = DigestAlgorithm::parse(credentials.algorithm.as_deref()) else {
+        return AuthCheck::Invalid;
+    };
+    let (Some(nc), Some(cnonce)) = (credentials.nc.as_deref(), credentials.cnonce.as_deref())
+    else {
+        return AuthCheck::Invalid;
+    };
+    let Ok(count) = u32::from_str_radix(nc, 16) else {
+        return AuthCheck::Invalid;
+    };
+    if credentials.realm != REALM
+        || credentials.uri != target
+        || !credentials
+            .qop
+            .as_deref()
+            .is_some_and(|qop| qop.eq_ignore_ascii_case("auth"))
+    {
+        return AuthCheck::Invalid;
+    }
+    let Some(password) = config_manager.password_of(&credentials.username).await else {
+        return AuthCheck::Invalid;
+    };
+    let expected = digest_response(
+        algorithm,
+        &credentials.username,
+        REALM,
+        &password,
+        "CONNECT",
+        &credentials.uri,
+        &credentials.nonce,
+        nc,
+        cnonce,
+    );
+    if !responses_match(&expected, &credentials.response) {
+        return AuthCheck::Invalid;
+    }
+    match nonces.consume(&credentials.nonce, count) {
+        NonceStatus::Fresh => {}
+        NonceStatus::Stale => return AuthCheck::StaleNonce,
+        NonceStatus::Replayed => {
+            warn!("Replayed Digest nonce count from {}", client_addr);
+            return AuthCheck::Invalid;
         }
     }
+    let limits = config_manager
+        .session_limits(&credentials.username, SessionLimits::default())
+        .await;
+    AuthCheck::User(AuthenticatedUser {
+        username: credentials.username,
+        limits,
+    })
+}
 
-    String::from_utf8(output).ok()
+/// Decode `Basic` credentials (base64 of `username:password`, padding optional).
+pub fn decode_basic_auth(encoded: &str) -> Option<(String, String)> {
+    let decoded = BASIC_AUTH.decode(encoded).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
 }
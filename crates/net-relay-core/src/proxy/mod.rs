@@ -1,9 +1,28 @@
 //! Proxy protocol implementations.
 
+mod accept;
+pub mod builder;
+pub mod client;
+pub mod combined;
+pub mod connect;
+pub mod digest;
+pub mod headers;
 pub mod http;
+pub mod parse;
+pub mod proxy_protocol;
 pub mod relay;
+pub mod response;
+pub mod sni;
 pub mod socks5;
+pub mod tcp_keepalive;
+pub mod tunnel;
 
+pub use builder::ProxyBuilder;
+pub use client::{HttpConnectClient, Socks5Client};
+pub use combined::CombinedProxy;
+pub use connect::{connect_target, ConnectRequest, ConnectedTarget, TargetStream};
 pub use http::HttpProxy;
-pub use relay::relay_tcp;
+pub use relay::{relay_tcp, RelayBufferStats, RelayResult};
 pub use socks5::Socks5Proxy;
+pub use tokio_util::sync::CancellationToken;
+pub use tunnel::TunnelServer;
@@ -0,0 +1,38 @@
+//! Reading handshake messages that are parsed from byte slices.
+//!
+//! The SOCKS5 and HTTP parsers take whatever the client has sent so far and
+//! never touch the socket, so they can be tested and fuzzed on plain bytes.
+
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::error::Result;
+
+/// A message parsed from the start of a buffer with the number of bytes it
+/// took, or `None` while the buffer doesn't hold all of it yet.
+pub type Parsed<T> = Option<(T, usize)>;
+
+/// Read from `stream` into `buf` until `parse` accepts a complete message,
+/// then remove the message from `buf`.
+///
+/// Bytes the client sent past the message stay in `buf`. Parsers bound the
+/// size of their messages, so `buf` only grows while a message is incomplete.
+pub(crate) async fn read_message<S, T>(
+    stream: &mut S,
+    buf: &mut Vec<u8>,
+    parse: impl Fn(&[u8]) -> Result<Parsed<T>>,
+) -> Result<T>
+where
+    S: AsyncRead + Unpin,
+{
+    loop {
+        if let Some((message, len)) = parse(buf)? {
+            buf.drain(..len);
+            return Ok(message);
+        }
+        buf.reserve(512);
+        if stream.read_buf(buf).await? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+    }
+}
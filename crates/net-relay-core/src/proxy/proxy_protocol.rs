@@ -0,0 +1,55 @@
+//! PROXY protocol v2 header encoding.
+//!
+//! See <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>.
+
+use std::net::{IpAddr, SocketAddr};
+
+/// Fixed 12-byte signature that starts every v2 header.
+const SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+/// Version 2, PROXY command.
+const VERSION_COMMAND: u8 = 0x21;
+
+/// AF_INET + STREAM.
+const FAMILY_TCP4: u8 = 0x11;
+
+/// AF_INET6 + STREAM.
+const FAMILY_TCP6: u8 = 0x21;
+
+/// Build a PROXY protocol v2 header for a TCP connection from `src` to `dst`.
+///
+/// When the address families differ, both addresses are sent as IPv6
+/// (IPv4 addresses are mapped).
+pub fn v2_header(src: SocketAddr, dst: SocketAddr) -> Vec<u8> {
+    let mut header = Vec::with_capacity(16 + 36);
+    header.extend_from_slice(&SIGNATURE);
+    header.push(VERSION_COMMAND);
+
+    match (src.ip().to_canonical(), dst.ip().to_canonical()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            header.push(FAMILY_TCP4);
+            header.extend_from_slice(&12u16.to_be_bytes());
+            header.extend_from_slice(&src_ip.octets());
+            header.extend_from_slice(&dst_ip.octets());
+        }
+        (src_ip, dst_ip) => {
+            header.push(FAMILY_TCP6);
+            header.extend_from_slice(&36u16.to_be_bytes());
+            header.extend_from_slice(&to_ipv6_octets(src_ip));
+            header.extend_from_slice(&to_ipv6_octets(dst_ip));
+        }
+    }
+
+    header.extend_from_slice(&src.port().to_be_bytes());
+    header.extend_from_slice(&dst.port().to_be_bytes());
+    header
+}
+
+fn to_ipv6_octets(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped().octets(),
+        IpAddr::V6(v6) => v6.octets(),
+    }
+}
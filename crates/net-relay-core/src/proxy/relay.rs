@@ -1,64 +1,638 @@
 //! TCP relay implementation.
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tracing::debug;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{debug, info};
+use uuid::Uuid;
 
-/// Relay data between two TCP streams.
+use crate::auth::SessionLimits;
+use crate::bandwidth::BandwidthLimiter;
+use crate::capture::{Capture, CaptureDirection, CaptureEnd};
+use crate::config::{ConfigManager, MIN_RELAY_BUFFER_SIZE};
+use crate::connection::CloseReason;
+use crate::stats::Stats;
+
+/// How often in-flight connections are checked against the user's quota.
+const QUOTA_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often a tracked relay reports its byte counts for rate calculation.
+const RATE_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a tracked relay checks whether data moved for `limits.idle_timeout`.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Buffer size of relays without a [`ConfigManager`] ([`relay_tcp_until`]).
+const DEFAULT_BUFFER_SIZE: usize = 8192;
+
+/// Outcome of a finished relay.
+#[derive(Debug, Clone, Copy)]
+pub struct RelayResult {
+    /// Bytes sent from the client to the target.
+    pub bytes_sent: u64,
+
+    /// Bytes received from the target and sent to the client.
+    pub bytes_received: u64,
+
+    /// Why the relay ended (the side that finished first).
+    pub close_reason: CloseReason,
+
+    /// When the first byte arrived from the target.
+    pub first_byte_at: Option<DateTime<Utc>>,
+
+    /// When the first client byte was relayed to the target.
+    pub first_byte_sent_at: Option<DateTime<Utc>>,
+}
+
+/// Live byte counts of a running relay.
+#[derive(Debug, Default)]
+pub struct RelayCounters {
+    sent: AtomicU64,
+    received: AtomicU64,
+    /// Unix milliseconds of the last write in either direction (0 = none yet).
+    last_activity_ms: AtomicI64,
+    first_byte_at: OnceLock<DateTime<Utc>>,
+    first_byte_sent_at: OnceLock<DateTime<Utc>>,
+    /// Set while `capture` holds a capture, so relays without one skip the lock.
+    capturing: AtomicBool,
+    capture: Mutex<Option<Arc<Capture>>>,
+}
+
+impl RelayCounters {
+    /// Bytes relayed so far from the client to the target.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Bytes relayed so far from the target to the client.
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Bytes relayed so far in both directions.
+    pub fn total(&self) -> u64 {
+        self.sent() + self.received()
+    }
+
+    /// When data last moved in either direction.
+    pub fn last_activity(&self) -> Option<DateTime<Utc>> {
+        match self.last_activity_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => DateTime::from_timestamp_millis(ms),
+        }
+    }
+
+    /// When the first byte arrived from the target.
+    pub fn first_byte_received(&self) -> Option<DateTime<Utc>> {
+        self.first_byte_at.get().copied()
+    }
+
+    /// When the first client byte was relayed to the target.
+    pub fn first_byte_sent(&self) -> Option<DateTime<Utc>> {
+        self.first_byte_sent_at.get().copied()
+    }
+
+    fn touch(&self) {
+        self.last_activity_ms
+            .store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    /// Copy relayed bytes into `capture`; returns `false` if a capture is
+    /// already attached.
+    pub(crate) fn attach_capture(&self, capture: Arc<Capture>) -> bool {
+        let mut slot = self.capture.lock().unwrap();
+        if slot.is_some() {
+            return false;
+        }
+        *slot = Some(capture);
+        self.capturing.store(true, Ordering::Release);
+        true
+    }
+
+    /// End the attached capture, if any, with `reason`.
+    pub(crate) fn detach_capture(&self, reason: CaptureEnd) {
+        if let Some(capture) = self.capture.lock().unwrap().take() {
+            capture.finish(reason);
+        }
+        self.capturing.store(false, Ordering::Release);
+    }
+
+    /// Hand `data` relayed in `direction` to the attached capture, detaching
+    /// it once it is full.
+    fn capture(&self, direction: CaptureDirection, data: &[u8]) {
+        if !self.capturing.load(Ordering::Acquire) {
+            return;
+        }
+        let mut slot = self.capture.lock().unwrap();
+        if let Some(capture) = slot.as_ref() {
+            if !capture.record(direction, data) {
+                *slot = None;
+                self.capturing.store(false, Ordering::Release);
+            }
+        }
+    }
+}
+
+/// Memory held by relay copy buffers across all relays.
+#[derive(Debug, Default)]
+pub struct RelayBufferMemory {
+    bytes: AtomicU64,
+    buffers: AtomicU64,
+    fallbacks: AtomicU64,
+}
+
+/// Snapshot of [`RelayBufferMemory`].
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct RelayBufferStats {
+    /// Bytes of buffer memory checked out by running relays.
+    pub bytes: u64,
+
+    /// Buffers checked out (two per relay).
+    pub buffers: u64,
+
+    /// Buffers handed out at the fallback size because the cap was reached.
+    pub fallbacks_total: u64,
+}
+
+impl RelayBufferMemory {
+    /// Check out a buffer of `size` bytes, or of [`MIN_RELAY_BUFFER_SIZE`]
+    /// when that would take the total past `cap` (0 = no cap).
+    ///
+    /// Fallback buffers are never refused, so the total can pass `cap` by
+    /// their size.
+    pub fn checkout(&self, size: usize, cap: u64) -> RelayBuffer<'_> {
+        let mut size = size.max(1);
+        let total = self.bytes.fetch_add(size as u64, Ordering::Relaxed) + size as u64;
+        if cap > 0 && total > cap && size > MIN_RELAY_BUFFER_SIZE {
+            self.bytes
+                .fetch_sub((size - MIN_RELAY_BUFFER_SIZE) as u64, Ordering::Relaxed);
+            size = MIN_RELAY_BUFFER_SIZE;
+            self.fallbacks.fetch_add(1, Ordering::Relaxed);
+        }
+        self.buffers.fetch_add(1, Ordering::Relaxed);
+        RelayBuffer {
+            buf: vec![0u8; size],
+            memory: self,
+        }
+    }
+
+    pub fn stats(&self) -> RelayBufferStats {
+        RelayBufferStats {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            buffers: self.buffers.load(Ordering::Relaxed),
+            fallbacks_total: self.fallbacks.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A relay copy buffer, returned to its [`RelayBufferMemory`] when dropped.
+pub struct RelayBuffer<'a> {
+    buf: Vec<u8>,
+    memory: &'a RelayBufferMemory,
+}
+
+impl RelayBuffer<'_> {
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+impl std::ops::Deref for RelayBuffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl std::ops::DerefMut for RelayBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+impl Drop for RelayBuffer<'_> {
+    fn drop(&mut self) {
+        self.memory
+            .bytes
+            .fetch_sub(self.buf.len() as u64, Ordering::Relaxed);
+        self.memory.buffers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Relay data between a client stream and a target stream.
+pub async fn relay_tcp<C, T>(client: C, target: T, limiter: &BandwidthLimiter) -> RelayResult
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    relay_tcp_until(
+        client,
+        target,
+        limiter,
+        None,
+        &RelayCounters::default(),
+        std::future::pending(),
+    )
+    .await
+}
+
+/// Relay data for a tracked connection.
 ///
-/// Returns (bytes_sent_to_target, bytes_received_from_target).
-pub async fn relay_tcp(client: TcpStream, target: TcpStream) -> (u64, u64) {
-    let (mut client_read, mut client_write) = client.into_split();
-    let (mut target_read, mut target_write) = target.into_split();
+/// Marks the connection active in `stats`, reports its byte counts for rate
+/// calculation every second and records its first byte in each direction.
+/// The relay stops with [`CloseReason::Killed`] when the connection is
+/// killed, [`CloseReason::Shutdown`] when the server shuts down,
+/// [`CloseReason::IdleTimeout`] after `limits.idle_timeout` without data and
+/// [`CloseReason::LifetimeExceeded`] once it has been open for
+/// `limits.max_connection_lifetime_secs` (or the user's override); whichever
+/// comes first ends it. When
+/// `limits.quota_cutoff_active` is set and the user has a quota,
+/// the connection is also closed once recorded usage plus this connection's
+/// bytes reach the quota. A `limits.bandwidth_limit` is shared with the
+/// user's other connections.
+pub async fn relay_for_user<C, T>(
+    client: C,
+    target: T,
+    conn_id: Uuid,
+    username: Option<&str>,
+    limits: &SessionLimits,
+    stats: &Stats,
+    config_manager: &ConfigManager,
+) -> RelayResult
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    relay_tracked(
+        client,
+        target,
+        conn_id,
+        username,
+        limits,
+        stats,
+        config_manager,
+        false,
+    )
+    .await
+}
 
-    let client_to_target = async {
-        let mut buf = [0u8; 8192];
-        let mut total: u64 = 0;
+/// Like [`relay_for_user`], but when the target closes first the relay ends
+/// right away and the client is left open, so it can send another request.
+/// The relay then ends with [`CloseReason::TargetEof`].
+pub(crate) async fn relay_reusing_client<C, T>(
+    client: C,
+    target: T,
+    conn_id: Uuid,
+    username: Option<&str>,
+    limits: &SessionLimits,
+    stats: &Stats,
+    config_manager: &ConfigManager,
+) -> RelayResult
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    relay_tracked(
+        client,
+        target,
+        conn_id,
+        username,
+        limits,
+        stats,
+        config_manager,
+        true,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn relay_tracked<C, T>(
+    client: C,
+    target: T,
+    conn_id: Uuid,
+    username: Option<&str>,
+    limits: &SessionLimits,
+    stats: &Stats,
+    config_manager: &ConfigManager,
+    reuse_client: bool,
+) -> RelayResult
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let user = match username {
+        Some(name) if config_manager.is_quota_cutoff_active().await => config_manager
+            .get_user(name)
+            .await
+            .filter(|u| u.quota_bytes > 0),
+        _ => None,
+    };
+    let limiter = config_manager.bandwidth_limiter();
+    let user_limiter = username.and_then(|name| {
+        config_manager.user_bandwidth_limiter(name, limits.bandwidth_limit.unwrap_or(0))
+    });
 
+    let config_limits = config_manager.get_limits().await;
+    let lifetime = limits
+        .max_connection_lifetime_secs
+        .unwrap_or(config_limits.max_connection_lifetime_secs);
+    let lifetime = (lifetime > 0).then(|| Duration::from_secs(lifetime));
+
+    let counters = stats.relay_counters(conn_id);
+    let lifetime_exceeded = async {
+        let Some(lifetime) = lifetime else {
+            return std::future::pending().await;
+        };
+        tokio::time::sleep(lifetime).await;
+        info!(
+            "Connection {} reached its maximum lifetime of {}s, closing",
+            conn_id,
+            lifetime.as_secs()
+        );
+        CloseReason::LifetimeExceeded
+    };
+    let quota_exhausted = async {
+        let Some(user) = user else {
+            return std::future::pending().await;
+        };
         loop {
-            match client_read.read(&mut buf).await {
-                Ok(0) => break,
+            tokio::time::sleep(QUOTA_CHECK_INTERVAL).await;
+            let used = stats.quota_status(&user).map_or(0, |s| s.used_bytes);
+            if used.saturating_add(counters.total()) >= user.quota_bytes {
+                info!(
+                    "Quota exhausted for user {}, closing connection",
+                    user.username
+                );
+                return CloseReason::QuotaExceeded;
+            }
+        }
+    };
+    let stop = async {
+        tokio::select! {
+            reason = quota_exhausted => reason,
+            _ = stats.killed(conn_id) => CloseReason::Killed,
+            _ = stats.shutdown_requested() => CloseReason::Shutdown,
+            reason = lifetime_exceeded => reason,
+            reason = idle_timeout(&counters, Duration::from_secs(config_limits.idle_timeout)) => reason,
+            reason = report_progress(stats, conn_id) => reason,
+        }
+    };
+
+    stats.mark_active(conn_id);
+    if let Some(lifetime) = lifetime {
+        let lifetime = chrono::Duration::from_std(lifetime).unwrap_or(chrono::Duration::MAX);
+        stats.set_expires_at(conn_id, Utc::now() + lifetime);
+    }
+    let buffers = [(); 2].map(|_| {
+        stats.relay_buffers().checkout(
+            config_limits.relay_buffer_size,
+            config_limits.max_relay_buffer_memory,
+        )
+    });
+    let result = relay_streams(
+        client,
+        target,
+        limiter,
+        user_limiter.as_deref(),
+        &counters,
+        stop,
+        reuse_client,
+        buffers,
+    )
+    .await;
+    counters.detach_capture(CaptureEnd::ConnectionClosed);
+    if let Some(at) = result.first_byte_at {
+        stats.mark_first_byte(conn_id, at);
+    }
+    if let Some(at) = result.first_byte_sent_at {
+        stats.mark_first_byte_sent(conn_id, at);
+    }
+    result
+}
+
+/// Report a relay's byte counts to `stats` periodically; never completes.
+async fn report_progress(stats: &Stats, conn_id: Uuid) -> CloseReason {
+    if !stats.is_enabled() {
+        return std::future::pending().await;
+    }
+    let mut interval = tokio::time::interval(RATE_SAMPLE_INTERVAL);
+    loop {
+        interval.tick().await;
+        stats.sample_rate(conn_id);
+    }
+}
+
+/// Resolve once no data moved through `counters` for `timeout`; never
+/// resolves for a zero `timeout`.
+async fn idle_timeout(counters: &RelayCounters, timeout: Duration) -> CloseReason {
+    if timeout.is_zero() {
+        return std::future::pending().await;
+    }
+    let mut interval = tokio::time::interval(IDLE_CHECK_INTERVAL.min(timeout));
+    let mut last_total = counters.total();
+    let mut idle_since = tokio::time::Instant::now();
+    loop {
+        interval.tick().await;
+        let total = counters.total();
+        if total != last_total {
+            last_total = total;
+            idle_since = tokio::time::Instant::now();
+        } else if idle_since.elapsed() >= timeout {
+            debug!("Relay idle for {}s, closing", timeout.as_secs());
+            return CloseReason::IdleTimeout;
+        }
+    }
+}
+
+/// Relay data until both directions finish or `stop` completes.
+///
+/// Every write draws from `user_limiter` (when set) and the server-wide
+/// `limiter` first. Byte counts and the last activity time are published to
+/// `counters` as data flows.
+pub async fn relay_tcp_until<C, T, F>(
+    client: C,
+    target: T,
+    limiter: &BandwidthLimiter,
+    user_limiter: Option<&BandwidthLimiter>,
+    counters: &RelayCounters,
+    stop: F,
+) -> RelayResult
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+    F: Future<Output = CloseReason>,
+{
+    let memory = RelayBufferMemory::default();
+    let buffers = [(); 2].map(|_| memory.checkout(DEFAULT_BUFFER_SIZE, 0));
+    relay_streams(
+        client,
+        target,
+        limiter,
+        user_limiter,
+        counters,
+        stop,
+        false,
+        buffers,
+    )
+    .await
+}
+
+/// [`relay_tcp_until`] through the given client-to-target and
+/// target-to-client `buffers`, optionally leaving the client open when the
+/// target closes first (see [`relay_reusing_client`]).
+#[allow(clippy::too_many_arguments)]
+async fn relay_streams<C, T, F>(
+    client: C,
+    target: T,
+    limiter: &BandwidthLimiter,
+    user_limiter: Option<&BandwidthLimiter>,
+    counters: &RelayCounters,
+    stop: F,
+    reuse_client: bool,
+    buffers: [RelayBuffer<'_>; 2],
+) -> RelayResult
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+    F: Future<Output = CloseReason>,
+{
+    let (mut client_read, mut client_write) = tokio::io::split(client);
+    let (mut target_read, mut target_write) = tokio::io::split(target);
+    let first_close = OnceLock::new();
+    let [mut client_buf, mut target_buf] = buffers;
+
+    let client_to_target = async {
+        let buf = &mut client_buf;
+
+        let reason = loop {
+            match client_read.read(buf).await {
+                Ok(0) => break CloseReason::ClientEof,
                 Ok(n) => {
-                    if target_write.write_all(&buf[..n]).await.is_err() {
-                        break;
+                    counters.touch();
+                    counters.capture(CaptureDirection::Sent, &buf[..n]);
+                    if write_limited(
+                        &mut target_write,
+                        &buf[..n],
+                        limiter,
+                        user_limiter,
+                        &counters.sent,
+                    )
+                    .await
+                    .is_err()
+                    {
+                        break CloseReason::TargetError;
                     }
-                    total += n as u64;
+                    counters.first_byte_sent_at.get_or_init(Utc::now);
                 }
-                Err(_) => break,
+                Err(_) => break CloseReason::ClientError,
             }
-        }
+        };
+        let _ = first_close.set(reason);
 
         let _ = target_write.shutdown().await;
-        total
     };
 
     let target_to_client = async {
-        let mut buf = [0u8; 8192];
-        let mut total: u64 = 0;
+        let buf = &mut target_buf;
 
-        loop {
-            match target_read.read(&mut buf).await {
-                Ok(0) => break,
+        let reason = loop {
+            match target_read.read(buf).await {
+                Ok(0) => break CloseReason::TargetEof,
                 Ok(n) => {
-                    if client_write.write_all(&buf[..n]).await.is_err() {
-                        break;
+                    counters.first_byte_at.get_or_init(Utc::now);
+                    counters.touch();
+                    counters.capture(CaptureDirection::Received, &buf[..n]);
+                    if write_limited(
+                        &mut client_write,
+                        &buf[..n],
+                        limiter,
+                        user_limiter,
+                        &counters.received,
+                    )
+                    .await
+                    .is_err()
+                    {
+                        break CloseReason::ClientError;
                     }
-                    total += n as u64;
                 }
-                Err(_) => break,
+                Err(_) => break CloseReason::TargetError,
             }
+        };
+        let _ = first_close.set(reason);
+
+        if !reuse_client {
+            let _ = client_write.shutdown().await;
         }
+    };
 
-        let _ = client_write.shutdown().await;
-        total
+    let relayed = async {
+        if !reuse_client {
+            tokio::join!(client_to_target, target_to_client);
+            return;
+        }
+        // The tunnel ends with the target; reading the client stops without
+        // losing bytes of its next request
+        tokio::pin!(client_to_target, target_to_client);
+        tokio::select! {
+            _ = &mut target_to_client => {}
+            _ = &mut client_to_target => target_to_client.await,
+        }
     };
 
-    let (bytes_sent, bytes_received) = tokio::join!(client_to_target, target_to_client);
+    let close_reason = tokio::select! {
+        _ = relayed => first_close
+            .get()
+            .copied()
+            .unwrap_or(CloseReason::ClientEof),
+        reason = stop => reason,
+    };
+    let bytes_sent = counters.sent.load(Ordering::Relaxed);
+    let bytes_received = counters.received.load(Ordering::Relaxed);
 
     debug!(
-        "Relay complete: sent={}, received={}",
-        bytes_sent, bytes_received
+        "Relay complete: sent={}, received={}, reason={}",
+        bytes_sent, bytes_received, close_reason
     );
 
-    (bytes_sent, bytes_received)
+    RelayResult {
+        bytes_sent,
+        bytes_received,
+        close_reason,
+        first_byte_at: counters.first_byte_received(),
+        first_byte_sent_at: counters.first_byte_sent(),
+    }
+}
+
+/// Write `data` in chunks granted by the bandwidth limiters, counting each chunk.
+async fn write_limited<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    mut data: &[u8],
+    limiter: &BandwidthLimiter,
+    user_limiter: Option<&BandwidthLimiter>,
+    counter: &AtomicU64,
+) -> std::io::Result<()> {
+    while !data.is_empty() {
+        let allowed = match user_limiter {
+            Some(user_limiter) => user_limiter.acquire(data.len()).await,
+            None => data.len(),
+        };
+        let (mut chunk, rest) = data.split_at(allowed);
+        while !chunk.is_empty() {
+            let granted = limiter.acquire(chunk.len()).await;
+            writer.write_all(&chunk[..granted]).await?;
+            counter.fetch_add(granted as u64, Ordering::Relaxed);
+            chunk = &chunk[granted..];
+        }
+        data = rest;
+    }
+    Ok(())
 }
@@ -0,0 +1,99 @@
+//! Responses the HTTP proxy sends to its clients.
+//!
+//! Every response carries the same identification headers from
+//! `[http_proxy]`: `Proxy-Agent` only when `send_proxy_agent` is set, so the
+//! software and version aren't revealed by default, followed by the
+//! configured static headers.
+
+use crate::access::TargetBlock;
+use crate::config::HttpProxyConfig;
+use crate::error::Error;
+
+/// Value of the `Proxy-Agent` header.
+pub const PROXY_AGENT: &str = concat!("net-relay/", env!("CARGO_PKG_VERSION"));
+
+/// Builds HTTP proxy responses with the configured identification headers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpResponses {
+    /// Header lines, each ending in CRLF, added after the status line.
+    identification: String,
+}
+
+impl HttpResponses {
+    /// Responses identified as `config` says.
+    pub fn new(config: &HttpProxyConfig) -> Self {
+        let mut identification = String::new();
+        if config.send_proxy_agent {
+            identification.push_str(&format!("Proxy-Agent: {}\r\n", PROXY_AGENT));
+        }
+        for (name, value) in &config.headers {
+            identification.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        Self { identification }
+    }
+
+    /// `200 Connection Established`, after which the tunnel starts.
+    pub fn established(&self) -> String {
+        format!(
+            "HTTP/1.1 200 Connection Established\r\n{}\r\n",
+            self.identification
+        )
+    }
+
+    /// Response for `error`, with a `Proxy-Authenticate` header per challenge.
+    pub fn error(&self, error: &Error, challenges: &[String]) -> String {
+        let headers: String = challenges
+            .iter()
+            .map(|challenge| format!("Proxy-Authenticate: {}\r\n", challenge))
+            .collect();
+        self.status(
+            error.code().http_status(),
+            &headers,
+            &format!("{}\r\n", error),
+        )
+    }
+
+    /// `403 Forbidden` for a blocked target, naming the rule in an
+    /// `X-Block-Reason` header. The body is `message` (from
+    /// `access_control.block_message`) when set, otherwise the error.
+    pub fn blocked(&self, error: &Error, block: &TargetBlock, message: Option<&str>) -> String {
+        let reason: String = block
+            .describe()
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        let body = match message {
+            Some(message) => format!("{}\r\n", message),
+            None => format!("{}\r\n", error),
+        };
+        self.status(403, &format!("X-Block-Reason: {}\r\n", reason), &body)
+    }
+
+    /// Response with `status`, extra header lines (each ending in CRLF) and a
+    /// plain text body.
+    pub fn status(&self, status: u16, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {} {}\r\n{}{}Content-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            reason_phrase(status),
+            self.identification,
+            headers,
+            body.len(),
+            body
+        )
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        405 => "Method Not Allowed",
+        407 => "Proxy Authentication Required",
+        429 => "Too Many Requests",
+        502 => "Bad Gateway",
+        504 => "Gateway Timeout",
+        _ => "Internal Server Error",
+    }
+}
@@ -0,0 +1,206 @@
+//! TLS ClientHello peeking for SNI-based access control.
+
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::config::ConfigManager;
+use crate::error::{DenyReason, Result};
+use crate::hostname;
+use crate::proxy::connect::{check_target_access, TargetStream};
+use crate::stats::Stats;
+
+/// TLS record content type for handshake messages.
+const TLS_HANDSHAKE: u8 = 0x16;
+
+/// Handshake message type for ClientHello.
+const CLIENT_HELLO: u8 = 0x01;
+
+/// Extension type for server_name.
+const EXT_SERVER_NAME: u16 = 0x0000;
+
+/// Largest TLS record we are willing to buffer (16 KiB payload + header).
+const MAX_RECORD_LEN: usize = 5 + 16 * 1024;
+
+/// How long to wait for the client to send its ClientHello.
+const PEEK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Read the first TLS record sent by the client, if it sends one.
+///
+/// `buf` may already contain bytes read from the client. Stops early when
+/// the data is not TLS, when the target speaks first (server-first
+/// protocols), on EOF, or after [`PEEK_TIMEOUT`]. Every byte read is left in
+/// `buf` so it can be forwarded unchanged.
+async fn read_first_record(
+    client: &mut TcpStream,
+    target: &TargetStream,
+    buf: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    let deadline = Instant::now() + PEEK_TIMEOUT;
+    let mut chunk = [0u8; 4096];
+
+    loop {
+        if let Some(&first) = buf.first() {
+            if first != TLS_HANDSHAKE {
+                return Ok(());
+            }
+        }
+        if buf.len() >= 5 {
+            let record_len = 5 + u16::from_be_bytes([buf[3], buf[4]]) as usize;
+            if buf.len() >= record_len.min(MAX_RECORD_LEN) {
+                return Ok(());
+            }
+        }
+
+        tokio::select! {
+            read = client.read(&mut chunk) => match read? {
+                0 => return Ok(()),
+                n => buf.extend_from_slice(&chunk[..n]),
+            },
+            _ = target.readable(), if buf.is_empty() => return Ok(()),
+            _ = tokio::time::sleep_until(deadline) => return Ok(()),
+        }
+    }
+}
+
+/// Extract the SNI host name from a buffer starting with a TLS ClientHello record.
+pub fn parse_sni(buf: &[u8]) -> Option<String> {
+    let mut r = Reader(buf);
+
+    if r.u8()? != TLS_HANDSHAKE {
+        return None;
+    }
+    r.skip(2)?; // record version
+    let record_len = r.u16()? as usize;
+    let mut r = Reader(r.take(record_len.min(r.0.len()))?);
+
+    if r.u8()? != CLIENT_HELLO {
+        return None;
+    }
+    r.skip(3)?; // handshake length
+    r.skip(2 + 32)?; // client version + random
+    let session_id_len = r.u8()? as usize;
+    r.skip(session_id_len)?;
+    let cipher_suites_len = r.u16()? as usize;
+    r.skip(cipher_suites_len)?;
+    let compression_len = r.u8()? as usize;
+    r.skip(compression_len)?;
+
+    let extensions_len = r.u16()? as usize;
+    let mut exts = Reader(r.take(extensions_len.min(r.0.len()))?);
+
+    while !exts.0.is_empty() {
+        let ext_type = exts.u16()?;
+        let ext_len = exts.u16()? as usize;
+        let data = exts.take(ext_len)?;
+        if ext_type != EXT_SERVER_NAME {
+            continue;
+        }
+
+        let mut names = Reader(data);
+        let list_len = names.u16()? as usize;
+        let mut names = Reader(names.take(list_len)?);
+        while !names.0.is_empty() {
+            let name_type = names.u8()?;
+            let name_len = names.u16()? as usize;
+            let name = names.take(name_len)?;
+            if name_type == 0 {
+                let name = std::str::from_utf8(name).ok()?;
+                return Some(name.to_ascii_lowercase());
+            }
+        }
+        return None;
+    }
+
+    None
+}
+
+/// Minimal big-endian byte reader.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+}
+
+/// Outcome of SNI inspection.
+#[derive(Debug, Default)]
+pub struct SniInspection {
+    /// Server name from the ClientHello, if any.
+    pub sni: Option<String>,
+
+    /// Client bytes already forwarded to the target.
+    pub forwarded: u64,
+}
+
+/// Peek at the client's ClientHello and apply access control to its SNI.
+///
+/// `pending` holds client bytes that were already read (e.g. buffered by the
+/// HTTP request parser). All consumed bytes are forwarded to the target before
+/// returning, so the TLS handshake proceeds untouched. Non-TLS traffic and
+/// ClientHellos without SNI pass through without a decision. `port` is the
+/// target port the rules are checked against, for the client and `username`;
+/// rule hits and monitored denials are recorded in `stats` under `source`.
+#[allow(clippy::too_many_arguments)]
+pub async fn inspect_sni(
+    client: &mut TcpStream,
+    target: &mut TargetStream,
+    pending: Vec<u8>,
+    port: u16,
+    client_addr: SocketAddr,
+    username: Option<&str>,
+    source: &str,
+    stats: &Stats,
+    config_manager: &ConfigManager,
+) -> Result<SniInspection> {
+    let mut buf = pending;
+    read_first_record(client, target, &mut buf).await?;
+
+    let sni = parse_sni(&buf);
+    if let Some(ref name) = sni {
+        debug!("ClientHello SNI: {}", name);
+        let host = hostname::normalize(name).unwrap_or(Cow::Borrowed(name));
+        if let Err((e, _)) = check_target_access(
+            &host,
+            port,
+            DenyReason::Sni,
+            client_addr,
+            username,
+            source,
+            stats,
+            config_manager,
+        )
+        .await
+        {
+            return Err(e);
+        }
+    }
+
+    target.write_all(&buf).await?;
+    Ok(SniInspection {
+        sni,
+        forwarded: buf.len() as u64,
+    })
+}
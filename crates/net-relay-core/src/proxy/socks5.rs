@@ -1,19 +1,35 @@
 //! SOCKS5 proxy implementation.
 
+use chrono::Utc;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpListener, TcpStream};
-use tracing::{debug, error, info, warn};
-
-use crate::config::ConfigManager;
-use crate::connection::Protocol;
-use crate::error::{Error, Result};
-use crate::proxy::relay::relay_tcp;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn, Instrument, Span};
+
+use crate::auth::{loggable_username, AuthRequest, AuthenticatedUser, SessionLimits};
+use crate::config::{ConfigManager, SecurityConfig};
+use crate::connection::{ConnectionInfo, Protocol};
+use crate::error::{DenyReason, Error, Result};
+use crate::hooks::ConnectionHooks;
+use crate::hostname;
+use crate::listeners::{self, ListenOptions};
+use crate::proxy::accept::AcceptBackoff;
+use crate::proxy::builder::ProxyBuilder;
+use crate::proxy::connect::{
+    check_connection_limit, check_quota, check_target_access, connect_failure, connect_target,
+    record_connect_outcome, track_connection, ConnectRequest,
+};
+use crate::proxy::parse::{read_message, Parsed};
+use crate::proxy::relay::{relay_for_user, RelayResult};
+use crate::proxy::sni::{inspect_sni, SniInspection};
+use crate::proxy::tcp_keepalive;
 use crate::stats::Stats;
+use crate::telemetry;
 
 // SOCKS5 constants
-const SOCKS_VERSION: u8 = 0x05;
+pub(crate) const SOCKS_VERSION: u8 = 0x05;
 const AUTH_NONE: u8 = 0x00;
 const AUTH_PASSWORD: u8 = 0x02;
 const AUTH_NO_ACCEPTABLE: u8 = 0xFF;
@@ -22,66 +38,128 @@ const ADDR_TYPE_IPV4: u8 = 0x01;
 const ADDR_TYPE_DOMAIN: u8 = 0x03;
 const ADDR_TYPE_IPV6: u8 = 0x04;
 const REP_SUCCESS: u8 = 0x00;
-#[allow(dead_code)]
-const REP_GENERAL_FAILURE: u8 = 0x01;
-const REP_CONNECTION_REFUSED: u8 = 0x05;
-const REP_CMD_NOT_SUPPORTED: u8 = 0x07;
-const REP_NOT_ALLOWED: u8 = 0x02;
-#[allow(dead_code)]
-const REP_ADDR_NOT_SUPPORTED: u8 = 0x08;
 
 /// SOCKS5 proxy server.
+///
+/// Embedders build it with [`Socks5Proxy::builder`]. [`run`](Socks5Proxy::run) serves a
+/// pre-bound listener, [`serve`](Socks5Proxy::serve) binds one itself; both return
+/// once the shutdown token is cancelled. Connections already accepted keep
+/// relaying until they close or [`Stats::begin_shutdown`] is called.
+#[derive(Clone)]
 pub struct Socks5Proxy {
-    /// Bind address.
-    bind_addr: SocketAddr,
-
     /// Statistics collector.
     stats: Arc<Stats>,
 
     /// Configuration manager.
     config_manager: ConfigManager,
+
+    /// Address `serve` binds instead of the configured one.
+    bind: Option<SocketAddr>,
+
+    /// Cancelled to stop accepting connections.
+    shutdown: CancellationToken,
+
+    /// Hooks run as connections open and close.
+    hooks: ConnectionHooks,
 }
 
 impl Socks5Proxy {
     /// Create a new SOCKS5 proxy.
     pub fn new(
-        bind_addr: SocketAddr,
         _auth: Option<(String, String)>, // Deprecated, uses config_manager now
         stats: Arc<Stats>,
         config_manager: ConfigManager,
     ) -> Self {
-        Self {
-            bind_addr,
-            stats,
-            config_manager,
-        }
+        Self::builder().stats(stats).config(config_manager).build()
+    }
+
+    /// Start configuring a SOCKS5 proxy.
+    pub fn builder() -> ProxyBuilder<Self> {
+        ProxyBuilder::new()
     }
 
-    /// Start the SOCKS5 proxy server.
-    pub async fn run(&self) -> Result<()> {
-        let listener = TcpListener::bind(self.bind_addr).await?;
-        info!("SOCKS5 proxy listening on {}", self.bind_addr);
+    /// Statistics collector connections are recorded in.
+    pub fn stats(&self) -> &Arc<Stats> {
+        &self.stats
+    }
+
+    /// Bind the builder's address (or `server.host`:`server.socks_port`) and run on it.
+    pub async fn serve(&self) -> Result<()> {
+        let server = self.config_manager.get_server().await;
+        let options = ListenOptions::from_config(&server);
+        let listener = match self.bind {
+            Some(addr) => listeners::bind(addr, &options)?,
+            None => listeners::bind_host(&server.host, server.socks_port, &options).await?,
+        };
+        self.run(listener).await
+    }
+
+    /// Run the SOCKS5 accept loop on an already bound listener.
+    ///
+    /// Returns once the shutdown token is cancelled.
+    pub async fn run(&self, listener: TcpListener) -> Result<()> {
+        info!("SOCKS5 proxy listening on {}", listener.local_addr()?);
+        let mut backoff = AcceptBackoff::new(&self.stats, "socks5", &listener)?;
 
         loop {
-            match listener.accept().await {
-                Ok((stream, client_addr)) => {
-                    let stats = Arc::clone(&self.stats);
-                    let config_manager = self.config_manager.clone();
-
-                    tokio::spawn(async move {
-                        if let Err(e) =
-                            handle_client(stream, client_addr, stats, config_manager).await
-                        {
-                            debug!("Connection from {} error: {}", client_addr, e);
-                        }
-                    });
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = self.shutdown.cancelled() => {
+                    info!("SOCKS5 proxy on {} stopped", listener.local_addr()?);
+                    return Ok(());
                 }
-                Err(e) => {
-                    error!("Failed to accept connection: {}", e);
+            };
+            match accepted {
+                Ok((stream, client_addr)) => {
+                    backoff.accepted();
+                    let proxy = self.clone();
+                    self.stats
+                        .spawn_connection("socks5", client_addr, async move {
+                            proxy.serve_client(stream, client_addr).await;
+                        });
                 }
+                Err(e) => backoff.failed(&e).await,
             }
         }
     }
+
+    /// Serve one accepted client connection until it closes.
+    pub(crate) async fn serve_client(&self, stream: TcpStream, client_addr: SocketAddr) {
+        let span = telemetry::connection_span(Protocol::Socks5, client_addr);
+        if let Err(e) = handle_client(
+            stream,
+            client_addr,
+            Arc::clone(&self.stats),
+            self.config_manager.clone(),
+            self.hooks.clone(),
+        )
+        .instrument(span.clone())
+        .await
+        {
+            telemetry::record_error(&span, &e);
+            debug!("Connection from {} error: {}", client_addr, e);
+            self.stats.record_rejected("socks5", client_addr, &e).await;
+        }
+    }
+
+    /// Token whose cancellation stops the accept loop.
+    pub(crate) fn shutdown_token(&self) -> &CancellationToken {
+        &self.shutdown
+    }
+}
+
+impl ProxyBuilder<Socks5Proxy> {
+    /// Build the proxy.
+    pub fn build(self) -> Socks5Proxy {
+        let parts = self.into_parts();
+        Socks5Proxy {
+            stats: parts.stats,
+            config_manager: parts.config_manager,
+            bind: parts.bind,
+            shutdown: parts.shutdown,
+            hooks: parts.hooks,
+        }
+    }
 }
 
 /// Handle a single SOCKS5 client connection.
@@ -90,125 +168,218 @@ async fn handle_client(
     client_addr: SocketAddr,
     stats: Arc<Stats>,
     config_manager: ConfigManager,
+    hooks: ConnectionHooks,
 ) -> Result<()> {
+    let accepted_at = Utc::now();
     debug!("New SOCKS5 connection from {}", client_addr);
+    tcp_keepalive::enable(&stream, &config_manager.get_limits().await.tcp_keepalive);
 
     // Check IP access control
     let client_ip = client_addr.ip().to_string();
     if !config_manager.is_ip_allowed(&client_ip).await {
         warn!("IP blocked: {}", client_ip);
-        return Err(Error::AccessDenied(format!("IP blocked: {}", client_ip)));
+        return Err(Error::AccessDenied(
+            DenyReason::ClientIp,
+            format!("IP blocked: {}", client_ip),
+        ));
     }
 
-    // Read version and auth methods
-    let mut buf = [0u8; 2];
-    stream.read_exact(&mut buf).await?;
-
-    if buf[0] != SOCKS_VERSION {
-        return Err(Error::InvalidSocks5Protocol(format!(
-            "Invalid version: {}",
-            buf[0]
-        )));
-    }
+    // Bytes read from the client but not parsed yet
+    let mut buf = Vec::new();
 
-    let nmethods = buf[1] as usize;
-    let mut methods = vec![0u8; nmethods];
-    stream.read_exact(&mut methods).await?;
+    // Read version and auth methods
+    let methods = read_message(&mut stream, &mut buf, parse_greeting).await?;
 
     // Handle authentication based on config
-    let auth_enabled = config_manager.is_auth_enabled().await;
-    let authenticated_user: Option<String>;
-
-    if auth_enabled {
-        if !methods.contains(&AUTH_PASSWORD) {
-            stream
-                .write_all(&[SOCKS_VERSION, AUTH_NO_ACCEPTABLE])
+    let policy = config_manager.socks5_auth_policy(&client_ip).await;
+    let method = select_auth_method(&methods, &policy);
+    stream.write_all(&[SOCKS_VERSION, method]).await?;
+    let mut limits = SessionLimits::default();
+    let authenticated_user = match method {
+        AUTH_PASSWORD => {
+            // Read and verify username/password auth
+            let user = authenticate_user(&mut stream, &mut buf, client_addr, &config_manager)
+                .instrument(telemetry::auth_span())
                 .await?;
-            return Err(Error::AuthenticationFailed);
-        }
-        stream.write_all(&[SOCKS_VERSION, AUTH_PASSWORD]).await?;
-
-        // Read and verify username/password auth
-        authenticated_user = authenticate_user(&mut stream, &config_manager).await?;
-        if authenticated_user.is_none() {
-            return Err(Error::AuthenticationFailed);
+            limits = user.limits;
+            Some(user.username)
         }
-    } else {
-        authenticated_user = None;
-        if !methods.contains(&AUTH_NONE) {
-            stream
-                .write_all(&[SOCKS_VERSION, AUTH_NO_ACCEPTABLE])
-                .await?;
-            return Err(Error::AuthenticationFailed);
+        // Clients on exempt subnets skip authentication
+        AUTH_NONE if policy.required => {
+            let exempt_user = policy.exempt_user.clone();
+            if let Some(exempt_user) = &exempt_user {
+                debug!("Auth exemption for {} as {}", client_addr, exempt_user);
+                limits = config_manager
+                    .session_limits(exempt_user, SessionLimits::default())
+                    .await;
+            }
+            exempt_user
         }
-        stream.write_all(&[SOCKS_VERSION, AUTH_NONE]).await?;
+        AUTH_NONE => None,
+        _ => return Err(Error::AuthenticationFailed),
+    };
+    let authenticated_at = Utc::now();
+    if let Some(user) = &authenticated_user {
+        Span::current().record("user", user.as_str());
     }
 
     // Read connection request
-    let mut header = [0u8; 4];
-    stream.read_exact(&mut header).await?;
+    let Socks5Request {
+        host: requested_host,
+        port: target_port,
+    } = match read_message(&mut stream, &mut buf, parse_request).await {
+        Ok(request) => request,
+        Err(e @ (Error::UnsupportedCommand(_) | Error::UnsupportedAddressType(_))) => {
+            return reject(&mut stream, e).await
+        }
+        Err(e) => return Err(e),
+    };
+    // Rules and stats see the normalized name
+    let target_addr = match hostname::normalize(&requested_host) {
+        Ok(host) => host.into_owned(),
+        Err(e) => return reject(&mut stream, Error::AddressResolution(e.to_string())).await,
+    };
+    let target = format!("{}:{}", target_addr, target_port);
+    Span::current().record("target", target.as_str());
 
-    if header[0] != SOCKS_VERSION {
-        return Err(Error::InvalidSocks5Protocol(
-            "Invalid request version".into(),
-        ));
+    // Check target access control
+    if let Err((e, _)) = check_target_access(
+        &target_addr,
+        target_port,
+        DenyReason::Target,
+        client_addr,
+        authenticated_user.as_deref(),
+        "socks5",
+        &stats,
+        &config_manager,
+    )
+    .await
+    {
+        return reject(&mut stream, e).await;
     }
 
-    let cmd = header[1];
-    let atyp = header[3];
-
-    if cmd != CMD_CONNECT {
-        send_reply(&mut stream, REP_CMD_NOT_SUPPORTED).await?;
-        return Err(Error::UnsupportedCommand(cmd));
+    if let Err(e) = check_quota(authenticated_user.as_deref(), &stats, &config_manager).await {
+        warn!("{}", e);
+        return reject(&mut stream, e).await;
+    }
+    if let Err(e) = check_connection_limit(authenticated_user.as_deref(), &limits, &stats).await {
+        warn!(
+            "{} for user {}",
+            e,
+            authenticated_user.as_deref().unwrap_or_default()
+        );
+        return reject(&mut stream, e).await;
     }
 
-    // Parse target address
-    let (target_addr, target_port) = parse_address(&mut stream, atyp).await?;
-
-    // Check target access control
-    if !config_manager.is_target_allowed(&target_addr, None).await {
-        warn!("Target blocked: {}:{}", target_addr, target_port);
-        send_reply(&mut stream, REP_NOT_ALLOWED).await?;
-        return Err(Error::AccessDenied(format!(
-            "Target blocked: {}:{}",
-            target_addr, target_port
-        )));
+    // Create connection for tracking with user info
+    let mut conn_info = ConnectionInfo::with_user(
+        Protocol::Socks5,
+        client_addr.to_string(),
+        target_addr.clone(),
+        target_port,
+        authenticated_user.clone(),
+    );
+    conn_info.requested_host = (requested_host != target_addr).then_some(requested_host);
+    conn_info.set_handshake(accepted_at, authenticated_at);
+    if let Err(e) = hooks.on_connect(&conn_info).await {
+        warn!("{}", e);
+        return reject(&mut stream, e).await;
     }
 
     debug!("SOCKS5 CONNECT to {}:{}", target_addr, target_port);
 
     // Connect to target
-    let target = format!("{}:{}", target_addr, target_port);
-    let target_stream = match TcpStream::connect(&target).await {
-        Ok(s) => s,
+    let request = ConnectRequest {
+        host: &target_addr,
+        port: target_port,
+        client_addr,
+        username: authenticated_user.as_deref(),
+        protocol: Protocol::Socks5,
+    };
+    let connect_started = Utc::now();
+    let connected = connect_target(&request, &config_manager).await;
+    record_connect_outcome(&stats, &target_addr, &connected);
+    let mut target_stream = match connected {
+        Ok(connected) => {
+            conn_info.set_target_connected(connect_started, Utc::now(), connected.attempts);
+            conn_info.resolve_ms = connected.resolve_ms;
+            connected.stream
+        }
         Err(e) => {
-            warn!("Failed to connect to {}: {}", target, e);
-            send_reply(&mut stream, REP_CONNECTION_REFUSED).await?;
-            return Err(Error::ConnectionRefused(target));
+            hooks.on_abort(conn_info).await;
+            return reject(&mut stream, connect_failure(e, &target)).await;
         }
     };
 
-    // Send success reply
-    send_reply(&mut stream, REP_SUCCESS).await?;
+    let opened = async {
+        // Send success reply
+        send_reply(&mut stream, REP_SUCCESS).await?;
+
+        // Apply domain rules to the TLS SNI (covers clients connecting to IP literals),
+        // forwarding any client bytes read past the request
+        if config_manager.is_sni_inspection_enabled().await {
+            inspect_sni(
+                &mut stream,
+                &mut target_stream,
+                buf,
+                target_port,
+                client_addr,
+                authenticated_user.as_deref(),
+                "socks5",
+                &stats,
+                &config_manager,
+            )
+            .await
+        } else {
+            target_stream.write_all(&buf).await?;
+            Ok(SniInspection {
+                sni: None,
+                forwarded: buf.len() as u64,
+            })
+        }
+    }
+    .await;
+    let inspection = match opened {
+        Ok(inspection) => inspection,
+        Err(e) => {
+            hooks.on_abort(conn_info).await;
+            return Err(e);
+        }
+    };
 
-    // Create connection for tracking with user info
-    let conn_info = crate::connection::ConnectionInfo::with_user(
-        Protocol::Socks5,
-        client_addr.to_string(),
-        target_addr.clone(),
-        target_port,
-        authenticated_user.clone(),
-    );
+    conn_info.sni = inspection.sni;
+    conn_info.upstream = target_stream.upstream().map(str::to_string);
+    conn_info.egress = target_stream.egress().map(|ip| ip.to_string());
     let conn_id = conn_info.id;
-    stats.add_connection(conn_info).await;
+    track_connection(conn_info, &stats, &config_manager).await;
 
     // Relay traffic
-    let (bytes_sent, bytes_received) = relay_tcp(stream, target_stream).await;
+    let RelayResult {
+        bytes_sent,
+        bytes_received,
+        close_reason,
+        ..
+    } = relay_for_user(
+        stream,
+        target_stream,
+        conn_id,
+        authenticated_user.as_deref(),
+        &limits,
+        &stats,
+        &config_manager,
+    )
+    .instrument(telemetry::relay_span())
+    .await;
+    let bytes_sent = bytes_sent + inspection.forwarded;
+    telemetry::record_close(&Span::current(), bytes_sent, bytes_received, close_reason);
 
     // Record stats
-    stats
-        .close_connection(conn_id, bytes_sent, bytes_received)
-        .await;
+    if let Some(closed) = stats
+        .close_connection(conn_id, bytes_sent, bytes_received, close_reason)
+        .await
+    {
+        hooks.on_close(&closed).await;
+    }
 
     let user_info = authenticated_user
         .map(|u| format!(" (user: {})", u))
@@ -221,86 +392,264 @@ async fn handle_client(
     Ok(())
 }
 
+/// How the server negotiates auth methods with one client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthPolicy {
+    /// Clients must authenticate (`security.auth_enabled`).
+    pub required: bool,
+    /// Synthetic user of a client in `security.auth_exempt_ips`, which may
+    /// skip authentication.
+    pub exempt_user: Option<String>,
+    /// Method codes the server accepts (`security.socks5_auth_methods`).
+    pub allowed_methods: Vec<u8>,
+}
+
+impl AuthPolicy {
+    /// Policy for a client at `client_ip`.
+    pub fn new(security: &SecurityConfig, client_ip: &str) -> Self {
+        Self {
+            required: security.auth_enabled,
+            exempt_user: if security.auth_enabled {
+                security.auth_exempt_user(client_ip)
+            } else {
+                None
+            },
+            allowed_methods: security
+                .socks5_auth_methods
+                .iter()
+                .map(|method| method.code())
+                .collect(),
+        }
+    }
+}
+
+/// Pick the auth method for a client offering `offered`, or `0xFF` when
+/// none of them is acceptable.
+///
+/// Username/password is preferred when authentication is required, so
+/// credentials take precedence over an exemption. Otherwise no
+/// authentication is preferred, and username/password is still accepted
+/// from clients that offer only that.
+pub fn select_auth_method(offered: &[u8], policy: &AuthPolicy) -> u8 {
+    let usable = |method: u8| offered.contains(&method) && policy.allowed_methods.contains(&method);
+    let may_skip = !policy.required || policy.exempt_user.is_some();
+    if policy.required && usable(AUTH_PASSWORD) {
+        AUTH_PASSWORD
+    } else if may_skip && usable(AUTH_NONE) {
+        AUTH_NONE
+    } else if usable(AUTH_PASSWORD) {
+        AUTH_PASSWORD
+    } else {
+        AUTH_NO_ACCEPTABLE
+    }
+}
+
 /// Authenticate using username/password with multi-user support.
-/// Returns the authenticated username on success, None on failure.
+///
+/// Refused credentials fail with [`Error::CredentialsRejected`] (or
+/// [`Error::AuthenticationFailed`] for a malformed request), after the
+/// failure status was sent.
 async fn authenticate_user(
     stream: &mut TcpStream,
+    buf: &mut Vec<u8>,
+    client_addr: SocketAddr,
     config_manager: &ConfigManager,
-) -> Result<Option<String>> {
-    let mut buf = [0u8; 1];
-    stream.read_exact(&mut buf).await?;
+) -> Result<AuthenticatedUser> {
+    let result = match read_message(stream, buf, parse_credentials).await {
+        Ok(credentials) => check_credentials(&credentials, client_addr, config_manager).await,
+        Err(Error::InvalidSocks5Protocol(_)) => Err(Error::AuthenticationFailed),
+        Err(e @ Error::CredentialsRejected { .. }) => Err(e),
+        Err(e) => return Err(e),
+    };
+    match result {
+        Ok(user) => {
+            stream.write_all(&[0x01, 0x00]).await?;
+            Ok(user)
+        }
+        Err(e) => {
+            if let Error::CredentialsRejected {
+                username: Some(username),
+                reason,
+            } = &e
+            {
+                warn!(
+                    "SOCKS5 authentication failed from {} as '{}': {}",
+                    client_addr.ip(),
+                    username,
+                    reason
+                );
+            }
+            stream.write_all(&[0x01, 0x01]).await?;
+            Err(e)
+        }
+    }
+}
+
+/// Check credentials against `security.credential_limits` and the users.
+async fn check_credentials(
+    credentials: &Credentials,
+    client_addr: SocketAddr,
+    config_manager: &ConfigManager,
+) -> Result<AuthenticatedUser> {
+    let rejected = |reason: &str| Error::CredentialsRejected {
+        username: Some(loggable_username(credentials.username.as_bytes())),
+        reason: reason.to_string(),
+    };
+    config_manager
+        .credential_limits()
+        .await
+        .check(&credentials.username, &credentials.password)
+        .map_err(rejected)?;
+
+    // Authenticate using config_manager (users, tokens and the auth backend)
+    let request = AuthRequest {
+        username: &credentials.username,
+        password: &credentials.password,
+        client_ip: client_addr.ip(),
+        protocol: Protocol::Socks5,
+    };
+    config_manager
+        .authenticate_client(&request)
+        .await
+        .ok_or_else(|| rejected("invalid username or password"))
+}
 
-    // Auth version (should be 0x01)
-    if buf[0] != 0x01 {
-        stream.write_all(&[0x01, 0x01]).await?;
+/// Parse the client greeting (`VER NMETHODS METHODS`) into the offered auth methods.
+pub fn parse_greeting(buf: &[u8]) -> Result<Parsed<Vec<u8>>> {
+    let Some(&version) = buf.first() else {
         return Ok(None);
+    };
+    if version != SOCKS_VERSION {
+        return Err(Error::InvalidSocks5Protocol(format!(
+            "Invalid version: {}",
+            version
+        )));
     }
+    let Some(&nmethods) = buf.get(1) else {
+        return Ok(None);
+    };
+    let len = 2 + nmethods as usize;
+    Ok(buf.get(2..len).map(|methods| (methods.to_vec(), len)))
+}
 
-    // Read username
-    stream.read_exact(&mut buf).await?;
-    let ulen = buf[0] as usize;
-    let mut username_bytes = vec![0u8; ulen];
-    stream.read_exact(&mut username_bytes).await?;
-
-    // Read password
-    stream.read_exact(&mut buf).await?;
-    let plen = buf[0] as usize;
-    let mut password_bytes = vec![0u8; plen];
-    stream.read_exact(&mut password_bytes).await?;
-
-    let username = String::from_utf8_lossy(&username_bytes);
-    let password = String::from_utf8_lossy(&password_bytes);
-
-    // Authenticate using config_manager (supports multi-user)
-    if let Some(authenticated_user) = config_manager.authenticate(&username, &password).await {
-        stream.write_all(&[0x01, 0x00]).await?;
-        Ok(Some(authenticated_user))
-    } else {
-        stream.write_all(&[0x01, 0x01]).await?;
-        Ok(None)
+/// Username and password of a username/password auth request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Parse a username/password auth request (`VER ULEN UNAME PLEN PASSWD`, RFC 1929).
+///
+/// Fields that aren't valid UTF-8 fail with [`Error::CredentialsRejected`]
+/// rather than being converted lossily, so they can't collide with a
+/// configured name.
+pub fn parse_credentials(buf: &[u8]) -> Result<Parsed<Credentials>> {
+    let Some(&version) = buf.first() else {
+        return Ok(None);
+    };
+    if version != 0x01 {
+        return Err(Error::InvalidSocks5Protocol(format!(
+            "Invalid auth version: {}",
+            version
+        )));
     }
+    let Some((username, rest)) = length_prefixed(&buf[1..]) else {
+        return Ok(None);
+    };
+    let Some((password, _)) = length_prefixed(rest) else {
+        return Ok(None);
+    };
+    let len = 3 + username.len() + password.len();
+    let (Ok(name), Ok(secret)) = (std::str::from_utf8(username), std::str::from_utf8(password))
+    else {
+        return Err(Error::CredentialsRejected {
+            username: Some(loggable_username(username)),
+            reason: "credentials are not valid UTF-8".into(),
+        });
+    };
+    let credentials = Credentials {
+        username: name.to_string(),
+        password: secret.to_string(),
+    };
+    Ok(Some((credentials, len)))
+}
+
+/// Target of a CONNECT request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socks5Request {
+    pub host: String,
+    pub port: u16,
 }
 
-/// Parse SOCKS5 address.
-async fn parse_address(stream: &mut TcpStream, atyp: u8) -> Result<(String, u16)> {
-    let addr = match atyp {
+/// Parse a CONNECT request (`VER CMD RSV ATYP DST.ADDR DST.PORT`).
+///
+/// Other commands fail with [`Error::UnsupportedCommand`] and unknown address
+/// types with [`Error::UnsupportedAddressType`], both before the address is read.
+pub fn parse_request(buf: &[u8]) -> Result<Parsed<Socks5Request>> {
+    let Some(header) = buf.get(..4) else {
+        return Ok(None);
+    };
+    if header[0] != SOCKS_VERSION {
+        return Err(Error::InvalidSocks5Protocol(
+            "Invalid request version".into(),
+        ));
+    }
+    if header[1] != CMD_CONNECT {
+        return Err(Error::UnsupportedCommand(header[1]));
+    }
+
+    let rest = &buf[4..];
+    let (host, addr_len) = match header[3] {
         ADDR_TYPE_IPV4 => {
-            let mut buf = [0u8; 4];
-            stream.read_exact(&mut buf).await?;
-            format!("{}.{}.{}.{}", buf[0], buf[1], buf[2], buf[3])
+            let Some(ip) = rest.get(..4) else {
+                return Ok(None);
+            };
+            (format!("{}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3]), 4)
         }
         ADDR_TYPE_DOMAIN => {
-            let mut len = [0u8; 1];
-            stream.read_exact(&mut len).await?;
-            let mut domain = vec![0u8; len[0] as usize];
-            stream.read_exact(&mut domain).await?;
-            String::from_utf8_lossy(&domain).to_string()
-        }
-        ADDR_TYPE_IPV6 => {
-            let mut buf = [0u8; 16];
-            stream.read_exact(&mut buf).await?;
-            format!(
-                "{:x}:{:x}:{:x}:{:x}:{:x}:{:x}:{:x}:{:x}",
-                u16::from_be_bytes([buf[0], buf[1]]),
-                u16::from_be_bytes([buf[2], buf[3]]),
-                u16::from_be_bytes([buf[4], buf[5]]),
-                u16::from_be_bytes([buf[6], buf[7]]),
-                u16::from_be_bytes([buf[8], buf[9]]),
-                u16::from_be_bytes([buf[10], buf[11]]),
-                u16::from_be_bytes([buf[12], buf[13]]),
-                u16::from_be_bytes([buf[14], buf[15]]),
+            let Some((domain, _)) = length_prefixed(rest) else {
+                return Ok(None);
+            };
+            (
+                String::from_utf8_lossy(domain).into_owned(),
+                1 + domain.len(),
             )
         }
-        _ => {
-            return Err(Error::UnsupportedAddressType(atyp));
+        ADDR_TYPE_IPV6 => {
+            let Some(ip) = rest.get(..16) else {
+                return Ok(None);
+            };
+            let groups: Vec<String> = ip
+                .chunks_exact(2)
+                .map(|group| format!("{:x}", u16::from_be_bytes([group[0], group[1]])))
+                .collect();
+            (groups.join(":"), 16)
         }
+        atyp => return Err(Error::UnsupportedAddressType(atyp)),
+    };
+
+    let Some(port) = rest.get(addr_len..addr_len + 2) else {
+        return Ok(None);
+    };
+    let request = Socks5Request {
+        host,
+        port: u16::from_be_bytes([port[0], port[1]]),
     };
+    Ok(Some((request, 4 + addr_len + 2)))
+}
 
-    let mut port_buf = [0u8; 2];
-    stream.read_exact(&mut port_buf).await?;
-    let port = u16::from_be_bytes(port_buf);
+/// Split a one-byte length prefixed field off the front of `buf`.
+fn length_prefixed(buf: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (&len, rest) = buf.split_first()?;
+    let len = len as usize;
+    (rest.len() >= len).then(|| rest.split_at(len))
+}
 
-    Ok((addr, port))
+/// Send the reply matching `error` and fail with it.
+async fn reject(stream: &mut TcpStream, error: Error) -> Result<()> {
+    send_reply(stream, error.code().socks5_reply()).await?;
+    Err(error)
 }
 
 /// Send SOCKS5 reply.
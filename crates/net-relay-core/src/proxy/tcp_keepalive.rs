@@ -0,0 +1,42 @@
+//! TCP keepalive on client and target sockets (`limits.tcp_keepalive`).
+
+use std::io;
+use std::time::Duration;
+
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+use crate::config::TcpKeepaliveConfig;
+
+/// Turn on keepalive probing of `stream` as configured; does nothing when
+/// keepalive is disabled.
+///
+/// The probe count is left at the OS default where it can't be set.
+pub fn set_keepalive(stream: &TcpStream, config: &TcpKeepaliveConfig) -> io::Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(config.idle_secs));
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "windows"
+    ))]
+    let keepalive = keepalive
+        .with_interval(Duration::from_secs(config.interval_secs))
+        .with_retries(config.count);
+    SockRef::from(stream).set_tcp_keepalive(&keepalive)
+}
+
+/// [`set_keepalive`], logging instead of failing: a socket without
+/// keepalive still works.
+pub(crate) fn enable(stream: &TcpStream, config: &TcpKeepaliveConfig) {
+    if let Err(e) = set_keepalive(stream, config) {
+        debug!("Failed to enable TCP keepalive: {}", e);
+    }
+}
@@ -0,0 +1,346 @@
+//! Relay-to-relay tunnels between net-relay instances.
+//!
+//! A downstream ("edge") instance with `upstream.enabled` forwards every
+//! proxied connection to a central instance over TLS. Each tunnel carries
+//! one connection and starts with a hop header:
+//!
+//! ```text
+//! "NRT1" | u16 length (big endian) | JSON HopHeader
+//! ```
+//!
+//! The upstream answers with a single status byte and, on success, relays
+//! raw bytes in both directions.
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::{debug, info, warn};
+
+use crate::auth::SessionLimits;
+use crate::config::{ConfigManager, UpstreamConfig};
+use crate::connection::{ConnectionInfo, Protocol};
+use crate::error::{DenyReason, Error, Result};
+use crate::proxy::accept::AcceptBackoff;
+use crate::proxy::connect::{connect_target, track_connection, ConnectRequest};
+use crate::proxy::relay::{relay_for_user, RelayResult};
+use crate::proxy::tcp_keepalive;
+use crate::stats::Stats;
+use crate::tls;
+
+/// Magic bytes at the start of every hop header.
+const HOP_MAGIC: &[u8; 4] = b"NRT1";
+
+/// Largest accepted hop header payload.
+const MAX_HOP_HEADER_LEN: usize = 4096;
+
+/// Time allowed for the TLS handshake and hop header.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Hop status codes
+const HOP_OK: u8 = 0x00;
+const HOP_AUTH_FAILED: u8 = 0x01;
+const HOP_DENIED: u8 = 0x02;
+const HOP_CONNECT_FAILED: u8 = 0x03;
+
+/// Header sent by the downstream at the start of a tunnel.
+#[derive(Debug, Serialize, Deserialize)]
+struct HopHeader {
+    /// Downstream node name.
+    node: String,
+
+    /// Downstream node secret.
+    secret: String,
+
+    /// Original client address.
+    client_addr: String,
+
+    /// Username the client authenticated with on the downstream.
+    username: Option<String>,
+
+    /// Protocol the client used on the downstream.
+    protocol: Protocol,
+
+    /// Target host.
+    target_host: String,
+
+    /// Target port.
+    target_port: u16,
+}
+
+async fn write_header<S: AsyncWrite + Unpin>(stream: &mut S, header: &HopHeader) -> Result<()> {
+    let payload = serde_json::to_vec(header)
+        .map_err(|e| Error::Tunnel(format!("Failed to encode hop header: {}", e)))?;
+    let mut frame = Vec::with_capacity(6 + payload.len());
+    frame.extend_from_slice(HOP_MAGIC);
+    frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    stream.write_all(&frame).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> Result<HopHeader> {
+    let mut prefix = [0u8; 6];
+    stream.read_exact(&mut prefix).await?;
+    if &prefix[..4] != HOP_MAGIC {
+        return Err(Error::Tunnel("Invalid hop header magic".into()));
+    }
+
+    let len = u16::from_be_bytes([prefix[4], prefix[5]]) as usize;
+    if len > MAX_HOP_HEADER_LEN {
+        return Err(Error::Tunnel(format!("Hop header too large: {}", len)));
+    }
+
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).await?;
+    serde_json::from_slice(&payload)
+        .map_err(|e| Error::Tunnel(format!("Invalid hop header: {}", e)))
+}
+
+/// Strip the port from a `host:port` address.
+fn host_of(address: &str) -> &str {
+    address
+        .rsplit_once(':')
+        .map(|(host, _)| host)
+        .unwrap_or(address)
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+}
+
+/// Open a tunnel through the upstream relay for a single client connection.
+pub async fn open_tunnel(
+    upstream: &UpstreamConfig,
+    request: &ConnectRequest<'_>,
+) -> Result<TlsStream<TcpStream>> {
+    let server_name = upstream
+        .server_name
+        .clone()
+        .unwrap_or_else(|| host_of(&upstream.address).to_string());
+    let server_name = ServerName::try_from(server_name)
+        .map_err(|e| Error::Config(format!("Invalid upstream server name: {}", e)))?;
+    let connector = TlsConnector::from(tls::client_config(upstream.ca_file.as_deref())?);
+
+    // Bounded so an unresponsive relay fails over instead of stalling the client
+    let mut stream = tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+        let tcp = TcpStream::connect(&upstream.address).await?;
+        connector.connect(server_name, tcp).await
+    })
+    .await
+    .map_err(|_| Error::Timeout)??;
+
+    let header = HopHeader {
+        node: upstream.node_name.clone(),
+        secret: upstream.node_secret.clone(),
+        client_addr: request.client_addr.to_string(),
+        username: request.username.map(str::to_string),
+        protocol: request.protocol.clone(),
+        target_host: request.host.to_string(),
+        target_port: request.port,
+    };
+    write_header(&mut stream, &header).await?;
+
+    let mut status = [0u8; 1];
+    stream.read_exact(&mut status).await?;
+    match status[0] {
+        HOP_OK => Ok(stream),
+        HOP_AUTH_FAILED => Err(Error::Tunnel("Upstream rejected node credentials".into())),
+        HOP_DENIED => Err(Error::AccessDenied(
+            DenyReason::Upstream,
+            format!("Denied by upstream: {}:{}", request.host, request.port),
+        )),
+        _ => Err(Error::ConnectionRefused(format!(
+            "{}:{} (via upstream)",
+            request.host, request.port
+        ))),
+    }
+}
+
+/// Tunnel server accepting connections from trusted downstream relays.
+pub struct TunnelServer {
+    /// TLS acceptor for the tunnel listener.
+    acceptor: TlsAcceptor,
+
+    /// Statistics collector.
+    stats: Arc<Stats>,
+
+    /// Configuration manager.
+    config_manager: ConfigManager,
+}
+
+impl TunnelServer {
+    /// Create a new tunnel server.
+    pub fn new(
+        tls_config: Arc<ServerConfig>,
+        stats: Arc<Stats>,
+        config_manager: ConfigManager,
+    ) -> Self {
+        Self {
+            acceptor: TlsAcceptor::from(tls_config),
+            stats,
+            config_manager,
+        }
+    }
+
+    /// Run the tunnel accept loop on an already bound listener.
+    pub async fn run(&self, listener: TcpListener) -> Result<()> {
+        info!("Tunnel server listening on {}", listener.local_addr()?);
+        let mut backoff = AcceptBackoff::new(&self.stats, "tunnel", &listener)?;
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer_addr)) => {
+                    backoff.accepted();
+                    let acceptor = self.acceptor.clone();
+                    let stats = Arc::clone(&self.stats);
+                    let config_manager = self.config_manager.clone();
+                    self.stats
+                        .spawn_connection("tunnel", peer_addr, async move {
+                            if let Err(e) = handle_downstream(
+                                stream,
+                                peer_addr,
+                                acceptor,
+                                stats,
+                                config_manager,
+                            )
+                            .await
+                            {
+                                debug!("Tunnel from {} error: {}", peer_addr, e);
+                            }
+                        });
+                }
+                Err(e) => backoff.failed(&e).await,
+            }
+        }
+    }
+}
+
+/// Handle a single tunnel from a downstream relay.
+async fn handle_downstream(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    acceptor: TlsAcceptor,
+    stats: Arc<Stats>,
+    config_manager: ConfigManager,
+) -> Result<()> {
+    let accepted_at = Utc::now();
+    tcp_keepalive::enable(&stream, &config_manager.get_limits().await.tcp_keepalive);
+    let (mut stream, header) = tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+        let mut stream = acceptor.accept(stream).await?;
+        let header = read_header(&mut stream).await?;
+        Ok::<_, Error>((stream, header))
+    })
+    .await
+    .map_err(|_| Error::Timeout)??;
+
+    if !config_manager
+        .verify_downstream(&header.node, &header.secret)
+        .await
+    {
+        warn!(
+            "Tunnel from {} rejected: invalid credentials for node '{}'",
+            peer_addr, header.node
+        );
+        stream.write_all(&[HOP_AUTH_FAILED]).await?;
+        return Err(Error::AuthenticationFailed);
+    }
+    let authenticated_at = Utc::now();
+
+    let client_addr: SocketAddr = header
+        .client_addr
+        .parse()
+        .map_err(|_| Error::Tunnel(format!("Invalid client address: {}", header.client_addr)))?;
+    let target = format!("{}:{}", header.target_host, header.target_port);
+
+    // The original client is still subject to this instance's access control
+    let client_ip = client_addr.ip().to_string();
+    let denied = if !config_manager.is_ip_allowed(&client_ip).await {
+        Some(DenyReason::ClientIp)
+    } else if !config_manager
+        .is_target_allowed(&header.target_host, header.target_port, None)
+        .await
+    {
+        Some(DenyReason::Target)
+    } else {
+        None
+    };
+    if let Some(reason) = denied {
+        warn!(
+            "Tunnel from node '{}' denied: {} -> {}",
+            header.node, client_addr, target
+        );
+        stream.write_all(&[HOP_DENIED]).await?;
+        return Err(Error::AccessDenied(reason, target));
+    }
+
+    let request = ConnectRequest {
+        host: &header.target_host,
+        port: header.target_port,
+        client_addr,
+        username: header.username.as_deref(),
+        protocol: header.protocol.clone(),
+    };
+    let connect_started = Utc::now();
+    let connected = match connect_target(&request, &config_manager).await {
+        Ok(connected) => connected,
+        Err(e) => {
+            warn!("Failed to connect to {}: {}", target, e);
+            stream.write_all(&[HOP_CONNECT_FAILED]).await?;
+            return Err(Error::ConnectionRefused(target));
+        }
+    };
+    let target_connected_at = Utc::now();
+    stream.write_all(&[HOP_OK]).await?;
+    stream.flush().await?;
+
+    let mut conn_info = ConnectionInfo::with_user(
+        header.protocol.clone(),
+        header.client_addr.clone(),
+        header.target_host.clone(),
+        header.target_port,
+        header.username.clone(),
+    );
+    conn_info.set_handshake(accepted_at, authenticated_at);
+    conn_info.set_target_connected(connect_started, target_connected_at, connected.attempts);
+    conn_info.resolve_ms = connected.resolve_ms;
+    let target_stream = connected.stream;
+    conn_info.via = Some(header.node.clone());
+    conn_info.upstream = target_stream.upstream().map(str::to_string);
+    conn_info.egress = target_stream.egress().map(|ip| ip.to_string());
+    let conn_id = conn_info.id;
+    track_connection(conn_info, &stats, &config_manager).await;
+
+    let RelayResult {
+        bytes_sent,
+        bytes_received,
+        close_reason,
+        ..
+    } = relay_for_user(
+        stream,
+        target_stream,
+        conn_id,
+        None,
+        &SessionLimits::default(),
+        &stats,
+        &config_manager,
+    )
+    .await;
+
+    stats
+        .close_connection(conn_id, bytes_sent, bytes_received, close_reason)
+        .await;
+
+    info!(
+        "Tunnel connection closed: {} -> {} via '{}' (sent: {}, recv: {})",
+        header.client_addr, target, header.node, bytes_sent, bytes_received
+    );
+
+    Ok(())
+}
@@ -0,0 +1,202 @@
+//! Per-user data quotas.
+
+use chrono::{DateTime, Datelike, Duration, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::config::User;
+
+/// Quota accounting period.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaPeriod {
+    /// Calendar month (UTC).
+    #[default]
+    Monthly,
+    /// ISO week starting Monday 00:00 UTC.
+    Weekly,
+}
+
+impl QuotaPeriod {
+    /// Start of the period containing `now`.
+    pub fn start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let date = match self {
+            QuotaPeriod::Monthly => now.date_naive().with_day(1).unwrap(),
+            QuotaPeriod::Weekly => {
+                now.date_naive() - Duration::days(now.weekday().num_days_from_monday() as i64)
+            }
+        };
+        Utc.from_utc_datetime(&date.and_time(NaiveTime::MIN))
+    }
+
+    /// Start of the period following the one that began at `start`.
+    pub fn next_start(&self, start: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            QuotaPeriod::Monthly => {
+                let date = start.date_naive();
+                let next = if date.month() == 12 {
+                    date.with_year(date.year() + 1)
+                        .and_then(|d| d.with_month(1))
+                } else {
+                    date.with_month(date.month() + 1)
+                };
+                Utc.from_utc_datetime(&next.unwrap().and_time(NaiveTime::MIN))
+            }
+            QuotaPeriod::Weekly => start + Duration::weeks(1),
+        }
+    }
+}
+
+/// Usage recorded for one user in the current period.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct QuotaUsage {
+    period_start: DateTime<Utc>,
+    used_bytes: u64,
+}
+
+/// Quota state for a user.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaStatus {
+    /// Bytes allowed per period.
+    pub quota_bytes: u64,
+
+    /// Bytes used in the current period.
+    pub used_bytes: u64,
+
+    /// Bytes left in the current period.
+    pub remaining_bytes: u64,
+
+    /// Accounting period.
+    pub period: QuotaPeriod,
+
+    /// Start of the current period.
+    pub period_start: DateTime<Utc>,
+
+    /// When usage resets.
+    pub resets_at: DateTime<Utc>,
+
+    /// Whether new connections are refused.
+    pub exceeded: bool,
+}
+
+/// Per-user usage counters, optionally persisted to a JSON file.
+///
+/// Usage is counted for every authenticated user and rolls over lazily
+/// when the user's period has moved on.
+#[derive(Debug, Default)]
+pub struct QuotaTracker {
+    usage: Mutex<HashMap<String, QuotaUsage>>,
+    path: Option<PathBuf>,
+    dirty: AtomicBool,
+}
+
+impl QuotaTracker {
+    /// In-memory tracker that is lost on restart.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load usage from `path` (if it exists) and persist to it on [`save`](Self::save).
+    pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let usage = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            usage: Mutex::new(usage),
+            path: Some(path),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    /// Add transferred bytes to a user's usage.
+    pub fn add(&self, username: &str, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+        let mut usage = self.usage.lock().unwrap();
+        match usage.get_mut(username) {
+            Some(entry) => entry.used_bytes = entry.used_bytes.saturating_add(bytes),
+            None => {
+                usage.insert(
+                    username.to_string(),
+                    QuotaUsage {
+                        period_start: Utc::now(),
+                        used_bytes: bytes,
+                    },
+                );
+            }
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Quota state for a user, or `None` if the user has no quota.
+    pub fn status(&self, user: &User) -> Option<QuotaStatus> {
+        if user.quota_bytes == 0 {
+            return None;
+        }
+        let period_start = user.quota_period.start(Utc::now());
+        let used_bytes = {
+            let mut usage = self.usage.lock().unwrap();
+            match usage.get_mut(&user.username) {
+                Some(entry) if entry.period_start < period_start => {
+                    // A new period began since the last connection
+                    *entry = QuotaUsage {
+                        period_start,
+                        used_bytes: 0,
+                    };
+                    self.dirty.store(true, Ordering::Relaxed);
+                    0
+                }
+                Some(entry) => entry.used_bytes,
+                None => 0,
+            }
+        };
+
+        Some(QuotaStatus {
+            quota_bytes: user.quota_bytes,
+            used_bytes,
+            remaining_bytes: user.quota_bytes.saturating_sub(used_bytes),
+            period: user.quota_period,
+            period_start,
+            resets_at: user.quota_period.next_start(period_start),
+            exceeded: used_bytes >= user.quota_bytes,
+        })
+    }
+
+    /// Clear a user's usage for the current period.
+    pub fn reset(&self, user: &User) {
+        let period_start = user.quota_period.start(Utc::now());
+        self.usage.lock().unwrap().insert(
+            user.username.clone(),
+            QuotaUsage {
+                period_start,
+                used_bytes: 0,
+            },
+        );
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Write usage to the state file if it changed since the last save.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let content = serde_json::to_string_pretty(&*self.usage.lock().unwrap())?;
+        // Write then rename so a crash never leaves a truncated file
+        let tmp = path.with_extension("tmp");
+        let result = std::fs::write(&tmp, content).and_then(|_| std::fs::rename(&tmp, path));
+        if result.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        Ok(result?)
+    }
+}
@@ -0,0 +1,140 @@
+//! Access rules as a plain text list of domains.
+//!
+//! Blocklists are usually handed around as one domain per line. A rule list
+//! is that, plus `#` comments and an optional `allow:` or `deny:` prefix per
+//! line; lines without a prefix take the action chosen for the import:
+//!
+//! ```text
+//! # Ad networks
+//! ads.example
+//! deny: *.tracker.example
+//! allow: cdn.example   # needed by the intranet
+//! ```
+
+use serde::Serialize;
+
+use crate::config::{new_rule_id, AccessRule, RuleAction, RuleMode};
+use crate::hostname;
+
+/// A domain read from a rule list.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RuleListEntry {
+    /// Line number, starting at 1.
+    pub line: usize,
+
+    pub action: RuleAction,
+
+    /// Normalized domain pattern.
+    pub domain: String,
+}
+
+impl RuleListEntry {
+    /// An enabled, enforced rule for the entry, named `name`.
+    pub fn to_rule(&self, name: &str) -> AccessRule {
+        AccessRule {
+            id: new_rule_id(),
+            name: name.to_string(),
+            domain: self.domain.clone(),
+            domain_unicode: hostname::to_unicode(&self.domain),
+            path: None,
+            ports: None,
+            action: self.action.clone(),
+            mode: RuleMode::Enforce,
+            enabled: true,
+            expires_at: None,
+        }
+    }
+}
+
+/// A line of a rule list that isn't a domain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleListError {
+    /// Line number, starting at 1.
+    pub line: usize,
+    pub message: String,
+}
+
+/// Read a rule list, giving lines without a prefix `default_action`.
+///
+/// Fails with every invalid line, so a list is used whole or not at all.
+pub fn parse(
+    text: &str,
+    default_action: RuleAction,
+) -> Result<Vec<RuleListEntry>, Vec<RuleListError>> {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let content = line.split('#').next().unwrap_or_default().trim();
+        if content.is_empty() {
+            continue;
+        }
+        let (action, domain) = match content.split_once(':') {
+            Some((prefix, domain)) => match prefix.trim().to_ascii_lowercase().as_str() {
+                "allow" => (RuleAction::Allow, domain.trim()),
+                "deny" => (RuleAction::Deny, domain.trim()),
+                _ => {
+                    errors.push(RuleListError {
+                        line: line_number,
+                        message: format!("unknown prefix '{}' (allow: or deny:)", prefix.trim()),
+                    });
+                    continue;
+                }
+            },
+            None => (default_action.clone(), content),
+        };
+        if domain.is_empty() || domain.contains(char::is_whitespace) {
+            errors.push(RuleListError {
+                line: line_number,
+                message: "expected one domain".to_string(),
+            });
+            continue;
+        }
+        match hostname::normalize_pattern(domain) {
+            Ok(domain) => entries.push(RuleListEntry {
+                line: line_number,
+                action,
+                domain: domain.into_owned(),
+            }),
+            Err(e) => errors.push(RuleListError {
+                line: line_number,
+                message: e.to_string(),
+            }),
+        }
+    }
+    if errors.is_empty() {
+        Ok(entries)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Whether `rule` can be written to a rule list without losing anything.
+pub fn is_listable(rule: &AccessRule) -> bool {
+    rule.path.is_none()
+        && rule.ports.is_none()
+        && rule.mode == RuleMode::Enforce
+        && rule.enabled
+        && rule.expires_at.is_none()
+}
+
+/// Write the [listable](is_listable) `rules` as a rule list, every line with
+/// its action prefix, noting in a comment how many rules were left out.
+pub fn format(rules: &[AccessRule]) -> String {
+    let mut out = String::from("# net-relay access rules\n");
+    let skipped = rules.iter().filter(|rule| !is_listable(rule)).count();
+    if skipped > 0 {
+        out.push_str(&format!(
+            "# {} rule(s) with a path, ports, an expiry, monitor mode or disabled left out\n",
+            skipped
+        ));
+    }
+    for rule in rules.iter().filter(|rule| is_listable(rule)) {
+        let action = match rule.action {
+            RuleAction::Allow => "allow",
+            RuleAction::Deny => "deny",
+        };
+        out.push_str(&format!("{}: {}\n", action, rule.domain));
+    }
+    out
+}
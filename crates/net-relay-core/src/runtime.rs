@@ -0,0 +1,177 @@
+//! Process and Tokio runtime metrics.
+//!
+//! Process figures and listen queues are read from procfs, so they are only
+//! reported on Linux; elsewhere they are left out.
+
+use serde::{Deserialize, Serialize};
+
+/// Resource usage of the relay process.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProcessMetrics {
+    /// Open file descriptors (sockets included).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_fds: Option<u64>,
+
+    /// Soft limit on open file descriptors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_fds: Option<u64>,
+
+    /// Resident memory in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resident_memory_bytes: Option<u64>,
+
+    /// Virtual memory in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub virtual_memory_bytes: Option<u64>,
+
+    /// OS threads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u64>,
+}
+
+impl ProcessMetrics {
+    /// Read the current figures (all `None` outside Linux).
+    pub fn collect() -> Self {
+        #[cfg(target_os = "linux")]
+        {
+            procfs::process_metrics()
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            Self::default()
+        }
+    }
+}
+
+/// Metrics of the Tokio runtime the caller runs on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokioMetrics {
+    /// Worker threads.
+    pub workers: usize,
+
+    /// Tasks currently alive (connection tasks included).
+    pub alive_tasks: usize,
+
+    /// Tasks waiting in the global (injection) queue.
+    pub global_queue_depth: usize,
+}
+
+impl TokioMetrics {
+    /// Read the current runtime's metrics (`None` outside a runtime).
+    pub fn collect() -> Option<Self> {
+        let metrics = tokio::runtime::Handle::try_current().ok()?.metrics();
+        Some(Self {
+            workers: metrics.num_workers(),
+            alive_tasks: metrics.num_alive_tasks(),
+            global_queue_depth: metrics.global_queue_depth(),
+        })
+    }
+}
+
+/// Accept queue of a listening port.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListenerQueue {
+    /// Local port.
+    pub port: u16,
+
+    /// Connections completed by the kernel but not yet accepted.
+    pub queued: u64,
+}
+
+/// Accept queues of the sockets listening on `ports` (empty outside Linux).
+///
+/// IPv4 and IPv6 sockets on the same port are reported together.
+pub fn listener_queues(ports: &[u16]) -> Vec<ListenerQueue> {
+    #[cfg(target_os = "linux")]
+    {
+        procfs::listener_queues(ports)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = ports;
+        Vec::new()
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod procfs {
+    use super::{ListenerQueue, ProcessMetrics};
+    use std::fs;
+
+    /// `st` column value of sockets in the LISTEN state.
+    const TCP_LISTEN: &str = "0A";
+
+    pub(super) fn process_metrics() -> ProcessMetrics {
+        let status = fs::read_to_string("/proc/self/status").unwrap_or_default();
+        // Sizes in /proc/self/status are in kB
+        let status_field = |name: &str| {
+            status.lines().find_map(|line| {
+                line.strip_prefix(name)?
+                    .strip_prefix(':')?
+                    .split_whitespace()
+                    .next()?
+                    .parse::<u64>()
+                    .ok()
+            })
+        };
+
+        ProcessMetrics {
+            open_fds: fs::read_dir("/proc/self/fd")
+                .ok()
+                .map(|entries| entries.count() as u64),
+            max_fds: max_open_files(),
+            resident_memory_bytes: status_field("VmRSS").map(|kb| kb * 1024),
+            virtual_memory_bytes: status_field("VmSize").map(|kb| kb * 1024),
+            threads: status_field("Threads"),
+        }
+    }
+
+    /// Soft `Max open files` limit from /proc/self/limits.
+    fn max_open_files() -> Option<u64> {
+        let limits = fs::read_to_string("/proc/self/limits").ok()?;
+        limits
+            .lines()
+            .find_map(|line| line.strip_prefix("Max open files"))?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()
+    }
+
+    pub(super) fn listener_queues(ports: &[u16]) -> Vec<ListenerQueue> {
+        let mut queues: Vec<ListenerQueue> = Vec::new();
+        for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+            let Ok(contents) = fs::read_to_string(table) else {
+                continue;
+            };
+            // sl local_address rem_address st tx_queue:rx_queue ...
+            for line in contents.lines().skip(1) {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.len() < 5 || fields[3] != TCP_LISTEN {
+                    continue;
+                }
+                let Some(port) = fields[1]
+                    .rsplit_once(':')
+                    .and_then(|(_, port)| u16::from_str_radix(port, 16).ok())
+                    .filter(|port| ports.contains(port))
+                else {
+                    continue;
+                };
+                // For listening sockets rx_queue is the accept queue length
+                let Some(queued) = fields[4]
+                    .split_once(':')
+                    .and_then(|(_, rx)| u64::from_str_radix(rx, 16).ok())
+                else {
+                    continue;
+                };
+
+                match queues.iter_mut().find(|queue| queue.port == port) {
+                    Some(queue) => queue.queued += queued,
+                    None => queues.push(ListenerQueue { port, queued }),
+                }
+            }
+        }
+        queues.sort_by_key(|queue| queue.port);
+        queues
+    }
+}
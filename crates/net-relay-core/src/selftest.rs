@@ -0,0 +1,342 @@
+//! End-to-end self-test of the proxy listeners.
+//!
+//! [`run`] starts the proxies a configuration enables on loopback ports next
+//! to a built-in echo server and drives real client connections through
+//! them: a relayed round trip, a connection without credentials (when
+//! authentication is on) and a connection to [`DENIED_HOST`].
+//!
+//! The proxies run on a copy of the configuration adjusted so the outcome
+//! can be observed from loopback: client IP lists and auth exemptions are
+//! dropped, the echo server is allowed and [`DENIED_HOST`] denied ahead of
+//! the access rules, a temporary user is added when authentication is on,
+//! and connections go out directly rather than through upstream relays.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::{new_rule_id, random_hex, AccessRule, AuthBackend, RuleAction, RuleMode};
+use crate::error::{Error, Result};
+use crate::proxy::{
+    CancellationToken, CombinedProxy, HttpConnectClient, HttpProxy, Socks5Client, Socks5Proxy,
+};
+use crate::{hostname, Config, ConfigManager, Stats, User};
+
+/// Target the self-test expects the proxies to refuse.
+pub const DENIED_HOST: &str = "denied.selftest.invalid";
+
+/// Longest a single check may take.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bytes sent through the proxies and expected back from the echo server.
+const ECHO_PAYLOAD: &[u8] = b"net-relay selftest";
+
+/// Result of a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckOutcome {
+    Pass,
+    Fail,
+    /// Not applicable to the configuration, e.g. auth checks with auth off.
+    Skip,
+}
+
+/// A check of one listener and protocol.
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTestCheck {
+    /// Listener and protocol, e.g. `combined/socks5`.
+    pub listener: String,
+
+    /// What was checked: `relay`, `auth` or `deny`.
+    pub check: &'static str,
+
+    pub outcome: CheckOutcome,
+
+    /// What happened, for failures and skips.
+    pub detail: String,
+
+    pub duration_ms: u64,
+}
+
+/// Outcome of [`run`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SelfTestReport {
+    pub checks: Vec<SelfTestCheck>,
+}
+
+impl SelfTestReport {
+    /// Whether no check failed.
+    pub fn passed(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.outcome != CheckOutcome::Fail)
+    }
+}
+
+/// Protocol a client speaks to a listener.
+#[derive(Debug, Clone, Copy)]
+enum Client {
+    Socks5,
+    Http,
+}
+
+impl Client {
+    fn name(self) -> &'static str {
+        match self {
+            Client::Socks5 => "socks5",
+            Client::Http => "http",
+        }
+    }
+
+    async fn connect(
+        self,
+        proxy: SocketAddr,
+        host: &str,
+        port: u16,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<TcpStream> {
+        match self {
+            Client::Socks5 => Socks5Client::connect(proxy, host, port, credentials).await,
+            Client::Http => HttpConnectClient::connect(proxy, host, port, credentials).await,
+        }
+    }
+}
+
+/// Run the self-test against the proxies `config` enables.
+///
+/// Fails only when nothing can be tested: no proxy is enabled or a loopback
+/// port can't be bound.
+pub async fn run(config: &Config) -> anyhow::Result<SelfTestReport> {
+    let echo = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let echo_addr = echo.local_addr()?;
+    let echo_task = tokio::spawn(serve_echo(echo));
+
+    let credentials = config
+        .security
+        .auth_enabled
+        .then(|| -> anyhow::Result<_> {
+            Ok((format!("selftest-{}", random_hex(4)?), random_hex(8)?))
+        })
+        .transpose()?;
+    let config = test_config(config, &echo_addr, credentials.as_ref());
+    let config_manager = ConfigManager::new(config.clone(), None);
+    let stats = Arc::new(Stats::new(config.stats.max_history));
+    let shutdown = CancellationToken::new();
+    let socks = Socks5Proxy::builder()
+        .config(config_manager.clone())
+        .stats(Arc::clone(&stats))
+        .shutdown(shutdown.clone())
+        .build();
+    let http = HttpProxy::builder()
+        .config(config_manager)
+        .stats(stats)
+        .shutdown(shutdown.clone())
+        .build();
+
+    let mut listeners: Vec<(&str, SocketAddr, &[Client])> = Vec::new();
+    if config.server.socks_enabled {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        listeners.push(("socks5", listener.local_addr()?, &[Client::Socks5]));
+        let proxy = socks.clone();
+        tokio::spawn(async move { proxy.run(listener).await });
+    }
+    if config.server.http_enabled {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        listeners.push(("http", listener.local_addr()?, &[Client::Http]));
+        let proxy = http.clone();
+        tokio::spawn(async move { proxy.run(listener).await });
+    }
+    if config.server.combined_port.is_some() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        listeners.push((
+            "combined",
+            listener.local_addr()?,
+            &[Client::Socks5, Client::Http],
+        ));
+        let proxy = CombinedProxy::new(socks, http);
+        tokio::spawn(async move { proxy.run(listener).await });
+    }
+    if listeners.is_empty() {
+        echo_task.abort();
+        anyhow::bail!("no proxy listener is enabled");
+    }
+
+    let credentials = credentials
+        .as_ref()
+        .map(|(user, pass)| (user.as_str(), pass.as_str()));
+    let mut report = SelfTestReport::default();
+    for (name, addr, clients) in listeners {
+        for &client in clients {
+            let listener = if clients.len() > 1 {
+                format!("{}/{}", name, client.name())
+            } else {
+                name.to_string()
+            };
+            report.checks.push(
+                timed(
+                    &listener,
+                    "relay",
+                    check_relay(client, addr, echo_addr, credentials),
+                )
+                .await,
+            );
+            let auth = if credentials.is_some() {
+                timed(&listener, "auth", check_auth(client, addr, echo_addr)).await
+            } else {
+                skipped(&listener, "auth", "authentication is disabled")
+            };
+            report.checks.push(auth);
+            report
+                .checks
+                .push(timed(&listener, "deny", check_deny(client, addr, credentials)).await);
+        }
+    }
+
+    shutdown.cancel();
+    echo_task.abort();
+    Ok(report)
+}
+
+/// Copy of `config` set up for the self-test, see the module documentation.
+fn test_config(
+    config: &Config,
+    echo: &SocketAddr,
+    credentials: Option<&(String, String)>,
+) -> Config {
+    let mut config = config.clone();
+    let access = &mut config.access_control;
+    access.ip_whitelist.clear();
+    access.ip_blacklist.clear();
+    let echo_host = echo.ip().to_string();
+    access.rules.splice(
+        0..0,
+        [
+            selftest_rule(&echo_host, RuleAction::Allow),
+            selftest_rule(DENIED_HOST, RuleAction::Deny),
+        ],
+    );
+    config.security.allowed_ips.clear();
+    config.security.auth_exempt_ips.clear();
+    if let Some((username, password)) = credentials {
+        config.auth.backend = AuthBackend::Config;
+        config.security.users.push(User::new(username, password));
+    }
+    config.upstream.enabled = false;
+    config
+}
+
+fn selftest_rule(domain: &str, action: RuleAction) -> AccessRule {
+    AccessRule {
+        id: new_rule_id(),
+        name: "selftest".to_string(),
+        domain: domain.to_string(),
+        domain_unicode: hostname::to_unicode(domain),
+        path: None,
+        ports: None,
+        action,
+        mode: RuleMode::Enforce,
+        enabled: true,
+        expires_at: None,
+    }
+}
+
+async fn serve_echo(listener: TcpListener) {
+    while let Ok((mut stream, _)) = listener.accept().await {
+        tokio::spawn(async move {
+            let (mut reader, mut writer) = stream.split();
+            let _ = tokio::io::copy(&mut reader, &mut writer).await;
+        });
+    }
+}
+
+/// Run `check` under [`CHECK_TIMEOUT`], recording how long it took.
+async fn timed(
+    listener: &str,
+    name: &'static str,
+    check: impl std::future::Future<Output = std::result::Result<(), String>>,
+) -> SelfTestCheck {
+    let started = Instant::now();
+    let (outcome, detail) = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(())) => (CheckOutcome::Pass, String::new()),
+        Ok(Err(detail)) => (CheckOutcome::Fail, detail),
+        Err(_) => (
+            CheckOutcome::Fail,
+            format!("no answer within {}s", CHECK_TIMEOUT.as_secs()),
+        ),
+    };
+    SelfTestCheck {
+        listener: listener.to_string(),
+        check: name,
+        outcome,
+        detail,
+        duration_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+fn skipped(listener: &str, name: &'static str, reason: &str) -> SelfTestCheck {
+    SelfTestCheck {
+        listener: listener.to_string(),
+        check: name,
+        outcome: CheckOutcome::Skip,
+        detail: reason.to_string(),
+        duration_ms: 0,
+    }
+}
+
+/// A round trip to the echo server comes back unchanged.
+async fn check_relay(
+    client: Client,
+    proxy: SocketAddr,
+    echo: SocketAddr,
+    credentials: Option<(&str, &str)>,
+) -> std::result::Result<(), String> {
+    let mut stream = client
+        .connect(proxy, &echo.ip().to_string(), echo.port(), credentials)
+        .await
+        .map_err(|e| format!("connect failed: {}", e))?;
+    let mut echoed = vec![0u8; ECHO_PAYLOAD.len()];
+    let round_trip = async {
+        stream.write_all(ECHO_PAYLOAD).await?;
+        stream.read_exact(&mut echoed).await
+    };
+    round_trip
+        .await
+        .map_err(|e| format!("relay failed: {}", e))?;
+    if echoed != ECHO_PAYLOAD {
+        return Err("echo came back altered".to_string());
+    }
+    Ok(())
+}
+
+/// A connection without credentials is refused.
+async fn check_auth(
+    client: Client,
+    proxy: SocketAddr,
+    echo: SocketAddr,
+) -> std::result::Result<(), String> {
+    match client
+        .connect(proxy, &echo.ip().to_string(), echo.port(), None)
+        .await
+    {
+        Err(Error::AuthenticationFailed | Error::CredentialsRejected { .. }) => Ok(()),
+        Ok(_) => Err("connected without credentials".to_string()),
+        Err(e) => Err(format!("expected an authentication failure, got: {}", e)),
+    }
+}
+
+/// A connection to [`DENIED_HOST`] is refused by access control.
+async fn check_deny(
+    client: Client,
+    proxy: SocketAddr,
+    credentials: Option<(&str, &str)>,
+) -> std::result::Result<(), String> {
+    match client.connect(proxy, DENIED_HOST, 80, credentials).await {
+        Err(Error::AccessDenied(..)) => Ok(()),
+        Ok(_) => Err(format!("connected to {}", DENIED_HOST)),
+        Err(e) => Err(format!("expected access to be denied, got: {}", e)),
+    }
+}
@@ -1,13 +1,35 @@
 //! Statistics collection and aggregation.
 
 use chrono::{DateTime, Utc};
+use futures::FutureExt;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, VecDeque};
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock as StdRwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, oneshot, watch, Notify, RwLock};
+use tokio_util::task::TaskTracker;
+use tracing::error;
 
-use crate::connection::ConnectionInfo;
+use crate::access::TargetCheck;
+use crate::access_log::AccessLog;
+use crate::alerts::{AlertEngine, AlertSample, AlertStatus, AlertTransition};
+use crate::bandwidth::BandwidthLimiter;
+use crate::capture::{Capture, CaptureDirection, CaptureError, MAX_CAPTURES};
+use crate::checkpoint::StatsCheckpoint;
+use crate::config::{AlertsConfig, User};
+use crate::connection::{CloseReason, ConnectionInfo, ConnectionState, Protocol};
+use crate::error::{Error, ErrorCode};
+use crate::event_log::{Event, EventLog};
+use crate::latency::{ConnectionPhase, LatencyHistogram, PhaseLatency};
+use crate::proxy::relay::{RelayBufferMemory, RelayCounters};
+use crate::quota::{QuotaStatus, QuotaTracker};
+use crate::target_errors::{ConnectOutcome, TargetConnectStats, TargetErrors};
 
 /// Statistics for a single connection.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,6 +37,41 @@ pub struct ConnectionStats {
     /// Connection info.
     #[serde(flatten)]
     pub info: ConnectionInfo,
+
+    /// Position in the history, increasing with every recorded connection.
+    #[serde(default)]
+    pub seq: u64,
+}
+
+/// Restricts [`Stats::get_history`] to entries recorded after a client's last poll.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HistorySince {
+    /// Only entries with a greater `seq`.
+    pub id: Option<u64>,
+
+    /// Only connections closed after this time.
+    pub time: Option<DateTime<Utc>>,
+}
+
+impl HistorySince {
+    fn is_unset(&self) -> bool {
+        self.id.is_none() && self.time.is_none()
+    }
+
+    fn includes(&self, entry: &ConnectionStats) -> bool {
+        self.id.is_none_or(|id| entry.seq > id)
+            && self.time.is_none_or(|time| history_time(entry) > time)
+    }
+}
+
+/// History entries with the cursor to continue from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryPage {
+    /// Connections, newest first.
+    pub connections: Vec<ConnectionStats>,
+
+    /// `seq` of the last entry covered; pass it as `since_id` to get only newer entries.
+    pub cursor: u64,
 }
 
 /// Per-user statistics.
@@ -37,6 +94,199 @@ pub struct UserStats {
 
     /// Last activity time.
     pub last_activity: Option<DateTime<Utc>>,
+
+    /// Counts connections without a username from one client address or
+    /// prefix (`stats.attribute_by_ip`) rather than an authenticated user.
+    #[serde(default)]
+    pub ip_bucket: bool,
+}
+
+/// Prefix of the pseudo-usernames of IP buckets, e.g. `anon:203.0.113.0/24`.
+pub const IP_BUCKET_PREFIX: &str = "anon:";
+
+/// Attribution of connections without a username to their client address
+/// (`stats.attribute_by_ip`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpAttribution {
+    /// Leading bits of IPv4 addresses sharing a bucket (32 = one per address).
+    pub ipv4_prefix: u8,
+
+    /// Leading bits of IPv6 addresses sharing a bucket (128 = one per address).
+    pub ipv6_prefix: u8,
+
+    /// Most buckets kept; the least recently active idle bucket makes room
+    /// for a new one.
+    pub max_buckets: usize,
+}
+
+impl IpAttribution {
+    /// Pseudo-username of the bucket of a client address (`ip:port` or `ip`).
+    pub fn bucket(&self, client_addr: &str) -> Option<String> {
+        let ip = match client_addr.parse::<SocketAddr>() {
+            Ok(addr) => addr.ip(),
+            Err(_) => client_addr.parse::<IpAddr>().ok()?,
+        };
+        let (network, prefix, full) = match ip.to_canonical() {
+            IpAddr::V4(v4) => {
+                let prefix = self.ipv4_prefix.min(32);
+                let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                (
+                    IpAddr::from(std::net::Ipv4Addr::from(u32::from(v4) & mask)),
+                    prefix,
+                    32,
+                )
+            }
+            IpAddr::V6(v6) => {
+                let prefix = self.ipv6_prefix.min(128);
+                let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                (
+                    IpAddr::from(std::net::Ipv6Addr::from(u128::from(v6) & mask)),
+                    prefix,
+                    128,
+                )
+            }
+        };
+        Some(if prefix == full {
+            format!("{}{}", IP_BUCKET_PREFIX, network)
+        } else {
+            format!("{}{}/{}", IP_BUCKET_PREFIX, network, prefix)
+        })
+    }
+}
+
+/// Traffic through one upstream relay or egress address.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrafficStats {
+    /// Relay tunnel address or local egress IP.
+    pub address: String,
+
+    /// Total connections through this address.
+    pub total_connections: u64,
+
+    /// Currently active connections.
+    pub active_connections: u64,
+
+    /// Total bytes sent.
+    pub total_bytes_sent: u64,
+
+    /// Total bytes received.
+    pub total_bytes_received: u64,
+}
+
+/// Events buffered for each [`Stats::subscribe`] receiver before it lags.
+const EVENT_CHANNEL_CAPACITY: usize = 4096;
+
+/// Change published to [`Stats::subscribe`] receivers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatsEvent {
+    /// A connection was recorded.
+    Opened {
+        protocol: Protocol,
+        user: Option<String>,
+    },
+    /// A connection was closed.
+    Closed {
+        protocol: Protocol,
+        user: Option<String>,
+        bytes_sent: u64,
+        bytes_received: u64,
+        reason: CloseReason,
+    },
+    /// An attempt was refused by access control.
+    Denied {
+        source: String,
+        code: Option<ErrorCode>,
+    },
+    /// A proxy client failed to authenticate.
+    AuthFailure { source: String },
+}
+
+tokio::task_local! {
+    /// Connections opened by the current connection task and not yet closed
+    /// by it, for [`Stats::spawn_connection`] to close if the task panics.
+    static TASK_CONNECTIONS: RefCell<Vec<uuid::Uuid>>;
+}
+
+/// Denied attempts kept in memory unless set with [`Stats::set_tracking_limits`].
+const DEFAULT_MAX_DENIED_ATTEMPTS: usize = 1000;
+
+/// Users with counters kept unless set with [`Stats::set_tracking_limits`].
+const DEFAULT_MAX_TRACKED_USERS: usize = 10_000;
+
+/// Share of the user limit evicted at once, so a stream of new names
+/// doesn't scan the whole map on every connection.
+const USER_EVICTION_FRACTION: usize = 10;
+
+/// A connection or request refused by access control.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeniedAttempt {
+    /// When the attempt was denied.
+    pub time: DateTime<Utc>,
+
+    /// Client address.
+    pub client_addr: String,
+
+    /// Where the denial happened ("socks5", "http" or "dashboard").
+    pub source: String,
+
+    /// Requested target (if known).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+
+    /// Why the attempt was denied.
+    pub reason: String,
+
+    /// Error code of the denial (e.g. `client_ip_denied`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+
+    /// Denied by a monitored rule only; the connection went ahead.
+    #[serde(default)]
+    pub would_deny: bool,
+}
+
+impl DeniedAttempt {
+    /// Create a denied attempt record stamped with the current time.
+    pub fn new(
+        client_addr: impl Into<String>,
+        source: &str,
+        target: Option<String>,
+        reason: impl Into<String>,
+    ) -> Self {
+        Self {
+            time: Utc::now(),
+            client_addr: client_addr.into(),
+            source: source.to_string(),
+            target,
+            reason: reason.into(),
+            code: None,
+            would_deny: false,
+        }
+    }
+
+    /// Attach the error code of the denial.
+    pub fn with_code(mut self, code: ErrorCode) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    /// Mark the attempt as let through by monitoring.
+    pub fn monitored(mut self) -> Self {
+        self.would_deny = true;
+        self
+    }
+}
+
+/// How often an access rule matched.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RuleHits {
+    /// Matches where the rule's action was applied.
+    pub enforced: u64,
+    /// Matches of the rule in monitor mode.
+    pub monitored: u64,
+    /// When the rule last matched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_hit: Option<DateTime<Utc>>,
 }
 
 /// Aggregated statistics.
@@ -63,149 +313,2129 @@ pub struct AggregatedStats {
     /// Per-user statistics.
     #[serde(default)]
     pub users: Vec<UserStats>,
+
+    /// Per-upstream relay traffic.
+    #[serde(default)]
+    pub upstreams: Vec<TrafficStats>,
+
+    /// Per-egress address traffic.
+    #[serde(default)]
+    pub egress: Vec<TrafficStats>,
+
+    /// Bytes relayed across all connections during the last second.
+    #[serde(default)]
+    pub current_throughput: u64,
+
+    /// Server-wide bandwidth cap in bytes per second (0 = unlimited).
+    #[serde(default)]
+    pub global_bandwidth: u64,
+
+    /// Proxy requests since start by how they ended.
+    #[serde(default)]
+    pub attempts: AttemptCounts,
+
+    /// Lookups of the access decision cache since start.
+    #[serde(default)]
+    pub decision_cache: DecisionCacheCounts,
 }
 
-/// Thread-safe statistics collector.
-#[derive(Debug)]
-pub struct Stats {
-    /// Total connections counter.
-    total_connections: AtomicU64,
+/// How a proxy request ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttemptOutcome {
+    /// The target was connected and relaying started.
+    Relayed,
+    /// Access control, a hook, a quota or a connection limit refused it.
+    Denied,
+    /// The target could not be resolved or connected to.
+    ConnectFailed,
+    /// The client's credentials were refused.
+    AuthFailed,
+}
 
-    /// Total bytes sent.
-    total_bytes_sent: AtomicU64,
+impl AttemptOutcome {
+    /// Outcome of a request the proxy turned away with `error`, `None` for
+    /// failures that don't end a request (protocol errors, dropped clients).
+    pub fn of_error(error: &Error) -> Option<Self> {
+        Some(match error.code() {
+            ErrorCode::ClientIpDenied
+            | ErrorCode::TargetDenied
+            | ErrorCode::SniDenied
+            | ErrorCode::UpstreamDenied
+            | ErrorCode::HookDenied
+            | ErrorCode::QuotaExceeded
+            | ErrorCode::MaxConnectionsReached => AttemptOutcome::Denied,
+            ErrorCode::ConnectionRefused
+            | ErrorCode::Timeout
+            | ErrorCode::AddressResolutionFailed => AttemptOutcome::ConnectFailed,
+            ErrorCode::AuthenticationFailed => AttemptOutcome::AuthFailed,
+            _ => return None,
+        })
+    }
+}
 
-    /// Total bytes received.
-    total_bytes_received: AtomicU64,
+/// Proxy requests by outcome: the funnel from attempts to relayed tunnels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttemptCounts {
+    /// All requests with an outcome (the sum of the others).
+    pub total: u64,
+    pub relayed: u64,
+    pub denied: u64,
+    pub connect_failed: u64,
+    pub auth_failed: u64,
+}
 
-    /// Server start time.
-    started_at: DateTime<Utc>,
+/// Counters behind [`AttemptCounts`].
+#[derive(Debug, Default)]
+struct AttemptCounters {
+    relayed: AtomicU64,
+    denied: AtomicU64,
+    connect_failed: AtomicU64,
+    auth_failed: AtomicU64,
+}
 
-    /// Recent connection history.
-    history: Arc<RwLock<VecDeque<ConnectionStats>>>,
+impl AttemptCounters {
+    fn record(&self, outcome: AttemptOutcome) {
+        let counter = match outcome {
+            AttemptOutcome::Relayed => &self.relayed,
+            AttemptOutcome::Denied => &self.denied,
+            AttemptOutcome::ConnectFailed => &self.connect_failed,
+            AttemptOutcome::AuthFailed => &self.auth_failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
 
-    /// Active connections.
-    active: Arc<RwLock<Vec<ConnectionInfo>>>,
+    fn snapshot(&self) -> AttemptCounts {
+        let relayed = self.relayed.load(Ordering::Relaxed);
+        let denied = self.denied.load(Ordering::Relaxed);
+        let connect_failed = self.connect_failed.load(Ordering::Relaxed);
+        let auth_failed = self.auth_failed.load(Ordering::Relaxed);
+        AttemptCounts {
+            total: relayed + denied + connect_failed + auth_failed,
+            relayed,
+            denied,
+            connect_failed,
+            auth_failed,
+        }
+    }
+}
 
-    /// Per-user statistics.
-    user_stats: Arc<RwLock<HashMap<String, UserStats>>>,
+/// Target checks answered from the [`DecisionCache`](crate::DecisionCache)
+/// (`hits`) and evaluated against the rules (`misses`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecisionCacheCounts {
+    pub hits: u64,
+    pub misses: u64,
+}
 
-    /// Maximum history size.
-    max_history: usize,
+/// Span of closed connections currently kept in the history.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryWindow {
+    /// Connections in the history.
+    pub entries: usize,
+
+    /// When the oldest connection in the history closed.
+    pub oldest: Option<DateTime<Utc>>,
+
+    /// Most connections kept (`stats.max_history`).
+    pub max_entries: usize,
+
+    /// Age after which connections are dropped, in seconds (`None` = kept until pushed out).
+    pub max_age_secs: Option<u64>,
 }
 
-impl Stats {
-    /// Create a new statistics collector.
-    pub fn new(max_history: usize) -> Self {
-        Self {
-            total_connections: AtomicU64::new(0),
-            total_bytes_sent: AtomicU64::new(0),
-            total_bytes_received: AtomicU64::new(0),
-            started_at: Utc::now(),
-            history: Arc::new(RwLock::new(VecDeque::with_capacity(max_history))),
-            active: Arc::new(RwLock::new(Vec::new())),
-            user_stats: Arc::new(RwLock::new(HashMap::new())),
-            max_history,
+/// Sizes of the in-memory structures kept by [`Stats`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StatsSizes {
+    /// Closed connections kept in the history.
+    pub history: usize,
+
+    /// Capacity of the history.
+    pub history_capacity: usize,
+
+    /// Active connections tracked.
+    pub active: usize,
+
+    /// Users with counters.
+    pub users: usize,
+
+    /// Upstream relays with counters.
+    pub upstreams: usize,
+
+    /// Egress addresses with counters.
+    pub egress: usize,
+
+    /// Denied attempts kept.
+    pub denied: usize,
+
+    /// Target hosts with connect outcomes.
+    #[serde(default)]
+    pub targets: usize,
+
+    /// Users dropped to stay within `stats.max_tracked_users`.
+    #[serde(default)]
+    pub evicted_users: u64,
+
+    /// IP buckets dropped to stay within `stats.max_ip_buckets`.
+    #[serde(default)]
+    pub evicted_ip_buckets: u64,
+
+    /// Denied attempts dropped to stay within `stats.max_denied_attempts`.
+    #[serde(default)]
+    pub evicted_denied: u64,
+}
+
+/// Bounds on the per-user counters and denied attempts, see [`Stats::set_tracking_limits`].
+#[derive(Debug)]
+struct TrackingLimits {
+    max_users: usize,
+    max_denied: usize,
+    /// Users in the config, whose counters are never evicted.
+    configured: HashSet<String>,
+}
+
+/// Entries dropped to stay within the tracking limits.
+#[derive(Debug, Default)]
+struct Evictions {
+    users: AtomicU64,
+    ip_buckets: AtomicU64,
+    denied: AtomicU64,
+}
+
+/// Default length of the window connection rates are averaged over.
+const DEFAULT_RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Number of shards in the active connection map.
+const ACTIVE_SHARDS: usize = 32;
+
+/// Maximum number of history entries applied per writer lock.
+const HISTORY_BATCH: usize = 256;
+
+/// Live per-user counters, shared by every connection of that user.
+#[derive(Debug, Default)]
+struct UserCounters {
+    total_connections: AtomicU64,
+    active_connections: AtomicU64,
+    total_bytes_sent: AtomicU64,
+    total_bytes_received: AtomicU64,
+    /// Unix milliseconds of the last activity (0 = never).
+    last_activity_ms: AtomicI64,
+    /// Counters of an IP bucket rather than a user.
+    ip_bucket: bool,
+}
+
+impl UserCounters {
+    fn touch(&self) {
+        self.last_activity_ms
+            .store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    fn snapshot(&self, username: &str) -> UserStats {
+        let last_activity_ms = self.last_activity_ms.load(Ordering::Relaxed);
+        UserStats {
+            username: username.to_string(),
+            total_connections: self.total_connections.load(Ordering::Relaxed),
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            total_bytes_sent: self.total_bytes_sent.load(Ordering::Relaxed),
+            total_bytes_received: self.total_bytes_received.load(Ordering::Relaxed),
+            last_activity: (last_activity_ms != 0)
+                .then(|| DateTime::from_timestamp_millis(last_activity_ms))
+                .flatten(),
+            ip_bucket: self.ip_bucket,
         }
     }
+}
 
-    /// Record a new connection.
-    pub async fn add_connection(&self, info: ConnectionInfo) {
+/// Live per-address counters, shared by every connection through that upstream relay or egress address.
+#[derive(Debug, Default)]
+struct TrafficCounters {
+    total_connections: AtomicU64,
+    active_connections: AtomicU64,
+    total_bytes_sent: AtomicU64,
+    total_bytes_received: AtomicU64,
+}
+
+impl TrafficCounters {
+    fn open(&self) {
         self.total_connections.fetch_add(1, Ordering::Relaxed);
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+    }
 
-        // Update per-user stats
-        if let Some(ref username) = info.username {
-            let mut user_stats = self.user_stats.write().await;
-            let stats = user_stats
-                .entry(username.clone())
-                .or_insert_with(|| UserStats {
-                    username: username.clone(),
-                    ..Default::default()
-                });
-            stats.total_connections += 1;
-            stats.active_connections += 1;
-            stats.last_activity = Some(Utc::now());
+    fn close(&self, bytes_sent: u64, bytes_received: u64) {
+        self.active_connections.fetch_sub(1, Ordering::Relaxed);
+        self.total_bytes_sent
+            .fetch_add(bytes_sent, Ordering::Relaxed);
+        self.total_bytes_received
+            .fetch_add(bytes_received, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self, address: &str) -> TrafficStats {
+        TrafficStats {
+            address: address.to_string(),
+            total_connections: self.total_connections.load(Ordering::Relaxed),
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            total_bytes_sent: self.total_bytes_sent.load(Ordering::Relaxed),
+            total_bytes_received: self.total_bytes_received.load(Ordering::Relaxed),
         }
+    }
+}
+
+/// Accept statistics of one listener.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListenerStats {
+    /// Service on the listener: `socks5`, `http`, `combined` or `tunnel`.
+    pub protocol: String,
+
+    /// Local address the listener is bound to.
+    pub address: String,
+
+    /// Connections accepted.
+    pub accepted: u64,
+
+    /// Failed `accept` calls, e.g. because file descriptors ran out.
+    pub accept_errors: u64,
+
+    /// Most recent accept error.
+    pub last_error: Option<String>,
 
-        self.active.write().await.push(info);
+    /// When the most recent accept error happened.
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+/// Live accept counters of one listener, see [`Stats::listener`].
+#[derive(Debug, Default)]
+pub struct ListenerCounters {
+    accepted: AtomicU64,
+    accept_errors: AtomicU64,
+    last_error: Mutex<Option<(String, DateTime<Utc>)>>,
+}
+
+impl ListenerCounters {
+    /// Count an accepted connection.
+    pub fn record_accept(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
     }
 
-    /// Update connection bytes.
-    pub fn add_bytes(&self, sent: u64, received: u64) {
-        self.total_bytes_sent.fetch_add(sent, Ordering::Relaxed);
-        self.total_bytes_received
-            .fetch_add(received, Ordering::Relaxed);
+    /// Count a failed accept.
+    pub fn record_error(&self, error: &std::io::Error) {
+        self.accept_errors.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().unwrap() = Some((error.to_string(), Utc::now()));
     }
 
-    /// Mark a connection as closed and move to history.
-    pub async fn close_connection(&self, id: uuid::Uuid, bytes_sent: u64, bytes_received: u64) {
-        let mut active = self.active.write().await;
-
-        if let Some(pos) = active.iter().position(|c| c.id == id) {
-            let mut info = active.remove(pos);
-            info.set_closed();
-            info.bytes_sent = bytes_sent;
-            info.bytes_received = bytes_received;
-
-            self.add_bytes(bytes_sent, bytes_received);
-
-            // Update per-user stats
-            if let Some(ref username) = info.username {
-                let mut user_stats = self.user_stats.write().await;
-                if let Some(stats) = user_stats.get_mut(username) {
-                    stats.active_connections = stats.active_connections.saturating_sub(1);
-                    stats.total_bytes_sent += bytes_sent;
-                    stats.total_bytes_received += bytes_received;
-                    stats.last_activity = Some(Utc::now());
-                }
-            }
+    fn snapshot(&self, protocol: &str, address: &str) -> ListenerStats {
+        let last_error = self.last_error.lock().unwrap().clone();
+        ListenerStats {
+            protocol: protocol.to_string(),
+            address: address.to_string(),
+            accepted: self.accepted.load(Ordering::Relaxed),
+            accept_errors: self.accept_errors.load(Ordering::Relaxed),
+            last_error_at: last_error.as_ref().map(|(_, at)| *at),
+            last_error: last_error.map(|(error, _)| error),
+        }
+    }
+}
 
-            let mut history = self.history.write().await;
-            if history.len() >= self.max_history {
-                history.pop_front();
-            }
-            history.push_back(ConnectionStats { info });
+/// Users of untracked authenticated connections, by connection id.
+type UntrackedSessions = Mutex<HashMap<uuid::Uuid, Arc<str>>>;
+
+/// Connections opened while statistics were disabled (see [`Stats::set_enabled`]).
+///
+/// Only what connection limits and quotas need is kept: the user of each
+/// authenticated connection and how many each user has open. Connections
+/// without a user cost a counter update.
+#[derive(Debug)]
+struct Untracked {
+    /// Open untracked connections.
+    active: AtomicU64,
+    /// User of each open authenticated connection, sharded by connection id.
+    sessions: Box<[UntrackedSessions]>,
+    /// Entries in `sessions`.
+    authenticated: AtomicU64,
+    /// Open untracked connections by user.
+    users: Mutex<HashMap<Arc<str>, u64>>,
+}
+
+impl Default for Untracked {
+    fn default() -> Self {
+        Self {
+            active: AtomicU64::new(0),
+            sessions: (0..ACTIVE_SHARDS)
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+            authenticated: AtomicU64::new(0),
+            users: Mutex::new(HashMap::new()),
         }
     }
+}
 
-    /// Get aggregated statistics.
-    pub async fn get_aggregated(&self) -> AggregatedStats {
-        let active_count = self.active.read().await.len() as u64;
-        let user_stats: Vec<UserStats> = self.user_stats.read().await.values().cloned().collect();
+impl Untracked {
+    fn open(&self, id: uuid::Uuid, username: Option<&str>) {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        let Some(username) = username else {
+            return;
+        };
+        let username: Arc<str> = Arc::from(username);
+        *self
+            .users
+            .lock()
+            .unwrap()
+            .entry(Arc::clone(&username))
+            .or_default() += 1;
+        self.session_shard(id).lock().unwrap().insert(id, username);
+        self.authenticated.fetch_add(1, Ordering::Relaxed);
+    }
 
-        AggregatedStats {
-            total_connections: self.total_connections.load(Ordering::Relaxed),
-            active_connections: active_count,
-            total_bytes_sent: self.total_bytes_sent.load(Ordering::Relaxed),
-            total_bytes_received: self.total_bytes_received.load(Ordering::Relaxed),
-            uptime_secs: (Utc::now() - self.started_at).num_seconds(),
-            started_at: self.started_at,
-            users: user_stats,
+    /// Forget an untracked connection, returning its user (`None` if no
+    /// untracked connection is open).
+    fn close(&self, id: uuid::Uuid) -> Option<Option<Arc<str>>> {
+        self.active
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .ok()?;
+        if self.authenticated.load(Ordering::Relaxed) == 0 {
+            return Some(None);
+        }
+        let Some(username) = self.session_shard(id).lock().unwrap().remove(&id) else {
+            return Some(None);
+        };
+        self.authenticated.fetch_sub(1, Ordering::Relaxed);
+        let mut users = self.users.lock().unwrap();
+        if let Some(open) = users.get_mut(&username) {
+            *open -= 1;
+            if *open == 0 {
+                users.remove(&username);
+            }
         }
+        Some(Some(username))
     }
 
-    /// Get per-user statistics.
-    pub async fn get_user_stats(&self) -> Vec<UserStats> {
-        self.user_stats.read().await.values().cloned().collect()
+    /// Open untracked connections of `username`.
+    fn active_for(&self, username: &str) -> u64 {
+        if self.authenticated.load(Ordering::Relaxed) == 0 {
+            return 0;
+        }
+        self.users
+            .lock()
+            .unwrap()
+            .get(username)
+            .copied()
+            .unwrap_or(0)
     }
 
-    /// Get statistics for a specific user.
-    pub async fn get_user(&self, username: &str) -> Option<UserStats> {
-        self.user_stats.read().await.get(username).cloned()
+    fn session_shard(&self, id: uuid::Uuid) -> &Mutex<HashMap<uuid::Uuid, Arc<str>>> {
+        &self.sessions[(id.as_u128() % ACTIVE_SHARDS as u128) as usize]
     }
+}
 
-    /// Get active connections.
-    pub async fn get_active(&self) -> Vec<ConnectionInfo> {
-        self.active.read().await.clone()
+/// An active connection and the cached counters of its user, upstream and egress.
+#[derive(Debug)]
+struct ActiveEntry {
+    info: ConnectionInfo,
+    user: Option<Arc<UserCounters>>,
+    upstream: Option<Arc<TrafficCounters>>,
+    egress: Option<Arc<TrafficCounters>>,
+    /// Notified to terminate the connection's relay.
+    kill: Arc<Notify>,
+    /// Live byte counts published by the relay.
+    counters: Arc<RelayCounters>,
+    /// Byte counts reported by the relay, oldest first; the first one is
+    /// taken at or before the start of the rate window.
+    samples: VecDeque<RateSample>,
+}
+
+/// Byte counts of a connection at one point in time.
+#[derive(Debug, Clone, Copy)]
+struct RateSample {
+    at: Instant,
+    sent: u64,
+    received: u64,
+}
+
+impl ActiveEntry {
+    /// Connection info with the relay's live byte counts and rates filled in.
+    fn snapshot(&self, window: Duration) -> ConnectionInfo {
+        let mut info = self.info.clone();
+        info.bytes_sent = self.counters.sent();
+        info.bytes_received = self.counters.received();
+        info.last_activity_at = self.counters.last_activity();
+        if let Some(at) = self.counters.first_byte_received() {
+            info.set_first_byte(at);
+        }
+        if let Some(at) = self.counters.first_byte_sent() {
+            info.set_first_byte_sent(at);
+        }
+        info.remaining_lifetime_secs = info
+            .expires_at
+            .map(|at| (at - Utc::now()).num_seconds().max(0) as u64);
+
+        // Average since the newest sample at or before the window start, so
+        // an idle connection falls to zero once its last burst leaves the window
+        let now = Instant::now();
+        let start = now.checked_sub(window);
+        let base = self
+            .samples
+            .iter()
+            .rev()
+            .find(|sample| start.is_some_and(|start| sample.at <= start))
+            .or(self.samples.front());
+        if let Some(base) = base {
+            let elapsed = now
+                .duration_since(base.at)
+                .max(Duration::from_secs(1))
+                .as_secs_f64();
+            let rate = |now: u64, then: u64| (now.saturating_sub(then) as f64 / elapsed) as u64;
+            info.current_rate_sent = Some(rate(info.bytes_sent, base.sent));
+            info.current_rate_received = Some(rate(info.bytes_received, base.received));
+        }
+        info
+    }
+}
+
+/// Snapshot of every address in `map`, sorted by address.
+fn traffic_snapshot(map: &StdRwLock<HashMap<String, Arc<TrafficCounters>>>) -> Vec<TrafficStats> {
+    let mut traffic: Vec<_> = map
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(address, counters)| counters.snapshot(address))
+        .collect();
+    traffic.sort_by(|a, b| a.address.cmp(&b.address));
+    traffic
+}
+
+/// Limits and sequence counter of the connection history, shared with the history writer.
+#[derive(Debug)]
+struct HistoryState {
+    max_entries: AtomicUsize,
+    /// Seconds (0 = no age limit).
+    max_age_secs: AtomicU64,
+    /// `seq` of the latest entry, assigned under the history write lock.
+    last_seq: AtomicU64,
+}
+
+impl HistoryState {
+    fn max_entries(&self) -> usize {
+        self.max_entries.load(Ordering::Relaxed)
     }
 
-    /// Get connection history.
-    pub async fn get_history(&self, limit: Option<usize>) -> Vec<ConnectionStats> {
-        let history = self.history.read().await;
-        let limit = limit.unwrap_or(history.len()).min(history.len());
-        history.iter().rev().take(limit).cloned().collect()
+    fn max_age(&self) -> Option<Duration> {
+        match self.max_age_secs.load(Ordering::Relaxed) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
     }
 }
 
+/// Message to the history writer task.
+#[derive(Debug)]
+enum HistoryOp {
+    Record(Box<ConnectionStats>),
+    /// Acknowledged once every earlier record has been applied.
+    Flush(oneshot::Sender<()>),
+}
+
+/// Thread-safe statistics collector.
+///
+/// Connection open/close only takes a short lock on one shard of the active
+/// map; per-user counters are atomics cached on the active entry, and
+/// history appends are batched through a channel to a single writer task.
+#[derive(Debug)]
+pub struct Stats {
+    /// Whether connections, history and per-user counters are recorded.
+    enabled: AtomicBool,
+
+    /// Connections opened while disabled.
+    untracked: Untracked,
+
+    /// Total connections counter.
+    total_connections: AtomicU64,
+
+    /// Proxy requests since start by outcome.
+    attempts: AttemptCounters,
+
+    /// Access decision cache hits and misses.
+    decision_cache_hits: AtomicU64,
+    decision_cache_misses: AtomicU64,
+
+    /// Currently active connections.
+    active_count: AtomicU64,
+
+    /// Total bytes sent.
+    total_bytes_sent: AtomicU64,
+
+    /// Total bytes received.
+    total_bytes_received: AtomicU64,
+
+    /// Server start time.
+    started_at: DateTime<Utc>,
+
+    /// Recent connection history (written by the history task).
+    history: Arc<StdRwLock<VecDeque<ConnectionStats>>>,
+
+    /// Channel to the history writer task (`None` outside a Tokio runtime).
+    history_tx: Option<mpsc::UnboundedSender<HistoryOp>>,
+
+    /// Active connections, sharded by connection id.
+    active: Box<[Mutex<HashMap<uuid::Uuid, ActiveEntry>>]>,
+
+    /// Per-user counters, including IP buckets.
+    user_stats: StdRwLock<HashMap<String, Arc<UserCounters>>>,
+
+    /// Attribution of connections without a username, if enabled.
+    ip_attribution: Option<IpAttribution>,
+
+    /// IP buckets in `user_stats`.
+    ip_buckets: AtomicUsize,
+
+    /// Bounds on `user_stats` and `denied`.
+    limits: StdRwLock<TrackingLimits>,
+
+    /// Entries dropped to stay within `limits`.
+    evictions: Evictions,
+
+    /// Durations of the phases of closed connections, by [`ConnectionPhase`].
+    latency: [LatencyHistogram; 3],
+
+    /// Per-upstream relay counters.
+    upstream_stats: StdRwLock<HashMap<String, Arc<TrafficCounters>>>,
+
+    /// Per-egress address counters.
+    egress_stats: StdRwLock<HashMap<String, Arc<TrafficCounters>>>,
+
+    /// Accept counters by protocol and listener address.
+    listener_stats: StdRwLock<BTreeMap<(String, String), Arc<ListenerCounters>>>,
+
+    /// Recent denied attempts.
+    denied: Arc<RwLock<VecDeque<DeniedAttempt>>>,
+
+    /// Match counters of access rules, by rule id.
+    rule_hits: StdRwLock<HashMap<String, RuleHits>>,
+
+    /// Connect outcomes by target host over the last hour.
+    target_errors: TargetErrors,
+
+    /// Limits and sequence counter of the history.
+    history_state: Arc<HistoryState>,
+
+    /// Access log written on every closed connection.
+    access_log: Option<AccessLog>,
+
+    /// Event log of opened and closed connections, denials and failed logins.
+    event_log: Option<EventLog>,
+
+    /// Publishes [`StatsEvent`]s to subscribers.
+    events: broadcast::Sender<StatsEvent>,
+
+    /// Per-user quota usage.
+    quota: QuotaTracker,
+
+    /// Server-wide bandwidth limiter (for throughput reporting).
+    bandwidth: Arc<BandwidthLimiter>,
+
+    /// Set once the server starts shutting down.
+    shutdown: watch::Sender<bool>,
+
+    /// Window connection rates are averaged over.
+    rate_window: Duration,
+
+    /// Spawned connection tasks (see [`Stats::spawn_connection`]).
+    tasks: TaskTracker,
+
+    /// Connection tasks that panicked.
+    panicked_tasks: AtomicU64,
+
+    /// Copy buffers held by running relays.
+    relay_buffers: RelayBufferMemory,
+
+    /// Debug captures, oldest first, kept after their connection closes.
+    captures: Mutex<VecDeque<Arc<Capture>>>,
+
+    /// Samples and rule states of the `[alerts]`.
+    alerts: AlertEngine,
+}
+
+impl Stats {
+    /// Create a new statistics collector.
+    ///
+    /// When called inside a Tokio runtime, a writer task is spawned for the
+    /// connection history; otherwise history is appended inline.
+    pub fn new(max_history: usize) -> Self {
+        let history = Arc::new(StdRwLock::new(VecDeque::new()));
+        let history_state = Arc::new(HistoryState {
+            max_entries: AtomicUsize::new(max_history),
+            max_age_secs: AtomicU64::new(0),
+            last_seq: AtomicU64::new(0),
+        });
+        let history_tx = tokio::runtime::Handle::try_current().ok().map(|handle| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            handle.spawn(history_writer(
+                Arc::clone(&history),
+                Arc::clone(&history_state),
+                rx,
+            ));
+            tx
+        });
+
+        let started_at = Utc::now();
+        Self {
+            enabled: AtomicBool::new(true),
+            untracked: Untracked::default(),
+            total_connections: AtomicU64::new(0),
+            attempts: AttemptCounters::default(),
+            decision_cache_hits: AtomicU64::new(0),
+            decision_cache_misses: AtomicU64::new(0),
+            active_count: AtomicU64::new(0),
+            total_bytes_sent: AtomicU64::new(0),
+            total_bytes_received: AtomicU64::new(0),
+            started_at,
+            history,
+            history_tx,
+            active: (0..ACTIVE_SHARDS)
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+            user_stats: StdRwLock::new(HashMap::new()),
+            ip_attribution: None,
+            ip_buckets: AtomicUsize::new(0),
+            limits: StdRwLock::new(TrackingLimits {
+                max_users: DEFAULT_MAX_TRACKED_USERS,
+                max_denied: DEFAULT_MAX_DENIED_ATTEMPTS,
+                configured: HashSet::new(),
+            }),
+            evictions: Evictions::default(),
+            latency: Default::default(),
+            upstream_stats: StdRwLock::new(HashMap::new()),
+            egress_stats: StdRwLock::new(HashMap::new()),
+            listener_stats: StdRwLock::new(BTreeMap::new()),
+            denied: Arc::new(RwLock::new(VecDeque::new())),
+            rule_hits: StdRwLock::new(HashMap::new()),
+            target_errors: TargetErrors::default(),
+            history_state,
+            access_log: None,
+            event_log: None,
+            events: broadcast::Sender::new(EVENT_CHANNEL_CAPACITY),
+            quota: QuotaTracker::new(),
+            bandwidth: Arc::new(BandwidthLimiter::default()),
+            shutdown: watch::Sender::new(false),
+            rate_window: DEFAULT_RATE_WINDOW,
+            tasks: TaskTracker::new(),
+            panicked_tasks: AtomicU64::new(0),
+            relay_buffers: RelayBufferMemory::default(),
+            captures: Mutex::default(),
+            alerts: AlertEngine::new(started_at),
+        }
+    }
+
+    /// Drop history entries once they are older than `max_age` (`stats.max_history_age_minutes`).
+    pub fn with_max_history_age(self, max_age: Option<Duration>) -> Self {
+        self.history_state.max_age_secs.store(
+            max_age.map_or(0, |age| age.as_secs().max(1)),
+            Ordering::Relaxed,
+        );
+        self
+    }
+
+    /// Average connection rates over `window` (`stats.rate_window_secs`).
+    pub fn with_rate_window(mut self, window: Duration) -> Self {
+        self.rate_window = window;
+        self
+    }
+
+    /// Report throughput and the cap of the limiter relays draw from.
+    pub fn with_bandwidth_limiter(mut self, bandwidth: Arc<BandwidthLimiter>) -> Self {
+        self.bandwidth = bandwidth;
+        self
+    }
+
+    /// Write an access log line for every closed connection.
+    pub fn with_access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    /// Append connection events to `event_log` (`stats.event_log`).
+    pub fn with_event_log(mut self, event_log: EventLog) -> Self {
+        self.event_log = Some(event_log);
+        self
+    }
+
+    /// Track quota usage with the given (typically persisted) tracker.
+    pub fn with_quota_tracker(mut self, quota: QuotaTracker) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Count connections without a username per client address or prefix.
+    pub fn with_ip_attribution(mut self, attribution: Option<IpAttribution>) -> Self {
+        self.ip_attribution = attribution;
+        self
+    }
+
+    /// Continue counting from the lifetime totals of `checkpoint`.
+    pub fn with_checkpoint(self, checkpoint: &StatsCheckpoint) -> Self {
+        self.total_connections
+            .fetch_add(checkpoint.total_connections, Ordering::Relaxed);
+        self.total_bytes_sent
+            .fetch_add(checkpoint.total_bytes_sent, Ordering::Relaxed);
+        self.total_bytes_received
+            .fetch_add(checkpoint.total_bytes_received, Ordering::Relaxed);
+        for user in &checkpoint.users {
+            let counters = if user.ip_bucket {
+                // Buckets are only kept while attribution is on, within its limit
+                match self.ip_bucket_counters(&user.username) {
+                    Some(counters) => counters,
+                    None => continue,
+                }
+            } else {
+                self.user_counters(&user.username)
+            };
+            counters
+                .total_connections
+                .fetch_add(user.total_connections, Ordering::Relaxed);
+            counters
+                .total_bytes_sent
+                .fetch_add(user.total_bytes_sent, Ordering::Relaxed);
+            counters
+                .total_bytes_received
+                .fetch_add(user.total_bytes_received, Ordering::Relaxed);
+            // Replaces the time the counters were created
+            counters.last_activity_ms.store(
+                user.last_activity
+                    .map_or(0, |last_activity| last_activity.timestamp_millis()),
+                Ordering::Relaxed,
+            );
+        }
+        self
+    }
+
+    /// Lifetime totals to persist with [`StatsCheckpoint::save`].
+    pub fn checkpoint(&self) -> StatsCheckpoint {
+        let mut users: Vec<_> = self
+            .user_stats
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(username, counters)| counters.snapshot(username))
+            .collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+        StatsCheckpoint {
+            saved_at: Utc::now(),
+            total_connections: self.total_connections.load(Ordering::Relaxed),
+            total_bytes_sent: self.total_bytes_sent.load(Ordering::Relaxed),
+            total_bytes_received: self.total_bytes_received.load(Ordering::Relaxed),
+            users,
+        }
+    }
+
+    fn shard(&self, id: uuid::Uuid) -> &Mutex<HashMap<uuid::Uuid, ActiveEntry>> {
+        &self.active[(id.as_u128() % ACTIVE_SHARDS as u128) as usize]
+    }
+
+    /// Get the counters for a user, creating them on first use.
+    ///
+    /// Once `max_users` users have counters, the least recently active ones
+    /// without open connections are evicted first; users in the config are
+    /// always kept.
+    fn user_counters(&self, username: &str) -> Arc<UserCounters> {
+        if let Some(counters) = self.user_stats.read().unwrap().get(username) {
+            return Arc::clone(counters);
+        }
+        let mut users = self.user_stats.write().unwrap();
+        if let Some(counters) = users.get(username) {
+            return Arc::clone(counters);
+        }
+        let limits = self.limits.read().unwrap();
+        let tracked = users.len() - self.ip_buckets.load(Ordering::Relaxed);
+        if tracked >= limits.max_users {
+            let batch = (limits.max_users / USER_EVICTION_FRACTION).max(1);
+            let target = limits.max_users.saturating_sub(batch);
+            self.evict_users(&mut users, &limits, tracked - target);
+        }
+        let counters = Arc::new(UserCounters::default());
+        counters.touch();
+        users.insert(username.to_string(), Arc::clone(&counters));
+        counters
+    }
+
+    /// Drop up to `count` of the least recently active idle users that aren't in the config.
+    fn evict_users(
+        &self,
+        users: &mut HashMap<String, Arc<UserCounters>>,
+        limits: &TrackingLimits,
+        count: usize,
+    ) {
+        let mut idle: Vec<(i64, String)> = users
+            .iter()
+            .filter(|(name, counters)| {
+                !counters.ip_bucket
+                    && counters.active_connections.load(Ordering::Relaxed) == 0
+                    && !limits.configured.contains(*name)
+            })
+            .map(|(name, counters)| {
+                (
+                    counters.last_activity_ms.load(Ordering::Relaxed),
+                    name.clone(),
+                )
+            })
+            .collect();
+        let count = count.min(idle.len());
+        if count == 0 {
+            return;
+        }
+        if count < idle.len() {
+            idle.select_nth_unstable(count);
+        }
+        for (_, name) in &idle[..count] {
+            users.remove(name);
+        }
+        self.evictions
+            .users
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Get the counters of an IP bucket, creating them on first use.
+    ///
+    /// Once `max_buckets` exist, the least recently active bucket without
+    /// open connections is dropped to make room; `None` if attribution is off
+    /// or every bucket is in use.
+    fn ip_bucket_counters(&self, bucket: &str) -> Option<Arc<UserCounters>> {
+        let attribution = self.ip_attribution?;
+        if let Some(counters) = self.user_stats.read().unwrap().get(bucket) {
+            return Some(Arc::clone(counters));
+        }
+        let mut users = self.user_stats.write().unwrap();
+        if let Some(counters) = users.get(bucket) {
+            return Some(Arc::clone(counters));
+        }
+        if self.ip_buckets.load(Ordering::Relaxed) >= attribution.max_buckets {
+            let idle = users
+                .iter()
+                .filter(|(_, counters)| {
+                    counters.ip_bucket && counters.active_connections.load(Ordering::Relaxed) == 0
+                })
+                .min_by_key(|(_, counters)| counters.last_activity_ms.load(Ordering::Relaxed))
+                .map(|(name, _)| name.clone())?;
+            users.remove(&idle);
+            self.ip_buckets.fetch_sub(1, Ordering::Relaxed);
+            self.evictions.ip_buckets.fetch_add(1, Ordering::Relaxed);
+        }
+        let counters = Arc::new(UserCounters {
+            ip_bucket: true,
+            ..UserCounters::default()
+        });
+        // Newest, so not the next bucket evicted before its connection counts
+        counters.touch();
+        users.insert(bucket.to_string(), Arc::clone(&counters));
+        self.ip_buckets.fetch_add(1, Ordering::Relaxed);
+        Some(counters)
+    }
+
+    /// Count a new connection through `address` in `map`, creating its counters on first use.
+    fn open_traffic(
+        map: &StdRwLock<HashMap<String, Arc<TrafficCounters>>>,
+        address: &str,
+    ) -> Arc<TrafficCounters> {
+        let existing = map.read().unwrap().get(address).cloned();
+        let counters = existing.unwrap_or_else(|| {
+            Arc::clone(map.write().unwrap().entry(address.to_string()).or_default())
+        });
+        counters.open();
+        counters
+    }
+
+    /// Record a new connection.
+    ///
+    /// While statistics are disabled only the totals are counted (and, for
+    /// authenticated connections, their user for limits and quotas).
+    pub async fn add_connection(&self, info: ConnectionInfo) {
+        self.attempts.record(AttemptOutcome::Relayed);
+        let _ = TASK_CONNECTIONS.try_with(|ids| ids.borrow_mut().push(info.id));
+        if !self.is_enabled() {
+            self.total_connections.fetch_add(1, Ordering::Relaxed);
+            self.active_count.fetch_add(1, Ordering::Relaxed);
+            self.untracked.open(info.id, info.username.as_deref());
+            return;
+        }
+        if let Some(ref event_log) = self.event_log {
+            event_log.record(&Event::open(&info));
+        }
+        self.publish(|| StatsEvent::Opened {
+            protocol: info.protocol.clone(),
+            user: info.username.clone(),
+        });
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+        self.active_count.fetch_add(1, Ordering::Relaxed);
+
+        // Update per-user stats, or the client's IP bucket without a username
+        let user = match info.username.as_deref() {
+            Some(username) => Some(self.user_counters(username)),
+            None => self
+                .ip_attribution
+                .and_then(|attribution| attribution.bucket(&info.client_addr))
+                .and_then(|bucket| self.ip_bucket_counters(&bucket)),
+        };
+        if let Some(ref counters) = user {
+            counters.total_connections.fetch_add(1, Ordering::Relaxed);
+            counters.active_connections.fetch_add(1, Ordering::Relaxed);
+            counters.touch();
+        }
+        let upstream = info
+            .upstream
+            .as_deref()
+            .map(|address| Self::open_traffic(&self.upstream_stats, address));
+        let egress = info
+            .egress
+            .as_deref()
+            .map(|address| Self::open_traffic(&self.egress_stats, address));
+
+        self.shard(info.id).lock().unwrap().insert(
+            info.id,
+            ActiveEntry {
+                info,
+                user,
+                upstream,
+                egress,
+                kill: Arc::new(Notify::new()),
+                counters: Arc::default(),
+                samples: VecDeque::from([RateSample {
+                    at: Instant::now(),
+                    sent: 0,
+                    received: 0,
+                }]),
+            },
+        );
+    }
+
+    /// Apply `f` to an active connection, if it is still open.
+    fn update_active(&self, id: uuid::Uuid, f: impl FnOnce(&mut ConnectionInfo)) {
+        if !self.is_enabled() {
+            return;
+        }
+        if let Some(entry) = self.shard(id).lock().unwrap().get_mut(&id) {
+            f(&mut entry.info);
+        }
+    }
+
+    /// Counters the relay of an active connection publishes its progress to.
+    pub fn relay_counters(&self, id: uuid::Uuid) -> Arc<RelayCounters> {
+        if !self.is_enabled() {
+            return Arc::default();
+        }
+        self.shard(id)
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|entry| Arc::clone(&entry.counters))
+            .unwrap_or_default()
+    }
+
+    /// Record the current byte counts of a connection for its rates.
+    ///
+    /// Called periodically by the relay; samples older than the rate
+    /// window are dropped except the one marking the window start.
+    pub fn sample_rate(&self, id: uuid::Uuid) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut shard = self.shard(id).lock().unwrap();
+        let Some(entry) = shard.get_mut(&id) else {
+            return;
+        };
+        let now = Instant::now();
+        entry.samples.push_back(RateSample {
+            at: now,
+            sent: entry.counters.sent(),
+            received: entry.counters.received(),
+        });
+        if let Some(start) = now.checked_sub(self.rate_window) {
+            while entry.samples.get(1).is_some_and(|next| next.at <= start) {
+                entry.samples.pop_front();
+            }
+        }
+    }
+
+    /// Mark a connection as active once relaying starts.
+    pub fn mark_active(&self, id: uuid::Uuid) {
+        self.update_active(id, |info| {
+            // Don't undo a shutdown that raced with the relay starting
+            if info.state == ConnectionState::Connecting {
+                info.set_active();
+            }
+        });
+    }
+
+    /// Record when the first byte arrived from the target.
+    pub fn mark_first_byte(&self, id: uuid::Uuid, at: DateTime<Utc>) {
+        self.update_active(id, |info| info.set_first_byte(at));
+    }
+
+    /// Record when the first client byte was relayed to the target.
+    pub fn mark_first_byte_sent(&self, id: uuid::Uuid, at: DateTime<Utc>) {
+        self.update_active(id, |info| info.set_first_byte_sent(at));
+    }
+
+    /// Latency histograms of the handshake, connect and time-to-first-byte
+    /// phases of closed connections.
+    pub fn get_latency(&self) -> Vec<PhaseLatency> {
+        ConnectionPhase::ALL
+            .iter()
+            .zip(&self.latency)
+            .map(|(&phase, histogram)| histogram.snapshot(phase))
+            .collect()
+    }
+
+    /// Record when an active connection reaches its maximum lifetime.
+    pub fn set_expires_at(&self, id: uuid::Uuid, at: DateTime<Utc>) {
+        self.update_active(id, |info| info.expires_at = Some(at));
+    }
+
+    /// Attach the client's reverse-DNS name to an active connection.
+    pub fn set_client_hostname(&self, id: uuid::Uuid, hostname: String) {
+        self.update_active(id, |info| info.client_hostname = Some(hostname));
+    }
+
+    /// Mark every active connection as closing and signal relays to stop.
+    ///
+    /// Relays started afterwards stop immediately; each one still reports
+    /// its close (with [`CloseReason::Shutdown`]) through
+    /// [`close_connection`](Self::close_connection).
+    pub fn begin_shutdown(&self) {
+        self.shutdown.send_replace(true);
+        for shard in self.active.iter() {
+            for entry in shard.lock().unwrap().values_mut() {
+                entry.info.set_closing();
+            }
+        }
+    }
+
+    /// Resolve once [`begin_shutdown`](Self::begin_shutdown) has been called.
+    pub async fn shutdown_requested(&self) {
+        let mut shutdown = self.shutdown.subscribe();
+        let _ = shutdown.wait_for(|stopping| *stopping).await;
+    }
+
+    /// Terminate an active connection. Returns `false` if it isn't open.
+    pub fn kill_connection(&self, id: uuid::Uuid) -> bool {
+        match self.shard(id).lock().unwrap().get_mut(&id) {
+            Some(entry) => {
+                entry.info.set_closing();
+                entry.kill.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Start capturing up to `limit` bytes the relay of active connection
+    /// `id` relays in `direction`.
+    ///
+    /// Replaces an earlier capture of the connection; beyond
+    /// [`MAX_CAPTURES`] the oldest capture is dropped.
+    pub fn start_capture(
+        &self,
+        id: uuid::Uuid,
+        direction: CaptureDirection,
+        limit: usize,
+        started_by: &str,
+    ) -> Result<Arc<Capture>, CaptureError> {
+        let counters = self
+            .shard(id)
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|entry| Arc::clone(&entry.counters))
+            .ok_or(CaptureError::NotActive)?;
+        let capture = Arc::new(Capture::new(id, direction, limit, started_by));
+        if !counters.attach_capture(Arc::clone(&capture)) {
+            return Err(CaptureError::AlreadyCapturing);
+        }
+        let mut captures = self.captures.lock().unwrap();
+        captures.retain(|c| c.connection_id() != id);
+        if captures.len() >= MAX_CAPTURES {
+            captures.pop_front();
+        }
+        captures.push_back(Arc::clone(&capture));
+        Ok(capture)
+    }
+
+    /// The latest capture of connection `id`, running or ended.
+    pub fn capture(&self, id: uuid::Uuid) -> Option<Arc<Capture>> {
+        self.captures
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|c| c.connection_id() == id)
+            .cloned()
+    }
+
+    /// Terminate every active connection from a client IP and return how many.
+    pub fn kill_client_ip(&self, ip: IpAddr) -> usize {
+        let ip = ip.to_canonical();
+        let mut killed = 0;
+        for shard in self.active.iter() {
+            for entry in shard.lock().unwrap().values_mut() {
+                if client_ip(&entry.info.client_addr) == Some(ip)
+                    && entry.info.state != ConnectionState::Closing
+                {
+                    entry.info.set_closing();
+                    entry.kill.notify_one();
+                    killed += 1;
+                }
+            }
+        }
+        killed
+    }
+
+    /// Resolve once the connection is killed; never resolves for unknown ids
+    /// (or while statistics are disabled).
+    pub async fn killed(&self, id: uuid::Uuid) {
+        if !self.is_enabled() {
+            return std::future::pending().await;
+        }
+        let kill = self
+            .shard(id)
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|entry| Arc::clone(&entry.kill));
+        match kill {
+            Some(kill) => kill.notified().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Number of currently active connections.
+    pub fn active_connections(&self) -> u64 {
+        self.active_count.load(Ordering::Relaxed)
+    }
+
+    /// Spawn `task`, serving the client at `client_addr` on the `source`
+    /// listener (e.g. `socks5`), as a connection task.
+    ///
+    /// The task is counted until it ends, handshake included, and
+    /// [`wait_for_tasks`](Self::wait_for_tasks) waits for it. Once
+    /// [`begin_shutdown`](Self::begin_shutdown) is called, a task without an
+    /// open connection (in a handshake or between keep-alive requests) is
+    /// dropped; one that is relaying ends with its relay. A panic in the
+    /// task is logged with the client and its targets, counted in
+    /// [`panicked_tasks`](Self::panicked_tasks), and closes the connections
+    /// the task opened with [`CloseReason::InternalError`].
+    pub fn spawn_connection<F>(
+        self: &Arc<Self>,
+        source: &'static str,
+        client_addr: SocketAddr,
+        task: F,
+    ) where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let stats = Arc::clone(self);
+        self.tasks
+            .spawn(TASK_CONNECTIONS.scope(RefCell::default(), async move {
+                let task = AssertUnwindSafe(task).catch_unwind();
+                tokio::pin!(task);
+                let result = tokio::select! {
+                    result = &mut task => result,
+                    _ = stats.shutdown_requested() => {
+                        if TASK_CONNECTIONS.with(|ids| ids.borrow().is_empty()) {
+                            return;
+                        }
+                        task.await
+                    }
+                };
+                if let Err(panic) = result {
+                    let opened = TASK_CONNECTIONS.with(|ids| ids.take());
+                    stats
+                        .close_panicked(source, client_addr, opened, panic)
+                        .await;
+                }
+            }));
+    }
+
+    /// Log and count a panicked connection task, closing the connections it
+    /// left open.
+    async fn close_panicked(
+        &self,
+        source: &str,
+        client_addr: SocketAddr,
+        opened: Vec<uuid::Uuid>,
+        panic: Box<dyn Any + Send>,
+    ) {
+        self.panicked_tasks.fetch_add(1, Ordering::Relaxed);
+        let message = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("non-string panic payload");
+        let mut targets = Vec::new();
+        for id in opened {
+            let counts = self
+                .shard(id)
+                .lock()
+                .unwrap()
+                .get(&id)
+                .map(|entry| (entry.counters.sent(), entry.counters.received()));
+            let (sent, received) = counts.unwrap_or_default();
+            if let Some(info) = self
+                .close_connection(id, sent, received, CloseReason::InternalError)
+                .await
+            {
+                targets.push(format!("{}:{}", info.target_addr, info.target_port));
+            }
+        }
+        error!(
+            "{} connection task for {} panicked{}: {}",
+            source,
+            client_addr,
+            if targets.is_empty() {
+                String::new()
+            } else {
+                format!(" (target {})", targets.join(", "))
+            },
+            message
+        );
+    }
+
+    /// Wait until every connection task has ended. Tasks spawned meanwhile
+    /// are waited for too.
+    pub async fn wait_for_tasks(&self) {
+        self.tasks.close();
+        self.tasks.wait().await;
+        self.tasks.reopen();
+    }
+
+    /// When statistics collection started, i.e. the server's start.
+    pub fn started_at(&self) -> DateTime<Utc> {
+        self.started_at
+    }
+
+    /// Connections recorded since start.
+    pub fn total_connections(&self) -> u64 {
+        self.total_connections.load(Ordering::Relaxed)
+    }
+
+    /// Proxy requests since start by outcome.
+    pub fn attempts(&self) -> AttemptCounts {
+        self.attempts.snapshot()
+    }
+
+    /// Server-wide throughput in bytes per second.
+    pub fn current_throughput(&self) -> u64 {
+        self.bandwidth.throughput()
+    }
+
+    /// Sample the current values and evaluate the alert rules of `config`,
+    /// appending transitions to the event log.
+    pub fn evaluate_alerts(&self, config: &AlertsConfig) -> Vec<AlertTransition> {
+        let transitions = self
+            .alerts
+            .evaluate(config, AlertSample::take(self), Utc::now());
+        if let Some(ref event_log) = self.event_log {
+            for transition in &transitions {
+                event_log.record(&Event::alert(&transition.status));
+            }
+        }
+        transitions
+    }
+
+    /// State of every alert rule at its latest evaluation.
+    pub fn alerts(&self) -> Vec<AlertStatus> {
+        self.alerts.statuses()
+    }
+
+    /// Number of connection tasks still running.
+    pub fn connection_tasks(&self) -> u64 {
+        self.tasks.len() as u64
+    }
+
+    /// Number of connection tasks that ended in a panic.
+    pub fn panicked_tasks(&self) -> u64 {
+        self.panicked_tasks.load(Ordering::Relaxed)
+    }
+
+    /// Copy buffer memory of running relays, counted whether or not
+    /// statistics are enabled.
+    pub fn relay_buffers(&self) -> &RelayBufferMemory {
+        &self.relay_buffers
+    }
+
+    /// Sizes of the in-memory history, connection and counter maps.
+    pub async fn sizes(&self) -> StatsSizes {
+        let denied = self.denied.read().await.len();
+        StatsSizes {
+            history: self.history.read().unwrap().len(),
+            history_capacity: self.history_state.max_entries(),
+            active: self
+                .active
+                .iter()
+                .map(|shard| shard.lock().unwrap().len())
+                .sum(),
+            users: self.user_stats.read().unwrap().len(),
+            upstreams: self.upstream_stats.read().unwrap().len(),
+            egress: self.egress_stats.read().unwrap().len(),
+            denied,
+            targets: self.target_errors.len(),
+            evicted_users: self.evictions.users.load(Ordering::Relaxed),
+            evicted_ip_buckets: self.evictions.ip_buckets.load(Ordering::Relaxed),
+            evicted_denied: self.evictions.denied.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Update connection bytes.
+    pub fn add_bytes(&self, sent: u64, received: u64) {
+        self.total_bytes_sent.fetch_add(sent, Ordering::Relaxed);
+        self.total_bytes_received
+            .fetch_add(received, Ordering::Relaxed);
+    }
+
+    /// Mark a connection as closed and move to history.
+    ///
+    /// Returns the connection as recorded, or `None` if it wasn't active or
+    /// statistics are disabled.
+    pub async fn close_connection(
+        &self,
+        id: uuid::Uuid,
+        bytes_sent: u64,
+        bytes_received: u64,
+        close_reason: CloseReason,
+    ) -> Option<ConnectionInfo> {
+        let _ = TASK_CONNECTIONS.try_with(|ids| ids.borrow_mut().retain(|opened| *opened != id));
+        // Nothing is tracked while disabled once the connections opened before are gone
+        let tracked = self.is_enabled()
+            || self.active_count.load(Ordering::Relaxed)
+                > self.untracked.active.load(Ordering::Relaxed);
+        let entry = if tracked {
+            self.shard(id).lock().unwrap().remove(&id)
+        } else {
+            None
+        };
+        let Some(ActiveEntry {
+            mut info,
+            user,
+            upstream,
+            egress,
+            counters,
+            ..
+        }) = entry
+        else {
+            let username = self.untracked.close(id)?;
+            self.close_untracked(username.as_deref(), bytes_sent, bytes_received);
+            return None;
+        };
+        if !self.is_enabled() {
+            // Disabled while the connection was being added
+            self.close_untracked(info.username.as_deref(), bytes_sent, bytes_received);
+            return None;
+        }
+        self.active_count.fetch_sub(1, Ordering::Relaxed);
+
+        info.set_closed();
+        info.stale = false;
+        info.last_activity_at = counters.last_activity();
+        if let Some(at) = counters.first_byte_received() {
+            info.set_first_byte(at);
+        }
+        if let Some(at) = counters.first_byte_sent() {
+            info.set_first_byte_sent(at);
+        }
+        for (phase, ms) in [info.handshake_ms, info.connect_ms, info.ttfb_ms]
+            .into_iter()
+            .enumerate()
+        {
+            if let Some(ms) = ms {
+                self.latency[phase].record(ms);
+            }
+        }
+        info.bytes_sent = bytes_sent;
+        info.bytes_received = bytes_received;
+        info.close_reason = Some(close_reason);
+
+        if let Some(ref access_log) = self.access_log {
+            access_log.record(&info);
+        }
+        if let Some(ref event_log) = self.event_log {
+            event_log.record(&Event::close(&info));
+        }
+        self.publish(|| StatsEvent::Closed {
+            protocol: info.protocol.clone(),
+            user: info.username.clone(),
+            bytes_sent,
+            bytes_received,
+            reason: close_reason,
+        });
+
+        self.add_bytes(bytes_sent, bytes_received);
+
+        // Update per-user stats
+        if let Some(counters) = user {
+            counters.active_connections.fetch_sub(1, Ordering::Relaxed);
+            counters
+                .total_bytes_sent
+                .fetch_add(bytes_sent, Ordering::Relaxed);
+            counters
+                .total_bytes_received
+                .fetch_add(bytes_received, Ordering::Relaxed);
+            counters.touch();
+        }
+        for counters in upstream.iter().chain(&egress) {
+            counters.close(bytes_sent, bytes_received);
+        }
+        if let Some(ref username) = info.username {
+            self.quota.add(username, bytes_sent + bytes_received);
+        }
+
+        self.record_history(ConnectionStats {
+            info: info.clone(),
+            seq: 0,
+        });
+        Some(info)
+    }
+
+    /// Count a connection that closed without being tracked.
+    fn close_untracked(&self, username: Option<&str>, bytes_sent: u64, bytes_received: u64) {
+        self.active_count.fetch_sub(1, Ordering::Relaxed);
+        self.add_bytes(bytes_sent, bytes_received);
+        if let Some(username) = username {
+            self.quota.add(username, bytes_sent + bytes_received);
+        }
+    }
+
+    /// Whether connections, history and per-user counters are recorded (`stats.enabled`).
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Turn recording of connections, history and per-user counters on or off.
+    ///
+    /// Turning it off clears what was recorded: the history, per-user,
+    /// upstream and egress counters, denied attempts and per-target connect
+    /// outcomes. Open connections stop being tracked too, so they can no
+    /// longer be listed or killed. Only the server-wide totals, latency
+    /// histograms and rule hits are kept.
+    pub async fn set_enabled(&self, enabled: bool) {
+        if self.enabled.swap(enabled, Ordering::Relaxed) == enabled || enabled {
+            return;
+        }
+        for shard in self.active.iter() {
+            for (id, entry) in shard.lock().unwrap().drain() {
+                self.untracked.open(id, entry.info.username.as_deref());
+            }
+        }
+        self.flush_history().await;
+        self.history.write().unwrap().clear();
+        self.user_stats.write().unwrap().clear();
+        self.ip_buckets.store(0, Ordering::Relaxed);
+        self.upstream_stats.write().unwrap().clear();
+        self.egress_stats.write().unwrap().clear();
+        self.denied.write().await.clear();
+        self.target_errors.clear();
+    }
+
+    /// Queue a closed connection for the history writer.
+    fn record_history(&self, entry: ConnectionStats) {
+        let entry = match &self.history_tx {
+            Some(tx) => match tx.send(HistoryOp::Record(Box::new(entry))) {
+                Ok(()) => return,
+                Err(mpsc::error::SendError(HistoryOp::Record(entry))) => *entry,
+                Err(_) => return,
+            },
+            None => entry,
+        };
+        // No writer task (runtime gone or never present): append inline
+        push_history(
+            &mut self.history.write().unwrap(),
+            &self.history_state,
+            entry,
+        );
+    }
+
+    /// Wait until every queued history entry has been written.
+    async fn flush_history(&self) {
+        if let Some(tx) = &self.history_tx {
+            let (done_tx, done_rx) = oneshot::channel();
+            if tx.send(HistoryOp::Flush(done_tx)).is_ok() {
+                let _ = done_rx.await;
+            }
+        }
+    }
+
+    /// Get aggregated statistics.
+    pub async fn get_aggregated(&self) -> AggregatedStats {
+        AggregatedStats {
+            total_connections: self.total_connections.load(Ordering::Relaxed),
+            active_connections: self.active_count.load(Ordering::Relaxed),
+            total_bytes_sent: self.total_bytes_sent.load(Ordering::Relaxed),
+            total_bytes_received: self.total_bytes_received.load(Ordering::Relaxed),
+            uptime_secs: (Utc::now() - self.started_at).num_seconds(),
+            started_at: self.started_at,
+            users: self.get_user_stats().await,
+            upstreams: self.get_upstream_stats(),
+            egress: self.get_egress_stats(),
+            current_throughput: self.bandwidth.throughput(),
+            global_bandwidth: self.bandwidth.rate(),
+            attempts: self.attempts.snapshot(),
+            decision_cache: DecisionCacheCounts {
+                hits: self.decision_cache_hits.load(Ordering::Relaxed),
+                misses: self.decision_cache_misses.load(Ordering::Relaxed),
+            },
+        }
+    }
+
+    /// Get per-user statistics.
+    pub async fn get_user_stats(&self) -> Vec<UserStats> {
+        self.user_stats
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(username, counters)| counters.snapshot(username))
+            .collect()
+    }
+
+    /// Get per-upstream relay traffic.
+    pub fn get_upstream_stats(&self) -> Vec<TrafficStats> {
+        traffic_snapshot(&self.upstream_stats)
+    }
+
+    /// Get per-egress address traffic.
+    pub fn get_egress_stats(&self) -> Vec<TrafficStats> {
+        traffic_snapshot(&self.egress_stats)
+    }
+
+    /// Accept counters of the `protocol` listener on `address`, created on
+    /// first use (a listener run again on the same address keeps counting).
+    pub fn listener(&self, protocol: &str, address: SocketAddr) -> Arc<ListenerCounters> {
+        let key = (protocol.to_string(), address.to_string());
+        Arc::clone(self.listener_stats.write().unwrap().entry(key).or_default())
+    }
+
+    /// Get accept statistics of every listener, by protocol and address.
+    pub fn get_listener_stats(&self) -> Vec<ListenerStats> {
+        self.listener_stats
+            .read()
+            .unwrap()
+            .iter()
+            .map(|((protocol, address), counters)| counters.snapshot(protocol, address))
+            .collect()
+    }
+
+    /// Get statistics for a specific user.
+    ///
+    /// Connections opened while statistics were disabled count towards
+    /// `active_connections` (for connection limits) until they close.
+    pub async fn get_user(&self, username: &str) -> Option<UserStats> {
+        let mut user = self
+            .user_stats
+            .read()
+            .unwrap()
+            .get(username)
+            .map(|counters| counters.snapshot(username));
+        let untracked = self.untracked.active_for(username);
+        if untracked > 0 {
+            user.get_or_insert_with(|| UserStats {
+                username: username.to_string(),
+                ..UserStats::default()
+            })
+            .active_connections += untracked;
+        }
+        user
+    }
+
+    /// Get active connections, oldest first.
+    pub async fn get_active(&self) -> Vec<ConnectionInfo> {
+        self.collect_active(|_| true)
+    }
+
+    /// Set the tags and/or the note (`None` leaves them unchanged) of an
+    /// active connection, whose history entry keeps them, or of a closed
+    /// one still in the history.
+    ///
+    /// Returns the updated connection, or `None` if the id is unknown.
+    pub async fn annotate_connection(
+        &self,
+        id: uuid::Uuid,
+        tags: Option<Vec<String>>,
+        note: Option<Option<String>>,
+    ) -> Option<ConnectionInfo> {
+        let annotate = |info: &mut ConnectionInfo| {
+            if let Some(tags) = &tags {
+                info.tags = tags.clone();
+            }
+            if let Some(note) = &note {
+                info.note = note.clone();
+            }
+        };
+
+        if let Some(entry) = self.shard(id).lock().unwrap().get_mut(&id) {
+            annotate(&mut entry.info);
+            return Some(entry.snapshot(self.rate_window));
+        }
+
+        // Closed: wait for the history entry of a connection closing right now
+        self.flush_history().await;
+        let mut history = self.history.write().unwrap();
+        let entry = history.iter_mut().rev().find(|entry| entry.info.id == id)?;
+        annotate(&mut entry.info);
+        Some(entry.info.clone())
+    }
+
+    /// Get an active connection by id.
+    pub fn get_connection(&self, id: uuid::Uuid) -> Option<ConnectionInfo> {
+        self.shard(id)
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|entry| entry.snapshot(self.rate_window))
+    }
+
+    /// Snapshot active connections matching `filter`, oldest first.
+    fn collect_active(&self, filter: impl Fn(&ConnectionInfo) -> bool) -> Vec<ConnectionInfo> {
+        let mut active: Vec<ConnectionInfo> = self
+            .active
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .filter(|entry| filter(&entry.info))
+                    .map(|entry| entry.snapshot(self.rate_window))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        active.sort_by_key(|info| info.connected_at);
+        active
+    }
+
+    /// Get a user's quota state, or `None` if the user has no quota.
+    pub fn quota_status(&self, user: &User) -> Option<QuotaStatus> {
+        self.quota.status(user)
+    }
+
+    /// Clear a user's quota usage for the current period and persist it.
+    pub fn reset_quota(&self, user: &User) -> anyhow::Result<()> {
+        self.quota.reset(user);
+        self.quota.save()
+    }
+
+    /// Persist quota usage if it changed.
+    pub fn save_quota(&self) -> anyhow::Result<()> {
+        self.quota.save()
+    }
+
+    /// Record an attempt refused by access control.
+    pub async fn record_denied(&self, attempt: DeniedAttempt) {
+        if let Some(ref event_log) = self.event_log {
+            event_log.record(&Event::denied(&attempt));
+        }
+        if !attempt.would_deny {
+            self.publish(|| StatsEvent::Denied {
+                source: attempt.source.clone(),
+                code: attempt.code,
+            });
+        }
+        if !self.is_enabled() {
+            return;
+        }
+        let max_denied = self.limits.read().unwrap().max_denied;
+        let mut denied = self.denied.write().await;
+        denied.push_back(attempt);
+        self.trim_denied(&mut denied, max_denied);
+    }
+
+    /// Drop the oldest denied attempts beyond `max_denied`.
+    fn trim_denied(&self, denied: &mut VecDeque<DeniedAttempt>, max_denied: usize) {
+        let excess = denied.len().saturating_sub(max_denied);
+        if excess > 0 {
+            denied.drain(..excess);
+            self.evictions
+                .denied
+                .fetch_add(excess as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Record a proxy client turned away with `error`.
+    ///
+    /// Access control denials are kept as [`DeniedAttempt`]s; failed logins
+    /// only go to the event log. Either way the request counts towards
+    /// [`AggregatedStats::attempts`].
+    pub async fn record_rejected(&self, source: &str, client_addr: SocketAddr, error: &Error) {
+        if let Some(outcome) = AttemptOutcome::of_error(error) {
+            self.attempts.record(outcome);
+        }
+        let code = error.code();
+        match code {
+            ErrorCode::ClientIpDenied
+            | ErrorCode::TargetDenied
+            | ErrorCode::SniDenied
+            | ErrorCode::UpstreamDenied
+            | ErrorCode::HookDenied => {
+                let attempt = DeniedAttempt::new(
+                    client_addr.ip().to_canonical().to_string(),
+                    source,
+                    None,
+                    error.to_string(),
+                )
+                .with_code(code);
+                self.record_denied(attempt).await;
+            }
+            ErrorCode::AuthenticationFailed => {
+                let user = match error {
+                    Error::CredentialsRejected { username, .. } => username.as_deref(),
+                    _ => None,
+                };
+                if let Some(ref event_log) = self.event_log {
+                    event_log.record(&Event::AuthFailure {
+                        client_ip: &client_addr.ip().to_canonical().to_string(),
+                        source,
+                        user,
+                        reason: &error.to_string(),
+                    });
+                }
+                self.publish(|| StatsEvent::AuthFailure {
+                    source: source.to_string(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Receive connection, denial and login failure events from now on.
+    ///
+    /// A receiver that falls more than a few thousand events behind misses
+    /// the oldest ones ([`broadcast::error::RecvError::Lagged`]).
+    pub fn subscribe(&self) -> broadcast::Receiver<StatsEvent> {
+        self.events.subscribe()
+    }
+
+    /// Send the event built by `event` if anyone is subscribed.
+    fn publish(&self, event: impl FnOnce() -> StatsEvent) {
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send(event());
+        }
+    }
+
+    /// Get recent denied attempts, newest first.
+    pub async fn get_denied(&self, limit: Option<usize>) -> Vec<DeniedAttempt> {
+        let denied = self.denied.read().await;
+        let limit = limit.unwrap_or(denied.len()).min(denied.len());
+        denied.iter().rev().take(limit).cloned().collect()
+    }
+
+    /// Count a target check answered from (`hit`) or added to the decision cache.
+    pub fn record_decision_cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.decision_cache_hits
+        } else {
+            &self.decision_cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count the rules that matched in a target check.
+    pub fn record_rule_hits(&self, check: &TargetCheck) {
+        let rules = check
+            .rule_id
+            .iter()
+            .map(|id| (id, false))
+            .chain(check.monitored_rule_id.iter().map(|id| (id, true)));
+        let now = Utc::now();
+        let mut hits = self.rule_hits.write().unwrap();
+        for (id, monitored) in rules {
+            let entry = hits.entry(id.clone()).or_default();
+            if monitored {
+                entry.monitored += 1;
+            } else {
+                entry.enforced += 1;
+            }
+            entry.last_hit = Some(now);
+        }
+    }
+
+    /// Match counters of access rules, by rule id.
+    pub fn get_rule_hits(&self) -> HashMap<String, RuleHits> {
+        self.rule_hits.read().unwrap().clone()
+    }
+
+    /// Count how connecting to `host` ended.
+    pub fn record_connect_outcome(&self, host: &str, outcome: ConnectOutcome) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.target_errors.record(host, outcome, Utc::now());
+    }
+
+    /// Targets that failed to connect in the last hour, most failures first.
+    pub fn get_target_errors(&self, limit: Option<usize>) -> Vec<TargetConnectStats> {
+        self.target_errors.failing(Utc::now(), limit)
+    }
+
+    /// Get active connections for a specific user.
+    pub async fn get_active_for_user(&self, username: &str) -> Vec<ConnectionInfo> {
+        self.collect_active(|c| c.username.as_deref() == Some(username))
+    }
+
+    /// Bound the users with counters (`stats.max_tracked_users`) and the
+    /// denied attempts kept (`stats.max_denied_attempts`), trimming both
+    /// right away if the limits shrank. Counters of `configured_users` (the
+    /// users in the config) are never evicted.
+    pub async fn set_tracking_limits(
+        &self,
+        max_users: usize,
+        max_denied: usize,
+        configured_users: HashSet<String>,
+    ) {
+        {
+            let mut limits = self.limits.write().unwrap();
+            limits.max_users = max_users;
+            limits.max_denied = max_denied;
+            limits.configured = configured_users;
+        }
+        {
+            let mut users = self.user_stats.write().unwrap();
+            let limits = self.limits.read().unwrap();
+            let tracked = users.len() - self.ip_buckets.load(Ordering::Relaxed);
+            if tracked > max_users {
+                self.evict_users(&mut users, &limits, tracked - max_users);
+            }
+        }
+        let mut denied = self.denied.write().await;
+        self.trim_denied(&mut denied, max_denied);
+    }
+
+    /// Change the history limits, trimming the history right away if they shrank.
+    pub fn set_history_limits(&self, max_entries: usize, max_age: Option<Duration>) {
+        self.history_state
+            .max_entries
+            .store(max_entries, Ordering::Relaxed);
+        self.history_state.max_age_secs.store(
+            max_age.map_or(0, |age| age.as_secs().max(1)),
+            Ordering::Relaxed,
+        );
+        self.trim_history();
+    }
+
+    /// Close active connections whose relay is gone with reason
+    /// [`CloseReason::Orphaned`], moving them into the history.
+    ///
+    /// A relay holds its connection's live counters while it runs. An entry
+    /// whose relay started but no longer holds them is flagged `stale` by
+    /// one sweep and closed by the next if it is still there, which leaves a
+    /// relay that just ended time to record its own close. Call this
+    /// periodically; returns the number of connections closed.
+    pub async fn sweep_orphans(&self) -> usize {
+        let mut orphaned = Vec::new();
+        for shard in self.active.iter() {
+            for entry in shard.lock().unwrap().values_mut() {
+                let relay_gone =
+                    entry.info.active_at.is_some() && Arc::strong_count(&entry.counters) == 1;
+                if !relay_gone {
+                    entry.info.stale = false;
+                } else if entry.info.stale {
+                    orphaned.push((
+                        entry.info.id,
+                        entry.counters.sent(),
+                        entry.counters.received(),
+                    ));
+                } else {
+                    entry.info.stale = true;
+                }
+            }
+        }
+        let mut closed = 0;
+        for (id, sent, received) in orphaned {
+            if self
+                .close_connection(id, sent, received, CloseReason::Orphaned)
+                .await
+                .is_some()
+            {
+                closed += 1;
+            }
+        }
+        closed
+    }
+
+    /// Active connections suspected dead: flagged `stale` by
+    /// [`sweep_orphans`](Self::sweep_orphans), or relaying but without data
+    /// in either direction for longer than `idle` (when given).
+    pub async fn get_stale(&self, idle: Option<Duration>) -> Vec<ConnectionInfo> {
+        let idle_since = idle
+            .and_then(|idle| chrono::Duration::from_std(idle).ok())
+            .map(|idle| Utc::now() - idle);
+        let mut active = self.get_active().await;
+        active.retain(|info| {
+            let last_activity = info.last_activity_at.or(info.active_at);
+            info.stale
+                || idle_since
+                    .zip(last_activity)
+                    .is_some_and(|(since, at)| at < since)
+        });
+        active
+    }
+
+    /// Drop history entries beyond the size limit or older than the age limit.
+    ///
+    /// Returns the number of entries dropped. The size limit is also applied on
+    /// every insert; the age limit only here, so call this periodically.
+    pub fn trim_history(&self) -> usize {
+        let max_entries = self.history_state.max_entries();
+        let cutoff = self
+            .history_state
+            .max_age()
+            .and_then(|age| chrono::Duration::from_std(age).ok())
+            .map(|age| Utc::now() - age);
+
+        let mut history = self.history.write().unwrap();
+        let before = history.len();
+        if before > max_entries {
+            history.drain(..before - max_entries);
+        }
+        if let Some(cutoff) = cutoff {
+            // Entries are appended as connections close, so the oldest are in front
+            while history
+                .front()
+                .is_some_and(|entry| history_time(entry) < cutoff)
+            {
+                history.pop_front();
+            }
+        }
+        let dropped = before - history.len();
+        if history.capacity() > history.len().max(max_entries) * 2 {
+            history.shrink_to(max_entries);
+        }
+        dropped
+    }
+
+    /// Span and limits of the connection history.
+    pub async fn history_window(&self) -> HistoryWindow {
+        self.flush_history().await;
+        let history = self.history.read().unwrap();
+        HistoryWindow {
+            entries: history.len(),
+            oldest: history.front().map(history_time),
+            max_entries: self.history_state.max_entries(),
+            max_age_secs: self.history_state.max_age().map(|age| age.as_secs()),
+        }
+    }
+
+    /// Get connection history, newest first.
+    ///
+    /// Without `since` this is the latest `limit` entries. With it, only newer
+    /// entries are returned; if more than `limit` match, the oldest of them are
+    /// returned and the cursor points at the last one, so paging continues
+    /// without gaps.
+    pub async fn get_history(&self, limit: Option<usize>, since: HistorySince) -> HistoryPage {
+        self.get_history_matching(limit, since, |_| true).await
+    }
+
+    /// Like [`get_history`](Self::get_history), counting only the entries
+    /// matching `filter`.
+    pub async fn get_history_matching(
+        &self,
+        limit: Option<usize>,
+        since: HistorySince,
+        filter: impl Fn(&ConnectionStats) -> bool,
+    ) -> HistoryPage {
+        self.flush_history().await;
+        let history = self.history.read().unwrap();
+        let latest = self.history_state.last_seq.load(Ordering::Relaxed);
+        let limit = limit.unwrap_or(usize::MAX);
+
+        if since.is_unset() {
+            return HistoryPage {
+                connections: history
+                    .iter()
+                    .rev()
+                    .filter(|entry| filter(entry))
+                    .take(limit)
+                    .cloned()
+                    .collect(),
+                cursor: latest,
+            };
+        }
+
+        let mut newer = history
+            .iter()
+            .filter(|entry| since.includes(entry) && filter(entry));
+        let mut connections: Vec<ConnectionStats> = newer.by_ref().take(limit).cloned().collect();
+        let cursor = match newer.next() {
+            // Stop right before the first entry left out
+            Some(next) => next.seq - 1,
+            None => latest,
+        };
+        connections.reverse();
+        HistoryPage {
+            connections,
+            cursor,
+        }
+    }
+
+    /// Get connection history for a specific user, newest first.
+    pub async fn get_history_for_user(
+        &self,
+        username: &str,
+        limit: Option<usize>,
+    ) -> Vec<ConnectionStats> {
+        self.flush_history().await;
+        let history = self.history.read().unwrap();
+        history
+            .iter()
+            .rev()
+            .filter(|c| c.info.username.as_deref() == Some(username))
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Number and append an entry to the history ring, dropping the oldest entries when full.
+fn push_history(
+    history: &mut VecDeque<ConnectionStats>,
+    state: &HistoryState,
+    mut entry: ConnectionStats,
+) {
+    while history.len() >= state.max_entries().max(1) {
+        history.pop_front();
+    }
+    entry.seq = state.last_seq.fetch_add(1, Ordering::Relaxed) + 1;
+    history.push_back(entry);
+}
+
+/// When a history entry's connection closed.
+fn history_time(entry: &ConnectionStats) -> DateTime<Utc> {
+    entry.info.closed_at.unwrap_or(entry.info.connected_at)
+}
+
+/// Single writer for the connection history, applying queued records in batches.
+async fn history_writer(
+    history: Arc<StdRwLock<VecDeque<ConnectionStats>>>,
+    state: Arc<HistoryState>,
+    mut rx: mpsc::UnboundedReceiver<HistoryOp>,
+) {
+    let mut batch = Vec::with_capacity(HISTORY_BATCH);
+    while rx.recv_many(&mut batch, HISTORY_BATCH).await > 0 {
+        let mut flushed = Vec::new();
+        {
+            let mut history = history.write().unwrap();
+            for op in batch.drain(..) {
+                match op {
+                    HistoryOp::Record(entry) => push_history(&mut history, &state, *entry),
+                    HistoryOp::Flush(done) => flushed.push(done),
+                }
+            }
+        }
+        for done in flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// IP part of a connection's client address (`ip:port` or a bare IP).
+fn client_ip(client_addr: &str) -> Option<IpAddr> {
+    client_addr
+        .parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| client_addr.parse::<IpAddr>())
+        .ok()
+        .map(|ip| ip.to_canonical())
+}
+
 impl Default for Stats {
     fn default() -> Self {
         Self::new(1000)
@@ -0,0 +1,274 @@
+//! StatsD / DogStatsD metrics emitter (`[metrics.statsd]`).
+//!
+//! Events from [`Stats::subscribe`] are added up in memory and sent to the
+//! agent over UDP every `flush_interval_secs`. Sending is fire-and-forget: an
+//! agent that is down only loses metrics, relays never wait on it.
+//!
+//! Metrics (all names start with `prefix.`):
+//!
+//! - `connections.opened` / `connections.closed` (counters, tagged `protocol`,
+//!   closes also `reason`)
+//! - `connections.active` (gauge)
+//! - `bytes.sent` / `bytes.received` (counters, counted when connections close)
+//! - `denials` (counter, tagged `source` and `code`)
+//! - `auth_failures` (counter, tagged `source`)
+//! - `user.bytes.sent` / `user.bytes.received` (counters, tagged `user`;
+//!   DogStatsD only)
+
+use std::collections::{BTreeMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, warn};
+
+use crate::config::StatsdConfig;
+use crate::connection::Protocol;
+use crate::stats::{Stats, StatsEvent};
+
+/// Largest datagram sent, safe for the common 1500 byte MTU.
+const MAX_PACKET_LEN: usize = 1432;
+
+/// `user` tag of users beyond `max_user_tags`.
+const OTHER_USER: &str = "other";
+
+/// Send metrics of `stats` to the agent every flush interval.
+pub async fn run(config: StatsdConfig, stats: Arc<Stats>) {
+    let mut events = stats.subscribe();
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(config.flush_interval_secs.max(1)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut sender = Sender::new(&config);
+    let mut metrics = Metrics::new(config);
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => metrics.record(&event),
+                Err(RecvError::Lagged(missed)) => debug!("StatsD emitter missed {} events", missed),
+                Err(RecvError::Closed) => return,
+            },
+            _ = interval.tick() => {
+                let lines = metrics.flush(stats.active_connections());
+                sender.send(&lines).await;
+            }
+        }
+    }
+}
+
+/// Counters added up between flushes.
+struct Metrics {
+    config: StatsdConfig,
+    /// Counter values by metric name and tags.
+    counters: BTreeMap<(&'static str, Vec<String>), u64>,
+    /// Users with their own `user` tag (at most `max_user_tags`).
+    tagged_users: HashSet<String>,
+}
+
+impl Metrics {
+    fn new(config: StatsdConfig) -> Self {
+        Self {
+            config,
+            counters: BTreeMap::new(),
+            tagged_users: HashSet::new(),
+        }
+    }
+
+    fn count(&mut self, name: &'static str, tags: Vec<String>, value: u64) {
+        *self.counters.entry((name, tags)).or_default() += value;
+    }
+
+    fn record(&mut self, event: &StatsEvent) {
+        match event {
+            StatsEvent::Opened { protocol, .. } => {
+                self.count("connections.opened", vec![protocol_tag(protocol)], 1);
+            }
+            StatsEvent::Closed {
+                protocol,
+                user,
+                bytes_sent,
+                bytes_received,
+                reason,
+            } => {
+                self.count(
+                    "connections.closed",
+                    vec![protocol_tag(protocol), format!("reason:{}", reason)],
+                    1,
+                );
+                self.count("bytes.sent", Vec::new(), *bytes_sent);
+                self.count("bytes.received", Vec::new(), *bytes_received);
+                if let Some(tag) = user.as_deref().and_then(|user| self.user_tag(user)) {
+                    self.count("user.bytes.sent", vec![tag.clone()], *bytes_sent);
+                    self.count("user.bytes.received", vec![tag], *bytes_received);
+                }
+            }
+            StatsEvent::Denied { source, code } => {
+                let code = code.map_or("unknown", |code| code.as_str());
+                self.count(
+                    "denials",
+                    vec![format!("source:{}", source), format!("code:{}", code)],
+                    1,
+                );
+            }
+            StatsEvent::AuthFailure { source } => {
+                self.count("auth_failures", vec![format!("source:{}", source)], 1);
+            }
+        }
+    }
+
+    /// `user` tag of `user`, `None` when per-user metrics are off.
+    fn user_tag(&mut self, user: &str) -> Option<String> {
+        if !self.config.dogstatsd || self.config.max_user_tags == 0 {
+            return None;
+        }
+        if !self.tagged_users.contains(user) && self.tagged_users.len() < self.config.max_user_tags
+        {
+            self.tagged_users.insert(user.to_string());
+        }
+        let user = if self.tagged_users.contains(user) {
+            sanitize_tag_value(user)
+        } else {
+            OTHER_USER.to_string()
+        };
+        Some(format!("user:{}", user))
+    }
+
+    /// Lines for the counters since the last flush and the `active` gauge.
+    fn flush(&mut self, active: u64) -> Vec<String> {
+        let counters = std::mem::take(&mut self.counters);
+        let mut lines: Vec<String> = counters
+            .into_iter()
+            .map(|((name, tags), value)| self.line(name, value, "c", &tags))
+            .collect();
+        lines.push(self.line("connections.active", active, "g", &[]));
+        lines
+    }
+
+    fn line(&self, name: &str, value: u64, kind: &str, tags: &[String]) -> String {
+        let mut line = if self.config.prefix.is_empty() {
+            format!("{}:{}|{}", name, value, kind)
+        } else {
+            format!("{}.{}:{}|{}", self.config.prefix, name, value, kind)
+        };
+        if self.config.dogstatsd {
+            let tags: Vec<&str> = self
+                .config
+                .tags
+                .iter()
+                .chain(tags)
+                .map(String::as_str)
+                .collect();
+            if !tags.is_empty() {
+                line.push_str("|#");
+                line.push_str(&tags.join(","));
+            }
+        }
+        line
+    }
+}
+
+fn protocol_tag(protocol: &Protocol) -> String {
+    format!("protocol:{}", protocol.as_str())
+}
+
+/// Replace characters that would break the DogStatsD line format.
+fn sanitize_tag_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '|' | ',' | '#' | ':' | '\n' | '\r' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// UDP socket to the agent, (re)connected on demand.
+struct Sender {
+    host: String,
+    port: u16,
+    socket: Option<UdpSocket>,
+    /// Last reported failure, so a down agent doesn't flood the log.
+    last_error: Option<String>,
+}
+
+impl Sender {
+    fn new(config: &StatsdConfig) -> Self {
+        Self {
+            host: config.host.clone(),
+            port: config.port,
+            socket: None,
+            last_error: None,
+        }
+    }
+
+    async fn connect(&self) -> std::io::Result<UdpSocket> {
+        let addr = tokio::net::lookup_host((self.host.as_str(), self.port))
+            .await?
+            .next()
+            .ok_or_else(|| std::io::Error::other("host resolved to no addresses"))?;
+        let local: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+        Ok(socket)
+    }
+
+    async fn send(&mut self, lines: &[String]) {
+        if self.socket.is_none() {
+            match self.connect().await {
+                Ok(socket) => self.socket = Some(socket),
+                Err(e) => return self.failed(format!("cannot reach {}: {}", self.host, e)),
+            }
+        }
+        let Some(socket) = &self.socket else {
+            return;
+        };
+
+        let mut result = Ok(());
+        for packet in packets(lines) {
+            if let Err(e) = socket.send(packet.as_bytes()).await {
+                result = Err(e);
+                break;
+            }
+        }
+        match result {
+            Ok(()) => self.last_error = None,
+            Err(e) => {
+                // Resolve again next time in case the agent moved
+                self.socket = None;
+                self.failed(format!("send to {} failed: {}", self.host, e));
+            }
+        }
+    }
+
+    fn failed(&mut self, message: String) {
+        if self.last_error.as_ref() != Some(&message) {
+            warn!("StatsD {}", message);
+            self.last_error = Some(message);
+        }
+    }
+}
+
+/// Join `lines` into datagrams of at most [`MAX_PACKET_LEN`] bytes.
+fn packets(lines: &[String]) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut packet = String::new();
+    for line in lines {
+        if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET_LEN {
+            packets.push(std::mem::take(&mut packet));
+        }
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(line);
+    }
+    if !packet.is_empty() {
+        packets.push(packet);
+    }
+    packets
+}
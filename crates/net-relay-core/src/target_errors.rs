@@ -0,0 +1,231 @@
+//! Connect outcomes per target host over the last hour.
+//!
+//! Both proxy handlers count how connecting to a target ended (see
+//! [`record_connect_outcome`](crate::proxy::connect::record_connect_outcome)),
+//! so a destination that starts refusing or timing out shows up as a rising
+//! error rate instead of being lost among all connections. Counts are kept
+//! in [`SLOT_SECS`] slots covering [`WINDOW_SECS`], so old failures age out.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+
+use crate::error::Error;
+
+/// Length of the window outcomes are counted over.
+pub const WINDOW_SECS: i64 = 3600;
+
+/// Granularity at which outcomes age out of the window.
+pub const SLOT_SECS: i64 = 300;
+
+const SLOTS: usize = (WINDOW_SECS / SLOT_SECS) as usize;
+
+/// Targets tracked at most; the least recently seen one makes room for a new one.
+pub const MAX_TRACKED_TARGETS: usize = 1000;
+
+/// How connecting to a target ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectOutcome {
+    Success,
+    /// The target (or the upstream relay on its behalf) refused the connection.
+    Refused,
+    /// Connecting took longer than `limits.timeout`.
+    Timeout,
+    /// No route to the target, or any other failure reaching it.
+    Unreachable,
+    /// The target's name could not be resolved.
+    DnsFailure,
+}
+
+impl ConnectOutcome {
+    /// Every outcome, successes first.
+    pub const ALL: [ConnectOutcome; 5] = [
+        ConnectOutcome::Success,
+        ConnectOutcome::Refused,
+        ConnectOutcome::Timeout,
+        ConnectOutcome::Unreachable,
+        ConnectOutcome::DnsFailure,
+    ];
+
+    /// Lowercase name used in metric labels.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectOutcome::Success => "success",
+            ConnectOutcome::Refused => "refused",
+            ConnectOutcome::Timeout => "timeout",
+            ConnectOutcome::Unreachable => "unreachable",
+            ConnectOutcome::DnsFailure => "dns_failure",
+        }
+    }
+
+    /// Outcome of a failed connect, `None` for failures that say nothing
+    /// about the target (access denied by the proxy's own rules).
+    pub fn of_error(error: &Error) -> Option<Self> {
+        Some(match error {
+            Error::AccessDenied(..) => return None,
+            Error::ConnectionRefused(_) => ConnectOutcome::Refused,
+            Error::Timeout => ConnectOutcome::Timeout,
+            Error::AddressResolution(_) => ConnectOutcome::DnsFailure,
+            Error::Io(e) => match e.kind() {
+                io::ErrorKind::ConnectionRefused => ConnectOutcome::Refused,
+                io::ErrorKind::TimedOut => ConnectOutcome::Timeout,
+                _ => ConnectOutcome::Unreachable,
+            },
+            _ => ConnectOutcome::Unreachable,
+        })
+    }
+}
+
+/// Connect outcomes of one target host in the last hour.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TargetConnectStats {
+    pub host: String,
+    /// Connects attempted (all outcomes together).
+    pub attempts: u64,
+    pub success: u64,
+    pub refused: u64,
+    pub timeout: u64,
+    pub unreachable: u64,
+    pub dns_failure: u64,
+    /// Failed share of `attempts`, from 0 to 1.
+    pub error_rate: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_failure_at: Option<DateTime<Utc>>,
+}
+
+impl TargetConnectStats {
+    /// Connects that did not succeed.
+    pub fn failures(&self) -> u64 {
+        self.attempts - self.success
+    }
+
+    /// Count of `outcome`.
+    pub fn count(&self, outcome: ConnectOutcome) -> u64 {
+        match outcome {
+            ConnectOutcome::Success => self.success,
+            ConnectOutcome::Refused => self.refused,
+            ConnectOutcome::Timeout => self.timeout,
+            ConnectOutcome::Unreachable => self.unreachable,
+            ConnectOutcome::DnsFailure => self.dns_failure,
+        }
+    }
+}
+
+/// Counts of one target, one slot per [`SLOT_SECS`].
+#[derive(Debug, Default)]
+struct TargetCounters {
+    /// Slot number (seconds since the epoch / `SLOT_SECS`) each entry counts.
+    slots: [i64; SLOTS],
+    counts: [[u64; ConnectOutcome::ALL.len()]; SLOTS],
+    last_seen: i64,
+    last_failure_at: Option<DateTime<Utc>>,
+}
+
+impl TargetCounters {
+    fn record(&mut self, outcome: ConnectOutcome, now: DateTime<Utc>) {
+        let slot = now.timestamp().div_euclid(SLOT_SECS);
+        let index = slot.rem_euclid(SLOTS as i64) as usize;
+        if self.slots[index] != slot {
+            self.slots[index] = slot;
+            self.counts[index] = Default::default();
+        }
+        let kind = ConnectOutcome::ALL
+            .iter()
+            .position(|o| *o == outcome)
+            .expect("every outcome is listed");
+        self.counts[index][kind] += 1;
+        self.last_seen = slot;
+        if outcome != ConnectOutcome::Success {
+            self.last_failure_at = Some(now);
+        }
+    }
+
+    /// Counts of the slots still in the window at `now`.
+    fn snapshot(&self, host: &str, now: DateTime<Utc>) -> TargetConnectStats {
+        let current = now.timestamp().div_euclid(SLOT_SECS);
+        let mut totals = [0u64; ConnectOutcome::ALL.len()];
+        for (slot, counts) in self.slots.iter().zip(&self.counts) {
+            if current - slot < SLOTS as i64 {
+                for (total, count) in totals.iter_mut().zip(counts) {
+                    *total += count;
+                }
+            }
+        }
+        let attempts: u64 = totals.iter().sum();
+        let [success, refused, timeout, unreachable, dns_failure] = totals;
+        TargetConnectStats {
+            host: host.to_string(),
+            attempts,
+            success,
+            refused,
+            timeout,
+            unreachable,
+            dns_failure,
+            error_rate: (attempts - success) as f64 / attempts.max(1) as f64,
+            last_failure_at: self.last_failure_at,
+        }
+    }
+}
+
+/// Connect outcomes by target host, bounded to [`MAX_TRACKED_TARGETS`].
+#[derive(Debug, Default)]
+pub struct TargetErrors {
+    targets: Mutex<HashMap<String, TargetCounters>>,
+}
+
+impl TargetErrors {
+    /// Count a connect to `host` that ended with `outcome` at `now`.
+    pub fn record(&self, host: &str, outcome: ConnectOutcome, now: DateTime<Utc>) {
+        let host = host.to_ascii_lowercase();
+        let mut targets = self.targets.lock().unwrap();
+        if !targets.contains_key(&host) && targets.len() >= MAX_TRACKED_TARGETS {
+            let oldest = targets
+                .iter()
+                .min_by_key(|(_, counters)| counters.last_seen)
+                .map(|(host, _)| host.clone());
+            if let Some(oldest) = oldest {
+                targets.remove(&oldest);
+            }
+        }
+        targets.entry(host).or_default().record(outcome, now);
+    }
+
+    /// Targets with failures in the window at `now`, most failures first,
+    /// at most `limit` of them.
+    pub fn failing(&self, now: DateTime<Utc>, limit: Option<usize>) -> Vec<TargetConnectStats> {
+        let mut failing: Vec<_> = {
+            let mut targets = self.targets.lock().unwrap();
+            let current = now.timestamp().div_euclid(SLOT_SECS);
+            targets.retain(|_, counters| current - counters.last_seen < SLOTS as i64);
+            targets
+                .iter()
+                .map(|(host, counters)| counters.snapshot(host, now))
+                .filter(|stats| stats.failures() > 0)
+                .collect()
+        };
+        failing.sort_by(|a, b| {
+            b.failures()
+                .cmp(&a.failures())
+                .then_with(|| a.host.cmp(&b.host))
+        });
+        failing.truncate(limit.unwrap_or(usize::MAX));
+        failing
+    }
+
+    /// Forget every target.
+    pub fn clear(&self) {
+        self.targets.lock().unwrap().clear();
+    }
+
+    /// Targets currently tracked.
+    pub fn len(&self) -> usize {
+        self.targets.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,74 @@
+//! Tracing spans for connection lifecycles.
+//!
+//! Every proxied connection runs in a `connection` span with child spans for
+//! its phases: `auth`, `resolve`, `connect` and `relay`. All of them use
+//! [`SPAN_TARGET`], which console and file logging leave out; they exist for
+//! the OpenTelemetry exporter (`[telemetry]`, `telemetry` build feature of
+//! the server). Without a subscriber interested in them they cost nothing.
+
+use std::net::SocketAddr;
+use tracing::field::Empty;
+use tracing::{info_span, Span};
+
+use crate::connection::{CloseReason, Protocol};
+use crate::error::Error;
+
+/// Target of all connection spans.
+pub const SPAN_TARGET: &str = "net_relay::connection";
+
+/// Root span of a client connection.
+///
+/// `user`, `target`, `upstream`, the byte counts and the close reason are
+/// filled in as the connection progresses.
+pub fn connection_span(protocol: Protocol, client_addr: SocketAddr) -> Span {
+    info_span!(
+        target: SPAN_TARGET,
+        "connection",
+        otel.kind = "server",
+        protocol = protocol.as_str(),
+        client = %client_addr,
+        user = Empty,
+        target = Empty,
+        upstream = Empty,
+        bytes_sent = Empty,
+        bytes_received = Empty,
+        close_reason = Empty,
+        error.code = Empty,
+        otel.status_code = Empty,
+        otel.status_message = Empty,
+    )
+}
+
+/// Span of the credential check.
+pub fn auth_span() -> Span {
+    info_span!(target: SPAN_TARGET, "auth")
+}
+
+/// Span of the DNS resolution of `host`.
+pub fn resolve_span(host: &str) -> Span {
+    info_span!(target: SPAN_TARGET, "resolve", host = host)
+}
+
+/// Span of the connection to the target, directly or through `upstream`.
+pub fn connect_span(upstream: Option<&str>) -> Span {
+    info_span!(target: SPAN_TARGET, "connect", upstream = upstream, peer = Empty)
+}
+
+/// Span of the data relay.
+pub fn relay_span() -> Span {
+    info_span!(target: SPAN_TARGET, "relay")
+}
+
+/// Record the outcome of a relayed connection on its `span`.
+pub fn record_close(span: &Span, bytes_sent: u64, bytes_received: u64, reason: CloseReason) {
+    span.record("bytes_sent", bytes_sent);
+    span.record("bytes_received", bytes_received);
+    span.record("close_reason", tracing::field::display(reason));
+}
+
+/// Mark `span` as failed with `error`.
+pub fn record_error(span: &Span, error: &Error) {
+    span.record("error.code", error.code().as_str());
+    span.record("otel.status_code", "ERROR");
+    span.record("otel.status_message", tracing::field::display(error));
+}
@@ -0,0 +1,562 @@
+//! TLS configuration helpers.
+
+use arc_swap::{ArcSwap, ArcSwapOption};
+use chrono::{DateTime, Utc};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::error::{Error, Result};
+
+/// ALPN protocol of TLS-ALPN-01 validation handshakes (RFC 8737).
+pub const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+/// Client configs keyed by CA file, so the file is read only once.
+static CLIENT_CONFIGS: OnceLock<Mutex<HashMap<Option<String>, Arc<ClientConfig>>>> =
+    OnceLock::new();
+
+/// Load all certificates from a PEM file.
+pub fn load_certs(path: impl AsRef<Path>) -> Result<Vec<CertificateDer<'static>>> {
+    let path = path.as_ref();
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| Error::Config(format!("Failed to read certificates {:?}: {}", path, e)))?;
+    if certs.is_empty() {
+        return Err(Error::Config(format!(
+            "No certificates found in {:?}",
+            path
+        )));
+    }
+    Ok(certs)
+}
+
+/// Load the first private key from a PEM file.
+pub fn load_private_key(path: impl AsRef<Path>) -> Result<PrivateKeyDer<'static>> {
+    let path = path.as_ref();
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| Error::Config(format!("Failed to read private key {:?}: {}", path, e)))
+}
+
+/// Build a TLS server config from PEM certificate chain and key files.
+pub fn server_config(
+    cert_path: impl AsRef<Path>,
+    key_path: impl AsRef<Path>,
+) -> Result<Arc<ServerConfig>> {
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| Error::Config(format!("Invalid TLS certificate or key: {}", e)))?;
+    Ok(Arc::new(config))
+}
+
+/// Get a TLS client config trusting `ca_file` (or the public web PKI roots).
+pub fn client_config(ca_file: Option<&str>) -> Result<Arc<ClientConfig>> {
+    let cache = CLIENT_CONFIGS.get_or_init(Default::default);
+    let key = ca_file.map(str::to_string);
+    if let Some(config) = cache.lock().unwrap().get(&key) {
+        return Ok(Arc::clone(config));
+    }
+
+    let mut roots = RootCertStore::empty();
+    match ca_file {
+        Some(path) => {
+            for cert in load_certs(path)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| Error::Config(format!("Invalid CA certificate: {}", e)))?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+
+    let config = Arc::new(
+        ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    );
+    cache.lock().unwrap().insert(key, Arc::clone(&config));
+    Ok(config)
+}
+
+/// Expiry of a DER certificate.
+pub fn not_after(cert: &CertificateDer<'_>) -> Result<DateTime<Utc>> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert)
+        .map_err(|e| Error::Config(format!("Invalid certificate: {}", e)))?;
+    DateTime::from_timestamp(parsed.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| Error::Config("Certificate expiry out of range".into()))
+}
+
+/// Verifier for client certificates issued by the CAs in `ca_path`.
+///
+/// Unless `required`, clients may still connect without a certificate.
+pub fn client_verifier(
+    ca_path: impl AsRef<Path>,
+    required: bool,
+) -> Result<Arc<dyn ClientCertVerifier>> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca_path)? {
+        roots
+            .add(cert)
+            .map_err(|e| Error::Config(format!("Invalid client CA certificate: {}", e)))?;
+    }
+    let builder = WebPkiClientVerifier::builder(Arc::new(roots));
+    let builder = if required {
+        builder
+    } else {
+        builder.allow_unauthenticated()
+    };
+    builder
+        .build()
+        .map_err(|e| Error::Config(format!("Invalid client CA: {}", e)))
+}
+
+/// Identity presented in a verified client certificate.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientIdentity {
+    /// Subject common name.
+    pub common_name: Option<String>,
+    /// DNS, email and URI subject alternative names.
+    pub alt_names: Vec<String>,
+}
+
+impl ClientIdentity {
+    /// Read the identity from a DER certificate.
+    pub fn from_der(cert: &CertificateDer<'_>) -> Option<Self> {
+        use x509_parser::extensions::GeneralName;
+
+        let (_, parsed) = x509_parser::parse_x509_certificate(cert).ok()?;
+        let common_name = parsed
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string);
+        let alt_names = match parsed.subject_alternative_name() {
+            Ok(Some(ext)) => ext
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(n) | GeneralName::RFC822Name(n) | GeneralName::URI(n) => {
+                        Some(n.to_string())
+                    }
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Some(Self {
+            common_name,
+            alt_names,
+        })
+    }
+
+    /// Common name, or the first alternative name when there is none.
+    pub fn name(&self) -> &str {
+        self.common_name
+            .as_deref()
+            .or(self.alt_names.first().map(String::as_str))
+            .unwrap_or("-")
+    }
+}
+
+/// Pair a certificate chain with its private key, checking that they match.
+pub fn certified_key(
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<Arc<CertifiedKey>> {
+    let provider = rustls::crypto::ring::default_provider();
+    CertifiedKey::from_der(certs, key, &provider)
+        .map(Arc::new)
+        .map_err(|e| Error::Config(format!("Invalid TLS certificate or key: {}", e)))
+}
+
+/// State of a certificate loaded from files, reported by `/api/health`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CertStatus {
+    /// Listener presenting the certificate (`api` or `tunnel`).
+    pub listener: String,
+    pub cert_path: String,
+    pub not_after: DateTime<Utc>,
+    /// When the certificate in use was read.
+    pub loaded_at: DateTime<Utc>,
+    /// Most recent reload failure, cleared by the next success. The
+    /// previously loaded certificate stays in use meanwhile.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+impl CertStatus {
+    /// Whether the certificate expires within `days` of `now`.
+    pub fn expires_within(&self, days: u32, now: DateTime<Utc>) -> bool {
+        self.not_after - now <= chrono::Duration::days(days.into())
+    }
+}
+
+/// A certificate chain and private key read from PEM files.
+///
+/// Listeners resolve the certificate on every handshake, so [`reload`]
+/// takes effect for new connections without restarting them.
+///
+/// [`reload`]: FileCert::reload
+#[derive(Debug)]
+pub struct FileCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: ArcSwap<CertifiedKey>,
+    status: Mutex<CertStatus>,
+    /// Modification times of the certificate and key files when last read.
+    modified: Mutex<(Option<SystemTime>, Option<SystemTime>)>,
+}
+
+impl FileCert {
+    /// Load the certificate `listener` presents.
+    pub fn load(
+        listener: &str,
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let (cert_path, key_path) = (cert_path.as_ref(), key_path.as_ref());
+        let modified = (modified(cert_path), modified(key_path));
+        let (key, expires) = read_cert(cert_path, key_path)?;
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            current: ArcSwap::new(key),
+            status: Mutex::new(CertStatus {
+                listener: listener.to_string(),
+                cert_path: cert_path.display().to_string(),
+                not_after: expires,
+                loaded_at: Utc::now(),
+                last_error: None,
+                last_error_at: None,
+            }),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    /// Read the files again and serve the new certificate.
+    ///
+    /// On failure the certificate in use is kept and the error is recorded
+    /// in the status.
+    pub fn reload(&self) -> Result<()> {
+        let modified = (modified(&self.cert_path), modified(&self.key_path));
+        *self.modified.lock().unwrap() = modified;
+        let mut status = self.status.lock().unwrap();
+        match read_cert(&self.cert_path, &self.key_path) {
+            Ok((key, expires)) => {
+                self.current.store(key);
+                status.not_after = expires;
+                status.loaded_at = Utc::now();
+                status.last_error = None;
+                status.last_error_at = None;
+                Ok(())
+            }
+            Err(e) => {
+                status.last_error = Some(e.to_string());
+                status.last_error_at = Some(Utc::now());
+                Err(e)
+            }
+        }
+    }
+
+    /// Whether either file changed since it was last read.
+    pub fn changed(&self) -> bool {
+        *self.modified.lock().unwrap() != (modified(&self.cert_path), modified(&self.key_path))
+    }
+
+    /// The certificate in use.
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        self.current.load_full()
+    }
+
+    /// Snapshot of the certificate state.
+    pub fn status(&self) -> CertStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// TLS server config presenting this certificate.
+    pub fn server_config(self: &Arc<Self>) -> Arc<ServerConfig> {
+        Arc::new(
+            ServerConfig::builder()
+                .with_no_client_auth()
+                .with_cert_resolver(Arc::clone(self) as Arc<dyn ResolvesServerCert>),
+        )
+    }
+}
+
+impl ResolvesServerCert for FileCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+/// Read and pair a certificate chain and key, with the leaf's expiry.
+fn read_cert(cert_path: &Path, key_path: &Path) -> Result<(Arc<CertifiedKey>, DateTime<Utc>)> {
+    let chain = load_certs(cert_path)?;
+    let expires = not_after(&chain[0])?;
+    Ok((certified_key(chain, load_private_key(key_path)?)?, expires))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Certificates loaded from files by all TLS listeners.
+///
+/// Reloading through the store (`POST /api/tls/reload`, or [`watch`] when
+/// the files change) swaps the certificate of every listener at once.
+///
+/// [`watch`]: CertStore::watch
+#[derive(Debug, Default)]
+pub struct CertStore {
+    certs: Mutex<Vec<Arc<FileCert>>>,
+}
+
+impl CertStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track `cert` for reloads.
+    pub fn add(&self, cert: Arc<FileCert>) {
+        self.certs.lock().unwrap().push(cert);
+    }
+
+    /// Reload every certificate, returning their state afterwards.
+    pub fn reload(&self) -> Vec<CertStatus> {
+        self.certs()
+            .iter()
+            .map(|cert| {
+                if let Err(e) = cert.reload() {
+                    warn!(
+                        "Keeping the previous {} certificate: {}",
+                        cert.status().listener,
+                        e
+                    );
+                }
+                cert.status()
+            })
+            .collect()
+    }
+
+    /// Reload the certificates whose files changed, returning how many did.
+    pub fn reload_changed(&self) -> usize {
+        let changed: Vec<_> = self.certs().into_iter().filter(|c| c.changed()).collect();
+        for cert in &changed {
+            let listener = cert.status().listener;
+            match cert.reload() {
+                Ok(()) => info!("Reloaded the {} TLS certificate", listener),
+                Err(e) => warn!("Keeping the previous {} certificate: {}", listener, e),
+            }
+        }
+        changed.len()
+    }
+
+    /// State of every certificate.
+    pub fn statuses(&self) -> Vec<CertStatus> {
+        self.certs().iter().map(|cert| cert.status()).collect()
+    }
+
+    /// Check the files every `interval` and reload the ones that changed.
+    pub async fn watch(self: Arc<Self>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            self.reload_changed();
+        }
+    }
+
+    fn certs(&self) -> Vec<Arc<FileCert>> {
+        self.certs.lock().unwrap().clone()
+    }
+}
+
+/// Where the API/Dashboard certificate comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CertSource {
+    /// `server.api_tls_cert` and `server.api_tls_key`.
+    File,
+    /// Issued and renewed through ACME.
+    Acme,
+}
+
+/// API/Dashboard certificate state reported by `/api/health`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DashboardTlsStatus {
+    pub source: CertSource,
+    /// Whether a certificate is loaded (ACME may still be issuing the first one).
+    pub ready: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issued_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_renewal: Option<DateTime<Utc>>,
+    /// Most recent issuance failure, cleared by the next success.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+/// Certificates served by the API/Dashboard listener.
+///
+/// The active certificate can be replaced at runtime, and pending ACME
+/// challenge responses live here so the listener can answer them.
+#[derive(Debug)]
+pub struct DashboardCerts {
+    current: ArcSwapOption<CertifiedKey>,
+    /// Certificate files served instead of `current`, for [`CertSource::File`].
+    files: Option<Arc<FileCert>>,
+    status: Mutex<DashboardTlsStatus>,
+    /// http-01 tokens to key authorizations.
+    http_challenges: Mutex<HashMap<String, String>>,
+    /// tls-alpn-01 validation certificates by server name.
+    alpn_challenges: Mutex<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl DashboardCerts {
+    fn new(source: CertSource, domain: Option<String>) -> Self {
+        Self {
+            current: ArcSwapOption::empty(),
+            files: None,
+            status: Mutex::new(DashboardTlsStatus {
+                source,
+                ready: false,
+                domain,
+                not_after: None,
+                issued_at: None,
+                next_renewal: None,
+                last_error: None,
+                last_error_at: None,
+            }),
+            http_challenges: Mutex::new(HashMap::new()),
+            alpn_challenges: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Serve a certificate chain and key from PEM files, reloaded through
+    /// the [`FileCert`] returned by [`file_cert`](Self::file_cert).
+    pub fn from_files(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> Result<Self> {
+        let mut certs = Self::new(CertSource::File, None);
+        let files = FileCert::load("api", cert_path, key_path)?;
+        certs.update_status(|status| status.ready = true);
+        certs.files = Some(Arc::new(files));
+        Ok(certs)
+    }
+
+    /// The certificate files served, for [`CertSource::File`].
+    pub fn file_cert(&self) -> Option<&Arc<FileCert>> {
+        self.files.as_ref()
+    }
+
+    /// Start without a certificate; ACME installs one once issued.
+    pub fn for_acme(domain: &str) -> Self {
+        Self::new(CertSource::Acme, Some(domain.to_string()))
+    }
+
+    /// Replace the served certificate.
+    pub fn set_certificate(&self, key: Arc<CertifiedKey>, not_after: Option<DateTime<Utc>>) {
+        self.current.store(Some(key));
+        self.update_status(|status| {
+            status.ready = true;
+            status.not_after = not_after;
+        });
+    }
+
+    /// Serve `key` until a real certificate is installed, without reporting it as ready.
+    pub fn set_placeholder(&self, key: Arc<CertifiedKey>) {
+        self.current.store(Some(key));
+    }
+
+    /// Snapshot of the certificate state.
+    pub fn status(&self) -> DashboardTlsStatus {
+        let mut status = self.status.lock().unwrap().clone();
+        if let Some(files) = &self.files {
+            let loaded = files.status();
+            status.not_after = Some(loaded.not_after);
+            status.last_error = loaded.last_error;
+            status.last_error_at = loaded.last_error_at;
+        }
+        status
+    }
+
+    /// Modify the reported certificate state.
+    pub fn update_status(&self, f: impl FnOnce(&mut DashboardTlsStatus)) {
+        f(&mut self.status.lock().unwrap());
+    }
+
+    /// TLS server config resolving certificates through `self`, verifying
+    /// client certificates with `client_verifier` when given.
+    pub fn server_config(
+        self: &Arc<Self>,
+        client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+    ) -> Arc<ServerConfig> {
+        let builder = ServerConfig::builder();
+        let builder = match client_verifier {
+            Some(verifier) => builder.with_client_cert_verifier(verifier),
+            None => builder.with_no_client_auth(),
+        };
+        let mut config =
+            builder.with_cert_resolver(Arc::clone(self) as Arc<dyn ResolvesServerCert>);
+        config.alpn_protocols = vec![b"http/1.1".to_vec(), ACME_TLS_ALPN.to_vec()];
+        Arc::new(config)
+    }
+
+    /// Key authorization to serve for an http-01 `token`.
+    pub fn http_challenge(&self, token: &str) -> Option<String> {
+        self.http_challenges.lock().unwrap().get(token).cloned()
+    }
+
+    /// Publish (`Some`) or withdraw (`None`) an http-01 response.
+    pub fn set_http_challenge(&self, token: &str, key_authorization: Option<String>) {
+        let mut challenges = self.http_challenges.lock().unwrap();
+        match key_authorization {
+            Some(value) => challenges.insert(token.to_string(), value),
+            None => challenges.remove(token),
+        };
+    }
+
+    /// Publish (`Some`) or withdraw (`None`) a tls-alpn-01 validation certificate.
+    pub fn set_alpn_challenge(&self, domain: &str, cert: Option<Arc<CertifiedKey>>) {
+        let mut challenges = self.alpn_challenges.lock().unwrap();
+        match cert {
+            Some(cert) => challenges.insert(domain.to_string(), cert),
+            None => challenges.remove(domain),
+        };
+    }
+}
+
+impl ResolvesServerCert for DashboardCerts {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let validation = client_hello
+            .alpn()
+            .is_some_and(|mut protocols| protocols.any(|p| p == ACME_TLS_ALPN));
+        if validation {
+            let name = client_hello.server_name()?;
+            return self.alpn_challenges.lock().unwrap().get(name).cloned();
+        }
+        match &self.files {
+            Some(files) => Some(files.current()),
+            None => self.current.load_full(),
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! Upstream relay failover.
+//!
+//! [`UpstreamPool`] tracks the health of every relay listed in `[upstream]`.
+//! [`run_health_checks`] probes each relay on `upstream.health_check_interval`;
+//! new tunnels go through the most preferred healthy relay, failing over when
+//! it goes down and back once it recovers.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tracing::{info, warn};
+
+use crate::config::{ConfigManager, UpstreamConfig};
+use crate::connection::Protocol;
+use crate::error::{Error, Result};
+use crate::proxy::connect::ConnectRequest;
+use crate::proxy::tunnel;
+
+/// Maximum number of failover events kept in memory.
+const MAX_FAILOVER_EVENTS: usize = 100;
+
+/// Health of one upstream relay.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamStatus {
+    /// Tunnel address (host:port).
+    pub address: String,
+
+    /// Priority (lower is preferred).
+    pub priority: u32,
+
+    /// Whether the relay passed its last check (relays start out healthy).
+    pub healthy: bool,
+
+    /// Whether new connections currently use this relay.
+    pub active: bool,
+
+    /// When the relay was last checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_check: Option<DateTime<Utc>>,
+
+    /// Duration of the last successful check in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+
+    /// Error from the last failed check or tunnel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+
+    /// Failures since the relay was last healthy.
+    pub consecutive_failures: u32,
+}
+
+/// A change of the relay new connections use.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailoverEvent {
+    /// When the switch happened.
+    pub time: DateTime<Utc>,
+
+    /// Relay used before (`None` when every relay was down).
+    pub from: Option<String>,
+
+    /// Relay used afterwards (`None` when every relay is down).
+    pub to: Option<String>,
+
+    /// What triggered the switch.
+    pub reason: String,
+}
+
+#[derive(Debug)]
+struct Relay {
+    config: UpstreamConfig,
+    status: UpstreamStatus,
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    /// Relays, most preferred first.
+    relays: Vec<Relay>,
+    /// Address of the relay new connections use.
+    active: Option<String>,
+    /// Recent switches, oldest first.
+    events: VecDeque<FailoverEvent>,
+}
+
+impl PoolState {
+    fn relay_mut(&mut self, address: &str) -> Option<&mut Relay> {
+        self.relays
+            .iter_mut()
+            .find(|relay| relay.config.address == address)
+    }
+
+    /// Pick the most preferred healthy relay, recording the switch if it changed.
+    fn select(&mut self, reason: String) {
+        let active = self
+            .relays
+            .iter()
+            .find(|relay| relay.status.healthy)
+            .map(|relay| relay.config.address.clone());
+        if active == self.active {
+            return;
+        }
+        let from = std::mem::replace(&mut self.active, active.clone());
+
+        let rank = |address: &Option<String>| {
+            address.as_ref().and_then(|address| {
+                self.relays
+                    .iter()
+                    .position(|relay| &relay.config.address == address)
+            })
+        };
+        let (from_rank, to_rank) = (rank(&from), rank(&active));
+        match (&from, &active) {
+            (_, None) => warn!("All upstream relays are down ({})", reason),
+            (Some(from), Some(to)) if from_rank.is_some_and(|f| to_rank > Some(f)) => {
+                warn!("Upstream failover from {} to {} ({})", from, to, reason)
+            }
+            (_, Some(to)) => info!("Upstream relay {} now in use ({})", to, reason),
+        }
+
+        if self.events.len() >= MAX_FAILOVER_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(FailoverEvent {
+            time: Utc::now(),
+            from,
+            to: active,
+            reason,
+        });
+    }
+}
+
+/// Health and selection state of the configured upstream relays.
+#[derive(Debug, Default)]
+pub struct UpstreamPool {
+    state: Mutex<PoolState>,
+}
+
+impl UpstreamPool {
+    /// Track the relays of `config`, keeping the health of relays already known.
+    pub fn sync(&self, config: &UpstreamConfig) {
+        let endpoints = if config.enabled {
+            config.endpoints()
+        } else {
+            Vec::new()
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let mut previous = std::mem::take(&mut state.relays);
+        let starting = previous.is_empty();
+        state.relays = endpoints
+            .into_iter()
+            .map(|config| {
+                let known = previous
+                    .iter()
+                    .position(|relay| relay.config.address == config.address);
+                let mut status = match known {
+                    Some(index) => previous.swap_remove(index).status,
+                    None => UpstreamStatus {
+                        address: config.address.clone(),
+                        priority: 0,
+                        healthy: true,
+                        active: false,
+                        last_check: None,
+                        latency_ms: None,
+                        last_error: None,
+                        consecutive_failures: 0,
+                    },
+                };
+                status.priority = config.priority;
+                Relay { config, status }
+            })
+            .collect();
+
+        if starting {
+            // The first relay in use isn't a switch
+            state.active = state
+                .relays
+                .iter()
+                .find(|relay| relay.status.healthy)
+                .map(|relay| relay.config.address.clone());
+        } else {
+            state.select("configuration changed".to_string());
+        }
+    }
+
+    /// Relays to try for a new tunnel: healthy ones by priority, then the
+    /// rest as a last resort.
+    pub fn candidates(&self) -> Vec<UpstreamConfig> {
+        let state = self.state.lock().unwrap();
+        let (healthy, down): (Vec<&Relay>, Vec<&Relay>) =
+            state.relays.iter().partition(|relay| relay.status.healthy);
+        healthy
+            .into_iter()
+            .chain(down)
+            .map(|relay| relay.config.clone())
+            .collect()
+    }
+
+    /// Record a passed health check, or a tunnel opened, through `address`.
+    ///
+    /// `latency` is the duration of a health check (`None` for tunnels).
+    pub fn record_success(&self, address: &str, latency: Option<Duration>) {
+        let mut state = self.state.lock().unwrap();
+        let Some(relay) = state.relay_mut(address) else {
+            return;
+        };
+        if let Some(latency) = latency {
+            relay.status.last_check = Some(Utc::now());
+            relay.status.latency_ms = Some(latency.as_millis() as u64);
+        }
+        if relay.status.healthy {
+            return;
+        }
+        relay.status.healthy = true;
+        relay.status.consecutive_failures = 0;
+        relay.status.last_error = None;
+        info!("Upstream relay {} recovered", address);
+        state.select(format!("{} recovered", address));
+    }
+
+    /// Record a failed health check, or a tunnel that couldn't be opened, through `address`.
+    ///
+    /// `checked` is set for health checks, which also update `last_check`.
+    pub fn record_failure(&self, address: &str, error: &str, checked: bool) {
+        let mut state = self.state.lock().unwrap();
+        let Some(relay) = state.relay_mut(address) else {
+            return;
+        };
+        if checked {
+            relay.status.last_check = Some(Utc::now());
+            relay.status.latency_ms = None;
+        }
+        relay.status.consecutive_failures += 1;
+        relay.status.last_error = Some(error.to_string());
+        if !relay.status.healthy {
+            return;
+        }
+        relay.status.healthy = false;
+        warn!("Upstream relay {} is down: {}", address, error);
+        state.select(format!("{} failed: {}", address, error));
+    }
+
+    /// Health of every relay, most preferred first.
+    pub fn statuses(&self) -> Vec<UpstreamStatus> {
+        let state = self.state.lock().unwrap();
+        state
+            .relays
+            .iter()
+            .map(|relay| {
+                let mut status = relay.status.clone();
+                status.active = state.active.as_deref() == Some(relay.config.address.as_str());
+                status
+            })
+            .collect()
+    }
+
+    /// Address of the relay new connections use (`None` when all are down or
+    /// forwarding is disabled).
+    pub fn active(&self) -> Option<String> {
+        self.state.lock().unwrap().active.clone()
+    }
+
+    /// Recent failover events, newest first.
+    pub fn events(&self) -> Vec<FailoverEvent> {
+        let state = self.state.lock().unwrap();
+        state.events.iter().rev().cloned().collect()
+    }
+}
+
+/// Probe every upstream relay on `upstream.health_check_interval`.
+///
+/// Runs for as long as the process does; while forwarding is disabled it
+/// only waits for the configuration to change.
+pub async fn run_health_checks(config_manager: ConfigManager) {
+    loop {
+        let interval = match config_manager.get_upstream().await {
+            Some(upstream) => {
+                let timeout = Duration::from_secs(upstream.health_check_timeout.max(1));
+                let probe_target = upstream.probe_target.as_deref();
+                let checks = upstream.endpoints().into_iter().map(|endpoint| async move {
+                    let started = Instant::now();
+                    let result = tokio::time::timeout(timeout, probe(&endpoint, probe_target))
+                        .await
+                        .unwrap_or(Err(Error::Timeout));
+                    (endpoint.address, result, started.elapsed())
+                });
+
+                let pool = config_manager.upstream_pool();
+                for (address, result, elapsed) in futures::future::join_all(checks).await {
+                    match result {
+                        Ok(()) => pool.record_success(&address, Some(elapsed)),
+                        Err(e) => pool.record_failure(&address, &e.to_string(), true),
+                    }
+                }
+                upstream.health_check_interval
+            }
+            None => UpstreamConfig::default().health_check_interval,
+        };
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+    }
+}
+
+/// Check one relay: a tunnel to `target` when set, a TCP connect otherwise.
+async fn probe(endpoint: &UpstreamConfig, target: Option<&str>) -> Result<()> {
+    let Some((host, port)) = target.and_then(|target| target.rsplit_once(':')) else {
+        TcpStream::connect(&endpoint.address).await?;
+        return Ok(());
+    };
+    let port = port
+        .parse()
+        .map_err(|_| Error::Config(format!("Invalid upstream probe target port: {}", port)))?;
+    let request = ConnectRequest {
+        host: host.trim_start_matches('[').trim_end_matches(']'),
+        port,
+        // Probes have no client; the upstream sees them as coming from 0.0.0.0
+        client_addr: SocketAddr::from(([0, 0, 0, 0], 0)),
+        username: None,
+        protocol: Protocol::HttpConnect,
+    };
+    tunnel::open_tunnel(endpoint, &request).await?;
+    Ok(())
+}
@@ -0,0 +1,203 @@
+//! The compiled matcher must agree with `AccessControlConfig` on every input.
+
+use chrono::{Duration, Utc};
+use net_relay_core::config::{new_rule_id, PortRanges};
+use net_relay_core::{AccessControlConfig, AccessMatcher, AccessRule, RuleAction, RuleMode};
+use proptest::prelude::*;
+
+/// IPv4 addresses from a small space so patterns and inputs collide often.
+fn ipv4() -> impl Strategy<Value = String> {
+    (prop_oneof![Just(10u8), Just(192)], 0u8..3, 0u8..3, 0u8..8)
+        .prop_map(|(a, b, c, d)| format!("{}.{}.{}.{}", a, b, c, d))
+}
+
+fn ipv6() -> impl Strategy<Value = String> {
+    prop_oneof![
+        (0u16..4, 0u16..4).prop_map(|(a, b)| format!("2001:db8::{:x}:{:x}", a, b)),
+        ipv4().prop_map(|v4| format!("::ffff:{}", v4)),
+        Just("::1".to_string()),
+    ]
+}
+
+fn address() -> impl Strategy<Value = String> {
+    prop_oneof![
+        3 => ipv4(),
+        2 => ipv6(),
+        1 => prop_oneof![Just("localhost"), Just("unknown"), Just("")].prop_map(String::from),
+    ]
+}
+
+fn ip_pattern() -> impl Strategy<Value = String> {
+    prop_oneof![
+        4 => address(),
+        4 => (address(), 0u8..=140).prop_map(|(ip, prefix)| format!("{}/{}", ip, prefix)),
+        1 => (address(), 0u8..=32).prop_map(|(ip, prefix)| format!(" {} / {} ", ip, prefix)),
+        1 => (address(), "[a-z0-9]{0,3}").prop_map(|(ip, prefix)| format!("{}/{}", ip, prefix)),
+    ]
+}
+
+fn host() -> impl Strategy<Value = String> {
+    prop::collection::vec(
+        prop_oneof![Just("a"), Just("b"), Just("example"), Just("com"), Just("")],
+        1..4,
+    )
+    .prop_map(|labels| labels.join("."))
+}
+
+fn domain_pattern() -> impl Strategy<Value = String> {
+    prop_oneof![
+        2 => host(),
+        2 => host().prop_map(|h| format!("*.{}", h)),
+        1 => prop_oneof![Just("*."), Just("*"), Just("")].prop_map(String::from),
+    ]
+}
+
+/// Target ports, around the ranges `ports()` produces.
+fn port() -> impl Strategy<Value = u16> {
+    prop_oneof![Just(80u16), Just(443), Just(8000), Just(8050), Just(8101)]
+}
+
+fn ports() -> impl Strategy<Value = Option<PortRanges>> {
+    prop::option::weighted(
+        0.3,
+        prop_oneof![
+            Just("443"),
+            Just("80,443"),
+            Just("8000-8100"),
+            Just("80,8050-8101")
+        ]
+        .prop_map(|ports| ports.parse::<PortRanges>().unwrap()),
+    )
+}
+
+fn path() -> impl Strategy<Value = Option<String>> {
+    prop::option::of(
+        prop_oneof![Just("/"), Just("/api"), Just("/api/v1"), Just("")].prop_map(String::from),
+    )
+}
+
+fn rule() -> impl Strategy<Value = AccessRule> {
+    (
+        domain_pattern(),
+        path(),
+        ports(),
+        any::<bool>(),
+        prop::bool::weighted(0.8),
+        prop::option::weighted(0.2, any::<bool>()),
+        prop::bool::weighted(0.2),
+    )
+        .prop_map(
+            |(domain, path, ports, allow, enabled, expired, monitor)| AccessRule {
+                id: new_rule_id(),
+                name: String::new(),
+                domain,
+                domain_unicode: None,
+                path,
+                ports,
+                action: if allow {
+                    RuleAction::Allow
+                } else {
+                    RuleAction::Deny
+                },
+                mode: if monitor {
+                    RuleMode::Monitor
+                } else {
+                    RuleMode::Enforce
+                },
+                enabled,
+                // Far enough from now that the two checks can't straddle the expiry
+                expires_at: expired.map(|expired| {
+                    Utc::now()
+                        + if expired {
+                            -Duration::hours(1)
+                        } else {
+                            Duration::hours(1)
+                        }
+                }),
+            },
+        )
+}
+
+fn access_control() -> impl Strategy<Value = AccessControlConfig> {
+    (
+        prop::collection::vec(ip_pattern(), 0..6),
+        prop::collection::vec(ip_pattern(), 0..6),
+        prop::collection::vec(rule(), 0..12),
+        any::<bool>(),
+        prop::bool::weighted(0.1),
+    )
+        .prop_map(
+            |(ip_whitelist, ip_blacklist, rules, allow_by_default, monitor_only)| {
+                AccessControlConfig {
+                    ip_whitelist,
+                    ip_blacklist,
+                    rules,
+                    allow_by_default,
+                    monitor_only,
+                    ..Default::default()
+                }
+            },
+        )
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2000))]
+
+    #[test]
+    fn ip_checks_agree(config in access_control(), ips in prop::collection::vec(address(), 1..16)) {
+        let matcher = AccessMatcher::new(&config);
+        for ip in &ips {
+            prop_assert_eq!(matcher.is_ip_allowed(ip), config.is_ip_allowed(ip), "ip {:?}", ip);
+        }
+    }
+
+    #[test]
+    fn target_checks_agree(
+        config in access_control(),
+        targets in prop::collection::vec((host(), port(), path()), 1..16),
+    ) {
+        let matcher = AccessMatcher::new(&config);
+        for (host, port, path) in &targets {
+            prop_assert_eq!(
+                matcher.is_target_allowed(host, Some(*port), path.as_deref()),
+                config.is_target_allowed(host, *port, path.as_deref()),
+                "target {:?} {} {:?}",
+                host,
+                port,
+                path
+            );
+
+            // Both report the same rules, monitored or not
+            let check = matcher.evaluate_target(host, Some(*port), path.as_deref());
+            let decision = config.evaluate_target(host, *port, path.as_deref());
+            prop_assert_eq!(check.block.is_none(), decision.allowed);
+            prop_assert_eq!(check.would_block.is_some(), decision.would_deny);
+            prop_assert_eq!(check.rule_id, decision.matched_rule.map(|rule| rule.id));
+            prop_assert_eq!(
+                check.monitored_rule_id,
+                decision.monitored_rule.map(|rule| rule.id)
+            );
+        }
+    }
+
+    #[test]
+    fn patterns_match_themselves(config in access_control(), port in port()) {
+        // Inputs equal to configured patterns exercise the literal and exact paths
+        let matcher = AccessMatcher::new(&config);
+        for ip in config.ip_whitelist.iter().chain(&config.ip_blacklist) {
+            prop_assert_eq!(matcher.is_ip_allowed(ip), config.is_ip_allowed(ip), "ip {:?}", ip);
+        }
+        for rule in &config.rules {
+            for path in [None, rule.path.as_deref()] {
+                prop_assert_eq!(
+                    matcher.is_target_allowed(&rule.domain, Some(port), path),
+                    config.is_target_allowed(&rule.domain, port, path),
+                    "target {:?} {} {:?}",
+                    rule.domain,
+                    port,
+                    path
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Threshold alerts: firing and resolving with hysteresis, counter windows,
+//! validation and webhook notifications.
+
+use chrono::{DateTime, Duration, Utc};
+use net_relay_core::alerts::notify;
+use net_relay_core::{AlertEngine, AlertSample, AlertState, AlertsConfig, Config};
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+fn alerts(toml: &str) -> AlertsConfig {
+    let config: Config = toml::from_str(toml).unwrap();
+    config.alerts
+}
+
+fn active(active_connections: u64) -> AlertSample {
+    AlertSample {
+        active_connections,
+        ..Default::default()
+    }
+}
+
+fn denied(denied_attempts: u64) -> AlertSample {
+    AlertSample {
+        denied_attempts,
+        ..Default::default()
+    }
+}
+
+fn at(start: DateTime<Utc>, secs: i64) -> DateTime<Utc> {
+    start + Duration::seconds(secs)
+}
+
+#[test]
+fn rules_fire_and_resolve_past_the_resolve_threshold() {
+    let config = alerts(
+        r#"
+        [[alerts.rules]]
+        name = "busy"
+        metric = "active_connections"
+        threshold = 100
+        resolve_threshold = 80
+        "#,
+    );
+    let start = Utc::now();
+    let engine = AlertEngine::new(start);
+
+    assert!(engine
+        .evaluate(&config, active(100), at(start, 10))
+        .is_empty());
+    let transitions = engine.evaluate(&config, active(101), at(start, 20));
+    assert_eq!(transitions.len(), 1);
+    assert_eq!(transitions[0].status.state, AlertState::Firing);
+    assert_eq!(transitions[0].status.since, at(start, 20));
+    assert_eq!(transitions[0].previous_since, at(start, 10));
+
+    // Dropping below the threshold but not the resolve threshold doesn't flap
+    assert!(engine
+        .evaluate(&config, active(90), at(start, 30))
+        .is_empty());
+    assert!(engine
+        .evaluate(&config, active(120), at(start, 40))
+        .is_empty());
+    let status = &engine.statuses()[0];
+    assert_eq!(
+        (status.state, status.since, status.value),
+        (AlertState::Firing, at(start, 20), 120)
+    );
+
+    let transitions = engine.evaluate(&config, active(80), at(start, 50));
+    assert_eq!(transitions[0].status.state, AlertState::Resolved);
+    assert_eq!(transitions[0].previous_since, at(start, 20));
+}
+
+#[test]
+fn below_rules_fire_under_the_threshold() {
+    let config = alerts(
+        r#"
+        [[alerts.rules]]
+        name = "idle"
+        metric = "active_connections"
+        comparison = "below"
+        threshold = 5
+        resolve_threshold = 10
+        "#,
+    );
+    let start = Utc::now();
+    let engine = AlertEngine::new(start);
+
+    let transitions = engine.evaluate(&config, active(4), at(start, 10));
+    assert_eq!(transitions[0].status.state, AlertState::Firing);
+    assert!(engine
+        .evaluate(&config, active(9), at(start, 20))
+        .is_empty());
+    let transitions = engine.evaluate(&config, active(10), at(start, 30));
+    assert_eq!(transitions[0].status.state, AlertState::Resolved);
+}
+
+#[test]
+fn counters_are_summed_over_the_window() {
+    let config = alerts(
+        r#"
+        [[alerts.rules]]
+        name = "denials"
+        metric = "denied_attempts"
+        threshold = 10
+        window_secs = 60
+        "#,
+    );
+    let start = Utc::now();
+    let engine = AlertEngine::new(start);
+
+    // Counted from the start while the history is shorter than the window
+    assert!(engine
+        .evaluate(&config, denied(8), at(start, 30))
+        .is_empty());
+    let transitions = engine.evaluate(&config, denied(12), at(start, 50));
+    assert_eq!(transitions[0].status.value, 12);
+
+    // At 100s the window starts at 40s, so the sample from 30s is the baseline
+    let transitions = engine.evaluate(&config, denied(15), at(start, 100));
+    assert_eq!(transitions[0].status.value, 7);
+    assert_eq!(transitions[0].status.state, AlertState::Resolved);
+
+    // At 140s the baseline is the sample from 50s
+    assert!(engine
+        .evaluate(&config, denied(20), at(start, 140))
+        .is_empty());
+    assert_eq!(engine.statuses()[0].value, 8);
+}
+
+#[test]
+fn removed_rules_are_dropped() {
+    let mut config = alerts(
+        r#"
+        [[alerts.rules]]
+        name = "busy"
+        metric = "active_connections"
+        threshold = 1
+
+        [[alerts.rules]]
+        name = "throughput"
+        metric = "throughput"
+        threshold = 1000
+        "#,
+    );
+    let start = Utc::now();
+    let engine = AlertEngine::new(start);
+    engine.evaluate(&config, active(5), at(start, 10));
+    assert_eq!(engine.statuses().len(), 2);
+
+    config.rules.remove(0);
+    engine.evaluate(&config, active(5), at(start, 20));
+    let statuses = engine.statuses();
+    assert_eq!(statuses.len(), 1);
+    assert_eq!(statuses[0].name, "throughput");
+}
+
+#[test]
+fn invalid_rules_are_rejected() {
+    let invalid = [
+        (
+            r#"
+            [[alerts.rules]]
+            name = "busy"
+            metric = "active_connections"
+            threshold = 100
+            resolve_threshold = 120
+            "#,
+            "resolve_threshold",
+        ),
+        (
+            r#"
+            [[alerts.rules]]
+            name = "busy"
+            metric = "active_connections"
+            threshold = 100
+            notify = "ops"
+            "#,
+            "unknown webhook",
+        ),
+        (
+            r#"
+            [[alerts.rules]]
+            name = "busy"
+            metric = "active_connections"
+            threshold = 100
+
+            [[alerts.rules]]
+            name = "busy"
+            metric = "connections"
+            threshold = 100
+            "#,
+            "duplicate name",
+        ),
+        (
+            r#"
+            [[alerts.rules]]
+            name = "denials"
+            metric = "denied_attempts"
+            threshold = 10
+            window_secs = 0
+            "#,
+            "window_secs",
+        ),
+        (
+            r#"
+            [alerts.webhooks.ops]
+            url = "ftp://example.com/hook"
+            "#,
+            "url",
+        ),
+    ];
+    for (toml, expected) in invalid {
+        let config: Config = toml::from_str(toml).unwrap();
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains(expected), "{}: {}", expected, error);
+    }
+
+    let unknown_metric = r#"
+        [[alerts.rules]]
+        name = "cpu"
+        metric = "cpu_load"
+        threshold = 90
+    "#;
+    assert!(toml::from_str::<Config>(unknown_metric).is_err());
+}
+
+#[tokio::test]
+async fn transitions_are_posted_to_the_webhook() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let config = alerts(&format!(
+        r#"
+        [alerts.webhooks.ops]
+        url = "http://{}/hooks/alerts"
+
+        [[alerts.rules]]
+        name = "busy"
+        metric = "active_connections"
+        threshold = 1
+        notify = "ops"
+        "#,
+        listener.local_addr().unwrap()
+    ));
+    let start = Utc::now();
+    let engine = AlertEngine::new(start);
+    let transition = engine
+        .evaluate(&config, active(3), at(start, 10))
+        .pop()
+        .unwrap();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse().unwrap())
+                    })
+                    .unwrap();
+                if body.len() >= length {
+                    stream
+                        .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                        .await
+                        .unwrap();
+                    return (head.to_string(), body.to_string());
+                }
+            }
+            assert!(n > 0, "connection closed before the whole request");
+        }
+    });
+
+    notify(config.webhooks["ops"].clone(), transition).await;
+    let (head, body) = server.await.unwrap();
+    assert!(head.starts_with("POST /hooks/alerts HTTP/1.1"), "{}", head);
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["name"], "busy");
+    assert_eq!(body["state"], "firing");
+    assert_eq!(body["metric"], "active_connections");
+    assert_eq!(body["value"], 3);
+    assert!(body["previous_since"].is_string());
+}
@@ -0,0 +1,146 @@
+//! The deprecated `security.allowed_ips`, moved into the IP whitelist.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use net_relay_core::proxy::HttpProxy;
+use net_relay_core::{AllowedIpsMigration, Config, ConfigManager, Stats};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+fn config(toml: &str) -> Config {
+    toml::from_str(toml).unwrap()
+}
+
+#[test]
+fn allowed_ips_become_the_whitelist() {
+    let mut config = config(
+        r#"
+        [security]
+        allowed_ips = ["10.0.0.0/8", "192.168.1.1"]
+        "#,
+    );
+    assert_eq!(
+        config.migrate_allowed_ips(),
+        Some(AllowedIpsMigration::Moved(vec![
+            "10.0.0.0/8".to_string(),
+            "192.168.1.1".to_string()
+        ]))
+    );
+    assert!(config.security.allowed_ips.is_empty());
+    assert_eq!(
+        config.access_control.ip_whitelist,
+        ["10.0.0.0/8", "192.168.1.1"]
+    );
+    assert_eq!(config.migrate_allowed_ips(), None);
+}
+
+#[test]
+fn an_existing_whitelist_wins() {
+    let mut config = config(
+        r#"
+        [security]
+        allowed_ips = ["10.0.0.0/8"]
+
+        [access_control]
+        ip_whitelist = ["127.0.0.1"]
+        "#,
+    );
+    assert_eq!(
+        config.migrate_allowed_ips(),
+        Some(AllowedIpsMigration::Dropped(vec!["10.0.0.0/8".to_string()]))
+    );
+    assert!(config.security.allowed_ips.is_empty());
+    assert_eq!(config.access_control.ip_whitelist, ["127.0.0.1"]);
+}
+
+/// A config file in a temporary directory.
+struct ConfigFile {
+    dir: PathBuf,
+}
+
+impl ConfigFile {
+    fn new(contents: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("net-relay-allowed-ips-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = Self { dir };
+        std::fs::write(file.path(), contents).unwrap();
+        file
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join("config.toml")
+    }
+}
+
+impl Drop for ConfigFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Start an HTTP proxy for the config file, migrated as at startup.
+async fn start_proxy(file: &ConfigFile) -> SocketAddr {
+    let config = Config::load_from_file(file.path()).unwrap();
+    let manager = ConfigManager::new(config, Some(file.path().to_string_lossy().into_owned()));
+    assert!(manager.migrate_allowed_ips().await.is_some());
+
+    let proxy = HttpProxy::builder()
+        .stats(Arc::new(Stats::new(10)))
+        .config(manager)
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+    addr
+}
+
+/// Send a CONNECT to `target_port` through the proxy, returning the response.
+async fn connect(proxy: SocketAddr, target_port: u16) -> String {
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    let request = format!("CONNECT 127.0.0.1:{} HTTP/1.1\r\n\r\n", target_port);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = vec![0u8; 64];
+    let n = stream.read(&mut response).await.unwrap_or(0);
+    String::from_utf8_lossy(&response[..n]).into_owned()
+}
+
+#[tokio::test]
+async fn unlisted_clients_are_blocked() {
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_port = target.local_addr().unwrap().port();
+    tokio::spawn(async move { while target.accept().await.is_ok() {} });
+
+    let file = ConfigFile::new(
+        r#"
+        [security]
+        allowed_ips = ["10.0.0.0/8"]
+        "#,
+    );
+    let proxy = start_proxy(&file).await;
+    let response = connect(proxy, target_port).await;
+    assert!(!response.starts_with("HTTP/1.1 200"), "{:?}", response);
+
+    // The migration was saved
+    let saved = Config::load_from_file(file.path()).unwrap();
+    assert!(saved.security.allowed_ips.is_empty());
+    assert_eq!(saved.access_control.ip_whitelist, ["10.0.0.0/8"]);
+    let raw: toml::Table = toml::from_str(&std::fs::read_to_string(file.path()).unwrap()).unwrap();
+    assert!(!raw["security"]
+        .as_table()
+        .unwrap()
+        .contains_key("allowed_ips"));
+
+    // Listed clients still get through
+    let file = ConfigFile::new(
+        r#"
+        [security]
+        allowed_ips = ["127.0.0.1"]
+        "#,
+    );
+    let proxy = start_proxy(&file).await;
+    let response = connect(proxy, target_port).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{:?}", response);
+}
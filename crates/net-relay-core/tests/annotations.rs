@@ -0,0 +1,124 @@
+//! Tags and notes on connections and users.
+
+use net_relay_core::access_log::format_line;
+use net_relay_core::connection::{normalize_note, normalize_tags, Protocol};
+use net_relay_core::stats::HistorySince;
+use net_relay_core::{AccessLogFormat, CloseReason, Config, ConnectionInfo, Stats};
+
+fn tags(tags: &[&str]) -> Vec<String> {
+    tags.iter().map(|tag| tag.to_string()).collect()
+}
+
+#[test]
+fn tags_are_trimmed_deduplicated_and_bounded() {
+    assert_eq!(
+        normalize_tags(&tags(&[" case-42 ", "vip", "case-42"])).unwrap(),
+        tags(&["case-42", "vip"])
+    );
+    assert!(normalize_tags(&tags(&["  "])).is_err());
+    assert!(normalize_tags(&tags(&["two words"])).is_err());
+    assert!(normalize_tags(&["x".repeat(65)]).is_err());
+    let many: Vec<String> = (0..17).map(|i| i.to_string()).collect();
+    assert_eq!(
+        normalize_tags(&many).unwrap_err(),
+        "must have at most 16 tags"
+    );
+
+    assert_eq!(normalize_note("  ").unwrap(), None);
+    assert_eq!(
+        normalize_note(" reported by customer X\n")
+            .unwrap()
+            .as_deref(),
+        Some("reported by customer X")
+    );
+    assert!(normalize_note(&"x".repeat(1025)).is_err());
+
+    let config: Config = toml::from_str(
+        r#"
+        [[security.users]]
+        username = "alice"
+        password = "wonderland"
+        tags = ["under investigation"]
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_err());
+}
+
+fn connection() -> ConnectionInfo {
+    ConnectionInfo::new(Protocol::Socks5, "10.0.0.1:5000".into(), "t".into(), 443)
+}
+
+#[tokio::test]
+async fn tags_follow_connections_into_history() {
+    let stats = Stats::new(10);
+    let tagged = connection();
+    let tagged_id = tagged.id;
+    stats.add_connection(tagged).await;
+    let plain = connection();
+    let plain_id = plain.id;
+    stats.add_connection(plain).await;
+
+    // Active connection: tags and note are set independently
+    let info = stats
+        .annotate_connection(tagged_id, Some(tags(&["case-42"])), None)
+        .await
+        .unwrap();
+    assert_eq!(info.tags, ["case-42"]);
+    let info = stats
+        .annotate_connection(tagged_id, None, Some(Some("reported by customer X".into())))
+        .await
+        .unwrap();
+    assert_eq!(info.tags, ["case-42"]);
+    assert_eq!(stats.get_connection(tagged_id).unwrap().note, info.note);
+
+    stats
+        .close_connection(tagged_id, 1, 2, CloseReason::ClientEof)
+        .await;
+    stats
+        .close_connection(plain_id, 1, 2, CloseReason::ClientEof)
+        .await;
+
+    let has_tag = |tag: &'static str| {
+        move |entry: &net_relay_core::ConnectionStats| entry.info.tags.iter().any(|t| t == tag)
+    };
+    let page = stats
+        .get_history_matching(None, HistorySince::default(), has_tag("case-42"))
+        .await;
+    assert_eq!(page.connections.len(), 1);
+    let info = &page.connections[0].info;
+    assert_eq!(info.id, tagged_id);
+    assert_eq!(info.note.as_deref(), Some("reported by customer X"));
+
+    // Closed connections can still be tagged while in the history
+    assert!(stats
+        .annotate_connection(plain_id, Some(tags(&["later"])), Some(None))
+        .await
+        .is_some());
+    let page = stats
+        .get_history_matching(None, HistorySince::default(), has_tag("later"))
+        .await;
+    assert_eq!(page.connections[0].info.id, plain_id);
+    assert!(stats
+        .annotate_connection(uuid::Uuid::new_v4(), Some(Vec::new()), None)
+        .await
+        .is_none());
+
+    // Cursor polling skips entries without the tag
+    let since = HistorySince {
+        id: Some(0),
+        time: None,
+    };
+    let page = stats
+        .get_history_matching(Some(1), since, has_tag("case-42"))
+        .await;
+    assert_eq!(page.connections.len(), 1);
+    assert_eq!(page.connections[0].info.id, tagged_id);
+
+    // JSON access log lines carry the tags
+    let line = format_line(info, AccessLogFormat::Json);
+    let record: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(record["tags"], serde_json::json!(["case-42"]));
+    let line = format_line(&connection(), AccessLogFormat::Json);
+    assert!(!line.contains("tags"));
+}
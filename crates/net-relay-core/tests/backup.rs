@@ -0,0 +1,62 @@
+//! Config file snapshots.
+
+use net_relay_core::backup;
+use net_relay_core::BackupConfig;
+
+#[test]
+fn snapshots_skip_unchanged_files_and_honor_retention() {
+    let dir = std::env::temp_dir().join(format!("net-relay-backup-{}", uuid::Uuid::new_v4()));
+    let config_file = dir.join("config.toml");
+    let backup_dir = dir.join("backups");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(&config_file, "[server]\nsocks_port = 1080\n").unwrap();
+    let config = BackupConfig {
+        enabled: true,
+        directory: backup_dir.to_string_lossy().to_string(),
+        retention: 2,
+        ..Default::default()
+    };
+
+    assert!(backup::list(&backup_dir).unwrap().is_empty());
+    let first = backup::snapshot(&config_file, &config, None)
+        .unwrap()
+        .unwrap();
+    assert_eq!(first.label, None);
+    assert!(backup::snapshot(&config_file, &config, None)
+        .unwrap()
+        .is_none());
+
+    // Labelled snapshots are written even when nothing changed
+    let labelled = backup::snapshot(&config_file, &config, Some("pre-restore"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(labelled.label.as_deref(), Some("pre-restore"));
+    assert_eq!(backup::list(&backup_dir).unwrap().len(), 2);
+
+    // Snapshots older than the two newest are removed
+    std::fs::write(&config_file, "[server]\nsocks_port = 1081\n").unwrap();
+    let old = backup_dir.join("config-20000101T000000.000Z.toml");
+    std::fs::write(&old, "").unwrap();
+    backup::snapshot(&config_file, &config, Some("manual")).unwrap();
+    let names: Vec<String> = backup::list(&backup_dir)
+        .unwrap()
+        .into_iter()
+        .map(|b| b.name)
+        .collect();
+    assert_eq!(names.len(), 2);
+    assert!(!old.exists());
+
+    let newest = backup::read(&backup_dir, &names[0]).unwrap().unwrap();
+    assert!(newest.contains("1081"));
+
+    // Only snapshot names are read
+    std::fs::write(dir.join("secret.toml"), "").unwrap();
+    for name in [
+        "../config.toml",
+        "config-20000101T000000.000Z-/../../secret.toml",
+    ] {
+        assert_eq!(backup::read(&backup_dir, name).unwrap(), None, "{}", name);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,139 @@
+//! Responses to blocked targets (`access_control.block_message`,
+//! `X-Block-Reason`).
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use net_relay_core::proxy::response::HttpResponses;
+use net_relay_core::proxy::HttpProxy;
+use net_relay_core::{
+    AccessMatcher, Config, ConfigManager, DenyReason, Error, HttpProxyConfig, Stats, TargetBlock,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+fn config(block_message: Option<&str>) -> Config {
+    let message = block_message
+        .map(|message| format!("block_message = {:?}\n", message))
+        .unwrap_or_default();
+    toml::from_str(&format!(
+        r#"
+        [access_control]
+        allow_by_default = false
+        {}
+        [[access_control.rules]]
+        id = "r1"
+        name = "Ads"
+        domain = "*.ads.example"
+        action = "deny"
+
+        [[access_control.rules]]
+        id = "r2"
+        domain = "blocked.example"
+        action = "deny"
+
+        [[access_control.rules]]
+        id = "r3"
+        domain = "ok.example"
+        action = "allow"
+        "#,
+        message
+    ))
+    .unwrap()
+}
+
+#[test]
+fn blocking_rule_is_reported() {
+    let matcher = AccessMatcher::new(&config(None).access_control);
+    assert_eq!(matcher.check_target("ok.example", Some(443), None), Ok(()));
+
+    let block = matcher
+        .check_target("x.ads.example", Some(443), None)
+        .unwrap_err();
+    assert_eq!(block.rule_id.as_deref(), Some("r1"));
+    assert_eq!(block.describe(), "rule \"Ads\" (id r1)");
+
+    let block = matcher
+        .check_target("blocked.example", Some(443), None)
+        .unwrap_err();
+    assert_eq!(block.rule_name, None);
+    assert_eq!(block.describe(), "rule r2");
+
+    let block = matcher
+        .check_target("other.example", Some(443), None)
+        .unwrap_err();
+    assert_eq!(block, TargetBlock::default());
+    assert_eq!(block.describe(), "default policy");
+}
+
+#[test]
+fn blocked_response_names_the_rule() {
+    let responses = HttpResponses::new(&HttpProxyConfig::default());
+    let block = TargetBlock {
+        rule_id: Some("r1".into()),
+        rule_name: Some("Ads\r\nInjected: 1".into()),
+    };
+    let error = Error::AccessDenied(DenyReason::Target, "Target blocked: a:1".into());
+    assert_eq!(
+        responses.blocked(&error, &block, None),
+        "HTTP/1.1 403 Forbidden\r\nX-Block-Reason: rule \"Ads  Injected: 1\" (id r1)\r\n\
+         Content-Type: text/plain\r\nContent-Length: 36\r\n\r\n\
+         Access denied: Target blocked: a:1\r\n"
+    );
+    assert!(responses
+        .blocked(&error, &block, Some("Ask IT to unblock"))
+        .ends_with("Content-Length: 19\r\n\r\nAsk IT to unblock\r\n"));
+}
+
+/// Send a CONNECT and return the full response.
+async fn connect(proxy: SocketAddr, target: &str) -> String {
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    let request = format!("CONNECT {} HTTP/1.1\r\n\r\n", target);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn proxy_explains_blocks() {
+    let stats = Arc::new(Stats::new(10));
+    let proxy = HttpProxy::builder()
+        .config(ConfigManager::new(
+            config(Some("Blocked by policy, see https://intranet/proxy")),
+            None,
+        ))
+        .stats(Arc::clone(&stats))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+
+    let response = connect(proxy_addr, "x.ads.example:443").await;
+    assert!(
+        response.starts_with("HTTP/1.1 403 Forbidden\r\n"),
+        "{}",
+        response
+    );
+    assert!(response.contains("\r\nX-Block-Reason: rule \"Ads\" (id r1)\r\n"));
+    assert!(response.ends_with("\r\n\r\nBlocked by policy, see https://intranet/proxy\r\n"));
+
+    let response = connect(proxy_addr, "other.example:443").await;
+    assert!(response.contains("\r\nX-Block-Reason: default policy\r\n"));
+
+    // Denied attempts name the rule too
+    let mut denied = Vec::new();
+    for _ in 0..50 {
+        denied = stats.get_denied(None).await;
+        if denied.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(denied.len(), 2);
+    assert!(
+        denied[1].reason.ends_with("by rule \"Ads\" (id r1)"),
+        "{:?}",
+        denied[1]
+    );
+}
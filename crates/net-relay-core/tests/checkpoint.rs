@@ -0,0 +1,73 @@
+//! Lifetime byte counters kept across restarts.
+
+use net_relay_core::connection::{CloseReason, ConnectionInfo, Protocol};
+use net_relay_core::{Config, Stats, StatsCheckpoint};
+
+async fn relay(stats: &Stats, user: &str, sent: u64, received: u64) {
+    let mut info = ConnectionInfo::new(
+        Protocol::Socks5,
+        "10.0.0.5:40000".to_string(),
+        "example.com".to_string(),
+        443,
+    );
+    info.username = Some(user.to_string());
+    let id = info.id;
+    stats.add_connection(info).await;
+    stats
+        .close_connection(id, sent, received, CloseReason::TargetEof)
+        .await;
+}
+
+#[tokio::test]
+async fn counters_survive_a_restart() {
+    let dir = std::env::temp_dir().join(format!("net-relay-checkpoint-{}", uuid::Uuid::new_v4()));
+    let config: Config = toml::from_str(&format!(
+        "[stats]\nstate_dir = {:?}",
+        dir.join("state").display().to_string()
+    ))
+    .unwrap();
+    let path = config.stats.checkpoint_path().unwrap();
+
+    let stats = Stats::new(10);
+    relay(&stats, "alice", 100, 200).await;
+    relay(&stats, "alice", 1, 2).await;
+    relay(&stats, "bob", 10, 20).await;
+    stats.checkpoint().save(&path).unwrap();
+
+    // Restored totals keep growing with new traffic
+    let checkpoint = StatsCheckpoint::load(&path).unwrap().unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    let stats = Stats::new(10).with_checkpoint(&checkpoint);
+    relay(&stats, "alice", 1000, 2000).await;
+
+    let aggregated = stats.get_aggregated().await;
+    assert_eq!(aggregated.total_connections, 4);
+    assert_eq!(aggregated.active_connections, 0);
+    assert_eq!(aggregated.total_bytes_sent, 1111);
+    assert_eq!(aggregated.total_bytes_received, 2222);
+    let alice = stats.get_user("alice").await.unwrap();
+    assert_eq!(alice.total_connections, 3);
+    assert_eq!(alice.active_connections, 0);
+    assert_eq!(alice.total_bytes_sent, 1101);
+    assert_eq!(alice.total_bytes_received, 2202);
+    let bob = stats.get_user("bob").await.unwrap();
+    assert_eq!(bob.total_bytes_sent, 10);
+    assert!(bob.last_activity.is_some());
+}
+
+#[test]
+fn missing_or_damaged_checkpoints() {
+    let dir = std::env::temp_dir().join(format!("net-relay-checkpoint-{}", uuid::Uuid::new_v4()));
+    let path = dir.join("stats_checkpoint.json");
+    assert!(StatsCheckpoint::load(&path).unwrap().is_none());
+
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(&path, "{\"total_connections\": 3, \"total_by").unwrap();
+    let result = StatsCheckpoint::load(&path);
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(result.is_err());
+
+    assert!(Config::default().stats.checkpoint_path().is_none());
+    let config: Config = toml::from_str("[stats]\ncheckpoint_interval_secs = 0").unwrap();
+    assert!(config.validate().is_err());
+}
@@ -0,0 +1,121 @@
+//! Saving the configuration: failed saves, attaching a file and change notifications.
+
+use net_relay_core::{Config, ConfigManager, ConfigSaveError};
+
+#[tokio::test]
+async fn failed_saves_keep_the_change_and_mark_the_config_dirty() {
+    // The config file's directory doesn't exist yet, so saving fails
+    let dir = std::env::temp_dir().join(format!("net-relay-save-{}", uuid::Uuid::new_v4()));
+    let path = dir.join("config.toml");
+    let manager = ConfigManager::new(Config::default(), Some(path.to_string_lossy().to_string()));
+    assert!(!manager.save_status().dirty);
+
+    let mut limits = manager.get_limits().await;
+    limits.max_connections = 7;
+    let err = manager.update_limits(limits).await.unwrap_err();
+    assert!(err.is::<ConfigSaveError>());
+    assert_eq!(manager.get_limits().await.max_connections, 7);
+    let status = manager.save_status();
+    assert!(status.dirty);
+    assert!(status.last_save_error.is_some());
+    assert!(status.last_save_error_at.is_some());
+
+    // Whole-config updates apply before saving too
+    let mut config = manager.get().await;
+    config.limits.max_connections = 8;
+    assert!(manager
+        .update(config)
+        .await
+        .unwrap_err()
+        .is::<ConfigSaveError>());
+    assert_eq!(manager.get_limits().await.max_connections, 8);
+    assert!(manager.flush().await.is_err());
+
+    // Once the file can be written, flushing saves the running configuration
+    std::fs::create_dir_all(&dir).unwrap();
+    let revision = manager.revision();
+    let status = manager.flush().await.unwrap();
+    assert!(!status.dirty);
+    assert!(status.last_save_error.is_none());
+    assert!(status.last_saved_at.is_some());
+    assert_eq!(manager.revision(), revision);
+    let saved = Config::load_from_file(&path).unwrap();
+    assert_eq!(saved.limits.max_connections, 8);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn managers_without_a_file_are_never_dirty() {
+    let manager = ConfigManager::new(Config::default(), None);
+    let mut limits = manager.get_limits().await;
+    limits.max_connections = 7;
+    manager.update_limits(limits).await.unwrap();
+    assert!(!manager.flush().await.unwrap().dirty);
+}
+
+#[tokio::test]
+async fn a_file_can_be_attached_later() {
+    let manager = ConfigManager::new(Config::default(), None);
+    assert!(!manager.is_persistent());
+    let mut limits = manager.get_limits().await;
+    limits.max_connections = 7;
+    manager.update_limits(limits).await.unwrap();
+
+    let dir = std::env::temp_dir().join(format!("net-relay-attach-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    let path_string = path.to_string_lossy().to_string();
+    // Clones share the path
+    manager.clone().set_config_path(Some(path_string.clone()));
+    assert!(manager.is_persistent());
+    assert_eq!(manager.config_path(), Some(path_string));
+    assert!(!path.exists());
+
+    manager.flush().await.unwrap();
+    let saved = Config::load_from_file(&path).unwrap();
+    assert_eq!(saved.limits.max_connections, 7);
+
+    manager.set_config_path(None);
+    assert!(!manager.is_persistent());
+    let mut limits = manager.get_limits().await;
+    limits.max_connections = 8;
+    manager.update_limits(limits).await.unwrap();
+    let saved = Config::load_from_file(&path).unwrap();
+    assert_eq!(saved.limits.max_connections, 7);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn subscribers_see_every_update() {
+    let manager = ConfigManager::new(Config::default(), None);
+    let mut changes = manager.subscribe();
+    assert!(changes.borrow_and_update().sections.is_empty());
+
+    let mut limits = manager.get_limits().await;
+    limits.max_connections = 7;
+    manager.update_limits(limits).await.unwrap();
+    changes.changed().await.unwrap();
+    {
+        let change = changes.borrow_and_update();
+        assert!(change.touches("limits"));
+        assert!(!change.touches("stats"));
+        assert_eq!(change.revision, manager.revision());
+        assert_eq!(change.config.limits.max_connections, 7);
+    }
+
+    let mut stats = manager.get_stats().await;
+    stats.enabled = false;
+    manager.update_stats(stats).await.unwrap();
+    let mut config = manager.get().await;
+    config.server.api_port = 3100;
+    manager.update(config).await.unwrap();
+    // Only the latest change is kept for a receiver that falls behind
+    changes.changed().await.unwrap();
+    let change = changes.borrow_and_update().clone();
+    assert_eq!(change.sections, ["server"]);
+    assert!(!change.config.stats.enabled);
+    assert_eq!(change.config.server.api_port, 3100);
+    assert!(!changes.has_changed().unwrap());
+}
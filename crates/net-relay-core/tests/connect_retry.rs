@@ -0,0 +1,133 @@
+//! Retrying refused target connections (`limits.connect_retries`).
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use net_relay_core::proxy::HttpProxy;
+use net_relay_core::stats::{ConnectionStats, HistorySince};
+use net_relay_core::{Config, ConfigManager, LimitsConfig, Stats};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+fn with_limits(toml: &str) -> Config {
+    toml::from_str(&format!("[limits]\n{}", toml)).unwrap()
+}
+
+#[test]
+fn retry_delay_doubles_with_bounded_jitter() {
+    let limits = LimitsConfig {
+        connect_retry_delay_ms: 100,
+        connect_retry_jitter_ms: 0,
+        ..LimitsConfig::default()
+    };
+    let delays: Vec<u128> = (1..=4)
+        .map(|retry| limits.connect_retry_delay(retry).as_millis())
+        .collect();
+    assert_eq!(delays, [100, 200, 400, 800]);
+
+    let limits = LimitsConfig {
+        connect_retry_jitter_ms: 50,
+        ..limits
+    };
+    for _ in 0..50 {
+        let delay = limits.connect_retry_delay(2).as_millis();
+        assert!((200..=250).contains(&delay), "{}", delay);
+    }
+
+    assert_eq!(LimitsConfig::default().connect_retries, 0);
+    assert!(with_limits("connect_retries = 10").validate().is_ok());
+    assert!(with_limits("connect_retries = 11").validate().is_err());
+}
+
+/// A local port nothing listens on (yet).
+async fn free_port() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap()
+}
+
+async fn start_proxy(config: Config) -> (SocketAddr, std::sync::Arc<Stats>) {
+    let proxy = HttpProxy::builder()
+        .config(ConfigManager::new(config, None))
+        .build();
+    let stats = proxy.stats().clone();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+    (addr, stats)
+}
+
+/// Send a CONNECT and return the response status line.
+async fn connect(proxy: SocketAddr, target: SocketAddr) -> (String, TcpStream) {
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    let request = format!("CONNECT {} HTTP/1.1\r\n\r\n", target);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n") {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await.unwrap();
+        head.push(byte[0]);
+    }
+    (String::from_utf8(head).unwrap(), stream)
+}
+
+async fn wait_for_history(stats: &Stats) -> Vec<ConnectionStats> {
+    for _ in 0..100 {
+        let history = stats
+            .get_history(None, HistorySince::default())
+            .await
+            .connections;
+        if !history.is_empty() {
+            return history;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("connection never closed");
+}
+
+#[tokio::test]
+async fn refused_target_is_retried_until_it_comes_up() {
+    let target = free_port().await;
+    let (proxy, stats) = start_proxy(with_limits(
+        "connect_retries = 5\nconnect_retry_delay_ms = 100\nconnect_retry_jitter_ms = 0",
+    ))
+    .await;
+
+    // The backend comes up while the proxy is backing off
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let listener = TcpListener::bind(target).await.unwrap();
+        let _ = listener.accept().await;
+    });
+
+    let (status, stream) = connect(proxy, target).await;
+    assert!(status.starts_with("HTTP/1.1 200"), "{}", status);
+    drop(stream);
+
+    let history = wait_for_history(&stats).await;
+    let attempts = history[0].info.connect_attempts.unwrap();
+    assert!(attempts >= 2, "{}", attempts);
+}
+
+#[tokio::test]
+async fn retries_stay_within_the_timeout() {
+    let target = free_port().await;
+    let (proxy, _) = start_proxy(with_limits(
+        "timeout = 1\nconnect_retries = 10\nconnect_retry_delay_ms = 400\nconnect_retry_jitter_ms = 0",
+    ))
+    .await;
+
+    // 400ms pause, then 800ms would end past the 1s budget: two attempts
+    let started = Instant::now();
+    let (status, _) = connect(proxy, target).await;
+    assert!(status.starts_with("HTTP/1.1 502"), "{}", status);
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(400), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+
+    // Without retries a refused target fails right away
+    let (proxy, _) = start_proxy(Config::default()).await;
+    let started = Instant::now();
+    let (status, _) = connect(proxy, target).await;
+    assert!(status.starts_with("HTTP/1.1 502"), "{}", status);
+    assert!(started.elapsed() < Duration::from_millis(400));
+}
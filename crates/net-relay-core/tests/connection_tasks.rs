@@ -0,0 +1,134 @@
+//! Connection tasks: panics closing their connections, and draining them at
+//! shutdown.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use net_relay_core::connection::Protocol;
+use net_relay_core::proxy::{Socks5Client, Socks5Proxy};
+use net_relay_core::{CloseReason, ConnectionInfo, Stats, StatsEvent};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const CLIENT: &str = "10.0.0.5:40000";
+
+async fn start_echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+async fn wait_for_tasks(stats: &Stats) {
+    tokio::time::timeout(Duration::from_secs(5), stats.wait_for_tasks())
+        .await
+        .expect("connection tasks still running");
+}
+
+#[tokio::test]
+async fn panicked_tasks_close_their_connections() {
+    let stats = Arc::new(Stats::new(10));
+    let mut events = stats.subscribe();
+
+    let task_stats = Arc::clone(&stats);
+    stats.spawn_connection("socks5", CLIENT.parse().unwrap(), async move {
+        let info = ConnectionInfo::new(
+            Protocol::Socks5,
+            CLIENT.to_string(),
+            "example.com".to_string(),
+            443,
+        );
+        task_stats.add_connection(info).await;
+        panic!("handler bug");
+    });
+    wait_for_tasks(&stats).await;
+
+    assert_eq!(stats.panicked_tasks(), 1);
+    assert_eq!(stats.connection_tasks(), 0);
+    assert_eq!(stats.active_connections(), 0);
+    let mut reasons = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        if let StatsEvent::Closed { reason, .. } = event {
+            reasons.push(reason);
+        }
+    }
+    assert_eq!(reasons, [CloseReason::InternalError]);
+}
+
+#[tokio::test]
+async fn connections_closed_before_the_panic_keep_their_reason() {
+    let stats = Arc::new(Stats::new(10));
+    let mut events = stats.subscribe();
+
+    let task_stats = Arc::clone(&stats);
+    stats.spawn_connection("http", CLIENT.parse().unwrap(), async move {
+        let info = ConnectionInfo::new(
+            Protocol::HttpConnect,
+            CLIENT.to_string(),
+            "example.com".to_string(),
+            443,
+        );
+        let id = info.id;
+        task_stats.add_connection(info).await;
+        task_stats
+            .close_connection(id, 10, 20, CloseReason::ClientEof)
+            .await;
+        panic!("{} after the first request", "handler bug");
+    });
+    wait_for_tasks(&stats).await;
+
+    assert_eq!(stats.panicked_tasks(), 1);
+    let mut reasons = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        if let StatsEvent::Closed { reason, .. } = event {
+            reasons.push(reason);
+        }
+    }
+    assert_eq!(reasons, [CloseReason::ClientEof]);
+}
+
+#[tokio::test]
+async fn shutdown_waits_for_relays_but_not_idle_clients() {
+    let echo = start_echo_server().await;
+    let proxy = Socks5Proxy::builder().build();
+    let stats = Arc::clone(proxy.stats());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+
+    let mut relaying = Socks5Client::connect(proxy_addr, "127.0.0.1", echo.port(), None)
+        .await
+        .unwrap();
+    relaying.write_all(b"ping").await.unwrap();
+    let mut echoed = [0u8; 4];
+    relaying.read_exact(&mut echoed).await.unwrap();
+    // Connected but never sends a greeting
+    let _idle = TcpStream::connect(proxy_addr).await.unwrap();
+    while stats.connection_tasks() < 2 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let mut events = stats.subscribe();
+    stats.begin_shutdown();
+    wait_for_tasks(&stats).await;
+
+    assert_eq!(stats.connection_tasks(), 0);
+    assert_eq!(stats.active_connections(), 0);
+    assert_eq!(stats.panicked_tasks(), 0);
+    let closed = events.try_recv().unwrap();
+    assert!(matches!(
+        closed,
+        StatsEvent::Closed {
+            reason: CloseReason::Shutdown,
+            ..
+        }
+    ));
+}
@@ -0,0 +1,165 @@
+//! Dashboard administrators, separate from proxy users.
+
+use net_relay_core::config::{hash_password, verify_password};
+use net_relay_core::{Config, ConfigManager, DashboardSecurityWarning, DashboardUser};
+
+fn parse(toml: &str) -> Config {
+    let config: Config = toml::from_str(toml).unwrap();
+    config.validate().unwrap();
+    config
+}
+
+#[tokio::test]
+async fn dashboard_and_proxy_credentials_are_separate() {
+    let config = parse(
+        r#"
+        [security]
+        auth_enabled = true
+        [[security.users]]
+        username = "alice"
+        password = "proxy-secret"
+
+        [dashboard]
+        auth_enabled = true
+        [[dashboard.users]]
+        username = "ops"
+        password = "dashboard-secret"
+        "#,
+    );
+    let manager = ConfigManager::new(config, None);
+
+    assert!(manager.is_dashboard_auth_enabled().await);
+    assert!(
+        manager
+            .authenticate_dashboard("ops", "dashboard-secret")
+            .await
+    );
+    assert!(!manager.authenticate_dashboard("ops", "proxy-secret").await);
+    assert!(
+        !manager
+            .authenticate_dashboard("alice", "proxy-secret")
+            .await
+    );
+    assert_eq!(
+        manager
+            .authenticate("alice", "proxy-secret")
+            .await
+            .as_deref(),
+        Some("alice")
+    );
+    assert!(manager
+        .authenticate("ops", "dashboard-secret")
+        .await
+        .is_none());
+
+    // Proxy auth alone leaves the dashboard open
+    let config = parse("[security]\nauth_enabled = true\nusername = \"u\"\npassword = \"p\"");
+    let manager = ConfigManager::new(config, None);
+    assert!(!manager.is_dashboard_auth_enabled().await);
+}
+
+#[test]
+fn legacy_login_is_migrated() {
+    let mut config = parse(
+        r#"
+        [dashboard]
+        auth_enabled = true
+        username = "admin"
+        password = "legacy-secret"
+        "#,
+    );
+    assert!(config.dashboard.authenticate("admin", "legacy-secret"));
+
+    assert!(config.dashboard.migrate_legacy_login());
+    assert!(!config.dashboard.migrate_legacy_login());
+    assert_eq!(config.dashboard.username, None);
+    assert_eq!(
+        config.dashboard.users,
+        [DashboardUser {
+            username: "admin".to_string(),
+            password: "legacy-secret".to_string(),
+        }]
+    );
+    assert!(config.dashboard.authenticate("admin", "legacy-secret"));
+    assert!(!config.dashboard.authenticate("admin", "wrong"));
+    config.validate().unwrap();
+
+    assert!(config
+        .dashboard
+        .set_password("admin", "new-secret".to_string()));
+    assert!(!config.dashboard.set_password("nobody", "x".to_string()));
+    assert!(config.dashboard.authenticate("admin", "new-secret"));
+}
+
+#[test]
+fn admins_are_validated() {
+    for toml in [
+        "[dashboard]\nauth_enabled = true",
+        "[dashboard]\nauth_enabled = true\nusername = \"admin\"",
+        "[[dashboard.users]]\nusername = \"a\"\npassword = \"\"",
+        "[[dashboard.users]]\nusername = \"a\"\npassword = \"x\"\n\
+         [[dashboard.users]]\nusername = \"a\"\npassword = \"y\"",
+    ] {
+        let config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_err(), "{}", toml);
+    }
+}
+
+#[test]
+fn hashed_and_plain_passwords_verify() {
+    let hashed = hash_password("correct-horse").unwrap();
+    assert!(hashed.starts_with("pbkdf2-sha256$"));
+    assert_ne!(hashed, hash_password("correct-horse").unwrap());
+    assert!(verify_password(&hashed, "correct-horse"));
+    assert!(!verify_password(&hashed, "correct-hors"));
+    assert!(!verify_password(
+        "pbkdf2-sha256$0$AAAA$AAAA",
+        "correct-horse"
+    ));
+
+    assert!(verify_password("plain-secret", "plain-secret"));
+    assert!(!verify_password("plain-secret", "plain-secreT"));
+}
+
+#[tokio::test]
+async fn default_credentials_and_disabled_auth_are_flagged() {
+    assert_eq!(
+        Config::default().dashboard.security_warning(),
+        Some(DashboardSecurityWarning::AuthDisabled)
+    );
+
+    let mut config = parse(
+        r#"
+        [dashboard]
+        auth_enabled = true
+        [[dashboard.users]]
+        username = "admin"
+        password = "admin"
+        "#,
+    );
+    assert_eq!(
+        config.dashboard.security_warning(),
+        Some(DashboardSecurityWarning::DefaultCredentials)
+    );
+    config.dashboard.users[0].password = hash_password("admin").unwrap();
+    assert_eq!(
+        config.dashboard.security_warning(),
+        Some(DashboardSecurityWarning::DefaultCredentials)
+    );
+    config.dashboard.users[0].password = hash_password("a-real-password").unwrap();
+    assert_eq!(config.dashboard.security_warning(), None);
+
+    let manager = ConfigManager::new(config, None);
+    assert!(
+        manager
+            .authenticate_dashboard("admin", "a-real-password")
+            .await
+    );
+    assert!(!manager
+        .complete_setup(DashboardUser {
+            username: "intruder".to_string(),
+            password: "whatever-it-is".to_string(),
+        })
+        .await
+        .unwrap());
+}
@@ -0,0 +1,143 @@
+//! Cached access decisions: reused per client, target and user, and never
+//! outliving the rules they were made by.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use net_relay_core::proxy::connect::check_target_access;
+use net_relay_core::{AccessControlConfig, Config, ConfigManager, DenyReason, Stats};
+
+const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+
+fn manager(toml: &str) -> ConfigManager {
+    ConfigManager::new(toml::from_str(toml).unwrap(), None)
+}
+
+fn access_control(toml: &str) -> AccessControlConfig {
+    let config: Config = toml::from_str(toml).unwrap();
+    config.access_control
+}
+
+fn allowed(manager: &ConfigManager, host: &str, username: Option<&str>) -> (bool, bool) {
+    let (check, cached) = manager.evaluate_target_cached(CLIENT, host, 443, username);
+    (check.block.is_none(), cached)
+}
+
+const DENY_ADS: &str = r#"
+    [[access_control.rules]]
+    id = "ads"
+    domain = "ads.example"
+    action = "deny"
+"#;
+
+#[test]
+fn decisions_are_cached_per_client_target_and_user() {
+    let manager = manager(DENY_ADS);
+
+    assert_eq!(allowed(&manager, "ads.example", None), (false, false));
+    assert_eq!(allowed(&manager, "ads.example", None), (false, true));
+    assert_eq!(allowed(&manager, "www.example", None), (true, false));
+
+    // Another user, port or client is a separate decision
+    assert_eq!(
+        allowed(&manager, "ads.example", Some("alice")),
+        (false, false)
+    );
+    assert_eq!(
+        allowed(&manager, "ads.example", Some("alice")),
+        (false, true)
+    );
+    let (_, cached) = manager.evaluate_target_cached(CLIENT, "ads.example", 80, None);
+    assert!(!cached);
+    let other: IpAddr = "10.0.0.2".parse().unwrap();
+    let (check, cached) = manager.evaluate_target_cached(other, "ads.example", 443, None);
+    assert!(!cached);
+    assert_eq!(check.block.unwrap().rule_id.as_deref(), Some("ads"));
+}
+
+#[tokio::test]
+async fn rule_changes_apply_immediately() {
+    let manager = manager("");
+    assert_eq!(allowed(&manager, "ads.example", None), (true, false));
+    assert_eq!(allowed(&manager, "ads.example", None), (true, true));
+
+    manager
+        .update_access_control(access_control(DENY_ADS))
+        .await
+        .unwrap();
+    assert_eq!(allowed(&manager, "ads.example", None), (false, false));
+    assert_eq!(allowed(&manager, "ads.example", None), (false, true));
+
+    // Whole-config updates too
+    let mut config = manager.get().await;
+    config.access_control.rules.clear();
+    manager.update(config).await.unwrap();
+    assert_eq!(allowed(&manager, "ads.example", None), (true, false));
+}
+
+#[tokio::test]
+async fn activating_a_profile_invalidates_decisions() {
+    let manager = manager(
+        r#"
+        active_profile = "open"
+
+        [profiles.open.access_control]
+        allow_by_default = true
+
+        [profiles.closed.access_control]
+        allow_by_default = false
+        "#,
+    );
+    assert_eq!(allowed(&manager, "www.example", None), (true, false));
+    assert_eq!(allowed(&manager, "www.example", None), (true, true));
+
+    assert!(manager.activate_profile("closed").await.unwrap());
+    assert_eq!(allowed(&manager, "www.example", None), (false, false));
+}
+
+#[tokio::test]
+async fn decisions_expire_with_the_rules() {
+    let expires_at = chrono::Utc::now() + chrono::Duration::milliseconds(300);
+    let manager = manager(&format!(
+        r#"
+        [[access_control.rules]]
+        id = "temporary"
+        domain = "ads.example"
+        action = "deny"
+        expires_at = "{}"
+        "#,
+        expires_at.to_rfc3339()
+    ));
+    assert_eq!(allowed(&manager, "ads.example", None), (false, false));
+    assert_eq!(allowed(&manager, "ads.example", None), (false, true));
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(allowed(&manager, "ads.example", None), (true, false));
+}
+
+#[tokio::test]
+async fn hits_and_misses_are_counted() {
+    let manager = manager(DENY_ADS);
+    let stats = Stats::new(10);
+    let client = SocketAddr::new(CLIENT, 40000);
+
+    for _ in 0..3 {
+        let result = check_target_access(
+            "ads.example",
+            443,
+            DenyReason::Target,
+            client,
+            Some("alice"),
+            "http",
+            &stats,
+            &manager,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    let counts = stats.get_aggregated().await.decision_cache;
+    assert_eq!((counts.hits, counts.misses), (2, 1));
+    // Cached decisions still count as rule hits
+    assert_eq!(stats.get_rule_hits()["ads"].enforced, 3);
+}
@@ -0,0 +1,284 @@
+//! HTTP Digest proxy authentication: published test vectors and the
+//! challenge-response exchange with the HTTP CONNECT proxy.
+
+use std::net::SocketAddr;
+
+use net_relay_core::proxy::digest::{
+    challenges, digest_response, DigestAlgorithm, DigestCredentials, NonceStatus, NonceStore,
+};
+use net_relay_core::proxy::HttpProxy;
+use net_relay_core::{Config, ConfigManager};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[test]
+fn rfc_2617_vector() {
+    let response = digest_response(
+        DigestAlgorithm::Md5,
+        "Mufasa",
+        "testrealm@host.com",
+        "Circle Of Life",
+        "GET",
+        "/dir/index.html",
+        "dcd98b7102dd2f0e8b11d0f600bfb0c093",
+        "00000001",
+        "0a4f113b",
+    );
+    assert_eq!(response, "6629fae49393a05397450978507c4ef1");
+}
+
+#[test]
+fn rfc_7616_vectors() {
+    let vectors = [
+        (DigestAlgorithm::Md5, "8ca523f5e9506fed4657c9700eebdbec"),
+        (
+            DigestAlgorithm::Sha256,
+            "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1",
+        ),
+    ];
+    for (algorithm, expected) in vectors {
+        let response = digest_response(
+            algorithm,
+            "Mufasa",
+            "http-auth@example.org",
+            "Circle of Life",
+            "GET",
+            "/dir/index.html",
+            "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v",
+            "00000001",
+            "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+        );
+        assert_eq!(response, expected, "{:?}", algorithm);
+    }
+}
+
+#[test]
+fn parses_authorization_parameters() {
+    let credentials = DigestCredentials::parse(
+        r#"username="Mufasa", realm="http-auth@example.org", uri="/dir/index.html",
+        algorithm=SHA-256, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", nc=00000001,
+        cnonce="f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ", qop=auth,
+        response="753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1",
+        opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#,
+    )
+    .unwrap();
+    assert_eq!(credentials.username, "Mufasa");
+    assert_eq!(credentials.uri, "/dir/index.html");
+    assert_eq!(credentials.algorithm.as_deref(), Some("SHA-256"));
+    assert_eq!(credentials.nc.as_deref(), Some("00000001"));
+    assert_eq!(credentials.qop.as_deref(), Some("auth"));
+
+    let escaped = DigestCredentials::parse(
+        r#"username="a\"b", realm="Proxy", nonce="n", uri="h:1", response="r""#,
+    )
+    .unwrap();
+    assert_eq!(escaped.username, "a\"b");
+
+    assert!(DigestCredentials::parse(r#"username="a", realm="Proxy""#).is_none());
+    assert!(DigestCredentials::parse(r#"username="unterminated"#).is_none());
+}
+
+#[test]
+fn nonce_counts_are_used_once() {
+    let nonces = NonceStore::default();
+    let nonce = nonces.issue().unwrap();
+    assert_eq!(nonces.consume(&nonce, 1), NonceStatus::Fresh);
+    assert_eq!(nonces.consume(&nonce, 1), NonceStatus::Replayed);
+    assert_eq!(nonces.consume(&nonce, 3), NonceStatus::Fresh);
+    assert_eq!(nonces.consume(&nonce, 2), NonceStatus::Replayed);
+    assert_eq!(nonces.consume("unknown", 1), NonceStatus::Stale);
+    assert_eq!(nonces.len(), 1);
+}
+
+async fn start_echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+/// Start an HTTP proxy requiring alice's password.
+async fn start_proxy() -> SocketAddr {
+    let config: Config = toml::from_str(
+        r#"
+        [security]
+        auth_enabled = true
+
+        [[security.users]]
+        username = "alice"
+        password = "wonderland"
+        "#,
+    )
+    .unwrap();
+    let proxy = HttpProxy::builder()
+        .config(ConfigManager::new(config, None))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+    addr
+}
+
+/// Send a CONNECT with an optional `Proxy-Authorization` value and return
+/// the response head and the stream.
+async fn connect(proxy: SocketAddr, target: &str, auth: Option<&str>) -> (String, TcpStream) {
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    let head = request(&mut stream, target, auth).await;
+    (head, stream)
+}
+
+/// Send a CONNECT on `stream` and return the response head.
+async fn request(stream: &mut TcpStream, target: &str, auth: Option<&str>) -> String {
+    let auth = auth
+        .map(|value| format!("Proxy-Authorization: {}\r\n", value))
+        .unwrap_or_default();
+    let request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n{1}\r\n", target, auth);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0u8; 1];
+        if stream.read(&mut byte).await.unwrap() == 0 {
+            break;
+        }
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8(head).unwrap();
+    // Skip the body of an error response
+    if let Some(length) = head
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+    {
+        let mut body = vec![0u8; length.parse().unwrap()];
+        stream.read_exact(&mut body).await.unwrap();
+    }
+    head
+}
+
+/// Nonce of the SHA-256 Digest challenge in a 407 response.
+fn sha256_nonce(head: &str) -> String {
+    let challenge = head
+        .lines()
+        .filter_map(|line| line.strip_prefix("Proxy-Authenticate: Digest "))
+        .find(|challenge| challenge.contains("algorithm=SHA-256"))
+        .unwrap_or_else(|| panic!("no SHA-256 challenge in {}", head));
+    let nonce = challenge.split("nonce=\"").nth(1).unwrap();
+    nonce[..nonce.find('"').unwrap()].to_string()
+}
+
+fn authorization(
+    algorithm: DigestAlgorithm,
+    password: &str,
+    target: &str,
+    nonce: &str,
+    nc: &str,
+) -> String {
+    let cnonce = "0a4f113b";
+    let response = digest_response(
+        algorithm, "alice", "Proxy", password, "CONNECT", target, nonce, nc, cnonce,
+    );
+    format!(
+        "Digest username=\"alice\", realm=\"Proxy\", nonce=\"{}\", uri=\"{}\", \
+         algorithm={}, qop=auth, nc={}, cnonce=\"{}\", response=\"{}\"",
+        nonce,
+        target,
+        algorithm.as_str(),
+        nc,
+        cnonce,
+        response
+    )
+}
+
+#[tokio::test]
+async fn digest_handshake() {
+    let echo = start_echo_server().await;
+    let proxy = start_proxy().await;
+    let target = format!("127.0.0.1:{}", echo.port());
+
+    let (head, mut stream) = connect(proxy, &target, None).await;
+    assert!(head.starts_with("HTTP/1.1 407"), "{}", head);
+    assert!(head.contains("Proxy-Authenticate: Basic realm=\"Proxy\""));
+    assert!(head.contains("algorithm=MD5"));
+    assert!(!head.contains("stale=true"));
+    let nonce = sha256_nonce(&head);
+
+    let auth = authorization(
+        DigestAlgorithm::Sha256,
+        "wonderland",
+        &target,
+        &nonce,
+        "00000001",
+    );
+    // Answered on the connection that got the challenge
+    let head = request(&mut stream, &target, Some(&auth)).await;
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    stream.write_all(b"ping").await.unwrap();
+    let mut echoed = [0u8; 4];
+    stream.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"ping");
+
+    // The same header again is a replay; a higher count is accepted
+    let (head, _) = connect(proxy, &target, Some(&auth)).await;
+    assert!(head.starts_with("HTTP/1.1 407"), "{}", head);
+    assert!(!head.contains("stale=true"));
+    let auth = authorization(
+        DigestAlgorithm::Md5,
+        "wonderland",
+        &target,
+        &nonce,
+        "00000002",
+    );
+    let (head, _) = connect(proxy, &target, Some(&auth)).await;
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+
+    // Wrong password, or a digest for another target
+    let auth = authorization(
+        DigestAlgorithm::Sha256,
+        "wrong",
+        &target,
+        &nonce,
+        "00000003",
+    );
+    let (head, _) = connect(proxy, &target, Some(&auth)).await;
+    assert!(head.starts_with("HTTP/1.1 407"), "{}", head);
+    let auth = authorization(
+        DigestAlgorithm::Sha256,
+        "wonderland",
+        "other:1",
+        &nonce,
+        "00000003",
+    );
+    let (head, _) = connect(proxy, &target, Some(&auth)).await;
+    assert!(head.starts_with("HTTP/1.1 407"), "{}", head);
+
+    // A nonce the proxy didn't issue (e.g. before a restart) is stale
+    let auth = authorization(
+        DigestAlgorithm::Sha256,
+        "wonderland",
+        &target,
+        "abc",
+        "00000001",
+    );
+    let (head, _) = connect(proxy, &target, Some(&auth)).await;
+    assert!(head.starts_with("HTTP/1.1 407"), "{}", head);
+    assert!(head.contains("stale=true"));
+    assert_ne!(sha256_nonce(&head), nonce);
+}
+
+#[test]
+fn challenges_offer_sha256_first() {
+    let offered = challenges("Proxy", "n", true);
+    assert_eq!(
+        offered,
+        [
+            "Digest realm=\"Proxy\", qop=\"auth\", algorithm=SHA-256, nonce=\"n\", stale=true",
+            "Digest realm=\"Proxy\", qop=\"auth\", algorithm=MD5, nonce=\"n\", stale=true",
+        ]
+    );
+}
@@ -0,0 +1,184 @@
+//! Lookup latency and failures per resolver, and the resolution time of
+//! each connection.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use net_relay_core::config::{DnsConfig, DnsMode};
+use net_relay_core::proxy::HttpProxy;
+use net_relay_core::stats::HistorySince;
+use net_relay_core::{Config, ConfigManager, DnsResolver, Stats};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+/// A UDP resolver answering every A query with 127.0.0.1 after `delay`,
+/// or never answering when `delay` is `None`.
+async fn start_resolver(delay: Option<Duration>) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+            let Some(delay) = delay else { continue };
+            let query = &buf[..n];
+            let qtype = u16::from_be_bytes([query[n - 4], query[n - 3]]);
+            let answers: u16 = if qtype == 1 { 1 } else { 0 };
+            let mut response = query[..2].to_vec();
+            response.extend_from_slice(&[0x81, 0x80, 0, 1]);
+            response.extend_from_slice(&answers.to_be_bytes());
+            response.extend_from_slice(&[0, 0, 0, 0]);
+            response.extend_from_slice(&query[12..]);
+            if answers == 1 {
+                // Name pointer, A, IN, TTL 60, 4 bytes
+                response.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+                response.extend_from_slice(&[127, 0, 0, 1]);
+            }
+            tokio::time::sleep(delay).await;
+            let _ = socket.send_to(&response, peer).await;
+        }
+    });
+    addr
+}
+
+fn udp_config(resolver: SocketAddr) -> DnsConfig {
+    DnsConfig {
+        mode: DnsMode::Udp,
+        resolver: Some(resolver.to_string()),
+        timeout_secs: 1,
+        slow_lookup_ms: 30,
+        ..DnsConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn lookups_are_timed_per_resolver() {
+    let resolver = start_resolver(Some(Duration::from_millis(50))).await;
+    let config = udp_config(resolver);
+    let dns = DnsResolver::new();
+
+    let addrs = dns.resolve("slow.example", &config).await.unwrap();
+    assert_eq!(addrs, ["127.0.0.1".parse::<std::net::IpAddr>().unwrap()]);
+    // Cached answers aren't lookups
+    dns.resolve("slow.example", &config).await.unwrap();
+    // Neither are IP targets
+    dns.resolve("192.0.2.1", &config).await.unwrap();
+
+    let stats = dns.stats(&config);
+    assert_eq!(stats.cache_hits, 1);
+    assert_eq!(stats.resolvers.len(), 1);
+    let udp = &stats.resolvers[0];
+    assert_eq!(udp.resolver, resolver.to_string());
+    assert_eq!(udp.mode, DnsMode::Udp);
+    assert_eq!(udp.lookups, 1);
+    assert_eq!(udp.failures, 0);
+    assert_eq!(udp.slow_lookups, 1);
+    assert_eq!(udp.latency.count, 1);
+    assert!(udp.latency.max_ms >= 50, "{}", udp.latency.max_ms);
+
+    // Not slow under a higher threshold
+    let relaxed = DnsConfig {
+        slow_lookup_ms: 10_000,
+        ..config.clone()
+    };
+    dns.resolve("other.example", &relaxed).await.unwrap();
+    assert_eq!(dns.stats(&relaxed).resolvers[0].slow_lookups, 1);
+}
+
+#[tokio::test]
+async fn timeouts_count_as_failures() {
+    let silent = start_resolver(None).await;
+    let config = udp_config(silent);
+    let dns = DnsResolver::new();
+
+    assert!(dns.resolve("lost.example", &config).await.is_err());
+    let stats = dns.stats(&config);
+    assert_eq!(stats.failures, 1);
+    let udp = &stats.resolvers[0];
+    assert_eq!((udp.lookups, udp.failures, udp.timeouts), (1, 1, 1));
+    assert!(udp.latency.max_ms >= 1000);
+
+    // Falling back to system DNS counts against the system resolver
+    let fallback = DnsConfig {
+        fallback: true,
+        ..config
+    };
+    dns.resolve("localhost", &fallback).await.unwrap();
+    let stats = dns.stats(&fallback);
+    let resolvers: Vec<&str> = stats
+        .resolvers
+        .iter()
+        .map(|r| r.resolver.as_str())
+        .collect();
+    assert_eq!(resolvers, [silent.to_string().as_str(), "system"]);
+    assert_eq!(stats.resolvers[1].lookups, 1);
+    assert_eq!(stats.resolvers[1].failures, 0);
+}
+
+async fn start_echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+/// CONNECT to `target` through the proxy and close once it's established.
+async fn connect_through(proxy: SocketAddr, target: &str) {
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    let request = format!("CONNECT {} HTTP/1.1\r\n\r\n", target);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = [0u8; 12];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(&response, b"HTTP/1.1 200");
+}
+
+#[tokio::test]
+async fn connections_record_their_resolution_time() {
+    let echo = start_echo_server().await;
+    let resolver = start_resolver(Some(Duration::from_millis(20))).await;
+    let config = Config {
+        dns: udp_config(resolver),
+        ..Config::default()
+    };
+    let stats = Arc::new(Stats::new(10));
+    let proxy = HttpProxy::builder()
+        .stats(Arc::clone(&stats))
+        .config(ConfigManager::new(config, None))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+
+    connect_through(proxy_addr, &format!("echo.example:{}", echo.port())).await;
+    connect_through(proxy_addr, &echo.to_string()).await;
+
+    let mut history = Vec::new();
+    for _ in 0..100 {
+        history = stats
+            .get_history(None, HistorySince::default())
+            .await
+            .connections;
+        if history.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(history.len(), 2);
+    for entry in &history {
+        let info = &entry.info;
+        if info.target_addr == "echo.example" {
+            let resolve_ms = info.resolve_ms.unwrap();
+            assert!(resolve_ms >= 20, "{}", resolve_ms);
+            assert!(resolve_ms <= info.connect_ms.unwrap());
+        } else {
+            assert_eq!(info.resolve_ms, None);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! Proxies built for embedding: optional stats and config, shutdown token.
+
+use std::time::Duration;
+
+use net_relay_core::proxy::{CancellationToken, HttpProxy};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[tokio::test]
+async fn proxy_built_with_defaults_relays_and_stops_on_shutdown() {
+    let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let echo_addr = echo.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = echo.accept().await.unwrap();
+        let (mut read, mut write) = stream.split();
+        let _ = tokio::io::copy(&mut read, &mut write).await;
+    });
+
+    let shutdown = CancellationToken::new();
+    let proxy = HttpProxy::builder().shutdown(shutdown.clone()).build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    let task = tokio::spawn(async move { proxy.run(listener).await });
+
+    let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+    let request = format!("CONNECT {} HTTP/1.1\r\n\r\n", echo_addr);
+    client.write_all(request.as_bytes()).await.unwrap();
+    let established = b"HTTP/1.1 200 Connection Established\r\n\r\n";
+    let mut response = vec![0u8; established.len()];
+    client.read_exact(&mut response).await.unwrap();
+    assert_eq!(response, established);
+    client.write_all(b"ping").await.unwrap();
+    let mut echoed = [0u8; 4];
+    client.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"ping");
+
+    shutdown.cancel();
+    tokio::time::timeout(Duration::from_secs(5), task)
+        .await
+        .expect("run returns after shutdown")
+        .unwrap()
+        .unwrap();
+    assert!(TcpStream::connect(proxy_addr).await.is_err());
+}
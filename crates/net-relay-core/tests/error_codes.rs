@@ -0,0 +1,142 @@
+//! Error code mapping tests.
+
+use std::collections::HashSet;
+
+use net_relay_core::{DenyReason, Error, ErrorCode};
+
+/// One error of every variant. The match has no wildcard, so adding a variant
+/// fails to compile until it is listed here (and given a code).
+fn every_error() -> Vec<Error> {
+    match Error::Timeout {
+        Error::Io(_)
+        | Error::InvalidSocks5Protocol(_)
+        | Error::InvalidHttpProtocol(_)
+        | Error::AuthenticationFailed
+        | Error::CredentialsRejected { .. }
+        | Error::ConnectionRefused(_)
+        | Error::Timeout
+        | Error::AddressResolution(_)
+        | Error::UnsupportedCommand(_)
+        | Error::UnsupportedAddressType(_)
+        | Error::Config(_)
+        | Error::MaxConnectionsReached
+        | Error::AccessDenied(..)
+        | Error::QuotaExceeded(_)
+        | Error::Tunnel(_)
+        | Error::Http(_)
+        | Error::Acme(_) => {}
+    }
+
+    vec![
+        Error::Io(std::io::Error::other("broken")),
+        Error::InvalidSocks5Protocol("bad".into()),
+        Error::InvalidHttpProtocol("bad".into()),
+        Error::AuthenticationFailed,
+        Error::CredentialsRejected {
+            username: Some("alice".into()),
+            reason: "empty username".into(),
+        },
+        Error::ConnectionRefused("example.com:443".into()),
+        Error::Timeout,
+        Error::AddressResolution("example.invalid".into()),
+        Error::UnsupportedCommand(0x02),
+        Error::UnsupportedAddressType(0x09),
+        Error::Config("bad".into()),
+        Error::MaxConnectionsReached,
+        Error::AccessDenied(DenyReason::ClientIp, "10.0.0.1".into()),
+        Error::AccessDenied(DenyReason::Target, "example.com:443".into()),
+        Error::AccessDenied(DenyReason::Sni, "example.com".into()),
+        Error::AccessDenied(DenyReason::Upstream, "example.com:443".into()),
+        Error::AccessDenied(DenyReason::Hook, "example.com:443".into()),
+        Error::QuotaExceeded("alice".into()),
+        Error::Tunnel("bad".into()),
+        Error::Http("bad".into()),
+        Error::Acme("bad".into()),
+    ]
+}
+
+#[test]
+fn errors_map_to_codes() {
+    let codes: Vec<&str> = every_error().iter().map(|e| e.code().as_str()).collect();
+    assert_eq!(
+        codes,
+        [
+            "io_error",
+            "invalid_socks5_protocol",
+            "invalid_http_protocol",
+            "authentication_failed",
+            "authentication_failed",
+            "connection_refused",
+            "timeout",
+            "address_resolution_failed",
+            "unsupported_command",
+            "unsupported_address_type",
+            "config_error",
+            "max_connections_reached",
+            "client_ip_denied",
+            "target_denied",
+            "sni_denied",
+            "upstream_denied",
+            "hook_denied",
+            "quota_exceeded",
+            "tunnel_error",
+            "http_error",
+            "acme_error",
+        ]
+    );
+}
+
+#[test]
+fn every_error_code_is_listed() {
+    let all: HashSet<ErrorCode> = ErrorCode::ALL.iter().copied().collect();
+    assert_eq!(all.len(), ErrorCode::ALL.len(), "duplicate entry in ALL");
+    for error in every_error() {
+        assert!(
+            all.contains(&error.code()),
+            "{} missing from ALL",
+            error.code()
+        );
+    }
+}
+
+#[test]
+fn codes_are_unique_and_serialize_as_str() {
+    let mut seen = HashSet::new();
+    for &code in ErrorCode::ALL {
+        assert!(seen.insert(code.as_str()), "duplicate code {}", code);
+        let json = serde_json::to_string(&code).unwrap();
+        assert_eq!(json, format!("\"{}\"", code.as_str()));
+        assert_eq!(serde_json::from_str::<ErrorCode>(&json).unwrap(), code);
+    }
+}
+
+#[test]
+fn codes_are_documented() {
+    let doc = include_str!("../../../docs/ERROR_CODES.md");
+    for &code in ErrorCode::ALL {
+        assert!(
+            doc.contains(&format!("`{}`", code.as_str())),
+            "{} missing from docs/ERROR_CODES.md",
+            code
+        );
+    }
+}
+
+#[test]
+fn replies_follow_codes() {
+    let denied = Error::AccessDenied(DenyReason::Target, "example.com:443".into());
+    assert_eq!(denied.code().socks5_reply(), 0x02);
+    assert_eq!(denied.code().http_status(), 403);
+
+    let unresolved = Error::AddressResolution("example.invalid".into());
+    assert_eq!(unresolved.code().socks5_reply(), 0x04);
+    assert_eq!(unresolved.code().http_status(), 502);
+
+    assert_eq!(Error::AuthenticationFailed.code().http_status(), 407);
+    assert_eq!(Error::MaxConnectionsReached.code().http_status(), 429);
+    assert_eq!(Error::UnsupportedCommand(0x02).code().socks5_reply(), 0x07);
+    assert_eq!(
+        Error::UnsupportedAddressType(0x09).code().socks5_reply(),
+        0x08
+    );
+}
@@ -0,0 +1,79 @@
+//! Connection event log tests.
+
+use std::net::SocketAddr;
+
+use net_relay_core::connection::{CloseReason, ConnectionInfo, Protocol};
+use net_relay_core::stats::DeniedAttempt;
+use net_relay_core::{
+    DenyReason, Error, ErrorCode, EventLog, EventLogBackpressure, LogRotation, Stats,
+};
+use serde_json::Value;
+
+#[tokio::test]
+async fn events_are_written_as_json_lines() {
+    let dir = std::env::temp_dir().join(format!("net-relay-events-{}", uuid::Uuid::new_v4()));
+    let path = dir.join("events.jsonl");
+    let event_log = EventLog::open(&path, LogRotation::Never, EventLogBackpressure::Block).unwrap();
+    let stats = Stats::new(10).with_event_log(event_log);
+
+    let mut info = ConnectionInfo::new(
+        Protocol::Socks5,
+        "10.0.0.5:40000".to_string(),
+        "example.com".to_string(),
+        443,
+    );
+    info.username = Some("alice".to_string());
+    let id = info.id;
+    stats.add_connection(info).await;
+    stats
+        .close_connection(id, 100, 200, CloseReason::TargetEof)
+        .await;
+
+    let client: SocketAddr = "10.0.0.6:40001".parse().unwrap();
+    let denied = Error::AccessDenied(DenyReason::Target, "Target blocked: a.example:443".into());
+    stats.record_rejected("http", client, &denied).await;
+    stats
+        .record_rejected("socks5", client, &Error::AuthenticationFailed)
+        .await;
+    // Not a denial or failed login
+    stats
+        .record_rejected("socks5", client, &Error::Timeout)
+        .await;
+    stats
+        .record_denied(DeniedAttempt::new(
+            "10.0.0.7",
+            "dashboard",
+            Some("/api/stats".to_string()),
+            "IP not in dashboard allowlist",
+        ))
+        .await;
+
+    // Dropping the last handle flushes the writer
+    drop(stats);
+    let content = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let events: Vec<Value> = content
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let types: Vec<&str> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+    assert_eq!(types, ["open", "close", "denied", "auth_failure", "denied"]);
+    assert!(events.iter().all(|e| e["v"] == 1 && e["time"].is_string()));
+
+    assert_eq!(events[0]["id"], id.to_string());
+    assert_eq!(events[0]["client_ip"], "10.0.0.5");
+    assert_eq!(events[0]["user"], "alice");
+    assert_eq!(events[0]["target"], "example.com:443");
+
+    assert_eq!(events[1]["bytes_sent"], 100);
+    assert_eq!(events[1]["bytes_received"], 200);
+    assert_eq!(events[1]["close_reason"], "target_eof");
+
+    assert_eq!(events[2]["source"], "http");
+    assert_eq!(events[2]["code"], ErrorCode::TargetDenied.as_str());
+    assert_eq!(events[3]["client_ip"], "10.0.0.6");
+    assert!(events[3]["user"].is_null());
+    assert_eq!(events[4]["source"], "dashboard");
+    assert_eq!(events[4]["target"], "/api/stats");
+}
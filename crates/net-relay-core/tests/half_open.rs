@@ -0,0 +1,158 @@
+//! Dead peers and relays: TCP keepalive and the orphaned connection sweep.
+
+use std::time::Duration;
+
+use net_relay_core::connection::{CloseReason, ConnectionInfo, Protocol};
+use net_relay_core::proxy::tcp_keepalive::set_keepalive;
+use net_relay_core::stats::HistorySince;
+use net_relay_core::{Config, Stats, TcpKeepaliveConfig};
+use socket2::SockRef;
+use tokio::net::{TcpListener, TcpStream};
+
+#[tokio::test]
+async fn keepalive_is_set_as_configured() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let socket = SockRef::from(&stream);
+    assert!(!socket.keepalive().unwrap());
+
+    let disabled = TcpKeepaliveConfig {
+        enabled: false,
+        ..TcpKeepaliveConfig::default()
+    };
+    set_keepalive(&stream, &disabled).unwrap();
+    assert!(!socket.keepalive().unwrap());
+
+    let config = TcpKeepaliveConfig {
+        enabled: true,
+        idle_secs: 30,
+        interval_secs: 5,
+        count: 3,
+    };
+    set_keepalive(&stream, &config).unwrap();
+    assert!(socket.keepalive().unwrap());
+    #[cfg(target_os = "linux")]
+    {
+        assert_eq!(
+            socket.tcp_keepalive_time().unwrap(),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            socket.tcp_keepalive_interval().unwrap(),
+            Duration::from_secs(5)
+        );
+        assert_eq!(socket.tcp_keepalive_retries().unwrap(), 3);
+    }
+    assert_eq!(config.detection_time(), Some(Duration::from_secs(45)));
+    assert_eq!(disabled.detection_time(), None);
+}
+
+#[test]
+fn keepalive_settings_are_validated() {
+    let config: Config = toml::from_str(
+        r#"
+        [limits.tcp_keepalive]
+        count = 0
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_err());
+
+    let config: Config = toml::from_str(
+        r#"
+        [limits.tcp_keepalive]
+        enabled = false
+        count = 0
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    assert!(Config::default().limits.tcp_keepalive.enabled);
+}
+
+fn connection() -> ConnectionInfo {
+    ConnectionInfo::new(
+        Protocol::Socks5,
+        "192.0.2.1:40000".to_string(),
+        "example.com".to_string(),
+        443,
+    )
+}
+
+#[tokio::test]
+async fn connections_outliving_their_relay_are_closed_as_orphaned() {
+    let stats = Stats::new(10);
+    let connecting = connection();
+    stats.add_connection(connecting.clone()).await;
+    let info = connection();
+    let id = info.id;
+    stats.add_connection(info).await;
+
+    // A running relay holds the connection's counters
+    let counters = stats.relay_counters(id);
+    stats.mark_active(id);
+    assert_eq!(stats.sweep_orphans().await, 0);
+    assert!(stats.get_stale(None).await.is_empty());
+
+    // The relay task exits without closing the connection
+    drop(counters);
+    assert_eq!(stats.sweep_orphans().await, 0);
+    let stale = stats.get_stale(None).await;
+    assert_eq!(stale.len(), 1);
+    assert_eq!(stale[0].id, id);
+    assert!(stale[0].stale);
+
+    assert_eq!(stats.sweep_orphans().await, 1);
+    let active = stats.get_active().await;
+    assert_eq!(active.len(), 1);
+    // Connections still being set up have no relay yet and are left alone
+    assert_eq!(active[0].id, connecting.id);
+    let history = stats.get_history(None, HistorySince::default()).await;
+    assert_eq!(history.connections.len(), 1);
+    let closed = &history.connections[0].info;
+    assert_eq!(closed.id, id);
+    assert_eq!(closed.close_reason, Some(CloseReason::Orphaned));
+    assert!(!closed.stale);
+}
+
+#[tokio::test]
+async fn relays_closing_between_sweeps_are_not_orphaned() {
+    let stats = Stats::new(10);
+    let info = connection();
+    let id = info.id;
+    stats.add_connection(info).await;
+    let counters = stats.relay_counters(id);
+    stats.mark_active(id);
+    drop(counters);
+
+    // Flagged by one sweep, but the relay records its own close before the next
+    assert_eq!(stats.sweep_orphans().await, 0);
+    let closed = stats
+        .close_connection(id, 1, 2, CloseReason::ClientEof)
+        .await
+        .unwrap();
+    assert!(!closed.stale);
+    assert_eq!(stats.sweep_orphans().await, 0);
+    assert_eq!(stats.get_aggregated().await.active_connections, 0);
+}
+
+#[tokio::test]
+async fn silent_relays_are_reported_stale() {
+    let stats = Stats::new(10);
+    let info = connection();
+    let id = info.id;
+    stats.add_connection(info).await;
+    let _counters = stats.relay_counters(id);
+    stats.mark_active(id);
+
+    assert!(stats
+        .get_stale(Some(Duration::from_secs(60)))
+        .await
+        .is_empty());
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let stale = stats.get_stale(Some(Duration::from_millis(10))).await;
+    assert_eq!(stale.len(), 1);
+    assert!(!stale[0].stale);
+}
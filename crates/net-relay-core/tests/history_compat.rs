@@ -0,0 +1,77 @@
+//! Reading connection history written by earlier releases.
+
+use net_relay_core::connection::Protocol;
+use net_relay_core::stats::ConnectionStats;
+use net_relay_core::{ConnectionState, CONNECTION_SCHEMA_VERSION};
+use serde_json::Value;
+
+const HISTORY_0_1_0: &str = include_str!("fixtures/history-0.1.0.json");
+
+#[test]
+fn history_from_0_1_0_still_reads() {
+    let entries: Vec<ConnectionStats> = serde_json::from_str(HISTORY_0_1_0).unwrap();
+    assert_eq!(entries.len(), 3);
+
+    let first = &entries[0].info;
+    assert_eq!(first.schema_version, 1);
+    assert_eq!(first.protocol, Protocol::Socks5);
+    assert_eq!(first.target_addr, "example.com");
+    assert_eq!(first.state, ConnectionState::Closed);
+    assert_eq!(first.bytes_received, 1294811);
+    assert_eq!(first.username.as_deref(), Some("alice"));
+    assert!(first.tags.is_empty());
+    assert!(first.close_reason.is_none());
+    assert_eq!(entries[0].seq, 0);
+
+    assert_eq!(entries[1].info.protocol, Protocol::HttpConnect);
+    assert!(entries[1].info.username.is_none());
+    assert_eq!(entries[2].info.state, ConnectionState::Active);
+    assert!(entries[2].info.closed_at.is_none());
+}
+
+#[test]
+fn history_from_0_1_0_round_trips() {
+    let old: Vec<Value> = serde_json::from_str(HISTORY_0_1_0).unwrap();
+    let entries: Vec<ConnectionStats> = serde_json::from_str(HISTORY_0_1_0).unwrap();
+    let written = serde_json::to_value(&entries).unwrap();
+
+    for (old, new) in old.iter().zip(written.as_array().unwrap()) {
+        for (field, value) in old.as_object().unwrap() {
+            assert_eq!(&new[field], value, "field {}", field);
+        }
+        assert_eq!(new["schema_version"], CONNECTION_SCHEMA_VERSION);
+    }
+
+    let again: Vec<ConnectionStats> = serde_json::from_value(written).unwrap();
+    assert_eq!(again.len(), entries.len());
+    for (a, b) in again.iter().zip(&entries) {
+        assert_eq!(a.info.id, b.info.id);
+        assert_eq!(a.info.protocol, b.info.protocol);
+        assert_eq!(a.info.connected_at, b.info.connected_at);
+    }
+}
+
+#[test]
+fn entries_from_a_later_release_keep_unknown_protocols() {
+    let entry = serde_json::json!({
+        "schema_version": CONNECTION_SCHEMA_VERSION + 1,
+        "id": "5c3e9b2f-0a41-4d8e-a6f7-1b2c3d4e5f60",
+        "protocol": "quic",
+        "client_addr": "10.0.0.8:5000",
+        "target_addr": "example.net",
+        "target_port": 443,
+        "state": "closed",
+        "connected_at": "2027-01-01T00:00:00Z",
+        "closed_at": "2027-01-01T00:00:01Z",
+        "bytes_sent": 1,
+        "bytes_received": 2,
+        "stream_count": 3
+    });
+    let stats: ConnectionStats = serde_json::from_value(entry).unwrap();
+    assert_eq!(stats.info.protocol, Protocol::Other("quic".to_string()));
+    assert_eq!(stats.info.protocol.as_str(), "quic");
+    assert_eq!(stats.info.schema_version, CONNECTION_SCHEMA_VERSION + 1);
+
+    let written = serde_json::to_value(&stats).unwrap();
+    assert_eq!(written["protocol"], "quic");
+}
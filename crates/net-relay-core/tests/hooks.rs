@@ -0,0 +1,174 @@
+//! Connection hooks registered on the proxy builders.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use net_relay_core::hooks::{ConnectionHook, HookDecision, HookFailurePolicy};
+use net_relay_core::proxy::{
+    HttpConnectClient, HttpProxy, ProxyBuilder, Socks5Client, Socks5Proxy,
+};
+use net_relay_core::{CloseReason, ConnectionInfo, Error, ErrorCode, Stats};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// What a [`TestHook`] does in `on_connect`.
+#[derive(Clone, Copy)]
+enum Behavior {
+    Allow,
+    Deny,
+    Fail,
+    Panic,
+}
+
+struct TestHook {
+    behavior: Behavior,
+    closed: Mutex<Vec<ConnectionInfo>>,
+}
+
+impl TestHook {
+    fn new(behavior: Behavior) -> Arc<Self> {
+        Arc::new(Self {
+            behavior,
+            closed: Mutex::default(),
+        })
+    }
+
+    /// Wait for the first connection reported closed.
+    async fn closed(&self) -> ConnectionInfo {
+        for _ in 0..100 {
+            if let Some(info) = self.closed.lock().unwrap().first() {
+                return info.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("on_close was not called");
+    }
+}
+
+impl ConnectionHook for TestHook {
+    fn name(&self) -> &str {
+        "test"
+    }
+
+    fn on_connect<'a>(
+        &'a self,
+        _info: &'a ConnectionInfo,
+    ) -> BoxFuture<'a, anyhow::Result<HookDecision>> {
+        Box::pin(async move {
+            match self.behavior {
+                Behavior::Allow => Ok(HookDecision::Allow),
+                Behavior::Deny => Ok(HookDecision::Deny("not today".to_string())),
+                Behavior::Fail => anyhow::bail!("backend down"),
+                Behavior::Panic => panic!("hook bug"),
+            }
+        })
+    }
+
+    fn on_close<'a>(&'a self, info: &'a ConnectionInfo) -> BoxFuture<'a, ()> {
+        Box::pin(async move { self.closed.lock().unwrap().push(info.clone()) })
+    }
+}
+
+async fn start_echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+async fn start_http(builder: ProxyBuilder<HttpProxy>) -> (SocketAddr, Arc<Stats>) {
+    let proxy = builder.build();
+    let stats = Arc::clone(proxy.stats());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+    (addr, stats)
+}
+
+#[tokio::test]
+async fn allowed_connections_are_reported_closed() {
+    let echo = start_echo_server().await;
+    let hook = TestHook::new(Behavior::Allow);
+    let proxy = Socks5Proxy::builder()
+        .hook(hook.clone(), HookFailurePolicy::Deny)
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+
+    let mut stream = Socks5Client::connect(proxy_addr, "127.0.0.1", echo.port(), None)
+        .await
+        .unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    let mut echoed = [0u8; 4];
+    stream.read_exact(&mut echoed).await.unwrap();
+    drop(stream);
+
+    let closed = hook.closed().await;
+    assert_eq!(closed.target_port, echo.port());
+    assert_eq!(closed.bytes_sent, 4);
+    assert_eq!(closed.bytes_received, 4);
+    assert_eq!(closed.close_reason, Some(CloseReason::ClientEof));
+}
+
+#[tokio::test]
+async fn unreachable_targets_are_reported_closed_without_reason() {
+    let unused = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = unused.local_addr().unwrap().port();
+    drop(unused);
+
+    let hook = TestHook::new(Behavior::Allow);
+    let (proxy, _) =
+        start_http(HttpProxy::builder().hook(hook.clone(), HookFailurePolicy::Deny)).await;
+    let result = HttpConnectClient::connect(proxy, "127.0.0.1", port, None).await;
+    assert!(matches!(result, Err(Error::ConnectionRefused(_))));
+
+    let closed = hook.closed().await;
+    assert_eq!(closed.target_port, port);
+    assert_eq!(closed.close_reason, None);
+    assert!(closed.closed_at.is_some());
+}
+
+#[tokio::test]
+async fn denials_and_failures_follow_the_policy() {
+    let echo = start_echo_server().await;
+    let cases = [
+        (Behavior::Deny, HookFailurePolicy::Allow, false),
+        (Behavior::Fail, HookFailurePolicy::Deny, false),
+        (Behavior::Panic, HookFailurePolicy::Deny, false),
+        (Behavior::Fail, HookFailurePolicy::Allow, true),
+        (Behavior::Panic, HookFailurePolicy::Allow, true),
+    ];
+    for (behavior, on_failure, allowed) in cases {
+        let hook = TestHook::new(behavior);
+        let (proxy, stats) = start_http(HttpProxy::builder().hook(hook.clone(), on_failure)).await;
+        let result = HttpConnectClient::connect(proxy, "127.0.0.1", echo.port(), None).await;
+        if allowed {
+            drop(result.unwrap());
+            assert_eq!(hook.closed().await.target_port, echo.port());
+        } else {
+            assert!(matches!(result, Err(Error::AccessDenied(..))));
+            // The denial is recorded after the response is sent
+            let mut denied = Vec::new();
+            for _ in 0..100 {
+                denied = stats.get_denied(None).await;
+                if !denied.is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            assert_eq!(denied.len(), 1);
+            assert_eq!(denied[0].code, Some(ErrorCode::HookDenied));
+            assert!(hook.closed.lock().unwrap().is_empty());
+        }
+    }
+}
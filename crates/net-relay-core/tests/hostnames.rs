@@ -0,0 +1,185 @@
+//! Normalized target host names: case, trailing dots and IDN forms.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use net_relay_core::hostname::{normalize, normalize_pattern, to_unicode};
+use net_relay_core::proxy::HttpProxy;
+use net_relay_core::stats::HistorySince;
+use net_relay_core::{AccessMatcher, AccessRule, Config, ConfigManager, Stats};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[test]
+fn hosts_are_lowercased_without_trailing_dot() {
+    assert_eq!(normalize("EXAMPLE.com.").unwrap(), "example.com");
+    assert_eq!(normalize("Mail.Example.COM").unwrap(), "mail.example.com");
+    assert_eq!(normalize("example.com").unwrap(), "example.com");
+    assert_eq!(
+        normalize("_dmarc.example.com").unwrap(),
+        "_dmarc.example.com"
+    );
+    assert_eq!(normalize("192.0.2.1").unwrap(), "192.0.2.1");
+    assert_eq!(normalize("[2001:db8::1]").unwrap(), "[2001:db8::1]");
+}
+
+#[test]
+fn internationalized_names_become_punycode() {
+    assert_eq!(
+        normalize("bücher.example").unwrap(),
+        "xn--bcher-kva.example"
+    );
+    assert_eq!(
+        normalize("BÜCHER.example.").unwrap(),
+        "xn--bcher-kva.example"
+    );
+    assert_eq!(
+        normalize("xn--bcher-kva.example").unwrap(),
+        "xn--bcher-kva.example"
+    );
+    assert_eq!(
+        to_unicode("xn--bcher-kva.example").as_deref(),
+        Some("bücher.example")
+    );
+    assert_eq!(to_unicode("example.com"), None);
+
+    assert_eq!(
+        normalize_pattern("*.Bücher.example.").unwrap(),
+        "*.xn--bcher-kva.example"
+    );
+    assert_eq!(normalize_pattern("*.example.com").unwrap(), "*.example.com");
+}
+
+#[test]
+fn invalid_names_are_refused() {
+    for host in [
+        "",
+        ".",
+        "a..example",
+        "exa mple.com",
+        "a/b.example",
+        "user@example.com",
+    ] {
+        assert!(normalize(host).is_err(), "{:?}", host);
+    }
+    assert!(normalize(&format!("{}.example", "a".repeat(64))).is_err());
+    assert!(normalize_pattern("*.").is_err());
+}
+
+fn rules_config() -> Config {
+    toml::from_str(
+        r#"
+        [access_control]
+        allow_by_default = true
+
+        [[access_control.rules]]
+        id = "books"
+        domain = "*.Bücher.example"
+        action = "deny"
+
+        [[access_control.rules]]
+        id = "mixed"
+        domain = "Blocked.Example."
+        action = "deny"
+        "#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn rules_match_normalized_hosts() {
+    let config = rules_config();
+    config.validate().unwrap();
+    let matcher = AccessMatcher::new(&config.access_control);
+    for host in [
+        "bücher.example",
+        "WWW.BÜCHER.example.",
+        "shop.xn--bcher-kva.example",
+    ] {
+        let host = normalize(host).unwrap();
+        let block = matcher.check_target(&host, Some(443), None).unwrap_err();
+        assert_eq!(block.rule_id.as_deref(), Some("books"), "{}", host);
+        assert!(!config.access_control.is_target_allowed(&host, 443, None));
+    }
+    let host = normalize("BLOCKED.example.").unwrap();
+    assert!(matcher.check_target(&host, Some(443), None).is_err());
+    assert!(!config.access_control.is_target_allowed(&host, 443, None));
+    assert!(matcher.check_target("example.com", Some(443), None).is_ok());
+}
+
+#[test]
+fn rule_domains_keep_both_forms() {
+    let mut rule: AccessRule = serde_json::from_value(serde_json::json!({
+        "domain": "*.Bücher.example.",
+        "action": "deny",
+    }))
+    .unwrap();
+    rule.normalize_domain().unwrap();
+    assert_eq!(rule.domain, "*.xn--bcher-kva.example");
+    assert_eq!(rule.domain_unicode.as_deref(), Some("*.bücher.example"));
+
+    let mut rule: AccessRule = serde_json::from_value(serde_json::json!({
+        "domain": "a..example",
+        "action": "deny",
+    }))
+    .unwrap();
+    assert!(rule.normalize_domain().is_err());
+}
+
+#[tokio::test]
+async fn proxies_record_the_normalized_target() {
+    let greeter = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_port = greeter.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = greeter.accept().await {
+            let _ = stream.write_all(b"hello").await;
+        }
+    });
+
+    let config: Config = toml::from_str(
+        r#"
+        [access_control]
+        allow_by_default = true
+
+        [[access_control.rules]]
+        id = "books"
+        domain = "bücher.example"
+        action = "deny"
+        "#,
+    )
+    .unwrap();
+    let stats = Arc::new(Stats::new(10));
+    let proxy = HttpProxy::builder()
+        .stats(Arc::clone(&stats))
+        .config(ConfigManager::new(config, None))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr: SocketAddr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+
+    // The punycode form hits the rule written in Unicode
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(b"CONNECT xn--BCHER-kva.example.:443 HTTP/1.1\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let request = format!("CONNECT LocalHost.:{} HTTP/1.1\r\n\r\n", target_port);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let expected = b"HTTP/1.1 200 Connection Established\r\n\r\nhello";
+    let mut response = vec![0u8; expected.len()];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(response, expected);
+    drop(stream);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let history = stats.get_history(None, HistorySince::default()).await;
+    let info = &history.connections[0].info;
+    assert_eq!(info.target_addr, "localhost");
+    assert_eq!(info.requested_host.as_deref(), Some("LocalHost."));
+}
@@ -0,0 +1,165 @@
+//! Identification headers on HTTP proxy responses (`[http_proxy]`).
+
+use std::net::SocketAddr;
+
+use net_relay_core::proxy::response::{HttpResponses, PROXY_AGENT};
+use net_relay_core::proxy::HttpProxy;
+use net_relay_core::{Config, ConfigManager, DenyReason, Error, HttpProxyConfig};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+fn identified() -> HttpProxyConfig {
+    toml::from_str::<Config>(
+        r#"
+        [http_proxy]
+        send_proxy_agent = true
+        headers = { "X-Relay-Site" = "ams-1" }
+        "#,
+    )
+    .unwrap()
+    .http_proxy
+}
+
+#[test]
+fn responses_without_identification() {
+    let responses = HttpResponses::new(&HttpProxyConfig::default());
+    assert_eq!(
+        responses.established(),
+        "HTTP/1.1 200 Connection Established\r\n\r\n"
+    );
+    assert_eq!(
+        responses.error(
+            &Error::AccessDenied(DenyReason::Target, "Target blocked: a:1".into()),
+            &[]
+        ),
+        "HTTP/1.1 403 Forbidden\r\nContent-Type: text/plain\r\nContent-Length: 36\r\n\r\n\
+         Access denied: Target blocked: a:1\r\n"
+    );
+    assert_eq!(
+        responses.error(
+            &Error::AuthenticationFailed,
+            &["Basic realm=\"Proxy\"".to_string()]
+        ),
+        "HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"Proxy\"\r\n\
+         Content-Type: text/plain\r\nContent-Length: 23\r\n\r\nAuthentication failed\r\n"
+    );
+    assert_eq!(
+        responses.error(&Error::ConnectionRefused("a:1".into()), &[]),
+        "HTTP/1.1 502 Bad Gateway\r\nContent-Type: text/plain\r\nContent-Length: 25\r\n\r\n\
+         Connection refused: a:1\r\n"
+    );
+    assert_eq!(
+        responses.status(405, "", "Method not allowed\r\n"),
+        "HTTP/1.1 405 Method Not Allowed\r\nContent-Type: text/plain\r\nContent-Length: 20\r\n\r\n\
+         Method not allowed\r\n"
+    );
+}
+
+#[test]
+fn responses_with_identification() {
+    let responses = HttpResponses::new(&identified());
+    let ident = format!("Proxy-Agent: {}\r\nX-Relay-Site: ams-1\r\n", PROXY_AGENT);
+    assert_eq!(
+        responses.established(),
+        format!("HTTP/1.1 200 Connection Established\r\n{}\r\n", ident)
+    );
+    assert_eq!(
+        responses.error(
+            &Error::AccessDenied(DenyReason::Target, "Target blocked: a:1".into()),
+            &[]
+        ),
+        format!(
+            "HTTP/1.1 403 Forbidden\r\n{}Content-Type: text/plain\r\nContent-Length: 36\r\n\r\n\
+             Access denied: Target blocked: a:1\r\n",
+            ident
+        )
+    );
+    assert_eq!(
+        responses.error(
+            &Error::AuthenticationFailed,
+            &["Basic realm=\"Proxy\"".to_string()]
+        ),
+        format!(
+            "HTTP/1.1 407 Proxy Authentication Required\r\n{}\
+             Proxy-Authenticate: Basic realm=\"Proxy\"\r\n\
+             Content-Type: text/plain\r\nContent-Length: 23\r\n\r\nAuthentication failed\r\n",
+            ident
+        )
+    );
+    assert_eq!(
+        responses.error(&Error::ConnectionRefused("a:1".into()), &[]),
+        format!(
+            "HTTP/1.1 502 Bad Gateway\r\n{}Content-Type: text/plain\r\nContent-Length: 25\r\n\r\n\
+             Connection refused: a:1\r\n",
+            ident
+        )
+    );
+    assert_eq!(
+        PROXY_AGENT,
+        concat!("net-relay/", env!("CARGO_PKG_VERSION"))
+    );
+}
+
+#[test]
+fn reserved_and_malformed_headers_are_rejected() {
+    for headers in [
+        r#"{ "Content-Length" = "1" }"#,
+        r#"{ "proxy-agent" = "x" }"#,
+        r#"{ "Bad Name" = "x" }"#,
+        r#"{ "X-Ok" = "a\r\nInjected: 1" }"#,
+    ] {
+        let config: Config =
+            toml::from_str(&format!("[http_proxy]\nheaders = {}", headers)).unwrap();
+        assert!(config.validate().is_err(), "{}", headers);
+    }
+}
+
+async fn start_echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn proxy_sends_configured_identification() {
+    let echo = start_echo_server().await;
+    let config = Config {
+        http_proxy: identified(),
+        ..Config::default()
+    };
+    let proxy = HttpProxy::builder()
+        .config(ConfigManager::new(config, None))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let request = format!("CONNECT {} HTTP/1.1\r\n\r\n", echo);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let expected = HttpResponses::new(&identified()).established();
+    let mut response = vec![0u8; expected.len()];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(String::from_utf8(response).unwrap(), expected);
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(
+        response.starts_with(&format!(
+            "HTTP/1.1 405 Method Not Allowed\r\nProxy-Agent: {}\r\nX-Relay-Site: ams-1\r\n",
+            PROXY_AGENT
+        )),
+        "{}",
+        response
+    );
+}
@@ -0,0 +1,141 @@
+//! Attribution of unauthenticated connections to client IP buckets.
+
+use net_relay_core::connection::{CloseReason, ConnectionInfo, Protocol};
+use net_relay_core::{Config, IpAttribution, Stats};
+
+fn attribution(ipv4_prefix: u8, ipv6_prefix: u8, max_buckets: usize) -> IpAttribution {
+    IpAttribution {
+        ipv4_prefix,
+        ipv6_prefix,
+        max_buckets,
+    }
+}
+
+async fn relay(stats: &Stats, client: &str, user: Option<&str>, bytes: u64) {
+    let mut info = ConnectionInfo::new(
+        Protocol::HttpConnect,
+        client.to_string(),
+        "example.com".to_string(),
+        443,
+    );
+    info.username = user.map(str::to_string);
+    let id = info.id;
+    stats.add_connection(info).await;
+    stats
+        .close_connection(id, bytes, bytes, CloseReason::TargetEof)
+        .await;
+}
+
+#[test]
+fn buckets_by_prefix() {
+    let exact = attribution(32, 128, 1);
+    assert_eq!(
+        exact.bucket("203.0.113.7:40000").as_deref(),
+        Some("anon:203.0.113.7")
+    );
+    assert_eq!(
+        exact.bucket("[2001:db8::1]:40000").as_deref(),
+        Some("anon:2001:db8::1")
+    );
+    // IPv4-mapped IPv6 clients count as IPv4
+    assert_eq!(
+        exact.bucket("[::ffff:203.0.113.7]:1").as_deref(),
+        Some("anon:203.0.113.7")
+    );
+
+    let grouped = attribution(24, 48, 1);
+    assert_eq!(
+        grouped.bucket("203.0.113.7:40000").as_deref(),
+        Some("anon:203.0.113.0/24")
+    );
+    assert_eq!(
+        grouped.bucket("2001:db8:aa:bb::1").as_deref(),
+        Some("anon:2001:db8:aa::/48")
+    );
+    assert_eq!(
+        attribution(0, 0, 1).bucket("203.0.113.7:1").as_deref(),
+        Some("anon:0.0.0.0/0")
+    );
+    assert_eq!(grouped.bucket("not an address"), None);
+}
+
+#[tokio::test]
+async fn unauthenticated_connections_are_bucketed() {
+    let stats = Stats::new(10).with_ip_attribution(Some(attribution(24, 128, 10)));
+    relay(&stats, "203.0.113.7:40000", None, 10).await;
+    relay(&stats, "203.0.113.9:40001", None, 5).await;
+    relay(&stats, "198.51.100.1:40002", Some("alice"), 7).await;
+
+    let bucket = stats.get_user("anon:203.0.113.0/24").await.unwrap();
+    assert!(bucket.ip_bucket);
+    assert_eq!(bucket.total_connections, 2);
+    assert_eq!(bucket.total_bytes_sent, 15);
+    let alice = stats.get_user("alice").await.unwrap();
+    assert!(!alice.ip_bucket);
+    assert_eq!(stats.get_user_stats().await.len(), 2);
+
+    // Off by default
+    let stats = Stats::new(10);
+    relay(&stats, "203.0.113.7:40000", None, 10).await;
+    assert!(stats.get_user_stats().await.is_empty());
+}
+
+#[tokio::test]
+async fn idle_buckets_are_evicted() {
+    let stats = Stats::new(10).with_ip_attribution(Some(attribution(32, 128, 2)));
+    relay(&stats, "192.0.2.1:1", None, 1).await;
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    relay(&stats, "192.0.2.2:1", None, 1).await;
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    // Users don't count against the limit
+    relay(&stats, "192.0.2.9:1", Some("alice"), 1).await;
+
+    // A scan of new addresses pushes out the least recently active bucket
+    relay(&stats, "192.0.2.3:1", None, 1).await;
+    let mut names: Vec<_> = stats
+        .get_user_stats()
+        .await
+        .into_iter()
+        .map(|user| user.username)
+        .collect();
+    names.sort();
+    assert_eq!(names, ["alice", "anon:192.0.2.2", "anon:192.0.2.3"]);
+
+    // Buckets with open connections are kept; the newcomer goes unattributed
+    let open = |client: &str| {
+        ConnectionInfo::new(
+            Protocol::HttpConnect,
+            client.to_string(),
+            "example.com".to_string(),
+            443,
+        )
+    };
+    stats.add_connection(open("192.0.2.2:2")).await;
+    stats.add_connection(open("192.0.2.3:2")).await;
+    stats.add_connection(open("192.0.2.4:2")).await;
+    assert!(stats.get_user("anon:192.0.2.4").await.is_none());
+    assert_eq!(stats.get_user_stats().await.len(), 3);
+}
+
+#[test]
+fn attribution_config() {
+    assert_eq!(Config::default().stats.ip_attribution(), None);
+    let config: Config = toml::from_str(
+        "[stats]\nattribute_by_ip = true\nattribute_ipv4_prefix = 24\nmax_ip_buckets = 50",
+    )
+    .unwrap();
+    config.validate().unwrap();
+    assert_eq!(
+        config.stats.ip_attribution(),
+        Some(attribution(24, 128, 50))
+    );
+
+    for toml in [
+        "[stats]\nattribute_ipv4_prefix = 33",
+        "[stats]\nattribute_ipv6_prefix = 129",
+        "[stats]\nattribute_by_ip = true\nmax_ip_buckets = 0",
+    ] {
+        let config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_err(), "{}", toml);
+    }
+}
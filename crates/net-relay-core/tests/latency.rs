@@ -0,0 +1,139 @@
+//! Connection phase timestamps and latency histograms.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use chrono::{TimeDelta, Utc};
+use net_relay_core::connection::Protocol;
+use net_relay_core::proxy::HttpProxy;
+use net_relay_core::stats::{ConnectionStats, HistorySince};
+use net_relay_core::{CloseReason, ConnectionInfo, ConnectionPhase, Stats};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[test]
+fn phase_durations_follow_timestamps() {
+    let mut info = ConnectionInfo::new(Protocol::Socks5, "c:1".into(), "t".into(), 80);
+    let accepted = Utc::now();
+    info.set_handshake(accepted, accepted + TimeDelta::milliseconds(12));
+    assert_eq!(info.handshake_ms, Some(12));
+
+    // The first byte may be recorded before the connect time is known
+    let connected = accepted + TimeDelta::milliseconds(40);
+    info.set_first_byte(connected + TimeDelta::milliseconds(75));
+    assert_eq!(info.ttfb_ms, None);
+    info.set_target_connected(accepted + TimeDelta::milliseconds(15), connected, 1);
+    assert_eq!(info.connect_ms, Some(25));
+    assert_eq!(info.ttfb_ms, Some(75));
+
+    // The first byte is kept; a clock going backwards counts as zero
+    info.set_first_byte(connected + TimeDelta::milliseconds(500));
+    assert_eq!(info.ttfb_ms, Some(75));
+    info.set_handshake(accepted, accepted - TimeDelta::milliseconds(5));
+    assert_eq!(info.handshake_ms, Some(0));
+}
+
+#[tokio::test]
+async fn closed_connections_fill_histograms() {
+    let stats = Stats::new(10);
+    for connect_ms in [3, 30, 30, 300, 20_000] {
+        let mut info = ConnectionInfo::new(Protocol::Socks5, "c:1".into(), "t".into(), 80);
+        let started = Utc::now();
+        info.set_target_connected(started, started + TimeDelta::milliseconds(connect_ms), 1);
+        let id = info.id;
+        stats.add_connection(info).await;
+        stats
+            .close_connection(id, 0, 0, CloseReason::ClientEof)
+            .await;
+    }
+
+    let latency = stats.get_latency();
+    let phases: Vec<_> = latency.iter().map(|phase| phase.phase).collect();
+    assert_eq!(phases, ConnectionPhase::ALL);
+    assert_eq!(latency[0].count, 0);
+    assert_eq!(latency[0].p50_ms, None);
+
+    let connect = &latency[1];
+    assert_eq!(connect.count, 5);
+    assert_eq!(connect.max_ms, 20_000);
+    assert_eq!(connect.avg_ms, 4072.6);
+    assert_eq!(connect.p50_ms, Some(50));
+    assert_eq!(connect.p90_ms, Some(20_000));
+    let counts: Vec<u64> = connect.buckets.iter().map(|bucket| bucket.count).collect();
+    assert_eq!(counts, [0, 1, 0, 0, 2, 0, 0, 1, 0, 0, 0, 0, 1]);
+    assert_eq!(connect.buckets.last().unwrap().le_ms, None);
+}
+
+async fn start_echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+/// Wait until a connection shows up in the history.
+async fn wait_for_history(stats: &Stats) -> Vec<ConnectionStats> {
+    for _ in 0..100 {
+        let history = stats
+            .get_history(None, HistorySince::default())
+            .await
+            .connections;
+        if !history.is_empty() {
+            return history;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("connection never closed");
+}
+
+#[tokio::test]
+async fn proxied_connection_records_phases() {
+    let echo = start_echo_server().await;
+    let proxy = HttpProxy::builder().build();
+    let stats = proxy.stats().clone();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let request = format!("CONNECT {} HTTP/1.1\r\n\r\n", echo);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await.unwrap();
+        head.push(byte[0]);
+    }
+    assert!(head.starts_with(b"HTTP/1.1 200"));
+    stream.write_all(b"ping").await.unwrap();
+    let mut echoed = [0u8; 4];
+    stream.read_exact(&mut echoed).await.unwrap();
+    drop(stream);
+
+    let history = wait_for_history(&stats).await;
+    let info = &history[0].info;
+    let accepted = info.accepted_at.unwrap();
+    let authenticated = info.authenticated_at.unwrap();
+    let connected = info.target_connected_at.unwrap();
+    let sent = info.first_byte_sent_at.unwrap();
+    let received = info.first_byte_at.unwrap();
+    assert!(accepted <= authenticated && authenticated <= connected);
+    // Both directions start after the target connection; their order is racy
+    assert!(connected <= sent && connected <= received);
+    assert!(info.handshake_ms.is_some());
+    assert!(info.connect_ms.is_some());
+    assert!(info.ttfb_ms.is_some());
+
+    let json = serde_json::to_value(info).unwrap();
+    for field in ["handshake_ms", "connect_ms", "ttfb_ms"] {
+        assert!(json.get(field).is_some(), "{}", field);
+    }
+    assert!(stats.get_latency().iter().all(|phase| phase.count == 1));
+}
@@ -0,0 +1,199 @@
+//! Maximum connection lifetime and how it races the idle timeout and shutdown.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use net_relay_core::connection::{CloseReason, ConnectionInfo, Protocol};
+use net_relay_core::proxy::relay::relay_for_user;
+use net_relay_core::proxy::RelayResult;
+use net_relay_core::{Config, ConfigManager, SessionLimits, Stats};
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// A relay between in-memory client and target streams.
+struct Relay {
+    stats: Arc<Stats>,
+    config_manager: ConfigManager,
+    /// Client end, kept open so the relay only ends through a limit.
+    client: DuplexStream,
+    _target: DuplexStream,
+    handle: JoinHandle<RelayResult>,
+    started: Instant,
+}
+
+async fn start_relay(limits: &str, session: SessionLimits) -> Relay {
+    let config: Config = toml::from_str(&format!(
+        r#"
+        [limits]
+        {}
+
+        [[security.users]]
+        username = "alice"
+        password = "wonderland"
+        max_connection_lifetime_secs = 30
+        "#,
+        limits
+    ))
+    .unwrap();
+    let stats = Arc::new(Stats::new(10));
+    let config_manager = ConfigManager::new(config, None);
+    let info = ConnectionInfo::new(
+        Protocol::Socks5,
+        "192.0.2.1:40000".to_string(),
+        "example.com".to_string(),
+        443,
+    );
+    let id = info.id;
+    stats.add_connection(info).await;
+
+    let (client, relay_client) = tokio::io::duplex(1024);
+    let (relay_target, target) = tokio::io::duplex(1024);
+    let handle = tokio::spawn({
+        let stats = Arc::clone(&stats);
+        let config_manager = config_manager.clone();
+        async move {
+            relay_for_user(
+                relay_client,
+                relay_target,
+                id,
+                None,
+                &session,
+                &stats,
+                &config_manager,
+            )
+            .await
+        }
+    });
+    Relay {
+        stats,
+        config_manager,
+        client,
+        _target: target,
+        handle,
+        started: Instant::now(),
+    }
+}
+
+impl Relay {
+    /// Wait for the relay to end, returning why and after how long.
+    async fn finish(self) -> (CloseReason, Duration) {
+        let result = self.handle.await.unwrap();
+        (result.close_reason, self.started.elapsed())
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn busy_connections_end_at_their_lifetime() {
+    let mut relay = start_relay(
+        "idle_timeout = 5\nmax_connection_lifetime_secs = 10",
+        SessionLimits::default(),
+    )
+    .await;
+    // Data every two seconds keeps the idle timeout away
+    while !relay.handle.is_finished() {
+        relay.client.write_all(b"x").await.unwrap();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+    let (reason, elapsed) = relay.finish().await;
+    assert_eq!(reason, CloseReason::LifetimeExceeded);
+    assert!(elapsed >= Duration::from_secs(10), "{:?}", elapsed);
+    assert!(elapsed <= Duration::from_secs(12), "{:?}", elapsed);
+}
+
+#[tokio::test(start_paused = true)]
+async fn idle_timeout_before_lifetime() {
+    let relay = start_relay(
+        "idle_timeout = 5\nmax_connection_lifetime_secs = 60",
+        SessionLimits::default(),
+    )
+    .await;
+    let (reason, elapsed) = relay.finish().await;
+    assert_eq!(reason, CloseReason::IdleTimeout);
+    assert!(elapsed >= Duration::from_secs(5), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(7), "{:?}", elapsed);
+}
+
+#[tokio::test(start_paused = true)]
+async fn lifetime_before_idle_timeout() {
+    let relay = start_relay(
+        "idle_timeout = 60\nmax_connection_lifetime_secs = 10",
+        SessionLimits::default(),
+    )
+    .await;
+    let (reason, elapsed) = relay.finish().await;
+    assert_eq!(reason, CloseReason::LifetimeExceeded);
+    assert_eq!(elapsed.as_secs(), 10);
+}
+
+#[tokio::test(start_paused = true)]
+async fn shutdown_before_lifetime() {
+    let relay = start_relay(
+        "idle_timeout = 0\nmax_connection_lifetime_secs = 10",
+        SessionLimits::default(),
+    )
+    .await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    relay.stats.begin_shutdown();
+    let (reason, elapsed) = relay.finish().await;
+    assert_eq!(reason, CloseReason::Shutdown);
+    assert_eq!(elapsed.as_secs(), 3);
+}
+
+#[tokio::test(start_paused = true)]
+async fn lifetime_before_shutdown() {
+    let relay = start_relay(
+        "idle_timeout = 0\nmax_connection_lifetime_secs = 2",
+        SessionLimits::default(),
+    )
+    .await;
+    let stats = Arc::clone(&relay.stats);
+    let shutdown = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        stats.begin_shutdown();
+    });
+    let (reason, elapsed) = relay.finish().await;
+    assert_eq!(reason, CloseReason::LifetimeExceeded);
+    assert_eq!(elapsed.as_secs(), 2);
+    shutdown.await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn user_lifetime_overrides_the_default() {
+    let relay = start_relay(
+        "max_connection_lifetime_secs = 3600",
+        SessionLimits::default(),
+    )
+    .await;
+    let alice = relay
+        .config_manager
+        .session_limits("alice", SessionLimits::default())
+        .await;
+    assert_eq!(alice.max_connection_lifetime_secs, Some(30));
+    drop(relay);
+
+    let relay = start_relay("max_connection_lifetime_secs = 3600", alice).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let active = relay.stats.get_active().await;
+    assert!(active[0].expires_at.is_some());
+    let remaining = active[0].remaining_lifetime_secs.unwrap();
+    assert!(remaining > 0 && remaining <= 30, "{}", remaining);
+
+    let (reason, elapsed) = relay.finish().await;
+    assert_eq!(reason, CloseReason::LifetimeExceeded);
+    assert_eq!(elapsed.as_secs(), 30);
+
+    // 0 turns the limit off for the user
+    let unlimited = SessionLimits {
+        max_connection_lifetime_secs: Some(0),
+        ..SessionLimits::default()
+    };
+    let relay = start_relay(
+        "max_connection_lifetime_secs = 10\nidle_timeout = 60",
+        unlimited,
+    )
+    .await;
+    let (reason, elapsed) = relay.finish().await;
+    assert_eq!(reason, CloseReason::IdleTimeout);
+    assert!(elapsed >= Duration::from_secs(60), "{:?}", elapsed);
+}
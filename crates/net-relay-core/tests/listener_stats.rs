@@ -0,0 +1,68 @@
+//! Accept counters of the proxy listeners.
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use net_relay_core::proxy::HttpProxy;
+use net_relay_core::Stats;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[test]
+fn counters_are_kept_per_listener() {
+    let stats = Stats::new(10);
+    let address: SocketAddr = "127.0.0.1:1080".parse().unwrap();
+    let counters = stats.listener("socks5", address);
+    counters.record_accept();
+    counters.record_accept();
+    counters.record_error(&io::Error::from_raw_os_error(24));
+    // The same listener again shares its counters
+    stats.listener("socks5", address).record_accept();
+    stats.listener("http", address);
+
+    let listeners = stats.get_listener_stats();
+    assert_eq!(listeners.len(), 2);
+    assert_eq!(listeners[0].protocol, "http");
+    assert_eq!(listeners[0].accepted, 0);
+    assert!(listeners[0].last_error.is_none());
+
+    let socks = &listeners[1];
+    assert_eq!(socks.protocol, "socks5");
+    assert_eq!(socks.address, "127.0.0.1:1080");
+    assert_eq!(socks.accepted, 3);
+    assert_eq!(socks.accept_errors, 1);
+    let message = io::Error::from_raw_os_error(24).to_string();
+    assert_eq!(socks.last_error.as_deref(), Some(message.as_str()));
+    assert!(socks.last_error_at.is_some());
+}
+
+#[tokio::test]
+async fn proxy_counts_accepted_connections() {
+    let proxy = HttpProxy::builder().build();
+    let stats = proxy.stats().clone();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+    }
+
+    let mut listeners = stats.get_listener_stats();
+    for _ in 0..50 {
+        if listeners.first().is_some_and(|l| l.accepted == 2) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        listeners = stats.get_listener_stats();
+    }
+    assert_eq!(listeners.len(), 1);
+    assert_eq!(listeners[0].protocol, "http");
+    assert_eq!(listeners[0].address, proxy_addr.to_string());
+    assert_eq!(listeners[0].accepted, 2);
+    assert_eq!(listeners[0].accept_errors, 0);
+}
@@ -0,0 +1,140 @@
+//! Listening sockets: options they are created with and the addresses
+//! recorded at startup, including OS-assigned ports.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use net_relay_core::listeners::{bind, bind_host};
+use net_relay_core::{Config, ListenOptions, ListenerInfo, ServerState};
+use socket2::SockRef;
+use tokio::net::{TcpListener, TcpStream};
+
+#[tokio::test]
+async fn ephemeral_ports_are_reported_as_bound() {
+    let state = ServerState::new();
+    let socks = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let socks_addr = socks.local_addr().unwrap();
+    let http_addr = http.local_addr().unwrap();
+    state.add(ListenerInfo::bound(
+        "socks5",
+        "127.0.0.1:0",
+        socks_addr,
+        1024,
+    ));
+    state.add(ListenerInfo::bound("http", "127.0.0.1:0", http_addr, 1024));
+    state.add(ListenerInfo::failed(
+        "http",
+        "127.0.0.1:0",
+        "failed to bind [::1]:0: address not available",
+    ));
+    state.add(ListenerInfo::disabled("api", "127.0.0.1:3000"));
+
+    let listeners = state.listeners();
+    assert_eq!(listeners.len(), 4);
+    assert_ne!(listeners[0].bound.unwrap().port(), 0);
+    assert_eq!(listeners[0].bound, Some(socks_addr));
+    assert!(listeners[0].up && listeners[0].enabled);
+    assert!(!listeners[2].up && listeners[2].enabled);
+    assert!(listeners[2].error.is_some());
+    assert!(!listeners[3].up && !listeners[3].enabled);
+    assert_eq!(state.bound_addrs(None), [socks_addr, http_addr]);
+    assert_eq!(state.bound_addrs(Some("http")), [http_addr]);
+
+    state.set_down("socks5", socks_addr);
+    assert!(!state.listeners()[0].up);
+    assert_eq!(state.bound_addrs(None), [http_addr]);
+    // Another protocol on the same address is left alone
+    state.set_down("http", socks_addr);
+    assert!(state.listeners()[1].up);
+}
+
+#[test]
+fn listeners_serialize_for_the_api() {
+    let bound: SocketAddr = "127.0.0.1:41000".parse().unwrap();
+    let json =
+        serde_json::to_value(ListenerInfo::bound("socks5", "127.0.0.1:0", bound, 4096)).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "protocol": "socks5",
+            "configured": "127.0.0.1:0",
+            "bound": "127.0.0.1:41000",
+            "enabled": true,
+            "up": true,
+            "backlog": 4096,
+        })
+    );
+    let json = serde_json::to_value(ListenerInfo::disabled("api", "0.0.0.0:3000")).unwrap();
+    assert_eq!(json["bound"], serde_json::Value::Null);
+}
+
+#[test]
+fn listen_options_come_from_the_server_section() {
+    let config: Config = toml::from_str(
+        r#"
+        [server]
+        listen_backlog = 4096
+        listen_recv_buffer = 262144
+        "#,
+    )
+    .unwrap();
+    let options = ListenOptions::from_config(&config.server);
+    assert_eq!(options.backlog, 4096);
+    assert_eq!(options.recv_buffer, Some(262144));
+    assert_eq!(options.send_buffer, None);
+    assert_eq!(ListenOptions::default().backlog, 1024);
+
+    let config: Config = toml::from_str("[server]\nlisten_backlog = 0").unwrap();
+    assert!(config.validate().is_err());
+}
+
+#[tokio::test]
+async fn buffer_sizes_are_applied() {
+    let options = ListenOptions {
+        backlog: 16,
+        recv_buffer: Some(65536),
+        send_buffer: Some(65536),
+    };
+    let listener = bind("127.0.0.1:0".parse().unwrap(), &options).unwrap();
+    let socket = SockRef::from(&listener);
+    // Linux doubles the requested size for bookkeeping overhead
+    assert!(socket.recv_buffer_size().unwrap() >= 65536);
+    assert!(socket.send_buffer_size().unwrap() >= 65536);
+
+    let listener = bind_host("localhost", 0, &ListenOptions::default())
+        .await
+        .unwrap();
+    assert_ne!(listener.local_addr().unwrap().port(), 0);
+}
+
+/// Connections to `listener` that complete, out of `attempts`, while
+/// nothing accepts them.
+#[cfg(target_os = "linux")]
+async fn queued_connections(listener: &TcpListener, attempts: usize) -> Vec<TcpStream> {
+    let addr = listener.local_addr().unwrap();
+    let mut streams = Vec::new();
+    for _ in 0..attempts {
+        let connect = TcpStream::connect(addr);
+        if let Ok(Ok(stream)) = tokio::time::timeout(Duration::from_millis(200), connect).await {
+            streams.push(stream);
+        }
+    }
+    streams
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn backlog_bounds_the_accept_queue() {
+    let small = ListenOptions {
+        backlog: 1,
+        ..ListenOptions::default()
+    };
+    let listener = bind("127.0.0.1:0".parse().unwrap(), &small).unwrap();
+    let queued = queued_connections(&listener, 5).await;
+    // The kernel queues backlog + 1 connections and drops further SYNs
+    assert!(queued.len() < 5, "{} connections queued", queued.len());
+
+    let listener = bind("127.0.0.1:0".parse().unwrap(), &ListenOptions::default()).unwrap();
+    assert_eq!(queued_connections(&listener, 5).await.len(), 5);
+}
@@ -0,0 +1,180 @@
+//! Monitored access rules (`mode = "monitor"`, `access_control.monitor_only`):
+//! matches are recorded but not enforced.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use net_relay_core::proxy::HttpProxy;
+use net_relay_core::{AccessControlConfig, Config, ConfigManager, ErrorCode, RuleMode, Stats};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+fn access_control(toml: &str) -> AccessControlConfig {
+    let config: Config = toml::from_str(toml).unwrap();
+    config.access_control
+}
+
+const RULES: &str = r#"
+    [[access_control.rules]]
+    id = "new-deny"
+    domain = "*.example.com"
+    action = "deny"
+    mode = "monitor"
+
+    [[access_control.rules]]
+    id = "allow"
+    domain = "*.example.com"
+    action = "allow"
+
+    [[access_control.rules]]
+    id = "new-allow"
+    domain = "internal.example"
+    action = "allow"
+    mode = "monitor"
+
+    [[access_control.rules]]
+    id = "deny"
+    domain = "internal.example"
+    action = "deny"
+"#;
+
+#[test]
+fn monitored_rules_are_reported_but_not_applied() {
+    let config = access_control(RULES);
+    assert_eq!(config.rules[0].mode, RuleMode::Monitor);
+    assert_eq!(config.rules[1].mode, RuleMode::Enforce);
+
+    // A monitored deny ahead of an enforced allow: allowed, would be denied
+    let decision = config.evaluate_target("www.example.com", 443, None);
+    assert!(decision.allowed);
+    assert!(decision.would_deny);
+    assert_eq!(decision.monitored_rule.unwrap().id, "new-deny");
+    assert_eq!(decision.matched_rule.unwrap().id, "allow");
+
+    // A monitored allow doesn't lift an enforced deny
+    let decision = config.evaluate_target("internal.example", 443, None);
+    assert!(!decision.allowed);
+    assert!(!decision.would_deny);
+    assert_eq!(decision.monitored_rule.unwrap().id, "new-allow");
+
+    let decision = config.evaluate_target("other.example", 443, None);
+    assert!(decision.allowed && !decision.would_deny && decision.default_applied);
+
+    // Enforced rules don't serialize their mode
+    let json = serde_json::to_value(&config.rules).unwrap();
+    assert_eq!(json[0]["mode"], "monitor");
+    assert!(json[1].get("mode").is_none());
+}
+
+#[test]
+fn monitor_only_lets_everything_through() {
+    let config = access_control(&format!(
+        "[access_control]\nallow_by_default = false\nmonitor_only = true\n{}",
+        RULES
+    ));
+    for (host, would_deny) in [
+        ("www.example.com", true),
+        // The first matching rule counts, whatever its mode
+        ("internal.example", false),
+        ("other.example", true),
+    ] {
+        let decision = config.evaluate_target(host, 443, None);
+        assert!(decision.allowed, "{}", host);
+        assert_eq!(decision.would_deny, would_deny, "{}", host);
+        assert!(decision.matched_rule.is_none(), "{}", host);
+    }
+    let decision = config.evaluate_target("internal.example", 443, None);
+    assert_eq!(decision.monitored_rule.unwrap().id, "new-allow");
+    assert!(config.is_target_allowed("other.example", 443, None));
+}
+
+async fn start_echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+/// Send a CONNECT and return the response status line.
+async fn connect(proxy: SocketAddr, target: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    let request = format!("CONNECT {} HTTP/1.1\r\n\r\n", target);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n") {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await.unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+#[tokio::test]
+async fn monitored_denials_are_logged_and_counted() {
+    let echo = start_echo_server().await;
+    let config: Config = toml::from_str(
+        r#"
+        [[access_control.rules]]
+        id = "rollout"
+        name = "New blocklist"
+        domain = "127.0.0.1"
+        action = "deny"
+        mode = "monitor"
+
+        [[access_control.rules]]
+        id = "local"
+        domain = "127.0.0.1"
+        action = "allow"
+        "#,
+    )
+    .unwrap();
+    let stats = Arc::new(Stats::new(10));
+    let proxy = HttpProxy::builder()
+        .config(ConfigManager::new(config, None))
+        .stats(Arc::clone(&stats))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+
+    let status = connect(proxy_addr, echo).await;
+    assert!(status.starts_with("HTTP/1.1 200"), "{}", status);
+
+    let mut denied = Vec::new();
+    for _ in 0..50 {
+        denied = stats.get_denied(None).await;
+        if !denied.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(denied.len(), 1);
+    let attempt = &denied[0];
+    assert!(attempt.would_deny);
+    assert_eq!(attempt.code, Some(ErrorCode::TargetDenied));
+    assert_eq!(attempt.target.as_deref(), Some(echo.to_string().as_str()));
+    assert!(
+        attempt
+            .reason
+            .starts_with("Target would be blocked: 127.0.0.1:"),
+        "{}",
+        attempt.reason
+    );
+    assert!(attempt
+        .reason
+        .ends_with("by rule \"New blocklist\" (id rollout)"));
+
+    let hits = stats.get_rule_hits();
+    assert_eq!(hits["rollout"].monitored, 1);
+    assert_eq!(hits["rollout"].enforced, 0);
+    assert_eq!(hits["local"].enforced, 1);
+    assert!(hits["local"].last_hit.is_some());
+}
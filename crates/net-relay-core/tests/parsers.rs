@@ -0,0 +1,235 @@
+//! SOCKS5 and HTTP handshake parsers on arbitrary and well-formed input.
+//!
+//! The cargo-fuzz targets in `fuzz/` exercise the same functions for longer.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use net_relay_core::proxy::headers::{Headers, MAX_HEADERS};
+use net_relay_core::proxy::http::{decode_basic_auth, parse_host_port, parse_request_head};
+use net_relay_core::proxy::parse::Parsed;
+use net_relay_core::proxy::socks5::{
+    parse_credentials, parse_greeting, parse_request, Credentials, Socks5Request,
+};
+use net_relay_core::Result;
+use proptest::prelude::*;
+
+/// A complete message must not be reported before all of it has arrived.
+fn check_prefixes<T>(parse: impl Fn(&[u8]) -> Result<Parsed<T>>, input: &[u8]) {
+    if let Ok(Some((_, len))) = parse(input) {
+        assert!(len <= input.len());
+        for end in 0..len {
+            assert!(matches!(parse(&input[..end]), Ok(None)), "prefix {}", end);
+        }
+    }
+}
+
+fn host() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[a-z0-9-]{1,20}(\\.[a-z]{2,6}){0,3}",
+        any::<[u8; 4]>().prop_map(|ip| format!("{}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3])),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn parsers_accept_arbitrary_bytes(input in prop::collection::vec(any::<u8>(), 0..600)) {
+        check_prefixes(parse_greeting, &input);
+        check_prefixes(parse_credentials, &input);
+        check_prefixes(parse_request, &input);
+        check_prefixes(parse_request_head, &input);
+
+        let text = String::from_utf8_lossy(&input);
+        let _ = parse_host_port(&text);
+        let _ = decode_basic_auth(&text);
+    }
+
+    #[test]
+    fn http_heads_accept_arbitrary_lines(
+        lines in prop::collection::vec("[ -~]{0,40}", 0..8),
+        eol in prop::sample::select(vec!["\r\n", "\n", "\r"]),
+    ) {
+        let head = lines.iter().map(|line| format!("{}{}", line, eol)).collect::<String>();
+        check_prefixes(parse_request_head, head.as_bytes());
+    }
+
+    #[test]
+    fn socks5_messages_round_trip(
+        methods in prop::collection::vec(any::<u8>(), 0..=255),
+        username in "[ -~]{0,255}",
+        password in "[ -~]{0,255}",
+        host in host(),
+        port in any::<u16>(),
+        trailing in prop::collection::vec(any::<u8>(), 0..16),
+    ) {
+        let mut greeting = vec![0x05, methods.len() as u8];
+        greeting.extend_from_slice(&methods);
+        greeting.extend_from_slice(&trailing);
+        prop_assert_eq!(
+            parse_greeting(&greeting).unwrap(),
+            Some((methods.clone(), 2 + methods.len()))
+        );
+
+        let mut auth = vec![0x01, username.len() as u8];
+        auth.extend_from_slice(username.as_bytes());
+        auth.push(password.len() as u8);
+        auth.extend_from_slice(password.as_bytes());
+        let auth_len = auth.len();
+        auth.extend_from_slice(&trailing);
+        let credentials = Credentials { username, password };
+        prop_assert_eq!(parse_credentials(&auth).unwrap(), Some((credentials, auth_len)));
+
+        let mut request = vec![0x05, 0x01, 0x00];
+        match host.parse::<std::net::Ipv4Addr>() {
+            Ok(ip) => {
+                request.push(0x01);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                request.extend_from_slice(&[0x03, host.len() as u8]);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        let request_len = request.len();
+        request.extend_from_slice(&trailing);
+        prop_assert_eq!(
+            parse_request(&request).unwrap(),
+            Some((Socks5Request { host, port }, request_len))
+        );
+    }
+
+    #[test]
+    fn http_connect_round_trips(
+        host in host(),
+        port in any::<u16>(),
+        username in "[^:]{0,20}",
+        password in "\\PC{0,20}",
+        trailing in prop::collection::vec(any::<u8>(), 0..16),
+    ) {
+        let token = STANDARD.encode(format!("{}:{}", username, password));
+        let head = format!(
+            "CONNECT {0}:{1} HTTP/1.1\r\nHost: {0}:{1}\r\nProxy-Authorization: Basic {2}\r\n\r\n",
+            host, port, token
+        );
+        let mut input = head.clone().into_bytes();
+        input.extend_from_slice(&trailing);
+
+        let (parsed, len) = parse_request_head(&input).unwrap().unwrap();
+        prop_assert_eq!(len, head.len());
+        prop_assert_eq!(&parsed.method, "CONNECT");
+        prop_assert_eq!(parse_host_port(&parsed.target).unwrap(), (host, port));
+        let authorization = parsed.proxy_authorization().unwrap();
+        let encoded = authorization.strip_prefix("Basic ").unwrap();
+        prop_assert_eq!(decode_basic_auth(encoded), Some((username.clone(), password.clone())));
+        prop_assert_eq!(
+            decode_basic_auth(encoded.trim_end_matches('=')),
+            Some((username, password))
+        );
+    }
+}
+
+#[test]
+fn oversized_http_heads_are_refused() {
+    let head = format!("CONNECT a:1 HTTP/1.1\r\nX: {}", "a".repeat(9000));
+    assert!(parse_request_head(head.as_bytes()).is_err());
+
+    let head = format!("CONNECT a:1 HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(9000));
+    assert!(parse_request_head(head.as_bytes()).is_err());
+}
+
+/// Request heads as odd clients send them, all meaning the same request.
+const HEAD_CORPUS: &[(&str, &str)] = &[
+    (
+        "crlf",
+        "CONNECT a.example:443 HTTP/1.1\r\nHost: a.example:443\r\nProxy-Authorization: Basic dTpw\r\n\r\n",
+    ),
+    (
+        "bare lf",
+        "CONNECT a.example:443 HTTP/1.1\nHost: a.example:443\nProxy-Authorization: Basic dTpw\n\n",
+    ),
+    (
+        "bare cr",
+        "CONNECT a.example:443 HTTP/1.1\rHost: a.example:443\rProxy-Authorization: Basic dTpw\r\r",
+    ),
+    (
+        "missing cr on some lines",
+        "CONNECT a.example:443 HTTP/1.1\r\nHost: a.example:443\nProxy-Authorization: Basic dTpw\r\n\n",
+    ),
+    (
+        "lowercase and odd whitespace",
+        "CONNECT a.example:443 HTTP/1.1\r\n  host :a.example:443  \r\nPROXY-AUTHORIZATION:\tBasic dTpw \r\n\r\n",
+    ),
+    (
+        "folded value",
+        "CONNECT a.example:443 HTTP/1.1\r\nHost: a.example:443\r\nProxy-Authorization:\r\n  Basic\r\n\tdTpw\r\n\r\n",
+    ),
+];
+
+#[test]
+fn http_head_corpus_parses_alike() {
+    for (name, head) in HEAD_CORPUS {
+        let (parsed, len) = parse_request_head(head.as_bytes())
+            .unwrap()
+            .unwrap_or_else(|| panic!("{}: incomplete", name));
+        assert_eq!(len, head.len(), "{}", name);
+        assert_eq!(parsed.target, "a.example:443", "{}", name);
+        assert_eq!(
+            parsed.headers.get("HOST"),
+            Some("a.example:443"),
+            "{}",
+            name
+        );
+        assert_eq!(parsed.proxy_authorization(), Some("Basic dTpw"), "{}", name);
+        assert_eq!(parsed.headers.len(), 2, "{}", name);
+        check_prefixes(parse_request_head, head.as_bytes());
+    }
+}
+
+#[test]
+fn http_heads_keep_every_header() {
+    let head = "CONNECT a:1 HTTP/1.1\r\nVia: 1.1 first\r\nX-Custom: one\r\nvia: 1.0 second\r\nProxy-Connection: Keep-Alive\r\nConnection: keep-alive, Close\r\n\r\n";
+    let (parsed, _) = parse_request_head(head.as_bytes()).unwrap().unwrap();
+
+    let via: Vec<_> = parsed.headers.get_all("via").collect();
+    assert_eq!(via, ["1.1 first", "1.0 second"]);
+    let names: Vec<_> = parsed.headers.iter().map(|(name, _)| name).collect();
+    assert_eq!(
+        names,
+        ["Via", "X-Custom", "via", "Proxy-Connection", "Connection"]
+    );
+    assert!(parsed.headers.has_token("connection", "close"));
+    assert!(parsed.wants_close());
+
+    let head = "CONNECT a:1 HTTP/1.1\nProxy-Connection: close\n\n";
+    assert!(parse_request_head(head.as_bytes())
+        .unwrap()
+        .unwrap()
+        .0
+        .wants_close());
+    let head = "CONNECT a:1 HTTP/1.1\nProxy-Connection: keep-alive\n\n";
+    assert!(!parse_request_head(head.as_bytes())
+        .unwrap()
+        .unwrap()
+        .0
+        .wants_close());
+}
+
+#[test]
+fn duplicate_and_excess_headers() {
+    // The first of repeated credentials is used
+    let head = "CONNECT a:1 HTTP/1.1\r\nProxy-Authorization: Basic first\r\nproxy-authorization: Basic second\r\n\r\n";
+    let (parsed, _) = parse_request_head(head.as_bytes()).unwrap().unwrap();
+    assert_eq!(parsed.proxy_authorization(), Some("Basic first"));
+    assert_eq!(parsed.headers.get_all("Proxy-Authorization").count(), 2);
+
+    // Lines without a name are skipped, a leading continuation is a field
+    let headers = Headers::parse(&[" X-Early: 1", "no colon", ": no name", "Y:"]).unwrap();
+    let fields: Vec<_> = headers.iter().collect();
+    assert_eq!(fields, [("X-Early", "1"), ("Y", "")]);
+
+    let lines: Vec<_> = (0..=MAX_HEADERS)
+        .map(|i| format!("X-{}: {}", i, i))
+        .collect();
+    assert!(Headers::parse(&lines[..MAX_HEADERS]).is_ok());
+    assert!(Headers::parse(&lines).is_err());
+}
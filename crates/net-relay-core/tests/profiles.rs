@@ -0,0 +1,72 @@
+//! Switching between named config profiles.
+
+use net_relay_core::{Config, ConfigManager};
+
+const CONFIG: &str = r#"
+active_profile = "office"
+
+[limits]
+max_connections = 1000
+
+[access_control]
+allow_by_default = true
+
+[profiles.office.access_control]
+allow_by_default = true
+ip_blacklist = ["10.0.0.9"]
+
+[profiles.lockdown.access_control]
+allow_by_default = false
+ip_whitelist = ["10.0.0.0/8"]
+
+[profiles.lockdown.limits]
+max_connections = 10
+"#;
+
+#[tokio::test]
+async fn activating_a_profile_swaps_access_control_and_limits() {
+    let dir = std::env::temp_dir().join(format!("net-relay-profiles-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    std::fs::write(&path, CONFIG).unwrap();
+
+    let config = Config::load_from_file(&path).unwrap();
+    config.validate().unwrap();
+    let manager = ConfigManager::new(config, Some(path.to_string_lossy().to_string()));
+
+    // The active profile is applied at load
+    let office = manager.get().await;
+    assert_eq!(office.access_control.ip_blacklist, ["10.0.0.9"]);
+
+    assert!(!manager.activate_profile("missing").await.unwrap());
+    assert!(manager.activate_profile("lockdown").await.unwrap());
+    let lockdown = manager.get().await;
+    assert_eq!(lockdown.active_profile.as_deref(), Some("lockdown"));
+    assert!(!lockdown.access_control.allow_by_default);
+    assert_eq!(lockdown.limits.max_connections, 10);
+
+    // Edits while a profile is active are saved into it
+    let mut access_control = lockdown.access_control.clone();
+    access_control.ip_blacklist.push("10.6.6.6".to_string());
+    manager.update_access_control(access_control).await.unwrap();
+
+    let saved = Config::load_from_file(&path).unwrap();
+    saved.validate().unwrap();
+    assert_eq!(saved.active_profile.as_deref(), Some("lockdown"));
+    assert_eq!(
+        saved.profiles["lockdown"].access_control.ip_blacklist,
+        ["10.6.6.6"]
+    );
+    assert_eq!(
+        saved.profiles["office"].access_control.ip_blacklist,
+        ["10.0.0.9"]
+    );
+
+    // Switching back keeps the limits of the profile that had them
+    assert!(manager.activate_profile("office").await.unwrap());
+    let office = manager.get().await;
+    assert!(office.access_control.allow_by_default);
+    assert_eq!(office.limits.max_connections, 10);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
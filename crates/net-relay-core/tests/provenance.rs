@@ -0,0 +1,89 @@
+//! Where each section of the running configuration came from.
+
+use net_relay_core::config::with_config_actor;
+use net_relay_core::{Config, ConfigManager, ConfigSource};
+
+fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn sources_are_layered_and_tracked_per_section() {
+    let path = std::env::temp_dir().join(format!(
+        "net-relay-provenance-{}.toml",
+        uuid::Uuid::new_v4()
+    ));
+    std::fs::write(
+        &path,
+        "[limits]\nmax_connections = 50\n\n[stats]\nmax_history = 10\n",
+    )
+    .unwrap();
+
+    let (config, provenance) = Config::load_layered(
+        Some(&path),
+        vars(&[
+            ("NET_RELAY__LIMITS__MAX_CONNECTIONS", "1000"),
+            ("NET_RELAY__SERVER__HOST", "127.0.0.1"),
+            ("PATH", "/usr/bin"),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(config.limits.max_connections, 1000);
+    assert_eq!(config.server.host, "127.0.0.1");
+    assert_eq!(config.stats.max_history, 10);
+    assert_eq!(provenance["limits"], ConfigSource::Env);
+    assert_eq!(provenance["server"], ConfigSource::Env);
+    assert_eq!(provenance["stats"], ConfigSource::File);
+    assert_eq!(provenance["security"], ConfigSource::Default);
+
+    let manager = ConfigManager::new(config, None).with_provenance(provenance);
+    let mut limits = manager.get_limits().await;
+    limits.max_connections = 7;
+    with_config_actor("alice".to_string(), manager.update_limits(limits))
+        .await
+        .unwrap();
+
+    // Changes outside a request are the server's own
+    manager.ban_ip("192.0.2.1", None).await.unwrap();
+
+    let provenance = manager.provenance();
+    let ConfigSource::Api { user, .. } = &provenance["limits"] else {
+        panic!("limits from {}", provenance["limits"]);
+    };
+    assert_eq!(user, "alice");
+    assert!(provenance["limits"].to_string().starts_with("api:alice@"));
+    assert_eq!(provenance["server"], ConfigSource::Env);
+    assert_eq!(provenance["stats"], ConfigSource::File);
+    assert!(matches!(
+        provenance["access_control"],
+        ConfigSource::Runtime { .. }
+    ));
+
+    // Whole-config updates only claim the sections that changed
+    let mut config = manager.get().await;
+    config.dns.timeout_secs += 1;
+    with_config_actor("bob".to_string(), manager.update(config))
+        .await
+        .unwrap();
+    let provenance = manager.provenance();
+    assert!(provenance["dns"].to_string().starts_with("api:bob@"));
+    assert!(provenance["limits"].to_string().starts_with("api:alice@"));
+    assert_eq!(provenance["stats"], ConfigSource::File);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn env_overrides_must_name_a_section() {
+    let err = Config::load_layered(None, vars(&[("NET_RELAY__NOPE__X", "1")])).unwrap_err();
+    assert!(err.to_string().contains("unknown config section"));
+
+    // Values that aren't TOML are taken as strings
+    let (config, provenance) =
+        Config::load_layered(None, vars(&[("NET_RELAY__LOGGING__LEVEL", "debug")])).unwrap();
+    assert_eq!(config.logging.level, "debug");
+    assert_eq!(provenance["logging"].to_string(), "env");
+}
@@ -0,0 +1,179 @@
+//! End-to-end tests of the SOCKS5 and HTTP proxies through the in-crate clients.
+
+use std::net::SocketAddr;
+
+use net_relay_core::proxy::sni::parse_sni;
+use net_relay_core::proxy::{HttpConnectClient, HttpProxy, Socks5Client, Socks5Proxy};
+use net_relay_core::{Config, ConfigManager, DenyReason, Error};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Start a TCP echo server and return its address.
+async fn start_echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+/// Start both proxies requiring alice's password and denying `blocked.example`.
+async fn start_proxies() -> (SocketAddr, SocketAddr) {
+    let config: Config = toml::from_str(
+        r#"
+        [security]
+        auth_enabled = true
+
+        [[security.users]]
+        username = "alice"
+        password = "wonderland"
+
+        [[access_control.rules]]
+        name = "Blocked"
+        domain = "blocked.example"
+        action = "deny"
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    let config_manager = ConfigManager::new(config, None);
+
+    let socks = Socks5Proxy::builder()
+        .config(config_manager.clone())
+        .build();
+    let socks_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let socks_addr = socks_listener.local_addr().unwrap();
+    tokio::spawn(async move { socks.run(socks_listener).await });
+
+    let http = HttpProxy::builder().config(config_manager).build();
+    let http_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http_addr = http_listener.local_addr().unwrap();
+    tokio::spawn(async move { http.run(http_listener).await });
+
+    (socks_addr, http_addr)
+}
+
+async fn assert_echoes(mut stream: TcpStream) {
+    stream.write_all(b"round trip").await.unwrap();
+    let mut echoed = [0u8; 10];
+    stream.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"round trip");
+}
+
+#[tokio::test]
+async fn socks5_relays_and_refuses() {
+    let echo = start_echo_server().await;
+    let (proxy, _) = start_proxies().await;
+    let alice = Some(("alice", "wonderland"));
+
+    let stream = Socks5Client::connect(proxy, "127.0.0.1", echo.port(), alice)
+        .await
+        .unwrap();
+    assert_echoes(stream).await;
+
+    for credentials in [None, Some(("alice", "wrong"))] {
+        let result = Socks5Client::connect(proxy, "127.0.0.1", echo.port(), credentials).await;
+        assert!(
+            matches!(result, Err(Error::AuthenticationFailed)),
+            "{:?}",
+            credentials
+        );
+    }
+
+    let result = Socks5Client::connect(proxy, "blocked.example", 443, alice).await;
+    assert!(matches!(
+        result,
+        Err(Error::AccessDenied(DenyReason::Target, _))
+    ));
+}
+
+#[tokio::test]
+async fn http_connect_relays_and_refuses() {
+    let echo = start_echo_server().await;
+    let (_, proxy) = start_proxies().await;
+    let alice = Some(("alice", "wonderland"));
+
+    let stream = HttpConnectClient::connect(proxy, "127.0.0.1", echo.port(), alice)
+        .await
+        .unwrap();
+    assert_echoes(stream).await;
+
+    for credentials in [None, Some(("alice", "wrong"))] {
+        let result = HttpConnectClient::connect(proxy, "127.0.0.1", echo.port(), credentials).await;
+        assert!(
+            matches!(result, Err(Error::AuthenticationFailed)),
+            "{:?}",
+            credentials
+        );
+    }
+
+    let result = HttpConnectClient::connect(proxy, "blocked.example", 443, alice).await;
+    assert!(matches!(
+        result,
+        Err(Error::AccessDenied(DenyReason::Target, _))
+    ));
+}
+
+/// A TLS ClientHello record carrying `server_name` as SNI.
+fn client_hello(server_name: &str) -> Vec<u8> {
+    let name = server_name.as_bytes();
+    let mut sni = vec![0x00, 0x00]; // server_name extension
+    sni.extend_from_slice(&(name.len() as u16 + 5).to_be_bytes());
+    sni.extend_from_slice(&(name.len() as u16 + 3).to_be_bytes());
+    sni.push(0x00); // host_name
+    sni.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    sni.extend_from_slice(name);
+
+    let mut hello = vec![0x03, 0x03];
+    hello.extend_from_slice(&[0x42; 32]); // random
+    hello.extend_from_slice(&[0x00, 0x00, 0x02, 0x13, 0x01, 0x01, 0x00]);
+    hello.extend_from_slice(&(sni.len() as u16).to_be_bytes());
+    hello.extend_from_slice(&sni);
+
+    let mut handshake = vec![0x01, 0x00];
+    handshake.extend_from_slice(&(hello.len() as u16).to_be_bytes());
+    handshake.extend_from_slice(&hello);
+
+    let mut record = vec![0x16, 0x03, 0x01];
+    record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+    record.extend_from_slice(&handshake);
+    record
+}
+
+#[tokio::test]
+async fn http_connect_forwards_bytes_sent_with_the_request() {
+    let echo = start_echo_server().await;
+    for inspect_sni in [false, true] {
+        let config: Config =
+            toml::from_str(&format!("[access_control]\ninspect_sni = {}", inspect_sni)).unwrap();
+        let proxy = HttpProxy::builder()
+            .config(ConfigManager::new(config, None))
+            .build();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { proxy.run(listener).await });
+
+        // The ClientHello and more data arrive in the same segment as the request
+        let mut early = client_hello("echo.example");
+        early.extend_from_slice(b"application data");
+        assert_eq!(parse_sni(&early).as_deref(), Some("echo.example"));
+        let mut request = format!("CONNECT {} HTTP/1.1\r\n\r\n", echo).into_bytes();
+        request.extend_from_slice(&early);
+        let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+        stream.write_all(&request).await.unwrap();
+
+        let established = b"HTTP/1.1 200 Connection Established\r\n\r\n";
+        let mut response = vec![0u8; established.len()];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, established);
+        let mut echoed = vec![0u8; early.len()];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(echoed, early, "inspect_sni = {}", inspect_sni);
+    }
+}
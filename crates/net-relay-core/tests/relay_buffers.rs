@@ -0,0 +1,98 @@
+//! Relay buffer memory accounting and its cap.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use net_relay_core::connection::{ConnectionInfo, Protocol};
+use net_relay_core::proxy::relay::{relay_for_user, RelayBufferMemory};
+use net_relay_core::{Config, ConfigManager, SessionLimits, Stats};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[test]
+fn buffers_past_the_cap_fall_back_to_the_small_size() {
+    let memory = RelayBufferMemory::default();
+    let first = memory.checkout(8192, 20_000);
+    let second = memory.checkout(8192, 20_000);
+    let third = memory.checkout(8192, 20_000);
+    assert_eq!(first.len(), 8192);
+    assert_eq!(second.len(), 8192);
+    assert_eq!(third.len(), 1024);
+
+    let stats = memory.stats();
+    assert_eq!(stats.bytes, 8192 * 2 + 1024);
+    assert_eq!(stats.buffers, 3);
+    assert_eq!(stats.fallbacks_total, 1);
+
+    drop(second);
+    assert_eq!(memory.checkout(8192, 20_000).len(), 8192);
+    drop((first, third));
+    assert_eq!(memory.stats().bytes, 0);
+    assert_eq!(memory.stats().buffers, 0);
+
+    // Without a cap every buffer gets the asked-for size
+    let big: Vec<_> = (0..4).map(|_| memory.checkout(1 << 20, 0)).collect();
+    assert!(big.iter().all(|buf| buf.len() == 1 << 20));
+    assert_eq!(memory.stats().fallbacks_total, 1);
+}
+
+#[tokio::test]
+async fn running_relays_hold_their_buffers_until_they_end() {
+    let config: Config = toml::from_str("[limits]\nrelay_buffer_size = 65536").unwrap();
+    let stats = Arc::new(Stats::new(10));
+    let config_manager = ConfigManager::new(config, None);
+    let info = ConnectionInfo::new(
+        Protocol::Socks5,
+        "192.0.2.1:40000".to_string(),
+        "example.com".to_string(),
+        443,
+    );
+    let id = info.id;
+    stats.add_connection(info).await;
+
+    let (mut client, relay_client) = tokio::io::duplex(1024);
+    let (relay_target, mut target) = tokio::io::duplex(1024);
+    let handle = tokio::spawn({
+        let stats = Arc::clone(&stats);
+        async move {
+            relay_for_user(
+                relay_client,
+                relay_target,
+                id,
+                None,
+                &SessionLimits::default(),
+                &stats,
+                &config_manager,
+            )
+            .await
+        }
+    });
+
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    target.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    let held = stats.relay_buffers().stats();
+    assert_eq!(held.bytes, 2 * 65536);
+    assert_eq!(held.buffers, 2);
+
+    drop(client);
+    drop(target);
+    tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stats.relay_buffers().stats().bytes, 0);
+    assert_eq!(stats.relay_buffers().stats().buffers, 0);
+}
+
+#[test]
+fn relay_buffer_size_must_be_in_range() {
+    for size in [0, 512, 8 * 1024 * 1024] {
+        let config: Config =
+            toml::from_str(&format!("[limits]\nrelay_buffer_size = {}", size)).unwrap();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("relay_buffer_size"), "{}", err);
+    }
+    let config: Config = toml::from_str("[limits]\nrelay_buffer_size = 262144").unwrap();
+    config.validate().unwrap();
+}
@@ -0,0 +1,89 @@
+//! Access rules as plain text domain lists.
+
+use net_relay_core::rule_list::{format, parse, RuleListEntry};
+use net_relay_core::{AccessRule, RuleAction, RuleMode};
+
+fn entry(line: usize, action: RuleAction, domain: &str) -> RuleListEntry {
+    RuleListEntry {
+        line,
+        action,
+        domain: domain.to_string(),
+    }
+}
+
+#[test]
+fn lines_become_entries() {
+    let text = "\
+# Ad networks
+Ads.Example.
+deny: *.tracker.example
+
+  allow:cdn.example   # needed by the intranet
+ALLOW: bücher.example
+";
+    let entries = parse(text, RuleAction::Deny).unwrap();
+    assert_eq!(
+        entries,
+        [
+            entry(2, RuleAction::Deny, "ads.example"),
+            entry(3, RuleAction::Deny, "*.tracker.example"),
+            entry(5, RuleAction::Allow, "cdn.example"),
+            entry(6, RuleAction::Allow, "xn--bcher-kva.example"),
+        ]
+    );
+    assert_eq!(
+        parse("ads.example", RuleAction::Allow).unwrap()[0].action,
+        RuleAction::Allow
+    );
+    assert!(parse("# nothing\n\n", RuleAction::Deny).unwrap().is_empty());
+
+    let rule = entries[3].to_rule("blocklist");
+    assert_eq!(rule.name, "blocklist");
+    assert_eq!(rule.domain_unicode.as_deref(), Some("bücher.example"));
+    assert!(rule.enabled && rule.path.is_none());
+}
+
+#[test]
+fn every_invalid_line_is_reported() {
+    let errors = parse(
+        "ok.example\nblock: a.example\ntwo domains.example\nexample.com:443\n.\n",
+        RuleAction::Deny,
+    )
+    .unwrap_err();
+    let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+    assert_eq!(lines, [2, 3, 4, 5]);
+    assert!(errors[0].message.contains("block"), "{}", errors[0].message);
+}
+
+fn rule(domain: &str, action: RuleAction) -> AccessRule {
+    parse(domain, action).unwrap()[0].to_rule("test")
+}
+
+#[test]
+fn exported_lists_read_back() {
+    let mut with_path = rule("api.example", RuleAction::Deny);
+    with_path.path = Some("/admin".to_string());
+    let mut monitored = rule("watch.example", RuleAction::Deny);
+    monitored.mode = RuleMode::Monitor;
+    let rules = vec![
+        rule("ads.example", RuleAction::Deny),
+        with_path,
+        rule("cdn.example", RuleAction::Allow),
+        monitored,
+    ];
+
+    let text = format(&rules);
+    assert!(text.contains("# 2 rule(s)"), "{}", text);
+    let entries = parse(&text, RuleAction::Allow).unwrap();
+    let listed: Vec<(RuleAction, &str)> = entries
+        .iter()
+        .map(|e| (e.action.clone(), e.domain.as_str()))
+        .collect();
+    assert_eq!(
+        listed,
+        [
+            (RuleAction::Deny, "ads.example"),
+            (RuleAction::Allow, "cdn.example")
+        ]
+    );
+}
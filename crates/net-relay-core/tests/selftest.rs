@@ -0,0 +1,87 @@
+//! The end-to-end self-test.
+
+use net_relay_core::selftest::{self, CheckOutcome};
+use net_relay_core::Config;
+
+#[tokio::test]
+async fn every_listener_passes_with_auth_enabled() {
+    let config: Config = toml::from_str(
+        r#"
+        [server]
+        combined_port = 1081
+
+        [security]
+        auth_enabled = true
+        auth_exempt_ips = ["127.0.0.1"]
+
+        [[security.users]]
+        username = "alice"
+        password = "wonderland"
+
+        [access_control]
+        allow_by_default = false
+        ip_whitelist = ["10.0.0.0/8"]
+        "#,
+    )
+    .unwrap();
+
+    let report = selftest::run(&config).await.unwrap();
+    assert!(report.passed(), "{:?}", report.checks);
+    let listeners: Vec<&str> = report
+        .checks
+        .iter()
+        .filter(|check| check.check == "auth")
+        .map(|check| {
+            assert_eq!(check.outcome, CheckOutcome::Pass);
+            check.listener.as_str()
+        })
+        .collect();
+    assert_eq!(
+        listeners,
+        ["socks5", "http", "combined/socks5", "combined/http"]
+    );
+}
+
+#[tokio::test]
+async fn an_unenforced_deny_fails() {
+    let config: Config = toml::from_str(
+        r#"
+        [server]
+        http_enabled = false
+
+        [access_control]
+        monitor_only = true
+        "#,
+    )
+    .unwrap();
+
+    let report = selftest::run(&config).await.unwrap();
+    assert!(!report.passed());
+    let outcomes: Vec<(&str, CheckOutcome)> = report
+        .checks
+        .iter()
+        .map(|check| (check.check, check.outcome))
+        .collect();
+    assert_eq!(
+        outcomes,
+        [
+            ("relay", CheckOutcome::Pass),
+            ("auth", CheckOutcome::Skip),
+            ("deny", CheckOutcome::Fail),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn nothing_to_test_is_an_error() {
+    let config: Config = toml::from_str(
+        r#"
+        [server]
+        socks_enabled = false
+        http_enabled = false
+        "#,
+    )
+    .unwrap();
+
+    assert!(selftest::run(&config).await.is_err());
+}
@@ -0,0 +1,160 @@
+//! SOCKS5 username/password policy: length limits, non-UTF-8 fields and
+//! logging of refused attempts.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use net_relay_core::auth::loggable_username;
+use net_relay_core::proxy::socks5::parse_credentials;
+use net_relay_core::proxy::Socks5Proxy;
+use net_relay_core::{
+    Config, ConfigManager, CredentialLimits, Error, EventLog, EventLogBackpressure, LogRotation,
+    Stats,
+};
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[test]
+fn limits_refuse_empty_and_out_of_range_credentials() {
+    let limits = CredentialLimits::default();
+    assert_eq!(limits.check("alice", "x"), Ok(()));
+    assert_eq!(limits.check("", ""), Err("empty username"));
+    assert_eq!(limits.check("alice", ""), Err("password too short"));
+
+    let limits = CredentialLimits {
+        min_length: 3,
+        max_length: 8,
+    };
+    assert_eq!(limits.check("al", "secret"), Err("username too short"));
+    assert_eq!(limits.check("alice", "secretive"), Err("password too long"));
+    assert_eq!(limits.check("alice", "secret"), Ok(()));
+
+    for section in [
+        "min_length = 4\nmax_length = 3",
+        "max_length = 0",
+        "max_length = 256",
+    ] {
+        let config: Config =
+            toml::from_str(&format!("[security.credential_limits]\n{}", section)).unwrap();
+        assert!(config.validate().is_err(), "{}", section);
+    }
+}
+
+#[test]
+fn non_utf8_credentials_are_rejected() {
+    let auth = [0x01, 0x03, b'a', 0xFF, b'b', 0x01, b'p'];
+    match parse_credentials(&auth) {
+        Err(Error::CredentialsRejected { username, .. }) => {
+            assert_eq!(username.as_deref(), Some("a?b"))
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    let auth = [0x01, 0x01, b'a', 0x01, 0xC3];
+    assert!(matches!(
+        parse_credentials(&auth),
+        Err(Error::CredentialsRejected { .. })
+    ));
+}
+
+#[test]
+fn logged_usernames_are_sanitized_and_truncated() {
+    assert_eq!(loggable_username(b"alice"), "alice");
+    assert_eq!(loggable_username(b"a\r\nb\x1b[0m"), "a??b?[0m");
+    let long = loggable_username(&[b'x'; 255]);
+    assert_eq!(long.chars().count(), 65);
+    assert!(long.ends_with('…'));
+}
+
+/// Run a username/password handshake and return the auth status byte.
+async fn login(proxy: SocketAddr, username: &[u8], password: &[u8]) -> u8 {
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    stream.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await.unwrap();
+    assert_eq!(choice, [0x05, 0x02]);
+
+    let mut auth = vec![0x01, username.len() as u8];
+    auth.extend_from_slice(username);
+    auth.push(password.len() as u8);
+    auth.extend_from_slice(password);
+    stream.write_all(&auth).await.unwrap();
+    let mut status = [0u8; 2];
+    stream.read_exact(&mut status).await.unwrap();
+    status[1]
+}
+
+#[tokio::test]
+async fn refused_logins_are_logged_with_the_attempted_username() {
+    let dir = std::env::temp_dir().join(format!("net-relay-creds-{}", uuid::Uuid::new_v4()));
+    let path = dir.join("events.jsonl");
+    let event_log = EventLog::open(&path, LogRotation::Never, EventLogBackpressure::Block).unwrap();
+    let stats = Arc::new(Stats::new(10).with_event_log(event_log));
+
+    let config: Config = toml::from_str(
+        r#"
+        [security]
+        auth_enabled = true
+
+        [[security.users]]
+        username = "alice"
+        password = "wonderland"
+        "#,
+    )
+    .unwrap();
+    let proxy = Socks5Proxy::builder()
+        .config(ConfigManager::new(config, None))
+        .stats(Arc::clone(&stats))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+
+    assert_eq!(login(proxy_addr, b"alice", b"wonderland").await, 0x00);
+    assert_eq!(login(proxy_addr, b"", b"").await, 0x01);
+    assert_eq!(login(proxy_addr, b"al\nice", b"wrong").await, 0x01);
+    assert_eq!(login(proxy_addr, b"alic\xC3", b"wonderland").await, 0x01);
+
+    // Failures are recorded after the reply; wait for all three
+    let mut events = Vec::new();
+    for _ in 0..100 {
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        let content = std::fs::read_to_string(&path).unwrap_or_default();
+        events = content
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .filter(|event| event["type"] == "auth_failure")
+            .collect();
+        if events.len() == 3 {
+            break;
+        }
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(events.len(), 3, "{:?}", events);
+    assert!(events.iter().all(|event| event["client_ip"] == "127.0.0.1"));
+    let mut attempts: Vec<(&str, &str)> = events
+        .iter()
+        .map(|event| {
+            (
+                event["user"].as_str().unwrap(),
+                event["reason"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    attempts.sort();
+    assert_eq!(
+        attempts,
+        [
+            ("", "Authentication failed: empty username"),
+            (
+                "al?ice",
+                "Authentication failed: invalid username or password"
+            ),
+            (
+                "alic?",
+                "Authentication failed: credentials are not valid UTF-8"
+            ),
+        ]
+    );
+}
@@ -0,0 +1,91 @@
+//! SOCKS5 auth method negotiation.
+
+use net_relay_core::proxy::socks5::{select_auth_method, AuthPolicy};
+use net_relay_core::{Config, Socks5AuthMethod};
+
+const NONE: u8 = 0x00;
+const GSSAPI: u8 = 0x01;
+const PASS: u8 = 0x02;
+/// No acceptable methods.
+const NO: u8 = 0xFF;
+
+fn policy(required: bool, exempt: bool, allowed: &[u8]) -> AuthPolicy {
+    AuthPolicy {
+        required,
+        exempt_user: exempt.then(|| "ip:10.0.0.0/8".to_string()),
+        allowed_methods: allowed.to_vec(),
+    }
+}
+
+#[test]
+fn selection_matrix() {
+    let both = &[NONE, PASS][..];
+    let offers: [&[u8]; 6] = [
+        &[],
+        &[NONE],
+        &[PASS],
+        &[NONE, PASS],
+        &[PASS, NONE, PASS, NONE],
+        &[GSSAPI, 0x80],
+    ];
+    // (required, exempt, allowed) -> selection per offer above
+    let cases: [(bool, bool, &[u8], [u8; 6]); 7] = [
+        (false, false, both, [NO, NONE, PASS, NONE, NONE, NO]),
+        (false, false, &[NONE], [NO, NONE, NO, NONE, NONE, NO]),
+        (false, false, &[PASS], [NO, NO, PASS, PASS, PASS, NO]),
+        (true, false, both, [NO, NO, PASS, PASS, PASS, NO]),
+        (true, true, both, [NO, NONE, PASS, PASS, PASS, NO]),
+        (true, true, &[PASS], [NO, NO, PASS, PASS, PASS, NO]),
+        (true, false, &[NONE], [NO; 6]),
+    ];
+    for (required, exempt, allowed, expected) in cases {
+        let policy = policy(required, exempt, allowed);
+        for (offered, expected) in offers.iter().zip(expected) {
+            assert_eq!(
+                select_auth_method(offered, &policy),
+                expected,
+                "{:?} offering {:?}",
+                policy,
+                offered
+            );
+        }
+    }
+}
+
+#[test]
+fn policy_from_security_config() {
+    let config: Config = toml::from_str(
+        r#"
+        [security]
+        auth_enabled = true
+        auth_exempt_ips = ["10.0.0.0/8"]
+        socks5_auth_methods = ["username_password"]
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    assert_eq!(
+        AuthPolicy::new(&config.security, "10.1.2.3"),
+        policy(true, true, &[PASS])
+    );
+    assert_eq!(
+        AuthPolicy::new(&config.security, "192.0.2.1"),
+        policy(true, false, &[PASS])
+    );
+
+    // Both methods by default; exemptions only matter when auth is required
+    let config = Config::default();
+    assert_eq!(
+        config.security.socks5_auth_methods,
+        [Socks5AuthMethod::NoAuth, Socks5AuthMethod::UsernamePassword]
+    );
+    let mut security = config.security;
+    security.auth_exempt_ips = vec!["10.0.0.0/8".to_string()];
+    assert_eq!(
+        AuthPolicy::new(&security, "10.1.2.3"),
+        policy(false, false, &[NONE, PASS])
+    );
+
+    let config: Config = toml::from_str("[security]\nsocks5_auth_methods = []").unwrap();
+    assert!(config.validate().is_err());
+}
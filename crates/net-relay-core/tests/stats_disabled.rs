@@ -0,0 +1,104 @@
+//! `Stats` with collection turned off (`stats.enabled = false`).
+
+use net_relay_core::connection::{CloseReason, ConnectionInfo, Protocol};
+use net_relay_core::stats::{DeniedAttempt, HistorySince};
+use net_relay_core::Stats;
+
+fn connection(user: Option<&str>) -> ConnectionInfo {
+    let mut info = ConnectionInfo::new(
+        Protocol::Socks5,
+        "192.0.2.1:40000".to_string(),
+        "example.com".to_string(),
+        443,
+    );
+    info.username = user.map(str::to_string);
+    info
+}
+
+async fn relay(stats: &Stats, user: Option<&str>) -> Option<ConnectionInfo> {
+    let info = connection(user);
+    let id = info.id;
+    stats.add_connection(info).await;
+    stats
+        .close_connection(id, 10, 20, CloseReason::TargetEof)
+        .await
+}
+
+#[tokio::test]
+async fn disabled_stats_only_count_totals() {
+    let stats = Stats::new(10);
+    stats.set_enabled(false).await;
+    assert!(!stats.is_enabled());
+
+    assert!(relay(&stats, Some("alice")).await.is_none());
+    assert!(relay(&stats, None).await.is_none());
+    stats
+        .record_denied(DeniedAttempt::new(
+            "192.0.2.1:40000",
+            "socks5",
+            None,
+            "denied",
+        ))
+        .await;
+
+    let aggregated = stats.get_aggregated().await;
+    assert_eq!(aggregated.total_connections, 2);
+    assert_eq!(aggregated.active_connections, 0);
+    assert_eq!(aggregated.total_bytes_sent, 20);
+    assert_eq!(aggregated.total_bytes_received, 40);
+
+    let history = stats.get_history(None, HistorySince::default()).await;
+    assert!(history.connections.is_empty());
+    assert!(stats.get_user_stats().await.is_empty());
+    assert!(stats.get_denied(None).await.is_empty());
+    assert!(stats.get_active().await.is_empty());
+}
+
+#[tokio::test]
+async fn untracked_connections_still_count_towards_user_limits() {
+    let stats = Stats::new(10);
+    stats.set_enabled(false).await;
+
+    let info = connection(Some("alice"));
+    let id = info.id;
+    stats.add_connection(info).await;
+    let alice = stats.get_user("alice").await.unwrap();
+    assert_eq!(alice.active_connections, 1);
+    assert!(stats.get_active().await.is_empty());
+
+    stats
+        .close_connection(id, 0, 0, CloseReason::TargetEof)
+        .await;
+    assert!(stats.get_user("alice").await.is_none());
+    assert_eq!(stats.get_aggregated().await.active_connections, 0);
+}
+
+#[tokio::test]
+async fn turning_stats_off_drops_recorded_data() {
+    let stats = Stats::new(10);
+    assert!(relay(&stats, Some("alice")).await.is_some());
+    let open = connection(Some("bob"));
+    let open_id = open.id;
+    stats.add_connection(open).await;
+
+    stats.set_enabled(false).await;
+    let history = stats.get_history(None, HistorySince::default()).await;
+    assert!(history.connections.is_empty());
+    assert!(stats.get_active().await.is_empty());
+    // bob's open connection is still counted until it closes
+    assert_eq!(stats.get_user("bob").await.unwrap().active_connections, 1);
+    assert_eq!(stats.get_aggregated().await.active_connections, 1);
+    assert!(stats
+        .close_connection(open_id, 1, 1, CloseReason::TargetEof)
+        .await
+        .is_none());
+    assert_eq!(stats.get_aggregated().await.active_connections, 0);
+    assert!(stats.get_user("bob").await.is_none());
+
+    // Turned on again, connections are recorded as before
+    stats.set_enabled(true).await;
+    assert!(relay(&stats, Some("alice")).await.is_some());
+    let history = stats.get_history(None, HistorySince::default()).await;
+    assert_eq!(history.connections.len(), 1);
+    assert_eq!(stats.get_aggregated().await.total_connections, 3);
+}
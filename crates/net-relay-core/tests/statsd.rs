@@ -0,0 +1,72 @@
+//! StatsD emitter tests.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use net_relay_core::connection::{CloseReason, ConnectionInfo, Protocol};
+use net_relay_core::{statsd, Error, Stats, StatsdConfig};
+use tokio::net::UdpSocket;
+
+#[tokio::test]
+async fn connection_metrics_reach_the_agent() {
+    let agent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let config = StatsdConfig {
+        enabled: true,
+        port: agent.local_addr().unwrap().port(),
+        tags: vec!["env:test".to_string()],
+        flush_interval_secs: 1,
+        max_user_tags: 1,
+        ..Default::default()
+    };
+    let stats = Arc::new(Stats::new(10));
+    tokio::spawn(statsd::run(config, Arc::clone(&stats)));
+    // Let the emitter subscribe
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for user in ["alice", "bob"] {
+        let mut info = ConnectionInfo::new(
+            Protocol::Socks5,
+            "10.0.0.5:40000".to_string(),
+            "example.com".to_string(),
+            443,
+        );
+        info.username = Some(user.to_string());
+        let id = info.id;
+        stats.add_connection(info).await;
+        stats
+            .close_connection(id, 100, 200, CloseReason::TargetEof)
+            .await;
+    }
+    let client = "10.0.0.6:40001".parse().unwrap();
+    stats
+        .record_rejected("socks5", client, &Error::AuthenticationFailed)
+        .await;
+
+    // The first flush may come before the events, keep reading until they show up
+    let mut buf = [0u8; 2048];
+    let lines = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let len = agent.recv(&mut buf).await.unwrap();
+            let packet = String::from_utf8_lossy(&buf[..len]).to_string();
+            if packet.contains("connections.opened") {
+                return packet;
+            }
+        }
+    })
+    .await
+    .expect("no metrics received");
+    let lines: Vec<&str> = lines.lines().collect();
+
+    for expected in [
+        "net_relay.connections.opened:2|c|#env:test,protocol:socks5",
+        "net_relay.connections.closed:2|c|#env:test,protocol:socks5,reason:target_eof",
+        "net_relay.bytes.sent:200|c|#env:test",
+        "net_relay.bytes.received:400|c|#env:test",
+        "net_relay.user.bytes.sent:100|c|#env:test,user:alice",
+        "net_relay.user.bytes.sent:100|c|#env:test,user:other",
+        "net_relay.auth_failures:1|c|#env:test,source:socks5",
+        "net_relay.connections.active:0|g|#env:test",
+    ] {
+        assert!(lines.contains(&expected), "{} not in {:?}", expected, lines);
+    }
+}
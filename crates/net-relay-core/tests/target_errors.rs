@@ -0,0 +1,123 @@
+//! Connect outcomes per target host.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use net_relay_core::proxy::{HttpProxy, Socks5Proxy};
+use net_relay_core::target_errors::{TargetErrors, MAX_TRACKED_TARGETS};
+use net_relay_core::{Config, ConfigManager, ConnectOutcome, Error, Stats};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// An address nothing listens on.
+async fn closed_port() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap()
+}
+
+#[test]
+fn outcomes_age_out_of_the_window() {
+    let errors = TargetErrors::default();
+    let start = Utc::now();
+    errors.record("API.partner.com", ConnectOutcome::Refused, start);
+    errors.record("api.partner.com", ConnectOutcome::Success, start);
+    errors.record("api.partner.com", ConnectOutcome::Timeout, start);
+    errors.record("ok.example", ConnectOutcome::Success, start);
+    errors.record("dns.example", ConnectOutcome::DnsFailure, start);
+
+    let failing = errors.failing(start, None);
+    assert_eq!(failing.len(), 2);
+    let partner = &failing[0];
+    assert_eq!(partner.host, "api.partner.com");
+    assert_eq!(partner.attempts, 3);
+    assert_eq!(partner.refused, 1);
+    assert_eq!(partner.timeout, 1);
+    assert!((partner.error_rate - 2.0 / 3.0).abs() < 1e-9);
+    assert_eq!(failing[1].host, "dns.example");
+    assert_eq!(errors.failing(start, Some(1)).len(), 1);
+
+    // Half an hour later the old failures still count alongside new ones
+    let later = start + Duration::minutes(30);
+    errors.record("api.partner.com", ConnectOutcome::Unreachable, later);
+    assert_eq!(errors.failing(later, None)[0].attempts, 4);
+
+    // After an hour only the recent failure is left, and quiet targets are dropped
+    let failing = errors.failing(start + Duration::minutes(70), None);
+    assert_eq!(failing.len(), 1);
+    assert_eq!(failing[0].attempts, 1);
+    assert_eq!(failing[0].unreachable, 1);
+    assert_eq!(errors.len(), 1);
+}
+
+#[test]
+fn tracked_targets_are_bounded() {
+    let errors = TargetErrors::default();
+    let now = Utc::now();
+    for i in 0..MAX_TRACKED_TARGETS + 10 {
+        errors.record(&format!("host{}.example", i), ConnectOutcome::Refused, now);
+    }
+    assert_eq!(errors.len(), MAX_TRACKED_TARGETS);
+}
+
+#[test]
+fn proxy_denials_are_not_connect_outcomes() {
+    let denied = Error::AccessDenied(net_relay_core::DenyReason::Target, "blocked".into());
+    assert_eq!(ConnectOutcome::of_error(&denied), None);
+    assert_eq!(
+        ConnectOutcome::of_error(&Error::AddressResolution("nope".into())),
+        Some(ConnectOutcome::DnsFailure)
+    );
+    let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+    assert_eq!(
+        ConnectOutcome::of_error(&Error::Io(refused)),
+        Some(ConnectOutcome::Refused)
+    );
+}
+
+#[tokio::test]
+async fn both_proxies_count_refused_connects() {
+    let target = closed_port().await;
+    let stats = Arc::new(Stats::new(10));
+    let config = ConfigManager::new(Config::default(), None);
+
+    let http = HttpProxy::builder()
+        .stats(Arc::clone(&stats))
+        .config(config.clone())
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { http.run(listener).await });
+
+    let socks = Socks5Proxy::builder()
+        .stats(Arc::clone(&stats))
+        .config(config)
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let socks_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { socks.run(listener).await });
+
+    let mut stream = TcpStream::connect(http_addr).await.unwrap();
+    let request = format!("CONNECT {} HTTP/1.1\r\n\r\n", target);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 502"));
+
+    let mut stream = TcpStream::connect(socks_addr).await.unwrap();
+    stream.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method).await.unwrap();
+    let mut request = vec![0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await.unwrap();
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_ne!(reply[1], 0x00);
+
+    let failing = stats.get_target_errors(None);
+    assert_eq!(failing.len(), 1);
+    assert_eq!(failing[0].host, "127.0.0.1");
+    assert_eq!(failing[0].refused, 2);
+    assert_eq!(failing[0].error_rate, 1.0);
+}
@@ -0,0 +1,134 @@
+//! Certificates reloaded from their files while listeners keep running.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{Datelike, Utc};
+use net_relay_core::tls::{CertStore, FileCert};
+
+/// Certificate and key files in a temporary directory.
+struct CertFiles {
+    dir: PathBuf,
+}
+
+impl CertFiles {
+    fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("net-relay-certs-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self { dir }
+    }
+
+    /// Write a self-signed `localhost` certificate expiring on January 1st of `year`.
+    fn write(&self, year: i32) {
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params.not_after = rcgen::date_time_ymd(year, 1, 1);
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        std::fs::write(self.cert_path(), cert.pem()).unwrap();
+        std::fs::write(self.key_path(), key.serialize_pem()).unwrap();
+    }
+
+    fn cert_path(&self) -> PathBuf {
+        self.dir.join("cert.pem")
+    }
+
+    fn key_path(&self) -> PathBuf {
+        self.dir.join("key.pem")
+    }
+}
+
+impl Drop for CertFiles {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn reload_swaps_the_certificate() {
+    let files = CertFiles::new();
+    files.write(2040);
+    let cert = FileCert::load("tunnel", files.cert_path(), files.key_path()).unwrap();
+    let before = cert.current();
+    assert_eq!(cert.status().not_after.year(), 2040);
+    assert!(!cert.changed());
+
+    files.write(2041);
+    assert!(cert.changed());
+    cert.reload().unwrap();
+    assert!(!cert.changed());
+    assert_ne!(cert.current().cert[0], before.cert[0]);
+    let status = cert.status();
+    assert_eq!(status.listener, "tunnel");
+    assert_eq!(status.not_after.year(), 2041);
+    assert!(status.last_error.is_none());
+}
+
+#[test]
+fn failed_reload_keeps_the_previous_certificate() {
+    let files = CertFiles::new();
+    files.write(2040);
+    let cert = FileCert::load("api", files.cert_path(), files.key_path()).unwrap();
+    let before = cert.current();
+
+    std::fs::write(files.cert_path(), "not a certificate").unwrap();
+    assert!(cert.reload().is_err());
+    assert_eq!(cert.current().cert[0], before.cert[0]);
+    let status = cert.status();
+    assert_eq!(status.not_after.year(), 2040);
+    assert!(status.last_error.is_some());
+    assert!(status.last_error_at.is_some());
+
+    // A key belonging to another certificate is refused as well
+    let other = CertFiles::new();
+    other.write(2042);
+    std::fs::copy(other.cert_path(), files.cert_path()).unwrap();
+    assert!(cert.reload().is_err());
+    assert_eq!(cert.current().cert[0], before.cert[0]);
+
+    files.write(2043);
+    cert.reload().unwrap();
+    let status = cert.status();
+    assert_eq!(status.not_after.year(), 2043);
+    assert!(status.last_error.is_none());
+}
+
+#[test]
+fn store_reloads_changed_certificates() {
+    let (api, tunnel) = (CertFiles::new(), CertFiles::new());
+    api.write(2040);
+    tunnel.write(2040);
+    let store = CertStore::new();
+    for (name, files) in [("api", &api), ("tunnel", &tunnel)] {
+        let cert = FileCert::load(name, files.cert_path(), files.key_path()).unwrap();
+        store.add(Arc::new(cert));
+    }
+    assert_eq!(store.reload_changed(), 0);
+
+    tunnel.write(2041);
+    assert_eq!(store.reload_changed(), 1);
+    let years: Vec<_> = store
+        .statuses()
+        .iter()
+        .map(|cert| (cert.listener.clone(), cert.not_after.year()))
+        .collect();
+    assert_eq!(
+        years,
+        [("api".to_string(), 2040), ("tunnel".to_string(), 2041)]
+    );
+
+    std::fs::write(api.key_path(), "garbage").unwrap();
+    let statuses = store.reload();
+    assert!(statuses[0].last_error.is_some());
+    assert!(statuses[1].last_error.is_none());
+}
+
+#[test]
+fn expiry_warning_window() {
+    let files = CertFiles::new();
+    files.write(Utc::now().year() + 1);
+    let cert = FileCert::load("api", files.cert_path(), files.key_path()).unwrap();
+    let status = cert.status();
+    assert!(!status.expires_within(14, Utc::now()));
+    assert!(status.expires_within(400, Utc::now()));
+    assert!(status.expires_within(0, status.not_after));
+}
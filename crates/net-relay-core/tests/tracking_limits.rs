@@ -0,0 +1,151 @@
+//! Bounds on the per-user counters and denied attempts kept by `Stats`.
+
+use std::collections::HashSet;
+
+use net_relay_core::connection::{CloseReason, ConnectionInfo, Protocol};
+use net_relay_core::stats::DeniedAttempt;
+use net_relay_core::{Config, IpAttribution, Stats};
+
+fn connection(user: Option<&str>) -> ConnectionInfo {
+    let mut info = ConnectionInfo::new(
+        Protocol::Socks5,
+        "192.0.2.1:40000".to_string(),
+        "example.com".to_string(),
+        443,
+    );
+    info.username = user.map(str::to_string);
+    info
+}
+
+async fn relay(stats: &Stats, user: &str) {
+    let info = connection(Some(user));
+    let id = info.id;
+    stats.add_connection(info).await;
+    stats
+        .close_connection(id, 1, 1, CloseReason::TargetEof)
+        .await;
+}
+
+fn configured(names: &[&str]) -> HashSet<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[tokio::test]
+async fn users_stay_within_the_limit() {
+    let stats = Stats::new(10);
+    stats
+        .set_tracking_limits(100, 10, configured(&["alice"]))
+        .await;
+    relay(&stats, "alice").await;
+    let busy = connection(Some("busy"));
+    stats.add_connection(busy).await;
+
+    // Every attempt under a new name must not grow the map without bound
+    for i in 0..5_000 {
+        relay(&stats, &format!("stuffed-{}", i)).await;
+    }
+    let sizes = stats.sizes().await;
+    assert!(sizes.users <= 100, "{} users", sizes.users);
+    assert!(sizes.evicted_users >= 4_900);
+
+    // Configured users and users with open connections are kept
+    assert!(stats.get_user("alice").await.is_some());
+    assert_eq!(stats.get_user("busy").await.unwrap().active_connections, 1);
+    // The most recently active names survive
+    assert!(stats.get_user("stuffed-4999").await.is_some());
+    assert!(stats.get_user("stuffed-0").await.is_none());
+
+    // Shrinking the limit evicts right away
+    stats
+        .set_tracking_limits(5, 10, configured(&["alice"]))
+        .await;
+    assert!(stats.sizes().await.users <= 5);
+    assert!(stats.get_user("alice").await.is_some());
+    assert!(stats.get_user("busy").await.is_some());
+}
+
+#[tokio::test]
+async fn ip_buckets_are_limited_separately() {
+    let stats = Stats::new(10).with_ip_attribution(Some(IpAttribution {
+        ipv4_prefix: 32,
+        ipv6_prefix: 128,
+        max_buckets: 3,
+    }));
+    stats.set_tracking_limits(2, 10, HashSet::new()).await;
+    for i in 0..10 {
+        let info = ConnectionInfo::new(
+            Protocol::Socks5,
+            format!("198.51.100.{}:1", i),
+            "example.com".to_string(),
+            443,
+        );
+        let id = info.id;
+        stats.add_connection(info).await;
+        stats
+            .close_connection(id, 1, 1, CloseReason::TargetEof)
+            .await;
+    }
+    relay(&stats, "a").await;
+    relay(&stats, "b").await;
+
+    let sizes = stats.sizes().await;
+    assert_eq!(sizes.users, 5);
+    assert_eq!(sizes.evicted_ip_buckets, 7);
+    assert_eq!(sizes.evicted_users, 0);
+}
+
+#[tokio::test]
+async fn denied_attempts_stay_within_the_limit() {
+    let stats = Stats::new(10);
+    stats.set_tracking_limits(10, 50, HashSet::new()).await;
+    for i in 0..120 {
+        stats
+            .record_denied(DeniedAttempt::new(
+                format!("203.0.113.{}", i % 250),
+                "socks5",
+                None,
+                format!("attempt {}", i),
+            ))
+            .await;
+    }
+    let sizes = stats.sizes().await;
+    assert_eq!(sizes.denied, 50);
+    assert_eq!(sizes.evicted_denied, 70);
+
+    stats.set_tracking_limits(10, 20, HashSet::new()).await;
+    let sizes = stats.sizes().await;
+    assert_eq!(sizes.denied, 20);
+    assert_eq!(sizes.evicted_denied, 100);
+}
+
+#[test]
+fn limits_config() {
+    let config = Config::default();
+    assert_eq!(config.stats.max_tracked_users, 10_000);
+    assert_eq!(config.stats.max_denied_attempts, 1000);
+
+    let config: Config = toml::from_str(
+        r#"
+        [security]
+        username = "legacy"
+        password = "x"
+        [[security.users]]
+        username = "alice"
+        password = "y"
+        enabled = false
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.security.configured_usernames(),
+        configured(&["alice", "legacy"])
+    );
+
+    for toml in [
+        "[stats]\nmax_tracked_users = 0",
+        "[stats]\nmax_denied_attempts = 0",
+    ] {
+        let config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_err(), "{}", toml);
+    }
+}
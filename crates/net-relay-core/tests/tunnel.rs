@@ -0,0 +1,305 @@
+//! Two-instance relay-to-relay tunnel tests.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use net_relay_core::proxy::{Socks5Proxy, TunnelServer};
+use net_relay_core::stats::{ConnectionStats, HistorySince};
+use net_relay_core::{tls, Config, ConfigManager, Stats};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Self-signed certificate for `localhost`, written to a temporary directory.
+struct TestCert {
+    dir: PathBuf,
+}
+
+impl TestCert {
+    fn generate() -> Self {
+        let dir = std::env::temp_dir().join(format!("net-relay-tunnel-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(dir.join("cert.pem"), certified.cert.pem()).unwrap();
+        std::fs::write(dir.join("key.pem"), certified.signing_key.serialize_pem()).unwrap();
+        Self { dir }
+    }
+
+    fn cert_path(&self) -> String {
+        self.dir.join("cert.pem").display().to_string()
+    }
+
+    fn key_path(&self) -> String {
+        self.dir.join("key.pem").display().to_string()
+    }
+}
+
+impl Drop for TestCert {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Start a TCP echo server and return its address.
+async fn start_echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+/// Start the central instance's tunnel server.
+async fn start_central(cert: &TestCert, downstream_secret: &str) -> (SocketAddr, Arc<Stats>) {
+    let config: Config = toml::from_str(&format!(
+        r#"
+        [[server.trusted_downstreams]]
+        name = "edge-1"
+        secret = "{}"
+        "#,
+        downstream_secret
+    ))
+    .unwrap();
+    let stats = Arc::new(Stats::new(100));
+    let tls_config = tls::server_config(cert.cert_path(), cert.key_path()).unwrap();
+    let server = TunnelServer::new(
+        tls_config,
+        Arc::clone(&stats),
+        ConfigManager::new(config, None),
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.run(listener).await });
+    (addr, stats)
+}
+
+/// Start the edge instance's SOCKS5 proxy, forwarding through `central`.
+async fn start_edge(
+    cert: &TestCert,
+    central: SocketAddr,
+    node_secret: &str,
+) -> (SocketAddr, Arc<Stats>) {
+    let (addr, stats, _) = start_edge_with(cert, central, node_secret, "").await;
+    (addr, stats)
+}
+
+/// Start an edge whose `[upstream]` table ends with `extra` (e.g. more relays).
+async fn start_edge_with(
+    cert: &TestCert,
+    primary: SocketAddr,
+    node_secret: &str,
+    extra: &str,
+) -> (SocketAddr, Arc<Stats>, ConfigManager) {
+    let config: Config = toml::from_str(&format!(
+        r#"
+        [security]
+        auth_enabled = true
+
+        [[security.users]]
+        username = "alice"
+        password = "wonderland"
+
+        [upstream]
+        enabled = true
+        address = "{}"
+        server_name = "localhost"
+        ca_file = "{}"
+        node_name = "edge-1"
+        node_secret = "{}"
+        {}
+        "#,
+        primary,
+        cert.cert_path().replace('\\', "\\\\"),
+        node_secret,
+        extra
+    ))
+    .unwrap();
+    config.validate().unwrap();
+    let stats = Arc::new(Stats::new(100));
+    let config_manager = ConfigManager::new(config, None);
+    let proxy = Socks5Proxy::new(None, Arc::clone(&stats), config_manager.clone());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { proxy.run(listener).await });
+    (addr, stats, config_manager)
+}
+
+/// Perform a SOCKS5 handshake as alice and return the reply code.
+async fn socks5_connect(stream: &mut TcpStream, target: SocketAddr) -> u8 {
+    stream.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method).await.unwrap();
+    assert_eq!(method, [0x05, 0x02]);
+
+    let mut auth = vec![0x01, 5];
+    auth.extend_from_slice(b"alice");
+    auth.push(10);
+    auth.extend_from_slice(b"wonderland");
+    stream.write_all(&auth).await.unwrap();
+    let mut auth_reply = [0u8; 2];
+    stream.read_exact(&mut auth_reply).await.unwrap();
+    assert_eq!(auth_reply, [0x01, 0x00]);
+
+    let SocketAddr::V4(target) = target else {
+        panic!("IPv4 target expected");
+    };
+    let mut request = vec![0x05, 0x01, 0x00, 0x01];
+    request.extend_from_slice(&target.ip().octets());
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await.unwrap();
+
+    let mut reply = [0u8; 10];
+    stream.read_exact(&mut reply).await.unwrap();
+    reply[1]
+}
+
+/// Wait until a connection shows up in the history.
+async fn wait_for_history(stats: &Stats) -> Vec<ConnectionStats> {
+    for _ in 0..100 {
+        let history = stats
+            .get_history(None, HistorySince::default())
+            .await
+            .connections;
+        if !history.is_empty() {
+            return history;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("connection never closed");
+}
+
+#[tokio::test]
+async fn tunneled_connection_is_attributed_on_both_nodes() {
+    let cert = TestCert::generate();
+    let echo = start_echo_server().await;
+    let (central_addr, central_stats) = start_central(&cert, "s3cret").await;
+    let (edge_addr, edge_stats) = start_edge(&cert, central_addr, "s3cret").await;
+
+    let mut client = TcpStream::connect(edge_addr).await.unwrap();
+    let client_addr = client.local_addr().unwrap();
+    assert_eq!(socks5_connect(&mut client, echo).await, 0x00);
+
+    client.write_all(b"hello through the tunnel").await.unwrap();
+    let mut echoed = [0u8; 24];
+    client.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"hello through the tunnel");
+    drop(client);
+
+    let edge_history = wait_for_history(&edge_stats).await;
+    assert_eq!(edge_history.len(), 1);
+    let edge = &edge_history[0].info;
+    assert_eq!(edge.username.as_deref(), Some("alice"));
+    assert_eq!(edge.client_addr, client_addr.to_string());
+    assert_eq!(edge.target_port, echo.port());
+    assert_eq!(edge.bytes_sent, 24);
+    assert_eq!(edge.via, None);
+
+    let central_history = wait_for_history(&central_stats).await;
+    assert_eq!(central_history.len(), 1);
+    let central = &central_history[0].info;
+    assert_eq!(central.username.as_deref(), Some("alice"));
+    assert_eq!(central.client_addr, client_addr.to_string());
+    assert_eq!(central.target_addr, echo.ip().to_string());
+    assert_eq!(central.target_port, echo.port());
+    assert_eq!(central.bytes_sent, 24);
+    assert_eq!(central.bytes_received, 24);
+    assert_eq!(central.via.as_deref(), Some("edge-1"));
+
+    let user = central_stats.get_user("alice").await.unwrap();
+    assert_eq!(user.total_connections, 1);
+}
+
+#[tokio::test]
+async fn fails_over_to_next_relay_when_primary_is_down() {
+    let cert = TestCert::generate();
+    let echo = start_echo_server().await;
+    let (central_addr, central_stats) = start_central(&cert, "s3cret").await;
+    // Nothing listens on the primary relay's port
+    let primary = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let (edge_addr, edge_stats, edge_config) = start_edge_with(
+        &cert,
+        primary,
+        "s3cret",
+        &format!(
+            r#"
+            [[upstream.relays]]
+            address = "{}"
+            server_name = "localhost"
+            priority = 10
+            "#,
+            central_addr
+        ),
+    )
+    .await;
+
+    let mut client = TcpStream::connect(edge_addr).await.unwrap();
+    assert_eq!(socks5_connect(&mut client, echo).await, 0x00);
+    client.write_all(b"hello through the backup").await.unwrap();
+    let mut echoed = [0u8; 24];
+    client.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"hello through the backup");
+    drop(client);
+
+    let edge_history = wait_for_history(&edge_stats).await;
+    assert_eq!(
+        edge_history[0].info.upstream.as_deref(),
+        Some(central_addr.to_string().as_str())
+    );
+    assert_eq!(wait_for_history(&central_stats).await.len(), 1);
+
+    let pool = edge_config.upstream_pool();
+    let relays = pool.statuses();
+    assert_eq!(relays.len(), 2);
+    assert_eq!(relays[0].address, primary.to_string());
+    assert!(!relays[0].healthy && !relays[0].active);
+    assert!(relays[1].healthy && relays[1].active);
+    assert_eq!(pool.active(), Some(central_addr.to_string()));
+
+    let events = pool.events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].from, Some(primary.to_string()));
+    assert_eq!(events[0].to, Some(central_addr.to_string()));
+
+    let traffic = edge_stats.get_upstream_stats();
+    assert_eq!(traffic.len(), 1);
+    assert_eq!(traffic[0].address, central_addr.to_string());
+    assert_eq!(traffic[0].total_connections, 1);
+    assert_eq!(traffic[0].total_bytes_sent, 24);
+    assert_eq!(traffic[0].total_bytes_received, 24);
+}
+
+#[tokio::test]
+async fn upstream_rejects_wrong_node_secret() {
+    let cert = TestCert::generate();
+    let echo = start_echo_server().await;
+    let (central_addr, central_stats) = start_central(&cert, "s3cret").await;
+    let (edge_addr, edge_stats) = start_edge(&cert, central_addr, "wrong").await;
+
+    let mut client = TcpStream::connect(edge_addr).await.unwrap();
+    assert_ne!(socks5_connect(&mut client, echo).await, 0x00);
+
+    assert!(edge_stats
+        .get_history(None, HistorySince::default())
+        .await
+        .connections
+        .is_empty());
+    assert!(central_stats.get_active().await.is_empty());
+    assert!(central_stats
+        .get_history(None, HistorySince::default())
+        .await
+        .connections
+        .is_empty());
+}
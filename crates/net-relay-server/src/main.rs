@@ -3,149 +3,1018 @@
 //! Main entry point for the net-relay proxy server.
 
 use anyhow::{Context, Result};
-use net_relay_api::create_router;
-use net_relay_core::proxy::{HttpProxy, Socks5Proxy};
-use net_relay_core::{Config, ConfigManager, LoggingConfig, Stats};
-use std::net::SocketAddr;
-use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use net_relay_api::{
+    create_router, serve_http_challenges, ActiveServices, TlsConnectInfo, TlsListener,
+};
+use net_relay_core::config::AcmeChallenge;
+use net_relay_core::listeners;
+use net_relay_core::proxy::{
+    CancellationToken, CombinedProxy, HttpProxy, Socks5Proxy, TunnelServer,
+};
+use net_relay_core::tls;
+use net_relay_core::tls::{CertStore, DashboardCerts, FileCert};
+use net_relay_core::upstream;
+use net_relay_core::{
+    AccessLog, AllowedIpsMigration, AuthBackend, Config, ConfigManager, ConfigProvenance,
+    DashboardSecurityWarning, EventLog, ListenOptions, ListenerInfo, LoggingConfig, QuotaTracker,
+    ServerState, Stats, StatsCheckpoint, TelemetryConfig,
+};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tracing::{error, info};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::{EnvFilter, Layer};
+
+mod selftest;
+mod syslog;
+mod telemetry;
+mod users;
+
+/// Where the config file is looked for, in order.
+const CONFIG_PATHS: [&str; 2] = ["config.toml", "/etc/net-relay/config.toml"];
+
+/// How often per-user quota usage is written to disk.
+const QUOTA_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often expired access rules are cleaned up.
+const RULE_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the connection history is trimmed to `stats` limits.
+const HISTORY_TRIM_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often active connections are checked for relays that are gone.
+const ORPHAN_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long shutdown waits for open connections to record their close.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often certificate files are checked for changes.
+const CERT_WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Command line arguments.
+#[derive(Debug, Parser)]
+#[command(
+    name = "net-relay",
+    version,
+    about = "SOCKS5 and HTTP CONNECT proxy with web dashboard"
+)]
+struct Cli {
+    /// Keep running when some listeners fail to bind instead of aborting startup
+    #[arg(long)]
+    allow_partial: bool,
+
+    /// Start with the configuration locked against edits through the API
+    #[arg(long)]
+    config_readonly: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Commands run instead of the server.
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Manage proxy users in the config file
+    User {
+        /// Config file to edit (default: the file the server would load)
+        #[arg(long, short, global = true)]
+        config: Option<PathBuf>,
+
+        #[command(subcommand)]
+        command: users::UserCommand,
+    },
+
+    /// Relay test connections through the configured proxies on loopback
+    /// ports and check authentication and access control
+    Selftest {
+        /// Config file to test (default: the file the server would load)
+        #[arg(long, short)]
+        config: Option<PathBuf>,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Some(Command::User { config, command }) => {
+            let path = config
+                .or_else(|| find_config_file().map(PathBuf::from))
+                .with_context(|| {
+                    format!(
+                        "No config file found (looked for {})",
+                        CONFIG_PATHS.join(", ")
+                    )
+                })?;
+            return users::run(command, &path);
+        }
+        Some(Command::Selftest { config }) => {
+            let path = config.or_else(|| find_config_file().map(PathBuf::from));
+            return selftest::run(path.as_deref()).await;
+        }
+        None => {}
+    }
+
     // Load configuration
-    let (config, config_path) = load_config()?;
+    let (mut config, provenance, config_path) = load_config()?;
 
     // Initialize logging (must be before any log calls)
-    let _guard = init_logging(&config.logging);
+    let _guard = init_logging(&config.logging, &config.telemetry)?;
+    if config.telemetry.enabled {
+        info!(
+            "Exporting connection traces to {} (sample ratio {})",
+            config.telemetry.endpoint, config.telemetry.sample_ratio
+        );
+    }
 
     info!(
         "Starting net-relay proxy server v{}",
         env!("CARGO_PKG_VERSION")
     );
 
+    if config.dashboard.migrate_legacy_login() {
+        warn!(
+            "dashboard.username/password is deprecated; treating it as an entry of \
+             [[dashboard.users]] (the config file is rewritten on its next save)"
+        );
+    }
+
     // Create config manager for runtime configuration
-    let config_manager = ConfigManager::new(config.clone(), config_path);
+    let config_manager =
+        ConfigManager::new(config.clone(), config_path).with_provenance(provenance);
+    if let Some(migration) = config_manager.migrate_allowed_ips().await {
+        warn_allowed_ips(&migration);
+    }
+    if cli.config_readonly {
+        let _ = config_manager
+            .lock_config("--config-readonly", Some("locked at startup".to_string()))
+            .await;
+        warn!("Configuration is locked (--config-readonly); API edits are refused until unlocked");
+    }
 
     // Create shared stats
-    let stats = Arc::new(Stats::new(1000));
-
-    // Prepare authentication
-    let auth = if config.security.auth_enabled {
-        match (&config.security.username, &config.security.password) {
-            (Some(u), Some(p)) => Some((u.clone(), p.clone())),
-            _ => {
-                error!("Authentication enabled but username/password not configured");
-                return Err(anyhow::anyhow!("Invalid authentication configuration"));
+    let mut stats =
+        Stats::new(config.stats.max_history).with_max_history_age(config.stats.max_history_age());
+    if let Some(ref path) = config.logging.access_log {
+        let access_log = AccessLog::open(
+            path,
+            config.logging.access_log_format,
+            config.logging.rotation,
+        )
+        .with_context(|| format!("Failed to open access log: {}", path))?;
+        info!("Writing access log to {}", path);
+        stats = stats.with_access_log(access_log);
+    }
+    if let Some(ref path) = config.stats.event_log {
+        let event_log = EventLog::open(
+            path,
+            config.logging.rotation,
+            config.stats.event_log_backpressure,
+        )
+        .with_context(|| format!("Failed to open event log: {}", path))?;
+        info!("Writing connection events to {}", path);
+        stats = stats.with_event_log(event_log);
+    }
+    let checkpoint_path = config.stats.checkpoint_path();
+    if let Some(ref path) = checkpoint_path {
+        // A damaged checkpoint only loses the old totals, so start anyway
+        match StatsCheckpoint::load(path) {
+            Ok(Some(checkpoint)) => {
+                info!(
+                    "Restored byte counters saved at {} from {}",
+                    checkpoint.saved_at,
+                    path.display()
+                );
+                stats = stats.with_checkpoint(&checkpoint);
+            }
+            Ok(None) => {}
+            Err(e) => warn!(
+                "Ignoring unreadable stats checkpoint {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+    let quota = QuotaTracker::load(&config.stats.quota_file)
+        .with_context(|| format!("Failed to load quota usage: {}", config.stats.quota_file))?;
+    stats = stats
+        .with_quota_tracker(quota)
+        .with_bandwidth_limiter(Arc::clone(config_manager.bandwidth_limiter()))
+        .with_rate_window(Duration::from_secs(config.stats.rate_window_secs))
+        .with_ip_attribution(config.stats.ip_attribution());
+    let stats = Arc::new(stats);
+    if !config.stats.enabled {
+        stats.set_enabled(false).await;
+        info!("Statistics collection disabled: only totals are counted");
+    }
+
+    // Persist quota usage periodically so restarts don't reset it
+    let quota_stats = Arc::clone(&stats);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(QUOTA_SAVE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = quota_stats.save_quota() {
+                warn!("Failed to save quota usage: {}", e);
+            }
+        }
+    });
+
+    // Checkpoint lifetime byte counters so restarts don't reset them
+    if let Some(path) = checkpoint_path.clone() {
+        let checkpoint_stats = Arc::clone(&stats);
+        let period = Duration::from_secs(config.stats.checkpoint_interval_secs);
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = checkpoint_stats.checkpoint().save(&path) {
+                    warn!("Failed to save stats checkpoint {}: {}", path.display(), e);
+                }
+            }
+        });
+    }
+
+    // Apply the current collection, history and tracking settings and drop entries that aged out
+    let history_stats = Arc::clone(&stats);
+    let history_config = config_manager.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HISTORY_TRIM_INTERVAL);
+        let mut changes = history_config.subscribe();
+        loop {
+            // Trim periodically, and apply edits to `stats` right away
+            tokio::select! {
+                _ = interval.tick() => {}
+                Ok(()) = changes.changed() => {
+                    if !changes.borrow_and_update().touches("stats") {
+                        continue;
+                    }
+                }
             }
+            let config = history_config.get().await;
+            let limits = &config.stats;
+            history_stats.set_history_limits(limits.max_history, limits.max_history_age());
+            history_stats.set_enabled(limits.enabled).await;
+            history_stats
+                .set_tracking_limits(
+                    limits.max_tracked_users,
+                    limits.max_denied_attempts,
+                    config.security.configured_usernames(),
+                )
+                .await;
         }
+    });
+
+    if config.dashboard.real_ip_header.is_some() && config.dashboard.trusted_proxies.is_empty() {
+        warn!("dashboard.real_ip_header is ignored until dashboard.trusted_proxies lists the reverse proxy");
+    }
+
+    let statsd = &config.metrics.statsd;
+    if statsd.enabled {
+        info!(
+            "Sending StatsD metrics to {}:{} every {}s",
+            statsd.host, statsd.port, statsd.flush_interval_secs
+        );
+        tokio::spawn(net_relay_core::statsd::run(
+            statsd.clone(),
+            Arc::clone(&stats),
+        ));
+    }
+
+    // Check that clients can authenticate
+    if config.security.auth_enabled
+        && (config.security.username.is_none() || config.security.password.is_none())
+        // Users or an external backend can authenticate clients on their own
+        && config.security.users.is_empty()
+        && config.auth.backend == AuthBackend::Config
+    {
+        error!("Authentication enabled but username/password not configured");
+        return Err(anyhow::anyhow!("Invalid authentication configuration"));
+    }
+
+    config
+        .check_port_conflicts()
+        .context("Invalid server configuration")?;
+    let host_ips = resolve_bind_host("server.host", &config.server.host).await?;
+    let api_ips = match &config.server.api_host {
+        Some(api_host) => resolve_bind_host("server.api_host", api_host).await?,
+        None => host_ips.clone(),
+    };
+
+    if config.server.api_enabled {
+        match config.dashboard.security_warning() {
+            Some(DashboardSecurityWarning::AuthDisabled) => {
+                warn!("==========================================================");
+                warn!("  Dashboard authentication is DISABLED: anyone who can");
+                warn!("  reach the API port can change this relay. Create the");
+                warn!("  first administrator with POST /api/setup or set");
+                warn!("  dashboard.auth_enabled with [[dashboard.users]].");
+                warn!("==========================================================");
+            }
+            Some(DashboardSecurityWarning::DefaultCredentials) => {
+                warn!("==========================================================");
+                warn!("  A dashboard administrator uses a well-known default");
+                warn!("  password. Change it with POST /api/auth/change-password.");
+                warn!("==========================================================");
+            }
+            None => {}
+        }
+    }
+
+    if !config.server.api_enabled && config.security.auth_enabled {
+        warn!("==========================================================");
+        warn!("  API/Dashboard is DISABLED while proxy auth is enabled.");
+        warn!("  Users and access control can only be changed by editing");
+        warn!("  the config file and restarting.");
+        warn!("==========================================================");
+    }
+
+    // Bind every enabled listener before spawning anything so port conflicts abort startup
+    let server_state = Arc::new(ServerState::new());
+    server_state.set_features(
+        [
+            ("acme", cfg!(feature = "acme")),
+            ("telemetry", cfg!(feature = "telemetry")),
+        ]
+        .into_iter()
+        .filter_map(|(name, enabled)| enabled.then_some(name))
+        .collect(),
+    );
+    let listen = ListenOptions::from_config(&config.server);
+    let host = &config.server.host;
+    let api_host = config.server.api_host.as_ref().unwrap_or(host);
+    let socks_listeners = if config.server.socks_enabled {
+        bind_listeners(
+            &server_state,
+            "socks5",
+            host,
+            config.server.socks_port,
+            &host_ips,
+            &listen,
+            cli.allow_partial,
+        )
+        .await?
+    } else {
+        server_state.add(ListenerInfo::disabled(
+            "socks5",
+            &configured_addr(host, config.server.socks_port),
+        ));
+        Vec::new()
+    };
+    let http_listeners = if config.server.http_enabled {
+        bind_listeners(
+            &server_state,
+            "http",
+            host,
+            config.server.http_port,
+            &host_ips,
+            &listen,
+            cli.allow_partial,
+        )
+        .await?
     } else {
-        None
+        server_state.add(ListenerInfo::disabled(
+            "http",
+            &configured_addr(host, config.server.http_port),
+        ));
+        Vec::new()
+    };
+    let api_listeners = if config.server.api_enabled {
+        bind_listeners(
+            &server_state,
+            "api",
+            api_host,
+            config.server.api_port,
+            &api_ips,
+            &listen,
+            cli.allow_partial,
+        )
+        .await?
+    } else {
+        server_state.add(ListenerInfo::disabled(
+            "api",
+            &configured_addr(api_host, config.server.api_port),
+        ));
+        Vec::new()
+    };
+    let combined_listeners = match config.server.combined_port {
+        Some(port) => {
+            bind_listeners(
+                &server_state,
+                "combined",
+                host,
+                port,
+                &host_ips,
+                &listen,
+                cli.allow_partial,
+            )
+            .await?
+        }
+        None => Vec::new(),
+    };
+    let tunnel_listeners = match config.server.tunnel_port {
+        Some(port) => {
+            bind_listeners(
+                &server_state,
+                "tunnel",
+                host,
+                port,
+                &host_ips,
+                &listen,
+                cli.allow_partial,
+            )
+            .await?
+        }
+        None => Vec::new(),
     };
 
-    // Start SOCKS5 proxy
-    let socks_addr: SocketAddr = format!("{}:{}", config.server.host, config.server.socks_port)
-        .parse()
-        .context("Invalid SOCKS5 bind address")?;
-    let socks_proxy = Socks5Proxy::new(
-        socks_addr,
-        auth.clone(),
-        Arc::clone(&stats),
+    let active = ActiveServices {
+        socks5: !socks_listeners.is_empty(),
+        http: !http_listeners.is_empty(),
+        api: !api_listeners.is_empty(),
+        tunnel: !tunnel_listeners.is_empty(),
+        combined: !combined_listeners.is_empty(),
+    };
+    let socks_bound = listener_addrs(&socks_listeners);
+    let http_bound = listener_addrs(&http_listeners);
+    let api_bound = listener_addrs(&api_listeners);
+    let tunnel_bound = listener_addrs(&tunnel_listeners);
+    let combined_bound = listener_addrs(&combined_listeners);
+    if !(active.socks5 || active.http || active.api || active.tunnel || active.combined) {
+        return Err(anyhow::anyhow!("No services to run"));
+    }
+
+    if let Some(ref upstream) = config_manager.get_upstream().await {
+        for relay in upstream.endpoints() {
+            info!(
+                "Forwarding connections through upstream relay {} (priority {}) as node '{}'",
+                relay.address, relay.priority, relay.node_name
+            );
+        }
+    }
+    tokio::spawn(upstream::run_health_checks(config_manager.clone()));
+
+    if config.backup.enabled {
+        if config_manager.config_path().is_some() {
+            info!(
+                "Snapshotting the config file into {} every {}s (keeping {})",
+                config.backup.directory, config.backup.interval_secs, config.backup.retention
+            );
+        } else {
+            warn!("backup.enabled has no effect without a config file");
+        }
+    }
+    tokio::spawn(net_relay_core::backup::run(config_manager.clone()));
+    tokio::spawn(net_relay_core::alerts::run(
         config_manager.clone(),
-    );
+        Arc::clone(&stats),
+    ));
 
-    let socks_handle = tokio::spawn(async move {
-        if let Err(e) = socks_proxy.run().await {
-            error!("SOCKS5 proxy error: {}", e);
+    // Drop access rules once they have been expired for a while
+    let rules_config = config_manager.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RULE_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match rules_config.remove_expired_rules().await {
+                Ok(0) => {}
+                Ok(removed) => info!("Removed {} expired access rule(s)", removed),
+                Err(e) => warn!(
+                    "Failed to save configuration after removing expired rules: {}",
+                    e
+                ),
+            }
         }
     });
 
+    // Close active entries whose relay ended without recording it
+    let sweep_stats = Arc::clone(&stats);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ORPHAN_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let closed = sweep_stats.sweep_orphans().await;
+            if closed > 0 {
+                warn!(
+                    "Closed {} orphaned connection(s) whose relay had ended",
+                    closed
+                );
+            }
+        }
+    });
+
+    // Each service task returns its name when it stops
+    let mut services = JoinSet::new();
+
+    // Cancelled on shutdown to stop the proxies accepting connections
+    let shutdown = CancellationToken::new();
+
+    // Start SOCKS5 proxy
+    let socks_proxy = Arc::new(
+        Socks5Proxy::builder()
+            .config(config_manager.clone())
+            .stats(Arc::clone(&stats))
+            .shutdown(shutdown.clone())
+            .build(),
+    );
+    for listener in socks_listeners {
+        let socks_proxy = Arc::clone(&socks_proxy);
+        let down = ListenerDown::new(&server_state, "socks5", &listener);
+        services.spawn(async move {
+            let _down = down;
+            if let Err(e) = socks_proxy.run(listener).await {
+                error!("SOCKS5 proxy error: {}", e);
+            }
+            "SOCKS5 proxy"
+        });
+    }
+
     // Start HTTP CONNECT proxy
-    let http_addr: SocketAddr = format!("{}:{}", config.server.host, config.server.http_port)
-        .parse()
-        .context("Invalid HTTP bind address")?;
-    let http_proxy = HttpProxy::new(http_addr, auth, Arc::clone(&stats), config_manager.clone());
+    let http_proxy = Arc::new(
+        HttpProxy::builder()
+            .config(config_manager.clone())
+            .stats(Arc::clone(&stats))
+            .shutdown(shutdown.clone())
+            .build(),
+    );
+    for listener in http_listeners {
+        let http_proxy = Arc::clone(&http_proxy);
+        let down = ListenerDown::new(&server_state, "http", &listener);
+        services.spawn(async move {
+            let _down = down;
+            if let Err(e) = http_proxy.run(listener).await {
+                error!("HTTP proxy error: {}", e);
+            }
+            "HTTP proxy"
+        });
+    }
 
-    let http_handle = tokio::spawn(async move {
-        if let Err(e) = http_proxy.run().await {
-            error!("HTTP proxy error: {}", e);
+    // Start the combined SOCKS5/HTTP listener
+    if !combined_listeners.is_empty() {
+        let combined_proxy = Arc::new(CombinedProxy::new(
+            (*socks_proxy).clone(),
+            (*http_proxy).clone(),
+        ));
+        for listener in combined_listeners {
+            let combined_proxy = Arc::clone(&combined_proxy);
+            let down = ListenerDown::new(&server_state, "combined", &listener);
+            services.spawn(async move {
+                let _down = down;
+                if let Err(e) = combined_proxy.run(listener).await {
+                    error!("Combined proxy error: {}", e);
+                }
+                "Combined proxy"
+            });
         }
-    });
+    }
 
-    // Start API server
-    let api_addr: SocketAddr = format!("{}:{}", config.server.host, config.server.api_port)
-        .parse()
-        .context("Invalid API bind address")?;
+    // Certificates loaded from files, reloaded together when they change
+    let cert_store = Arc::new(CertStore::new());
 
-    let static_dir = find_static_dir();
-    let router = create_router(Arc::clone(&stats), config_manager, static_dir);
+    // Start relay-to-relay tunnel server
+    if !tunnel_listeners.is_empty() {
+        let (Some(cert), Some(key)) = (
+            &config.server.tunnel_tls_cert,
+            &config.server.tunnel_tls_key,
+        ) else {
+            return Err(anyhow::anyhow!(
+                "server.tunnel_port requires tunnel_tls_cert and tunnel_tls_key"
+            ));
+        };
+        let tunnel_cert = Arc::new(
+            FileCert::load("tunnel", cert, key).context("Invalid tunnel TLS configuration")?,
+        );
+        let tls_config = tunnel_cert.server_config();
+        cert_store.add(tunnel_cert);
+        let tunnel_server = Arc::new(TunnelServer::new(
+            tls_config,
+            Arc::clone(&stats),
+            config_manager.clone(),
+        ));
+        for listener in tunnel_listeners {
+            let tunnel_server = Arc::clone(&tunnel_server);
+            let down = ListenerDown::new(&server_state, "tunnel", &listener);
+            services.spawn(async move {
+                let _down = down;
+                if let Err(e) = tunnel_server.run(listener).await {
+                    error!("Tunnel server error: {}", e);
+                }
+                "Tunnel server"
+            });
+        }
+    }
 
-    let api_handle = tokio::spawn(async move {
-        info!("API server listening on http://{}", api_addr);
-        let listener = tokio::net::TcpListener::bind(api_addr).await.unwrap();
-        if let Err(e) = axum::serve(listener, router).await {
-            error!("API server error: {}", e);
+    // Start API server
+    let mut api_scheme = "http";
+    if !api_listeners.is_empty() {
+        let api_tls = api_certificates(&config)?;
+        if let Some(files) = api_tls.as_ref().and_then(|certs| certs.file_cert()) {
+            cert_store.add(Arc::clone(files));
         }
-    });
+        if config.acme.enabled && config.acme.challenge == AcmeChallenge::Http01 {
+            if let Some(port) = config.acme.http_port {
+                for addr in bind_addrs(&api_ips, port) {
+                    let challenge_listener = TcpListener::bind(addr)
+                        .await
+                        .with_context(|| format!("Failed to bind ACME challenge port {}", addr))?;
+                    info!("Answering ACME http-01 challenges on {}", addr);
+                    tokio::spawn(serve_http_challenges(
+                        challenge_listener,
+                        Arc::clone(api_tls.as_ref().expect("ACME certificates")),
+                    ));
+                }
+            }
+        }
+        let tls_setup = match &api_tls {
+            Some(certs) => {
+                api_scheme = "https";
+                let verifier = match &config.server.api_client_ca_path {
+                    Some(ca_path) => Some(
+                        tls::client_verifier(ca_path, config.server.api_require_client_cert)
+                            .context("Invalid API client CA")?,
+                    ),
+                    None => None,
+                };
+                Some((Arc::clone(certs), certs.server_config(verifier)))
+            }
+            None => None,
+        };
+        let static_dir = find_static_dir();
+        let router = create_router(
+            Arc::clone(&stats),
+            config_manager,
+            static_dir,
+            active,
+            api_tls,
+            Arc::clone(&cert_store),
+            Arc::clone(&server_state),
+        );
+        for listener in api_listeners {
+            let router = router.clone();
+            let tls_setup = tls_setup.clone();
+            let down = ListenerDown::new(&server_state, "api", &listener);
+            services.spawn(async move {
+                let _down = down;
+                let result = match tls_setup {
+                    Some((certs, tls_config)) => {
+                        match TlsListener::new(listener, tls_config, certs) {
+                            Ok(listener) => {
+                                axum::serve(listener, TlsConnectInfo::new(router)).await
+                            }
+                            Err(e) => Err(e),
+                        }
+                    }
+                    None => {
+                        let service = router.into_make_service_with_connect_info::<SocketAddr>();
+                        axum::serve(listener, service).await
+                    }
+                };
+                if let Err(e) = result {
+                    error!("API server error: {}", e);
+                }
+                "API server"
+            });
+        }
+        info!("API server listening on {}://{}", api_scheme, api_bound);
+    }
 
+    let certificates = cert_store.statuses();
+    for cert in &certificates {
+        if cert.expires_within(config.server.tls_expiry_warning_days, chrono::Utc::now()) {
+            warn!(
+                "The {} TLS certificate {} expires at {}",
+                cert.listener, cert.cert_path, cert.not_after
+            );
+        }
+    }
+    if !certificates.is_empty() {
+        tokio::spawn(Arc::clone(&cert_store).watch(CERT_WATCH_INTERVAL));
+    }
+
+    let banner = |on: bool, addr: String| if on { addr } else { "disabled".to_string() };
     info!("Net-relay is running:");
-    info!("  SOCKS5 proxy: {}", socks_addr);
-    info!("  HTTP proxy:   {}", http_addr);
-    info!("  Dashboard:    http://{}", api_addr);
+    info!("  SOCKS5 proxy: {}", banner(active.socks5, socks_bound));
+    info!("  HTTP proxy:   {}", banner(active.http, http_bound));
+    info!(
+        "  Dashboard:    {}",
+        banner(active.api, format!("{}://{}", api_scheme, api_bound))
+    );
+    if config.server.combined_port.is_some() {
+        info!(
+            "  Combined:     {}",
+            banner(active.combined, combined_bound)
+        );
+    }
+    if config.server.tunnel_port.is_some() {
+        info!("  Tunnel:       {}", banner(active.tunnel, tunnel_bound));
+    }
 
     // Wait for all services
     tokio::select! {
-        _ = socks_handle => error!("SOCKS5 proxy stopped"),
-        _ = http_handle => error!("HTTP proxy stopped"),
-        _ = api_handle => error!("API server stopped"),
+        Some(stopped) = services.join_next() => match stopped {
+            Ok(name) => error!("{} stopped", name),
+            Err(e) => error!("Service task failed: {}", e),
+        },
         _ = tokio::signal::ctrl_c() => {
             info!("Received shutdown signal");
         }
     }
 
+    // Stop accepting, then stop relays so every open connection is recorded with reason "shutdown"
+    shutdown.cancel();
+    stats.begin_shutdown();
+    let drained = tokio::time::timeout(SHUTDOWN_DRAIN_TIMEOUT, stats.wait_for_tasks()).await;
+    if drained.is_err() {
+        warn!(
+            "{} connection tasks ({} connections) still running after shutdown drain",
+            stats.connection_tasks(),
+            stats.active_connections()
+        );
+    }
+
+    if let Err(e) = stats.save_quota() {
+        warn!("Failed to save quota usage: {}", e);
+    }
+    if let Some(ref path) = checkpoint_path {
+        if let Err(e) = stats.checkpoint().save(path) {
+            warn!("Failed to save stats checkpoint {}: {}", path.display(), e);
+        }
+    }
     info!("Net-relay shutting down");
     Ok(())
 }
 
-/// Load configuration from file or use defaults.
-/// Returns (Config, Option<config_path>)
-fn load_config() -> Result<(Config, Option<String>)> {
-    let config_paths = ["config.toml", "/etc/net-relay/config.toml"];
+/// IP addresses to bind for `host`, the value of config key `key`.
+///
+/// IP addresses are used as is; host names are resolved and every address
+/// they resolve to is bound.
+async fn resolve_bind_host(key: &str, host: &str) -> Result<Vec<IpAddr>> {
+    let literal = host.strip_prefix('[').and_then(|h| h.strip_suffix(']'));
+    if let Ok(ip) = literal.unwrap_or(host).parse::<IpAddr>() {
+        return Ok(vec![ip]);
+    }
+    if host.is_empty() {
+        anyhow::bail!("{} is empty; set it to an IP address such as 0.0.0.0", key);
+    }
+
+    let resolved = tokio::net::lookup_host((host, 0)).await.map_err(|e| {
+        anyhow::anyhow!(
+            "{} = \"{}\" is neither an IP address nor a resolvable host name: {}",
+            key,
+            host,
+            e
+        )
+    })?;
+    let mut ips: Vec<IpAddr> = Vec::new();
+    for addr in resolved {
+        if !ips.contains(&addr.ip()) {
+            ips.push(addr.ip());
+        }
+    }
+    if ips.is_empty() {
+        anyhow::bail!("{} = \"{}\" resolved to no addresses", key, host);
+    }
+    info!(
+        "{} = \"{}\" resolved to {}",
+        key,
+        host,
+        ips.iter()
+            .map(|ip| ip.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(ips)
+}
+
+fn bind_addrs(ips: &[IpAddr], port: u16) -> Vec<SocketAddr> {
+    ips.iter().map(|&ip| SocketAddr::new(ip, port)).collect()
+}
+
+/// Service name of listeners for `protocol`, as used in logs.
+fn service_name(protocol: &str) -> &'static str {
+    match protocol {
+        "socks5" => "SOCKS5 proxy",
+        "http" => "HTTP proxy",
+        "api" => "API server",
+        _ => "Tunnel server",
+    }
+}
+
+/// `host:port` as written in the configuration, IPv6 literals bracketed.
+fn configured_addr(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Bind the `protocol` listeners on each of `ips` with `options`, recording
+/// every bind (and its actual address) in `state`.
+///
+/// Failures abort startup unless `allow_partial` is set, in which case the
+/// address is skipped; the service is disabled when no address could be bound.
+async fn bind_listeners(
+    state: &ServerState,
+    protocol: &str,
+    host: &str,
+    port: u16,
+    ips: &[IpAddr],
+    options: &ListenOptions,
+    allow_partial: bool,
+) -> Result<Vec<TcpListener>> {
+    let name = service_name(protocol);
+    let configured = configured_addr(host, port);
+    let mut listeners = Vec::new();
+    for addr in bind_addrs(ips, port) {
+        match listeners::bind(addr, options).and_then(|l| {
+            let bound = l.local_addr()?;
+            Ok((l, bound))
+        }) {
+            Ok((listener, bound)) => {
+                let info = ListenerInfo::bound(protocol, &configured, bound, options.backlog);
+                state.add(info);
+                listeners.push(listener);
+            }
+            Err(e) if allow_partial => {
+                warn!("{}: failed to bind {} ({:?}): {}", name, addr, e.kind(), e);
+                let error = format!("failed to bind {}: {}", addr, e);
+                state.add(ListenerInfo::failed(protocol, &configured, error));
+            }
+            Err(e) => {
+                return Err(anyhow::anyhow!(
+                    "Failed to bind {} on {} ({:?}): {}",
+                    name,
+                    addr,
+                    e.kind(),
+                    e
+                ))
+            }
+        }
+    }
+    if listeners.is_empty() {
+        warn!("{} disabled: no address could be bound", name);
+    }
+    Ok(listeners)
+}
+
+/// Marks a listener down in [`ServerState`] when its service task ends.
+struct ListenerDown {
+    state: Arc<ServerState>,
+    protocol: &'static str,
+    bound: Option<SocketAddr>,
+}
+
+impl ListenerDown {
+    fn new(state: &Arc<ServerState>, protocol: &'static str, listener: &TcpListener) -> Self {
+        Self {
+            state: Arc::clone(state),
+            protocol,
+            bound: listener.local_addr().ok(),
+        }
+    }
+}
 
-    for path in config_paths {
-        if std::path::Path::new(path).exists() {
-            let content = std::fs::read_to_string(path)
-                .with_context(|| format!("Failed to read config file: {}", path))?;
-            let config: Config = toml::from_str(&content)
-                .with_context(|| format!("Failed to parse config file: {}", path))?;
-            info!("Loaded configuration from {}", path);
-            return Ok((config, Some(path.to_string())));
+impl Drop for ListenerDown {
+    fn drop(&mut self) {
+        if let Some(bound) = self.bound {
+            self.state.set_down(self.protocol, bound);
         }
     }
+}
+
+/// Local addresses of `listeners`, comma separated.
+fn listener_addrs(listeners: &[TcpListener]) -> String {
+    listeners
+        .iter()
+        .filter_map(|l| l.local_addr().ok())
+        .map(|a| a.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Explain what became of the deprecated `security.allowed_ips`.
+fn warn_allowed_ips(migration: &AllowedIpsMigration) {
+    match migration {
+        AllowedIpsMigration::Moved(ips) => warn!(
+            "security.allowed_ips is deprecated and was never enforced; moved {:?} \
+             into access_control.ip_whitelist, which now admits only those clients",
+            ips
+        ),
+        AllowedIpsMigration::Dropped(ips) => warn!(
+            "security.allowed_ips is deprecated and was never enforced; dropped {:?} \
+             in favor of the existing access_control.ip_whitelist",
+            ips
+        ),
+    }
+}
 
-    info!("No config file found, using defaults");
-    Ok((Config::default(), None))
+/// Certificates for serving the API/Dashboard over HTTPS, if configured.
+fn api_certificates(config: &Config) -> Result<Option<Arc<DashboardCerts>>> {
+    if config.acme.enabled {
+        return acme_certificates(config).map(Some);
+    }
+    match (&config.server.api_tls_cert, &config.server.api_tls_key) {
+        (Some(cert), Some(key)) => {
+            let certs =
+                DashboardCerts::from_files(cert, key).context("Invalid API TLS configuration")?;
+            Ok(Some(Arc::new(certs)))
+        }
+        _ => Ok(None),
+    }
 }
 
-/// Initialize logging with the specified config.
-/// Returns a guard that must be kept alive for the duration of the program
-/// when using file logging (to ensure logs are flushed).
+/// Load the stored ACME certificate and start the issuance/renewal task.
+#[cfg(feature = "acme")]
+fn acme_certificates(config: &Config) -> Result<Arc<DashboardCerts>> {
+    let certs = Arc::new(DashboardCerts::for_acme(&config.acme.domain));
+    let manager = net_relay_core::AcmeManager::new(config.acme.clone(), Arc::clone(&certs));
+    match manager.load_existing() {
+        Ok(true) => info!("Loaded ACME certificate for {}", config.acme.domain),
+        Ok(false) => info!("Requesting ACME certificate for {}", config.acme.domain),
+        Err(e) => warn!(
+            "Ignoring stored ACME certificate for {}: {}",
+            config.acme.domain, e
+        ),
+    }
+    tokio::spawn(manager.run());
+    Ok(certs)
+}
+
+#[cfg(not(feature = "acme"))]
+fn acme_certificates(_config: &Config) -> Result<Arc<DashboardCerts>> {
+    Err(anyhow::anyhow!(
+        "acme.enabled requires net-relay to be built with the `acme` feature"
+    ))
+}
+
+/// Load configuration from file or use defaults.
+/// Returns (Config, Option<config_path>)
+fn load_config() -> Result<(Config, ConfigProvenance, Option<String>)> {
+    let path = find_config_file();
+    let (config, provenance) = Config::load_layered(path.map(Path::new), std::env::vars())
+        .with_context(|| match path {
+            Some(path) => format!("Failed to load config file: {}", path),
+            None => "Failed to load configuration".to_string(),
+        })?;
+    match path {
+        Some(path) => info!("Loaded configuration from {}", path),
+        None => info!("No config file found, using defaults"),
+    }
+    Ok((config, provenance, path.map(str::to_string)))
+}
+
+/// First of [`CONFIG_PATHS`] that exists.
+fn find_config_file() -> Option<&'static str> {
+    CONFIG_PATHS
+        .into_iter()
+        .find(|path| std::path::Path::new(path).exists())
+}
+
+/// Keeps log outputs flushing until dropped.
+struct LoggingGuards {
+    _file: Option<tracing_appender::non_blocking::WorkerGuard>,
+    _telemetry: telemetry::TelemetryGuard,
+    _syslog: Option<syslog::SyslogGuard>,
+}
+
+/// Initialize logging and trace export with the specified config.
+/// Returns guards that must be kept alive for the duration of the program
+/// when using file logging, syslog or trace export (to ensure logs are flushed).
 fn init_logging(
     logging_config: &LoggingConfig,
-) -> Option<tracing_appender::non_blocking::WorkerGuard> {
-    let filter =
-        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&logging_config.level));
+    telemetry_config: &TelemetryConfig,
+) -> Result<LoggingGuards> {
+    let env_filter = || {
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&logging_config.level))
+    };
+    // Each output gets its own filter; connection spans only go to the trace
+    // exporter and, as context of the events inside them, to syslog
+    let filter = || {
+        env_filter().add_directive(
+            format!("{}=off", net_relay_core::telemetry::SPAN_TARGET)
+                .parse()
+                .expect("valid directive"),
+        )
+    };
+    let (telemetry_layer, telemetry_guard) = telemetry::layer(telemetry_config)?;
 
     let fmt_layer = tracing_subscriber::fmt::layer()
         .with_target(true)
         .with_thread_ids(false)
-        .with_file(false);
+        .with_file(false)
+        .with_filter(filter());
 
-    // If log file is configured, set up dual output (console + file)
-    if let Some(ref log_file) = logging_config.file {
+    // If log file is configured, also write to the file
+    let (file_layer, file_guard) = if let Some(ref log_file) = logging_config.file {
         // Parse the file path to get directory and filename
         let log_path = PathBuf::from(log_file);
         let log_dir = log_path.parent().unwrap_or(std::path::Path::new("."));
@@ -172,25 +1041,43 @@ fn init_logging(
             .with_thread_ids(false)
             .with_file(false)
             .with_ansi(false)
-            .with_writer(non_blocking);
-
-        tracing_subscriber::registry()
-            .with(filter)
-            .with(fmt_layer)
-            .with(file_layer)
-            .init();
+            .with_writer(non_blocking)
+            .with_filter(filter());
 
         eprintln!("Logging to console and file: {}", log_file);
-        Some(guard)
+        (Some(file_layer), Some(guard))
     } else {
-        // Console only
-        tracing_subscriber::registry()
-            .with(filter)
-            .with(fmt_layer)
-            .init();
+        (None, None)
+    };
 
-        None
-    }
+    // Syslog gets the level as severity and the timestamp in its header
+    let syslog_config = &logging_config.syslog;
+    let (syslog_layer, syslog_guard) = if syslog_config.enabled {
+        let (writer, guard) = syslog::writer(syslog_config)?;
+        let syslog_layer = tracing_subscriber::fmt::layer()
+            .with_target(true)
+            .with_level(false)
+            .without_time()
+            .with_ansi(false)
+            .with_writer(writer)
+            .with_filter(env_filter());
+        (Some(syslog_layer), Some(guard))
+    } else {
+        (None, None)
+    };
+
+    tracing_subscriber::registry()
+        .with(telemetry_layer)
+        .with(fmt_layer)
+        .with(file_layer)
+        .with(syslog_layer)
+        .init();
+
+    Ok(LoggingGuards {
+        _file: file_guard,
+        _telemetry: telemetry_guard,
+        _syslog: syslog_guard,
+    })
 }
 
 /// Find the static files directory for the frontend.
@@ -0,0 +1,50 @@
+//! `net-relay selftest`: check the proxies end to end before taking traffic.
+//!
+//! Runs [`net_relay_core::selftest`] against the configuration on loopback
+//! ports, so it works next to a running server.
+
+use anyhow::{bail, Context, Result};
+use net_relay_core::selftest::{self, CheckOutcome};
+use net_relay_core::Config;
+use std::path::Path;
+
+/// Run the self-test with the configuration at `path` (defaults when `None`)
+/// and print the results, failing if any check failed.
+pub async fn run(path: Option<&Path>) -> Result<()> {
+    let (config, _) = Config::load_layered(path, std::env::vars()).with_context(|| match path {
+        Some(path) => format!("Failed to load config file: {}", path.display()),
+        None => "Failed to load configuration".to_string(),
+    })?;
+    match path {
+        Some(path) => println!("Testing the proxies configured in {}", path.display()),
+        None => println!("No config file found, testing the default proxies"),
+    }
+
+    let report = selftest::run(&config).await?;
+    println!(
+        "{:<16} {:<6} {:<6} {:>8}  DETAIL",
+        "LISTENER", "CHECK", "RESULT", "TIME"
+    );
+    for check in &report.checks {
+        let outcome = match check.outcome {
+            CheckOutcome::Pass => "pass",
+            CheckOutcome::Fail => "FAIL",
+            CheckOutcome::Skip => "skip",
+        };
+        println!(
+            "{:<16} {:<6} {:<6} {:>6}ms  {}",
+            check.listener, check.check, outcome, check.duration_ms, check.detail
+        );
+    }
+
+    let failed = report
+        .checks
+        .iter()
+        .filter(|check| check.outcome == CheckOutcome::Fail)
+        .count();
+    if failed > 0 {
+        bail!("{} of {} checks failed", failed, report.checks.len());
+    }
+    println!("All checks passed");
+    Ok(())
+}
@@ -0,0 +1,354 @@
+//! Application log output to syslog (`logging.syslog`).
+//!
+//! Formatted events are queued for a background thread that owns the
+//! socket, so a slow or unreachable syslog server never blocks logging:
+//! when the queue is full or the server can't be reached, messages are
+//! dropped and counted, and the count is reported once delivery resumes.
+
+use anyhow::{Context, Result};
+use chrono::{Local, Utc};
+use net_relay_core::{SyslogConfig, SyslogTransport};
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Messages waiting for the sender thread before new ones are dropped.
+const QUEUE_SIZE: usize = 1024;
+
+/// Pause between connection attempts to an unreachable server.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Timeout of connecting and writing to a TCP server.
+const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long shutdown waits for queued messages to be sent.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Largest message sent, longer ones are truncated.
+const MAX_MESSAGE: usize = 8192;
+
+/// Writer factory for a `fmt` layer that sends each event to syslog.
+#[derive(Clone)]
+pub struct SyslogMakeWriter {
+    facility: u8,
+    header: Arc<Header>,
+    queue: SyncSender<Vec<u8>>,
+    shared: Arc<Shared>,
+}
+
+/// Counters shared with the sender thread.
+#[derive(Default)]
+struct Shared {
+    queued: AtomicU64,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Message header fields besides priority and timestamp.
+struct Header {
+    transport: SyslogTransport,
+    hostname: String,
+    app_name: String,
+    pid: u32,
+}
+
+impl Header {
+    /// Message in the format the transport expects: the traditional BSD
+    /// format (RFC 3164) for local sockets, RFC 5424 for remote servers.
+    fn format(&self, priority: u8, message: &str) -> Vec<u8> {
+        match self.transport {
+            SyslogTransport::Unix(_) => format!(
+                "<{}>{} {}[{}]: {}",
+                priority,
+                Local::now().format("%b %e %H:%M:%S"),
+                self.app_name,
+                self.pid,
+                message
+            ),
+            SyslogTransport::Udp(_) | SyslogTransport::Tcp(_) => format!(
+                "<{}>1 {} {} {} {} - - {}",
+                priority,
+                Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                self.hostname,
+                self.app_name,
+                self.pid,
+                message
+            ),
+        }
+        .into_bytes()
+    }
+}
+
+/// Waits briefly for queued messages to be sent when dropped.
+pub struct SyslogGuard {
+    shared: Arc<Shared>,
+}
+
+impl Drop for SyslogGuard {
+    fn drop(&mut self) {
+        let deadline = Instant::now() + FLUSH_TIMEOUT;
+        while self.shared.sent.load(Ordering::Relaxed) + self.shared.dropped.load(Ordering::Relaxed)
+            < self.shared.queued.load(Ordering::Relaxed)
+            && Instant::now() < deadline
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let dropped = self.shared.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            eprintln!(
+                "Warning: {} log messages could not be sent to syslog",
+                dropped
+            );
+        }
+    }
+}
+
+/// Start the sender thread for `config` and return the writer factory.
+pub fn writer(config: &SyslogConfig) -> Result<(SyslogMakeWriter, SyslogGuard)> {
+    let header = Arc::new(Header {
+        transport: config.transport()?,
+        hostname: hostname(),
+        app_name: config.app_name.clone(),
+        pid: std::process::id(),
+    });
+    let shared = Arc::new(Shared::default());
+    let (queue, messages) = mpsc::sync_channel(QUEUE_SIZE);
+
+    let sender = Sender {
+        header: Arc::clone(&header),
+        facility: config.facility.code(),
+        shared: Arc::clone(&shared),
+        socket: None,
+        last_attempt: None,
+        reported_dropped: 0,
+    };
+    thread::Builder::new()
+        .name("syslog".to_string())
+        .spawn(move || sender.run(messages))
+        .context("Failed to start syslog thread")?;
+
+    Ok((
+        SyslogMakeWriter {
+            facility: config.facility.code(),
+            header,
+            queue,
+            shared: Arc::clone(&shared),
+        },
+        SyslogGuard { shared },
+    ))
+}
+
+impl<'a> MakeWriter<'a> for SyslogMakeWriter {
+    type Writer = SyslogWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.writer(Level::INFO)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.writer(*meta.level())
+    }
+}
+
+impl SyslogMakeWriter {
+    fn writer(&self, level: Level) -> SyslogWriter<'_> {
+        SyslogWriter {
+            make: self,
+            priority: self.facility * 8 + severity(level),
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// Collects one formatted event and queues it when dropped.
+pub struct SyslogWriter<'a> {
+    make: &'a SyslogMakeWriter,
+    priority: u8,
+    buf: Vec<u8>,
+}
+
+impl Write for SyslogWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogWriter<'_> {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.buf);
+        let text = text.trim_end();
+        if text.is_empty() {
+            return;
+        }
+        let mut message = self.make.header.format(self.priority, text);
+        message.truncate(MAX_MESSAGE);
+        let shared = &self.make.shared;
+        shared.queued.fetch_add(1, Ordering::Relaxed);
+        match self.make.queue.try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                shared.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Syslog severity of a tracing level.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// Host name for RFC 5424 headers, `-` (nil) when unknown.
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty() && !name.contains(char::is_whitespace))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+enum Socket {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixDatagram),
+}
+
+/// Sender thread state.
+struct Sender {
+    header: Arc<Header>,
+    facility: u8,
+    shared: Arc<Shared>,
+    socket: Option<Socket>,
+    last_attempt: Option<Instant>,
+    reported_dropped: u64,
+}
+
+impl Sender {
+    fn run(mut self, messages: Receiver<Vec<u8>>) {
+        for message in messages {
+            if self.socket.is_none() && !self.connect() {
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            self.report_dropped();
+            if self.send(&message).is_ok() {
+                self.shared.sent.fetch_add(1, Ordering::Relaxed);
+            } else {
+                // Reconnect for the next message
+                self.socket = None;
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Open the socket unless the last attempt was too recent.
+    fn connect(&mut self) -> bool {
+        if self
+            .last_attempt
+            .is_some_and(|at| at.elapsed() < RECONNECT_DELAY)
+        {
+            return false;
+        }
+        self.last_attempt = Some(Instant::now());
+        match open(&self.header.transport) {
+            Ok(socket) => {
+                self.socket = Some(socket);
+                true
+            }
+            Err(e) => {
+                eprintln!("Warning: Failed to connect to syslog: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Log how many messages were dropped since the last report.
+    fn report_dropped(&mut self) {
+        let dropped = self.shared.dropped.load(Ordering::Relaxed);
+        if dropped == self.reported_dropped {
+            return;
+        }
+        let notice = format!(
+            "syslog: {} log messages were dropped",
+            dropped - self.reported_dropped
+        );
+        self.reported_dropped = dropped;
+        // Warning severity
+        let message = self.header.format(self.facility * 8 + 4, &notice);
+        let _ = self.send(&message);
+    }
+
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        match self.socket.as_mut() {
+            Some(Socket::Udp(socket)) => socket.send(message).map(|_| ()),
+            Some(Socket::Tcp(stream)) => {
+                // Octet counting (RFC 6587)
+                stream.write_all(format!("{} ", message.len()).as_bytes())?;
+                stream.write_all(message)
+            }
+            #[cfg(unix)]
+            Some(Socket::Unix(socket)) => socket.send(message).map(|_| ()),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+}
+
+fn open(transport: &SyslogTransport) -> io::Result<Socket> {
+    match transport {
+        SyslogTransport::Udp(address) => {
+            let addr = resolve(address)?;
+            let local = if addr.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            let socket = UdpSocket::bind(local)?;
+            socket.connect(addr)?;
+            Ok(Socket::Udp(socket))
+        }
+        SyslogTransport::Tcp(address) => {
+            let addr = resolve(address)?;
+            let stream = TcpStream::connect_timeout(&addr, TCP_TIMEOUT)?;
+            stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+            Ok(Socket::Tcp(stream))
+        }
+        #[cfg(unix)]
+        SyslogTransport::Unix(path) => {
+            let socket = std::os::unix::net::UnixDatagram::unbound()?;
+            socket.connect(path)?;
+            Ok(Socket::Unix(socket))
+        }
+        #[cfg(not(unix))]
+        SyslogTransport::Unix(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "local syslog sockets are only supported on Unix",
+        )),
+    }
+}
+
+fn resolve(address: &str) -> io::Result<SocketAddr> {
+    address.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} did not resolve", address),
+        )
+    })
+}
@@ -0,0 +1,90 @@
+//! Export of connection traces over OTLP (`[telemetry]`).
+//!
+//! The exporter only receives the connection spans of
+//! [`net_relay_core::telemetry`] and warnings raised inside them; console and
+//! file logging are unaffected.
+
+use anyhow::Result;
+use net_relay_core::TelemetryConfig;
+use tracing_subscriber::{Layer, Registry};
+
+#[cfg(feature = "telemetry")]
+use {
+    anyhow::Context,
+    net_relay_core::telemetry::SPAN_TARGET,
+    opentelemetry::trace::TracerProvider,
+    opentelemetry_otlp::{WithExportConfig, WithHttpConfig},
+    opentelemetry_sdk::trace::{Sampler, SdkTracerProvider},
+    opentelemetry_sdk::Resource,
+    tracing::Level,
+    tracing_subscriber::filter::Targets,
+};
+
+/// Flushes traces that are still queued when dropped.
+pub struct TelemetryGuard {
+    #[cfg(feature = "telemetry")]
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "telemetry")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Warning: Failed to flush traces: {}", e);
+            }
+        }
+    }
+}
+
+/// Layer exporting connection traces, `None` when telemetry is disabled.
+#[cfg(feature = "telemetry")]
+pub fn layer(config: &TelemetryConfig) -> Result<(Option<impl Layer<Registry>>, TelemetryGuard)> {
+    if !config.enabled {
+        return Ok((None, TelemetryGuard { provider: None }));
+    }
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&config.endpoint)
+        .with_headers(config.headers.clone())
+        .build()
+        .with_context(|| format!("Failed to create OTLP exporter for {}", config.endpoint))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::TraceIdRatioBased(config.sample_ratio))
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build();
+
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer("net-relay"))
+        .with_filter(
+            Targets::new()
+                .with_target(SPAN_TARGET, Level::INFO)
+                .with_target("net_relay_core", Level::WARN),
+        );
+    Ok((
+        Some(layer),
+        TelemetryGuard {
+            provider: Some(provider),
+        },
+    ))
+}
+
+/// Without the `telemetry` feature traces can't be exported.
+#[cfg(not(feature = "telemetry"))]
+pub fn layer(config: &TelemetryConfig) -> Result<(Option<impl Layer<Registry>>, TelemetryGuard)> {
+    if config.enabled {
+        anyhow::bail!(
+            "telemetry.enabled requires net-relay to be built with the `telemetry` feature"
+        );
+    }
+    Ok((
+        None::<tracing_subscriber::layer::Identity>,
+        TelemetryGuard {},
+    ))
+}
@@ -0,0 +1,175 @@
+//! `net-relay user ...`: manage proxy users in the config file.
+//!
+//! These commands edit the file directly and don't need the server to be
+//! running. A running server keeps its loaded configuration until restarted.
+
+use anyhow::{bail, Context, Result};
+use clap::Subcommand;
+use net_relay_core::config::User;
+use net_relay_core::Config;
+use std::io::BufRead;
+use std::path::Path;
+
+/// User management commands.
+#[derive(Debug, Subcommand)]
+pub enum UserCommand {
+    /// Add a user (a password is generated unless --password-stdin is given)
+    Add {
+        /// Username
+        name: String,
+
+        /// Read the password from the first line of stdin
+        #[arg(long)]
+        password_stdin: bool,
+
+        /// Bandwidth limit in bytes per second (0 = unlimited)
+        #[arg(long, value_name = "BYTES_PER_SEC", default_value_t = 0)]
+        bandwidth: u64,
+
+        /// Concurrent connection limit (0 = unlimited)
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        connections: u32,
+    },
+
+    /// Remove a user
+    Remove {
+        /// Username
+        name: String,
+    },
+
+    /// List users
+    List,
+
+    /// Change the password of a user
+    SetPassword {
+        /// Username
+        name: String,
+
+        /// Read the password from the first line of stdin
+        #[arg(long)]
+        password_stdin: bool,
+    },
+}
+
+/// Run `command` against the config file at `path`.
+pub fn run(command: UserCommand, path: &Path) -> Result<()> {
+    let mut config = Config::load_from_file(path)
+        .with_context(|| format!("Failed to load config file: {}", path.display()))?;
+
+    let message = match command {
+        UserCommand::List => {
+            list(&config);
+            return Ok(());
+        }
+        UserCommand::Add {
+            name,
+            password_stdin,
+            bandwidth,
+            connections,
+        } => {
+            if name.is_empty() || name.contains(':') {
+                bail!("Username must be non-empty and must not contain ':'");
+            }
+            let (password, generated) = password(&config, password_stdin)?;
+            let mut user = User::new(name.clone(), password);
+            user.bandwidth_limit = bandwidth;
+            user.connection_limit = connections;
+            if !config.security.add_user(user) {
+                bail!("User '{}' already exists", name);
+            }
+            match generated {
+                Some(password) => format!("Added user '{}' with password: {}", name, password),
+                None => format!("Added user '{}'", name),
+            }
+        }
+        UserCommand::Remove { name } => {
+            if !config.security.remove_user(&name) {
+                bail!("No user named '{}'", name);
+            }
+            format!("Removed user '{}'", name)
+        }
+        UserCommand::SetPassword {
+            name,
+            password_stdin,
+        } => {
+            if !config.security.users.iter().any(|u| u.username == name) {
+                bail!("No user named '{}'", name);
+            }
+            let (password, generated) = password(&config, password_stdin)?;
+            if let Some(user) = config
+                .security
+                .users
+                .iter_mut()
+                .find(|u| u.username == name)
+            {
+                user.password = password;
+            }
+            match generated {
+                Some(password) => format!("Set password of user '{}' to: {}", name, password),
+                None => format!("Set password of user '{}'", name),
+            }
+        }
+    };
+
+    config.validate()?;
+    config
+        .save_to_file(path)
+        .with_context(|| format!("Failed to save config file: {}", path.display()))?;
+    println!("{}", message);
+    println!(
+        "Saved {}; restart a running net-relay to apply the change",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Read the password from stdin, or generate one with the configured policy.
+///
+/// Returns the password and, when generated, a copy to show the operator.
+fn password(config: &Config, from_stdin: bool) -> Result<(String, Option<String>)> {
+    let policy = &config.security.password_policy;
+    if !from_stdin {
+        let password = policy.generate(policy.generated_length)?;
+        return Ok((password.clone(), Some(password)));
+    }
+
+    let mut line = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .context("Failed to read password from stdin")?;
+    let password = line.trim_end_matches(['\r', '\n']).to_string();
+    let problems = policy.check(&password);
+    if !problems.is_empty() {
+        bail!("Password {}", problems.join(", "));
+    }
+    Ok((password, None))
+}
+
+fn list(config: &Config) {
+    let users = &config.security.users;
+    if users.is_empty() {
+        println!("No users configured");
+        return;
+    }
+    println!(
+        "{:<24} {:<8} {:>16} {:>12}",
+        "USERNAME", "ENABLED", "BANDWIDTH (B/s)", "CONNECTIONS"
+    );
+    let limit = |value: u64| {
+        if value == 0 {
+            "unlimited".to_string()
+        } else {
+            value.to_string()
+        }
+    };
+    for user in users {
+        println!(
+            "{:<24} {:<8} {:>16} {:>12}",
+            user.username,
+            if user.enabled { "yes" } else { "no" },
+            limit(user.bandwidth_limit),
+            limit(user.connection_limit.into()),
+        );
+    }
+}
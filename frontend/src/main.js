@@ -5,6 +5,7 @@
 
 const API_BASE = '/api';
 const REFRESH_INTERVAL = 2000; // 2 seconds
+const HISTORY_ROWS = 50;
 
 /**
  * Wrapper for fetch that handles authentication.
@@ -195,6 +196,8 @@ class Dashboard {
     constructor() {
         this.elements = {
             status: document.getElementById('status'),
+            configLock: document.getElementById('config-lock'),
+            configUnsaved: document.getElementById('config-unsaved'),
             authBadge: document.getElementById('auth-badge'),
             activeConnections: document.getElementById('active-connections'),
             totalConnections: document.getElementById('total-connections'),
@@ -203,6 +206,7 @@ class Dashboard {
             uptime: document.getElementById('uptime'),
             activeTbody: document.getElementById('active-tbody'),
             historyTbody: document.getElementById('history-tbody'),
+            historyWindow: document.getElementById('history-window'),
             version: document.getElementById('version'),
             userStatsPanel: document.getElementById('user-stats-panel'),
             userStatsGrid: document.getElementById('user-stats-grid'),
@@ -211,10 +215,13 @@ class Dashboard {
         
         this.isConnected = false;
         this.accessControl = null;
+        this.ruleHits = {};
         this.securityConfig = null;
         this.serverConfig = null;
         this.refreshInterval = null;
         this.historyInterval = null;
+        this.history = [];
+        this.historyCursor = null;
         this.init();
     }
 
@@ -223,6 +230,7 @@ class Dashboard {
         this.setupSettingsHandlers();
         this.setupUserHandlers();
         this.setupServerConfigHandlers();
+        this.elements.configUnsaved.addEventListener('click', () => this.flushConfig());
         
         // Show/hide logout button based on auth status
         if (authManager.authEnabled) {
@@ -239,6 +247,7 @@ class Dashboard {
         // Start periodic refresh
         setInterval(() => this.refresh(), REFRESH_INTERVAL);
         setInterval(() => this.loadHistory(), REFRESH_INTERVAL * 5);
+        setInterval(() => this.checkHealth(), REFRESH_INTERVAL * 5);
     }
 
     // ==================== Tab Navigation ====================
@@ -319,13 +328,16 @@ class Dashboard {
             const name = document.getElementById('rule-name').value.trim();
             const domain = domainInput.value.trim();
             const path = document.getElementById('rule-path').value.trim() || null;
+            const ports = document.getElementById('rule-ports').value.replace(/\s/g, '') || null;
             const action = document.getElementById('rule-action').value;
+            const mode = document.getElementById('rule-mode').value;
 
             if (domain) {
-                this.addRule({ name, domain, path, action, enabled: true });
+                this.addRule({ name, domain, path, ports, action, mode, enabled: true });
                 document.getElementById('rule-name').value = '';
                 domainInput.value = '';
                 document.getElementById('rule-path').value = '';
+                document.getElementById('rule-ports').value = '';
                 document.getElementById('rule-name').focus();
             } else {
                 this.shakeElement(domainInput);
@@ -336,7 +348,7 @@ class Dashboard {
         addRuleBtn?.addEventListener('click', handleAddRule);
         
         // Allow Enter on any rule form field
-        ['rule-name', 'rule-domain', 'rule-path'].forEach(id => {
+        ['rule-name', 'rule-domain', 'rule-path', 'rule-ports'].forEach(id => {
             document.getElementById(id)?.addEventListener('keypress', (e) => {
                 if (e.key === 'Enter') handleAddRule();
             });
@@ -368,6 +380,21 @@ class Dashboard {
         } catch (error) {
             console.error('Failed to load access control:', error);
         }
+        this.loadRuleHits();
+    }
+
+    async loadRuleHits() {
+        try {
+            const response = await apiFetch(`${API_BASE}/stats/rules`);
+            const data = await response.json();
+
+            if (data.success) {
+                this.ruleHits = data.data;
+                if (this.accessControl) this.renderRules();
+            }
+        } catch (error) {
+            console.error('Failed to load rule hits:', error);
+        }
     }
 
     renderAccessControl() {
@@ -389,6 +416,10 @@ class Dashboard {
             modeDisplay.textContent = 'Whitelist Mode';
             modeDesc.textContent = 'All domains blocked except those allowed by rules below';
         }
+        if (this.accessControl.monitor_only) {
+            modeDisplay.textContent += ' (Monitor Only)';
+            modeDesc.textContent += '. Nothing is blocked: denials are only logged as would-deny';
+        }
 
         // Render rules
         this.renderRules();
@@ -431,6 +462,16 @@ class Dashboard {
         });
     }
 
+    renderRuleHits(rule) {
+        const hits = this.ruleHits[rule.id];
+        if (!hits) return '';
+        const parts = [];
+        if (hits.enforced) parts.push(`${hits.enforced} enforced`);
+        if (hits.monitored) parts.push(`${hits.monitored} monitored`);
+        const title = hits.last_hit ? `Last hit ${new Date(hits.last_hit).toLocaleString()}` : '';
+        return parts.length ? `<span class="rule-hits" title="${this.escapeHtml(title)}">${parts.join(' · ')}</span>` : '';
+    }
+
     renderRules() {
         const tbody = document.getElementById('rules-tbody');
         const rulesCount = document.getElementById('rules-count');
@@ -459,17 +500,43 @@ class Dashboard {
         }
 
         tbody.innerHTML = rules.map((rule, index) => `
-            <tr>
-                <td>${this.escapeHtml(rule.name || '-')}</td>
-                <td><code>${this.escapeHtml(rule.domain)}</code></td>
+            <tr class="${rule.enabled && !rule.expired ? '' : 'rule-inactive'}">
+                <td>
+                    ${this.escapeHtml(rule.name || '-')}
+                    ${rule.expired ? '<span class="rule-state-badge">expired</span>' : ''}
+                    ${rule.expires_at && !rule.expired ? `<span class="rule-state-badge" title="${this.escapeHtml(rule.expires_at)}">until ${this.escapeHtml(new Date(rule.expires_at).toLocaleString())}</span>` : ''}
+                    ${rule.mode === 'monitor' ? '<span class="rule-state-badge monitor" title="Matches are logged, the action is not applied">monitor</span>' : ''}
+                </td>
+                <td><code>${this.escapeHtml(rule.domain)}${rule.ports ? ':' + this.escapeHtml(rule.ports.join(',')) : ''}</code></td>
                 <td><code>${rule.path ? this.escapeHtml(rule.path) : '*'}</code></td>
-                <td><span class="action-badge ${rule.action}">${rule.action}</span></td>
                 <td>
+                    <span class="action-badge ${rule.action}">${rule.action}</span>
+                    ${this.renderRuleHits(rule)}
+                </td>
+                <td>
+                    <button class="btn btn-sm toggle-mode" data-id="${this.escapeHtml(rule.id)}" data-mode="${rule.mode || 'enforce'}">
+                        ${rule.mode === 'monitor' ? 'Enforce' : 'Monitor'}
+                    </button>
+                    <button class="btn btn-sm toggle-rule" data-id="${this.escapeHtml(rule.id)}" data-enabled="${rule.enabled}">
+                        ${rule.enabled ? 'Disable' : 'Enable'}
+                    </button>
                     <button class="btn btn-sm btn-danger remove-rule" data-index="${index}">Remove</button>
                 </td>
             </tr>
         `).join('');
 
+        tbody.querySelectorAll('.toggle-mode').forEach(btn => {
+            btn.addEventListener('click', () => {
+                this.updateRule(btn.dataset.id, { mode: btn.dataset.mode === 'monitor' ? 'enforce' : 'monitor' });
+            });
+        });
+
+        tbody.querySelectorAll('.toggle-rule').forEach(btn => {
+            btn.addEventListener('click', () => {
+                this.updateRule(btn.dataset.id, { enabled: btn.dataset.enabled !== 'true' });
+            });
+        });
+
         // Add remove handlers
         tbody.querySelectorAll('.remove-rule').forEach(btn => {
             btn.addEventListener('click', () => {
@@ -563,6 +630,25 @@ class Dashboard {
         }
     }
 
+    async updateRule(id, changes) {
+        try {
+            const response = await apiFetch(`${API_BASE}/config/rules/${encodeURIComponent(id)}`, {
+                method: 'PATCH',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify(changes)
+            });
+            const data = await response.json();
+            if (data.success) {
+                this.accessControl = data.data;
+                this.renderAccessControl();
+            } else if (data.error) {
+                alert(data.error);
+            }
+        } catch (error) {
+            console.error('Failed to update rule:', error);
+        }
+    }
+
     async removeRule(index) {
         try {
             const response = await apiFetch(`${API_BASE}/config/rules`, {
@@ -594,15 +680,16 @@ class Dashboard {
         const usernameInput = document.getElementById('user-username');
         const passwordInput = document.getElementById('user-password');
         
-        const handleAddUser = () => {
+        const handleAddUser = async () => {
             const username = usernameInput?.value.trim();
             const password = passwordInput?.value;
             const description = document.getElementById('user-description')?.value.trim() || null;
             
             if (username && password) {
-                this.addUser({ username, password, description });
+                if (!await this.addUser({ username, password, description })) return;
                 usernameInput.value = '';
                 passwordInput.value = '';
+                passwordInput.type = 'password';
                 document.getElementById('user-description').value = '';
                 usernameInput.focus();
             } else {
@@ -612,6 +699,20 @@ class Dashboard {
         };
         
         addUserBtn?.addEventListener('click', handleAddUser);
+
+        // Fill in a random password that satisfies the server's policy
+        document.getElementById('generate-password-btn')?.addEventListener('click', async () => {
+            try {
+                const response = await apiFetch(`${API_BASE}/config/users/generate-password`);
+                const data = await response.json();
+                if (data.success) {
+                    passwordInput.value = data.data.password;
+                    passwordInput.type = 'text';
+                }
+            } catch (error) {
+                console.error('Failed to generate password:', error);
+            }
+        });
         
         // Allow Enter on user form fields
         ['user-username', 'user-password', 'user-description'].forEach(id => {
@@ -739,36 +840,33 @@ class Dashboard {
 
     async addUser(user) {
         try {
-            const response = await apiFetch(`${API_BASE}/config/users`, {
+            const response = await apiFetch(`${API_BASE}/users`, {
                 method: 'POST',
                 headers: { 'Content-Type': 'application/json' },
                 body: JSON.stringify(user)
             });
             const data = await response.json();
             if (data.success) {
-                this.securityConfig = data.data;
-                this.renderSecurityConfig();
-            } else if (data.message) {
-                alert(data.message);
+                await this.loadSecurityConfig();
+                return true;
             }
+            alert(data.error || data.message);
         } catch (error) {
             console.error('Failed to add user:', error);
         }
+        return false;
     }
 
     async removeUser(username) {
         if (!confirm(`Remove user "${username}"?`)) return;
         
         try {
-            const response = await apiFetch(`${API_BASE}/config/users`, {
-                method: 'DELETE',
-                headers: { 'Content-Type': 'application/json' },
-                body: JSON.stringify({ username })
+            const response = await apiFetch(`${API_BASE}/users/${encodeURIComponent(username)}`, {
+                method: 'DELETE'
             });
             const data = await response.json();
             if (data.success) {
-                this.securityConfig = data.data;
-                this.renderSecurityConfig();
+                await this.loadSecurityConfig();
             }
         } catch (error) {
             console.error('Failed to remove user:', error);
@@ -907,6 +1005,8 @@ class Dashboard {
             if (data.success) {
                 this.setConnected(true);
                 this.elements.version.textContent = data.data.version;
+                this.updateConfigLock(data.data.config_lock);
+                this.updateConfigSave(data.data.config_save);
             } else {
                 this.setConnected(false);
             }
@@ -916,6 +1016,40 @@ class Dashboard {
         }
     }
 
+    updateConfigLock(lock) {
+        const el = this.elements.configLock;
+        if (!lock) {
+            el.style.display = 'none';
+            return;
+        }
+        el.title = `Locked by ${lock.locked_by} at ${new Date(lock.locked_at).toLocaleString()}`
+            + (lock.reason ? `: ${lock.reason}` : '');
+        el.style.display = '';
+    }
+
+    updateConfigSave(status) {
+        const el = this.elements.configUnsaved;
+        if (!status) {
+            el.style.display = 'none';
+            return;
+        }
+        el.title = `Changes are only in memory: ${status.last_save_error}. Click to retry saving.`;
+        el.style.display = '';
+    }
+
+    async flushConfig() {
+        try {
+            const response = await apiFetch(`${API_BASE}/config/flush`, { method: 'POST' });
+            const data = await response.json();
+            if (!data.success) {
+                alert(data.error || 'Failed to save configuration');
+            }
+        } catch (error) {
+            console.error('Failed to save configuration:', error);
+        }
+        await this.checkHealth();
+    }
+
     setConnected(connected) {
         this.isConnected = connected;
         const statusEl = this.elements.status;
@@ -950,6 +1084,13 @@ class Dashboard {
     updateStats(stats) {
         this.elements.activeConnections.textContent = stats.active_connections.toLocaleString();
         this.elements.totalConnections.textContent = stats.total_connections.toLocaleString();
+        if (stats.attempts) {
+            const a = stats.attempts;
+            this.elements.totalConnections.title =
+                `${a.total.toLocaleString()} requests: ${a.relayed.toLocaleString()} relayed, ` +
+                `${a.denied.toLocaleString()} denied, ${a.connect_failed.toLocaleString()} connect failed, ` +
+                `${a.auth_failed.toLocaleString()} auth failed`;
+        }
         this.elements.bytesSent.textContent = this.formatBytes(stats.total_bytes_sent);
         this.elements.bytesReceived.textContent = this.formatBytes(stats.total_bytes_received);
         this.elements.uptime.textContent = this.formatDuration(stats.uptime_secs);
@@ -1085,36 +1226,103 @@ class Dashboard {
         const tbody = this.elements.activeTbody;
         
         if (connections.length === 0) {
-            tbody.innerHTML = '<tr class="empty-row"><td colspan="7">No active connections</td></tr>';
+            tbody.innerHTML = '<tr class="empty-row"><td colspan="9">No active connections</td></tr>';
             return;
         }
 
         tbody.innerHTML = connections.map(conn => `
             <tr>
                 <td><span class="protocol-badge ${conn.protocol}">${conn.protocol}</span></td>
-                <td>${this.escapeHtml(conn.client_addr)}</td>
+                <td title="${this.escapeHtml(conn.client_addr)}">${this.escapeHtml(conn.client_hostname || conn.client_addr)}</td>
                 <td>${this.escapeHtml(conn.target_addr)}:${conn.target_port}</td>
                 <td class="user-cell ${conn.username ? '' : 'anonymous'}">${conn.username ? this.escapeHtml(conn.username) : '-'}</td>
                 <td>${this.formatDuration(this.calculateDuration(conn.connected_at))}</td>
                 <td>${this.formatBytes(conn.bytes_sent)}</td>
                 <td>${this.formatBytes(conn.bytes_received)}</td>
+                <td title="Sent / received per second">${this.formatBytes(conn.current_rate_sent || 0)}/s / ${this.formatBytes(conn.current_rate_received || 0)}/s</td>
+                <td>
+                    <button class="btn btn-sm btn-danger ban-connection" data-id="${conn.id}" data-client="${this.escapeHtml(conn.client_addr)}">Ban</button>
+                </td>
             </tr>
         `).join('');
+
+        tbody.querySelectorAll('.ban-connection').forEach(btn => {
+            btn.addEventListener('click', () => {
+                this.banConnection(btn.dataset.id, btn.dataset.client);
+            });
+        });
+    }
+
+    async banConnection(id, client) {
+        const input = prompt(`Ban ${client} and close its connections.\nBan length in minutes (leave empty for a permanent blacklist entry):`, '');
+        if (input === null) return;
+        const minutes = input.trim() === '' ? null : parseInt(input, 10);
+        if (minutes !== null && !(minutes > 0)) {
+            alert('Ban length must be a positive number of minutes');
+            return;
+        }
+
+        const query = minutes ? `?duration=${minutes * 60}` : '';
+        try {
+            const response = await apiFetch(`${API_BASE}/connections/${id}/ban${query}`, { method: 'POST' });
+            const data = await response.json();
+            if (data.success) {
+                alert(`Banned ${data.data.ip}, closed ${data.data.terminated} connection(s)`);
+                this.refresh();
+                this.loadAccessControl();
+            } else {
+                alert(data.error || 'Failed to ban client');
+            }
+        } catch (error) {
+            console.error('Failed to ban client:', error);
+        }
     }
 
     async loadHistory() {
         try {
-            const response = await apiFetch(`${API_BASE}/history?limit=50`);
+            // After the first load only entries newer than the cursor are fetched
+            let url = `${API_BASE}/history?limit=${HISTORY_ROWS}`;
+            if (this.historyCursor !== null) {
+                url += `&since_id=${this.historyCursor}`;
+            }
+            const response = await apiFetch(url);
             const data = await response.json();
             
             if (data.success) {
-                this.updateHistory(data.data);
+                const { connections, cursor, window: span } = data.data;
+                if (this.historyCursor !== null && cursor < this.historyCursor) {
+                    // Server restarted and numbering began again
+                    this.history = [];
+                    this.historyCursor = null;
+                    return this.loadHistory();
+                }
+                // Drop rows the server no longer keeps
+                const oldest = span.oldest ? new Date(span.oldest) : null;
+                this.history = connections
+                    .concat(this.history)
+                    .filter(conn => oldest && new Date(conn.closed_at || conn.connected_at) >= oldest)
+                    .sort((a, b) => b.seq - a.seq)
+                    .slice(0, HISTORY_ROWS);
+                this.historyCursor = cursor;
+                this.updateHistory(this.history);
+                this.updateHistoryWindow(span);
             }
         } catch (error) {
             console.error('Failed to fetch history:', error);
         }
     }
 
+    updateHistoryWindow(span) {
+        let text = `Keeping the last ${span.max_entries.toLocaleString()} connections`;
+        if (span.max_age_secs) {
+            text += ` from the past ${Math.round(span.max_age_secs / 60)} minutes`;
+        }
+        if (span.oldest) {
+            text += ` · ${span.entries.toLocaleString()} since ${new Date(span.oldest).toLocaleString()}`;
+        }
+        this.elements.historyWindow.textContent = text;
+    }
+
     updateHistory(history) {
         const tbody = this.elements.historyTbody;
         
@@ -1128,7 +1336,7 @@ class Dashboard {
             return `
                 <tr>
                     <td><span class="protocol-badge ${conn.protocol}">${conn.protocol}</span></td>
-                    <td>${this.escapeHtml(conn.client_addr)}</td>
+                    <td title="${this.escapeHtml(conn.client_addr)}">${this.escapeHtml(conn.client_hostname || conn.client_addr)}</td>
                     <td>${this.escapeHtml(conn.target_addr)}:${conn.target_port}</td>
                     <td class="user-cell ${conn.username ? '' : 'anonymous'}">${conn.username ? this.escapeHtml(conn.username) : '-'}</td>
                     <td>${this.formatDuration(this.calculateConnectionDuration(conn))}</td>
@@ -0,0 +1,12 @@
+//! `Basic` credentials of the `Proxy-Authorization` header.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use net_relay_core::proxy::http::decode_basic_auth;
+
+fuzz_target!(|encoded: &str| {
+    if let Some((username, _)) = decode_basic_auth(encoded) {
+        assert!(!username.contains(':'));
+    }
+});
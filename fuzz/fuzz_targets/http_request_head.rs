@@ -0,0 +1,20 @@
+//! Request line and headers of an HTTP CONNECT request.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use net_relay_core::proxy::http::{decode_basic_auth, parse_host_port, parse_request_head};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(Some((head, len))) = parse_request_head(data) else {
+        return;
+    };
+    assert!(len <= data.len());
+    let _ = parse_host_port(&head.target);
+    let _ = head.wants_close();
+    if let Some(authorization) = head.proxy_authorization() {
+        if let Some((_, encoded)) = authorization.split_once(' ') {
+            let _ = decode_basic_auth(encoded.trim());
+        }
+    }
+});
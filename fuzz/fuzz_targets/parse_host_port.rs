@@ -0,0 +1,10 @@
+//! `host:port` targets of HTTP CONNECT requests.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use net_relay_core::proxy::http::parse_host_port;
+
+fuzz_target!(|target: &str| {
+    let _ = parse_host_port(target);
+});
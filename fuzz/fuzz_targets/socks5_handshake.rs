@@ -0,0 +1,27 @@
+//! Greeting, username/password request and CONNECT request, as a client sends them.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use net_relay_core::proxy::socks5::{parse_credentials, parse_greeting, parse_request};
+
+fuzz_target!(|data: &[u8]| {
+    let mut rest = data;
+    let Ok(Some((methods, len))) = parse_greeting(rest) else {
+        return;
+    };
+    assert!(len <= rest.len());
+    rest = &rest[len..];
+
+    if methods.contains(&0x02) {
+        let Ok(Some((_, len))) = parse_credentials(rest) else {
+            return;
+        };
+        assert!(len <= rest.len());
+        rest = &rest[len..];
+    }
+
+    if let Ok(Some((_, len))) = parse_request(rest) {
+        assert!(len <= rest.len());
+    }
+});